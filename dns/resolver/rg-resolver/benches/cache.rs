@@ -0,0 +1,76 @@
+use rg_resolver::cache::{Cache, Trust};
+use rg_resolver::message::{QuestionClass, QuestionType};
+use rg_resolver::provenance::Provenance;
+use rg_resolver::rr;
+use rg_resolver_common::DomainName;
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::thread;
+use std::time::Instant;
+
+// Example run: cargo bench --bench cache
+//
+// Compares the throughput of a cache behind a single lock with a sharded one, with several
+// threads each doing mostly lookups and some inserts. The difference only shows on a machine
+// with at least as many cores as threads.
+const THREADS: usize = 8;
+const OPERATIONS_PER_THREAD: usize = 200_000;
+const NAMES: usize = 1024;
+/// One operation in this many is an insert.
+const INSERT_EVERY: usize = 10;
+
+const A: QuestionType = QuestionType::RrType(rr::Type::A);
+const IN: QuestionClass = QuestionClass::RrClass(rr::Class::IN);
+
+fn main() -> anyhow::Result<()> {
+    let names = (0..NAMES)
+        .map(|i| format!("host{i}.example.com.").parse::<DomainName>())
+        .collect::<Result<Vec<_>, _>>()?;
+    let records = names
+        .iter()
+        .map(|name| {
+            rr::ResourceRecord::new(
+                name.clone(),
+                rr::Type::A,
+                rr::Class::IN,
+                300,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for shards in [1, Cache::DEFAULT_SHARDS] {
+        let cache = Cache::new(shards);
+        for record in &records {
+            cache.insert("default", vec![record.clone()], Trust::Answer, provenance());
+        }
+
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for t in 0..THREADS {
+                let (cache, names, records) = (&cache, &names, &records);
+                scope.spawn(move || {
+                    for i in 0..OPERATIONS_PER_THREAD {
+                        let idx = (i * 31 + t * 7) % NAMES;
+                        if i % INSERT_EVERY == 0 {
+                            let record = vec![records[idx].clone()];
+                            cache.insert("default", record, Trust::Answer, provenance());
+                        } else {
+                            black_box(cache.get("default", &names[idx], A, IN));
+                        }
+                    }
+                });
+            }
+        });
+        let operations = (THREADS * OPERATIONS_PER_THREAD) as f64;
+        println!(
+            "{shards:>2} shards: {:.0} operations/s",
+            operations / started.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn provenance() -> Provenance {
+    Provenance::new("192.0.2.53:53", None)
+}
@@ -0,0 +1,66 @@
+use bytes::BytesMut;
+use rg_resolver::message::{self, ResponseCode};
+use rg_resolver::rr;
+use std::hint::black_box;
+use std::net::Ipv4Addr;
+use std::time::Instant;
+
+// Example run: cargo bench --bench serialize
+//
+// Compares serializing a typical response into a new Vec with serializing it into a reused
+// buffer.
+const ITERATIONS: u32 = 200_000;
+
+fn main() -> anyhow::Result<()> {
+    let answers = (1..=4)
+        .map(|i| {
+            rr::ResourceRecord::new(
+                "www.example.com.".parse()?,
+                rr::Type::A,
+                rr::Class::IN,
+                300,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, i)),
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let authorities = vec![rr::ResourceRecord::new(
+        "example.com.".parse()?,
+        rr::Type::NS,
+        rr::Class::IN,
+        300,
+        rr::Data::NS("ns1.example.com.".parse()?),
+    )?];
+    let response = message::address_query(&"www.example.com.".parse()?).response(
+        ResponseCode::NoError,
+        answers,
+        authorities,
+        vec![],
+    );
+
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(black_box(&response).serialize()?);
+    }
+    report("serialize", started);
+
+    let mut buf = [0_u8; 512];
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(black_box(&response).serialize_into(&mut buf)?);
+    }
+    report("serialize_into", started);
+
+    let mut bytes = BytesMut::with_capacity(512);
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        bytes.clear();
+        black_box(black_box(&response).serialize_into_bytes(&mut bytes)?);
+    }
+    report("serialize_into_bytes", started);
+    Ok(())
+}
+
+fn report(name: &str, started: Instant) {
+    let per_message = started.elapsed() / ITERATIONS;
+    println!("{name:<22} {per_message:?} per message");
+}
@@ -0,0 +1,40 @@
+use rg_resolver::message::QuestionType;
+use rg_resolver::resolve;
+use rg_resolver::rr;
+use std::net::Ipv4Addr;
+
+// Example run: cargo run --example embed -- printer.lan.
+//
+// Answers names under lan. from a fixed set of records and forwards everything else
+// to the nameserver, falling back to the OS resolver.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let name = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("printer.lan."));
+
+    let records = vec![rr::ResourceRecord::new(
+        "printer.lan.".parse()?,
+        rr::Type::A,
+        rr::Class::IN,
+        300,
+        rr::Data::A(Ipv4Addr::new(192, 168, 50, 20)),
+    )?];
+    let resolver = resolve::Chain::new(vec![
+        Box::new(resolve::Static::new("lan", records)),
+        Box::new(resolve::Forwarder),
+        Box::new(resolve::System),
+    ]);
+
+    let resolution = resolve::resolve(&resolver, &name, QuestionType::RrType(rr::Type::A)).await?;
+    for rr in resolution.cnames.iter().chain(&resolution.rrset) {
+        println!("{} {:?}", rr.name(), rr.data());
+    }
+    match resolution.outcome {
+        resolve::Outcome::Answer => {}
+        resolve::Outcome::NxDomain => println!("{name} doesn't exist"),
+        _ => println!("{name} has no addresses"),
+    }
+    println!("took {:?}", resolution.timing.total);
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use rg_resolver::message::QuestionType;
+use rg_resolver::resolve::{self, Answer, BoxFuture, Resolve};
+use rg_resolver::rr;
+use std::time::Instant;
+
+// Example run: cargo run --example middleware -- ads.example.com.
+//
+// Resolvers are composed by wrapping one in another. Blocklist answers names under
+// blocked zones as having no records and times everything else it passes on.
+struct Blocklist<R> {
+    inner: R,
+    zones: Vec<String>,
+}
+
+impl<R: Resolve> Blocklist<R> {
+    fn is_blocked(&self, name: &str) -> bool {
+        self.zones.iter().any(|zone| {
+            name.eq_ignore_ascii_case(zone)
+                || (name.len() > zone.len()
+                    && name[name.len() - zone.len()..].eq_ignore_ascii_case(zone)
+                    && name.as_bytes()[name.len() - zone.len() - 1] == b'.')
+        })
+    }
+}
+
+impl<R: Resolve> Resolve for Blocklist<R> {
+    fn name(&self) -> &str {
+        "blocklist"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            if self.is_blocked(name) {
+                // * An answer without records is still an answer, so no later resolver is tried.
+                return Ok(Some(Answer::no_data()));
+            }
+            let started = Instant::now();
+            let result = self.inner.lookup(name, qtype).await;
+            println!("{} took {:?}", self.inner.name(), started.elapsed());
+            result
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let name = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("ads.example.com."));
+
+    let resolver = Blocklist {
+        inner: resolve::Forwarder,
+        zones: vec![String::from("ads.example.com.")],
+    };
+    match resolver
+        .lookup(&name, QuestionType::RrType(rr::Type::A))
+        .await?
+    {
+        Some(Answer::Records(rrset)) => println!("{name} has {} records", rrset.len()),
+        Some(_) => println!("{name} is blocked"),
+        None => println!("{name} has no answer"),
+    }
+    Ok(())
+}
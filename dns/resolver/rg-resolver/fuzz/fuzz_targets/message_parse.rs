@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rg_resolver::message::{Message, Parser};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Message::parse(data);
+    // * Also as a TCP stream of messages, each preceded by its length.
+    let mut parser = Parser::new(data);
+    while parser.next_framed().is_ok() {}
+});
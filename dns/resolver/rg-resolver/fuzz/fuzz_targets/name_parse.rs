@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rg_resolver::name;
+
+// * The first byte is where in the rest the name starts, so pointers have somewhere earlier to
+// * point to.
+fuzz_target!(|data: &[u8]| {
+    let Some((&start, msg)) = data.split_first() else {
+        return;
+    };
+    let mut unparsed = &msg[(start as usize).min(msg.len())..];
+    if let Ok(parsed) = name::parse(msg, &mut unparsed) {
+        // * Any name read has to fit in a message again.
+        name::serialize(&parsed, None).expect("a parsed name serializes");
+    }
+});
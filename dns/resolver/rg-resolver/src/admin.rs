@@ -0,0 +1,183 @@
+//! Operating the running daemon without restarting it: inspecting and flushing the cache,
+//! reporting stats, reloading the configuration, changing the negative trust anchors and what's
+//! logged, for the admin methods clients can call.
+
+use crate::cache::{Cache, CacheEntry, CacheStats};
+use crate::config::{self, UpstreamConfig};
+use crate::hosts::OverridesFile;
+use crate::monitor::{Monitor, UpstreamReport};
+use crate::net::{self, UpstreamStats};
+use crate::nta::NegativeTrustAnchors;
+use crate::task;
+use rg_resolver_common::DomainName;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Shared handles to the parts of the daemon the admin methods act on. Any left unset are
+/// skipped, e.g. flushing without a cache flushes nothing.
+#[derive(Default)]
+pub struct Admin {
+    cache: Option<Arc<Cache>>,
+    upstreams_path: Option<PathBuf>,
+    overrides: Option<Arc<OverridesFile>>,
+    monitor: Option<Monitor>,
+    anchors: Arc<Mutex<NegativeTrustAnchors>>,
+}
+
+/// The stats reported by dump_stats.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stats {
+    pub cache: Option<CacheStats>,
+    /// The upstreams queries are forwarded to, in the configured order.
+    pub upstreams: Vec<UpstreamEntry>,
+    /// The number of names in the overrides.
+    pub overrides: Option<usize>,
+    /// What the upstream monitor's probes found, if it's running.
+    pub monitor: Option<Vec<UpstreamReport>>,
+    /// The negative trust anchors in force, sorted by name.
+    pub negative_trust_anchors: Vec<AnchorEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AnchorEntry {
+    pub name: String,
+    /// The seconds until the anchor expires.
+    pub expires_in: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpstreamEntry {
+    pub upstream: SocketAddr,
+    #[serde(flatten)]
+    pub stats: UpstreamStats,
+}
+
+/// What reload_config read.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Reloaded {
+    /// The number of upstreams now configured.
+    pub upstreams: usize,
+    /// The number of names now in the overrides, if there are overrides.
+    pub overrides: Option<usize>,
+}
+
+impl Admin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Reloads the upstreams from path rather than from the system's configuration.
+    pub fn with_upstreams_file(mut self, path: PathBuf) -> Self {
+        self.upstreams_path = Some(path);
+        self
+    }
+
+    pub fn with_overrides(mut self, overrides: Arc<OverridesFile>) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Changes anchors, shared with the validator, rather than a set of its own.
+    pub fn with_negative_trust_anchors(
+        mut self,
+        anchors: Arc<Mutex<NegativeTrustAnchors>>,
+    ) -> Self {
+        self.anchors = anchors;
+        self
+    }
+
+    /// Skips validation at and below name for lifetime, replacing any anchor it has.
+    pub fn add_negative_trust_anchor(&self, name: &str, lifetime: Duration) -> anyhow::Result<()> {
+        self.anchors.lock().unwrap().add(name, lifetime)
+    }
+
+    /// Returns true if there was an anchor for name.
+    pub fn remove_negative_trust_anchor(&self, name: &str) -> bool {
+        self.anchors.lock().unwrap().remove(name)
+    }
+
+    /// Removes name's entries from the cache, or every entry if name is None, returning how
+    /// many were removed.
+    pub fn flush_cache(&self, name: Option<&DomainName>) -> usize {
+        let Some(cache) = &self.cache else {
+            return 0;
+        };
+        match name {
+            Some(name) => cache.flush_name(name),
+            None => cache.flush(),
+        }
+    }
+
+    /// The cache's live entries for name.
+    pub fn cache_entries(&self, name: &DomainName) -> Vec<CacheEntry> {
+        self.cache
+            .as_ref()
+            .map_or_else(Vec::new, |cache| cache.entries(name))
+    }
+
+    /// Logs what directives say from now on; see [task::set_log_filter].
+    pub fn set_log_filter(&self, directives: &str) -> anyhow::Result<()> {
+        task::set_log_filter(directives)
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            cache: self.cache.as_ref().map(|cache| cache.stats()),
+            upstreams: net::forwarding_stats()
+                .into_iter()
+                .map(|(upstream, stats)| UpstreamEntry { upstream, stats })
+                .collect(),
+            overrides: self
+                .overrides
+                .as_ref()
+                .map(|overrides| overrides.current().len()),
+            monitor: self.monitor.as_ref().map(Monitor::report),
+            negative_trust_anchors: self
+                .anchors
+                .lock()
+                .unwrap()
+                .list()
+                .into_iter()
+                .map(|(name, expires_in)| AnchorEntry {
+                    name,
+                    expires_in: expires_in.as_secs(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads the upstreams and overrides again. Nothing is changed unless both can be read, so
+    /// a broken file leaves the daemon running as it was.
+    pub fn reload_config(&self) -> anyhow::Result<Reloaded> {
+        let upstreams = match &self.upstreams_path {
+            Some(path) => UpstreamConfig::load(path)?,
+            None => UpstreamConfig::system()?,
+        };
+        if let Some(overrides) = &self.overrides {
+            overrides.reload()?;
+        }
+        let reloaded = Reloaded {
+            upstreams: upstreams.servers.len(),
+            overrides: self
+                .overrides
+                .as_ref()
+                .map(|overrides| overrides.current().len()),
+        };
+        // * The forwarder starts using them with its next query.
+        config::set_upstreams(upstreams);
+        Ok(reloaded)
+    }
+}
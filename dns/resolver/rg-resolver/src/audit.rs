@@ -0,0 +1,321 @@
+use crate::message::QuestionType;
+use crate::resolve::{Answer, BoxFuture, Resolve};
+use crate::{context, privacy, querylog};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Where the audit log, or the query log, is written and when it's rotated.
+#[derive(Clone, Debug)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Rotate once the log reaches this many bytes.
+    pub max_bytes: u64,
+    /// Rotate once the log has been open this long.
+    pub max_age: Duration,
+    /// The number of rotated logs to keep, named <path>.1 (newest) to <path>.<keep>.
+    pub keep: usize,
+}
+
+impl AuditConfig {
+    pub fn new(path: PathBuf) -> Self {
+        AuditConfig {
+            path,
+            max_bytes: 64 * 1024 * 1024,
+            max_age: Duration::from_secs(24 * 60 * 60),
+            keep: 7,
+        }
+    }
+}
+
+/// One audited request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditRecord {
+    pub id: u64,
+    pub client: String,
+    pub qname: String,
+    pub qtype: QuestionType,
+    pub outcome: String,
+    pub duration: Duration,
+    /// Supplied by the client to match the record to its request.
+    pub correlation_id: Option<String>,
+}
+
+impl AuditRecord {
+    /// The CSV header line, without the line terminator.
+    pub const HEADER: &'static str =
+        "timestamp,id,client,qname,qtype,outcome,duration_us,correlation_id";
+
+    /// Formats the record as a CSV line, without the line terminator.
+    pub fn to_csv(&self, timestamp: SystemTime) -> String {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!(
+            "{},{},{},{},{:?},{},{},{}",
+            timestamp,
+            self.id,
+            csv_field(&self.client),
+            csv_field(&self.qname),
+            self.qtype,
+            csv_field(&self.outcome),
+            self.duration.as_micros(),
+            csv_field(self.correlation_id.as_deref().unwrap_or_default())
+        )
+    }
+}
+
+/// Quotes field if it contains a character that's special in CSV.
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A CSV log of requests, separate from the tracing output, rotated by size and age.
+///
+/// Safe to share between threads; each record is written whole.
+pub struct AuditLog {
+    log: RotatingLog,
+}
+
+impl AuditLog {
+    pub fn open(config: AuditConfig) -> anyhow::Result<Self> {
+        let log = RotatingLog::open(config, Some(AuditRecord::HEADER))?;
+        Ok(AuditLog { log })
+    }
+
+    pub fn write(&self, record: &AuditRecord) -> anyhow::Result<()> {
+        self.log.write_line(&record.to_csv(SystemTime::now()))
+    }
+}
+
+/// A log of lines, rotated by size and age, starting each file with header if there is one.
+pub(crate) struct RotatingLog {
+    config: AuditConfig,
+    header: Option<&'static str>,
+    file: Mutex<LogFile>,
+}
+
+struct LogFile {
+    writer: BufWriter<File>,
+    bytes: u64,
+    opened: Instant,
+}
+
+impl RotatingLog {
+    pub(crate) fn open(config: AuditConfig, header: Option<&'static str>) -> anyhow::Result<Self> {
+        let file = Mutex::new(LogFile::open(&config, header)?);
+        Ok(RotatingLog {
+            config,
+            header,
+            file,
+        })
+    }
+
+    /// Appends line, which mustn't hold a line terminator.
+    pub(crate) fn write_line(&self, line: &str) -> anyhow::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let full =
+            file.bytes >= self.config.max_bytes || file.opened.elapsed() >= self.config.max_age;
+        // * Don't rotate out a log holding nothing but the header.
+        let header_len = self.header.map_or(0, |header| header.len() as u64 + 1);
+        if full && file.bytes > header_len {
+            file.writer.flush()?;
+            self.rotate()?;
+            *file = LogFile::open(&self.config, self.header)?;
+        }
+        writeln!(file.writer, "{line}")?;
+        // * Flush every record so the log is complete if the resolver exits abruptly.
+        file.writer.flush()?;
+        file.bytes += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shifts <path> to <path>.1, <path>.1 to <path>.2, and so on, dropping the oldest.
+    fn rotate(&self) -> anyhow::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = self.config.path.clone().into_os_string();
+            path.push(format!(".{n}"));
+            PathBuf::from(path)
+        };
+        if self.config.keep == 0 {
+            fs::remove_file(&self.config.path)?;
+            return Ok(());
+        }
+        for n in (1..self.config.keep).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.config.path, rotated(1))?;
+        Ok(())
+    }
+}
+
+impl LogFile {
+    fn open(config: &AuditConfig, header: Option<&str>) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let mut bytes = file.metadata()?.len();
+        let mut writer = BufWriter::new(file);
+        if let Some(header) = header.filter(|_| bytes == 0) {
+            writeln!(writer, "{header}")?;
+            bytes = header.len() as u64 + 1;
+        }
+        Ok(LogFile {
+            writer,
+            bytes,
+            opened: Instant::now(),
+        })
+    }
+}
+
+/// Writes an audit record for every lookup made through the inner resolver, naming the client
+/// whose request made it as the query log does.
+pub struct Audited<R> {
+    inner: R,
+    log: AuditLog,
+    next_id: AtomicU64,
+}
+
+impl<R: Resolve> Audited<R> {
+    pub fn new(inner: R, log: AuditLog) -> Self {
+        Audited {
+            inner,
+            log,
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for Audited<R> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            let start = Instant::now();
+            let result = self.inner.lookup(name, qtype).await;
+            let outcome = match &result {
+                Ok(Some(Answer::Records(rrset))) => format!("answered {}", rrset.len()),
+                Ok(Some(Answer::NoData { .. })) => String::from("no records"),
+                Ok(Some(Answer::NxDomain { .. })) => String::from("no such name"),
+                Ok(None) => String::from("no answer"),
+                Err(e) => format!("error: {e}"),
+            };
+            let record = AuditRecord {
+                id,
+                client: querylog::client(),
+                qname: privacy::qname(name).to_string(),
+                qtype,
+                outcome,
+                duration: start.elapsed(),
+                correlation_id: context::correlation_id(),
+            };
+            // * A broken audit log shouldn't fail the lookup.
+            if let Err(e) = self.log.write(&record) {
+                warn!("Writing audit record {id}: {e}");
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::Static;
+    use crate::rr;
+
+    fn temp_config(test_name: &str) -> AuditConfig {
+        let dir = std::env::temp_dir().join(format!(
+            "rg-resolver-audit-{test_name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AuditConfig::new(dir.join("audit.csv"))
+    }
+
+    fn record(id: u64, qname: &str) -> AuditRecord {
+        AuditRecord {
+            id,
+            client: String::from("127.0.0.1:5000"),
+            qname: qname.to_string(),
+            qtype: QuestionType::RrType(rr::Type::A),
+            outcome: String::from("answered 1"),
+            duration: Duration::from_micros(1500),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn csv_line() {
+        let line = record(7, "a,b.example.").to_csv(UNIX_EPOCH + Duration::from_secs(100));
+        assert_eq!(
+            line,
+            "100,7,127.0.0.1:5000,\"a,b.example.\",RrType(A),answered 1,1500,"
+        );
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn rotates_by_size() -> anyhow::Result<()> {
+        let mut config = temp_config("size");
+        config.max_bytes = 1;
+        config.keep = 2;
+        let log = AuditLog::open(config.clone())?;
+        for id in 1..=4 {
+            log.write(&record(id, "google.com."))?;
+        }
+
+        let rotated = |n: usize| PathBuf::from(format!("{}.{n}", config.path.display()));
+        let current = fs::read_to_string(&config.path)?;
+        assert!(current.starts_with(AuditRecord::HEADER));
+        assert!(current.contains(",4,"));
+        assert!(fs::read_to_string(rotated(1))?.contains(",3,"));
+        assert!(fs::read_to_string(rotated(2))?.contains(",2,"));
+        assert!(!rotated(3).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audits_lookups() -> anyhow::Result<()> {
+        let config = temp_config("lookups");
+        let log = AuditLog::open(config.clone())?;
+        let resolver = Audited::new(Static::new("hosts", Vec::new()), log);
+        resolver
+            .lookup("google.com.", QuestionType::RrType(rr::Type::A))
+            .await?;
+        let looking_up = context::with_correlation_id(
+            String::from("req-42"),
+            resolver.lookup("yahoo.com.", QuestionType::RrType(rr::Type::MX)),
+        );
+        querylog::with_client(String::from("192.0.2.7:5353"), looking_up).await?;
+
+        let contents = fs::read_to_string(&config.path)?;
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(",1,local,google.com.,RrType(A),no answer,"));
+        assert!(lines[1].ends_with(','));
+        assert!(lines[2].contains(",2,192.0.2.7:5353,yahoo.com.,RrType(MX),no answer,"));
+        assert!(lines[2].ends_with(",req-42"));
+        Ok(())
+    }
+}
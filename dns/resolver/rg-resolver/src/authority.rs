@@ -0,0 +1,371 @@
+use crate::hosts::{Override, OverridesFile};
+use crate::message::{Message, QuestionClass, QuestionType, ResponseCode};
+use crate::zone::{Found, Node, Tree};
+use crate::{rr, tsig};
+use rg_resolver_common::DomainName;
+use std::sync::Arc;
+
+/// The most CNAMEs followed within the local zones for one answer.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Answers queries for names in the local zones as their authoritative server, rather than
+/// passing them on to be resolved.
+#[derive(Default)]
+pub struct Authority {
+    zones: Vec<Tree>,
+    overrides: Option<Arc<OverridesFile>>,
+    required_keys: Vec<tsig::Key>,
+}
+
+impl Authority {
+    pub fn new(zones: Vec<Tree>) -> Self {
+        Authority {
+            zones,
+            overrides: None,
+            required_keys: Vec::new(),
+        }
+    }
+
+    /// Answers for the names in overrides ahead of the zones, with NXDOMAIN for blocked ones.
+    pub fn with_overrides(mut self, overrides: Arc<OverridesFile>) -> Self {
+        self.overrides = Some(overrides);
+        self
+    }
+
+    /// Answers only queries signed with one of keys, signing the responses with the same key.
+    /// Other queries are refused, or rejected with NOTAUTH if they're signed with some other
+    /// key or their signature doesn't check out (RFC 8945 section 5.2).
+    pub fn with_required_keys(mut self, keys: Vec<tsig::Key>) -> Self {
+        self.required_keys = keys;
+        self
+    }
+
+    /// The keys queries have to be signed with, if any.
+    pub fn required_keys(&self) -> &[tsig::Key] {
+        &self.required_keys
+    }
+
+    /// The response to query, if it asks about a name in one of the zones.
+    ///
+    /// Answers carry the AA bit and the zone's NS records. A name that doesn't exist gets
+    /// NXDOMAIN, and one without records of the type gets an empty answer, both with the
+    /// zone's SOA so the answer can be cached (RFC 2308). A name below a delegation gets a
+    /// referral to the servers it's delegated to. Names in the overrides are answered from
+    /// them before any zone.
+    pub fn answer(&self, query: &Message) -> Option<Message> {
+        let [question] = query.questions() else {
+            return None;
+        };
+        if question.class() != QuestionClass::RrClass(rr::Class::IN) {
+            return None;
+        }
+        if let Some(response) = self.overridden(query) {
+            return Some(response);
+        }
+        let zone = self.zone_for(question.name())?;
+        let qtype = question.r#type();
+
+        let mut answers = Vec::new();
+        let mut name = question.name().clone();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let node = match zone.lookup(&name) {
+                // * The DS records at a delegation are the parent's to answer.
+                Found::Delegation(cut) if !(is_ds(qtype) && owns(cut, &name)) => {
+                    return Some(referral(query, zone, cut, answers));
+                }
+                Found::Delegation(node) | Found::Name(node) | Found::Wildcard(node) => node,
+                Found::NoName => {
+                    return Some(negative(query, zone, ResponseCode::NameError, answers))
+                }
+            };
+            // * A wildcard's records are given as the name asked about (RFC 4592 section 3.3.1).
+            let at_name = |rr: &rr::ResourceRecord| {
+                rr::ResourceRecord::new(
+                    name.clone(),
+                    rr.r#type(),
+                    rr.class(),
+                    rr.ttl(),
+                    rr.data().clone(),
+                )
+                .expect("the type and data come from a valid record")
+            };
+            let found = node
+                .records()
+                .iter()
+                .filter(|rr| qtype.matches(rr.r#type()))
+                .map(at_name)
+                .collect::<Vec<_>>();
+            if !found.is_empty() {
+                answers.extend(found);
+                return Some(positive(query, zone, answers));
+            }
+            let Some(cname) = node
+                .records()
+                .iter()
+                .find(|rr| rr.r#type() == rr::Type::CNAME)
+            else {
+                return Some(negative(query, zone, ResponseCode::NoError, answers));
+            };
+            let rr::Data::CNAME(target) = cname.data() else {
+                unreachable!("a CNAME record holds a CNAME");
+            };
+            answers.push(at_name(cname));
+            // * The client has to follow a CNAME out of the zone itself.
+            if !zone.contains(target) {
+                return Some(positive(query, zone, answers));
+            }
+            name = target.clone();
+        }
+        Some(positive(query, zone, answers))
+    }
+
+    /// The response to query from the overrides, if they mention its name.
+    fn overridden(&self, query: &Message) -> Option<Message> {
+        let overrides = self.overrides.as_ref()?.current();
+        let question = &query.questions()[0];
+        let code = match overrides.get(question.name())? {
+            Override::Blocked => ResponseCode::NameError,
+            Override::Addresses(_) => ResponseCode::NoError,
+        };
+        let answers = overrides.records(question.name(), question.r#type())?;
+        let mut response = query.response(code, answers, vec![], vec![]);
+        response.set_authoritative_answer(true);
+        Some(response)
+    }
+
+    /// The zone name is in, the deepest if the zones nest.
+    fn zone_for(&self, qname: &DomainName) -> Option<&Tree> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.contains(qname))
+            .max_by_key(|zone| zone.origin().num_labels())
+    }
+}
+
+fn is_ds(qtype: QuestionType) -> bool {
+    qtype == QuestionType::RrType(rr::Type::DS)
+}
+
+/// Returns true if node's records are owned by name.
+fn owns(node: &Node, name: &DomainName) -> bool {
+    node.records().first().is_some_and(|rr| rr.name() == name)
+}
+
+fn of_type(node: &Node, r#type: rr::Type) -> Vec<rr::ResourceRecord> {
+    node.records()
+        .iter()
+        .filter(|rr| rr.r#type() == r#type)
+        .cloned()
+        .collect()
+}
+
+fn apex(zone: &Tree) -> &Node {
+    zone.find(zone.origin())
+        .expect("a zone always has its apex")
+}
+
+fn positive(query: &Message, zone: &Tree, answers: Vec<rr::ResourceRecord>) -> Message {
+    let authorities = of_type(apex(zone), rr::Type::NS);
+    let mut response = query.response(ResponseCode::NoError, answers, authorities, vec![]);
+    response.set_authoritative_answer(true);
+    response
+}
+
+fn negative(
+    query: &Message,
+    zone: &Tree,
+    code: ResponseCode,
+    answers: Vec<rr::ResourceRecord>,
+) -> Message {
+    // * A negative answer is cached for the lesser of the SOA's TTL and its minimum field
+    // * (RFC 2308 section 5), so that's the TTL it's given.
+    let soa = zone.soa().map(|soa| match soa.data() {
+        rr::Data::SOA { minimum, .. } => soa.clone().with_ttl(soa.ttl().min(*minimum)),
+        _ => soa.clone(),
+    });
+    let mut response = query.response(code, answers, soa.into_iter().collect(), vec![]);
+    response.set_authoritative_answer(true);
+    response
+}
+
+/// Refers the client to the servers a name is delegated to, with the addresses of those
+/// servers that are in the zone (glue).
+fn referral(query: &Message, zone: &Tree, cut: &Node, answers: Vec<rr::ResourceRecord>) -> Message {
+    let authorities = of_type(cut, rr::Type::NS);
+    let additionals = authorities
+        .iter()
+        .filter_map(|ns| match ns.data() {
+            rr::Data::NS(host) => zone.find(host),
+            _ => None,
+        })
+        .flat_map(|host| {
+            host.records()
+                .iter()
+                .filter(|rr| matches!(rr.r#type(), rr::Type::A | rr::Type::AAAA))
+                .cloned()
+        })
+        .collect();
+    // * Only the CNAMEs followed to get here, if any, are this server's to vouch for.
+    let is_authoritative = !answers.is_empty();
+    let mut response = query.response(ResponseCode::NoError, answers, authorities, additionals);
+    response.set_authoritative_answer(is_authoritative);
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::zone::{self, ZoneSpec};
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn authority() -> anyhow::Result<Authority> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "rg-resolver-authority-{}-{}.zone",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(
+            &path,
+            [
+                "$TTL 3600",
+                "@         SOA  ns1 hostmaster 1 3600 600 86400 300",
+                "          NS   ns1",
+                "ns1       A    192.0.2.1",
+                "www       A    192.0.2.10",
+                "alias     CNAME www",
+                "away      CNAME www.example.net.",
+                "*.hosts   A    192.0.2.20",
+                "sub       NS   ns.sub",
+                "          DS   1 8 2 abcd",
+                "ns.sub    A    192.0.2.30",
+                "a.b.c     TXT  deep",
+            ]
+            .join("\n"),
+        )?;
+        let loaded = zone::load(&ZoneSpec {
+            origin: String::from("example.com."),
+            path: path.clone(),
+        });
+        std::fs::remove_file(&path)?;
+        Ok(Authority::new(vec![loaded?.tree()]))
+    }
+
+    fn ask(authority: &Authority, name: &str, qtype: rr::Type) -> Option<Message> {
+        let query = Message::query(&name.parse().unwrap(), QuestionType::RrType(qtype));
+        authority.answer(&query)
+    }
+
+    fn types(records: &[rr::ResourceRecord]) -> Vec<rr::Type> {
+        records.iter().map(|rr| rr.r#type()).collect()
+    }
+
+    #[test]
+    fn answers() -> anyhow::Result<()> {
+        let authority = authority()?;
+        let response = ask(&authority, "WWW.example.com.", rr::Type::A).unwrap();
+        assert!(response.is_authoritative_answer());
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            &rr::Data::A(Ipv4Addr::new(192, 0, 2, 10))
+        );
+        assert_eq!(types(response.authorities()), [rr::Type::NS]);
+
+        // * A CNAME in the zone is followed, one leaving it isn't.
+        let response = ask(&authority, "alias.example.com.", rr::Type::A).unwrap();
+        assert_eq!(types(response.answers()), [rr::Type::CNAME, rr::Type::A]);
+        let response = ask(&authority, "away.example.com.", rr::Type::A).unwrap();
+        assert_eq!(types(response.answers()), [rr::Type::CNAME]);
+
+        // * Names outside the zones are left to be resolved.
+        assert!(ask(&authority, "www.example.net.", rr::Type::A).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn negative_answers() -> anyhow::Result<()> {
+        let authority = authority()?;
+        let response = ask(&authority, "nowhere.example.com.", rr::Type::A).unwrap();
+        assert!(response.is_authoritative_answer());
+        assert_eq!(response.response_code(), ResponseCode::NameError);
+        assert!(response.answers().is_empty());
+        assert_eq!(types(response.authorities()), [rr::Type::SOA]);
+        // * The SOA's minimum caps how long the answer is cached.
+        assert_eq!(response.authorities()[0].ttl(), 300);
+
+        let response = ask(&authority, "www.example.com.", rr::Type::AAAA).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(types(response.authorities()), [rr::Type::SOA]);
+
+        // * A name with nothing but names below it exists.
+        let response = ask(&authority, "b.c.example.com.", rr::Type::TXT).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        Ok(())
+    }
+
+    #[test]
+    fn wildcards() -> anyhow::Result<()> {
+        let authority = authority()?;
+        let response = ask(&authority, "printer.hosts.example.com.", rr::Type::A).unwrap();
+        assert_eq!(response.answers().len(), 1);
+        let name = response.answers()[0].name().to_string();
+        assert_eq!(name, "printer.hosts.example.com.");
+
+        // * It matches names any number of labels below it...
+        let response = ask(&authority, "a.printer.hosts.example.com.", rr::Type::A).unwrap();
+        let name = response.answers()[0].name().to_string();
+        assert_eq!(name, "a.printer.hosts.example.com.");
+        // * ...but not names that do exist.
+        let response = ask(&authority, "hosts.example.com.", rr::Type::A).unwrap();
+        assert!(response.answers().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn overrides() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "rg-resolver-authority-overrides-{}",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "192.0.2.99 www.example.com\n0.0.0.0 ads.example.net\n",
+        )?;
+        let overrides = OverridesFile::load(&path);
+        std::fs::remove_file(&path)?;
+        let authority = authority()?.with_overrides(Arc::new(overrides?));
+
+        // * They're consulted before the zones.
+        let response = ask(&authority, "www.example.com.", rr::Type::A).unwrap();
+        assert!(response.is_authoritative_answer());
+        assert_eq!(
+            response.answers()[0].data(),
+            &rr::Data::A(Ipv4Addr::new(192, 0, 2, 99))
+        );
+        let response = ask(&authority, "ads.example.net.", rr::Type::A).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NameError);
+        assert!(response.answers().is_empty());
+        assert!(ask(&authority, "www.example.net.", rr::Type::A).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn referrals() -> anyhow::Result<()> {
+        let authority = authority()?;
+        let response = ask(&authority, "www.sub.example.com.", rr::Type::A).unwrap();
+        assert!(!response.is_authoritative_answer());
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(types(response.authorities()), [rr::Type::NS]);
+        assert_eq!(types(response.additionals()), [rr::Type::A]);
+
+        // * Except the DS records, which the parent holds.
+        let response = ask(&authority, "sub.example.com.", rr::Type::DS).unwrap();
+        assert!(response.is_authoritative_answer());
+        assert_eq!(types(response.answers()), [rr::Type::DS]);
+        Ok(())
+    }
+}
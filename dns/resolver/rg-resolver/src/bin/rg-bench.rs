@@ -0,0 +1,269 @@
+use clap::Parser;
+use rg_resolver::message::{self, QueryBuilder, QuestionClass, QuestionType};
+use rg_resolver::rr;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::time;
+
+// Example run: cargo run --release --bin rg-bench -- --server 192.168.50.1:53 --names names.txt --qps 200
+#[derive(Parser)]
+#[command(about = "Drives a nameserver with queries and reports latency and error rates over time")]
+struct Args {
+    /// The nameserver to query.
+    #[arg(long, default_value = "192.168.50.1:53")]
+    server: SocketAddr,
+    /// File holding the query names, one per line, most popular first.
+    #[arg(long)]
+    names: String,
+    /// Queries sent per second, at most one a nanosecond.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=1_000_000_000))]
+    qps: u32,
+    /// How long to run, in seconds.
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Exponent of the Zipf distribution the names are drawn from (0 is uniform).
+    #[arg(long, default_value_t = 1.0)]
+    zipf: f64,
+    /// Seconds between progress reports.
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    report_interval: u64,
+    /// Milliseconds to wait for each response.
+    #[arg(long, default_value_t = 2000)]
+    timeout_ms: u64,
+    /// Responses faster than this many milliseconds are counted as cache hits.
+    /// The nameserver doesn't report whether it answered from its cache, so this is an estimate.
+    #[arg(long, default_value_t = 2)]
+    cache_hit_ms: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("ERROR: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let names = std::fs::read_to_string(&args.names)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let zipf = Zipf::new(names.len(), args.zipf)?;
+
+    let interval_stats = Arc::new(Mutex::new(Stats::default()));
+    let mut total_stats = Stats::default();
+    let timeout = Duration::from_millis(args.timeout_ms);
+    let cache_hit = Duration::from_millis(args.cache_hit_ms);
+
+    let start = Instant::now();
+    let end = start + Duration::from_secs(args.duration);
+    let mut send_tick = time::interval(Duration::from_secs(1) / args.qps);
+    let mut report_tick = time::interval(Duration::from_secs(args.report_interval));
+    // The first tick of an interval completes immediately.
+    report_tick.tick().await;
+
+    println!("elapsed      sent        ok    errors  timeouts  hit%     p50     p90     p99");
+    while Instant::now() < end {
+        tokio::select! {
+            _ = send_tick.tick() => {
+                let name = names[zipf.sample(rand::random::<f64>())].clone();
+                let stats = interval_stats.clone();
+                let server = args.server;
+                tokio::spawn(async move {
+                    let outcome = query(server, &name, timeout).await;
+                    stats.lock().unwrap().record(outcome, cache_hit);
+                });
+            }
+            _ = report_tick.tick() => {
+                let mut stats = std::mem::take(&mut *interval_stats.lock().unwrap());
+                stats.print_row(start.elapsed());
+                total_stats.merge(stats);
+            }
+        }
+    }
+
+    // Give the queries still in flight a chance to finish.
+    time::sleep(timeout).await;
+    total_stats.merge(std::mem::take(&mut *interval_stats.lock().unwrap()));
+    println!("total:");
+    total_stats.print_row(start.elapsed());
+    Ok(())
+}
+
+enum Outcome {
+    Response(Duration),
+    Timeout,
+    Error,
+}
+
+async fn query(server: SocketAddr, name: &str, timeout: Duration) -> Outcome {
+    let exchange = async {
+        let query = QueryBuilder::new()
+            .recursion_desired(true)
+            .question(
+                &name.parse()?,
+                QuestionType::RrType(rr::Type::A),
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build()
+            .serialize()?;
+        let bind_addr: SocketAddr = match server {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+            SocketAddr::V6(_) => "[::]:0".parse()?,
+        };
+        let sock = UdpSocket::bind(bind_addr).await?;
+        sock.connect(server).await?;
+        let sent_at = Instant::now();
+        sock.send(&query).await?;
+        let mut buf = [0_u8; 512];
+        let size = sock.recv(&mut buf).await?;
+        let rtt = sent_at.elapsed();
+        message::Message::parse(&buf[..size])?;
+        anyhow::Ok(rtt)
+    };
+    match time::timeout(timeout, exchange).await {
+        Ok(Ok(rtt)) => Outcome::Response(rtt),
+        Ok(Err(_)) => Outcome::Error,
+        Err(_) => Outcome::Timeout,
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: u64,
+    errors: u64,
+    timeouts: u64,
+    cache_hits: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    fn record(&mut self, outcome: Outcome, cache_hit: Duration) {
+        self.sent += 1;
+        match outcome {
+            Outcome::Response(rtt) => {
+                if rtt < cache_hit {
+                    self.cache_hits += 1;
+                }
+                self.latencies.push(rtt);
+            }
+            Outcome::Timeout => self.timeouts += 1,
+            Outcome::Error => self.errors += 1,
+        }
+    }
+
+    fn merge(&mut self, other: Stats) {
+        self.sent += other.sent;
+        self.errors += other.errors;
+        self.timeouts += other.timeouts;
+        self.cache_hits += other.cache_hits;
+        self.latencies.extend(other.latencies);
+    }
+
+    fn print_row(&mut self, elapsed: Duration) {
+        self.latencies.sort();
+        let hit_perc = if self.latencies.is_empty() {
+            0_f64
+        } else {
+            self.cache_hits as f64 * 100_f64 / self.latencies.len() as f64
+        };
+        let fmt_latency = |latency: Option<Duration>| match latency {
+            Some(latency) => format!("{:.1}ms", latency.as_secs_f64() * 1000_f64),
+            None => String::from("-"),
+        };
+        println!(
+            "{:>6.1}s {:>9} {:>9} {:>9} {:>9} {:>5.1} {:>7} {:>7} {:>7}",
+            elapsed.as_secs_f64(),
+            self.sent,
+            self.latencies.len(),
+            self.errors,
+            self.timeouts,
+            hit_perc,
+            fmt_latency(percentile(&self.latencies, 50)),
+            fmt_latency(percentile(&self.latencies, 90)),
+            fmt_latency(percentile(&self.latencies, 99)),
+        );
+    }
+}
+
+/// The nearest-rank percentile of sorted.
+fn percentile(sorted: &[Duration], perc: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (perc * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+/// Samples ranks 0..n, where rank k is drawn with probability proportional to 1 / (k + 1)^s.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, s: f64) -> anyhow::Result<Self> {
+        if n == 0 {
+            anyhow::bail!("no names to query");
+        }
+        if s < 0_f64 {
+            anyhow::bail!("zipf exponent must not be negative");
+        }
+        let mut cdf = Vec::with_capacity(n);
+        let mut total = 0_f64;
+        for k in 1..=n {
+            total += 1_f64 / (k as f64).powf(s);
+            cdf.push(total);
+        }
+        cdf.iter_mut().for_each(|p| *p /= total);
+        Ok(Zipf { cdf })
+    }
+
+    /// Maps uniform, which must be in [0, 1), to a rank.
+    fn sample(&self, uniform: f64) -> usize {
+        let rank = self.cdf.partition_point(|&p| p <= uniform);
+        rank.min(self.cdf.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn zipf_uniform() -> anyhow::Result<()> {
+        let zipf = Zipf::new(4, 0_f64)?;
+        assert_eq!(zipf.sample(0.0), 0);
+        assert_eq!(zipf.sample(0.3), 1);
+        assert_eq!(zipf.sample(0.6), 2);
+        assert_eq!(zipf.sample(0.99), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn zipf_skewed() -> anyhow::Result<()> {
+        // With s = 1 and 3 names the probabilities are 6/11, 3/11, and 2/11.
+        let zipf = Zipf::new(3, 1_f64)?;
+        assert_eq!(zipf.sample(0.5), 0);
+        assert_eq!(zipf.sample(0.6), 1);
+        assert_eq!(zipf.sample(0.9), 2);
+
+        assert!(Zipf::new(0, 1_f64).is_err());
+        assert!(Zipf::new(3, -1_f64).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn percentiles() {
+        let latencies = (1..=10).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 50), Some(Duration::from_millis(5)));
+        assert_eq!(percentile(&latencies, 90), Some(Duration::from_millis(9)));
+        assert_eq!(percentile(&latencies, 99), Some(Duration::from_millis(10)));
+        assert_eq!(percentile(&[], 50), None);
+    }
+}
@@ -0,0 +1,1444 @@
+use crate::classify::{self, Classification};
+use crate::message::{Message, Question, QuestionClass, QuestionType};
+use crate::netwatch::NetworkWatcher;
+use crate::provenance::{self, AnswerSource, Provenance};
+use crate::resolve::{self, BoxFuture, RRset, Resolve};
+use crate::rr;
+use crate::ttl::TtlOverrides;
+use rg_resolver_common::DomainName;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::mem;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Answers kept in memory until their TTLs run out, shared by every task handling queries.
+///
+/// Records are kept as RRsets, all those with the same owner, type and class together, each
+/// ranked by how far it's trusted. A lookup follows cached CNAMEs to the RRset it asked for,
+/// so an alias and its target are each cached once however many names lead to them. Negative
+/// answers are kept per question.
+///
+/// Entries are split across shards by a hash of the owner name, each behind its own lock, so
+/// lookups of different names rarely contend.
+///
+/// Unless bounded with [Cache::with_max_entries] or [Cache::with_max_bytes], the cache grows
+/// with every name looked up until their TTLs run out. A bounded cache splits its limits
+/// evenly between the shards, and a shard that goes over evicts entries as [Eviction] says.
+pub struct Cache {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    overrides: TtlOverrides,
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    eviction: Eviction,
+    /// Whether addresses are served in rotating order; see [Cache::with_rotation].
+    rotate: bool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Which entries a full cache evicts first.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// The least recently used.
+    #[default]
+    Lru,
+    /// The least frequently used, and the least recently used of those. Names looked up all
+    /// the time stay cached through bursts of one-off lookups, e.g. a crawl.
+    Lfu,
+}
+
+impl FromStr for Eviction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "lru" => Ok(Eviction::Lru),
+            "lfu" => Ok(Eviction::Lfu),
+            _ => anyhow::bail!("unknown eviction policy {s}, expected lru or lfu"),
+        }
+    }
+}
+
+/// One shard's entries, and the order they'd be evicted in.
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<Key, Entry>,
+    /// The key of every entry by its [Entry::rank], lowest first.
+    ranks: BTreeMap<(u64, u64), Key>,
+    /// The sum of the entries' sizes.
+    bytes: usize,
+    /// Counts the entries' uses, so each use has its own time.
+    clock: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    /// The view the entry belongs to, so views never see each other's answers.
+    namespace: String,
+    /// Compared and hashed ignoring case, as names are.
+    name: DomainName,
+    qtype: QuestionType,
+    qclass: QuestionClass,
+}
+
+struct Entry {
+    answer: Answer,
+    provenance: Provenance,
+    trust: Trust,
+    stored_at: Instant,
+    expires_at: Instant,
+    /// The times the entry was stored or returned.
+    uses: u64,
+    /// The shard's clock when it was last stored or returned.
+    last_used: u64,
+    /// Roughly the memory the entry and its key take; see [Entry::size].
+    size: usize,
+    /// The times the entry's addresses have been served rotated, which is how far the next
+    /// are rotated.
+    rotations: u64,
+}
+
+/// How far cached data is trusted, least first (RFC 2181 section 5.4.1). Data replaces a
+/// cached RRset only if it's trusted at least as far, so an authoritative answer replaces
+/// glue, but glue never replaces an answer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trust {
+    /// The additional section of a response, e.g. a referral's glue. Only used to reach
+    /// nameservers, never to answer a lookup.
+    Additional,
+    /// The authority section of a response, e.g. a referral's NS records.
+    Authority,
+    /// The answer of a response without the AA bit, e.g. from a forwarder, or of a resolver
+    /// that doesn't say.
+    Answer,
+    /// The answer of an authoritative response.
+    AuthoritativeAnswer,
+}
+
+/// What the cache knows about a question.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Answer {
+    Records(RRset),
+    /// The name exists, but has no records of the asked-for type.
+    NoData {
+        soa: rr::ResourceRecord,
+    },
+    /// The name doesn't exist. It's cached per question type like other answers, so a lookup
+    /// of another type still goes upstream.
+    NxDomain {
+        soa: rr::ResourceRecord,
+    },
+}
+
+impl Answer {
+    /// The records answering the question, which there are none of for a negative answer.
+    pub fn records(&self) -> &[rr::ResourceRecord] {
+        match self {
+            Answer::Records(records) => records,
+            Answer::NoData { .. } | Answer::NxDomain { .. } => &[],
+        }
+    }
+
+    /// Every record the answer holds, including a negative answer's SOA.
+    fn all_records(&self) -> &[rr::ResourceRecord] {
+        match self {
+            Answer::Records(records) => records,
+            Answer::NoData { soa } | Answer::NxDomain { soa } => std::slice::from_ref(soa),
+        }
+    }
+
+    fn map_records(&self, f: impl Fn(&rr::ResourceRecord) -> rr::ResourceRecord) -> Self {
+        match self {
+            Answer::Records(records) => Answer::Records(records.iter().map(f).collect()),
+            Answer::NoData { soa } => Answer::NoData { soa: f(soa) },
+            Answer::NxDomain { soa } => Answer::NxDomain { soa: f(soa) },
+        }
+    }
+}
+
+impl From<Answer> for resolve::Answer {
+    fn from(answer: Answer) -> Self {
+        match answer {
+            Answer::Records(records) => resolve::Answer::Records(records),
+            Answer::NoData { soa } => resolve::Answer::NoData {
+                cnames: RRset::new(),
+                soa: Some(soa),
+            },
+            Answer::NxDomain { soa } => resolve::Answer::NxDomain {
+                cnames: RRset::new(),
+                soa: Some(soa),
+            },
+        }
+    }
+}
+
+/// A cached answer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// The answer, with its TTLs reduced by the time it's been cached.
+    pub answer: Answer,
+    /// Where the RRset or negative answer at the end of any CNAMEs came from.
+    pub provenance: Provenance,
+    /// How far the least trusted of the records is.
+    pub trust: Trust,
+}
+
+/// An entry in the cache, as listed by [Cache::entries] for inspecting it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CacheEntry {
+    pub namespace: String,
+    pub qtype: String,
+    pub qclass: String,
+    /// "records", or "nodata" or "nxdomain" for a negative answer.
+    pub answer: &'static str,
+    /// The records, or a negative answer's SOA, with their TTLs reduced by the time they've
+    /// been cached.
+    pub records: Vec<String>,
+    pub trust: String,
+    /// Where the records came from.
+    pub provenance: String,
+    /// The seconds until the entry expires.
+    pub expires_in: u64,
+}
+
+/// Counts of how the cache has been used.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    /// Roughly the memory the entries take, as counted against [CacheStats::max_bytes].
+    pub bytes: usize,
+    /// The live entries removed to keep the cache within its limits.
+    pub evictions: u64,
+    pub max_entries: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+impl Cache {
+    pub const DEFAULT_SHARDS: usize = 16;
+
+    /// An empty, unbounded cache split into shards shards, at least one.
+    pub fn new(shards: usize) -> Self {
+        Cache {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            hasher: RandomState::new(),
+            overrides: TtlOverrides::new(),
+            max_entries: None,
+            max_bytes: None,
+            eviction: Eviction::default(),
+            rotate: false,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Bounds the TTLs of inserted records with overrides.
+    pub fn with_ttl_overrides(mut self, overrides: TtlOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Keeps at most max entries, evicting to make room for more.
+    pub fn with_max_entries(mut self, max: usize) -> Self {
+        self.max_entries = Some(max);
+        self
+    }
+
+    /// Keeps the entries to roughly max bytes of memory, evicting to make room for more. An
+    /// entry is counted as the records it holds plus some overhead; the maps holding the
+    /// entries aren't counted, so the process uses somewhat more.
+    pub fn with_max_bytes(mut self, max: usize) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// Evicts by policy rather than the least recently used entries first.
+    pub fn with_eviction(mut self, policy: Eviction) -> Self {
+        self.eviction = policy;
+        self
+    }
+
+    /// Serves the addresses of each cached A and AAAA RRset rotated one further each time,
+    /// round-robin, so clients that take the first spread their load across the hosts. The
+    /// first lookup gets them in the order they were cached.
+    pub fn with_rotation(mut self) -> Self {
+        self.rotate = true;
+        self
+    }
+
+    /// The records cached in namespace answering the question, unless they've expired: those
+    /// of the asked-for type, preceded by any CNAMEs leading to them, or that they or the name
+    /// don't exist. Records only trusted as [Trust::Additional] or [Trust::Authority] aren't
+    /// answers, so aren't returned.
+    pub fn get(
+        &self,
+        namespace: &str,
+        name: &DomainName,
+        qtype: QuestionType,
+        qclass: QuestionClass,
+    ) -> Option<Hit> {
+        let hit = self.follow(namespace, name, qtype, qclass);
+        match hit {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        hit
+    }
+
+    /// The addresses cached in namespace for name, however far they're trusted, e.g. a
+    /// nameserver's glue. Unlike [Cache::get], this doesn't count as a hit or a miss.
+    pub fn addresses(&self, namespace: &str, name: &DomainName) -> Vec<IpAddr> {
+        [rr::Type::A, rr::Type::AAAA]
+            .into_iter()
+            .filter_map(|r#type| {
+                let key = Key::new(
+                    namespace,
+                    name,
+                    QuestionType::RrType(r#type),
+                    QuestionClass::RrClass(rr::Class::IN),
+                );
+                self.entry(&key, Trust::Additional, false)
+            })
+            .flat_map(|(answer, ..)| answer.records().to_vec())
+            .filter_map(|rr| match rr.data() {
+                rr::Data::A(addr) => Some(IpAddr::V4(*addr)),
+                rr::Data::AAAA(addr) => Some(IpAddr::V6(*addr)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Caches records in namespace, trusted as trust, as RRsets of those with the same owner,
+    /// type and class. Each replaces the RRset cached before unless that's live and trusted
+    /// further, and expires when its record with the lowest TTL does.
+    ///
+    /// Records with a TTL of zero may only be used for the query they answered (RFC 1035
+    /// section 3.2.1), so an RRset with one isn't cached.
+    pub fn insert(&self, namespace: &str, records: RRset, trust: Trust, provenance: Provenance) {
+        let mut rrsets: Vec<RRset> = Vec::new();
+        for rr in records {
+            let rr = self.bound_ttl(rr);
+            match rrsets.iter_mut().find(|rrset| {
+                rrset[0].name() == rr.name()
+                    && rrset[0].r#type() == rr.r#type()
+                    && rrset[0].class() == rr.class()
+            }) {
+                Some(rrset) => rrset.push(rr),
+                None => rrsets.push(vec![rr]),
+            }
+        }
+        for rrset in rrsets {
+            let Some(ttl) = rrset.iter().map(rr::ResourceRecord::ttl).min() else {
+                continue;
+            };
+            let key = Key::new(
+                namespace,
+                rrset[0].name(),
+                QuestionType::RrType(rrset[0].r#type()),
+                QuestionClass::RrClass(rrset[0].class()),
+            );
+            self.store(key, Answer::Records(rrset), ttl, trust, provenance.clone());
+        }
+    }
+
+    /// Caches a negative answer in namespace to question (RFC 2308), trusted as trust. It
+    /// expires after the lower of the SOA's TTL and its minimum field (section 5), and soa's
+    /// TTL is set to that too.
+    pub fn insert_negative(
+        &self,
+        namespace: &str,
+        question: &Question,
+        answer: Answer,
+        trust: Trust,
+        provenance: Provenance,
+    ) {
+        let answer = answer.map_records(|soa| {
+            let minimum = match soa.data() {
+                rr::Data::SOA { minimum, .. } => *minimum,
+                _ => 0,
+            };
+            self.bound_ttl(soa.clone().with_ttl(soa.ttl().min(minimum)))
+        });
+        let ttl = match &answer {
+            Answer::Records(_) => return,
+            Answer::NoData { soa } | Answer::NxDomain { soa } => soa.ttl(),
+        };
+        let key = Key::new(
+            namespace,
+            question.name(),
+            question.r#type(),
+            question.class(),
+        );
+        self.store(key, answer, ttl, trust, provenance);
+    }
+
+    /// Merges what response to query, classified as classification, says into the cache:
+    /// the records answering the question or the CNAMEs leading towards them, trusted further
+    /// if the response is authoritative; that they or the name don't exist; or a referral's
+    /// NS records and glue. Errors, and negative answers without an SOA to bound their
+    /// lifetime, aren't cached.
+    pub fn insert_classified(
+        &self,
+        namespace: &str,
+        query: &Message,
+        response: &Message,
+        classification: &Classification,
+        provenance: Provenance,
+    ) {
+        let Some(question) = query.questions().first() else {
+            return;
+        };
+        let trust = if response.is_authoritative_answer() {
+            Trust::AuthoritativeAnswer
+        } else {
+            Trust::Answer
+        };
+        match classification {
+            Classification::Answer(records) => {
+                self.insert(namespace, records.clone(), trust, provenance)
+            }
+            Classification::Cname { chain, .. } => {
+                self.insert(namespace, chain.clone(), trust, provenance)
+            }
+            Classification::NoData { soa: Some(soa) } => {
+                let answer = Answer::NoData { soa: soa.clone() };
+                self.insert_negative(namespace, question, answer, trust, provenance)
+            }
+            Classification::NxDomain { soa: Some(soa) } => {
+                let answer = Answer::NxDomain { soa: soa.clone() };
+                self.insert_negative(namespace, question, answer, trust, provenance)
+            }
+            Classification::Referral {
+                nameservers, glue, ..
+            } => {
+                let ns = nameservers.clone();
+                self.insert(namespace, ns, Trust::Authority, provenance.clone());
+                self.insert(namespace, glue.clone(), Trust::Additional, provenance);
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes every entry, returning how many there were.
+    pub fn flush(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut shard = shard.lock().unwrap();
+                let len = shard.entries.len();
+                *shard = Shard::default();
+                len
+            })
+            .sum()
+    }
+
+    /// Removes every entry for name, whatever its type and namespace, returning how many
+    /// there were.
+    pub fn flush_name(&self, name: &DomainName) -> usize {
+        self.shard(name)
+            .lock()
+            .unwrap()
+            .remove_where(|key, _| key.name == *name, self.eviction)
+    }
+
+    /// The live entries for name, whatever their type and namespace, sorted by namespace and
+    /// type. Unlike [Cache::get], this doesn't count as a use of them.
+    pub fn entries(&self, name: &DomainName) -> Vec<CacheEntry> {
+        let now = Instant::now();
+        let shard = self.shard(name).lock().unwrap();
+        let mut entries = shard
+            .entries
+            .iter()
+            .filter(|(key, entry)| key.name == *name && entry.expires_at > now)
+            .map(|(key, entry)| {
+                let elapsed = now.duration_since(entry.stored_at).as_secs() as i32;
+                CacheEntry {
+                    namespace: key.namespace.clone(),
+                    qtype: key.qtype.to_string(),
+                    qclass: key.qclass.to_string(),
+                    answer: match entry.answer {
+                        Answer::Records(_) => "records",
+                        Answer::NoData { .. } => "nodata",
+                        Answer::NxDomain { .. } => "nxdomain",
+                    },
+                    records: entry
+                        .answer
+                        .all_records()
+                        .iter()
+                        .map(|rr| rr.clone().with_ttl((rr.ttl() - elapsed).max(0)).to_string())
+                        .collect(),
+                    trust: format!("{:?}", entry.trust),
+                    provenance: entry.provenance.to_string(),
+                    expires_in: entry.expires_at.duration_since(now).as_secs(),
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| (&a.namespace, &a.qtype).cmp(&(&b.namespace, &b.qtype)));
+        entries
+    }
+
+    /// Removes the expired entries, returning how many there were. Expired entries are never
+    /// returned anyway, so this only frees their memory.
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        self.shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .unwrap()
+                    .remove_where(|_, entry| entry.expires_at <= now, self.eviction)
+            })
+            .sum()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = self.shards.iter().fold((0, 0), |(entries, bytes), shard| {
+            let shard = shard.lock().unwrap();
+            (entries + shard.entries.len(), bytes + shard.bytes)
+        });
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            bytes,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+        }
+    }
+
+    /// The number of times each TTL override has changed a cached record's TTL, by suffix.
+    pub fn ttl_overrides_applied(&self) -> Vec<(String, u64)> {
+        self.overrides.applied_counts()
+    }
+
+    /// Flushes the cache whenever watcher sees the network change, since answers fetched
+    /// through the old network (e.g. from a captive portal or a VPN's resolver) may be wrong
+    /// on the new one. Runs until the watcher stops.
+    pub async fn flush_on_network_change(self: Arc<Self>, mut watcher: NetworkWatcher) {
+        while let Some(change) = watcher.next().await {
+            let flushed = self.flush();
+            info!("Network changed ({change:?}), flushed {flushed} cache entries");
+        }
+    }
+
+    /// rr with its TTL bounded by the overrides.
+    fn bound_ttl(&self, rr: rr::ResourceRecord) -> rr::ResourceRecord {
+        let ttl = self.overrides.apply(rr.name(), rr.ttl().max(0) as u32);
+        rr.with_ttl(ttl.min(i32::MAX as u32) as i32)
+    }
+
+    /// Follows CNAMEs from name to the answer to the question, as [Cache::get].
+    fn follow(
+        &self,
+        namespace: &str,
+        name: &DomainName,
+        qtype: QuestionType,
+        qclass: QuestionClass,
+    ) -> Option<Hit> {
+        let cname = QuestionType::RrType(rr::Type::CNAME);
+        let mut chain = RRset::new();
+        let mut least_trust = Trust::AuthoritativeAnswer;
+        let mut name = name.clone();
+        loop {
+            let key = Key::new(namespace, &name, qtype, qclass);
+            if let Some((answer, provenance, trust)) = self.entry(&key, Trust::Answer, self.rotate)
+            {
+                let answer = match answer {
+                    Answer::Records(records) => {
+                        chain.extend(records);
+                        Answer::Records(chain)
+                    }
+                    // * The CNAMEs are all there is to say about a name an alias leads to
+                    // * that doesn't exist or has no such records.
+                    negative if chain.is_empty() => negative,
+                    _ => Answer::Records(chain),
+                };
+                return Some(Hit {
+                    answer,
+                    provenance,
+                    trust: least_trust.min(trust),
+                });
+            }
+            if qtype == cname || chain.len() >= classify::MAX_CNAME_CHAIN {
+                return None;
+            }
+            let key = Key::new(namespace, &name, cname, qclass);
+            let (answer, _, trust) = self.entry(&key, Trust::Answer, false)?;
+            let alias = answer.records().first()?.clone();
+            let rr::Data::CNAME(target) = alias.data() else {
+                return None;
+            };
+            name = target.clone();
+            least_trust = least_trust.min(trust);
+            chain.push(alias);
+        }
+    }
+
+    /// The answer cached under key, with its TTLs reduced by the time it's been cached, and
+    /// where it came from, unless it's expired or trusted less than min_trust. If rotate is
+    /// set, addresses come rotated as [Cache::with_rotation] says.
+    fn entry(
+        &self,
+        key: &Key,
+        min_trust: Trust,
+        rotate: bool,
+    ) -> Option<(Answer, Provenance, Trust)> {
+        let now = Instant::now();
+        let mut shard = self.shard(&key.name).lock().unwrap();
+        match shard.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                if entry.trust < min_trust {
+                    return None;
+                }
+                let elapsed = now.duration_since(entry.stored_at).as_secs() as i32;
+                let mut answer = entry
+                    .answer
+                    .map_records(|rr| rr.clone().with_ttl((rr.ttl() - elapsed).max(0)));
+                let is_address = [rr::Type::A, rr::Type::AAAA]
+                    .map(QuestionType::RrType)
+                    .contains(&key.qtype);
+                if let (true, true, Answer::Records(records)) = (rotate, is_address, &mut answer) {
+                    let len = records.len() as u64;
+                    if len > 0 {
+                        records.rotate_left((entry.rotations % len) as usize);
+                        entry.rotations += 1;
+                    }
+                }
+                let found = (answer, entry.provenance.clone(), entry.trust);
+                shard.touch(key, self.eviction);
+                Some(found)
+            }
+            Some(_) => {
+                shard.remove(key, self.eviction);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, key: Key, answer: Answer, ttl: i32, trust: Trust, provenance: Provenance) {
+        if ttl <= 0 {
+            return;
+        }
+        let stored_at = Instant::now();
+        let mut shard = self.shard(&key.name).lock().unwrap();
+        if let Some(cached) = shard.entries.get(&key) {
+            if cached.expires_at > stored_at && cached.trust > trust {
+                return;
+            }
+        }
+        let entry = Entry {
+            size: Entry::size(&key, &answer),
+            answer,
+            provenance,
+            trust,
+            stored_at,
+            expires_at: stored_at + Duration::from_secs(ttl as u64),
+            uses: 0,
+            last_used: 0,
+            rotations: 0,
+        };
+        shard.insert(key.clone(), entry, self.eviction);
+        // * Each shard gets an even share of the limits, rounded up.
+        let max_entries = self.max_entries.map(|max| max.div_ceil(self.shards.len()));
+        let max_bytes = self.max_bytes.map(|max| max.div_ceil(self.shards.len()));
+        let evicted = shard.evict(max_entries, max_bytes, self.eviction, &key);
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    fn shard(&self, name: &DomainName) -> &Mutex<Shard> {
+        let idx = self.hasher.hash_one(name) as usize % self.shards.len();
+        &self.shards[idx]
+    }
+}
+
+impl Shard {
+    /// Adds entry, replacing any under key, and counts it as used.
+    fn insert(&mut self, key: Key, mut entry: Entry, eviction: Eviction) {
+        self.remove(&key, eviction);
+        self.clock += 1;
+        entry.uses = 1;
+        entry.last_used = self.clock;
+        self.bytes += entry.size;
+        self.ranks.insert(entry.rank(eviction), key.clone());
+        self.entries.insert(key, entry);
+    }
+
+    /// Counts key's entry as used, moving it back in the eviction order.
+    fn touch(&mut self, key: &Key, eviction: Eviction) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        let Some(key) = self.ranks.remove(&entry.rank(eviction)) else {
+            return;
+        };
+        self.clock += 1;
+        entry.uses += 1;
+        entry.last_used = self.clock;
+        self.ranks.insert(entry.rank(eviction), key);
+    }
+
+    fn remove(&mut self, key: &Key, eviction: Eviction) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.ranks.remove(&entry.rank(eviction));
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Removes the entries f matches, returning how many there were.
+    fn remove_where(&mut self, f: impl Fn(&Key, &Entry) -> bool, eviction: Eviction) -> usize {
+        let keys = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| f(key, entry))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            self.remove(key, eviction);
+        }
+        keys.len()
+    }
+
+    /// Evicts entries, lowest ranked first, until the shard is within max_entries and
+    /// max_bytes, returning how many were live. Expired entries are evicted before any live
+    /// one.
+    ///
+    /// The entry just stored under `stored` is never evicted: with LFU it has the fewest uses
+    /// of all, so would otherwise go first and the cache could never take anything new.
+    fn evict(
+        &mut self,
+        max_entries: Option<usize>,
+        max_bytes: Option<usize>,
+        eviction: Eviction,
+        stored: &Key,
+    ) -> u64 {
+        let over = |shard: &Shard| {
+            max_entries.is_some_and(|max| shard.entries.len() > max)
+                || max_bytes.is_some_and(|max| shard.bytes > max)
+        };
+        if !over(self) {
+            return 0;
+        }
+        let now = Instant::now();
+        self.remove_where(|_, entry| entry.expires_at <= now, eviction);
+        let mut evicted = 0;
+        let mut kept = None;
+        while over(self) {
+            let Some((rank, key)) = self.ranks.pop_first() else {
+                break;
+            };
+            if key == *stored {
+                kept = Some((rank, key));
+                continue;
+            }
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.size;
+                evicted += 1;
+            }
+        }
+        if let Some((rank, key)) = kept {
+            self.ranks.insert(rank, key);
+        }
+        evicted
+    }
+}
+
+impl Entry {
+    /// Where the entry is in the eviction order, lowest first: by when it was last used for
+    /// LRU, and by how often then when for LFU. The shard's clock makes each rank distinct.
+    fn rank(&self, eviction: Eviction) -> (u64, u64) {
+        match eviction {
+            Eviction::Lru => (0, self.last_used),
+            Eviction::Lfu => (self.uses, self.last_used),
+        }
+    }
+
+    /// Roughly the memory an entry for answer under key takes: the key and entry themselves,
+    /// their names' labels, and each record with its data counted at its wire size.
+    fn size(key: &Key, answer: &Answer) -> usize {
+        let name_size = |name: &DomainName| {
+            name.labels()
+                .map(|label| mem::size_of::<String>() + label.len())
+                .sum::<usize>()
+        };
+        let records = answer
+            .all_records()
+            .iter()
+            .map(|rr| {
+                let data = rr.data().serialize().map_or(0, |data| data.len());
+                mem::size_of::<rr::ResourceRecord>() + name_size(rr.name()) + data
+            })
+            .sum::<usize>();
+        mem::size_of::<Key>()
+            + mem::size_of::<Entry>()
+            + key.namespace.len()
+            + name_size(&key.name)
+            + records
+    }
+}
+
+impl Key {
+    fn new(namespace: &str, name: &DomainName, qtype: QuestionType, qclass: QuestionClass) -> Self {
+        Key {
+            namespace: namespace.to_string(),
+            name: name.clone(),
+            qtype,
+            qclass,
+        }
+    }
+}
+
+/// Answers repeat lookups from the cache, and caches the inner resolver's answers, negative
+/// ones included if they come with an SOA.
+pub struct Cached<R> {
+    inner: R,
+    cache: Arc<Cache>,
+    namespace: String,
+}
+
+impl<R: Resolve> Cached<R> {
+    /// Caches in namespace, e.g. the [View::cache_namespace](crate::view::View::cache_namespace)
+    /// of the view inner answers for.
+    pub fn new(inner: R, cache: Arc<Cache>, namespace: &str) -> Self {
+        Cached {
+            inner,
+            cache,
+            namespace: namespace.to_string(),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for Cached<R> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<resolve::Answer>>> {
+        Box::pin(async move {
+            let qname = name.parse::<DomainName>()?;
+            let qclass = QuestionClass::RrClass(rr::Class::IN);
+            if let Some(hit) = self.cache.get(&self.namespace, &qname, qtype, qclass) {
+                provenance::record_answer_source(AnswerSource::Cache);
+                return Ok(Some(hit.answer.into()));
+            }
+            let answer = self.inner.lookup(name, qtype).await?;
+            let Some(answer) = answer else {
+                return Ok(None);
+            };
+            let provenance = Provenance::new(self.inner.name(), None);
+            self.cache.insert(
+                &self.namespace,
+                answer.records().to_vec(),
+                Trust::Answer,
+                provenance.clone(),
+            );
+            let negative = match &answer {
+                resolve::Answer::Records(_) => None,
+                resolve::Answer::NoData { soa, .. } => {
+                    soa.clone().map(|soa| Answer::NoData { soa })
+                }
+                resolve::Answer::NxDomain { soa, .. } => {
+                    soa.clone().map(|soa| Answer::NxDomain { soa })
+                }
+            };
+            if let Some(negative) = negative {
+                // * A negative answer is about the name its CNAMEs lead to.
+                let end = match answer.records().last().map(rr::ResourceRecord::data) {
+                    Some(rr::Data::CNAME(target)) => target.clone(),
+                    _ => qname,
+                };
+                let question = Question::new(end, qtype, qclass);
+                self.cache.insert_negative(
+                    &self.namespace,
+                    &question,
+                    negative,
+                    Trust::Answer,
+                    provenance,
+                );
+            }
+            Ok(Some(answer))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::ResponseCode;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::AtomicUsize;
+
+    const A: QuestionType = QuestionType::RrType(rr::Type::A);
+    const IN: QuestionClass = QuestionClass::RrClass(rr::Class::IN);
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
+
+    fn a(owner: &str, ttl: i32) -> rr::ResourceRecord {
+        rr::ResourceRecord::new(
+            name(owner),
+            rr::Type::A,
+            rr::Class::IN,
+            ttl,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )
+        .unwrap()
+    }
+
+    fn provenance() -> Provenance {
+        Provenance::new("192.0.2.53:53", None)
+    }
+
+    #[test]
+    fn expires_and_decays_ttls() {
+        let cache = Cache::new(4);
+        cache.insert(
+            "default",
+            vec![a("www.example.", 300)],
+            Trust::Answer,
+            provenance(),
+        );
+        // * Names compare case-insensitively.
+        let hit = cache.get("default", &name("WWW.Example."), A, IN).unwrap();
+        assert_eq!(hit.answer, Answer::Records(vec![a("www.example.", 300)]));
+        assert_eq!(hit.provenance.source, "192.0.2.53:53");
+        assert!(cache
+            .get(
+                "default",
+                &name("www.example."),
+                QuestionType::RrType(rr::Type::AAAA),
+                IN
+            )
+            .is_none());
+        assert!(cache.get("lab", &name("www.example."), A, IN).is_none());
+
+        // * Pretend the entry was stored two minutes ago.
+        let key = Key::new("default", &name("www.example."), A, IN);
+        let ago = Duration::from_secs(120);
+        cache
+            .shard(&key.name)
+            .lock()
+            .unwrap()
+            .entries
+            .get_mut(&key)
+            .unwrap()
+            .stored_at -= ago;
+        let hit = cache.get("default", &name("www.example."), A, IN).unwrap();
+        assert_eq!(hit.answer.records()[0].ttl(), 180);
+
+        let mut shard = cache.shard(&key.name).lock().unwrap();
+        shard.entries.get_mut(&key).unwrap().expires_at = Instant::now() - Duration::from_secs(1);
+        drop(shard);
+        assert!(cache.get("default", &name("www.example."), A, IN).is_none());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 3,
+                entries: 0,
+                bytes: 0,
+                evictions: 0,
+                max_entries: None,
+                max_bytes: None,
+            }
+        );
+    }
+
+    #[test]
+    fn skips_uncacheable_answers_and_applies_overrides() -> anyhow::Result<()> {
+        let mut overrides = TtlOverrides::new();
+        overrides.add("internal.", None, Some(30))?;
+        let cache = Cache::new(1).with_ttl_overrides(overrides);
+        cache.insert("default", vec![a("zero.", 0)], Trust::Answer, provenance());
+        cache.insert("default", vec![], Trust::Answer, provenance());
+        assert_eq!(cache.stats().entries, 0);
+
+        cache.insert(
+            "default",
+            vec![a("db.internal.", 3600)],
+            Trust::Answer,
+            provenance(),
+        );
+        let hit = cache.get("default", &name("db.internal."), A, IN).unwrap();
+        assert_eq!(hit.answer.records()[0].ttl(), 30);
+        assert_eq!(
+            cache.ttl_overrides_applied(),
+            vec![(String::from("internal."), 1)]
+        );
+
+        assert_eq!(cache.purge_expired(), 0);
+        cache.insert(
+            "lab",
+            vec![a("db.internal.", 60)],
+            Trust::Answer,
+            provenance(),
+        );
+        cache.insert(
+            "default",
+            vec![a("web.internal.", 60)],
+            Trust::Answer,
+            provenance(),
+        );
+        assert_eq!(cache.flush_name(&name("DB.internal.")), 2);
+        assert_eq!(cache.flush(), 1);
+        assert!(cache.get("default", &name("db.internal."), A, IN).is_none());
+        Ok(())
+    }
+
+    fn insert_a(cache: &Cache, owner: &str) {
+        cache.insert("default", vec![a(owner, 300)], Trust::Answer, provenance());
+    }
+
+    fn cached(cache: &Cache, owner: &str) -> bool {
+        cache.get("default", &name(owner), A, IN).is_some()
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        // * One shard, so the whole limit applies to every entry.
+        let cache = Cache::new(1).with_max_entries(2);
+        insert_a(&cache, "a.example.");
+        insert_a(&cache, "b.example.");
+        assert!(cached(&cache, "a.example."));
+        insert_a(&cache, "c.example.");
+        assert!(cached(&cache, "a.example."));
+        assert!(!cached(&cache, "b.example."));
+        assert!(cached(&cache, "c.example."));
+        // * Replacing an entry doesn't make room for another.
+        insert_a(&cache, "c.example.");
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 1));
+        assert_eq!(stats.max_entries, Some(2));
+
+        // * Expired entries go before any live one, and don't count as evictions.
+        let key = Key::new("default", &name("c.example."), A, IN);
+        cache
+            .shard(&key.name)
+            .lock()
+            .unwrap()
+            .entries
+            .get_mut(&key)
+            .unwrap()
+            .expires_at = Instant::now() - Duration::from_secs(1);
+        insert_a(&cache, "d.example.");
+        assert!(cached(&cache, "a.example."));
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn evicts_least_frequently_used() {
+        let cache = Cache::new(1)
+            .with_max_entries(2)
+            .with_eviction(Eviction::Lfu);
+        insert_a(&cache, "a.example.");
+        insert_a(&cache, "b.example.");
+        for _ in 0..3 {
+            assert!(cached(&cache, "a.example."));
+        }
+        assert!(cached(&cache, "b.example."));
+        // * b was used last, but a more often.
+        insert_a(&cache, "c.example.");
+        assert!(cached(&cache, "a.example."));
+        assert!(!cached(&cache, "b.example."));
+        assert_eq!("LFU".parse::<Eviction>().unwrap(), Eviction::Lfu);
+        assert!("fifo".parse::<Eviction>().is_err());
+    }
+
+    #[test]
+    fn stays_within_max_bytes() {
+        let cache = Cache::new(1);
+        insert_a(&cache, "a.example.");
+        let size = cache.stats().bytes;
+        assert!(size > 0);
+        insert_a(&cache, "b.example.");
+        assert_eq!(cache.stats().bytes, 2 * size);
+        assert_eq!(cache.flush_name(&name("a.example.")), 1);
+        assert_eq!(cache.stats().bytes, size);
+
+        let cache = Cache::new(1).with_max_bytes(size * 3 / 2);
+        insert_a(&cache, "a.example.");
+        insert_a(&cache, "b.example.");
+        assert!(!cached(&cache, "a.example."));
+        assert!(cached(&cache, "b.example."));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (1, size, 1));
+        cache.flush();
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    fn cname(owner: &str, target: &str) -> rr::ResourceRecord {
+        rr::ResourceRecord::new(
+            name(owner),
+            rr::Type::CNAME,
+            rr::Class::IN,
+            300,
+            rr::Data::CNAME(name(target)),
+        )
+        .unwrap()
+    }
+
+    fn a_at(owner: &str, last: u8) -> rr::ResourceRecord {
+        rr::ResourceRecord::new(
+            name(owner),
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, last)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn stores_rrsets_and_follows_cnames() {
+        let cache = Cache::new(4);
+        cache.insert(
+            "default",
+            vec![
+                cname("www.example.", "web.example."),
+                a_at("web.example.", 1),
+                a_at("web.example.", 2),
+            ],
+            Trust::Answer,
+            provenance(),
+        );
+        assert_eq!(cache.stats().entries, 2);
+        let hit = cache.get("default", &name("www.example."), A, IN).unwrap();
+        assert_eq!(
+            hit.answer.records(),
+            [
+                cname("www.example.", "web.example."),
+                a_at("web.example.", 1),
+                a_at("web.example.", 2)
+            ]
+        );
+        // * The target is cached under its own name, and the alias as a CNAME.
+        let hit = cache.get("default", &name("web.example."), A, IN).unwrap();
+        assert_eq!(hit.answer.records().len(), 2);
+        let cnames = QuestionType::RrType(rr::Type::CNAME);
+        let hit = cache
+            .get("default", &name("www.example."), cnames, IN)
+            .unwrap();
+        assert_eq!(hit.answer.records().len(), 1);
+
+        // * An alias whose target isn't cached is a miss.
+        cache.insert(
+            "default",
+            vec![cname("ftp.example.", "files.example.")],
+            Trust::Answer,
+            provenance(),
+        );
+        assert!(cache.get("default", &name("ftp.example."), A, IN).is_none());
+    }
+
+    #[test]
+    fn lists_entries() {
+        let cache = Cache::new(4);
+        cache.insert(
+            "default",
+            vec![
+                cname("www.example.", "web.example."),
+                a("web.example.", 300),
+            ],
+            Trust::Answer,
+            provenance(),
+        );
+        cache.insert(
+            "internal",
+            vec![a("web.example.", 60)],
+            Trust::Answer,
+            provenance(),
+        );
+        let entries = cache.entries(&name("Web.Example."));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.namespace.as_str(), entry.answer))
+                .collect::<Vec<_>>(),
+            [("default", "records"), ("internal", "records")]
+        );
+        assert_eq!(entries[0].qtype, "A");
+        assert_eq!(entries[0].records, [a("web.example.", 300).to_string()]);
+        assert!((59..=60).contains(&entries[1].expires_in));
+        // * Listing isn't a use.
+        assert_eq!(cache.stats().hits, 0);
+        assert!(cache.entries(&name("other.example.")).is_empty());
+    }
+
+    #[test]
+    fn ranks_by_trust() {
+        let cache = Cache::new(1);
+        cache.insert(
+            "default",
+            vec![a_at("ns.example.", 1)],
+            Trust::Additional,
+            provenance(),
+        );
+        // * Glue reaches nameservers, but doesn't answer lookups.
+        assert!(cache.get("default", &name("ns.example."), A, IN).is_none());
+        assert_eq!(
+            cache.addresses("default", &name("ns.example.")),
+            [IpAddr::from([192, 0, 2, 1])]
+        );
+
+        // * An authoritative answer replaces the glue.
+        cache.insert(
+            "default",
+            vec![a_at("ns.example.", 2)],
+            Trust::AuthoritativeAnswer,
+            provenance(),
+        );
+        let hit = cache.get("default", &name("ns.example."), A, IN).unwrap();
+        assert_eq!(hit.answer.records(), [a_at("ns.example.", 2)]);
+        assert_eq!(hit.trust, Trust::AuthoritativeAnswer);
+
+        // * Glue or a forwarded answer doesn't replace it.
+        for trust in [Trust::Additional, Trust::Answer] {
+            cache.insert("default", vec![a_at("ns.example.", 3)], trust, provenance());
+        }
+        let hit = cache.get("default", &name("ns.example."), A, IN).unwrap();
+        assert_eq!(hit.answer.records(), [a_at("ns.example.", 2)]);
+
+        // * Unless it's expired.
+        let key = Key::new("default", &name("ns.example."), A, IN);
+        cache
+            .shard(&key.name)
+            .lock()
+            .unwrap()
+            .entries
+            .get_mut(&key)
+            .unwrap()
+            .expires_at = Instant::now() - Duration::from_secs(1);
+        cache.insert(
+            "default",
+            vec![a_at("ns.example.", 4)],
+            Trust::Additional,
+            provenance(),
+        );
+        assert_eq!(
+            cache.addresses("default", &name("ns.example.")),
+            [IpAddr::from([192, 0, 2, 4])]
+        );
+    }
+
+    #[test]
+    fn merges_referrals() {
+        let cache = Cache::new(1);
+        let ns = rr::ResourceRecord::new(
+            name("example."),
+            rr::Type::NS,
+            rr::Class::IN,
+            3600,
+            rr::Data::NS(name("ns.example.")),
+        )
+        .unwrap();
+        let query = Message::query(&name("www.example."), A);
+        let classification = Classification::Referral {
+            zone: name("example."),
+            nameservers: vec![ns.clone()],
+            glue: vec![a_at("ns.example.", 1)],
+        };
+        let response = query.empty_response(ResponseCode::NoError);
+        cache.insert_classified("default", &query, &response, &classification, provenance());
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.get("default", &name("www.example."), A, IN).is_none());
+        assert_eq!(
+            cache.addresses("default", &name("ns.example.")),
+            [IpAddr::from([192, 0, 2, 1])]
+        );
+
+        // * The nameserver's own authoritative answer then replaces its glue.
+        let query = Message::query(&name("ns.example."), A);
+        let mut response = query.empty_response(ResponseCode::NoError);
+        response.set_authoritative_answer(true);
+        let classification = Classification::Answer(vec![a_at("ns.example.", 2)]);
+        cache.insert_classified("default", &query, &response, &classification, provenance());
+        let hit = cache.get("default", &name("ns.example."), A, IN).unwrap();
+        assert_eq!(hit.trust, Trust::AuthoritativeAnswer);
+        assert_eq!(hit.answer.records(), [a_at("ns.example.", 2)]);
+    }
+
+    #[test]
+    fn rotates_addresses() {
+        let cache = Cache::new(1).with_rotation();
+        cache.insert(
+            "default",
+            vec![
+                cname("www.example.", "web.example."),
+                a_at("web.example.", 1),
+                a_at("web.example.", 2),
+                a_at("web.example.", 3),
+            ],
+            Trust::Answer,
+            provenance(),
+        );
+        let lasts = |owner: &str| match cache.get("default", &name(owner), A, IN).unwrap().answer {
+            Answer::Records(records) => records
+                .iter()
+                .filter_map(|rr| match rr.data() {
+                    rr::Data::A(addr) => Some(addr.octets()[3]),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            answer => panic!("unexpected answer {answer:?}"),
+        };
+        assert_eq!(lasts("www.example."), [1, 2, 3]);
+        // * The rotation is the RRset's, however it's reached.
+        assert_eq!(lasts("web.example."), [2, 3, 1]);
+        assert_eq!(lasts("www.example."), [3, 1, 2]);
+        assert_eq!(lasts("www.example."), [1, 2, 3]);
+        // * Nameserver addresses aren't served to clients, so don't move the rotation on.
+        cache.addresses("default", &name("web.example."));
+        assert_eq!(lasts("web.example."), [2, 3, 1]);
+
+        let cache = Cache::new(1);
+        cache.insert(
+            "default",
+            vec![a_at("web.example.", 1), a_at("web.example.", 2)],
+            Trust::Answer,
+            provenance(),
+        );
+        for _ in 0..2 {
+            let hit = cache.get("default", &name("web.example."), A, IN).unwrap();
+            assert_eq!(
+                hit.answer.records(),
+                [a_at("web.example.", 1), a_at("web.example.", 2)]
+            );
+        }
+    }
+
+    /// Counts the lookups that reach it.
+    struct Counting(AtomicUsize);
+
+    impl Resolve for Counting {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<resolve::Answer>>> {
+            Box::pin(async move {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                if name.starts_with("nope.") {
+                    return Ok(Some(resolve::Answer::NxDomain {
+                        cnames: vec![],
+                        soa: Some(soa()),
+                    }));
+                }
+                Ok(Some(resolve::Answer::Records(vec![a(name, 300)])))
+            })
+        }
+    }
+
+    fn soa() -> rr::ResourceRecord {
+        rr::ResourceRecord::new(
+            name("example."),
+            rr::Type::SOA,
+            rr::Class::IN,
+            3600,
+            rr::Data::SOA {
+                mname: name("ns.example."),
+                rname: name("hostmaster.example."),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_repeat_lookups_from_the_cache() -> anyhow::Result<()> {
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let resolver = Arc::new(Cached::new(
+            Counting(AtomicUsize::new(0)),
+            cache.clone(),
+            "default",
+        ));
+        resolver.lookup("www.example.", A).await?;
+
+        let lookups = (0..8)
+            .map(|_| {
+                let resolver = resolver.clone();
+                tokio::spawn(async move { resolver.lookup("www.example.", A).await })
+            })
+            .collect::<Vec<_>>();
+        for lookup in lookups {
+            assert_eq!(
+                lookup.await??,
+                Some(resolve::Answer::Records(vec![a("www.example.", 300)]))
+            );
+        }
+        assert_eq!(resolver.inner.0.load(Ordering::Relaxed), 1);
+        let hit = cache.get("default", &name("www.example."), A, IN).unwrap();
+        assert_eq!(hit.provenance.source, "counting");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn caches_negative_answers() -> anyhow::Result<()> {
+        let soa = soa();
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let query = Message::query(&name("nope.example."), A);
+        let classification = Classification::NxDomain {
+            soa: Some(soa.clone()),
+        };
+        let response = query.empty_response(ResponseCode::NameError);
+        cache.insert_classified("default", &query, &response, &classification, provenance());
+        let hit = cache.get("default", &name("nope.example."), A, IN).unwrap();
+        // * The SOA's minimum is lower than its TTL, so it bounds how long the answer is kept.
+        assert_eq!(
+            hit.answer,
+            Answer::NxDomain {
+                soa: soa.clone().with_ttl(300)
+            }
+        );
+        assert!(hit.answer.records().is_empty());
+
+        // * Without an SOA there's nothing saying how long the answer holds.
+        let query = Message::query(&name("www.example."), A);
+        let response = query.empty_response(ResponseCode::NoError);
+        let classification = Classification::NoData { soa: None };
+        cache.insert_classified("default", &query, &response, &classification, provenance());
+        assert!(cache.get("default", &name("www.example."), A, IN).is_none());
+
+        let resolver = Cached::new(Counting(AtomicUsize::new(0)), cache, "default");
+        let nxdomain = Some(resolve::Answer::NxDomain {
+            cnames: vec![],
+            soa: Some(soa.with_ttl(300)),
+        });
+        assert_eq!(resolver.lookup("nope.example.", A).await?, nxdomain);
+        assert_eq!(resolver.inner.0.load(Ordering::Relaxed), 0);
+
+        // * The inner resolver's negative answers are cached too.
+        let aaaa = QuestionType::RrType(rr::Type::AAAA);
+        resolver.lookup("nope.example.", aaaa).await?;
+        assert_eq!(resolver.lookup("nope.example.", aaaa).await?, nxdomain);
+        assert_eq!(resolver.inner.0.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use crate::message::{Message, QuestionType, ResponseCode};
+use crate::rr;
+use rg_resolver_common::DomainName;
+
+/// What an upstream response says about the question it answers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Classification {
+    /// The records answering the question, preceded by any CNAMEs leading to them.
+    Answer(Vec<rr::ResourceRecord>),
+    /// A CNAME chain that ends before reaching records of the asked-for type,
+    /// so resolution has to continue at target.
+    Cname {
+        chain: Vec<rr::ResourceRecord>,
+        target: DomainName,
+    },
+    /// The name exists, but has no records of the asked-for type.
+    /// soa bounds how long this may be cached (RFC 2308).
+    NoData { soa: Option<rr::ResourceRecord> },
+    /// The name doesn't exist.
+    NxDomain { soa: Option<rr::ResourceRecord> },
+    /// The server delegated the question to the nameservers for zone.
+    Referral {
+        zone: DomainName,
+        nameservers: Vec<rr::ResourceRecord>,
+        glue: Vec<rr::ResourceRecord>,
+    },
+    /// The server failed or refused to answer, or the response doesn't answer the query.
+    Error(String),
+}
+
+/// The most CNAMEs followed within one response.
+pub(crate) const MAX_CNAME_CHAIN: usize = 16;
+
+/// Classifies response, which should answer the single question of query.
+pub fn classify(query: &Message, response: &Message) -> Classification {
+    let Some(question) = query.questions().first() else {
+        return Classification::Error(String::from("query has no question"));
+    };
+    if !response.is_response() || response.id() != query.id() {
+        return Classification::Error(String::from("response doesn't match the query ID"));
+    }
+    if !response.is_response_to(query) {
+        return Classification::Error(String::from("response question doesn't match the query"));
+    }
+
+    let soa = response
+        .authorities()
+        .iter()
+        .find(|rr| rr.r#type() == rr::Type::SOA)
+        .cloned();
+    match response.response_code() {
+        ResponseCode::NoError => {}
+        // * This includes a CNAME chain leading to a name that doesn't exist.
+        ResponseCode::NameError => return Classification::NxDomain { soa },
+        code => return Classification::Error(format!("server responded with {code:?}")),
+    }
+
+    let (chain, answers, target) =
+        follow_cnames(response.answers(), question.name(), question.r#type());
+    if !answers.is_empty() {
+        let mut records = chain;
+        records.extend(answers);
+        return Classification::Answer(records);
+    }
+    if soa.is_some() {
+        return Classification::NoData { soa };
+    }
+    if !chain.is_empty() {
+        return Classification::Cname { chain, target };
+    }
+
+    let nameservers = response
+        .authorities()
+        .iter()
+        .filter(|rr| rr.r#type() == rr::Type::NS)
+        .cloned()
+        .collect::<Vec<_>>();
+    let Some(zone) = nameservers.first().map(|ns| ns.name().clone()) else {
+        // * NOERROR with neither records, an SOA, nor a delegation is still NODATA (RFC 2308 type 3).
+        return Classification::NoData { soa: None };
+    };
+    if !question.name().is_subdomain_of(&zone) {
+        return Classification::Error(format!(
+            "referral to {zone} doesn't cover {}",
+            question.name()
+        ));
+    }
+    let glue = response
+        .additionals()
+        .iter()
+        .filter(|rr| {
+            nameservers.iter().any(|ns| match ns.data() {
+                rr::Data::NS(host) => host == rr.name(),
+                _ => false,
+            })
+        })
+        .cloned()
+        .collect();
+    Classification::Referral {
+        zone,
+        nameservers,
+        glue,
+    }
+}
+
+/// Follows the CNAMEs in answers starting at name, returning the CNAMEs followed, the records
+/// of type qtype found at the end of the chain, and the name the chain ended at.
+pub(crate) fn follow_cnames(
+    answers: &[rr::ResourceRecord],
+    name: &DomainName,
+    qtype: QuestionType,
+) -> (Vec<rr::ResourceRecord>, Vec<rr::ResourceRecord>, DomainName) {
+    let mut chain = Vec::new();
+    let mut current = name.clone();
+    loop {
+        let found = answers
+            .iter()
+            .filter(|rr| *rr.name() == current && qtype.matches(rr.r#type()))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !found.is_empty() || chain.len() == MAX_CNAME_CHAIN {
+            return (chain, found, current);
+        }
+        let cname = answers
+            .iter()
+            .find(|rr| *rr.name() == current && rr.r#type() == rr::Type::CNAME);
+        let Some(cname) = cname else {
+            return (chain, Vec::new(), current);
+        };
+        let rr::Data::CNAME(target) = cname.data() else {
+            unreachable!("CNAME record without CNAME data");
+        };
+        // * A loop ends the chain; the last name is where resolution would have to continue.
+        if chain
+            .iter()
+            .any(|rr: &rr::ResourceRecord| rr.name() == target)
+            || name == target
+        {
+            chain.push(cname.clone());
+            return (chain, Vec::new(), target.clone());
+        }
+        current = target.clone();
+        chain.push(cname.clone());
+    }
+}
+
+/// Returns true if name is strictly below zone.
+pub(crate) fn is_below(name: &DomainName, zone: &DomainName) -> bool {
+    name != zone && name.is_subdomain_of(zone)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
+
+    fn query(qname: &str, qtype: rr::Type) -> Message {
+        Message::query(&name(qname), QuestionType::RrType(qtype))
+    }
+
+    fn rr(owner: &str, data: rr::Data) -> rr::ResourceRecord {
+        let r#type = match data {
+            rr::Data::A(_) => rr::Type::A,
+            rr::Data::NS(_) => rr::Type::NS,
+            rr::Data::CNAME(_) => rr::Type::CNAME,
+            rr::Data::SOA { .. } => rr::Type::SOA,
+            _ => unimplemented!(),
+        };
+        rr::ResourceRecord::new(name(owner), r#type, rr::Class::IN, 300, data).unwrap()
+    }
+
+    fn a(owner: &str) -> rr::ResourceRecord {
+        rr(owner, rr::Data::A(Ipv4Addr::new(10, 0, 0, 1)))
+    }
+
+    fn cname(owner: &str, target: &str) -> rr::ResourceRecord {
+        rr(owner, rr::Data::CNAME(name(target)))
+    }
+
+    fn soa(zone: &str) -> rr::ResourceRecord {
+        rr(
+            zone,
+            rr::Data::SOA {
+                mname: name(&format!("ns1.{zone}")),
+                rname: name(&format!("hostmaster.{zone}")),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )
+    }
+
+    fn ns(zone: &str, host: &str) -> rr::ResourceRecord {
+        rr(zone, rr::Data::NS(name(host)))
+    }
+
+    #[test]
+    fn answer() {
+        let q = query("www.example.com.", rr::Type::A);
+        let response = q.response(
+            ResponseCode::NoError,
+            vec![a("WWW.example.com.")],
+            vec![],
+            vec![],
+        );
+        assert_eq!(
+            classify(&q, &response),
+            Classification::Answer(vec![a("WWW.example.com.")])
+        );
+    }
+
+    #[test]
+    fn cname_chain() {
+        let q = query("example.com.", rr::Type::A);
+        let answers = vec![
+            cname("example.com.", "edge.cdn.net."),
+            cname("edge.cdn.net.", "a1.cdn.net."),
+            a("a1.cdn.net."),
+        ];
+        let response = q.answer_response(answers.clone());
+        assert_eq!(classify(&q, &response), Classification::Answer(answers));
+
+        // * The chain ends without an address, so the target has to be resolved.
+        let answers = vec![cname("example.com.", "edge.cdn.net.")];
+        let response = q.answer_response(answers.clone());
+        assert_eq!(
+            classify(&q, &response),
+            Classification::Cname {
+                chain: answers,
+                target: name("edge.cdn.net.")
+            }
+        );
+
+        // * Asking for the CNAME itself is answered by it.
+        let q = query("example.com.", rr::Type::CNAME);
+        let answers = vec![cname("example.com.", "edge.cdn.net.")];
+        let response = q.answer_response(answers.clone());
+        assert_eq!(classify(&q, &response), Classification::Answer(answers));
+    }
+
+    #[test]
+    fn cname_loop() {
+        let q = query("a.example.", rr::Type::A);
+        let answers = vec![
+            cname("a.example.", "b.example."),
+            cname("b.example.", "a.example."),
+        ];
+        let response = q.answer_response(answers.clone());
+        assert!(matches!(
+            classify(&q, &response),
+            Classification::Cname { chain, .. } if chain.len() == 2
+        ));
+    }
+
+    #[test]
+    fn nodata_and_nxdomain() {
+        let q = query("example.com.", rr::Type::MX);
+        let response = q.response(
+            ResponseCode::NoError,
+            vec![],
+            vec![soa("example.com.")],
+            vec![],
+        );
+        assert_eq!(
+            classify(&q, &response),
+            Classification::NoData {
+                soa: Some(soa("example.com."))
+            }
+        );
+        let response = q.empty_response(ResponseCode::NoError);
+        assert_eq!(
+            classify(&q, &response),
+            Classification::NoData { soa: None }
+        );
+
+        let response = q.response(ResponseCode::NameError, vec![], vec![soa("com.")], vec![]);
+        assert_eq!(
+            classify(&q, &response),
+            Classification::NxDomain {
+                soa: Some(soa("com."))
+            }
+        );
+    }
+
+    #[test]
+    fn referral() {
+        let q = query("www.example.com.", rr::Type::A);
+        let nameservers = vec![
+            ns("example.com.", "ns1.example.com."),
+            ns("example.com.", "ns2.other.net."),
+        ];
+        let response = q.response(
+            ResponseCode::NoError,
+            vec![],
+            nameservers.clone(),
+            vec![a("ns1.example.com."), a("unrelated.example.com.")],
+        );
+        assert_eq!(
+            classify(&q, &response),
+            Classification::Referral {
+                zone: name("example.com."),
+                nameservers,
+                glue: vec![a("ns1.example.com.")],
+            }
+        );
+
+        let response = q.response(
+            ResponseCode::NoError,
+            vec![],
+            vec![ns("other.com.", "ns1.other.com.")],
+            vec![],
+        );
+        assert!(matches!(classify(&q, &response), Classification::Error(_)));
+    }
+
+    #[test]
+    fn errors() {
+        let q = query("example.com.", rr::Type::A);
+        let response = q.empty_response(ResponseCode::ServerFailure);
+        assert!(matches!(classify(&q, &response), Classification::Error(_)));
+
+        let other = query("example.com.", rr::Type::A);
+        let response = other.response(
+            ResponseCode::NoError,
+            vec![a("example.com.")],
+            vec![],
+            vec![],
+        );
+        assert!(matches!(classify(&q, &response), Classification::Error(_)));
+
+        // * A query isn't a response to itself.
+        assert!(matches!(classify(&q, &q), Classification::Error(_)));
+    }
+
+    #[test]
+    fn below_zone() {
+        let example = name("example.com.");
+        assert!(is_below(&name("www.example.com."), &example));
+        assert!(is_below(&name("com."), &DomainName::root()));
+        assert!(!is_below(&name("Example.com."), &example));
+        assert!(!is_below(&name("badexample.com."), &example));
+    }
+}
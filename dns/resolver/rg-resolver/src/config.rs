@@ -0,0 +1,756 @@
+use crate::classify;
+use crate::interfaces::{self, Interface};
+use crate::net::{self, SourcePorts, Upstreams};
+use crate::netwatch::NetworkWatcher;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// The process-wide upstream configuration, loaded from the system settings the first time
+/// it's needed unless one has been set.
+static UPSTREAMS: LazyLock<Mutex<Option<UpstreamConfig>>> = LazyLock::new(|| Mutex::new(None));
+
+const DNS_PORT: u16 = 53;
+
+/// The most attempts resolv.conf may set, as in glibc.
+const MAX_RESOLV_CONF_ATTEMPTS: u32 = 5;
+
+/// A nameserver queries are forwarded to, and how long to wait for it to answer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameServer {
+    pub addr: SocketAddr,
+    pub timeout: Duration,
+    /// The interface the nameserver is reached through, for those the system configured;
+    /// see [UpstreamConfig::system].
+    pub interface: Option<String>,
+}
+
+impl std::fmt::Display for NameServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.interface {
+            Some(interface) => write!(f, "{} on {interface}", self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+/// The nameservers queries are forwarded to. They're tried in order until they've been
+/// timed, then fastest first; see [crate::rank::Rankings].
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpstreamConfig {
+    pub servers: Vec<NameServer>,
+    /// If set, the servers are raced, with the next queried each time this passes without
+    /// an answer; see [Upstreams::with_stagger].
+    pub stagger: Option<Duration>,
+    /// The times a query is sent to a server that doesn't answer before moving on; see
+    /// [Upstreams::with_attempts].
+    pub attempts: u32,
+    pub source_ports: SourcePorts,
+    /// The most CNAMEs the forwarder follows for one query, across queries for their targets.
+    pub max_cname_chain: usize,
+    /// Whether query names are sent in random case; see [Upstreams::with_case_randomization].
+    pub randomize_case: bool,
+}
+
+impl UpstreamConfig {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Parses nameservers, one per line, of the form `<address>[:<port>] [timeout <ms>]`, with
+    /// IPv6 addresses in brackets if they have a port. A `stagger <ms>` line races them, and
+    /// an `attempts <n>` line sets how many times each is sent a query before moving on. A
+    /// `source-ports <first>-<last>` line pins the local ports queries are sent from, a
+    /// `max-cname-chain <n>` line limits the CNAMEs followed, and a `randomize-case` line sends
+    /// query names in random case.
+    ///
+    /// For example:
+    ///   192.0.2.53
+    ///   [2001:db8::53]:5353 timeout 500
+    ///   stagger 100
+    ///   attempts 3
+    ///   source-ports 20000-29999
+    ///   max-cname-chain 8
+    ///   randomize-case
+    ///
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(config: &str) -> anyhow::Result<Self> {
+        let mut servers = Vec::new();
+        let mut stagger = None;
+        let mut attempts = net::DEFAULT_ATTEMPTS;
+        let mut source_ports = SourcePorts::ephemeral();
+        let mut max_cname_chain = classify::MAX_CNAME_CHAIN;
+        let mut randomize_case = false;
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error =
+                |e: String| anyhow::anyhow!("parsing upstreams: line {}: {e}", line_num + 1);
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields[..] {
+                ["stagger", ms] => {
+                    stagger = Some(
+                        ms.parse::<u64>()
+                            .ok()
+                            .filter(|ms| *ms > 0)
+                            .map(Duration::from_millis)
+                            .ok_or_else(|| error(format!("invalid stagger {ms}")))?,
+                    );
+                    continue;
+                }
+                ["attempts", n] => {
+                    attempts = n
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| error(format!("invalid attempts {n}")))?;
+                    continue;
+                }
+                ["source-ports", range] => {
+                    source_ports = SourcePorts::parse(range).map_err(|e| error(e.to_string()))?;
+                    continue;
+                }
+                ["max-cname-chain", n] => {
+                    max_cname_chain = n
+                        .parse::<usize>()
+                        .map_err(|e| error(format!("invalid max-cname-chain {n}: {e}")))?;
+                    continue;
+                }
+                ["randomize-case"] => {
+                    randomize_case = true;
+                    continue;
+                }
+                ["stagger" | "attempts" | "source-ports" | "max-cname-chain", ..] => {
+                    return Err(error(format!("expected {} <value>", fields[0])));
+                }
+                ["randomize-case", ..] => {
+                    return Err(error(String::from("expected randomize-case on its own")));
+                }
+                _ => {}
+            }
+            let timeout = match fields[1..] {
+                [] => Self::DEFAULT_TIMEOUT,
+                ["timeout", ms] => ms
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis)
+                    .ok_or_else(|| error(format!("invalid timeout {ms}")))?,
+                _ => return Err(error(String::from("expected timeout <ms>"))),
+            };
+            let addr = parse_addr(fields[0]).map_err(|e| error(e.to_string()))?;
+            servers.push(NameServer {
+                addr,
+                timeout,
+                interface: None,
+            });
+        }
+        Ok(UpstreamConfig {
+            stagger,
+            attempts,
+            source_ports,
+            max_cname_chain,
+            randomize_case,
+            ..Self::new(servers)?
+        })
+    }
+
+    /// Parses the nameserver lines of a resolv.conf file, along with the timeout and attempts
+    /// options, which apply to every one. A link-local nameserver's zone (fe80::1%eth0) names
+    /// the interface it's reached through.
+    pub fn parse_resolv_conf(contents: &str) -> anyhow::Result<Self> {
+        let mut servers = Vec::new();
+        let mut timeout = Self::DEFAULT_TIMEOUT;
+        let mut attempts = net::DEFAULT_ATTEMPTS;
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") => {
+                    if let Some((addr, interface)) = fields.next().and_then(parse_zoned_addr) {
+                        servers.push((addr, interface));
+                    }
+                }
+                Some("options") => {
+                    // * Out of range values are clamped, as glibc does.
+                    for option in fields {
+                        if let Some(Ok(seconds)) =
+                            option.strip_prefix("timeout:").map(str::parse::<u64>)
+                        {
+                            timeout = Duration::from_secs(seconds.max(1));
+                        } else if let Some(Ok(n)) =
+                            option.strip_prefix("attempts:").map(str::parse::<u32>)
+                        {
+                            attempts = n.clamp(1, MAX_RESOLV_CONF_ATTEMPTS);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let servers = servers
+            .into_iter()
+            .map(|(addr, interface)| NameServer {
+                addr,
+                timeout,
+                interface,
+            })
+            .collect();
+        Ok(UpstreamConfig {
+            attempts,
+            ..Self::new(servers)?
+        })
+    }
+
+    /// Parses the output of macOS's `scutil --dns`, taking the nameservers of the resolvers
+    /// that answer for every name: those without a domain, before the ones for scoped queries.
+    /// A resolver's if_index names the interface its nameservers are reached through, and its
+    /// timeout applies to them.
+    ///
+    /// For example:
+    ///   DNS configuration
+    ///
+    ///   resolver #1
+    ///     search domain[0] : example.com
+    ///     nameserver[0] : 192.168.1.1
+    ///     nameserver[1] : fe80::1%en0
+    ///     if_index : 6 (en0)
+    ///     flags    : Request A records, Request AAAA records
+    ///
+    ///   resolver #2
+    ///     domain   : local
+    ///     options  : mdns
+    ///     timeout  : 5
+    pub fn parse_scutil_dns(output: &str) -> anyhow::Result<Self> {
+        #[derive(Default)]
+        struct Resolver {
+            servers: Vec<(SocketAddr, Option<String>)>,
+            interface: Option<String>,
+            timeout: Option<Duration>,
+            has_domain: bool,
+        }
+        let mut resolvers: Vec<Resolver> = Vec::new();
+        for line in output.lines() {
+            let line = line.trim();
+            if line.starts_with("DNS configuration") && line.contains("scoped") {
+                break;
+            }
+            if line.starts_with("resolver #") {
+                resolvers.push(Resolver::default());
+                continue;
+            }
+            let (Some((key, value)), Some(resolver)) =
+                (line.split_once(" : "), resolvers.last_mut())
+            else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key.starts_with("nameserver[") {
+                if let Some(server) = parse_zoned_addr(value) {
+                    resolver.servers.push(server);
+                }
+            } else if key == "if_index" {
+                // * e.g. "6 (en0)"
+                resolver.interface = value
+                    .split_once('(')
+                    .and_then(|(_, name)| name.strip_suffix(')'))
+                    .map(str::to_string);
+            } else if key == "timeout" {
+                if let Ok(seconds) = value.parse::<u64>() {
+                    resolver.timeout = Some(Duration::from_secs(seconds.max(1)));
+                }
+            } else if key == "domain" {
+                resolver.has_domain = true;
+            }
+        }
+        let mut servers: Vec<NameServer> = Vec::new();
+        for resolver in resolvers
+            .into_iter()
+            .filter(|resolver| !resolver.has_domain)
+        {
+            for (addr, zone) in resolver.servers {
+                if servers.iter().any(|server| server.addr == addr) {
+                    continue;
+                }
+                servers.push(NameServer {
+                    addr,
+                    timeout: resolver.timeout.unwrap_or(Self::DEFAULT_TIMEOUT),
+                    interface: zone.or_else(|| resolver.interface.clone()),
+                });
+            }
+        }
+        Self::new(servers)
+    }
+
+    /// Reads the nameservers configured at path, in the format of [UpstreamConfig::parse].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let config = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading upstreams {}: {e}", path.display()))?;
+        Self::parse(&config)
+    }
+
+    /// The nameservers the OS is configured to use: those of `scutil --dns` on macOS, those
+    /// in /etc/resolv.conf on other Unixes, or the DNS servers of the network adapters that are
+    /// up on Windows. Each is tied to the interface it's reached through; see
+    /// [UpstreamConfig::on_interfaces].
+    pub fn system() -> anyhow::Result<Self> {
+        platform::system()
+    }
+
+    /// Ties each nameserver not already tied to an interface to the one whose subnet it's on,
+    /// or whose index is its scope, and scopes link-local nameservers to their interface's
+    /// index. Link-local nameservers on no interface that's up can't be reached, so they're
+    /// dropped.
+    fn on_interfaces(self, interfaces: &[Interface]) -> anyhow::Result<Self> {
+        let servers = self
+            .servers
+            .into_iter()
+            .filter_map(|mut server| {
+                let interface = match &server.interface {
+                    Some(name) => interfaces.iter().find(|interface| interface.name == *name),
+                    None => match server.addr {
+                        SocketAddr::V6(addr) if addr.scope_id() != 0 => interfaces
+                            .iter()
+                            .find(|interface| interface.index == addr.scope_id()),
+                        _ => interfaces
+                            .iter()
+                            .find(|interface| interface.is_on_link(server.addr.ip())),
+                    },
+                };
+                if let Some(interface) = interface {
+                    server.interface = Some(interface.name.clone());
+                }
+                if let SocketAddr::V6(addr) = &mut server.addr {
+                    if addr.scope_id() == 0 && interfaces::is_link_local(IpAddr::V6(*addr.ip())) {
+                        addr.set_scope_id(interface?.index);
+                    }
+                }
+                Some(server)
+            })
+            .collect();
+        Self::new(servers).map(|config| UpstreamConfig {
+            servers: config.servers,
+            ..self
+        })
+    }
+
+    /// Upstreams querying the nameservers as configured.
+    pub fn upstreams(&self) -> Upstreams {
+        let upstreams = Upstreams::with_timeouts(
+            self.servers
+                .iter()
+                .map(|server| (server.addr, server.timeout))
+                .collect(),
+        )
+        .with_attempts(self.attempts)
+        .with_source_ports(self.source_ports.clone());
+        let upstreams = match self.stagger {
+            Some(stagger) => upstreams.with_stagger(stagger),
+            None => upstreams,
+        };
+        if self.randomize_case {
+            upstreams.with_case_randomization()
+        } else {
+            upstreams
+        }
+    }
+
+    fn new(servers: Vec<NameServer>) -> anyhow::Result<Self> {
+        if servers.is_empty() {
+            anyhow::bail!("no upstream nameservers configured");
+        }
+        Ok(UpstreamConfig {
+            servers,
+            stagger: None,
+            attempts: net::DEFAULT_ATTEMPTS,
+            source_ports: SourcePorts::ephemeral(),
+            max_cname_chain: classify::MAX_CNAME_CHAIN,
+            randomize_case: false,
+        })
+    }
+}
+
+/// Makes config the process-wide upstream configuration.
+pub fn set_upstreams(config: UpstreamConfig) {
+    *UPSTREAMS.lock().unwrap() = Some(config);
+}
+
+/// Reads the system's nameservers again whenever watcher sees the network change, e.g. on
+/// joining a network with its own, and makes them the process-wide upstream configuration.
+/// This replaces any set with [set_upstreams], so is only for following the system's. Runs
+/// until the watcher stops.
+pub async fn follow_system_on_network_change(mut watcher: NetworkWatcher) {
+    while let Some(change) = watcher.next().await {
+        let config = match UpstreamConfig::system() {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Network changed ({change:?}), but reading its nameservers failed: {e}");
+                continue;
+            }
+        };
+        let mut upstreams = UPSTREAMS.lock().unwrap();
+        if upstreams.as_ref() != Some(&config) {
+            let servers = config.servers.iter().map(NameServer::to_string);
+            info!(
+                "Network changed ({change:?}), forwarding to {}",
+                servers.collect::<Vec<_>>().join(", ")
+            );
+            // * The forwarder starts using them with its next query.
+            *upstreams = Some(config);
+        }
+    }
+}
+
+/// The process-wide upstream configuration: the one set with [set_upstreams], otherwise the
+/// system's.
+pub fn upstreams() -> anyhow::Result<UpstreamConfig> {
+    let mut upstreams = UPSTREAMS.lock().unwrap();
+    if let Some(config) = &*upstreams {
+        return Ok(config.clone());
+    }
+    let config = UpstreamConfig::system()?;
+    *upstreams = Some(config.clone());
+    Ok(config)
+}
+
+/// Parses a nameserver's address as written in system configuration, with an optional zone
+/// (fe80::1%eth0 or fe80::1%2) naming or numbering the interface it's reached through.
+fn parse_zoned_addr(addr: &str) -> Option<(SocketAddr, Option<String>)> {
+    let (ip, zone) = match addr.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (addr, None),
+    };
+    let mut addr = SocketAddr::new(ip.parse().ok()?, DNS_PORT);
+    match (zone, &mut addr) {
+        (None, _) => Some((addr, None)),
+        (Some(zone), SocketAddr::V6(v6)) => match zone.parse::<u32>() {
+            Ok(index) => {
+                v6.set_scope_id(index);
+                Some((addr, None))
+            }
+            Err(_) => Some((addr, Some(zone.to_string()))),
+        },
+        (Some(_), SocketAddr::V4(_)) => None,
+    }
+}
+
+/// Parses an address with an optional port, defaulting to port 53.
+fn parse_addr(addr: &str) -> anyhow::Result<SocketAddr> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    addr.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .map_err(|e| anyhow::anyhow!("invalid address {addr}: {e}"))
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::UpstreamConfig;
+    use crate::interfaces;
+    use std::fs;
+
+    const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+    pub(super) fn system() -> anyhow::Result<UpstreamConfig> {
+        configured()?.on_interfaces(&interfaces::list()?)
+    }
+
+    /// macOS's own resolver doesn't read resolv.conf, which is only kept up to date for other
+    /// programs, so its configuration comes from scutil unless that can't be run.
+    #[cfg(target_os = "macos")]
+    fn configured() -> anyhow::Result<UpstreamConfig> {
+        match std::process::Command::new("scutil").arg("--dns").output() {
+            Ok(output) if output.status.success() => {
+                UpstreamConfig::parse_scutil_dns(&String::from_utf8_lossy(&output.stdout))
+                    .or_else(|_| resolv_conf())
+            }
+            _ => resolv_conf(),
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    fn configured() -> anyhow::Result<UpstreamConfig> {
+        resolv_conf()
+    }
+
+    fn resolv_conf() -> anyhow::Result<UpstreamConfig> {
+        let contents = fs::read_to_string(RESOLV_CONF)
+            .map_err(|e| anyhow::anyhow!("reading {RESOLV_CONF}: {e}"))?;
+        UpstreamConfig::parse_resolv_conf(&contents)
+            .map_err(|e| anyhow::anyhow!("parsing {RESOLV_CONF}: {e}"))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{NameServer, UpstreamConfig, DNS_PORT};
+    use crate::interfaces::{self, adapters, wide_string};
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+    use windows_sys::Win32::NetworkManagement::IpHelper::{
+        GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_MULTICAST, GAA_FLAG_SKIP_UNICAST,
+        IP_ADAPTER_ADDRESSES_LH,
+    };
+    use windows_sys::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+    use windows_sys::Win32::Networking::WinSock::{AF_INET, AF_INET6, SOCKADDR_IN, SOCKADDR_IN6};
+
+    /// Each adapter's DNS servers are its own, so they're tied to it as listed.
+    pub(super) fn system() -> anyhow::Result<UpstreamConfig> {
+        let buf =
+            adapters(GAA_FLAG_SKIP_UNICAST | GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST)?;
+        let mut servers: Vec<NameServer> = Vec::new();
+        let mut adapter = buf.as_ptr().cast::<IP_ADAPTER_ADDRESSES_LH>();
+        // SAFETY: GetAdaptersAddresses wrote linked lists of adapters and their DNS servers
+        // into buf, terminated by null pointers, and buf outlives the walk.
+        unsafe {
+            while !adapter.is_null() {
+                if (*adapter).OperStatus == IfOperStatusUp {
+                    let name = wide_string((*adapter).FriendlyName);
+                    let mut dns = (*adapter).FirstDnsServerAddress;
+                    while !dns.is_null() {
+                        let sockaddr = (*dns).Address.lpSockaddr;
+                        let addr = match (*sockaddr).sa_family {
+                            AF_INET => {
+                                let sin = &*sockaddr.cast::<SOCKADDR_IN>();
+                                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.S_un.S_addr));
+                                Some(SocketAddr::from((ip, DNS_PORT)))
+                            }
+                            AF_INET6 => {
+                                let sin6 = &*sockaddr.cast::<SOCKADDR_IN6>();
+                                let ip = Ipv6Addr::from(sin6.sin6_addr.u.Byte);
+                                // * Site-local fec0:0:0:ffff::/64 addresses are placeholders
+                                // * Windows lists when no IPv6 DNS server is configured.
+                                (ip.segments()[..4] != [0xfec0, 0, 0, 0xffff]).then(|| {
+                                    let scope_id = sin6.Anonymous.sin6_scope_id;
+                                    SocketAddr::V6(SocketAddrV6::new(ip, DNS_PORT, 0, scope_id))
+                                })
+                            }
+                            _ => None,
+                        };
+                        if let Some(addr) = addr {
+                            if !servers.iter().any(|server| server.addr == addr) {
+                                servers.push(NameServer {
+                                    addr,
+                                    timeout: UpstreamConfig::DEFAULT_TIMEOUT,
+                                    interface: Some(name.clone()),
+                                });
+                            }
+                        }
+                        dns = (*dns).Next;
+                    }
+                }
+                adapter = (*adapter).Next;
+            }
+        }
+        UpstreamConfig::new(servers)?.on_interfaces(&interfaces::list()?)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::UpstreamConfig;
+
+    pub(super) fn system() -> anyhow::Result<UpstreamConfig> {
+        anyhow::bail!("reading the system's nameservers isn't supported on this platform")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn server(addr: &str, timeout: Duration) -> NameServer {
+        NameServer {
+            addr: addr.parse().unwrap(),
+            timeout,
+            interface: None,
+        }
+    }
+
+    fn server_on(addr: &str, timeout: Duration, interface: &str) -> NameServer {
+        NameServer {
+            interface: Some(interface.to_string()),
+            ..server(addr, timeout)
+        }
+    }
+
+    #[test]
+    fn parse_config() -> anyhow::Result<()> {
+        let config = UpstreamConfig::parse(
+            "# Local resolver first.\n\
+             192.0.2.53\n\
+             \n\
+             [2001:db8::53]:5353 timeout 500\n\
+             2001:db8::54\n",
+        )?;
+        assert_eq!(
+            config.servers,
+            vec![
+                server("192.0.2.53:53", UpstreamConfig::DEFAULT_TIMEOUT),
+                server("[2001:db8::53]:5353", Duration::from_millis(500)),
+                server("[2001:db8::54]:53", UpstreamConfig::DEFAULT_TIMEOUT),
+            ]
+        );
+        assert_eq!(config.stagger, None);
+        let config = UpstreamConfig::parse("192.0.2.53\nstagger 100\n192.0.2.54\n")?;
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.stagger, Some(Duration::from_millis(100)));
+        let config = UpstreamConfig::parse("attempts 3\n192.0.2.53\n")?;
+        assert_eq!(config.attempts, 3);
+        let config = UpstreamConfig::parse("192.0.2.53\nsource-ports 20000-29999\n")?;
+        assert_eq!(config.source_ports, SourcePorts::range(20000, 29999)?);
+        assert_eq!(config.max_cname_chain, classify::MAX_CNAME_CHAIN);
+        let config = UpstreamConfig::parse("192.0.2.53\nmax-cname-chain 0\n")?;
+        assert_eq!(config.max_cname_chain, 0);
+        assert!(!config.randomize_case);
+        let config = UpstreamConfig::parse("192.0.2.53\nrandomize-case\n")?;
+        assert!(config.randomize_case);
+
+        assert!(UpstreamConfig::parse("").is_err());
+        assert!(UpstreamConfig::parse("ns.example.").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53 timeout").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53 timeout 0").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53 wait 500").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nstagger").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nstagger soon").is_err());
+        assert!(UpstreamConfig::parse("stagger 100").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nattempts 0").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nattempts 2 3").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nsource-ports 20000").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nsource-ports 29999-20000").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nsource-ports 0-100").is_err());
+        assert!(UpstreamConfig::parse("192.0.2.53\nrandomize-case yes").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_resolv_conf() -> anyhow::Result<()> {
+        let config = UpstreamConfig::parse_resolv_conf(
+            "# Generated by NetworkManager\n\
+             search example.com\n\
+             nameserver 192.0.2.1\n\
+             nameserver fe80::1%eth0\n\
+             nameserver 2001:db8::1\n\
+             nameserver fe80::2%3\n\
+             nameserver 192.0.2.2%eth0\n\
+             options edns0 timeout:5 attempts:2\n",
+        )?;
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            config.servers,
+            vec![
+                server("192.0.2.1:53", timeout),
+                server_on("[fe80::1]:53", timeout, "eth0"),
+                server("[2001:db8::1]:53", timeout),
+                server("[fe80::2%3]:53", timeout),
+            ]
+        );
+        assert_eq!(config.attempts, 2);
+        let config = UpstreamConfig::parse_resolv_conf(
+            "nameserver 192.0.2.1\n\
+             options attempts:9\n",
+        )?;
+        assert_eq!(config.attempts, 5);
+        assert_eq!(config.servers[0].timeout, UpstreamConfig::DEFAULT_TIMEOUT);
+        assert!(UpstreamConfig::parse_resolv_conf("search example.com\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_scutil_dns() -> anyhow::Result<()> {
+        let config = UpstreamConfig::parse_scutil_dns(
+            "\n\
+             DNS configuration\n\
+             \n\
+             resolver #1\n  \
+               search domain[0] : example.com\n  \
+               nameserver[0] : 192.168.1.1\n  \
+               nameserver[1] : fe80::1%en1\n  \
+               if_index : 6 (en0)\n  \
+               flags    : Request A records, Request AAAA records\n  \
+               reach    : 0x00020002 (Reachable,Directly Reachable Address)\n\
+             \n\
+             resolver #2\n  \
+               domain   : local\n  \
+               options  : mdns\n  \
+               timeout  : 5\n\
+             \n\
+             resolver #3\n  \
+               nameserver[0] : 10.0.0.53\n  \
+               nameserver[1] : 192.168.1.1\n  \
+               timeout  : 3\n\
+             \n\
+             DNS configuration (for scoped queries)\n\
+             \n\
+             resolver #1\n  \
+               nameserver[0] : 192.168.1.2\n  \
+               if_index : 6 (en0)\n",
+        )?;
+        let timeout = UpstreamConfig::DEFAULT_TIMEOUT;
+        assert_eq!(
+            config.servers,
+            vec![
+                server_on("192.168.1.1:53", timeout, "en0"),
+                // * The zone is more specific than the resolver's interface.
+                server_on("[fe80::1]:53", timeout, "en1"),
+                server("10.0.0.53:53", Duration::from_secs(3)),
+            ]
+        );
+        assert!(UpstreamConfig::parse_scutil_dns(
+            "DNS configuration\n\nresolver #1\n  domain : local\n  nameserver[0] : 224.0.0.251\n"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn on_interfaces() -> anyhow::Result<()> {
+        let interfaces = [
+            Interface {
+                name: String::from("lo"),
+                index: 1,
+                addrs: vec![("127.0.0.1".parse()?, 8), ("::1".parse()?, 128)],
+            },
+            Interface {
+                name: String::from("eth0"),
+                index: 2,
+                addrs: vec![("192.168.1.10".parse()?, 24), ("fe80::10".parse()?, 64)],
+            },
+        ];
+        let timeout = UpstreamConfig::DEFAULT_TIMEOUT;
+        let config = UpstreamConfig::parse_resolv_conf(
+            "nameserver 127.0.0.53\n\
+             nameserver 192.168.1.1\n\
+             nameserver 198.51.100.53\n\
+             nameserver fe80::1%eth0\n\
+             nameserver fe80::2%2\n\
+             nameserver fe80::3%wlan0\n\
+             nameserver fe80::4\n\
+             options attempts:3\n",
+        )?
+        .on_interfaces(&interfaces)?;
+        assert_eq!(
+            config.servers,
+            vec![
+                server_on("127.0.0.53:53", timeout, "lo"),
+                server_on("192.168.1.1:53", timeout, "eth0"),
+                // * Reached through the default route, whichever interface that is.
+                server("198.51.100.53:53", timeout),
+                server_on("[fe80::1%2]:53", timeout, "eth0"),
+                server_on("[fe80::2%2]:53", timeout, "eth0"),
+                // * wlan0 is down, and fe80::4 is on eth0's link-local subnet.
+                server_on("[fe80::4%2]:53", timeout, "eth0"),
+            ]
+        );
+        // * The rest of the configuration is kept.
+        assert_eq!(config.attempts, 3);
+        assert!(
+            UpstreamConfig::parse_resolv_conf("nameserver fe80::3%wlan0\n")?
+                .on_interfaces(&interfaces)
+                .is_err()
+        );
+        Ok(())
+    }
+}
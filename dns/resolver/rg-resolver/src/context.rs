@@ -0,0 +1,89 @@
+use std::future::Future;
+use tracing::Instrument;
+
+/// The longest correlation ID accepted.
+pub const MAX_CORRELATION_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Checks that a client-supplied correlation ID is safe to copy into logs: printable ASCII
+/// without spaces, commas, or quotes, and at most MAX_CORRELATION_ID_LEN characters.
+pub fn validate_correlation_id(id: &str) -> anyhow::Result<()> {
+    if id.is_empty() || id.len() > MAX_CORRELATION_ID_LEN {
+        anyhow::bail!(
+            "correlation ID must be 1 to {MAX_CORRELATION_ID_LEN} characters, got {}",
+            id.len()
+        );
+    }
+    if let Some(c) = id
+        .chars()
+        .find(|c| !c.is_ascii_graphic() || matches!(c, ',' | '"'))
+    {
+        anyhow::bail!("correlation ID contains invalid character {c:?}");
+    }
+    Ok(())
+}
+
+/// Runs request with id as its correlation ID, which is recorded on a tracing span around it
+/// and in the audit log, and can be added to errors with [tag_error].
+pub async fn with_correlation_id<F: Future>(id: String, request: F) -> F::Output {
+    let span = tracing::info_span!("request", correlation_id = %id);
+    CORRELATION_ID.scope(id, request.instrument(span)).await
+}
+
+/// The correlation ID of the request being handled, if the client supplied one.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Adds the correlation ID of the request being handled, if any, to e so the client can match
+/// the error to its request.
+pub fn tag_error(e: anyhow::Error) -> anyhow::Error {
+    match correlation_id() {
+        Some(id) => anyhow::anyhow!("{e} (correlation ID {id})"),
+        None => e,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn propagates_through_request() {
+        assert_eq!(correlation_id(), None);
+        let id = with_correlation_id(String::from("req-42"), async {
+            // * Still set after an await point.
+            tokio::task::yield_now().await;
+            correlation_id()
+        })
+        .await;
+        assert_eq!(id.as_deref(), Some("req-42"));
+        assert_eq!(correlation_id(), None);
+    }
+
+    #[tokio::test]
+    async fn tags_errors() {
+        let e = with_correlation_id(String::from("req-42"), async {
+            tag_error(anyhow::anyhow!("forwarding google.com.: timed out"))
+        })
+        .await;
+        assert_eq!(
+            e.to_string(),
+            "forwarding google.com.: timed out (correlation ID req-42)"
+        );
+        assert_eq!(tag_error(anyhow::anyhow!("failed")).to_string(), "failed");
+    }
+
+    #[test]
+    fn validates_ids() {
+        assert!(validate_correlation_id("4bf92f3577b34da6-a3ce929d0e0e4736").is_ok());
+        assert!(validate_correlation_id("").is_err());
+        assert!(validate_correlation_id(&"a".repeat(MAX_CORRELATION_ID_LEN + 1)).is_err());
+        assert!(validate_correlation_id("a b").is_err());
+        assert!(validate_correlation_id("a,b").is_err());
+        assert!(validate_correlation_id("line\nbreak").is_err());
+    }
+}
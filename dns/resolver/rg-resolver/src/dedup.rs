@@ -0,0 +1,143 @@
+use crate::message::{Message, Question};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Remembers the transactions completed within the last window, so a response an upstream
+/// sent more than once isn't processed again or counted towards its RTT.
+///
+/// A transaction is identified by the message ID and the question.
+pub struct DuplicateFilter {
+    window: Duration,
+    completed: HashMap<u16, Vec<(Instant, Vec<Question>)>>,
+    /// The completed transactions, oldest first, so they can be expired in order.
+    order: VecDeque<(Instant, u16)>,
+    duplicates: u64,
+}
+
+impl DuplicateFilter {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(2);
+
+    pub fn new(window: Duration) -> Self {
+        DuplicateFilter {
+            window,
+            completed: HashMap::new(),
+            order: VecDeque::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Records that the transaction response belongs to has completed.
+    pub fn complete(&mut self, response: &Message) {
+        self.expire();
+        let now = Instant::now();
+        self.completed
+            .entry(response.id())
+            .or_default()
+            .push((now, response.questions().to_vec()));
+        self.order.push_back((now, response.id()));
+    }
+
+    /// Returns true, and counts the duplicate, if response belongs to a transaction that
+    /// completed within the window.
+    pub fn is_duplicate(&mut self, response: &Message) -> bool {
+        self.expire();
+        let duplicate = self.completed.get(&response.id()).is_some_and(|completed| {
+            completed
+                .iter()
+                .any(|(_, questions)| questions == response.questions())
+        });
+        if duplicate {
+            self.duplicates += 1;
+        }
+        duplicate
+    }
+
+    /// Returns true if a transaction with ID id completed within the window, so a new one
+    /// under it could be taken for a duplicate.
+    pub fn completed_recently(&mut self, id: u16) -> bool {
+        self.expire();
+        self.completed.contains_key(&id)
+    }
+
+    /// The number of duplicate responses detected.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    fn expire(&mut self) {
+        while let Some(&(at, id)) = self.order.front() {
+            if at.elapsed() < self.window {
+                break;
+            }
+            self.order.pop_front();
+            if let Some(completed) = self.completed.get_mut(&id) {
+                completed.retain(|(completed_at, _)| *completed_at != at);
+                if completed.is_empty() {
+                    self.completed.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+impl Default for DuplicateFilter {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{self, ResponseCode};
+
+    fn response(name: &str) -> Message {
+        message::address_query(&name.parse().unwrap()).response(
+            ResponseCode::NoError,
+            vec![],
+            vec![],
+            vec![],
+        )
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let mut filter = DuplicateFilter::default();
+        let first = response("google.com.");
+        assert!(!filter.is_duplicate(&first));
+        filter.complete(&first);
+        assert!(filter.is_duplicate(&first));
+        assert!(filter.is_duplicate(&first));
+        assert_eq!(filter.duplicates(), 2);
+
+        // * A different transaction isn't a duplicate, even for the same name.
+        assert!(!filter.is_duplicate(&response("google.com.")));
+        assert_eq!(filter.duplicates(), 2);
+        assert!(filter.completed_recently(first.id()));
+    }
+
+    #[test]
+    fn same_id_different_question() -> anyhow::Result<()> {
+        let mut filter = DuplicateFilter::default();
+        let first = response("google.com.");
+        filter.complete(&first);
+
+        let mut buf = first.serialize()?;
+        // * Rewrite the first label to keep the ID but change the question.
+        buf[13] = b'h';
+        let other = Message::parse(&buf)?;
+        assert_eq!(other.id(), first.id());
+        assert!(!filter.is_duplicate(&other));
+        Ok(())
+    }
+
+    #[test]
+    fn window_expires() {
+        let mut filter = DuplicateFilter::new(Duration::ZERO);
+        let first = response("google.com.");
+        filter.complete(&first);
+        assert!(!filter.is_duplicate(&first));
+        assert!(filter.completed.is_empty());
+        assert!(filter.order.is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+//! Querying one nameserver directly and showing its response as dig does, for the binary's dig
+//! subcommand.
+
+use crate::config::UpstreamConfig;
+use crate::edns::{self, Edns};
+use crate::exchange::{self, Exchange};
+use crate::message::{Message, QueryBuilder, QuestionClass, QuestionType};
+use crate::resolve;
+use crate::rr;
+use crate::soa::Serial;
+use crate::stub::DNS_PORT;
+use crate::transport::Transport;
+use crate::tsig;
+use crate::xfr::{Transferred, ZoneTransfer};
+use rg_resolver_common::DomainName;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// How long to wait for a response unless told otherwise, as dig does.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A query for one nameserver and how to show its response, built up from dig's arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dig {
+    /// The nameserver's address or hostname. The system's first nameserver if unset.
+    pub server: Option<String>,
+    pub port: u16,
+    /// The root if unset, as dig asks for the root's nameservers when given no name.
+    pub name: Option<DomainName>,
+    /// A if unset, or NS for the root.
+    pub qtype: Option<QuestionType>,
+    /// IN if unset.
+    pub qclass: Option<QuestionClass>,
+    /// For an IXFR, the serial of the version of the zone the client has. Without one, the
+    /// whole zone is transferred.
+    pub serial: Option<Serial>,
+    pub transport: Transport,
+    pub recursion_desired: bool,
+    /// Whether to show only the answers' data rather than the whole response.
+    pub short: bool,
+    pub timeout: Duration,
+    /// The key to sign the query with, whose signature the response then has to carry.
+    pub key: Option<tsig::Key>,
+}
+
+impl Dig {
+    pub fn new() -> Self {
+        Dig {
+            server: None,
+            port: DNS_PORT,
+            name: None,
+            qtype: None,
+            qclass: None,
+            serial: None,
+            transport: Transport::Udp,
+            recursion_desired: true,
+            short: false,
+            timeout: DEFAULT_TIMEOUT,
+            key: None,
+        }
+    }
+
+    /// Takes one of dig's free-form arguments: @server, a +option, ixfr=<serial>, or the name,
+    /// type or class to ask about. A word that could be a type or class is taken as one, as dig
+    /// does, unless one has already been given.
+    pub fn apply(&mut self, arg: &str) -> anyhow::Result<()> {
+        if let Some(server) = arg.strip_prefix('@') {
+            self.server = Some(server.to_string());
+        } else if let Some(option) = arg.strip_prefix('+') {
+            self.apply_option(option)?;
+        } else if let Some(serial) = arg.strip_prefix("ixfr=").or(arg.strip_prefix("IXFR=")) {
+            let serial = serial
+                .parse::<u32>()
+                .map_err(|e| anyhow::anyhow!("invalid serial {serial}: {e}"))?;
+            self.qtype = Some(QuestionType::Ixfr);
+            self.serial = Some(Serial(serial));
+        } else if let (None, Ok(qtype)) = (self.qtype, arg.parse::<QuestionType>()) {
+            self.qtype = Some(qtype);
+        } else if let (None, Ok(qclass)) = (self.qclass, arg.parse::<QuestionClass>()) {
+            self.qclass = Some(qclass);
+        } else if self.name.is_none() {
+            self.name = Some(absolute(arg)?);
+        } else {
+            anyhow::bail!("unexpected argument {arg}");
+        }
+        Ok(())
+    }
+
+    fn apply_option(&mut self, option: &str) -> anyhow::Result<()> {
+        match option.split_once('=') {
+            None if option == "tcp" || option == "vc" => self.transport = Transport::Tcp,
+            None if option == "udp" || option == "notcp" || option == "novc" => {
+                self.transport = Transport::Udp
+            }
+            None if option == "recurse" => self.recursion_desired = true,
+            None if option == "norecurse" => self.recursion_desired = false,
+            None if option == "short" => self.short = true,
+            None if option == "noshort" => self.short = false,
+            Some(("time", secs)) => {
+                let secs = secs
+                    .parse::<u64>()
+                    .map_err(|e| anyhow::anyhow!("invalid timeout {secs}: {e}"))?;
+                // * As with dig, a query is always given at least a second.
+                self.timeout = Duration::from_secs(secs.max(1));
+            }
+            _ => anyhow::bail!("unknown option +{option}"),
+        }
+        Ok(())
+    }
+
+    /// Asks for the names of addr, from its PTR records.
+    pub fn reverse(&mut self, addr: IpAddr) -> anyhow::Result<()> {
+        self.name = Some(resolve::reverse_name(addr).parse()?);
+        self.qtype = Some(QuestionType::RrType(rr::Type::PTR));
+        Ok(())
+    }
+
+    /// The query to send, advertising the default EDNS payload size as dig does.
+    pub fn query(&self) -> Message {
+        let (name, default_qtype) = match &self.name {
+            Some(name) => (name.clone(), QuestionType::RrType(rr::Type::A)),
+            None => (DomainName::root(), QuestionType::RrType(rr::Type::NS)),
+        };
+        QueryBuilder::new()
+            .recursion_desired(self.recursion_desired)
+            .question(
+                &name,
+                self.qtype.unwrap_or(default_qtype),
+                self.qclass.unwrap_or(QuestionClass::RrClass(rr::Class::IN)),
+            )
+            .edns(Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE))
+            .build()
+    }
+
+    /// The address of the nameserver to query, looking up its hostname if it was given one.
+    pub async fn server_addr(&self) -> anyhow::Result<SocketAddr> {
+        let Some(server) = &self.server else {
+            let system = UpstreamConfig::system()?;
+            let first = system
+                .servers
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("no nameservers configured"))?;
+            return Ok(SocketAddr::new(first.addr.ip(), self.port));
+        };
+        if let Ok(ip) = server.parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, self.port));
+        }
+        tokio::net::lookup_host((server.as_str(), self.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("looking up server {server}: {e}"))?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("server {server} has no addresses"))
+    }
+
+    /// Sends the query, returning the server it went to and its response. A truncated UDP
+    /// response is fetched again over TCP.
+    pub async fn send(&self) -> anyhow::Result<(SocketAddr, Message)> {
+        let server = self.server_addr().await?;
+        let exchange: Box<dyn Exchange> = match self.transport {
+            Transport::Udp => Box::new(exchange::Udp {
+                timeout: self.timeout,
+            }),
+            Transport::Tcp => Box::new(exchange::Tcp {
+                timeout: self.timeout,
+            }),
+            Transport::Tls => anyhow::bail!("queries over TLS aren't supported"),
+        };
+        let mut query = self.query();
+        if let Some(key) = &self.key {
+            key.sign(&mut query, None)?;
+        }
+        let response = exchange.exchange(server, &query).await?;
+        if let Some(key) = &self.key {
+            let request_mac = query.tsig().map(tsig::Tsig::mac);
+            key.verify(&response, request_mac)
+                .map_err(|e| anyhow::anyhow!("couldn't verify signature: {e}"))?;
+        }
+        Ok((server, response))
+    }
+
+    /// Returns true if the query is for a zone transfer, which takes a stream of messages
+    /// rather than one response; see [Dig::transfer].
+    pub fn is_transfer(&self) -> bool {
+        matches!(self.qtype, Some(QuestionType::Afxr | QuestionType::Ixfr))
+    }
+
+    /// Starts transferring the zone named by the query, always over TCP.
+    pub async fn transfer(&self) -> anyhow::Result<ZoneTransfer> {
+        let server = self.server_addr().await?;
+        let zone = self.name.clone().unwrap_or_else(DomainName::root);
+        match (self.qtype, self.serial) {
+            (Some(QuestionType::Ixfr), Some(serial)) => {
+                ZoneTransfer::ixfr(server, &zone, serial, self.timeout, self.key.as_ref()).await
+            }
+            _ => ZoneTransfer::axfr(server, &zone, self.timeout, self.key.as_ref()).await,
+        }
+    }
+
+    /// A record of a transfer as dig shows it: just its data with +short.
+    pub fn render_transferred(&self, transferred: &Transferred) -> String {
+        match (self.short, transferred) {
+            (false, transferred) => transferred.to_string(),
+            (true, Transferred::Record(record)) => record.data().to_string(),
+            (true, Transferred::Removed(record)) => format!("- {}", record.data()),
+            (true, Transferred::Added(record)) => format!("+ {}", record.data()),
+        }
+    }
+
+    /// The response as dig shows it: just the answers' data with +short, otherwise the whole
+    /// message followed by how long it took and where it came from.
+    pub fn render(&self, server: SocketAddr, response: &Message, elapsed: Duration) -> String {
+        if self.short {
+            return response
+                .answers()
+                .iter()
+                .map(|answer| format!("{}\n", answer.data()))
+                .collect();
+        }
+        let transport = self.transport.to_string().to_ascii_uppercase();
+        format!(
+            ";; Got answer:\n{response}\n;; Query time: {} msec\n;; SERVER: {}#{}({}) ({transport})\n",
+            elapsed.as_millis(),
+            server.ip(),
+            server.port(),
+            server.ip()
+        )
+    }
+}
+
+impl Default for Dig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// name, made absolute if it's relative, as names on the command line usually are.
+fn absolute(name: &str) -> anyhow::Result<DomainName> {
+    let name = name.parse::<DomainName>()?;
+    if name.is_absolute() {
+        Ok(name)
+    } else {
+        Ok(name.join(&DomainName::root())?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::FakeNameserver;
+    use std::net::Ipv4Addr;
+
+    fn dig_with(args: &[&str]) -> anyhow::Result<Dig> {
+        let mut dig = Dig::new();
+        for arg in args {
+            dig.apply(arg)?;
+        }
+        Ok(dig)
+    }
+
+    #[test]
+    fn apply() -> anyhow::Result<()> {
+        let dig = dig_with(&[
+            "@192.0.2.53",
+            "example.com",
+            "mx",
+            "+norecurse",
+            "+tcp",
+            "+time=2",
+        ])?;
+        assert_eq!(dig.server.as_deref(), Some("192.0.2.53"));
+        assert_eq!(dig.name, Some("example.com.".parse()?));
+        assert_eq!(dig.qtype, Some(QuestionType::RrType(rr::Type::MX)));
+        assert!(!dig.recursion_desired);
+        assert_eq!(dig.transport, Transport::Tcp);
+        assert_eq!(dig.timeout, Duration::from_secs(2));
+
+        // * Once a type and class are given, words that could be either are names.
+        let dig = dig_with(&["ns", "ch", "a"])?;
+        assert_eq!(dig.qtype, Some(QuestionType::RrType(rr::Type::NS)));
+        assert_eq!(dig.qclass, Some(QuestionClass::RrClass(rr::Class::CH)));
+        assert_eq!(dig.name, Some("a.".parse()?));
+
+        let dig = dig_with(&["example.com", "ixfr=2024010101"])?;
+        assert_eq!(dig.qtype, Some(QuestionType::Ixfr));
+        assert_eq!(dig.serial, Some(Serial(2024010101)));
+        assert!(dig.is_transfer());
+        assert!(!dig_with(&["example.com", "soa"])?.is_transfer());
+
+        assert!(dig_with(&["+bogus"]).is_err());
+        assert!(dig_with(&["+time=soon"]).is_err());
+        assert!(dig_with(&["one.example", "two.example"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn query() -> anyhow::Result<()> {
+        // * With no name, dig asks for the root's nameservers.
+        let query = Dig::new().query();
+        let question = &query.questions()[0];
+        assert_eq!(question.name(), &DomainName::root());
+        assert_eq!(question.r#type(), QuestionType::RrType(rr::Type::NS));
+        assert!(query.is_recursion_desired());
+        assert!(query.edns().is_some());
+
+        let mut dig = dig_with(&["+norecurse"])?;
+        dig.reverse(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))?;
+        let query = dig.query();
+        let question = &query.questions()[0];
+        assert_eq!(question.name(), &"1.2.0.192.in-addr.arpa.".parse()?);
+        assert_eq!(question.r#type(), QuestionType::RrType(rr::Type::PTR));
+        assert!(!query.is_recursion_desired());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_and_render() -> anyhow::Result<()> {
+        let answer = rr::ResourceRecord::new(
+            "www.example.com.".parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )?;
+        let server = FakeNameserver::new(vec![answer]).start().await?;
+        for transport in ["+udp", "+tcp"] {
+            let mut dig = dig_with(&["@127.0.0.1", "www.example.com", transport])?;
+            dig.port = server.addr.port();
+            let (from, response) = dig.send().await?;
+            assert_eq!(from, server.addr);
+            let shown = dig.render(from, &response, Duration::from_millis(3));
+            assert!(shown.contains(";; ANSWER SECTION:\nwww.example.com.\t300\tIN\tA\t192.0.2.1\n"));
+            assert!(shown.ends_with(&format!(
+                ";; Query time: 3 msec\n;; SERVER: 127.0.0.1#{0}(127.0.0.1) ({1})\n",
+                server.addr.port(),
+                transport[1..].to_ascii_uppercase()
+            )));
+
+            dig.short = true;
+            assert_eq!(dig.render(from, &response, Duration::ZERO), "192.0.2.1\n");
+        }
+        assert_eq!(server.udp_queries(), 1);
+        assert_eq!(server.tcp_queries(), 1);
+        Ok(())
+    }
+}
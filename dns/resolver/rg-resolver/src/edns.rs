@@ -0,0 +1,260 @@
+use crate::wire::{Full, Writer};
+use bytes::{Buf, BufMut};
+
+/// The RR type of the OPT pseudo-record (RFC 6891).
+const OPT_TYPE: u16 = 41;
+
+/// The option code of the Padding option (RFC 7830).
+const PADDING_CODE: u16 = 12;
+
+/// The size of an option's code and length fields.
+const OPTION_HEADER_LEN: usize = 4;
+
+/// The UDP payload size advertised when none is configured, as recommended by DNS Flag Day 2020.
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+/// The EDNS(0) parameters carried by a message's OPT pseudo-record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edns {
+    /// The largest UDP payload the sender can reassemble.
+    pub udp_payload_size: u16,
+    /// The upper 8 bits of the 12-bit response code.
+    pub extended_rcode: u8,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EdnsOption {
+    /// The given number of zero bytes, only there to disguise the message's size.
+    Padding(u16),
+    Unknown {
+        code: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Self {
+        Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    /// Returns true if unparsed starts with an OPT pseudo-record.
+    ///
+    /// The OPT record is always owned by the root, so the peek doesn't need to parse a name.
+    pub fn is_next(unparsed: &[u8]) -> bool {
+        unparsed.len() >= 3
+            && unparsed[0] == 0
+            && u16::from_be_bytes([unparsed[1], unparsed[2]]) == OPT_TYPE
+    }
+
+    pub fn parse(unparsed: &mut &[u8]) -> anyhow::Result<Self> {
+        if !Self::is_next(unparsed) {
+            anyhow::bail!("parsing OPT record: not an OPT record");
+        }
+        unparsed.advance(3);
+        if unparsed.remaining() < 8 {
+            anyhow::bail!("parsing OPT record: incomplete record");
+        }
+        let udp_payload_size = unparsed.get_u16();
+        let extended_rcode = unparsed.get_u8();
+        let version = unparsed.get_u8();
+        let flags = unparsed.get_u16();
+        let data_len = unparsed.get_u16() as usize;
+        if unparsed.remaining() < data_len {
+            anyhow::bail!("parsing OPT record: incomplete data");
+        }
+        let mut data = &unparsed[..data_len];
+        unparsed.advance(data_len);
+
+        let mut options = Vec::new();
+        while data.has_remaining() {
+            if data.remaining() < OPTION_HEADER_LEN {
+                anyhow::bail!("parsing OPT record: incomplete option");
+            }
+            let code = data.get_u16();
+            let len = data.get_u16() as usize;
+            if data.remaining() < len {
+                anyhow::bail!("parsing OPT record: incomplete option {code}");
+            }
+            let option = match code {
+                PADDING_CODE => EdnsOption::Padding(len as u16),
+                _ => EdnsOption::Unknown {
+                    code,
+                    data: data[..len].to_vec(),
+                },
+            };
+            data.advance(len);
+            options.push(option);
+        }
+
+        Ok(Edns {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok: flags & 0x8000 != 0,
+            options,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for option in &self.options {
+            match option {
+                EdnsOption::Padding(len) => {
+                    data.put_u16(PADDING_CODE);
+                    data.put_u16(*len);
+                    data.put_bytes(0, *len as usize);
+                }
+                EdnsOption::Unknown {
+                    code,
+                    data: option_data,
+                } => {
+                    data.put_u16(*code);
+                    data.put_u16(option_data.len() as u16);
+                    data.put_slice(option_data);
+                }
+            }
+        }
+
+        let mut buf = Vec::new();
+        buf.put_u8(0);
+        buf.put_u16(OPT_TYPE);
+        buf.put_u16(self.udp_payload_size);
+        buf.put_u8(self.extended_rcode);
+        buf.put_u8(self.version);
+        buf.put_u16(if self.dnssec_ok { 0x8000 } else { 0 });
+        buf.put_u16(data.len() as u16);
+        buf.append(&mut data);
+        buf
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> Result<(), Full> {
+        w.put_u8(0)?;
+        w.put_u16(OPT_TYPE)?;
+        w.put_u16(self.udp_payload_size)?;
+        w.put_u8(self.extended_rcode)?;
+        w.put_u8(self.version)?;
+        w.put_u16(if self.dnssec_ok { 0x8000 } else { 0 })?;
+        // * The data length is filled in once the options are written.
+        let len_offset = w.len();
+        w.put_u16(0)?;
+        for option in &self.options {
+            match option {
+                EdnsOption::Padding(len) => {
+                    w.put_u16(PADDING_CODE)?;
+                    w.put_u16(*len)?;
+                    w.put_zeros(*len as usize)?;
+                }
+                EdnsOption::Unknown { code, data } => {
+                    w.put_u16(*code)?;
+                    w.put_u16(data.len() as u16)?;
+                    w.put_slice(data)?;
+                }
+            }
+        }
+        w.patch_u16(len_offset, (w.len() - len_offset - 2) as u16);
+        Ok(())
+    }
+
+    /// Replaces any padding with enough to bring a message of unpadded_len bytes,
+    /// which includes this record without padding, to the size chosen by policy.
+    pub fn pad(&mut self, unpadded_len: usize, policy: Padding) {
+        self.options
+            .retain(|option| !matches!(option, EdnsOption::Padding(_)));
+        if let Some(len) = policy.padding_len(unpadded_len) {
+            self.options.push(EdnsOption::Padding(len));
+        }
+    }
+}
+
+/// How much an encrypted transport pads its messages so their sizes don't reveal the names
+/// being resolved.
+///
+/// Padding must never be used without encryption, since it only makes the messages bigger.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Padding {
+    #[default]
+    None,
+    /// Pad each message to a multiple of the given number of bytes (RFC 8467).
+    Block(usize),
+}
+
+impl Padding {
+    /// The block size recommended for queries (RFC 8467 section 4.1).
+    pub const QUERY_BLOCK_SIZE: usize = 128;
+    /// The block size recommended for responses (RFC 8467 section 4.1).
+    pub const RESPONSE_BLOCK_SIZE: usize = 468;
+
+    pub fn queries() -> Self {
+        Padding::Block(Self::QUERY_BLOCK_SIZE)
+    }
+
+    pub fn responses() -> Self {
+        Padding::Block(Self::RESPONSE_BLOCK_SIZE)
+    }
+
+    /// The length of the Padding option's data for a message of unpadded_len bytes,
+    /// or None if no option should be added.
+    fn padding_len(&self, unpadded_len: usize) -> Option<u16> {
+        match *self {
+            Padding::None | Padding::Block(0) => None,
+            Padding::Block(block) => {
+                let len = unpadded_len + OPTION_HEADER_LEN;
+                Some(((block - len % block) % block) as u16)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let mut edns = Edns::new(DEFAULT_UDP_PAYLOAD_SIZE);
+        edns.dnssec_ok = true;
+        edns.options = vec![
+            EdnsOption::Padding(5),
+            EdnsOption::Unknown {
+                code: 10,
+                data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            },
+        ];
+        let buf = edns.serialize();
+        assert!(Edns::is_next(&buf));
+
+        let mut unparsed = buf.as_slice();
+        assert_eq!(Edns::parse(&mut unparsed)?, edns);
+        assert!(unparsed.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_truncated() {
+        let buf = Edns::new(512).serialize();
+        let mut unparsed = &buf[..buf.len() - 1];
+        assert!(Edns::parse(&mut unparsed).is_err());
+
+        let mut unparsed: &[u8] = &[0, 0, 1];
+        assert!(Edns::parse(&mut unparsed).is_err());
+    }
+
+    #[test]
+    fn padding_len() {
+        let policy = Padding::queries();
+        assert_eq!(policy.padding_len(100), Some(24));
+        // * The option's own header can push the message into the next block.
+        assert_eq!(policy.padding_len(126), Some(126));
+        assert_eq!(policy.padding_len(124), Some(0));
+        assert_eq!(Padding::None.padding_len(100), None);
+    }
+}
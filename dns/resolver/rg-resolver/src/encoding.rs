@@ -0,0 +1,159 @@
+//! The text encodings record data is written in: hex, base64 (RFC 4648 section 4), and base32
+//! with the extended hex alphabet (RFC 4648 section 7) for NSEC3's hashed names.
+
+/// Decodes hex digits of either case.
+pub(crate) fn decode_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        anyhow::bail!("odd number of hex digits");
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)?;
+            u8::from_str_radix(pair, 16).map_err(|_| anyhow::anyhow!("invalid hex digits {pair}"))
+        })
+        .collect()
+}
+
+/// Decodes base64, which has to be padded to a multiple of 4 characters.
+pub(crate) fn decode_base64(text: &str) -> anyhow::Result<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    decode_base(text, 6, 4, value).map_err(|e| anyhow::anyhow!("invalid base64: {e}"))
+}
+
+/// Decodes base32 with the extended hex alphabet, of either case. NSEC3 names leave out the
+/// padding, so it's optional.
+pub(crate) fn decode_base32hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let value = |c: u8| match c.to_ascii_uppercase() {
+        c @ b'0'..=b'9' => Some(c - b'0'),
+        c @ b'A'..=b'V' => Some(c - b'A' + 10),
+        _ => None,
+    };
+    let padded = format!("{text:=<width$}", width = text.len().div_ceil(8) * 8);
+    decode_base(&padded, 5, 8, value).map_err(|e| anyhow::anyhow!("invalid base32hex: {e}"))
+}
+
+/// Decodes text of bits-per-character digits, padded with '=' to a multiple of chunk digits.
+fn decode_base(
+    text: &str,
+    bits: u32,
+    chunk: usize,
+    value: impl Fn(u8) -> Option<u8>,
+) -> anyhow::Result<Vec<u8>> {
+    if !text.len().is_multiple_of(chunk) {
+        anyhow::bail!("length not a multiple of {chunk}");
+    }
+    let digits = text.trim_end_matches('=');
+    if text.len() - digits.len() >= chunk {
+        anyhow::bail!("too much padding");
+    }
+    let mut bytes = Vec::with_capacity(digits.len() * bits as usize / 8);
+    let (mut acc, mut acc_bits) = (0_u32, 0);
+    for c in digits.bytes() {
+        let Some(v) = value(c) else {
+            anyhow::bail!("unexpected character {:?}", c as char);
+        };
+        acc = (acc << bits) | u32::from(v);
+        acc_bits += bits;
+        if acc_bits >= 8 {
+            acc_bits -= 8;
+            bytes.push((acc >> acc_bits) as u8);
+            acc &= (1 << acc_bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const BASE32HEX_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+/// Encodes bytes as uppercase hex digits, as dig writes digests.
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// Encodes bytes as base64, padded to a multiple of 4 characters.
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = encode_base(bytes, 6, BASE64_ALPHABET);
+    while !text.len().is_multiple_of(4) {
+        text.push('=');
+    }
+    text
+}
+
+/// Encodes bytes as base32 with the extended hex alphabet, without padding, as NSEC3 names
+/// are written.
+pub(crate) fn encode_base32hex(bytes: &[u8]) -> String {
+    encode_base(bytes, 5, BASE32HEX_ALPHABET)
+}
+
+/// Encodes bytes as digits of bits bits each from alphabet, the last filled out with zeros.
+fn encode_base(bytes: &[u8], bits: u32, alphabet: &[u8]) -> String {
+    let mut text = String::with_capacity((bytes.len() * 8).div_ceil(bits as usize));
+    let (mut acc, mut acc_bits) = (0_u32, 0);
+    for b in bytes {
+        acc = (acc << 8) | u32::from(*b);
+        acc_bits += 8;
+        while acc_bits >= bits {
+            acc_bits -= bits;
+            text.push(alphabet[(acc >> acc_bits) as usize] as char);
+            acc &= (1 << acc_bits) - 1;
+        }
+    }
+    if acc_bits > 0 {
+        text.push(alphabet[(acc << (bits - acc_bits)) as usize] as char);
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex() -> anyhow::Result<()> {
+        assert_eq!(decode_hex("00ffA0")?, [0x00, 0xff, 0xa0]);
+        assert_eq!(decode_hex("")?, Vec::<u8>::new());
+        assert!(decode_hex("abc").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert_eq!(encode_hex(&[0x00, 0xff, 0xa0]), "00FFA0");
+        Ok(())
+    }
+
+    #[test]
+    fn base64() -> anyhow::Result<()> {
+        // * The test vectors from RFC 4648 section 10.
+        assert_eq!(decode_base64("")?, b"");
+        assert_eq!(decode_base64("Zg==")?, b"f");
+        assert_eq!(decode_base64("Zm8=")?, b"fo");
+        assert_eq!(decode_base64("Zm9v")?, b"foo");
+        assert_eq!(decode_base64("Zm9vYmFy")?, b"foobar");
+        assert!(decode_base64("Zm9").is_err());
+        assert!(decode_base64("Zm9v====").is_err());
+        assert!(decode_base64("Zm9*").is_err());
+        for text in ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYmFy"] {
+            assert_eq!(encode_base64(&decode_base64(text)?), text);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn base32hex() -> anyhow::Result<()> {
+        assert_eq!(decode_base32hex("CO======")?, b"f");
+        assert_eq!(decode_base32hex("cpnmu")?, b"foo");
+        assert_eq!(decode_base32hex("CPNMUOJ1E8======")?, b"foobar");
+        assert!(decode_base32hex("W").is_err());
+        assert_eq!(encode_base32hex(b"f"), "CO");
+        assert_eq!(encode_base32hex(b"foobar"), "CPNMUOJ1E8");
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::message::Message;
+use crate::net::{self, Upstreams};
+use crate::resolve::BoxFuture;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time;
+
+/// Sends a query to one nameserver and waits for its response.
+///
+/// Forwarding ([Upstreams::with_exchange]) and recursion ([crate::recurse::Recursor]) are
+/// written against this rather than sockets, so they can be given any transport, or a fake
+/// nameserver in tests.
+pub trait Exchange: Send + Sync {
+    fn exchange<'a>(
+        &'a self,
+        server: SocketAddr,
+        query: &'a Message,
+    ) -> BoxFuture<'a, anyhow::Result<Message>>;
+}
+
+/// Exchanges messages over UDP, fetching a truncated response again over TCP.
+pub struct Udp {
+    pub timeout: Duration,
+}
+
+impl Exchange for Udp {
+    fn exchange<'a>(
+        &'a self,
+        server: SocketAddr,
+        query: &'a Message,
+    ) -> BoxFuture<'a, anyhow::Result<Message>> {
+        Box::pin(async move {
+            let (_, response) = Upstreams::new(vec![server], self.timeout)
+                .exchange(query)
+                .await?;
+            Ok(response)
+        })
+    }
+}
+
+/// Exchanges messages over TCP, one connection per query (RFC 7766).
+pub struct Tcp {
+    pub timeout: Duration,
+}
+
+impl Exchange for Tcp {
+    fn exchange<'a>(
+        &'a self,
+        server: SocketAddr,
+        query: &'a Message,
+    ) -> BoxFuture<'a, anyhow::Result<Message>> {
+        Box::pin(async move {
+            time::timeout(self.timeout, net::exchange_tcp(server, query))
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("querying {server} over TCP: timed out")))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{self, ResponseCode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn tcp() -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let server = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let len = stream.read_u16().await?;
+            let mut buf = vec![0_u8; len as usize];
+            stream.read_exact(&mut buf).await?;
+            let query = Message::parse(&buf)?;
+            let response = query.empty_response(ResponseCode::NoError);
+            stream.write_all(&response.serialize_framed()?).await?;
+            anyhow::Ok(())
+        });
+
+        let exchange: Box<dyn Exchange> = Box::new(Tcp {
+            timeout: Duration::from_secs(5),
+        });
+        let query = message::address_query(&"example.com.".parse()?);
+        let response = exchange.exchange(server, &query).await?;
+        assert!(response.is_response_to(&query));
+        Ok(())
+    }
+}
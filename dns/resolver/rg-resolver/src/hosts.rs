@@ -0,0 +1,347 @@
+use crate::message::QuestionType;
+use crate::provenance::{self, AnswerSource};
+use crate::resolve::{Answer, BoxFuture, RRset, Resolve};
+use crate::rr;
+use rg_resolver_common::DomainName;
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// How often the overrides file is checked for changes unless told otherwise.
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The TTL of the records made from overrides, kept short so clients notice a reload soon.
+const TTL: i32 = 60;
+
+/// What the overrides say about a name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Override {
+    /// The name has these addresses and no other records.
+    Addresses(Vec<IpAddr>),
+    /// The name doesn't exist.
+    Blocked,
+}
+
+/// Operator-configured answers for names, in the format of a hosts file, taking precedence
+/// over every other source, e.g. to give local development names addresses or to block ads.
+///
+/// Each line is an address followed by the names that have it:
+///   192.168.1.10  nas nas.lan
+///   ::1           localhost
+///   0.0.0.0       ads.example.com tracker.example.net
+///
+/// A name given the unspecified address, 0.0.0.0 or ::, is blocked instead, so ad-blocking
+/// lists written for hosts files work unchanged. Names are matched exactly, ignoring case.
+/// Text from a '#' to the end of the line is a comment.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    names: HashMap<DomainName, Override>,
+}
+
+impl Overrides {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut names = HashMap::<DomainName, Override>::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(addr) = fields.next() else {
+                continue;
+            };
+            let addr = addr
+                .parse::<IpAddr>()
+                .map_err(|e| anyhow::anyhow!("line {}: invalid address {addr}: {e}", idx + 1))?;
+            let mut hostnames = fields.peekable();
+            if hostnames.peek().is_none() {
+                anyhow::bail!("line {}: no names for {addr}", idx + 1);
+            }
+            for hostname in hostnames {
+                let hostname = absolute(hostname).map_err(|e| {
+                    anyhow::anyhow!("line {}: invalid name {hostname}: {e}", idx + 1)
+                })?;
+                // * Blocking a name wins over any addresses it's given elsewhere.
+                if addr.is_unspecified() {
+                    names.insert(hostname, Override::Blocked);
+                    continue;
+                }
+                match names
+                    .entry(hostname)
+                    .or_insert_with(|| Override::Addresses(Vec::new()))
+                {
+                    Override::Addresses(addrs) if !addrs.contains(&addr) => addrs.push(addr),
+                    _ => {}
+                }
+            }
+        }
+        Ok(Overrides { names })
+    }
+
+    /// What the overrides say about name, if anything.
+    pub fn get(&self, name: &DomainName) -> Option<&Override> {
+        self.names.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The records of type qtype that the overrides give name: None if they don't mention
+    /// it, or an empty RRset if it's blocked or has no addresses of the type.
+    pub fn records(&self, name: &DomainName, qtype: QuestionType) -> Option<RRset> {
+        let Override::Addresses(addrs) = self.get(name)? else {
+            return Some(RRset::new());
+        };
+        let records = addrs
+            .iter()
+            .map(|addr| match addr {
+                IpAddr::V4(addr) => (rr::Type::A, rr::Data::A(*addr)),
+                IpAddr::V6(addr) => (rr::Type::AAAA, rr::Data::AAAA(*addr)),
+            })
+            .filter(|(r#type, _)| qtype.matches(*r#type))
+            .map(|(r#type, data)| {
+                rr::ResourceRecord::new(name.clone(), r#type, rr::Class::IN, TTL, data)
+                    .expect("an address record holds an address")
+            })
+            .collect();
+        Some(records)
+    }
+}
+
+/// name, made absolute if it's relative, as names in hosts files usually are.
+fn absolute(name: &str) -> anyhow::Result<DomainName> {
+    let name = name.parse::<DomainName>()?;
+    if name.is_absolute() {
+        Ok(name)
+    } else {
+        Ok(name.join(&DomainName::root())?)
+    }
+}
+
+/// The overrides from a file, read again whenever it changes.
+pub struct OverridesFile {
+    path: PathBuf,
+    current: RwLock<Loaded>,
+}
+
+struct Loaded {
+    overrides: Arc<Overrides>,
+    /// The file's modification time when it was read.
+    modified: Option<SystemTime>,
+}
+
+impl OverridesFile {
+    /// Reads the overrides in path, failing if they can't be read or parsed.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let loaded = Self::read(path)?;
+        Ok(OverridesFile {
+            path: path.to_path_buf(),
+            current: RwLock::new(loaded),
+        })
+    }
+
+    /// The overrides as last read.
+    pub fn current(&self) -> Arc<Overrides> {
+        self.current.read().unwrap().overrides.clone()
+    }
+
+    /// Reads the file again if it's been modified since it was last read. A file that can no
+    /// longer be read or parsed leaves the overrides as they were.
+    ///
+    /// Returns true if the overrides were replaced.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let modified = modified(&self.path);
+        if modified == self.current.read().unwrap().modified {
+            return Ok(false);
+        }
+        match Self::read(&self.path) {
+            Ok(loaded) => {
+                *self.current.write().unwrap() = loaded;
+                Ok(true)
+            }
+            Err(e) => {
+                // * Not read again until it changes again, so the error is reported once.
+                self.current.write().unwrap().modified = modified;
+                Err(e)
+            }
+        }
+    }
+
+    /// Reads the file again whether or not it's changed, leaving the overrides as they were if
+    /// it can't be read or parsed.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let loaded = Self::read(&self.path)?;
+        *self.current.write().unwrap() = loaded;
+        Ok(())
+    }
+
+    /// Checks the file for changes every interval, until the task is dropped.
+    pub async fn reload_on_change(self: Arc<Self>, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            match self.reload_if_changed() {
+                Ok(true) => info!(
+                    "Reloaded {} overrides from {}",
+                    self.current().len(),
+                    self.path.display()
+                ),
+                Ok(false) => {}
+                Err(e) => warn!("Keeping the previous overrides: {e}"),
+            }
+        }
+    }
+
+    fn read(path: &Path) -> anyhow::Result<Loaded> {
+        // * The time is taken first, so a change made while reading is seen next time.
+        let modified = modified(path);
+        let text = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("reading overrides {}: {e}", path.display()))?;
+        let overrides = Overrides::parse(&text)
+            .map_err(|e| anyhow::anyhow!("overrides {}: {e}", path.display()))?;
+        Ok(Loaded {
+            overrides: Arc::new(overrides),
+            modified,
+        })
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl Resolve for OverridesFile {
+    fn name(&self) -> &str {
+        "overrides"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let name = absolute(name)?;
+            let overrides = self.current();
+            let answer = match overrides.get(&name) {
+                None => return Ok(None),
+                Some(Override::Blocked) => Answer::NxDomain {
+                    cnames: RRset::new(),
+                    soa: None,
+                },
+                Some(Override::Addresses(_)) => match overrides.records(&name, qtype) {
+                    Some(records) if !records.is_empty() => Answer::Records(records),
+                    _ => Answer::no_data(),
+                },
+            };
+            provenance::record_answer_source(AnswerSource::Authoritative);
+            Ok(Some(answer))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const HOSTS: &str = "\
+        # Local development\n\
+        127.0.0.1 app.test api.test # and the API\n\
+        ::1       app.test\n\
+        \n\
+        0.0.0.0   ads.example.com\n\
+        192.0.2.1 ADS.example.com\n";
+
+    #[test]
+    fn parse() -> anyhow::Result<()> {
+        let overrides = Overrides::parse(HOSTS)?;
+        assert_eq!(overrides.len(), 3);
+        assert_eq!(
+            overrides.get(&absolute("App.Test.")?),
+            Some(&Override::Addresses(vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]))
+        );
+        let blocked = absolute("ads.example.com")?;
+        assert_eq!(overrides.get(&blocked), Some(&Override::Blocked));
+        assert_eq!(overrides.get(&absolute("example.com")?), None);
+
+        assert!(Overrides::parse("192.0.2.300 host\n").is_err());
+        assert!(Overrides::parse("192.0.2.1 two..dots\n").is_err());
+        let e = Overrides::parse("\n192.0.2.1\n").unwrap_err();
+        assert_eq!(e.to_string(), "line 2: no names for 192.0.2.1");
+        Ok(())
+    }
+
+    #[test]
+    fn records() -> anyhow::Result<()> {
+        let overrides = Overrides::parse(HOSTS)?;
+        let a = QuestionType::RrType(rr::Type::A);
+        let api = absolute("api.test.")?;
+        let records = overrides.records(&api, a).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data(), &rr::Data::A(Ipv4Addr::LOCALHOST));
+
+        // * A name in the overrides has nothing else.
+        let mx = QuestionType::RrType(rr::Type::MX);
+        assert_eq!(overrides.records(&api, mx), Some(vec![]));
+        let blocked = absolute("ads.example.com.")?;
+        assert_eq!(overrides.records(&blocked, a), Some(vec![]));
+        assert_eq!(overrides.records(&absolute("www.example.com.")?, a), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reloads_when_changed() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("rg-resolver-hosts-{}", std::process::id()));
+        // * Each write is dated a minute later, as file systems may only keep times to the
+        // * second.
+        let started = SystemTime::now();
+        let write = |minutes: u64, text: &str| -> anyhow::Result<()> {
+            fs::write(&path, text)?;
+            let modified = started + Duration::from_secs(minutes * 60);
+            fs::File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(modified)?;
+            Ok(())
+        };
+        write(0, "192.0.2.1 host.test\n")?;
+        let file = OverridesFile::load(&path)?;
+        let a = QuestionType::RrType(rr::Type::A);
+        assert_eq!(
+            file.lookup("host.test.", a).await?.unwrap().records().len(),
+            1
+        );
+        assert!(!file.reload_if_changed()?);
+
+        write(1, "0.0.0.0 host.test\n")?;
+        assert!(file.reload_if_changed()?);
+        assert_eq!(
+            file.lookup("host.test.", a).await?,
+            Some(Answer::NxDomain {
+                cnames: vec![],
+                soa: None
+            })
+        );
+
+        // * A broken file doesn't replace the overrides, and is only reported once.
+        write(2, "nonsense\n")?;
+        assert!(file.reload_if_changed().is_err());
+        assert!(!file.reload_if_changed()?);
+        let host = absolute("host.test")?;
+        assert_eq!(file.current().get(&host), Some(&Override::Blocked));
+
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
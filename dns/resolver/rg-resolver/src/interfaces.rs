@@ -0,0 +1,283 @@
+//! The host's network interfaces, so nameservers can be tied to the interface they're
+//! reached through: link-local ones need its index as their scope, and a nameserver goes
+//! away with its interface.
+//!
+//! Interfaces come from getifaddrs on Unix and GetAdaptersAddresses on Windows.
+
+use std::net::IpAddr;
+
+/// A network interface that's up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    /// The OS's index of the interface, which scopes its IPv6 link-local addresses.
+    pub index: u32,
+    /// The interface's addresses, each with the length of its subnet's prefix.
+    pub addrs: Vec<(IpAddr, u8)>,
+}
+
+impl Interface {
+    /// Whether ip is on one of the interface's subnets, so reached through it.
+    pub fn is_on_link(&self, ip: IpAddr) -> bool {
+        self.addrs.iter().any(|&(addr, prefix)| {
+            let (addr, ip, bits) = match (addr, ip) {
+                (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                    (u32::from(addr).into(), u32::from(ip).into(), 32)
+                }
+                (IpAddr::V6(addr), IpAddr::V6(ip)) => (u128::from(addr), u128::from(ip), 128),
+                _ => return false,
+            };
+            // * A 0 length prefix is a default route, not a subnet.
+            prefix > 0 && prefix <= bits && (addr ^ ip) >> (bits - prefix) == 0
+        })
+    }
+}
+
+/// The interfaces that are up, in the order the OS lists them.
+pub fn list() -> anyhow::Result<Vec<Interface>> {
+    platform::list()
+}
+
+/// Whether ip is an IPv6 link-local address, which is only meaningful with the interface it's
+/// reached through.
+pub fn is_link_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// The length of the prefix a netmask covers.
+fn prefix_len(mask: IpAddr) -> u8 {
+    match mask {
+        IpAddr::V4(mask) => u32::from(mask).count_ones() as u8,
+        IpAddr::V6(mask) => u128::from(mask).count_ones() as u8,
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::{prefix_len, Interface};
+    use std::ffi::CStr;
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::ptr;
+
+    pub(super) fn list() -> anyhow::Result<Vec<Interface>> {
+        let mut ifaddrs = ptr::null_mut();
+        // SAFETY: getifaddrs only writes the head of the list it allocates to ifaddrs.
+        if unsafe { libc::getifaddrs(&mut ifaddrs) } < 0 {
+            anyhow::bail!("listing network interfaces: {}", io::Error::last_os_error());
+        }
+        let mut interfaces: Vec<Interface> = Vec::new();
+        let mut ifaddr = ifaddrs;
+        // SAFETY: getifaddrs wrote a linked list terminated by a null pointer, whose names and
+        // addresses stay valid until it's freed after the walk.
+        unsafe {
+            while !ifaddr.is_null() {
+                let entry = &*ifaddr;
+                ifaddr = entry.ifa_next;
+                if entry.ifa_flags & libc::IFF_UP as libc::c_uint == 0 {
+                    continue;
+                }
+                // * There's an entry for each of an interface's addresses, and on Linux one for
+                // * its link layer.
+                let name = CStr::from_ptr(entry.ifa_name).to_string_lossy();
+                let i = match interfaces
+                    .iter()
+                    .position(|interface| interface.name == name)
+                {
+                    Some(i) => i,
+                    None => {
+                        interfaces.push(Interface {
+                            name: name.into_owned(),
+                            index: libc::if_nametoindex(entry.ifa_name),
+                            addrs: Vec::new(),
+                        });
+                        interfaces.len() - 1
+                    }
+                };
+                if let (Some(addr), Some(mask)) = (ip(entry.ifa_addr), ip(entry.ifa_netmask)) {
+                    interfaces[i].addrs.push((addr, prefix_len(mask)));
+                }
+            }
+            libc::freeifaddrs(ifaddrs);
+        }
+        Ok(interfaces)
+    }
+
+    /// The address in sockaddr, if it's an IPv4 or IPv6 one. sockaddr must be null or valid.
+    unsafe fn ip(sockaddr: *const libc::sockaddr) -> Option<IpAddr> {
+        if sockaddr.is_null() {
+            return None;
+        }
+        match (*sockaddr).sa_family as libc::c_int {
+            libc::AF_INET => {
+                let sin = &*sockaddr.cast::<libc::sockaddr_in>();
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.s_addr,
+                ))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*sockaddr.cast::<libc::sockaddr_in6>();
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(windows)]
+pub(crate) use platform::{adapters, wide_string};
+
+#[cfg(windows)]
+mod platform {
+    use super::Interface;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::ptr;
+    use windows_sys::Win32::Foundation::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+    use windows_sys::Win32::NetworkManagement::IpHelper::{
+        GetAdaptersAddresses, GAA_FLAG_SKIP_ANYCAST, GAA_FLAG_SKIP_DNS_SERVER,
+        GAA_FLAG_SKIP_MULTICAST, IP_ADAPTER_ADDRESSES_LH,
+    };
+    use windows_sys::Win32::NetworkManagement::Ndis::IfOperStatusUp;
+    use windows_sys::Win32::Networking::WinSock::{
+        AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6,
+    };
+
+    pub(super) fn list() -> anyhow::Result<Vec<Interface>> {
+        let buf =
+            adapters(GAA_FLAG_SKIP_ANYCAST | GAA_FLAG_SKIP_MULTICAST | GAA_FLAG_SKIP_DNS_SERVER)?;
+        let mut interfaces = Vec::new();
+        let mut adapter = buf.as_ptr().cast::<IP_ADAPTER_ADDRESSES_LH>();
+        // SAFETY: GetAdaptersAddresses wrote linked lists of adapters and their addresses into
+        // buf, terminated by null pointers, and buf outlives the walk.
+        unsafe {
+            while !adapter.is_null() {
+                if (*adapter).OperStatus == IfOperStatusUp {
+                    let mut addrs = Vec::new();
+                    let mut unicast = (*adapter).FirstUnicastAddress;
+                    while !unicast.is_null() {
+                        if let Some(ip) = ip((*unicast).Address.lpSockaddr) {
+                            addrs.push((ip, (*unicast).OnLinkPrefixLength));
+                        }
+                        unicast = (*unicast).Next;
+                    }
+                    // * An adapter without IPv4 has an IfIndex of 0.
+                    let index = match (*adapter).Anonymous1.Anonymous.IfIndex {
+                        0 => (*adapter).Ipv6IfIndex,
+                        index => index,
+                    };
+                    interfaces.push(Interface {
+                        name: wide_string((*adapter).FriendlyName),
+                        index,
+                        addrs,
+                    });
+                }
+                adapter = (*adapter).Next;
+            }
+        }
+        Ok(interfaces)
+    }
+
+    /// Calls GetAdaptersAddresses with flags, growing the buffer until the adapters fit. The
+    /// buffer holds u64s to keep the structures written into it aligned.
+    pub(crate) fn adapters(flags: u32) -> anyhow::Result<Vec<u64>> {
+        let mut len = 16 * 1024_u32;
+        let mut buf = Vec::<u64>::new();
+        loop {
+            buf.resize((len as usize).div_ceil(8), 0);
+            let adapters = buf.as_mut_ptr().cast::<IP_ADAPTER_ADDRESSES_LH>();
+            // SAFETY: buf holds len bytes, and the call writes no more than that.
+            let err = unsafe {
+                GetAdaptersAddresses(AF_UNSPEC as u32, flags, ptr::null(), adapters, &mut len)
+            };
+            match err {
+                NO_ERROR => return Ok(buf),
+                // * len has been set to the size needed.
+                ERROR_BUFFER_OVERFLOW => continue,
+                err => anyhow::bail!(
+                    "listing network adapters: {}",
+                    std::io::Error::from_raw_os_error(err as i32)
+                ),
+            }
+        }
+    }
+
+    /// Reads a null-terminated UTF-16 string. s must be null or valid.
+    pub(crate) unsafe fn wide_string(s: *const u16) -> String {
+        if s.is_null() {
+            return String::new();
+        }
+        let len = (0..).take_while(|&i| *s.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(s, len))
+    }
+
+    /// The address in sockaddr, if it's an IPv4 or IPv6 one. sockaddr must be null or valid.
+    unsafe fn ip(sockaddr: *const SOCKADDR) -> Option<IpAddr> {
+        if sockaddr.is_null() {
+            return None;
+        }
+        match (*sockaddr).sa_family {
+            AF_INET => {
+                let sin = &*sockaddr.cast::<SOCKADDR_IN>();
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.S_un.S_addr,
+                ))))
+            }
+            AF_INET6 => {
+                let sin6 = &*sockaddr.cast::<SOCKADDR_IN6>();
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.u.Byte)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::Interface;
+
+    pub(super) fn list() -> anyhow::Result<Vec<Interface>> {
+        anyhow::bail!("listing network interfaces isn't supported on this platform")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn interface(addrs: &[(&str, u8)]) -> Interface {
+        Interface {
+            name: String::from("eth0"),
+            index: 2,
+            addrs: addrs
+                .iter()
+                .map(|(addr, prefix)| (addr.parse().unwrap(), *prefix))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn on_link() {
+        let eth0 = interface(&[("192.168.1.10", 24), ("2001:db8:1::10", 64)]);
+        assert!(eth0.is_on_link("192.168.1.1".parse().unwrap()));
+        assert!(!eth0.is_on_link("192.168.2.1".parse().unwrap()));
+        assert!(eth0.is_on_link("2001:db8:1::53".parse().unwrap()));
+        assert!(!eth0.is_on_link("2001:db8:2::53".parse().unwrap()));
+        // * An IPv4-mapped address isn't on an IPv4 subnet.
+        assert!(!eth0.is_on_link("::ffff:192.168.1.1".parse().unwrap()));
+        assert!(!interface(&[("192.168.1.10", 0)]).is_on_link("198.51.100.1".parse().unwrap()));
+        assert!(interface(&[("192.168.1.10", 32)]).is_on_link("192.168.1.10".parse().unwrap()));
+    }
+
+    #[test]
+    fn link_local() {
+        assert!(is_link_local("fe80::1".parse().unwrap()));
+        assert!(is_link_local("febf::1".parse().unwrap()));
+        assert!(!is_link_local("fec0::1".parse().unwrap()));
+        assert!(!is_link_local("169.254.0.1".parse().unwrap()));
+        assert_eq!(prefix_len("255.255.255.0".parse().unwrap()), 24);
+        assert_eq!(prefix_len("ffff:ffff:ffff:ffff::".parse().unwrap()), 64);
+    }
+}
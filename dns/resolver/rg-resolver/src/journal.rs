@@ -0,0 +1,307 @@
+use crate::audit;
+use crate::message::QuestionType;
+use crate::privacy;
+use crate::querylog;
+use crate::resolve::{Answer, BoxFuture, Resolve};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// A request that was being handled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InFlight {
+    pub id: u64,
+    pub client: String,
+    pub qname: String,
+    pub started: SystemTime,
+}
+
+impl InFlight {
+    /// The CSV header line, without the line terminator.
+    pub const HEADER: &'static str = "id,client,qname,started";
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.id,
+            audit::csv_field(&self.client),
+            audit::csv_field(&self.qname),
+            self.started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        )
+    }
+
+    fn from_csv(line: &str) -> anyhow::Result<Self> {
+        let [id, client, qname, started] = &split_csv(line)[..] else {
+            anyhow::bail!("expected {}", Self::HEADER);
+        };
+        let started = started
+            .parse::<u64>()
+            .map_err(|e| anyhow::anyhow!("invalid start time {started}: {e}"))?;
+        Ok(InFlight {
+            id: id
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid ID {id}: {e}"))?,
+            client: client.clone(),
+            qname: qname.clone(),
+            started: UNIX_EPOCH + Duration::from_secs(started),
+        })
+    }
+}
+
+/// Returned to requests still in flight when the resolver shuts down, so clients get a reason
+/// rather than a dropped connection.
+#[derive(Debug)]
+pub struct ShuttingDown;
+
+impl std::fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("server restarting")
+    }
+}
+
+impl std::error::Error for ShuttingDown {}
+
+/// Tracks the requests being handled, so those dropped by a shutdown can be written down for
+/// the operator to see after the restart.
+pub struct Journal {
+    in_flight: Mutex<BTreeMap<u64, InFlight>>,
+    next_id: AtomicU64,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal {
+            in_flight: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            shutdown: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The requests being handled, oldest first.
+    pub fn in_flight(&self) -> Vec<InFlight> {
+        self.in_flight.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Writes the requests being handled to path, then fails each of them with [ShuttingDown].
+    /// Returns the number of requests written.
+    pub fn shut_down(&self, path: &Path) -> anyhow::Result<usize> {
+        let in_flight = self.in_flight();
+        // * Requests are failed even if the journal can't be written, so shutdown isn't held up.
+        self.shutdown.send_replace(true);
+
+        let mut contents = format!("{}\n", InFlight::HEADER);
+        for request in &in_flight {
+            contents.push_str(&request.to_csv());
+            contents.push('\n');
+        }
+        // * Write the whole journal or none of it, so a crash mid-write can't leave half of one.
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)
+            .and_then(|()| fs::rename(&tmp, path))
+            .map_err(|e| anyhow::anyhow!("writing request journal {}: {e}", path.display()))?;
+        Ok(in_flight.len())
+    }
+
+    /// Reads and removes the journal a previous run left at path, returning the requests it
+    /// dropped. Returns no requests if there's no journal, because the last run didn't shut
+    /// down or nothing was in flight when it did.
+    pub fn recover(path: &Path) -> anyhow::Result<Vec<InFlight>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                anyhow::bail!("reading request journal {}: {e}", path.display())
+            }
+        };
+        let requests = contents
+            .lines()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(line_num, line)| {
+                InFlight::from_csv(line).map_err(|e| {
+                    anyhow::anyhow!(
+                        "parsing request journal {}: line {}: {e}",
+                        path.display(),
+                        line_num + 1
+                    )
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        fs::remove_file(path)
+            .map_err(|e| anyhow::anyhow!("removing request journal {}: {e}", path.display()))?;
+        Ok(requests)
+    }
+
+    fn begin(&self, client: &str, qname: &str) -> Entry<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = InFlight {
+            id,
+            client: client.to_string(),
+            qname: privacy::qname(qname).to_string(),
+            started: SystemTime::now(),
+        };
+        self.in_flight.lock().unwrap().insert(id, request);
+        Entry { journal: self, id }
+    }
+}
+
+/// Removes a request from the journal once it's no longer in flight.
+struct Entry<'a> {
+    journal: &'a Journal,
+    id: u64,
+}
+
+impl Drop for Entry<'_> {
+    fn drop(&mut self) {
+        self.journal.in_flight.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Records every lookup made through the inner resolver in a journal while it's in flight,
+/// under the client whose request made it, and fails it with [ShuttingDown] if the journal
+/// shuts down first.
+pub struct Journaled<R> {
+    inner: R,
+    journal: Arc<Journal>,
+}
+
+impl<R: Resolve> Journaled<R> {
+    pub fn new(inner: R, journal: Arc<Journal>) -> Self {
+        Journaled { inner, journal }
+    }
+}
+
+impl<R: Resolve> Resolve for Journaled<R> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            // * The request is journaled before checking for shutdown, so one arriving while
+            // * the journal is written is either in it or failed without being started.
+            let _entry = self.journal.begin(&querylog::client(), name);
+            let mut shutdown = self.journal.shutdown.subscribe();
+            tokio::select! {
+                biased;
+                _ = shutdown.wait_for(|shutting_down| *shutting_down) => Err(ShuttingDown.into()),
+                result = self.inner.lookup(name, qtype) => result,
+            }
+        })
+    }
+}
+
+/// Splits a CSV line written with [audit::csv_field] into its fields.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rr;
+
+    /// Never answers.
+    struct Hung;
+
+    impl Resolve for Hung {
+        fn name(&self) -> &str {
+            "hung"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            _name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(std::future::pending())
+        }
+    }
+
+    #[tokio::test]
+    async fn journals_requests_dropped_by_shutdown() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("rg-resolver-journal-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("in-flight.csv");
+
+        let journal = Arc::new(Journal::new());
+        let resolver = Arc::new(Journaled::new(Hung, journal.clone()));
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let lookups = ["example.com.", "a,b.example."].map(|name| {
+            let resolver = resolver.clone();
+            let client = String::from("192.0.2.7:5353");
+            tokio::spawn(querylog::with_client(client, async move {
+                resolver.lookup(name, qtype).await
+            }))
+        });
+        while journal.in_flight().len() < lookups.len() {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(journal.shut_down(&path)?, 2);
+        for lookup in lookups {
+            let e = lookup.await?.unwrap_err();
+            assert!(e.is::<ShuttingDown>());
+            assert_eq!(e.to_string(), "server restarting");
+        }
+        assert!(journal.in_flight().is_empty());
+        // * Lookups made after shutdown fail straight away.
+        assert!(resolver.lookup("late.example.", qtype).await.is_err());
+
+        let mut dropped = Journal::recover(&path)?;
+        dropped.sort_by(|a, b| a.qname.cmp(&b.qname));
+        let qnames = dropped.iter().map(|r| r.qname.as_str()).collect::<Vec<_>>();
+        assert_eq!(qnames, ["a,b.example.", "example.com."]);
+        assert!(dropped.iter().all(|r| r.client == "192.0.2.7:5353"));
+        // * The journal is consumed, so the next restart doesn't report the same requests.
+        assert!(Journal::recover(&path)?.is_empty());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn splits_csv() {
+        assert_eq!(split_csv("1,a,b"), ["1", "a", "b"]);
+        assert_eq!(
+            split_csv(r#"1,"a,b","say ""hi""""#),
+            ["1", "a,b", r#"say "hi""#]
+        );
+        assert_eq!(split_csv(""), [""]);
+    }
+}
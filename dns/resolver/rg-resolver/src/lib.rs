@@ -0,0 +1,62 @@
+//! A stub resolver, usable as a library.
+//!
+//! # Stability
+//!
+//! Until 1.0, only these items are kept compatible between minor releases:
+//!
+//! - the [`resolve::Resolve`] trait and the resolvers in [`resolve`],
+//! - building queries and reading responses through [`message`],
+//! - the record types in [`rr`].
+//!
+//! Everything else (e.g. [`net`], [`monitor`], [`queue`]) is how the binary is put together
+//! and may change in any release.
+
+pub mod admin;
+pub mod audit;
+pub mod authority;
+pub mod cache;
+pub mod classify;
+pub mod config;
+pub mod context;
+pub mod dedup;
+pub mod dig;
+pub mod edns;
+mod encoding;
+pub mod exchange;
+pub mod hosts;
+pub mod interfaces;
+pub mod journal;
+pub mod message;
+pub mod metrics;
+pub mod monitor;
+pub mod mux;
+pub mod name;
+pub mod net;
+pub mod netwatch;
+pub mod nta;
+pub mod privacy;
+pub mod provenance;
+pub mod querylog;
+pub mod queue;
+pub mod rank;
+pub mod recurse;
+pub mod resolve;
+pub mod rewrite;
+pub mod rr;
+pub mod server;
+pub mod shell;
+pub mod shutdown;
+pub mod soa;
+pub mod stub;
+pub mod system;
+pub mod task;
+#[cfg(test)]
+mod testing;
+pub mod transport;
+pub mod tsig;
+pub mod ttl;
+pub mod update;
+pub mod view;
+mod wire;
+pub mod xfr;
+pub mod zone;
@@ -1,32 +1,664 @@
+use clap::Parser;
+use rg_resolver::admin::Admin;
+use rg_resolver::audit;
+use rg_resolver::authority::Authority;
+use rg_resolver::cache::{Cache, Cached, Eviction};
+use rg_resolver::config::{self, UpstreamConfig};
+use rg_resolver::dig::Dig;
+use rg_resolver::hosts::{self, OverridesFile};
+use rg_resolver::journal::{Journal, Journaled};
+use rg_resolver::message::{QuestionClass, QuestionType};
+use rg_resolver::monitor::{Monitor, MonitorConfig};
+use rg_resolver::mux::Multiplexer;
+use rg_resolver::name::{Strictness, Validated};
+use rg_resolver::net::SourcePorts;
+use rg_resolver::netwatch::NetworkWatcher;
+use rg_resolver::nta::NegativeTrustAnchors;
+use rg_resolver::querylog::{self, QueryLog};
+use rg_resolver::queue::QueueConfig;
+use rg_resolver::recurse::{self, Recursor};
+use rg_resolver::resolve::{self, Resolve};
+use rg_resolver::rewrite::{AddressRewrites, Rewritten};
+use rg_resolver::shell::Shell;
+use rg_resolver::shutdown::{self, Shutdown};
+use rg_resolver::ttl::TtlOverrides;
+use rg_resolver::view::Views;
+use rg_resolver::{context, metrics, privacy, rr, server, stub, task, tsig, zone};
 use std::env;
-use tracing::info;
-use tracing_subscriber;
-
-mod message;
-mod name;
-mod net;
-mod rr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
 // Example run: RUST_LOG=info cargo run -- yahoo.com.
-fn main() {
-    if let Err(e) = run() {
+// Pass --system-fallback to fall back to the OS resolver if the nameserver can't be reached.
+// It isn't asked again about a name it's looking up, so it doesn't loop when this resolver
+// is the OS's nameserver.
+// Pass --audit-log=<path> to append a CSV record of each lookup to path.
+// Pass --query-log=<path> to append a JSON line for each query a client sends to path, with
+// who sent it, the upstream it went to, whether the cache answered it and how long it took.
+// Pass --private to keep query names out of the logs.
+// Pass --zone=<origin>:<path> (repeatable) to answer from a zone file before forwarding.
+// Pass --check-zones to validate the zone files and exit without looking anything up.
+// Pass --correlation-id=<id> to tag the logs, audit record, and any error with id.
+// Pass --negative-trust-anchors=<path> to load negative trust anchors (RFC 7646), the names to
+// skip DNSSEC validation at and below, each with the seconds until it expires, e.g.
+// "broken.example. 86400". Clients on loopback may also call add_negative_trust_anchor and
+// remove_negative_trust_anchor, and dump_stats lists them. Nothing validates yet, so for now
+// they're only kept for the validator.
+// Pass --views=<path> to answer each client from the first view in path that lists it, e.g.
+// "lab clients 10.1.0.0/16 block ads.example.", and refuse clients no view lists. Each view
+// answers names in its blocked zones with no records, and caches answers apart from the rest.
+// This host's own lookup is from 127.0.0.1.
+// Pass --type=AAAA to look up IPv6 addresses instead of IPv4 addresses.
+// Pass --journal=<path> to write the lookup to path if interrupted with Ctrl-C, and to report
+// what the previous run dropped.
+// Pass --overrides=<path> to answer for the names in the hosts-style file at path before
+// anything else, blocking those given 0.0.0.0 with NXDOMAIN. The file is reloaded when it
+// changes, checked every 5 seconds or --overrides-reload=<secs>.
+// Pass --rewrites=<path> to rewrite addresses in answers by the rules in path for the client
+// asking, this host being 127.0.0.1.
+// Pass --upstreams=<path> to forward to the nameservers listed in path rather than the
+// system's. Answers from the upstreams are cached.
+// Pass --cache-max-entries=<n> or --cache-max-memory=<MiB> to bound the cache, evicting the
+// least recently used answers to stay within it, or with --cache-eviction=lfu the least
+// frequently used. The cache is split into 16 shards by name, each behind its own lock, or
+// --cache-shards=<n>; more let more lookups run at once on a busy many-core host.
+// Pass --ttl-overrides=<path> to bound the TTLs of cached records by the rules in path, e.g.
+// "internal.example. max 30" or "cdn.example. min 300", the longest matching suffix winning.
+// Pass --names=hostname to refuse questions for names that aren't host names, e.g. with
+// underscores, or --names=binary to allow any label, e.g. mDNS's UTF-8 ones. By default names
+// may be any ASCII.
+// Pass --round-robin to rotate the order of cached addresses on each answer, so clients that
+// connect to the first spread across the hosts.
+// Pass --recurse to resolve from the root servers rather than forwarding, and
+// --root-hints=<path> to read their addresses from a named.root file rather than the built-in
+// list.
+// Pass --source-ports=<first>-<last> to send the recursor's queries from ports in that range.
+// The forwarder's are set in the upstreams file.
+// Pass --listen to answer rg-resolver-client's requests on port 17553 rather than looking up a
+// name, or --listen=<addr> (repeatable) to listen on other addresses, e.g. --listen=5353,
+// --listen=[::1]:17553 or --listen=0.0.0.0. On Ctrl-C it stops accepting clients and waits
+// for their requests in flight, for up to 10 seconds or --drain-timeout=<secs>. Clients on
+// loopback may also call flush_cache, inspect_cache, dump_stats, set_log_filter and
+// reload_config, which reads the upstreams and overrides again. Without --upstreams, the
+// system's nameservers are also read again whenever the network changes. general_lookup
+// answers with just the records, unless the request or the client's handshake asks for
+// verbose answers, which add a negative answer's SOA and where the answer came from.
+// Pass --stub to also answer standard DNS queries on UDP and TCP port 53 of 127.0.0.1, so it
+// can be the nameserver in /etc/resolv.conf, or --stub=<addr> (repeatable) as for --listen.
+// A name that doesn't exist is answered NXDOMAIN, and it or one without records of the type
+// asked for comes with the SOA of its zone, so clients can cache that.
+// Each listener queues the requests it reads, DNS queries or clients' JSON-RPC requests, for up to
+// 256 to be processed at once, or --queue-workers=<n>. Up to 1024, or --queue-capacity=<n>,
+// wait, and past that the listener stops reading until there's room, or with
+// --shed=wait:<ms> drops what has waited that long, or with --shed=reject drops it straight
+// away. Each client address may have up to 64 requests waiting or being processed, or
+// --queue-per-client=<n>, and clients take turns, so one flooding the resolver can't starve the
+// rest; past its share a JSON-RPC client is told the server is busy, with how long to wait before
+// retrying, and its DNS queries are dropped. The queues' depths and what they dropped are in
+// the metrics.
+// Pass --tsig-key=[<algorithm>:]<name>:<base64 secret> (repeatable) to answer only queries
+// signed with one of the keys, and sign the answers.
+// Pass --monitor to probe each upstream every 10 seconds while listening, reporting how often
+// and how quickly each answered in dump_stats and the metrics, or --monitor=<name> to ask for
+// a name other than the root. The upstreams probed are those configured at startup.
+// Pass --metrics to serve Prometheus metrics at http://127.0.0.1:9153/metrics while listening,
+// or --metrics=<addr> (repeatable) as for --listen.
+//
+// Run `rg-resolver dig [@server] [name] [type] [class] [+options]` to query a nameserver
+// directly and print its response as dig does; see DigArgs. With type AXFR, or ixfr=<serial>
+// for the changes since that version, it transfers the zone and prints its records, those an
+// IXFR removes marked - and those it adds marked +.
+//
+// Run `rg-resolver shell [addr]` for a shell that sends a running resolver, on port 17553 or at
+// addr as for --listen, lookups and admin commands: inspecting and flushing the cache, changing
+// what's logged and showing stats. Type help for the commands.
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
         eprintln!("ERROR: {e}");
         std::process::exit(1);
     }
 }
 
-fn run() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+async fn run() -> anyhow::Result<()> {
+    task::init_tracing();
+
+    if env::args().nth(1).as_deref() == Some("dig") {
+        return dig(DigArgs::parse_from(env::args().skip(1))).await;
+    }
+    if env::args().nth(1).as_deref() == Some("shell") {
+        return shell(ShellArgs::parse_from(env::args().skip(1))).await;
+    }
+
+    let (flags, names): (Vec<_>, Vec<_>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let mut system_fallback = false;
+    let mut audit_log = None;
+    let mut zone_specs = Vec::new();
+    let mut check_zones = false;
+    let mut correlation_id = None;
+    let mut views_path = None;
+    let mut qtype = QuestionType::RrType(rr::Type::A);
+    let mut journal_path = None;
+    let mut rewrites_path = None;
+    let mut overrides_path = None;
+    let mut overrides_reload = hosts::DEFAULT_RELOAD_INTERVAL;
+    let mut upstreams_path = None;
+    let mut recurse = false;
+    let mut root_hints = None;
+    let mut source_ports = SourcePorts::ephemeral();
+    let mut listen = Vec::new();
+    let mut stub_addrs = Vec::new();
+    let mut tsig_keys = Vec::new();
+    let mut metrics_addrs = Vec::new();
+    let mut drain_timeout = shutdown::DEFAULT_DRAIN_TIMEOUT;
+    let mut queue = QueueConfig::default();
+    let mut monitor_canary = None;
+    let mut cache_shards = Cache::DEFAULT_SHARDS;
+    let mut ttl_overrides_path = None;
+    let mut anchors_path = None;
+    let mut cache_max_entries = None;
+    let mut cache_max_bytes = None;
+    let mut cache_eviction = Eviction::default();
+    let mut round_robin = false;
+    let mut strictness = Strictness::default();
+    for flag in flags {
+        match flag.split_once('=') {
+            None if flag == "--system-fallback" => system_fallback = true,
+            None if flag == "--private" => privacy::global().set_aggregate_only(true),
+            None if flag == "--check-zones" => check_zones = true,
+            None if flag == "--recurse" => recurse = true,
+            None if flag == "--round-robin" => round_robin = true,
+            None if flag == "--listen" => listen.push(SocketAddr::from((
+                Ipv4Addr::LOCALHOST,
+                server::DEFAULT_PORT,
+            ))),
+            Some(("--listen", addr)) => {
+                listen.push(server::parse_listen_addr(addr, server::DEFAULT_PORT)?)
+            }
+            None if flag == "--stub" => {
+                stub_addrs.push(SocketAddr::from((Ipv4Addr::LOCALHOST, stub::DNS_PORT)))
+            }
+            Some(("--stub", addr)) => {
+                stub_addrs.push(server::parse_listen_addr(addr, stub::DNS_PORT)?)
+            }
+            Some(("--tsig-key", key)) => tsig_keys.push(key.parse::<tsig::Key>()?),
+            None if flag == "--metrics" => metrics_addrs.push(SocketAddr::from((
+                Ipv4Addr::LOCALHOST,
+                metrics::DEFAULT_PORT,
+            ))),
+            Some(("--metrics", addr)) => {
+                metrics_addrs.push(server::parse_listen_addr(addr, metrics::DEFAULT_PORT)?)
+            }
+            Some(("--drain-timeout", secs)) => {
+                drain_timeout = Duration::from_secs(
+                    secs.parse()
+                        .map_err(|e| anyhow::anyhow!("invalid drain timeout {secs}: {e}"))?,
+                )
+            }
+            Some(("--cache-shards", n)) => {
+                cache_shards = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid cache shards {n}"))?
+            }
+            Some(("--queue-capacity", n)) => {
+                queue.capacity = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid queue capacity {n}"))?
+            }
+            Some(("--queue-workers", n)) => {
+                queue.workers = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid queue workers {n}"))?
+            }
+            Some(("--queue-per-client", n)) => {
+                queue.per_client = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| anyhow::anyhow!("invalid queue share per client {n}"))?
+            }
+            Some(("--shed", policy)) => queue.policy = policy.parse()?,
+            None if flag == "--monitor" => monitor_canary = Some(String::from(".")),
+            Some(("--monitor", name)) => monitor_canary = Some(name.to_string()),
+            Some(("--cache-max-entries", n)) => {
+                cache_max_entries = Some(
+                    n.parse::<usize>()
+                        .map_err(|e| anyhow::anyhow!("invalid cache max entries {n}: {e}"))?,
+                )
+            }
+            Some(("--cache-max-memory", mib)) => {
+                cache_max_bytes = Some(
+                    mib.parse::<usize>()
+                        .map_err(|e| anyhow::anyhow!("invalid cache max memory {mib}: {e}"))?
+                        * 1024
+                        * 1024,
+                )
+            }
+            Some(("--cache-eviction", policy)) => cache_eviction = policy.parse()?,
+            Some(("--names", level)) => strictness = level.parse()?,
+            Some(("--root-hints", path)) => root_hints = Some(PathBuf::from(path)),
+            Some(("--source-ports", range)) => source_ports = SourcePorts::parse(range)?,
+            Some(("--audit-log", path)) => audit_log = Some(PathBuf::from(path)),
+            Some(("--query-log", path)) => querylog::set_query_log(QueryLog::open(
+                audit::AuditConfig::new(PathBuf::from(path)),
+            )?),
+            Some(("--journal", path)) => journal_path = Some(PathBuf::from(path)),
+            Some(("--rewrites", path)) => rewrites_path = Some(PathBuf::from(path)),
+            Some(("--overrides", path)) => overrides_path = Some(PathBuf::from(path)),
+            Some(("--overrides-reload", secs)) => {
+                overrides_reload = Duration::from_secs(secs.parse().map_err(|e| {
+                    anyhow::anyhow!("invalid overrides reload interval {secs}: {e}")
+                })?)
+            }
+            Some(("--upstreams", path)) => {
+                config::set_upstreams(UpstreamConfig::load(path.as_ref())?);
+                upstreams_path = Some(PathBuf::from(path));
+            }
+            Some(("--zone", spec)) => zone_specs.push(zone::ZoneSpec::parse(spec)?),
+            Some(("--type", r#type)) => {
+                qtype = match r#type.to_ascii_uppercase().as_str() {
+                    "A" => QuestionType::RrType(rr::Type::A),
+                    "AAAA" => QuestionType::RrType(rr::Type::AAAA),
+                    _ => anyhow::bail!("unsupported query type {type}, expected A or AAAA"),
+                }
+            }
+            Some(("--correlation-id", id)) => {
+                context::validate_correlation_id(id)?;
+                correlation_id = Some(id.to_string());
+            }
+            Some(("--negative-trust-anchors", path)) => anchors_path = Some(PathBuf::from(path)),
+            Some(("--views", path)) => views_path = Some(PathBuf::from(path)),
+            Some(("--ttl-overrides", path)) => ttl_overrides_path = Some(PathBuf::from(path)),
+            _ => anyhow::bail!("unknown option {flag}"),
+        }
+    }
+
+    // * Every zone is loaded, so all the broken ones are reported at once.
+    let mut zones = Vec::new();
+    let mut failed = 0;
+    for (spec, loaded) in zone_specs.iter().zip(zone::load_all(&zone_specs).await) {
+        match loaded {
+            Ok(zone) => {
+                info!(
+                    "Loaded zone {} from {} with {} records",
+                    zone.origin,
+                    spec.path.display(),
+                    zone.records.len()
+                );
+                zones.push(zone);
+            }
+            Err(e) => {
+                eprintln!("ERROR: zone {}: {e}", spec.origin);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} zones failed to load", zone_specs.len());
+    }
+    if check_zones {
+        println!("{} zones OK", zones.len());
+        return Ok(());
+    }
+
+    // * DNS clients get authoritative answers for the zones' names, with NXDOMAIN for ones that
+    // * don't exist, rather than having them resolved elsewhere.
+    let mut authority = Authority::new(zones.iter().map(zone::Zone::tree).collect());
+    if !tsig_keys.is_empty() {
+        authority = authority.with_required_keys(tsig_keys);
+    }
+    let mut resolvers: Vec<Arc<dyn Resolve>> = Vec::new();
+    let mut admin = Admin::new();
+    // * Without an upstreams file, the forwarder follows the system's nameservers.
+    let follow_system = upstreams_path.is_none() && !recurse;
+    if let Some(path) = upstreams_path {
+        admin = admin.with_upstreams_file(path);
+    }
+    if let Some(path) = overrides_path {
+        let overrides = Arc::new(OverridesFile::load(&path)?);
+        info!(
+            "Loaded {} overrides from {}",
+            overrides.current().len(),
+            path.display()
+        );
+        task::spawn_named(
+            "overrides reload",
+            overrides.clone().reload_on_change(overrides_reload),
+        );
+        authority = authority.with_overrides(overrides.clone());
+        admin = admin.with_overrides(overrides.clone());
+        // * Ahead of the zones, the cache and the upstreams.
+        resolvers.push(overrides);
+    }
+    let authority = Arc::new(authority);
+    resolvers.extend(zones.into_iter().map(|zone| -> Arc<dyn Resolve> {
+        Arc::new(resolve::Static::new(&zone.origin.to_string(), zone.records))
+    }));
+    // * The overrides and zones are already in memory, so only what comes from elsewhere is
+    // * cached, and flushing the cache never hides them.
+    let mut cache = Cache::new(cache_shards).with_eviction(cache_eviction);
+    if let Some(max) = cache_max_entries {
+        cache = cache.with_max_entries(max);
+    }
+    if let Some(max) = cache_max_bytes {
+        cache = cache.with_max_bytes(max);
+    }
+    if round_robin {
+        cache = cache.with_rotation();
+    }
+    if let Some(path) = ttl_overrides_path {
+        let config = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("reading TTL overrides {}: {e}", path.display()))?;
+        cache = cache.with_ttl_overrides(TtlOverrides::parse(&config)?);
+    }
+    let cache = Arc::new(cache);
+    let mut upstreams: Vec<Box<dyn Resolve>> = Vec::new();
+    if recurse {
+        // * The recursor's queries, which go to many servers, share one socket.
+        let exchange = Multiplexer::bind_from(Duration::from_secs(2), source_ports).await?;
+        // * What the recursor learns along the way, e.g. nameserver addresses, is cached with
+        // * the answers.
+        let mut recursor = Recursor::new(Box::new(exchange)).with_cache(cache.clone(), "default");
+        if let Some(path) = root_hints {
+            recursor = recursor.with_roots(recurse::load_root_hints(&path)?);
+        }
+        upstreams.push(Box::new(recursor));
+    } else {
+        upstreams.push(Box::new(resolve::Forwarder));
+    }
+    if system_fallback {
+        // * The system resolver's records carry no TTLs, so it's only a last resort.
+        upstreams.push(Box::new(resolve::System));
+    }
+    let upstreams: Arc<dyn Resolve> = Arc::new(resolve::Chain::new(upstreams));
+    // * Every view answers from the overrides, the zones and the upstreams, but caches what
+    // * the upstreams say in its own namespace.
+    let answering = |namespace: &str| -> Box<dyn Resolve> {
+        let mut chain = resolvers
+            .iter()
+            .map(|resolver| -> Box<dyn Resolve> { Box::new(resolver.clone()) })
+            .collect::<Vec<_>>();
+        chain.push(Box::new(Cached::new(
+            upstreams.clone(),
+            cache.clone(),
+            namespace,
+        )));
+        Box::new(resolve::Chain::new(chain))
+    };
+    let answering = match views_path {
+        Some(path) => {
+            let config = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("reading views {}: {e}", path.display()))?;
+            Box::new(Views::parse(&config, answering)?)
+        }
+        None => answering("default"),
+    };
+    let mut admin = admin.with_cache(cache);
+    if let Some(path) = anchors_path {
+        let config = std::fs::read_to_string(&path).map_err(|e| {
+            anyhow::anyhow!("reading negative trust anchors {}: {e}", path.display())
+        })?;
+        let anchors = NegativeTrustAnchors::parse(&config)?;
+        info!(
+            "Loaded {} negative trust anchors from {}",
+            anchors.list().len(),
+            path.display()
+        );
+        admin = admin.with_negative_trust_anchors(Arc::new(Mutex::new(anchors)));
+    }
+    let mut monitor = None;
+    if let Some(canary) = monitor_canary {
+        if recurse {
+            // * The recursor's upstreams are whichever nameservers it's referred to.
+            warn!("Not monitoring upstreams, there are none to monitor when recursing");
+        } else {
+            let servers = config::upstreams()?.servers;
+            let mut config = MonitorConfig::new(servers.iter().map(|server| server.addr).collect());
+            config.canary = canary;
+            let started = Monitor::new(config);
+            admin = admin.with_monitor(started.clone());
+            metrics::global().set_monitor(started.clone());
+            monitor = Some(started);
+        }
+    }
+    let admin = Arc::new(admin);
+    let mut resolver: Box<dyn Resolve> = Box::new(Validated::new(answering, strictness));
+    if let Some(path) = rewrites_path {
+        let config = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("reading address rewrites {}: {e}", path.display()))?;
+        let rewrites = Arc::new(AddressRewrites::parse(&config)?);
+        resolver = Box::new(Rewritten::new(resolver, rewrites));
+    }
+    let mut journal = None;
+    if let Some(path) = journal_path {
+        for dropped in Journal::recover(&path)? {
+            warn!(
+                "Previous run dropped request {} from {} for {}",
+                dropped.id, dropped.client, dropped.qname
+            );
+        }
+        let shared = Arc::new(Journal::new());
+        resolver = Box::new(Journaled::new(resolver, shared.clone()));
+        journal = Some((shared, path));
+    }
+    if let Some(path) = audit_log {
+        let log = audit::AuditLog::open(audit::AuditConfig::new(path))?;
+        resolver = Box::new(audit::Audited::new(resolver, log));
+    }
+
+    if !listen.is_empty() || !stub_addrs.is_empty() {
+        let listeners = server::bind_all(&listen).await?;
+        for listener in &listeners {
+            info!("Listening for clients on {}", listener.local_addr()?);
+        }
+        let stub_listeners = stub::bind_all(&stub_addrs).await?;
+        for listener in &stub_listeners {
+            info!("Answering DNS queries on {}", listener.udp.local_addr()?);
+        }
+        let metrics_listeners = server::bind_all(&metrics_addrs).await?;
+        for listener in &metrics_listeners {
+            info!(
+                "Serving metrics on http://{}/metrics",
+                listener.local_addr()?
+            );
+        }
+        if let Some(monitor) = &monitor {
+            monitor.spawn();
+        }
+        if follow_system {
+            match NetworkWatcher::start(NetworkWatcher::DEFAULT_DEBOUNCE) {
+                Ok(watcher) => {
+                    task::spawn_named(
+                        "nameserver reload",
+                        config::follow_system_on_network_change(watcher),
+                    );
+                }
+                Err(e) => warn!("Not reading nameservers again on network changes: {e}"),
+            }
+        }
+        let resolver: Arc<dyn Resolve> = Arc::from(resolver);
+        let shutdown = Shutdown::new();
+        let rpc = server::serve_all(
+            listeners,
+            resolver.clone(),
+            admin,
+            queue,
+            shutdown.subscribe(),
+        );
+        let dns = stub::serve_all(
+            stub_listeners,
+            resolver,
+            authority,
+            queue,
+            shutdown.subscribe(),
+        );
+        let metrics = metrics::serve_all(metrics_listeners, shutdown.subscribe());
+        // * A kind of server with no addresses to listen on is done straight away.
+        let serving = async { tokio::try_join!(rpc, dns, metrics).map(drop) };
+        tokio::pin!(serving);
+        tokio::select! {
+            res = &mut serving => return Ok(res?),
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        info!("Shutting down, waiting up to {drain_timeout:?} for requests in flight");
+        // * serve stops accepting once it sees the shutdown, and drops its signal.
+        let (served, drained) = tokio::join!(serving, shutdown.drain(drain_timeout));
+        if let Err(e) = served {
+            warn!("{e}");
+        }
+        if !drained {
+            warn!("Gave up waiting for requests in flight after {drain_timeout:?}");
+        }
+        // * Whatever is still in flight is journaled for the next run to report.
+        if let Some((journal, path)) = journal {
+            journal_in_flight(&journal, &path);
+        }
+        return Ok(());
+    }
 
-    let Some(domain_name) = env::args().skip(1).next() else {
+    if let Some((journal, path)) = journal {
+        // * The lookup fails with a "server restarting" error once it's journaled.
+        task::spawn_named("shutdown", async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                journal_in_flight(&journal, &path);
+            }
+        });
+    }
+
+    let Some(domain_name) = names.into_iter().next() else {
         anyhow::bail!("must specify domain name".to_string());
     };
+    let lookup = async {
+        info!(
+            "Querying address(es) for domain name {}...",
+            privacy::qname(&domain_name)
+        );
+        let answer = resolver
+            .lookup(&domain_name, qtype)
+            .await
+            .map_err(context::tag_error)?;
+        match answer {
+            // * The records hold names too, so only their number may be logged in private mode.
+            Some(answer) if privacy::global().is_aggregate_only() => {
+                info!("Got answer with {} records", answer.records().len())
+            }
+            Some(answer) => {
+                info!("Got answer with {} records", answer.records().len());
+                println!(";; ANSWER SECTION:");
+                answer
+                    .records()
+                    .iter()
+                    .for_each(|record| println!("{record}"));
+            }
+            None => info!("No answer"),
+        }
+        anyhow::Ok(())
+    };
+    match correlation_id {
+        Some(id) => context::with_correlation_id(id, lookup).await?,
+        None => lookup.await?,
+    }
+
+    Ok(())
+}
 
-    info!("Querying address(es) for domain name {domain_name}...");
-    let query = message::address_query(&domain_name);
-    info!("Sending query {:#?}", query);
-    let response = net::tx_then_rx_udp(&query)?;
-    info!("Got response: {:#?}", response);
+/// The arguments of the dig subcommand, which takes dig's own as well as its flags.
+#[derive(Parser)]
+#[command(
+    bin_name = "rg-resolver dig",
+    about = "Queries a nameserver directly and prints its response as dig does"
+)]
+struct DigArgs {
+    /// The query type, e.g. MX or AXFR.
+    #[arg(short = 't')]
+    r#type: Option<QuestionType>,
+    /// The query class, e.g. CH.
+    #[arg(short = 'c')]
+    class: Option<QuestionClass>,
+    /// Look up the names of this address, from its PTR records.
+    #[arg(short = 'x')]
+    reverse: Option<IpAddr>,
+    /// The port to query the server on.
+    #[arg(short = 'p')]
+    port: Option<u16>,
+    /// Sign the query, or zone transfer, with this TSIG key, given as
+    /// [algorithm:]name:secret, with the secret in base64.
+    #[arg(short = 'y')]
+    key: Option<tsig::Key>,
+    /// @server, then the name, type and class to ask about, and +options: +tcp or +udp,
+    /// +norecurse, +short and +time=<secs>.
+    args: Vec<String>,
+}
 
+async fn dig(args: DigArgs) -> anyhow::Result<()> {
+    let mut dig = Dig::new();
+    for arg in &args.args {
+        dig.apply(arg)?;
+    }
+    if let Some(addr) = args.reverse {
+        dig.reverse(addr)?;
+    }
+    // * The flags win over the free-form arguments.
+    if let Some(qtype) = args.r#type {
+        dig.qtype = Some(qtype);
+    }
+    if let Some(qclass) = args.class {
+        dig.qclass = Some(qclass);
+    }
+    if let Some(port) = args.port {
+        dig.port = port;
+    }
+    dig.key = args.key;
+    if dig.is_transfer() {
+        let mut transfer = dig.transfer().await?;
+        let mut count = 0;
+        while let Some(transferred) = transfer.next().await? {
+            println!("{}", dig.render_transferred(&transferred));
+            count += 1;
+        }
+        if !dig.short {
+            println!(";; XFR size: {count} records");
+        }
+        return Ok(());
+    }
+    let started = Instant::now();
+    let (server, response) = dig.send().await?;
+    print!("{}", dig.render(server, &response, started.elapsed()));
     Ok(())
 }
+
+/// The arguments of the shell subcommand.
+#[derive(Parser)]
+#[command(
+    bin_name = "rg-resolver shell",
+    about = "Sends a running resolver lookups and admin commands typed at a prompt"
+)]
+struct ShellArgs {
+    /// The resolver's JSON-RPC listener, as for --listen.
+    #[arg(default_value_t = server::DEFAULT_PORT.to_string())]
+    addr: String,
+}
+
+async fn shell(args: ShellArgs) -> anyhow::Result<()> {
+    let addr = server::parse_listen_addr(&args.addr, server::DEFAULT_PORT)?;
+    let mut shell = Shell::connect(addr).await?;
+    let input = tokio::io::BufReader::new(tokio::io::stdin());
+    shell.run(input, &mut std::io::stdout(), true).await
+}
+
+fn journal_in_flight(journal: &Journal, path: &Path) {
+    match journal.shut_down(path) {
+        Ok(count) => info!("Journaled {count} in-flight requests"),
+        Err(e) => warn!("{e}"),
+    }
+}
@@ -1,94 +1,975 @@
-use crate::{name, rr};
-use bytes::{Buf, BufMut};
-
-pub fn address_query(name: &str) -> Message {
-    let header = Header {
-        id: 1,
-        is_response: false,
-        opcode: Opcode::StandardQuery,
-        is_authoritative_answer: false,
-        is_truncated: false,
-        is_recursion_desired: false,
-        is_recursion_available: false,
-        response_code: ResponseCode::NoError,
-        question_count: 1,
-        answer_count: 0,
-        authority_count: 0,
-        additional_count: 0,
-    };
-    let question = Question {
-        name: name.to_string(),
-        r#type: QuestionType::RrType(rr::Type::A),
-        class: QuestionClass::RrClass(rr::Class::IN),
-    };
-    Message {
-        header,
-        questions: vec![question],
-        answers: Vec::new(),
-        authorities: Vec::new(),
-        additionals: Vec::new(),
-    }
-}
-
-#[derive(Debug)]
+use crate::edns::{self, Edns, EdnsOption, Padding};
+use crate::tsig::Tsig;
+use crate::wire::{self, Writer};
+use crate::{encoding, name, rr};
+use bytes::{Buf, BufMut, BytesMut};
+use rg_resolver_common::DomainName;
+
+/// A random query ID, so an off-path attacker can't predict it to forge a response
+/// (RFC 5452 section 9.2).
+fn next_id() -> u16 {
+    rand::random()
+}
+
+/// name with each letter made upper or lower case at random.
+fn randomize_case(name: &DomainName) -> DomainName {
+    let mut labels = name
+        .labels()
+        .map(|label| {
+            label
+                .chars()
+                .map(|c| {
+                    if rand::random() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        c.to_ascii_lowercase()
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    if name.is_absolute() {
+        labels.push(String::new());
+    }
+    DomainName::from_labels(labels).expect("changing the case of letters keeps a name valid")
+}
+
+pub fn address_query(name: &DomainName) -> Message {
+    Message::query(name, QuestionType::RrType(rr::Type::A))
+}
+
+/// Builds a query message question by question. The ID starts out random, recursion desired
+/// cleared, and the query without questions, authority records or an OPT record.
+#[derive(Clone, Debug)]
+pub struct QueryBuilder {
+    id: u16,
+    is_recursion_desired: bool,
+    questions: Vec<Question>,
+    authorities: Vec<rr::ResourceRecord>,
+    edns: Option<Edns>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        QueryBuilder {
+            id: next_id(),
+            is_recursion_desired: false,
+            questions: Vec::new(),
+            authorities: Vec::new(),
+            edns: None,
+        }
+    }
+
+    /// Replaces the random ID, e.g. to reproduce a query exactly.
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn recursion_desired(mut self, is_recursion_desired: bool) -> Self {
+        self.is_recursion_desired = is_recursion_desired;
+        self
+    }
+
+    /// Adds a question. Servers generally answer only queries with exactly one.
+    pub fn question(
+        mut self,
+        name: &DomainName,
+        qtype: QuestionType,
+        qclass: QuestionClass,
+    ) -> Self {
+        self.questions.push(Question {
+            name: name.clone(),
+            r#type: qtype,
+            class: qclass,
+        });
+        self
+    }
+
+    /// Adds a record to the authority section, as an IXFR query carries the SOA record of the
+    /// version of the zone the client has (RFC 1995 section 3).
+    pub fn authority(mut self, record: rr::ResourceRecord) -> Self {
+        self.authorities.push(record);
+        self
+    }
+
+    pub fn edns(mut self, edns: Edns) -> Self {
+        self.edns = Some(edns);
+        self
+    }
+
+    /// Adds an EDNS option, adding an OPT record advertising the default UDP payload size if
+    /// the query doesn't have one yet.
+    pub fn edns_option(mut self, option: EdnsOption) -> Self {
+        self.edns
+            .get_or_insert_with(|| Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE))
+            .options
+            .push(option);
+        self
+    }
+
+    pub fn build(self) -> Message {
+        let header = HeaderBuilder::new(self.id)
+            .recursion_desired(self.is_recursion_desired)
+            .counts(
+                self.questions.len() as u16,
+                0,
+                self.authorities.len() as u16,
+                0,
+            )
+            .build();
+        Message {
+            header,
+            questions: self.questions,
+            answers: Vec::new(),
+            authorities: self.authorities,
+            additionals: Vec::new(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            edns: self.edns,
+            tsig: None,
+        }
+    }
+}
+
+impl Default for QueryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// * The class and type update records use in place of a record's (RFC 2136 section 2.4).
+const CLASS_NONE: u16 = 254;
+const CLASS_ANY: u16 = 255;
+const TYPE_ANY: u16 = 255;
+
+/// The fewest bytes a question takes: the root name, a type and a class.
+const MIN_QUESTION_LEN: usize = 5;
+/// The fewest bytes a record takes: the root name, a type, a class, a TTL and an empty RDATA.
+const MIN_RECORD_LEN: usize = 11;
+
+/// A condition the zone has to meet for an UPDATE to be applied (RFC 2136 section 2.4).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Prerequisite {
+    /// The name has an RRset of the type, whatever its records.
+    RrsetExists(DomainName, rr::Type),
+    /// The record's RRset exists and holds exactly the records of the Record prerequisites for
+    /// it. The record's TTL is ignored.
+    Record(rr::ResourceRecord),
+    /// The name has no RRset of the type.
+    RrsetAbsent(DomainName, rr::Type),
+    /// The name owns at least one record.
+    NameInUse(DomainName),
+    /// The name owns no records.
+    NameNotInUse(DomainName),
+}
+
+impl Prerequisite {
+    fn to_record(&self, zone_class: u16) -> UpdateRecord {
+        use Prerequisite::*;
+        match self {
+            RrsetExists(name, r#type) => UpdateRecord::empty(name, r#type.serialize(), CLASS_ANY),
+            Record(rr) => UpdateRecord {
+                class: zone_class,
+                ttl: 0,
+                ..UpdateRecord::from(rr)
+            },
+            RrsetAbsent(name, r#type) => UpdateRecord::empty(name, r#type.serialize(), CLASS_NONE),
+            NameInUse(name) => UpdateRecord::empty(name, TYPE_ANY, CLASS_ANY),
+            NameNotInUse(name) => UpdateRecord::empty(name, TYPE_ANY, CLASS_NONE),
+        }
+    }
+}
+
+impl std::fmt::Display for Prerequisite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let zone_class = match self {
+            Prerequisite::Record(rr) => rr.class().serialize(),
+            _ => rr::Class::IN.serialize(),
+        };
+        write!(f, "{}", self.to_record(zone_class))
+    }
+}
+
+/// A change an UPDATE makes to the zone (RFC 2136 section 2.5).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    /// Adds the record to its RRset, unless the RRset already holds it.
+    Add(rr::ResourceRecord),
+    /// Deletes the name's RRset of the type.
+    DeleteRrset(DomainName, rr::Type),
+    /// Deletes every RRset the name owns.
+    DeleteName(DomainName),
+    /// Deletes the record from its RRset. The record's TTL is ignored.
+    Delete(rr::ResourceRecord),
+}
+
+impl Update {
+    fn to_record(&self) -> UpdateRecord {
+        use Update::*;
+        match self {
+            Add(rr) => UpdateRecord::from(rr),
+            DeleteRrset(name, r#type) => UpdateRecord::empty(name, r#type.serialize(), CLASS_ANY),
+            DeleteName(name) => UpdateRecord::empty(name, TYPE_ANY, CLASS_ANY),
+            Delete(rr) => UpdateRecord {
+                class: CLASS_NONE,
+                ttl: 0,
+                ..UpdateRecord::from(rr)
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Update {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_record())
+    }
+}
+
+/// A prerequisite or update as it's written in an UPDATE: a record whose class and type may be
+/// ANY or NONE, and whose data may be missing.
+#[derive(Clone, Debug)]
+struct UpdateRecord {
+    name: DomainName,
+    type_code: u16,
+    class: u16,
+    ttl: i32,
+    data: Option<rr::Data>,
+}
+
+impl UpdateRecord {
+    fn empty(name: &DomainName, type_code: u16, class: u16) -> Self {
+        UpdateRecord {
+            name: name.clone(),
+            type_code,
+            class,
+            ttl: 0,
+            data: None,
+        }
+    }
+
+    fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Self> {
+        let name = name::parse(msg, unparsed)?;
+        if unparsed.remaining() < 10 {
+            anyhow::bail!("parsing message: incomplete update record");
+        }
+        let type_code = unparsed.get_u16();
+        let class = unparsed.get_u16();
+        let ttl = unparsed.get_i32();
+        // * A record without data is written with an RDLENGTH of 0.
+        let data = if unparsed[..2] == [0, 0] {
+            unparsed.advance(2);
+            None
+        } else {
+            let r#type = rr::Type::parse(&mut &type_code.to_be_bytes()[..])?;
+            Some(rr::Data::parse(msg, unparsed, r#type)?)
+        };
+        Ok(UpdateRecord {
+            name,
+            type_code,
+            class,
+            ttl,
+            data,
+        })
+    }
+
+    /// The record's meaning in the prerequisite section of an UPDATE of a zone in zone_class.
+    fn into_prerequisite(self, zone_class: u16) -> anyhow::Result<Prerequisite> {
+        match (self.class, self.type_code, self.data.is_some()) {
+            (CLASS_ANY, TYPE_ANY, false) => Ok(Prerequisite::NameInUse(self.name)),
+            (CLASS_ANY, _, false) => {
+                let rr_type = self.rr_type()?;
+                Ok(Prerequisite::RrsetExists(self.name, rr_type))
+            }
+            (CLASS_NONE, TYPE_ANY, false) => Ok(Prerequisite::NameNotInUse(self.name)),
+            (CLASS_NONE, _, false) => {
+                let rr_type = self.rr_type()?;
+                Ok(Prerequisite::RrsetAbsent(self.name, rr_type))
+            }
+            (class, _, true) if class == zone_class => {
+                Ok(Prerequisite::Record(self.into_record(zone_class)?))
+            }
+            _ => anyhow::bail!("parsing message: invalid prerequisite for {}", self.name),
+        }
+    }
+
+    /// The record's meaning in the update section of an UPDATE of a zone in zone_class.
+    fn into_update(self, zone_class: u16) -> anyhow::Result<Update> {
+        match (self.class, self.type_code, self.data.is_some()) {
+            (CLASS_ANY, TYPE_ANY, false) => Ok(Update::DeleteName(self.name)),
+            (CLASS_ANY, _, false) => {
+                let rr_type = self.rr_type()?;
+                Ok(Update::DeleteRrset(self.name, rr_type))
+            }
+            (CLASS_NONE, _, true) => Ok(Update::Delete(self.into_record(zone_class)?)),
+            (class, _, true) if class == zone_class => {
+                Ok(Update::Add(self.into_record(zone_class)?))
+            }
+            _ => anyhow::bail!("parsing message: invalid update for {}", self.name),
+        }
+    }
+
+    fn rr_type(&self) -> anyhow::Result<rr::Type> {
+        rr::Type::parse(&mut &self.type_code.to_be_bytes()[..])
+    }
+
+    /// The record, in class, that the update record adds, deletes or requires.
+    fn into_record(self, class: u16) -> anyhow::Result<rr::ResourceRecord> {
+        let r#type = self.rr_type()?;
+        let class = rr::Class::parse(&mut &class.to_be_bytes()[..])?;
+        let data = self.data.expect("only records with data are converted");
+        rr::ResourceRecord::new(self.name, r#type, class, self.ttl, data)
+    }
+
+    fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        name::serialize_into(&self.name, None, w)?;
+        w.put_u16(self.type_code)?;
+        w.put_u16(self.class)?;
+        w.put_i32(self.ttl)?;
+        // * The data length is filled in once the data is written.
+        let len_offset = w.len();
+        w.put_u16(0)?;
+        if let Some(data) = &self.data {
+            data.write(w)?;
+        }
+        w.patch_u16(len_offset, (w.len() - len_offset - 2) as u16);
+        Ok(())
+    }
+
+    fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = name::serialize(&self.name, None)?;
+        buf.put_u16(self.type_code);
+        buf.put_u16(self.class);
+        buf.put_i32(self.ttl);
+        let mut data = match &self.data {
+            Some(data) => data.serialize()?,
+            None => Vec::new(),
+        };
+        buf.put_u16(data.len() as u16);
+        buf.append(&mut data);
+        Ok(buf)
+    }
+}
+
+impl From<&rr::ResourceRecord> for UpdateRecord {
+    fn from(rr: &rr::ResourceRecord) -> Self {
+        UpdateRecord {
+            name: rr.name().clone(),
+            type_code: rr.r#type().serialize(),
+            class: rr.class().serialize(),
+            ttl: rr.ttl(),
+            data: Some(rr.data().clone()),
+        }
+    }
+}
+
+impl std::fmt::Display for UpdateRecord {
+    /// The record as dig writes it, with ANY or NONE for the class and ANY for the type.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let class = match self.class {
+            CLASS_ANY => "ANY".to_string(),
+            CLASS_NONE => "NONE".to_string(),
+            code => match rr::Class::parse(&mut &code.to_be_bytes()[..]) {
+                Ok(class) => class.to_string(),
+                Err(_) => format!("CLASS{code}"),
+            },
+        };
+        let r#type = match (self.type_code, self.rr_type()) {
+            (TYPE_ANY, _) => "ANY".to_string(),
+            (_, Ok(r#type)) => r#type.to_string(),
+            (code, Err(_)) => format!("TYPE{code}"),
+        };
+        write!(f, "{}\t{}\t{class}\t{type}", self.name, self.ttl)?;
+        if let Some(data) = &self.data {
+            write!(f, "\t{data}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds an UPDATE of a zone in class IN (RFC 2136 section 2), prerequisite by prerequisite
+/// and update by update. The ID starts out random.
+#[derive(Clone, Debug)]
+pub struct UpdateBuilder {
+    id: u16,
+    zone: DomainName,
+    prerequisites: Vec<Prerequisite>,
+    updates: Vec<Update>,
+}
+
+impl UpdateBuilder {
+    pub fn new(zone: &DomainName) -> Self {
+        UpdateBuilder {
+            id: next_id(),
+            zone: zone.clone(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    /// Replaces the random ID, e.g. to reproduce an update exactly.
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn prerequisite(mut self, prerequisite: Prerequisite) -> Self {
+        self.prerequisites.push(prerequisite);
+        self
+    }
+
+    pub fn update(mut self, update: Update) -> Self {
+        self.updates.push(update);
+        self
+    }
+
+    pub fn build(self) -> Message {
+        let header = HeaderBuilder::new(self.id)
+            .opcode(Opcode::Update)
+            .counts(
+                1,
+                self.prerequisites.len() as u16,
+                self.updates.len() as u16,
+                0,
+            )
+            .build();
+        // * The zone section is a single question naming the zone, of type SOA.
+        let zone = Question {
+            name: self.zone,
+            r#type: QuestionType::RrType(rr::Type::SOA),
+            class: QuestionClass::RrClass(rr::Class::IN),
+        };
+        Message {
+            header,
+            questions: vec![zone],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            prerequisites: self.prerequisites,
+            updates: self.updates,
+            edns: None,
+            tsig: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Message {
     header: Header,
     questions: Vec<Question>,
     answers: Vec<rr::ResourceRecord>,
     authorities: Vec<rr::ResourceRecord>,
     additionals: Vec<rr::ResourceRecord>,
+    // * An UPDATE's answer and authority sections hold these instead (RFC 2136 section 2).
+    prerequisites: Vec<Prerequisite>,
+    updates: Vec<Update>,
+    edns: Option<Edns>,
+    tsig: Option<Tsig>,
 }
 
 impl Message {
-    pub fn parse(msg: &mut &[u8]) -> anyhow::Result<Message> {
-        // Keep msg pointing at the first byte of the message until the very end.
-        let mut unparsed = *msg;
+    /// The largest message sent over a stream transport, whose messages are length-prefixed.
+    const MAX_STREAM_LEN: usize = u16::MAX as usize;
+
+    /// Builds a query asking for the records of type qtype in class IN at name, with a random
+    /// ID and recursion desired cleared. See [QueryBuilder] for anything else.
+    pub fn query(name: &DomainName, qtype: QuestionType) -> Message {
+        QueryBuilder::new()
+            .question(name, qtype, QuestionClass::RrClass(rr::Class::IN))
+            .build()
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn id(&self) -> u16 {
+        self.header.id
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.header.id = id;
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.header.is_response
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        self.header.opcode
+    }
+
+    pub fn is_recursion_desired(&self) -> bool {
+        self.header.is_recursion_desired
+    }
+
+    pub fn is_authoritative_answer(&self) -> bool {
+        self.header.is_authoritative_answer
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.header.is_truncated
+    }
+
+    pub fn is_recursion_available(&self) -> bool {
+        self.header.is_recursion_available
+    }
+
+    /// Marks a response as answered from a zone this server is authoritative for.
+    pub fn set_authoritative_answer(&mut self, is_authoritative_answer: bool) {
+        self.header.is_authoritative_answer = is_authoritative_answer;
+    }
+
+    /// Marks a response as coming from a server that resolves queries recursively.
+    pub fn set_recursion_available(&mut self, is_recursion_available: bool) {
+        self.header.is_recursion_available = is_recursion_available;
+    }
+
+    pub fn response_code(&self) -> ResponseCode {
+        self.header.response_code
+    }
+
+    pub fn questions(&self) -> &[Question] {
+        &self.questions
+    }
+
+    pub fn answers(&self) -> &[rr::ResourceRecord] {
+        &self.answers
+    }
+
+    pub fn authorities(&self) -> &[rr::ResourceRecord] {
+        &self.authorities
+    }
+
+    pub fn additionals(&self) -> &[rr::ResourceRecord] {
+        &self.additionals
+    }
+
+    /// The zone an UPDATE updates, named by its zone section.
+    pub fn zone(&self) -> Option<&DomainName> {
+        match self.opcode() {
+            Opcode::Update => self.questions.first().map(|zone| &zone.name),
+            _ => None,
+        }
+    }
+
+    /// An UPDATE's prerequisite section.
+    pub fn prerequisites(&self) -> &[Prerequisite] {
+        &self.prerequisites
+    }
+
+    /// An UPDATE's update section.
+    pub fn updates(&self) -> &[Update] {
+        &self.updates
+    }
+
+    /// The answers of type rr_type, e.g. the addresses among a chain of CNAMEs.
+    pub fn answers_of_type(
+        &self,
+        rr_type: rr::Type,
+    ) -> impl Iterator<Item = &rr::ResourceRecord> + '_ {
+        self.answers.iter().filter(move |rr| rr.r#type() == rr_type)
+    }
+
+    /// The records in every section, answers first, then authorities, then additionals.
+    pub fn records(&self) -> impl Iterator<Item = &rr::ResourceRecord> + '_ {
+        self.answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+    }
+
+    /// The message's OPT pseudo-record, which isn't included in additionals.
+    pub fn edns(&self) -> Option<&Edns> {
+        self.edns.as_ref()
+    }
+
+    pub fn set_edns(&mut self, edns: Option<Edns>) {
+        self.edns = edns;
+    }
+
+    /// The message's TSIG pseudo-record, which isn't included in additionals either. See
+    /// [crate::tsig::Key] to sign and verify messages.
+    pub fn tsig(&self) -> Option<&Tsig> {
+        self.tsig.as_ref()
+    }
+
+    pub fn set_tsig(&mut self, tsig: Option<Tsig>) {
+        self.tsig = tsig;
+    }
+
+    /// Returns true if this message is a response to query: it has the query's ID and asks
+    /// the same questions (RFC 5452 section 9.1). Anything else is stray or forged.
+    pub fn is_response_to(&self, query: &Message) -> bool {
+        self.is_response()
+            && self.id() == query.id()
+            && self.questions.len() == query.questions.len()
+            && self
+                .questions
+                .iter()
+                .zip(&query.questions)
+                .all(|(response, query)| response.matches(query))
+    }
+
+    /// This query with each letter of its question names in random case, so a forged response
+    /// also has to guess the case of every letter (draft-vixie-dnsext-dns0x20). A response to
+    /// it has to pass [Message::echoes_case_of].
+    pub fn with_random_case(&self) -> Message {
+        let mut query = self.clone();
+        for question in &mut query.questions {
+            question.name = randomize_case(&question.name);
+        }
+        query
+    }
+
+    /// Returns true if this response's questions have the very names of query's, down to the
+    /// case of each letter, as servers copy them.
+    pub fn echoes_case_of(&self, query: &Message) -> bool {
+        self.questions.len() == query.questions.len()
+            && self
+                .questions
+                .iter()
+                .zip(&query.questions)
+                .all(|(response, query)| response.name.eq_exact(&query.name))
+    }
+
+    /// Gives this response's question names, and the records they own, the case of the names
+    /// in query's questions, undoing [Message::with_random_case] before it's passed on.
+    pub fn restore_case(&mut self, query: &Message) {
+        for (response, query) in self.questions.iter_mut().zip(&query.questions) {
+            if response.name != query.name {
+                continue;
+            }
+            response.name = query.name.clone();
+            for section in [
+                &mut self.answers,
+                &mut self.authorities,
+                &mut self.additionals,
+            ] {
+                *section = std::mem::take(section)
+                    .into_iter()
+                    .map(|rr| {
+                        if *rr.name() == query.name {
+                            rr.with_name(query.name.clone())
+                        } else {
+                            rr
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Builds a response to this query with the given records.
+    pub fn response(
+        &self,
+        response_code: ResponseCode,
+        answers: Vec<rr::ResourceRecord>,
+        authorities: Vec<rr::ResourceRecord>,
+        additionals: Vec<rr::ResourceRecord>,
+    ) -> Message {
+        let header = Header {
+            is_response: true,
+            is_authoritative_answer: false,
+            is_truncated: false,
+            is_recursion_available: false,
+            response_code,
+            answer_count: answers.len(),
+            authority_count: authorities.len(),
+            additional_count: additionals.len(),
+            ..self.header.clone()
+        };
+        Message {
+            header,
+            questions: self.questions.clone(),
+            answers,
+            authorities,
+            additionals,
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            edns: None,
+            tsig: None,
+        }
+    }
+
+    /// Builds a response to this query with no records, e.g. to refuse it or report an error.
+    pub fn empty_response(&self, response_code: ResponseCode) -> Message {
+        self.response(response_code, Vec::new(), Vec::new(), Vec::new())
+    }
+
+    /// Builds a NOERROR response to this query with answers and no other records.
+    pub fn answer_response(&self, answers: Vec<rr::ResourceRecord>) -> Message {
+        self.response(ResponseCode::NoError, answers, Vec::new(), Vec::new())
+    }
+
+    /// Parses the message at the start of buf. Use a Parser to find out how many bytes it
+    /// took up, or to parse several messages from one buffer.
+    pub fn parse(buf: &[u8]) -> anyhow::Result<Message> {
+        Parser::new(buf).next_message()
+    }
+
+    /// Parses the message starting at the first byte of msg, returning it along with its length.
+    fn parse_prefix(msg: &[u8]) -> anyhow::Result<(Message, usize)> {
+        let mut unparsed = msg;
         let header = Header::parse(&mut unparsed)?;
         if header.is_truncated {
             anyhow::bail!("parsing message: message is truncated");
         }
 
-        let mut questions = Vec::with_capacity(header.question_count);
+        // * The counts are only what the header claims, so no more is reserved than the rest of
+        // * the message could hold.
+        let mut questions =
+            Vec::with_capacity(header.question_count.min(unparsed.len() / MIN_QUESTION_LEN));
         for _ in 0..header.question_count {
             let question = Question::parse(msg, &mut unparsed)?;
             questions.push(question);
         }
 
-        let mut answers = Vec::with_capacity(header.answer_count);
-        for _ in 0..header.answer_count {
-            let answer = rr::ResourceRecord::parse(msg, &mut unparsed)?;
-            answers.push(answer);
-        }
+        let mut answers = Vec::new();
+        let mut authorities = Vec::new();
+        let mut prerequisites = Vec::new();
+        let mut updates = Vec::new();
+        if header.opcode == Opcode::Update {
+            let zone_class = match questions.as_slice() {
+                [zone] => zone.class.serialize(),
+                _ => anyhow::bail!("parsing message: UPDATE without exactly one zone"),
+            };
+            for _ in 0..header.answer_count {
+                let record = UpdateRecord::parse(msg, &mut unparsed)?;
+                prerequisites.push(record.into_prerequisite(zone_class)?);
+            }
+            for _ in 0..header.authority_count {
+                let record = UpdateRecord::parse(msg, &mut unparsed)?;
+                updates.push(record.into_update(zone_class)?);
+            }
+        } else {
+            answers.reserve(header.answer_count.min(unparsed.len() / MIN_RECORD_LEN));
+            for _ in 0..header.answer_count {
+                let answer = rr::ResourceRecord::parse(msg, &mut unparsed)?;
+                answers.push(answer);
+            }
 
-        let mut authorities = Vec::with_capacity(header.authority_count);
-        for _ in 0..header.authority_count {
-            let authority = rr::ResourceRecord::parse(msg, &mut unparsed)?;
-            authorities.push(authority);
+            authorities.reserve(header.authority_count.min(unparsed.len() / MIN_RECORD_LEN));
+            for _ in 0..header.authority_count {
+                let authority = rr::ResourceRecord::parse(msg, &mut unparsed)?;
+                authorities.push(authority);
+            }
         }
 
-        let mut additionals = Vec::with_capacity(header.additional_count);
-        for _ in 0..header.additional_count {
+        let mut additionals =
+            Vec::with_capacity(header.additional_count.min(unparsed.len() / MIN_RECORD_LEN));
+        let mut edns = None;
+        let mut tsig = None;
+        for i in 0..header.additional_count {
+            if Tsig::is_next(msg, unparsed) {
+                // * The TSIG record has to come last, after everything it signs (RFC 8945
+                // * section 5.1).
+                if i + 1 != header.additional_count {
+                    anyhow::bail!("parsing message: TSIG record isn't the last record");
+                }
+                tsig = Some(Tsig::parse(msg, &mut unparsed)?);
+                continue;
+            }
+            if Edns::is_next(unparsed) {
+                if edns.is_some() {
+                    anyhow::bail!("parsing message: more than one OPT record");
+                }
+                edns = Some(Edns::parse(&mut unparsed)?);
+                continue;
+            }
             let additional = rr::ResourceRecord::parse(msg, &mut unparsed)?;
             additionals.push(additional);
         }
 
-        *msg = unparsed;
+        let len = msg.len() - unparsed.len();
         let message = Message {
             header,
             questions,
             answers,
             authorities,
             additionals,
+            prerequisites,
+            updates,
+            edns,
+            tsig,
         };
-        Ok(message)
+        Ok((message, len))
     }
 
+    /// Serializes the message for a datagram transport, which limits its size to 512 bytes,
+    /// or to the advertised UDP payload size if the message has an OPT record.
     pub fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        let limit = self.datagram_limit();
+        let vec = self.serialize_unchecked()?;
+        if vec.len() > limit {
+            anyhow::bail!("serializing message: message requires truncation")
+        }
+        Ok(vec)
+    }
+
+    /// Serializes a response for a datagram transport to a client that can receive limit bytes.
+    ///
+    /// A response that doesn't fit is sent without its records and with the TC bit set, so the
+    /// client asks again over TCP (RFC 2181 section 9).
+    pub fn serialize_truncating(&self, limit: usize) -> anyhow::Result<Vec<u8>> {
+        let vec = self.serialize_unchecked()?;
+        if vec.len() <= limit {
+            return Ok(vec);
+        }
+        let truncated = Message {
+            header: Header {
+                is_truncated: true,
+                answer_count: 0,
+                authority_count: 0,
+                additional_count: 0,
+                ..self.header.clone()
+            },
+            questions: self.questions.clone(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            edns: self.edns.clone(),
+            tsig: None,
+        };
+        truncated.serialize_unchecked()
+    }
+
+    /// Serializes the message like [Message::serialize], but into the start of buf rather than
+    /// a new Vec, returning the number of bytes written.
+    ///
+    /// Only names with escapes need any allocation.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let limit = self.datagram_limit();
+        let buf_len = buf.len();
+        let mut w = Writer::new(&mut buf[..buf_len.min(limit)]);
+        match self.write(&mut w) {
+            Ok(()) => Ok(w.len()),
+            Err(e) if wire::is_full(&e) && buf_len >= limit => {
+                anyhow::bail!("serializing message: message requires truncation")
+            }
+            Err(e) if wire::is_full(&e) => anyhow::bail!("serializing message: buffer too small"),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Serializes the message like [Message::serialize], appending it to buf, which only
+    /// allocates if buf doesn't already have the capacity.
+    pub fn serialize_into_bytes(&self, buf: &mut BytesMut) -> anyhow::Result<usize> {
+        let start = buf.len();
+        buf.resize(start + self.datagram_limit(), 0);
+        let written = self.serialize_into(&mut buf[start..]);
+        buf.truncate(start + *written.as_ref().unwrap_or(&0));
+        written
+    }
+
+    /// 512 bytes, or the advertised UDP payload size if the message has an OPT record.
+    fn datagram_limit(&self) -> usize {
+        match &self.edns {
+            Some(edns) => (edns.udp_payload_size as usize).max(512),
+            None => 512,
+        }
+    }
+
+    fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        let header = Header {
+            additional_count: self.additionals.len()
+                + usize::from(self.edns.is_some())
+                + usize::from(self.tsig.is_some()),
+            ..self.header.clone()
+        };
+        header.write(w)?;
+        for question in &self.questions {
+            question.write(w)?;
+        }
+        for record in self.update_records() {
+            record.write(w)?;
+        }
+        for rr in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            rr.write(w)?;
+        }
+        if let Some(edns) = &self.edns {
+            edns.write(w)?;
+        }
+        if let Some(tsig) = &self.tsig {
+            tsig.write(w)?;
+        }
+        Ok(())
+    }
+
+    /// Serializes the message for an encrypted stream transport, padded according to padding.
+    ///
+    /// An OPT record is added to carry the padding if the message doesn't have one.
+    pub fn serialize_padded(&self, padding: Padding) -> anyhow::Result<Vec<u8>> {
+        let mut padded = Message {
+            header: self.header.clone(),
+            questions: self.questions.clone(),
+            answers: self.answers.clone(),
+            authorities: self.authorities.clone(),
+            additionals: self.additionals.clone(),
+            prerequisites: self.prerequisites.clone(),
+            updates: self.updates.clone(),
+            edns: self.edns.clone(),
+            tsig: self.tsig.clone(),
+        };
+        if padding != Padding::None {
+            let edns = padded
+                .edns
+                .get_or_insert_with(|| Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE));
+            edns.pad(0, Padding::None);
+            let unpadded_len = padded.serialize_unchecked()?.len();
+            padded.edns.as_mut().unwrap().pad(unpadded_len, padding);
+        }
+        let vec = padded.serialize_unchecked()?;
+        if vec.len() > Self::MAX_STREAM_LEN {
+            anyhow::bail!("serializing message: message is too long for a stream transport")
+        }
+        Ok(vec)
+    }
+
+    /// Serializes the message for a TCP stream, preceded by its 2-byte length (RFC 1035 section
+    /// 4.2.2).
+    pub fn serialize_framed(&self) -> anyhow::Result<Vec<u8>> {
+        let message = self.serialize_padded(Padding::None)?;
+        let mut framed = Vec::with_capacity(2 + message.len());
+        framed.put_u16(message.len() as u16);
+        framed.extend_from_slice(&message);
+        Ok(framed)
+    }
+
+    /// An UPDATE's prerequisites then its updates, as they're written after the zone section.
+    fn update_records(&self) -> impl Iterator<Item = UpdateRecord> + '_ {
+        let zone_class = self
+            .questions
+            .first()
+            .map_or(rr::Class::IN.serialize(), |zone| zone.class.serialize());
+        self.prerequisites
+            .iter()
+            .map(move |prerequisite| prerequisite.to_record(zone_class))
+            .chain(self.updates.iter().map(|update| update.to_record()))
+    }
+
+    fn serialize_unchecked(&self) -> anyhow::Result<Vec<u8>> {
+        let header = Header {
+            additional_count: self.additionals.len()
+                + usize::from(self.edns.is_some())
+                + usize::from(self.tsig.is_some()),
+            ..self.header.clone()
+        };
         let mut vec = Vec::new();
-        vec.append(&mut self.header.serialize());
+        vec.append(&mut header.serialize());
         for question in &self.questions {
             vec.append(&mut question.serialize()?);
         }
+        for record in self.update_records() {
+            vec.append(&mut record.serialize()?);
+        }
         for answer in &self.answers {
             vec.append(&mut answer.serialize()?);
         }
@@ -98,13 +979,174 @@ impl Message {
         for additional in &self.additionals {
             vec.append(&mut additional.serialize()?);
         }
-        if vec.len() > 512 {
-            anyhow::bail!("serializing message: message requires truncation")
+        if let Some(edns) = &self.edns {
+            vec.append(&mut edns.serialize());
+        }
+        if let Some(tsig) = &self.tsig {
+            vec.append(&mut tsig.serialize()?);
         }
         Ok(vec)
     }
 }
 
+impl std::fmt::Display for Message {
+    /// The message as dig prints it: the header, the OPT pseudo-record if there is one, then
+    /// each non-empty section with its records in presentation format.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            self.opcode(),
+            self.response_code(),
+            self.id()
+        )?;
+        let flags = [
+            (self.is_response(), "qr"),
+            (self.is_authoritative_answer(), "aa"),
+            (self.is_truncated(), "tc"),
+            (self.is_recursion_desired(), "rd"),
+            (self.is_recursion_available(), "ra"),
+        ];
+        let flags = flags
+            .iter()
+            .filter(|(is_set, _)| *is_set)
+            .map(|(_, flag)| *flag)
+            .collect::<Vec<_>>();
+        // * An UPDATE's sections are named as RFC 2136 names them.
+        let is_update = self.opcode() == Opcode::Update;
+        let [question, answer, authority] = if is_update {
+            ["ZONE", "PREREQ", "UPDATE"]
+        } else {
+            ["QUERY", "ANSWER", "AUTHORITY"]
+        };
+        // * The OPT and TSIG records are counted as additional records, as they are on the wire.
+        writeln!(
+            f,
+            ";; flags: {}; {question}: {}, {answer}: {}, {authority}: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            self.questions.len(),
+            self.answers.len() + self.prerequisites.len(),
+            self.authorities.len() + self.updates.len(),
+            self.additionals.len()
+                + usize::from(self.edns.is_some())
+                + usize::from(self.tsig.is_some())
+        )?;
+        if let Some(edns) = &self.edns {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            let flags = if edns.dnssec_ok { " do" } else { "" };
+            writeln!(
+                f,
+                "; EDNS: version: {}, flags:{flags}; udp: {}",
+                edns.version, edns.udp_payload_size
+            )?;
+            for option in &edns.options {
+                match option {
+                    EdnsOption::Padding(len) => writeln!(f, "; PADDING: ({len} bytes)")?,
+                    EdnsOption::Unknown { code, data } => {
+                        writeln!(f, "; OPT={code}: {}", encoding::encode_hex(data))?
+                    }
+                }
+            }
+        }
+        if !self.questions.is_empty() {
+            let section = if is_update { "ZONE" } else { "QUESTION" };
+            writeln!(f, "\n;; {section} SECTION:")?;
+            for question in &self.questions {
+                writeln!(f, ";{question}")?;
+            }
+        }
+        if !self.prerequisites.is_empty() {
+            writeln!(f, "\n;; PREREQUISITE SECTION:")?;
+            for prerequisite in &self.prerequisites {
+                writeln!(f, "{prerequisite}")?;
+            }
+        }
+        if !self.updates.is_empty() {
+            writeln!(f, "\n;; UPDATE SECTION:")?;
+            for update in &self.updates {
+                writeln!(f, "{update}")?;
+            }
+        }
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.additionals),
+        ];
+        for (section, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {section} SECTION:")?;
+            for record in records {
+                writeln!(f, "{record}")?;
+            }
+        }
+        if let Some(tsig) = &self.tsig {
+            writeln!(f, "\n;; TSIG PSEUDOSECTION:")?;
+            writeln!(f, "{tsig}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses messages one after another from a buffer, keeping track of where the next one starts.
+pub struct Parser<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Parser { buf, offset: 0 }
+    }
+
+    /// The number of bytes consumed by the messages parsed so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The bytes not yet parsed.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.offset..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == self.buf.len()
+    }
+
+    /// Parses the message starting at the offset.
+    pub fn next_message(&mut self) -> anyhow::Result<Message> {
+        // * Compressed names point from the start of the message, not the buffer.
+        let (message, len) = Message::parse_prefix(self.remaining())?;
+        self.offset += len;
+        Ok(message)
+    }
+
+    /// Parses the message starting at the offset, preceded by its 2-byte length as on a TCP
+    /// stream (RFC 1035 section 4.2.2).
+    ///
+    /// If the buffer doesn't hold all of the message yet, nothing is consumed, so the parse can
+    /// be retried once more of the stream has been read.
+    pub fn next_framed(&mut self) -> anyhow::Result<Message> {
+        let remaining = self.remaining();
+        if remaining.len() < 2 {
+            anyhow::bail!("parsing message: incomplete length prefix");
+        }
+        let len = u16::from_be_bytes([remaining[0], remaining[1]]) as usize;
+        let Some(framed) = remaining.get(2..2 + len) else {
+            anyhow::bail!("parsing message: incomplete message");
+        };
+        let (message, parsed_len) = Message::parse_prefix(framed)?;
+        if parsed_len != len {
+            anyhow::bail!(
+                "parsing message: length prefix is {len} but message is {parsed_len} bytes"
+            );
+        }
+        self.offset += 2 + len;
+        Ok(message)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Header {
     id: u16,
@@ -122,6 +1164,65 @@ pub struct Header {
 }
 
 impl Header {
+    /// Parses just the header at the start of buf. Unlike [Message::parse], this accepts a
+    /// truncated message, so a response with the TC bit set can be recognized.
+    pub fn peek(buf: &[u8]) -> anyhow::Result<Header> {
+        Self::parse(&mut &buf[..])
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.is_response
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.is_truncated
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    pub fn is_authoritative_answer(&self) -> bool {
+        self.is_authoritative_answer
+    }
+
+    pub fn is_recursion_desired(&self) -> bool {
+        self.is_recursion_desired
+    }
+
+    pub fn is_recursion_available(&self) -> bool {
+        self.is_recursion_available
+    }
+
+    pub fn response_code(&self) -> ResponseCode {
+        self.response_code
+    }
+
+    /// The number of questions the header says the message has.
+    pub fn question_count(&self) -> usize {
+        self.question_count
+    }
+
+    /// The number of answers the header says the message has.
+    pub fn answer_count(&self) -> usize {
+        self.answer_count
+    }
+
+    /// The number of authority records the header says the message has.
+    pub fn authority_count(&self) -> usize {
+        self.authority_count
+    }
+
+    /// The number of additional records the header says the message has, counting an OPT
+    /// record.
+    pub fn additional_count(&self) -> usize {
+        self.additional_count
+    }
+
     fn parse(unparsed: &mut &[u8]) -> anyhow::Result<Header> {
         macro_rules! get_u16_field {
             ($size:expr, $field:expr) => {{
@@ -163,38 +1264,143 @@ impl Header {
             response_code,
             question_count,
             answer_count,
-            authority_count: authority_count,
+            authority_count,
             additional_count,
         };
         Ok(header)
     }
 
-    fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-
-        buf.put_u16(self.id);
-        let bitfields: u16 = (self.is_response as u16) << 15
-            | (self.opcode.serialize() as u16) << 11
+    fn bitfields(&self) -> u16 {
+        (self.is_response as u16) << 15
+            | self.opcode.serialize() << Opcode::BIT_POS
             | (self.is_authoritative_answer as u16) << 10
             | (self.is_truncated as u16) << 9
             | (self.is_recursion_desired as u16) << 8
             | (self.is_recursion_available as u16) << 7
-            | self.response_code.serialize() as u16;
-        buf.put_u16(bitfields);
+            | self.response_code.serialize()
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.put_u16(self.id);
+        buf.put_u16(self.bitfields());
         buf.put_u16(self.question_count as u16);
         buf.put_u16(self.answer_count as u16);
         buf.put_u16(self.authority_count as u16);
         buf.put_u16(self.additional_count as u16);
 
-        buf
+        buf
+    }
+
+    /// Serializes the header into the start of buf, returning the number of bytes written.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let mut w = Writer::new(buf);
+        self.write(&mut w)
+            .map_err(|_| anyhow::anyhow!("serializing header: buffer too small"))?;
+        Ok(w.len())
+    }
+
+    fn write(&self, w: &mut Writer) -> Result<(), wire::Full> {
+        w.put_u16(self.id)?;
+        w.put_u16(self.bitfields())?;
+        w.put_u16(self.question_count as u16)?;
+        w.put_u16(self.answer_count as u16)?;
+        w.put_u16(self.authority_count as u16)?;
+        w.put_u16(self.additional_count as u16)
+    }
+}
+
+/// Builds a [Header] field by field. Every flag starts cleared, the opcode as a standard
+/// query, the response code as NOERROR, and the counts as zero.
+#[derive(Clone, Debug)]
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    pub fn new(id: u16) -> Self {
+        HeaderBuilder {
+            header: Header {
+                id,
+                is_response: false,
+                opcode: Opcode::StandardQuery,
+                is_authoritative_answer: false,
+                is_truncated: false,
+                is_recursion_desired: false,
+                is_recursion_available: false,
+                response_code: ResponseCode::NoError,
+                question_count: 0,
+                answer_count: 0,
+                authority_count: 0,
+                additional_count: 0,
+            },
+        }
+    }
+
+    pub fn response(mut self, is_response: bool) -> Self {
+        self.header.is_response = is_response;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> Self {
+        self.header.opcode = opcode;
+        self
+    }
+
+    pub fn authoritative_answer(mut self, is_authoritative_answer: bool) -> Self {
+        self.header.is_authoritative_answer = is_authoritative_answer;
+        self
+    }
+
+    pub fn truncated(mut self, is_truncated: bool) -> Self {
+        self.header.is_truncated = is_truncated;
+        self
+    }
+
+    pub fn recursion_desired(mut self, is_recursion_desired: bool) -> Self {
+        self.header.is_recursion_desired = is_recursion_desired;
+        self
+    }
+
+    pub fn recursion_available(mut self, is_recursion_available: bool) -> Self {
+        self.header.is_recursion_available = is_recursion_available;
+        self
+    }
+
+    pub fn response_code(mut self, response_code: ResponseCode) -> Self {
+        self.header.response_code = response_code;
+        self
+    }
+
+    /// Sets the number of questions, answers, authorities, and additionals.
+    pub fn counts(
+        mut self,
+        questions: u16,
+        answers: u16,
+        authorities: u16,
+        additionals: u16,
+    ) -> Self {
+        self.header.question_count = questions as usize;
+        self.header.answer_count = answers as usize;
+        self.header.authority_count = authorities as usize;
+        self.header.additional_count = additionals as usize;
+        self
+    }
+
+    pub fn build(self) -> Header {
+        self.header
     }
 }
 
+/// The kind of query a message is (RFC 1035 section 4.1.1).
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum Opcode {
+pub enum Opcode {
     StandardQuery,
     InverseQuery,
     ServerStatusRequest,
+    /// A dynamic update of a zone (RFC 2136).
+    Update,
 }
 
 impl Opcode {
@@ -205,6 +1411,7 @@ impl Opcode {
             0 => Ok(Opcode::StandardQuery),
             1 => Ok(Opcode::InverseQuery),
             2 => Ok(Opcode::ServerStatusRequest),
+            5 => Ok(Opcode::Update),
             n => Err(anyhow::anyhow!("reserved opcode: {n}")),
         }
     }
@@ -215,18 +1422,43 @@ impl Opcode {
             StandardQuery => 0,
             InverseQuery => 1,
             ServerStatusRequest => 2,
+            Update => 5,
         }
     }
 }
 
+impl std::fmt::Display for Opcode {
+    /// The opcode's mnemonic, as dig shows it.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Opcode::StandardQuery => "QUERY",
+            Opcode::InverseQuery => "IQUERY",
+            Opcode::ServerStatusRequest => "STATUS",
+            Opcode::Update => "UPDATE",
+        };
+        f.write_str(mnemonic)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum ResponseCode {
+pub enum ResponseCode {
     NoError,
     FormatError,
     ServerFailure,
     NameError,
     NotImplemented,
     Refused,
+    // The codes an UPDATE fails with (RFC 2136 section 2.2).
+    /// A name that should not exist does.
+    YxDomain,
+    /// An RRset that should not exist does.
+    YxRrset,
+    /// An RRset that should exist doesn't.
+    NxRrset,
+    /// The server isn't authoritative for the zone.
+    NotAuth,
+    /// A name isn't in the zone.
+    NotZone,
 }
 
 impl ResponseCode {
@@ -238,6 +1470,11 @@ impl ResponseCode {
             3 => Ok(ResponseCode::NameError),
             4 => Ok(ResponseCode::NotImplemented),
             5 => Ok(ResponseCode::Refused),
+            6 => Ok(ResponseCode::YxDomain),
+            7 => Ok(ResponseCode::YxRrset),
+            8 => Ok(ResponseCode::NxRrset),
+            9 => Ok(ResponseCode::NotAuth),
+            10 => Ok(ResponseCode::NotZone),
             n => Err(anyhow::anyhow!("reserved response code: {n}")),
         }
     }
@@ -251,18 +1488,69 @@ impl ResponseCode {
             NameError => 3,
             NotImplemented => 4,
             Refused => 5,
+            YxDomain => 6,
+            YxRrset => 7,
+            NxRrset => 8,
+            NotAuth => 9,
+            NotZone => 10,
         }
     }
 }
 
+impl std::fmt::Display for ResponseCode {
+    /// The code's mnemonic, as dig shows it (RFC 6895 section 2.3).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            ResponseCode::NoError => "NOERROR",
+            ResponseCode::FormatError => "FORMERR",
+            ResponseCode::ServerFailure => "SERVFAIL",
+            ResponseCode::NameError => "NXDOMAIN",
+            ResponseCode::NotImplemented => "NOTIMP",
+            ResponseCode::Refused => "REFUSED",
+            ResponseCode::YxDomain => "YXDOMAIN",
+            ResponseCode::YxRrset => "YXRRSET",
+            ResponseCode::NxRrset => "NXRRSET",
+            ResponseCode::NotAuth => "NOTAUTH",
+            ResponseCode::NotZone => "NOTZONE",
+        };
+        f.write_str(mnemonic)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Question {
-    name: String,
+    name: DomainName,
     r#type: QuestionType,
     class: QuestionClass,
 }
 
 impl Question {
+    pub fn new(name: DomainName, r#type: QuestionType, class: QuestionClass) -> Self {
+        Question {
+            name,
+            r#type,
+            class,
+        }
+    }
+
+    /// Returns true if other asks the same question, comparing names without regard to case
+    /// as DNS does.
+    pub fn matches(&self, other: &Question) -> bool {
+        self.name == other.name && self.r#type == other.r#type && self.class == other.class
+    }
+
+    pub fn name(&self) -> &DomainName {
+        &self.name
+    }
+
+    pub fn r#type(&self) -> QuestionType {
+        self.r#type
+    }
+
+    pub fn class(&self) -> QuestionClass {
+        self.class
+    }
+
     /// * msg must point to the very first byte of the message.
     fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Self> {
         let name = name::parse(msg, unparsed)?;
@@ -286,11 +1574,37 @@ impl Question {
 
         Ok(buf)
     }
+
+    /// Serializes the question into the start of buf, returning the number of bytes written.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let mut w = Writer::new(buf);
+        match self.write(&mut w) {
+            Ok(()) => Ok(w.len()),
+            Err(e) if wire::is_full(&e) => anyhow::bail!("serializing question: buffer too small"),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        name::serialize_into(&self.name, None, w)?;
+        w.put_u16(self.r#type.serialize())?;
+        w.put_u16(self.class.serialize())?;
+        Ok(())
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl std::fmt::Display for Question {
+    /// The name, class and type, laid out like a record's so dig's columns line up.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\t\t{}\t{}", self.name, self.class, self.r#type)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum QuestionType {
     RrType(rr::Type),
+    /// An incremental zone transfer (RFC 1995).
+    Ixfr,
     Afxr,
     Mailb,
     Maila,
@@ -298,6 +1612,20 @@ pub enum QuestionType {
 }
 
 impl QuestionType {
+    /// Returns true if a resource record of type rr_type answers a question of this type.
+    pub fn matches(&self, rr_type: rr::Type) -> bool {
+        use QuestionType::*;
+
+        match self {
+            RrType(r#type) => *r#type == rr_type,
+            // * A zone transfer isn't answered by individual records.
+            Ixfr | Afxr => false,
+            Mailb => matches!(rr_type, rr::Type::MB | rr::Type::MG | rr::Type::MR),
+            Maila => matches!(rr_type, rr::Type::MD | rr::Type::MF),
+            All => true,
+        }
+    }
+
     fn parse(unparsed: &mut &[u8]) -> anyhow::Result<Self> {
         use crate::rr;
         use QuestionType::*;
@@ -317,6 +1645,7 @@ impl QuestionType {
             Err(_) => {
                 // Not a base resource record type. Check the remaining possibilities.
                 match unparsed.get_u16() {
+                    251 => Ixfr,
                     252 => Afxr,
                     253 => Mailb,
                     254 => Maila,
@@ -334,6 +1663,7 @@ impl QuestionType {
 
         match self {
             RrType(rr_type) => rr_type.serialize(),
+            Ixfr => 251,
             Afxr => 252,
             Mailb => 253,
             Maila => 254,
@@ -342,7 +1672,38 @@ impl QuestionType {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl std::fmt::Display for QuestionType {
+    /// The type's mnemonic, as written in zone files and by dig.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuestionType::RrType(rr_type) => write!(f, "{rr_type}"),
+            QuestionType::Ixfr => f.write_str("IXFR"),
+            QuestionType::Afxr => f.write_str("AXFR"),
+            QuestionType::Mailb => f.write_str("MAILB"),
+            QuestionType::Maila => f.write_str("MAILA"),
+            QuestionType::All => f.write_str("ANY"),
+        }
+    }
+}
+
+impl std::str::FromStr for QuestionType {
+    type Err = anyhow::Error;
+
+    /// Parses a type's mnemonic as dig takes it: an RR type, or one only asked about.
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let question_type = match text.to_ascii_uppercase().as_str() {
+            "IXFR" => QuestionType::Ixfr,
+            "AXFR" => QuestionType::Afxr,
+            "MAILB" => QuestionType::Mailb,
+            "MAILA" => QuestionType::Maila,
+            "ANY" | "*" => QuestionType::All,
+            _ => QuestionType::RrType(text.parse()?),
+        };
+        Ok(question_type)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum QuestionClass {
     RrClass(rr::Class),
     Any,
@@ -387,6 +1748,26 @@ impl QuestionClass {
     }
 }
 
+impl std::fmt::Display for QuestionClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuestionClass::RrClass(rr_class) => write!(f, "{rr_class}"),
+            QuestionClass::Any => f.write_str("ANY"),
+        }
+    }
+}
+
+impl std::str::FromStr for QuestionClass {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        match text.to_ascii_uppercase().as_str() {
+            "ANY" => Ok(QuestionClass::Any),
+            _ => Ok(QuestionClass::RrClass(text.parse()?)),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::net::Ipv4Addr;
@@ -394,6 +1775,67 @@ mod test {
     use super::*;
     use bytes::BufMut;
 
+    #[test]
+    fn matches_responses_to_queries() -> anyhow::Result<()> {
+        let query = address_query(&"www.Example.com.".parse()?);
+        let response = query.empty_response(ResponseCode::NoError);
+        assert!(response.is_response_to(&query));
+        assert!(!query.is_response_to(&query));
+
+        let mut other_id = response.clone();
+        other_id.set_id(query.id().wrapping_add(1));
+        assert!(!other_id.is_response_to(&query));
+
+        // * Servers needn't preserve the case of the name.
+        let mut lower = address_query(&"www.example.com.".parse()?);
+        lower.set_id(query.id());
+        let response = lower.empty_response(ResponseCode::NoError);
+        assert!(response.is_response_to(&query));
+
+        for other in [
+            address_query(&"mail.example.com.".parse()?),
+            Message::query(
+                &"www.example.com.".parse()?,
+                QuestionType::RrType(rr::Type::AAAA),
+            ),
+        ] {
+            let mut other = other.empty_response(ResponseCode::NoError);
+            other.set_id(query.id());
+            assert!(!other.is_response_to(&query));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn randomizes_case() -> anyhow::Result<()> {
+        let name = "abcdefghijklmnopqrstuvwxyz.example.".parse::<DomainName>()?;
+        let query = address_query(&name);
+        let sent = query.with_random_case();
+        assert_eq!(sent.id(), query.id());
+        assert_eq!(sent.questions()[0].name(), &name);
+        // * 2^-33 odds of every letter keeping its case.
+        assert!(!sent.questions()[0].name().eq_exact(&name));
+
+        let answer = rr::ResourceRecord::new(
+            sent.questions()[0].name().clone(),
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )?;
+        let mut response = sent.answer_response(vec![answer]);
+        assert!(response.is_response_to(&query));
+        assert!(response.echoes_case_of(&sent));
+        assert!(!response.echoes_case_of(&query));
+        let lower = query.empty_response(ResponseCode::NoError);
+        assert!(!lower.echoes_case_of(&sent));
+
+        response.restore_case(&query);
+        assert!(response.echoes_case_of(&query));
+        assert!(response.answers()[0].name().eq_exact(&name));
+        Ok(())
+    }
+
     #[test]
     fn parse_opcode() -> anyhow::Result<()> {
         assert_eq!(
@@ -408,6 +1850,10 @@ mod test {
             Opcode::parse(Opcode::ServerStatusRequest.serialize() << Opcode::BIT_POS)?,
             Opcode::ServerStatusRequest
         );
+        assert_eq!(
+            Opcode::parse(Opcode::Update.serialize() << Opcode::BIT_POS)?,
+            Opcode::Update
+        );
 
         let bitfields = 3 << Opcode::BIT_POS;
         assert!(Opcode::parse(bitfields).is_err());
@@ -442,7 +1888,12 @@ mod test {
             ResponseCode::Refused
         );
 
-        let bitfields = 6;
+        assert_eq!(
+            ResponseCode::parse(ResponseCode::NotZone.serialize())?,
+            ResponseCode::NotZone
+        );
+
+        let bitfields = 11;
         assert!(ResponseCode::parse(bitfields).is_err());
 
         Ok(())
@@ -533,13 +1984,17 @@ mod test {
             };
         }
 
+        test_qtype!(Ixfr);
         test_qtype!(Afxr);
         test_qtype!(Mailb);
         test_qtype!(Maila);
         test_qtype!(All);
 
+        // * A question can be about a type this resolver doesn't know (RFC 3597 section 3).
+        test_qtype!(RrType(rr::Type::Unknown(256)));
+
         let mut buf = Vec::new();
-        buf.put_u16(256);
+        buf.put_u16(128);
         let mut unparsed = &buf[..];
         assert!(QuestionType::parse(&mut unparsed).is_err());
 
@@ -587,10 +2042,32 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn question_from_str() -> anyhow::Result<()> {
+        assert_eq!(
+            "mx".parse::<QuestionType>()?,
+            QuestionType::RrType(rr::Type::MX)
+        );
+        assert_eq!(
+            "TYPE731".parse::<QuestionType>()?,
+            QuestionType::RrType(rr::Type::Unknown(731))
+        );
+        assert_eq!("axfr".parse::<QuestionType>()?, QuestionType::Afxr);
+        assert_eq!("ANY".parse::<QuestionType>()?, QuestionType::All);
+        assert!("TELEPORT".parse::<QuestionType>().is_err());
+        assert_eq!(
+            "ch".parse::<QuestionClass>()?,
+            QuestionClass::RrClass(rr::Class::CH)
+        );
+        assert_eq!("any".parse::<QuestionClass>()?, QuestionClass::Any);
+        assert!("XX".parse::<QuestionClass>().is_err());
+        Ok(())
+    }
+
     #[test]
     fn parse_question() -> anyhow::Result<()> {
         let question = Question {
-            name: "google.com.".to_string(),
+            name: "google.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::CNAME),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
@@ -628,12 +2105,12 @@ mod test {
         };
 
         let question1 = Question {
-            name: "google.com.".to_string(),
+            name: "google.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::A),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
         let question2 = Question {
-            name: "amazon.com.".to_string(),
+            name: "amazon.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::A),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
@@ -642,14 +2119,14 @@ mod test {
         // * Use uncompressed names since only implementing the resolver at this time.
         // * If at some point a name server is implemented, use compressed names.
         let answer1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::A,
             rr::Class::IN,
             100,
             rr::Data::A(Ipv4Addr::new(113, 234, 56, 89)),
         )?;
         let answer2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::A,
             rr::Class::IN,
             100,
@@ -658,34 +2135,34 @@ mod test {
         let answers = vec![answer1, answer2];
 
         let authority1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::NS,
             rr::Class::IN,
             250,
-            rr::Data::NS("ns.google.com.".to_string()),
+            rr::Data::NS("ns.google.com.".parse()?),
         )?;
         let authority2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::NS,
             rr::Class::IN,
             250,
-            rr::Data::NS("ns.amazon.com.".to_string()),
+            rr::Data::NS("ns.amazon.com.".parse()?),
         )?;
         let authorities = vec![authority1, authority2];
 
         let additional1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::CNAME,
             rr::Class::IN,
             150,
-            rr::Data::CNAME("www.google.com.".to_string()),
+            rr::Data::CNAME("www.google.com.".parse()?),
         )?;
         let additional2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::CNAME,
             rr::Class::IN,
             150,
-            rr::Data::CNAME("www.amazon.com.".to_string()),
+            rr::Data::CNAME("www.amazon.com.".parse()?),
         )?;
         let additionals = vec![additional1, additional2];
 
@@ -695,21 +2172,174 @@ mod test {
             answers: answers.clone(),
             authorities: authorities.clone(),
             additionals: additionals.clone(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            edns: None,
+            tsig: None,
         };
         let buf = message.serialize()?;
 
-        let mut unparsed = buf.as_slice();
-        let parsed_msg = Message::parse(&mut unparsed)?;
+        let mut parser = Parser::new(&buf);
+        let parsed_msg = parser.next_message()?;
 
         assert_eq!(parsed_msg.header, message.header);
         assert_eq!(parsed_msg.questions, message.questions);
         assert_eq!(parsed_msg.answers, message.answers);
         assert_eq!(parsed_msg.authorities, message.authorities);
         assert_eq!(parsed_msg.additionals, message.additionals);
+        assert_eq!(parser.offset(), buf.len());
+        assert!(parser.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_round_trip() -> anyhow::Result<()> {
+        let query = QueryBuilder::new()
+            .recursion_desired(true)
+            .question(
+                &"google.com.".parse()?,
+                QuestionType::RrType(rr::Type::MX),
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build();
+
+        let parsed_msg = Message::parse(&query.serialize()?)?;
+        assert_eq!(parsed_msg.header.id, query.id());
+        assert!(!parsed_msg.header.is_response);
+        assert_eq!(parsed_msg.header.opcode, Opcode::StandardQuery);
+        assert!(parsed_msg.header.is_recursion_desired);
         assert_eq!(
-            unsafe { unparsed.as_ptr().offset_from(buf.as_ptr()) as usize },
-            buf.len()
+            parsed_msg.questions,
+            vec![Question {
+                name: "google.com.".parse()?,
+                r#type: QuestionType::RrType(rr::Type::MX),
+                class: QuestionClass::RrClass(rr::Class::IN),
+            }]
+        );
+        assert!(parsed_msg.answers.is_empty());
+        assert!(parsed_msg.authorities.is_empty());
+        assert!(parsed_msg.additionals.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn query_root_and_tld() -> anyhow::Result<()> {
+        for name in [".", "com."] {
+            let query = Message::query(&name.parse()?, QuestionType::RrType(rr::Type::NS));
+            let parsed_msg = Message::parse(&query.serialize()?)?;
+            assert_eq!(parsed_msg.questions[0].name.to_string(), name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_concatenated() -> anyhow::Result<()> {
+        let first = address_query(&"google.com.".parse()?).serialize()?;
+        let second = address_query(&"amazon.com.".parse()?).serialize()?;
+        let mut buf = first.clone();
+        buf.extend_from_slice(&second);
+
+        let mut parser = Parser::new(&buf);
+        assert_eq!(
+            parser.next_message()?.questions()[0].name().to_string(),
+            "google.com."
+        );
+        assert_eq!(parser.offset(), first.len());
+        assert_eq!(
+            parser.next_message()?.questions()[0].name().to_string(),
+            "amazon.com."
+        );
+        assert!(parser.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_framed() -> anyhow::Result<()> {
+        let mut stream = Vec::new();
+        for name in ["google.com.", "amazon.com."] {
+            stream.extend_from_slice(&address_query(&name.parse()?).serialize_framed()?);
+        }
+
+        // * A partly read message is left for the next read.
+        let mut parser = Parser::new(&stream[..stream.len() - 1]);
+        assert_eq!(
+            parser.next_framed()?.questions()[0].name().to_string(),
+            "google.com."
+        );
+        let offset = parser.offset();
+        assert!(parser.next_framed().is_err());
+        assert_eq!(parser.offset(), offset);
+
+        let mut parser = Parser::new(&stream);
+        parser.next_framed()?;
+        assert_eq!(
+            parser.next_framed()?.questions()[0].name().to_string(),
+            "amazon.com."
         );
+        assert!(parser.is_empty());
+
+        // * The prefix has to match the message.
+        let mut stream = stream[..offset].to_vec();
+        stream[1] += 1;
+        stream.push(0);
+        assert!(Parser::new(&stream).next_framed().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn edns_round_trip() -> anyhow::Result<()> {
+        let mut query = address_query(&"google.com.".parse()?);
+        query.set_edns(Some(Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE)));
+        let buf = query.serialize()?;
+
+        let parsed = Message::parse(&buf)?;
+        assert_eq!(parsed.edns(), query.edns());
+        assert!(parsed.additionals().is_empty());
+        assert_eq!(parsed.header.additional_count, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_padded() -> anyhow::Result<()> {
+        for name in ["a.", "google.com.", "a-much-longer-name.example.com."] {
+            let query = address_query(&name.parse()?);
+            let buf = query.serialize_padded(Padding::queries())?;
+            assert_eq!(buf.len(), Padding::QUERY_BLOCK_SIZE);
+
+            let parsed = Message::parse(&buf)?;
+            assert_eq!(parsed.questions(), query.questions());
+            assert!(matches!(
+                parsed.edns().unwrap().options.as_slice(),
+                [edns::EdnsOption::Padding(_)]
+            ));
+
+            // * Padding an already padded message replaces the padding.
+            let buf = parsed.serialize_padded(Padding::queries())?;
+            assert_eq!(buf.len(), Padding::QUERY_BLOCK_SIZE);
+
+            let response = query.empty_response(ResponseCode::NoError);
+            let buf = response.serialize_padded(Padding::responses())?;
+            assert_eq!(buf.len(), Padding::RESPONSE_BLOCK_SIZE);
+        }
+
+        let query = address_query(&"google.com.".parse()?);
+        assert_eq!(query.serialize_padded(Padding::None)?, query.serialize()?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_unique_ids() -> anyhow::Result<()> {
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let id1 = Message::query(&"google.com.".parse()?, qtype).id();
+        let id2 = Message::query(&"google.com.".parse()?, qtype).id();
+        assert_ne!(id1, id2);
+
+        // * Only absolute names can be sent.
+        assert!(Message::query(&"google.com".parse()?, qtype)
+            .serialize()
+            .is_err());
 
         Ok(())
     }
@@ -766,9 +2396,408 @@ mod test {
         assert_eq!(cursor.get_u16(), header.additional_count as u16);
     }
 
+    const OPCODES: [Opcode; 3] = [
+        Opcode::StandardQuery,
+        Opcode::InverseQuery,
+        Opcode::ServerStatusRequest,
+    ];
+    const RESPONSE_CODES: [ResponseCode; 6] = [
+        ResponseCode::NoError,
+        ResponseCode::FormatError,
+        ResponseCode::ServerFailure,
+        ResponseCode::NameError,
+        ResponseCode::NotImplemented,
+        ResponseCode::Refused,
+    ];
+
+    #[test]
+    fn header_builder_flags_set_one_bit_each() {
+        let bitfields = |builder: fn(HeaderBuilder) -> HeaderBuilder| {
+            builder(HeaderBuilder::new(0)).build().bitfields()
+        };
+        assert_eq!(bitfields(|b| b), 0);
+        assert_eq!(bitfields(|b| b.response(true)), 1 << 15);
+        assert_eq!(bitfields(|b| b.authoritative_answer(true)), 1 << 10);
+        assert_eq!(bitfields(|b| b.truncated(true)), 1 << 9);
+        assert_eq!(bitfields(|b| b.recursion_desired(true)), 1 << 8);
+        assert_eq!(bitfields(|b| b.recursion_available(true)), 1 << 7);
+        assert_eq!(
+            bitfields(|b| b.opcode(Opcode::ServerStatusRequest)),
+            2 << 11
+        );
+        assert_eq!(bitfields(|b| b.response_code(ResponseCode::Refused)), 5);
+    }
+
+    #[test]
+    fn header_round_trips_every_flag_combination() -> anyhow::Result<()> {
+        for flags in 0..32_u8 {
+            for opcode in OPCODES {
+                for response_code in RESPONSE_CODES {
+                    let flag = |bit: u8| flags & (1 << bit) != 0;
+                    let header = HeaderBuilder::new(0xbeef)
+                        .response(flag(0))
+                        .authoritative_answer(flag(1))
+                        .truncated(flag(2))
+                        .recursion_desired(flag(3))
+                        .recursion_available(flag(4))
+                        .opcode(opcode)
+                        .response_code(response_code)
+                        .counts(1, 2, 3, 4)
+                        .build();
+                    let buf = header.serialize();
+                    let mut w_buf = [0_u8; 12];
+                    assert_eq!(header.serialize_into(&mut w_buf)?, 12);
+                    assert_eq!(w_buf.as_slice(), buf.as_slice());
+                    assert_eq!(Header::parse(&mut buf.as_slice())?, header);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn header_bitfields_parse_iff_valid_and_round_trip() {
+        for bitfields in 0..=u16::MAX {
+            let opcode = (bitfields >> 11) & 0xf;
+            let reserved = (bitfields >> 4) & 7;
+            let response_code = bitfields & 0xf;
+            // * QUERY, IQUERY, STATUS and UPDATE, and the codes up to NOTZONE (RFC 2136).
+            let valid = matches!(opcode, 0..=2 | 5) && reserved == 0 && response_code <= 10;
+
+            let mut buf = vec![0x12, 0x34];
+            buf.put_u16(bitfields);
+            buf.extend_from_slice(&[0; 8]);
+            match Header::parse(&mut buf.as_slice()) {
+                Ok(header) => {
+                    assert!(valid, "parsed invalid bitfields {bitfields:#06x}");
+                    assert_eq!(header.bitfields(), bitfields);
+                    assert_eq!(header.serialize(), buf);
+                }
+                Err(_) => assert!(!valid, "rejected valid bitfields {bitfields:#06x}"),
+            }
+        }
+    }
+
+    #[test]
+    fn header_ids_and_counts_round_trip() -> anyhow::Result<()> {
+        for _ in 0..1000 {
+            let header = HeaderBuilder::new(rand::random())
+                .response(rand::random())
+                .counts(
+                    rand::random(),
+                    rand::random(),
+                    rand::random(),
+                    rand::random(),
+                )
+                .build();
+            assert_eq!(Header::parse(&mut header.serialize().as_slice())?, header);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn query_header() -> anyhow::Result<()> {
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let qclass = QuestionClass::RrClass(rr::Class::IN);
+        let query = QueryBuilder::new()
+            .recursion_desired(true)
+            .question(&"example.com.".parse()?, qtype, qclass)
+            .build();
+        let expected = HeaderBuilder::new(query.id())
+            .recursion_desired(true)
+            .counts(1, 0, 0, 0)
+            .build();
+        assert_eq!(query.header, expected);
+        let parsed = Message::parse(&query.serialize()?)?;
+        assert_eq!(parsed.header, expected);
+
+        let query = Message::query(&"example.com.".parse()?, qtype);
+        assert!(!query.is_recursion_desired());
+        assert_eq!(query.questions()[0].class(), qclass);
+        Ok(())
+    }
+
+    #[test]
+    fn build_query() -> anyhow::Result<()> {
+        let a = QuestionType::RrType(rr::Type::A);
+        let aaaa = QuestionType::RrType(rr::Type::AAAA);
+        let qclass = QuestionClass::RrClass(rr::Class::IN);
+        let name = "example.com.".parse()?;
+        let option = EdnsOption::Unknown {
+            code: 65001,
+            data: vec![1, 2],
+        };
+        let query = QueryBuilder::new()
+            .id(0x1234)
+            .question(&name, a, qclass)
+            .question(&name, aaaa, qclass)
+            .edns_option(option.clone())
+            .build();
+
+        let parsed = Message::parse(&query.serialize()?)?;
+        assert_eq!(parsed.id(), 0x1234);
+        assert!(!parsed.is_response());
+        assert_eq!(
+            parsed
+                .questions()
+                .iter()
+                .map(Question::r#type)
+                .collect::<Vec<_>>(),
+            [a, aaaa]
+        );
+        let edns = parsed.edns().unwrap();
+        assert_eq!(edns.udp_payload_size, edns::DEFAULT_UDP_PAYLOAD_SIZE);
+        assert_eq!(edns.options, [option]);
+
+        let query = QueryBuilder::new()
+            .question(&name, a, qclass)
+            .edns(Edns::new(4096))
+            .build();
+        assert_eq!(query.edns().unwrap().udp_payload_size, 4096);
+        Ok(())
+    }
+
+    #[test]
+    fn build_responses() -> anyhow::Result<()> {
+        let query = address_query(&"example.com.".parse()?);
+        let refused = query.empty_response(ResponseCode::Refused);
+        assert!(refused.is_response_to(&query));
+        assert_eq!(refused.response_code(), ResponseCode::Refused);
+        assert!(refused.answers().is_empty());
+
+        let answer = rr::ResourceRecord::new(
+            "example.com.".parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )?;
+        let answered = query.answer_response(vec![answer.clone()]);
+        assert!(answered.is_response_to(&query));
+        assert_eq!(answered.response_code(), ResponseCode::NoError);
+        assert_eq!(answered.answers(), [answer]);
+        assert!(answered.authorities().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn display_like_dig() -> anyhow::Result<()> {
+        let query = QueryBuilder::new()
+            .id(4660)
+            .recursion_desired(true)
+            .question(
+                &"example.com.".parse()?,
+                QuestionType::RrType(rr::Type::A),
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build();
+        let answer = rr::ResourceRecord::new(
+            "example.com.".parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )?;
+        let mut response = query.answer_response(vec![answer]);
+        response.set_recursion_available(true);
+        let mut edns = Edns::new(1232);
+        edns.dnssec_ok = true;
+        response.set_edns(Some(edns));
+        assert_eq!(
+            response.to_string(),
+            ";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660\n\
+             ;; flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1\n\
+             \n\
+             ;; OPT PSEUDOSECTION:\n\
+             ; EDNS: version: 0, flags: do; udp: 1232\n\
+             \n\
+             ;; QUESTION SECTION:\n\
+             ;example.com.\t\tIN\tA\n\
+             \n\
+             ;; ANSWER SECTION:\n\
+             example.com.\t300\tIN\tA\t192.0.2.1\n"
+        );
+
+        let refused = query.empty_response(ResponseCode::Refused).to_string();
+        assert!(refused.starts_with(";; ->>HEADER<<- opcode: QUERY, status: REFUSED, id: 4660\n"));
+        assert!(!refused.contains("ANSWER SECTION"));
+        Ok(())
+    }
+
+    #[test]
+    fn update_round_trip() -> anyhow::Result<()> {
+        let name: DomainName = "www.example.com.".parse()?;
+        let record = |last: u8, ttl: i32| {
+            rr::ResourceRecord::new(
+                name.clone(),
+                rr::Type::A,
+                rr::Class::IN,
+                ttl,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, last)),
+            )
+        };
+        let prerequisites = vec![
+            Prerequisite::RrsetExists(name.clone(), rr::Type::A),
+            Prerequisite::Record(record(1, 0)?),
+            Prerequisite::RrsetAbsent(name.clone(), rr::Type::AAAA),
+            Prerequisite::NameInUse(name.clone()),
+            Prerequisite::NameNotInUse("new.example.com.".parse()?),
+        ];
+        let updates = vec![
+            Update::Add(record(2, 300)?),
+            Update::DeleteRrset(name.clone(), rr::Type::TXT),
+            Update::DeleteName("old.example.com.".parse()?),
+            Update::Delete(record(1, 0)?),
+        ];
+        let update = prerequisites.iter().fold(
+            UpdateBuilder::new(&"example.com.".parse()?),
+            |builder, p| builder.prerequisite(p.clone()),
+        );
+        let update = updates
+            .iter()
+            .fold(update, |builder, u| builder.update(u.clone()))
+            .build();
+
+        let serialized = update.serialize()?;
+        let parsed = Message::parse(&serialized)?;
+        assert_eq!(parsed.opcode(), Opcode::Update);
+        assert_eq!(parsed.zone(), Some(&"example.com.".parse()?));
+        assert_eq!(parsed.prerequisites(), prerequisites);
+        assert_eq!(parsed.updates(), updates);
+        assert!(parsed.answers().is_empty() && parsed.authorities().is_empty());
+
+        let mut buf = [0_u8; 512];
+        let len = update.serialize_into(&mut buf)?;
+        assert_eq!(&buf[..len], serialized);
+
+        // * Deleting an RRset is written with class ANY, TTL 0 and no data.
+        let delete_rrset = Update::DeleteRrset(name, rr::Type::TXT).to_record();
+        let written = delete_rrset.serialize()?;
+        let mut unparsed = &written[..];
+        let parsed = UpdateRecord::parse(&written, &mut unparsed)?;
+        assert_eq!(
+            (parsed.class, parsed.type_code, parsed.ttl),
+            (CLASS_ANY, 16, 0)
+        );
+        assert!(parsed.data.is_none() && unparsed.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_update() -> anyhow::Result<()> {
+        // * An UPDATE has exactly one zone.
+        let mut update = UpdateBuilder::new(&"example.com.".parse()?).build();
+        update.questions.clear();
+        update.header.question_count = 0;
+        assert!(Message::parse(&update.serialize()?).is_err());
+
+        // * A record of class NONE can't be added.
+        let record = UpdateRecord::empty(&"www.example.com.".parse()?, 1, CLASS_NONE);
+        assert!(record.into_update(rr::Class::IN.serialize()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn display_update_like_dig() -> anyhow::Result<()> {
+        let name: DomainName = "www.example.com.".parse()?;
+        let update = UpdateBuilder::new(&"example.com.".parse()?)
+            .id(4660)
+            .prerequisite(Prerequisite::NameNotInUse(name.clone()))
+            .update(Update::Add(rr::ResourceRecord::new(
+                name.clone(),
+                rr::Type::A,
+                rr::Class::IN,
+                300,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )?))
+            .update(Update::DeleteRrset(name, rr::Type::AAAA))
+            .build();
+        assert_eq!(
+            update.to_string(),
+            ";; ->>HEADER<<- opcode: UPDATE, status: NOERROR, id: 4660\n\
+             ;; flags: ; ZONE: 1, PREREQ: 1, UPDATE: 2, ADDITIONAL: 0\n\
+             \n\
+             ;; ZONE SECTION:\n\
+             ;example.com.\t\tIN\tSOA\n\
+             \n\
+             ;; PREREQUISITE SECTION:\n\
+             www.example.com.\t0\tNONE\tANY\n\
+             \n\
+             ;; UPDATE SECTION:\n\
+             www.example.com.\t300\tIN\tA\t192.0.2.1\n\
+             www.example.com.\t0\tANY\tAAAA\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_parsed_response() -> anyhow::Result<()> {
+        let query = QueryBuilder::new()
+            .recursion_desired(true)
+            .question(
+                &"www.example.com.".parse()?,
+                QuestionType::RrType(rr::Type::A),
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build();
+        let record = |name: &str, data: rr::Data| {
+            let r#type = match data {
+                rr::Data::CNAME(_) => rr::Type::CNAME,
+                _ => rr::Type::A,
+            };
+            rr::ResourceRecord::new(name.parse()?, r#type, rr::Class::IN, 300, data)
+        };
+        let answers = vec![
+            record(
+                "www.example.com.",
+                rr::Data::CNAME("web.example.com.".parse()?),
+            )?,
+            record("web.example.com.", rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)))?,
+            record("web.example.com.", rr::Data::A(Ipv4Addr::new(192, 0, 2, 2)))?,
+        ];
+        let mut response = query.answer_response(answers.clone());
+        response.set_recursion_available(true);
+
+        let parsed = Message::parse(&response.serialize()?)?;
+        let header = parsed.header();
+        assert_eq!(header.id(), query.id());
+        assert!(header.is_response());
+        assert_eq!(header.opcode(), Opcode::StandardQuery);
+        assert!(header.is_recursion_desired());
+        assert!(header.is_recursion_available());
+        assert!(!header.is_authoritative_answer());
+        assert!(!parsed.is_truncated());
+        assert_eq!(header.response_code(), ResponseCode::NoError);
+        assert_eq!(header.question_count(), 1);
+        assert_eq!(header.answer_count(), 3);
+        assert_eq!(header.additional_count(), 0);
+
+        assert_eq!(parsed.questions()[0].name(), query.questions()[0].name());
+        let addresses = parsed
+            .answers_of_type(rr::Type::A)
+            .map(rr::ResourceRecord::data)
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, [answers[1].data(), answers[2].data()]);
+        assert_eq!(parsed.answers_of_type(rr::Type::MX).count(), 0);
+        assert_eq!(parsed.records().count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn question_type_matches() {
+        assert!(QuestionType::RrType(rr::Type::A).matches(rr::Type::A));
+        assert!(!QuestionType::RrType(rr::Type::A).matches(rr::Type::CNAME));
+        assert!(!QuestionType::Afxr.matches(rr::Type::SOA));
+        assert!(QuestionType::Mailb.matches(rr::Type::MG));
+        assert!(!QuestionType::Mailb.matches(rr::Type::MX));
+        assert!(QuestionType::Maila.matches(rr::Type::MF));
+        assert!(QuestionType::All.matches(rr::Type::TXT));
+    }
+
     #[test]
     fn serialize_question_type() {
         assert_eq!(QuestionType::RrType(rr::Type::CNAME).serialize(), 5);
+        assert_eq!(QuestionType::Ixfr.serialize(), 251);
         assert_eq!(QuestionType::Afxr.serialize(), 252);
         assert_eq!(QuestionType::Mailb.serialize(), 253);
         assert_eq!(QuestionType::Maila.serialize(), 254);
@@ -784,7 +2813,7 @@ mod test {
     #[test]
     fn serialize_question() -> anyhow::Result<()> {
         let question = Question {
-            name: "google.com.".to_string(),
+            name: "google.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::CNAME),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
@@ -817,12 +2846,12 @@ mod test {
         };
 
         let question1 = Question {
-            name: "google.com.".to_string(),
+            name: "google.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::A),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
         let question2 = Question {
-            name: "amazon.com.".to_string(),
+            name: "amazon.com.".parse()?,
             r#type: QuestionType::RrType(rr::Type::A),
             class: QuestionClass::RrClass(rr::Class::IN),
         };
@@ -831,14 +2860,14 @@ mod test {
         // * Use uncompressed names since only implementing the resolver at this time.
         // * If at some point a name server is implemented, use compressed names.
         let answer1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::A,
             rr::Class::IN,
             100,
             rr::Data::A(Ipv4Addr::new(113, 234, 56, 89)),
         )?;
         let answer2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::A,
             rr::Class::IN,
             100,
@@ -847,34 +2876,34 @@ mod test {
         let answers = vec![answer1, answer2];
 
         let authority1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::NS,
             rr::Class::IN,
             250,
-            rr::Data::NS("ns.google.com.".to_string()),
+            rr::Data::NS("ns.google.com.".parse()?),
         )?;
         let authority2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::NS,
             rr::Class::IN,
             250,
-            rr::Data::NS("ns.amazon.com.".to_string()),
+            rr::Data::NS("ns.amazon.com.".parse()?),
         )?;
         let authorities = vec![authority1, authority2];
 
         let additional1 = rr::ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             rr::Type::CNAME,
             rr::Class::IN,
             150,
-            rr::Data::CNAME("www.google.com.".to_string()),
+            rr::Data::CNAME("www.google.com.".parse()?),
         )?;
         let additional2 = rr::ResourceRecord::new(
-            "amazon.com.".to_string(),
+            "amazon.com.".parse()?,
             rr::Type::CNAME,
             rr::Class::IN,
             150,
-            rr::Data::CNAME("www.amazon.com.".to_string()),
+            rr::Data::CNAME("www.amazon.com.".parse()?),
         )?;
         let additionals = vec![additional1, additional2];
 
@@ -884,22 +2913,205 @@ mod test {
             answers: answers.clone(),
             authorities: authorities.clone(),
             additionals: additionals.clone(),
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+            edns: None,
+            tsig: None,
         };
         let buf = message.serialize()?;
 
-        let mut unparsed = buf.as_slice();
-        let parsed_msg = Message::parse(&mut unparsed)?;
+        let mut parser = Parser::new(&buf);
+        let parsed_msg = parser.next_message()?;
 
         assert_eq!(parsed_msg.header, header);
         assert_eq!(parsed_msg.questions, questions);
         assert_eq!(parsed_msg.answers, answers);
         assert_eq!(parsed_msg.authorities, authorities);
         assert_eq!(parsed_msg.additionals, additionals);
+        assert_eq!(parser.offset(), buf.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_into_matches_serialize() -> anyhow::Result<()> {
+        let record = |name: &str, r#type, data| {
+            rr::ResourceRecord::new(name.parse()?, r#type, rr::Class::IN, 300, data)
+        };
+        let answers = vec![
+            record(
+                "www.example.com.",
+                rr::Type::CNAME,
+                rr::Data::CNAME("a\\.b.example.net.".parse()?),
+            )?,
+            record(
+                "a\\.b.example.net.",
+                rr::Type::A,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )?,
+            record(
+                "example.com.",
+                rr::Type::MX,
+                rr::Data::MX {
+                    preference: 10,
+                    exchange: "mail.example.com.".parse()?,
+                },
+            )?,
+            record(
+                "example.com.",
+                rr::Type::TXT,
+                rr::Data::TXT(vec!["v=spf1 -all".to_string(), String::new()]),
+            )?,
+        ];
+        let authorities = vec![record(
+            "example.com.",
+            rr::Type::SOA,
+            rr::Data::SOA {
+                mname: "ns1.example.com.".parse()?,
+                rname: "hostmaster.example.com.".parse()?,
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )?];
+        let mut message = address_query(&"www.example.com.".parse()?).response(
+            ResponseCode::NoError,
+            answers,
+            authorities,
+            vec![],
+        );
+        let mut buf = [0_u8; 512];
+        let len = message.serialize_into(&mut buf)?;
+        assert_eq!(&buf[..len], message.serialize()?);
+
+        let mut edns = Edns::new(1232);
+        edns.options.push(edns::EdnsOption::Padding(3));
+        message.set_edns(Some(edns));
+        let mut bytes = BytesMut::from(&b"prefix"[..]);
+        let len = message.serialize_into_bytes(&mut bytes)?;
+        assert_eq!(bytes.len(), 6 + len);
+        assert_eq!(&bytes[..6], b"prefix");
+        assert_eq!(&bytes[6..], message.serialize()?);
+
+        let header = message.header.clone();
+        let len = header.serialize_into(&mut buf)?;
+        assert_eq!(&buf[..len], header.serialize());
+        let question = &message.questions[0];
+        let len = question.serialize_into(&mut buf)?;
+        assert_eq!(&buf[..len], question.serialize()?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_into_too_small() -> anyhow::Result<()> {
+        let message = address_query(&"google.com.".parse()?);
+        let mut buf = [0_u8; 20];
+        let e = message.serialize_into(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), "serializing message: buffer too small");
+        let e = message.header.serialize_into(&mut buf[..11]).unwrap_err();
+        assert_eq!(e.to_string(), "serializing header: buffer too small");
+
+        // * A buffer bigger than the datagram limit doesn't raise it.
+        let label = "a".repeat(63);
+        let long_name: DomainName = format!("{label}.{label}.{label}.").parse()?;
+        let records = (0..3)
+            .map(|_| {
+                rr::ResourceRecord::new(
+                    long_name.clone(),
+                    rr::Type::NS,
+                    rr::Class::IN,
+                    300,
+                    rr::Data::NS(long_name.clone()),
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let message = address_query(&"google.com.".parse()?).response(
+            ResponseCode::NoError,
+            records,
+            vec![],
+            vec![],
+        );
+        let mut buf = [0_u8; 4096];
+        let e = message.serialize_into(&mut buf).unwrap_err();
         assert_eq!(
-            unsafe { unparsed.as_ptr().offset_from(buf.as_ptr()) as usize },
-            buf.len()
+            e.to_string(),
+            "serializing message: message requires truncation"
         );
 
+        // * Errors in the records themselves are still reported as such.
+        let bad = rr::ResourceRecord::new(
+            "google.com.".parse()?,
+            rr::Type::NS,
+            rr::Class::IN,
+            300,
+            rr::Data::NS("relative".parse()?),
+        )?;
+        let message = address_query(&"google.com.".parse()?).response(
+            ResponseCode::NoError,
+            vec![bad],
+            vec![],
+            vec![],
+        );
+        let e = message.serialize_into(&mut buf).unwrap_err();
+        assert_eq!(e.to_string(), message.serialize().unwrap_err().to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_truncating() -> anyhow::Result<()> {
+        let label = "a".repeat(63);
+        let long_name: DomainName = format!("{label}.{label}.{label}.").parse()?;
+        let records = (0..3)
+            .map(|_| {
+                rr::ResourceRecord::new(
+                    long_name.clone(),
+                    rr::Type::NS,
+                    rr::Class::IN,
+                    300,
+                    rr::Data::NS(long_name.clone()),
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let query = address_query(&"google.com.".parse()?);
+        let message = query.answer_response(records);
+
+        let whole = message.serialize_truncating(4096)?;
+        assert_eq!(Message::parse(&whole)?.answers().len(), 3);
+
+        let truncated = message.serialize_truncating(512)?;
+        assert!(truncated.len() <= 512);
+        let header = Header::peek(&truncated)?;
+        assert!(header.is_truncated() && header.is_response());
+        assert_eq!(header.id(), query.id());
+        assert_eq!(header.answer_count, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_test_vectors() -> anyhow::Result<()> {
+        use rg_resolver_common::vectors::{self, Expected};
+
+        for vector in vectors::all() {
+            let parsed = Message::parse(&vector.message);
+            let answers = match vector.expected {
+                Expected::Malformed => {
+                    assert!(parsed.is_err(), "{} should be rejected", vector.name);
+                    continue;
+                }
+                Expected::Answers(answers) => answers,
+            };
+            let message = parsed.map_err(|e| anyhow::anyhow!("{}: {e}", vector.name))?;
+            assert_eq!(message.id(), vectors::ID, "{}", vector.name);
+            assert_eq!(message.answers().len(), answers.len(), "{}", vector.name);
+            for (rr, expected) in message.answers().iter().zip(answers) {
+                assert_eq!(rr.name().to_string(), expected.owner, "{}", vector.name);
+                assert_eq!(rr.r#type().serialize(), expected.rtype, "{}", vector.name);
+                assert_eq!(rr.ttl() as u32, expected.ttl, "{}", vector.name);
+                assert_eq!(rr.data().serialize()?, expected.rdata, "{}", vector.name);
+            }
+        }
         Ok(())
     }
 }
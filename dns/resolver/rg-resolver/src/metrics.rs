@@ -0,0 +1,575 @@
+//! Counters of what the resolver is doing, served over HTTP in the Prometheus text format so
+//! the daemon can be monitored.
+
+use crate::message::{QuestionType, ResponseCode};
+use crate::monitor::Monitor;
+use crate::provenance::AnswerSource;
+use crate::queue::QueueStats;
+use crate::shutdown::ShutdownSignal;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::debug;
+
+/// The port metrics are served on unless told otherwise, the one CoreDNS uses.
+pub const DEFAULT_PORT: u16 = 9153;
+
+/// The process-wide metrics, so every part of the resolver counts into the same ones.
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// The upper bounds of the upstream RTT histogram's buckets, in seconds.
+const RTT_BUCKETS: [f64; 11] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// The longest request head a scraper may send.
+const MAX_REQUEST_LEN: usize = 8192;
+
+/// How long a scraper has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn global() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// DNS queries answered, by type and response code.
+    queries: Mutex<BTreeMap<(String, String), u64>>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    upstreams: Mutex<BTreeMap<SocketAddr, UpstreamMetrics>>,
+    active_connections: AtomicU64,
+    /// Cached records whose TTL an override changed, by the override's suffix.
+    ttl_overrides: Mutex<BTreeMap<String, u64>>,
+    /// The work queues between listeners and processing, by name.
+    queues: Mutex<BTreeMap<String, Arc<QueueStats>>>,
+    /// The upstream monitor, whose probes are reported, if it's running.
+    monitor: Mutex<Option<Monitor>>,
+}
+
+#[derive(Debug, Default)]
+struct UpstreamMetrics {
+    rtt: Histogram,
+    timeouts: u64,
+    duplicates: u64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The observations in each bucket alone; they're summed when rendered.
+    buckets: [u64; RTT_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = RTT_BUCKETS.iter().position(|&bound| value <= bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    pub fn count_query(&self, qtype: QuestionType, rcode: ResponseCode) {
+        *self
+            .queries
+            .lock()
+            .unwrap()
+            .entry((qtype.to_string(), rcode.to_string()))
+            .or_default() += 1;
+    }
+
+    /// Counts a query answered from source as a cache hit or miss. Authoritative answers are
+    /// neither, since they never go through the cache.
+    pub fn count_answer_source(&self, source: Option<AnswerSource>) {
+        match source {
+            Some(AnswerSource::Cache) => self.cache_hits.fetch_add(1, Ordering::Relaxed),
+            Some(AnswerSource::Authoritative) => return,
+            Some(AnswerSource::Upstream | AnswerSource::SystemFallback) | None => {
+                self.cache_misses.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+    }
+
+    pub fn observe_rtt(&self, upstream: SocketAddr, rtt: Duration) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        upstreams
+            .entry(upstream)
+            .or_default()
+            .rtt
+            .observe(rtt.as_secs_f64());
+    }
+
+    pub fn count_timeout(&self, upstream: SocketAddr) {
+        self.upstreams
+            .lock()
+            .unwrap()
+            .entry(upstream)
+            .or_default()
+            .timeouts += 1;
+    }
+
+    pub fn count_ttl_override(&self, suffix: &str) {
+        *self
+            .ttl_overrides
+            .lock()
+            .unwrap()
+            .entry(suffix.to_string())
+            .or_default() += 1;
+    }
+
+    /// Exports the stats of a work queue as name. A queue registered under a name already
+    /// taken replaces the earlier one.
+    pub fn register_queue(&self, name: String, stats: Arc<QueueStats>) {
+        self.queues.lock().unwrap().insert(name, stats);
+    }
+
+    /// Reports what monitor's probes find.
+    pub fn set_monitor(&self, monitor: Monitor) {
+        *self.monitor.lock().unwrap() = Some(monitor);
+    }
+
+    /// Counts a response upstream sent again after its query was answered.
+    pub fn count_duplicate(&self, upstream: SocketAddr) {
+        self.upstreams
+            .lock()
+            .unwrap()
+            .entry(upstream)
+            .or_default()
+            .duplicates += 1;
+    }
+
+    /// Counts a client connection as active until the returned guard is dropped.
+    pub fn connection(&self) -> ActiveConnection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection { metrics: self }
+    }
+
+    /// The metrics in the Prometheus text exposition format, version 0.0.4.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out)
+            .expect("writing to a String can't fail");
+        out
+    }
+
+    fn write(&self, out: &mut String) -> fmt::Result {
+        header(
+            out,
+            "rg_resolver_queries_total",
+            "counter",
+            "DNS queries answered, by type and response code.",
+        )?;
+        for ((qtype, rcode), count) in self.queries.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "rg_resolver_queries_total{{qtype=\"{qtype}\",rcode=\"{rcode}\"}} {count}"
+            )?;
+        }
+
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        header(
+            out,
+            "rg_resolver_cache_lookups_total",
+            "counter",
+            "Queries answered from the cache (hit) or elsewhere (miss).",
+        )?;
+        writeln!(
+            out,
+            "rg_resolver_cache_lookups_total{{result=\"hit\"}} {hits}"
+        )?;
+        writeln!(
+            out,
+            "rg_resolver_cache_lookups_total{{result=\"miss\"}} {misses}"
+        )?;
+        header(
+            out,
+            "rg_resolver_cache_hit_ratio",
+            "gauge",
+            "The share of queries answered from the cache.",
+        )?;
+        let ratio = match hits + misses {
+            0 => 0.0,
+            lookups => hits as f64 / lookups as f64,
+        };
+        writeln!(out, "rg_resolver_cache_hit_ratio {ratio}")?;
+
+        let upstreams = self.upstreams.lock().unwrap();
+        header(
+            out,
+            "rg_resolver_upstream_rtt_seconds",
+            "histogram",
+            "How long upstreams took to answer.",
+        )?;
+        for (upstream, metrics) in upstreams.iter() {
+            let name = "rg_resolver_upstream_rtt_seconds";
+            let rtt = &metrics.rtt;
+            let mut cumulative = 0;
+            for (bound, count) in RTT_BUCKETS.iter().zip(rtt.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "{name}_bucket{{upstream=\"{upstream}\",le=\"{bound}\"}} {cumulative}"
+                )?;
+            }
+            writeln!(
+                out,
+                "{name}_bucket{{upstream=\"{upstream}\",le=\"+Inf\"}} {}",
+                rtt.count
+            )?;
+            writeln!(out, "{name}_sum{{upstream=\"{upstream}\"}} {}", rtt.sum)?;
+            writeln!(out, "{name}_count{{upstream=\"{upstream}\"}} {}", rtt.count)?;
+        }
+        header(
+            out,
+            "rg_resolver_upstream_timeouts_total",
+            "counter",
+            "Queries to upstreams that timed out.",
+        )?;
+        for (upstream, metrics) in upstreams.iter() {
+            writeln!(
+                out,
+                "rg_resolver_upstream_timeouts_total{{upstream=\"{upstream}\"}} {}",
+                metrics.timeouts
+            )?;
+        }
+        header(
+            out,
+            "rg_resolver_upstream_duplicate_responses_total",
+            "counter",
+            "Responses upstreams sent again after their query was answered, which were dropped.",
+        )?;
+        for (upstream, metrics) in upstreams.iter() {
+            writeln!(
+                out,
+                "rg_resolver_upstream_duplicate_responses_total{{upstream=\"{upstream}\"}} {}",
+                metrics.duplicates
+            )?;
+        }
+
+        header(
+            out,
+            "rg_resolver_ttl_overrides_applied_total",
+            "counter",
+            "Cached records whose TTL an override changed, by the override's suffix.",
+        )?;
+        for (suffix, count) in self.ttl_overrides.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "rg_resolver_ttl_overrides_applied_total{{suffix=\"{suffix}\"}} {count}"
+            )?;
+        }
+
+        let reports = self
+            .monitor
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(Monitor::report)
+            .unwrap_or_default();
+        header(
+            out,
+            "rg_resolver_upstream_availability_percent",
+            "gauge",
+            "The percentage of the monitor's recent probes each upstream answered.",
+        )?;
+        for report in &reports {
+            writeln!(
+                out,
+                "rg_resolver_upstream_availability_percent{{upstream=\"{}\"}} {}",
+                report.upstream, report.availability
+            )?;
+        }
+        header(
+            out,
+            "rg_resolver_upstream_probe_latency_seconds",
+            "gauge",
+            "Percentiles of how long each upstream took to answer the monitor's recent probes.",
+        )?;
+        for report in &reports {
+            let percentiles = [("0.5", report.p50), ("0.99", report.p99)];
+            for (quantile, latency) in percentiles {
+                let Some(latency) = latency else {
+                    continue;
+                };
+                writeln!(
+                    out,
+                    "rg_resolver_upstream_probe_latency_seconds{{upstream=\"{}\",quantile=\"{quantile}\"}} {}",
+                    report.upstream,
+                    latency.as_secs_f64()
+                )?;
+            }
+        }
+
+        let queues = self.queues.lock().unwrap();
+        let series = |out: &mut String, name, r#type, help, value: fn(&QueueStats) -> u64| {
+            header(out, name, r#type, help)?;
+            for (queue, stats) in queues.iter() {
+                writeln!(out, "{name}{{queue=\"{queue}\"}} {}", value(stats))?;
+            }
+            fmt::Result::Ok(())
+        };
+        series(
+            out,
+            "rg_resolver_queue_depth",
+            "gauge",
+            "Requests waiting to be processed.",
+            |stats| stats.depth() as u64,
+        )?;
+        series(
+            out,
+            "rg_resolver_queue_max_depth",
+            "gauge",
+            "The most requests that have waited to be processed at once.",
+            |stats| stats.max_depth() as u64,
+        )?;
+        series(
+            out,
+            "rg_resolver_queue_enqueued_total",
+            "counter",
+            "Requests queued for processing.",
+            QueueStats::enqueued,
+        )?;
+        series(
+            out,
+            "rg_resolver_queue_shed_total",
+            "counter",
+            "Requests dropped because the queue was full.",
+            QueueStats::shed,
+        )?;
+        series(
+            out,
+            "rg_resolver_queue_busy_total",
+            "counter",
+            "Requests refused because their client had too many in flight.",
+            QueueStats::busy,
+        )?;
+
+        header(
+            out,
+            "rg_resolver_active_connections",
+            "gauge",
+            "Client TCP connections open.",
+        )?;
+        writeln!(
+            out,
+            "rg_resolver_active_connections {}",
+            self.active_connections.load(Ordering::Relaxed)
+        )
+    }
+}
+
+fn header(out: &mut String, name: &str, r#type: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {type}")
+}
+
+/// A client connection counted as active, until dropped.
+pub struct ActiveConnection<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Serves the global metrics at /metrics on every listener until accepting on any of them
+/// fails or shutdown is requested.
+pub async fn serve_all(listeners: Vec<TcpListener>, signal: ShutdownSignal) -> io::Result<()> {
+    let serving = listeners
+        .into_iter()
+        .map(|listener| serve(listener, signal.clone()));
+    futures::future::try_join_all(serving).await?;
+    Ok(())
+}
+
+/// Answers each scrape on listener with the global metrics, over HTTP/1.1.
+pub async fn serve(listener: TcpListener, mut signal: ShutdownSignal) -> io::Result<()> {
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = signal.requested() => return Ok(()),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = scrape(stream).await {
+                debug!("Metrics client {peer}: {e}");
+            }
+        });
+    }
+}
+
+/// Reads one request from stream and answers it, then closes the connection.
+async fn scrape(mut stream: TcpStream) -> anyhow::Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out reading request"))??;
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut fields = request_line.split_whitespace();
+    let response = match (fields.next(), fields.next()) {
+        (Some("GET"), Some("/metrics")) => response(
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            &global().render(),
+        ),
+        (Some("GET"), _) => response("404 Not Found", "text/plain", "Not found\n"),
+        _ => response(
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n",
+        ),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads the request line and headers, up to the blank line that ends them.
+async fn read_head(stream: &mut TcpStream) -> anyhow::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0_u8; 1024];
+    while !head.ends_with(b"\r\n\r\n") {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            anyhow::bail!("connection closed mid-request");
+        }
+        head.extend_from_slice(&buf[..len]);
+        if head.len() > MAX_REQUEST_LEN {
+            anyhow::bail!("request longer than {MAX_REQUEST_LEN} bytes");
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shutdown::Shutdown;
+    use crate::{queue, rr};
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn render() {
+        let metrics = Metrics::default();
+        let a = QuestionType::RrType(rr::Type::A);
+        metrics.count_query(a, ResponseCode::NoError);
+        metrics.count_query(a, ResponseCode::NoError);
+        metrics.count_query(a, ResponseCode::NameError);
+        metrics.count_answer_source(Some(AnswerSource::Cache));
+        metrics.count_answer_source(Some(AnswerSource::Upstream));
+        metrics.count_answer_source(Some(AnswerSource::Authoritative));
+        let upstream = SocketAddr::from(([192, 0, 2, 53], 53));
+        metrics.observe_rtt(upstream, Duration::from_millis(20));
+        metrics.observe_rtt(upstream, Duration::from_secs(5));
+        metrics.count_timeout(upstream);
+        metrics.count_duplicate(upstream);
+        metrics.count_ttl_override("internal.example.");
+        let (tx, _rx) = queue::work_queue(4, 1, queue::ShedPolicy::Reject);
+        metrics.register_queue(String::from("dns/udp 127.0.0.1:53"), tx.stats().clone());
+        let client = IpAddr::from([192, 0, 2, 1]);
+        tx.submit(client, 1).await.unwrap();
+        assert!(tx.submit(client, 2).await.is_err());
+        let connection = metrics.connection();
+
+        let rendered = metrics.render();
+        let has = |line: &str| rendered.lines().any(|l| l == line);
+        assert!(has(
+            "rg_resolver_queries_total{qtype=\"A\",rcode=\"NOERROR\"} 2"
+        ));
+        assert!(has(
+            "rg_resolver_queries_total{qtype=\"A\",rcode=\"NXDOMAIN\"} 1"
+        ));
+        assert!(has("rg_resolver_cache_lookups_total{result=\"hit\"} 1"));
+        assert!(has("rg_resolver_cache_lookups_total{result=\"miss\"} 1"));
+        assert!(has("rg_resolver_cache_hit_ratio 0.5"));
+        assert!(has(
+            "rg_resolver_upstream_rtt_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"0.01\"} 0"
+        ));
+        assert!(has(
+            "rg_resolver_upstream_rtt_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"0.025\"} 1"
+        ));
+        // * An RTT past the last bound only counts towards +Inf.
+        assert!(has(
+            "rg_resolver_upstream_rtt_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"2.5\"} 1"
+        ));
+        assert!(has(
+            "rg_resolver_upstream_rtt_seconds_bucket{upstream=\"192.0.2.53:53\",le=\"+Inf\"} 2"
+        ));
+        assert!(has(
+            "rg_resolver_upstream_rtt_seconds_count{upstream=\"192.0.2.53:53\"} 2"
+        ));
+        assert!(has(
+            "rg_resolver_upstream_timeouts_total{upstream=\"192.0.2.53:53\"} 1"
+        ));
+        assert!(has(
+            "rg_resolver_upstream_duplicate_responses_total{upstream=\"192.0.2.53:53\"} 1"
+        ));
+        assert!(has(
+            "rg_resolver_ttl_overrides_applied_total{suffix=\"internal.example.\"} 1"
+        ));
+        assert!(has(
+            "rg_resolver_queue_depth{queue=\"dns/udp 127.0.0.1:53\"} 1"
+        ));
+        assert!(has(
+            "rg_resolver_queue_shed_total{queue=\"dns/udp 127.0.0.1:53\"} 0"
+        ));
+        assert!(has(
+            "rg_resolver_queue_busy_total{queue=\"dns/udp 127.0.0.1:53\"} 1"
+        ));
+        assert!(has("rg_resolver_active_connections 1"));
+        drop(connection);
+        assert!(metrics
+            .render()
+            .lines()
+            .any(|l| l == "rg_resolver_active_connections 0"));
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> anyhow::Result<String> {
+        let mut stream = TcpStream::connect(addr).await?;
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn serves_metrics() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        let serving = tokio::spawn(serve(listener, shutdown.subscribe()));
+
+        let response = get(addr, "/metrics").await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\r\n\r\n# HELP rg_resolver_queries_total "));
+        let response = get(addr, "/").await?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        assert!(shutdown.drain(Duration::from_secs(1)).await);
+        serving.await??;
+        Ok(())
+    }
+}
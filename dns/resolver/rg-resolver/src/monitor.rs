@@ -0,0 +1,257 @@
+use crate::message::{Message, QueryBuilder, QuestionClass, QuestionType};
+use crate::{rr, task};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::debug;
+
+/// How the upstream monitor probes each nameserver.
+#[derive(Clone, Debug)]
+pub struct MonitorConfig {
+    pub upstreams: Vec<SocketAddr>,
+    /// The name queried on each probe. It should be one the upstreams can always answer.
+    pub canary: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// The number of probes kept per upstream.
+    pub history: usize,
+}
+
+impl MonitorConfig {
+    pub fn new(upstreams: Vec<SocketAddr>) -> Self {
+        MonitorConfig {
+            upstreams,
+            canary: String::from("."),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            history: 360,
+        }
+    }
+}
+
+/// The outcome of one probe.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Sample {
+    pub at: SystemTime,
+    /// The round-trip time, or None if the upstream didn't answer.
+    pub latency: Option<Duration>,
+}
+
+/// Availability and latency of one upstream over the kept history.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UpstreamReport {
+    pub upstream: SocketAddr,
+    pub probes: usize,
+    /// The percentage of probes answered.
+    pub availability: f64,
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub samples: Vec<Sample>,
+}
+
+/// Periodically queries each upstream for a canary name, keeping a time series of the results.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+    config: MonitorConfig,
+    samples: Arc<Mutex<HashMap<SocketAddr, VecDeque<Sample>>>>,
+}
+
+impl Monitor {
+    pub fn new(config: MonitorConfig) -> Self {
+        Monitor {
+            config,
+            samples: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Probes every upstream each interval until the returned task is aborted.
+    pub fn spawn(&self) -> JoinHandle<()> {
+        let monitor = self.clone();
+        task::spawn_named("upstream monitor", async move {
+            let mut tick = time::interval(monitor.config.interval);
+            loop {
+                tick.tick().await;
+                monitor.probe_all().await;
+            }
+        })
+    }
+
+    /// Probes every upstream once, concurrently.
+    pub async fn probe_all(&self) {
+        let probes = self.config.upstreams.iter().map(|&upstream| {
+            let monitor = self.clone();
+            tokio::spawn(async move {
+                let at = SystemTime::now();
+                let latency =
+                    match probe(upstream, &monitor.config.canary, monitor.config.timeout).await {
+                        Ok(latency) => Some(latency),
+                        Err(e) => {
+                            debug!("Probe of {upstream} failed: {e}");
+                            None
+                        }
+                    };
+                monitor.record(upstream, Sample { at, latency });
+            })
+        });
+        for probe in probes.collect::<Vec<_>>() {
+            let _ = probe.await;
+        }
+    }
+
+    fn record(&self, upstream: SocketAddr, sample: Sample) {
+        let mut samples = self.samples.lock().unwrap();
+        let history = samples.entry(upstream).or_default();
+        history.push_back(sample);
+        while history.len() > self.config.history {
+            history.pop_front();
+        }
+    }
+
+    /// Reports on every upstream, in the configured order.
+    pub fn report(&self) -> Vec<UpstreamReport> {
+        let samples = self.samples.lock().unwrap();
+        self.config
+            .upstreams
+            .iter()
+            .map(|&upstream| {
+                let history = samples
+                    .get(&upstream)
+                    .map(|history| history.iter().cloned().collect::<Vec<_>>())
+                    .unwrap_or_default();
+                let mut latencies = history.iter().filter_map(|s| s.latency).collect::<Vec<_>>();
+                latencies.sort();
+                let availability = if history.is_empty() {
+                    0_f64
+                } else {
+                    latencies.len() as f64 * 100_f64 / history.len() as f64
+                };
+                UpstreamReport {
+                    upstream,
+                    probes: history.len(),
+                    availability,
+                    p50: percentile(&latencies, 50),
+                    p99: percentile(&latencies, 99),
+                    samples: history,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Queries upstream for name, returning the round-trip time of a matching response.
+pub async fn probe(
+    upstream: SocketAddr,
+    name: &str,
+    timeout: Duration,
+) -> anyhow::Result<Duration> {
+    let query = QueryBuilder::new()
+        .recursion_desired(true)
+        .question(
+            &name.parse()?,
+            QuestionType::RrType(rr::Type::NS),
+            QuestionClass::RrClass(rr::Class::IN),
+        )
+        .build();
+    let exchange = async {
+        let bind_addr: SocketAddr = match upstream {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse()?,
+            SocketAddr::V6(_) => "[::]:0".parse()?,
+        };
+        let sock = UdpSocket::bind(bind_addr).await?;
+        sock.connect(upstream).await?;
+        let sent_at = Instant::now();
+        sock.send(&query.serialize()?).await?;
+        let mut buf = [0_u8; 512];
+        loop {
+            let size = sock.recv(&mut buf).await?;
+            // * Ignore stray datagrams rather than failing the probe.
+            match Message::parse(&buf[..size]) {
+                Ok(response) if response.is_response_to(&query) => {
+                    return anyhow::Ok(sent_at.elapsed());
+                }
+                _ => continue,
+            }
+        }
+    };
+    time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| anyhow::anyhow!("probing {upstream}: timed out"))?
+}
+
+/// The nearest-rank percentile of sorted.
+fn percentile(sorted: &[Duration], perc: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (perc * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::ResponseCode;
+    use crate::metrics::Metrics;
+
+    /// Answers every query with an empty NOERROR response.
+    async fn fake_upstream() -> anyhow::Result<SocketAddr> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn probe_upstreams() -> anyhow::Result<()> {
+        let up = fake_upstream().await?;
+        // * Nothing listens on a socket that's been dropped.
+        let down = UdpSocket::bind("127.0.0.1:0").await?.local_addr()?;
+
+        let mut config = MonitorConfig::new(vec![up, down]);
+        config.timeout = Duration::from_millis(100);
+        config.history = 2;
+        let monitor = Monitor::new(config);
+        for _ in 0..3 {
+            monitor.probe_all().await;
+        }
+
+        let report = monitor.report();
+        assert_eq!(report[0].upstream, up);
+        assert_eq!(report[0].probes, 2);
+        assert_eq!(report[0].availability, 100_f64);
+        assert!(report[0].p50.is_some());
+        assert_eq!(report[1].upstream, down);
+        assert_eq!(report[1].availability, 0_f64);
+        assert!(report[1].p99.is_none());
+        assert!(report[1].samples.iter().all(|s| s.latency.is_none()));
+
+        let metrics = Metrics::default();
+        metrics.set_monitor(monitor);
+        let rendered = metrics.render();
+        let has = |line: String| rendered.lines().any(|l| l == line);
+        assert!(has(format!(
+            "rg_resolver_upstream_availability_percent{{upstream=\"{up}\"}} 100"
+        )));
+        assert!(has(format!(
+            "rg_resolver_upstream_availability_percent{{upstream=\"{down}\"}} 0"
+        )));
+        assert!(rendered.contains(&format!(
+            "rg_resolver_upstream_probe_latency_seconds{{upstream=\"{up}\",quantile=\"0.99\"}} "
+        )));
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+use crate::dedup::DuplicateFilter;
+use crate::exchange::Exchange;
+use crate::message::{Header, Message};
+use crate::net::{self, SourcePorts};
+use crate::resolve::BoxFuture;
+use crate::{metrics, task};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::debug;
+
+/// The largest UDP datagram, so no response is cut short on receipt.
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// The queries sent from one socket before it's replaced with one on another port, so a
+/// forger can't learn the port from one query and use it against the rest.
+pub const QUERIES_PER_SOCKET: usize = 32;
+
+/// One UDP socket per address family shared by the queries outstanding at once, rather than
+/// a socket per query. Each response is routed back to the query it answers by its message
+/// ID, and only if it came from the server that query went to.
+///
+/// Each socket sends a batch of [QUERIES_PER_SOCKET] queries, then is replaced with one bound
+/// to another of the source ports. It's closed once the queries sent from it are done.
+///
+/// The sockets aren't connected, so the OS doesn't report unreachable servers on them, and
+/// a query to one waits out the timeout.
+///
+/// A response a server sends again after its query is answered is dropped and counted, and
+/// the ID isn't used for another query until [DuplicateFilter]'s window has passed, so the
+/// duplicate can't be taken for that query's response.
+pub struct Multiplexer {
+    v4: Mutex<Slot>,
+    /// None if the host has no IPv6.
+    v6: Option<Mutex<Slot>>,
+    pending: Arc<Mutex<HashMap<u16, Pending>>>,
+    completed: Arc<Mutex<DuplicateFilter>>,
+    timeout: Duration,
+    source_ports: SourcePorts,
+}
+
+/// The random IDs tried for a query whose own ID isn't free before trying every ID in turn.
+const RANDOM_ID_ATTEMPTS: usize = 32;
+
+/// The socket new queries for one address family are sent from.
+struct Slot {
+    socket: Arc<Socket>,
+    queries: usize,
+}
+
+struct Socket {
+    sock: Arc<UdpSocket>,
+    receiver: JoinHandle<()>,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
+
+/// A query waiting for its response.
+struct Pending {
+    server: SocketAddr,
+    /// The query as sent, under the ID it was sent with.
+    query: Message,
+    tx: oneshot::Sender<Received>,
+}
+
+enum Received {
+    Response(Box<Message>),
+    /// The response had the TC bit set, so has to be fetched over TCP.
+    Truncated,
+}
+
+/// Removes a pending query when its exchange finishes or is cancelled.
+struct Outstanding<'a> {
+    pending: &'a Mutex<HashMap<u16, Pending>>,
+    id: u16,
+}
+
+impl Drop for Outstanding<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Multiplexer {
+    /// Binds the shared sockets to ephemeral ports, waiting up to timeout for each response.
+    pub async fn bind(timeout: Duration) -> anyhow::Result<Self> {
+        Self::bind_from(timeout, SourcePorts::ephemeral()).await
+    }
+
+    /// Binds the shared sockets to ports picked from source_ports.
+    pub async fn bind_from(timeout: Duration, source_ports: SourcePorts) -> anyhow::Result<Self> {
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let completed = Arc::new(Mutex::new(DuplicateFilter::default()));
+        let bind = |addr: SocketAddr| Slot::bind(addr, &source_ports, &pending, &completed);
+        let v4 = bind((Ipv4Addr::UNSPECIFIED, 0).into())?;
+        let v6 = match bind((Ipv6Addr::UNSPECIFIED, 0).into()) {
+            Ok(v6) => Some(Mutex::new(v6)),
+            Err(e) => {
+                debug!("Not querying over IPv6: {e}");
+                None
+            }
+        };
+        Ok(Multiplexer {
+            v4: Mutex::new(v4),
+            v6,
+            pending,
+            completed,
+            timeout,
+            source_ports,
+        })
+    }
+
+    /// The addresses the sockets new queries are sent from are bound to.
+    pub fn local_addrs(&self) -> anyhow::Result<Vec<SocketAddr>> {
+        let mut addrs = vec![self.v4.lock().unwrap().socket.sock.local_addr()?];
+        if let Some(v6) = &self.v6 {
+            addrs.push(v6.lock().unwrap().socket.sock.local_addr()?);
+        }
+        Ok(addrs)
+    }
+
+    /// The number of queries waiting for a response.
+    pub fn outstanding(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// The number of responses dropped as duplicates of ones already received.
+    pub fn duplicates(&self) -> u64 {
+        self.completed.lock().unwrap().duplicates()
+    }
+
+    /// Sends query to server and waits for its response.
+    ///
+    /// If another outstanding query has the same ID, query is sent under an unused one, and
+    /// the response is given back under query's.
+    pub async fn exchange(&self, server: SocketAddr, query: &Message) -> anyhow::Result<Message> {
+        let socket = self.socket_for(server)?;
+        let (tx, rx) = oneshot::channel();
+        let (id, bytes) = self.register(server, query, tx)?;
+        let _outstanding = Outstanding {
+            pending: &self.pending,
+            id,
+        };
+        socket.sock.send_to(&bytes, server).await?;
+
+        let received = time::timeout(self.timeout, rx)
+            .await
+            .map_err(|_| anyhow::anyhow!("querying {server}: timed out"))?
+            .map_err(|_| anyhow::anyhow!("querying {server}: the socket stopped receiving"))?;
+        let mut response = match received {
+            Received::Response(response) => *response,
+            Received::Truncated => {
+                debug!("{server} truncated its response, retrying over TCP");
+                return net::exchange_tcp(server, query).await;
+            }
+        };
+        response.set_id(query.id());
+        Ok(response)
+    }
+
+    /// The socket to send the next query to server from, replacing the current one if it's
+    /// sent its batch.
+    fn socket_for(&self, server: SocketAddr) -> anyhow::Result<Arc<Socket>> {
+        let slot = match server {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => self
+                .v6
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("querying {server}: no IPv6 socket"))?,
+        };
+        let mut slot = slot.lock().unwrap();
+        if slot.queries == QUERIES_PER_SOCKET {
+            *slot = Slot::bind(server, &self.source_ports, &self.pending, &self.completed)?;
+        }
+        slot.queries += 1;
+        Ok(slot.socket.clone())
+    }
+
+    /// Registers a query to server, returning the ID to send it under, query's if it's free
+    /// and otherwise a random one that is, along with the bytes to send. An ID is free if no
+    /// query is waiting under it and none was answered under it within the duplicate window.
+    ///
+    /// Fails if no ID is free.
+    fn register(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        tx: oneshot::Sender<Received>,
+    ) -> anyhow::Result<(u16, Vec<u8>)> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut completed = self.completed.lock().unwrap();
+        let mut is_free = |id: u16| !pending.contains_key(&id) && !completed.completed_recently(id);
+        let random = std::iter::repeat_with(rand::random::<u16>).take(RANDOM_ID_ATTEMPTS);
+        // * Only when nearly every ID is taken are they all tried, from a random one on.
+        let start = rand::random::<u16>();
+        let every = (0..=u16::MAX).map(|offset| start.wrapping_add(offset));
+        let id = std::iter::once(query.id())
+            .chain(random)
+            .chain(every)
+            .find(|id| is_free(*id))
+            .ok_or_else(|| {
+                anyhow::anyhow!("every query ID is in use or was answered too recently to reuse")
+            })?;
+        let mut query = query.clone();
+        query.set_id(id);
+        let bytes = query.serialize()?;
+        pending.insert(id, Pending { server, query, tx });
+        Ok((id, bytes))
+    }
+}
+
+impl Slot {
+    /// Binds a socket for sending to servers of server's address family.
+    fn bind(
+        server: SocketAddr,
+        source_ports: &SourcePorts,
+        pending: &Arc<Mutex<HashMap<u16, Pending>>>,
+        completed: &Arc<Mutex<DuplicateFilter>>,
+    ) -> anyhow::Result<Self> {
+        let sock = Arc::new(source_ports.bind_udp(server)?);
+        let receiving = receive(sock.clone(), pending.clone(), completed.clone());
+        let receiver = task::spawn_named("query multiplexer", receiving);
+        Ok(Slot {
+            socket: Arc::new(Socket { sock, receiver }),
+            queries: 0,
+        })
+    }
+}
+
+/// Hands each response received on sock to the pending query it answers, recording it in
+/// completed so a duplicate of it is recognized.
+async fn receive(
+    sock: Arc<UdpSocket>,
+    pending: Arc<Mutex<HashMap<u16, Pending>>>,
+    completed: Arc<Mutex<DuplicateFilter>>,
+) {
+    let mut buf = vec![0_u8; MAX_DATAGRAM_LEN];
+    loop {
+        let (size, from) = match sock.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // * Windows reports ICMP errors for earlier sends even on unconnected sockets.
+            Err(e) => {
+                debug!("Receiving on the shared socket: {e}");
+                continue;
+            }
+        };
+        let Ok(header) = Header::peek(&buf[..size]) else {
+            continue;
+        };
+        if !header.is_response() {
+            continue;
+        }
+        let mut pending = pending.lock().unwrap();
+        // * A response from anywhere but the server queried could be spoofed.
+        if pending
+            .get(&header.id())
+            .is_none_or(|query| query.server != from)
+        {
+            let duplicate = Message::parse(&buf[..size])
+                .is_ok_and(|response| completed.lock().unwrap().is_duplicate(&response));
+            if duplicate {
+                metrics::global().count_duplicate(from);
+                debug!("Dropped a duplicate response from {from}");
+            } else {
+                debug!("Dropped an unexpected response from {from}");
+            }
+            continue;
+        }
+        let received = if header.is_truncated() {
+            Received::Truncated
+        } else {
+            match Message::parse(&buf[..size]) {
+                // * query is known to be there, as checked above.
+                Ok(response) if response.is_response_to(&pending[&header.id()].query) => {
+                    completed.lock().unwrap().complete(&response);
+                    Received::Response(Box::new(response))
+                }
+                Ok(_) => {
+                    debug!("Dropped a response from {from} that doesn't match the query");
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Dropped a response from {from}: {e}");
+                    continue;
+                }
+            }
+        };
+        let query = pending.remove(&header.id()).expect("checked above");
+        let _ = query.tx.send(received);
+    }
+}
+
+impl Exchange for Multiplexer {
+    fn exchange<'a>(
+        &'a self,
+        server: SocketAddr,
+        query: &'a Message,
+    ) -> BoxFuture<'a, anyhow::Result<Message>> {
+        Box::pin(Multiplexer::exchange(self, server, query))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{self, ResponseCode};
+
+    /// Collects count queries, then answers them in reverse order, also sending a response
+    /// to each from another address first.
+    async fn reversing_server(count: usize) -> anyhow::Result<SocketAddr> {
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let spoofer = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            let mut queries = Vec::new();
+            while queries.len() < count {
+                let (size, from) = sock.recv_from(&mut buf).await.unwrap();
+                queries.push((Message::parse(&buf[..size]).unwrap(), from));
+            }
+            for (query, from) in queries.into_iter().rev() {
+                let spoofed = query.empty_response(ResponseCode::NameError);
+                let _ = spoofer.send_to(&spoofed.serialize().unwrap(), from).await;
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn routes_concurrent_responses() -> anyhow::Result<()> {
+        let server = reversing_server(3).await?;
+        let mux = Multiplexer::bind(Duration::from_secs(5)).await?;
+        let first = message::address_query(&"one.example.".parse()?);
+        let second = message::address_query(&"two.example.".parse()?);
+        // * Two queries with the same ID can be outstanding at once.
+        let third = first.clone();
+
+        let (a, b, c) = tokio::join!(
+            mux.exchange(server, &first),
+            mux.exchange(server, &second),
+            mux.exchange(server, &third),
+        );
+        for (query, response) in [(&first, a?), (&second, b?), (&third, c?)] {
+            assert_eq!(response.id(), query.id());
+            assert_eq!(response.questions(), query.questions());
+            assert_eq!(response.response_code(), ResponseCode::NoError);
+        }
+        assert_eq!(mux.outstanding(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rotates_source_ports() -> anyhow::Result<()> {
+        let server = reversing_server(1).await?;
+        let source_ports = SourcePorts::range(40000, 49999)?;
+        let mux = Multiplexer::bind_from(Duration::from_secs(5), source_ports).await?;
+        let first = mux.local_addrs()?[0];
+        assert!((40000..=49999).contains(&first.port()));
+
+        // * The first query of each batch picks a new port.
+        mux.exchange(server, &message::address_query(&"example.com.".parse()?))
+            .await?;
+        for _ in 1..QUERIES_PER_SOCKET {
+            mux.socket_for(server)?;
+        }
+        assert_eq!(mux.local_addrs()?[0], first);
+        let new = mux.socket_for(server)?;
+        assert_ne!(new.sock.local_addr()?.port(), first.port());
+        assert!((40000..=49999).contains(&new.sock.local_addr()?.port()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drops_duplicate_responses() -> anyhow::Result<()> {
+        // * Answers every query twice, with the query's ID.
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let server = sock.local_addr()?;
+        let ids = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn({
+            let ids = ids.clone();
+            async move {
+                let mut buf = [0_u8; 512];
+                while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                    let query = Message::parse(&buf[..size]).unwrap();
+                    ids.lock().unwrap().push(query.id());
+                    let response = query.response(ResponseCode::NoError, vec![], vec![], vec![]);
+                    for _ in 0..2 {
+                        let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+                    }
+                }
+            }
+        });
+        let mux = Multiplexer::bind(Duration::from_secs(5)).await?;
+        let query = message::address_query(&"example.com.".parse()?);
+        mux.exchange(server, &query).await?;
+        // * The same query again goes out under another ID, so the first one's duplicate
+        // * isn't taken for its response.
+        let response = mux.exchange(server, &query).await?;
+        assert_eq!(response.id(), query.id());
+        let ids = ids.lock().unwrap().clone();
+        assert_eq!(ids[0], query.id());
+        assert_ne!(ids[1], query.id());
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(mux.duplicates(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn runs_out_of_ids() -> anyhow::Result<()> {
+        let mux = Multiplexer::bind(Duration::from_secs(5)).await?;
+        let server = SocketAddr::from((Ipv4Addr::LOCALHOST, 53));
+        let query = message::address_query(&"example.com.".parse()?);
+        let pend = |id| {
+            let (tx, _) = oneshot::channel();
+            let query = query.clone();
+            mux.pending
+                .lock()
+                .unwrap()
+                .insert(id, Pending { server, query, tx });
+        };
+        (0..=u16::MAX).filter(|id| *id != 7).for_each(pend);
+        // * The one ID left is found, however unlikely it is to be picked at random.
+        let (tx, _) = oneshot::channel();
+        assert_eq!(mux.register(server, &query, tx)?.0, 7);
+
+        // * An ID answered within the duplicate window isn't free either.
+        mux.pending.lock().unwrap().remove(&7);
+        let mut response = query.empty_response(ResponseCode::NoError);
+        response.set_id(7);
+        mux.completed.lock().unwrap().complete(&response);
+        let (tx, _) = oneshot::channel();
+        assert!(mux.register(server, &query, tx).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn times_out() -> anyhow::Result<()> {
+        // * Never answers.
+        let sock = UdpSocket::bind("127.0.0.1:0").await?;
+        let mux = Multiplexer::bind(Duration::from_millis(50)).await?;
+        let query = message::address_query(&"example.com.".parse()?);
+        assert!(mux.exchange(sock.local_addr()?, &query).await.is_err());
+        assert_eq!(mux.outstanding(), 0);
+        Ok(())
+    }
+}
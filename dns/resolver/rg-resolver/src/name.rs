@@ -1,54 +1,242 @@
-use anyhow::Context;
+use crate::message::QuestionType;
+use crate::resolve::{Answer, BoxFuture, Resolve};
+use crate::wire::Writer;
 use bytes::{Buf, BufMut};
+use rg_resolver_common::{DomainName, DomainNameError};
+use std::str::FromStr;
+
+/// How strictly the names of questions are checked, beyond fitting in a message.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Host names: letters, digits and hyphens, no label starting or ending with a hyphen
+    /// (RFC 1123 section 2.1). Rejects the underscores of service names like _sip._udp.
+    Hostname,
+    /// Any ASCII, as the DNS itself allows (RFC 2181 section 11).
+    #[default]
+    Standard,
+    /// Any label a name can hold, e.g. mDNS's UTF-8 ones (RFC 6762 section 16).
+    Binary,
+}
+
+impl FromStr for Strictness {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "hostname" => Ok(Strictness::Hostname),
+            "standard" => Ok(Strictness::Standard),
+            "binary" => Ok(Strictness::Binary),
+            _ => {
+                anyhow::bail!("unknown name strictness {s}, expected hostname, standard or binary")
+            }
+        }
+    }
+}
+
+/// Checks that name fits in a message, its labels in 63 bytes and it in 255, and that its
+/// labels have only what strictness allows.
+///
+/// [DomainName] only checks a name's structure, so this is the one place its contents are
+/// checked: names read from messages are held to [Strictness::Binary], and questions to
+/// whatever the resolver's configured with; see [Validated].
+pub fn validate(name: &DomainName, strictness: Strictness) -> Result<(), DomainNameError> {
+    let mut len = 1;
+    for label in name.labels() {
+        if label.len() > DomainName::MAX_LABEL_LENGTH {
+            return Err(DomainNameError::LabelTooLong(label.to_string()));
+        }
+        len += label.len() + 1;
+        match strictness {
+            Strictness::Binary => {}
+            _ if !label.is_ascii() => {
+                return Err(DomainNameError::LabelNotAscii(label.to_string()));
+            }
+            Strictness::Standard => {}
+            Strictness::Hostname if label.contains('_') => {
+                return Err(DomainNameError::LabelHasUnderscore(label.to_string()));
+            }
+            Strictness::Hostname => {
+                let ldh = label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+                if !ldh || label.starts_with('-') || label.ends_with('-') {
+                    return Err(DomainNameError::LabelNotHostname(label.to_string()));
+                }
+            }
+        }
+    }
+    if len > DomainName::MAX_LENGTH {
+        return Err(DomainNameError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Refuses to look up names the inner resolver shouldn't be asked about, failing with the
+/// [DomainNameError] saying why.
+pub struct Validated<R> {
+    inner: R,
+    strictness: Strictness,
+}
+
+impl<R: Resolve> Validated<R> {
+    pub fn new(inner: R, strictness: Strictness) -> Self {
+        Validated { inner, strictness }
+    }
+}
+
+impl<R: Resolve> Resolve for Validated<R> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let qname = name.parse::<DomainName>()?;
+            if let Err(e) = validate(&qname, self.strictness) {
+                return Err(anyhow::Error::new(e).context(format!("invalid QNAME {name}")));
+            }
+            self.inner.lookup(name, qtype).await
+        })
+    }
+}
 
-/// ptr holds the offset within the *message* of the tail end of a compressed name.
+/// ptr holds the offset within the *message* of the tail end of a compressed name, which must
+/// then be relative.
 // TODO: To make this safer and ensure that the pointer offset is before the current
 // TODO: offset into the message, create a Pointer structure and make the ptr
 // TODO: parameter have type Option<Pointer>.
-pub fn serialize(name: &str, ptr: Option<u16>) -> anyhow::Result<Vec<u8>> {
-    if !name.is_ascii() {
-        anyhow::bail!("serializing name: name not ASCII");
-    }
+pub fn serialize(name: &DomainName, ptr: Option<u16>) -> anyhow::Result<Vec<u8>> {
+    check_end(name, ptr)?;
     let mut buf = Vec::new();
-    let labels = name.split('.').map(str::trim).collect::<Vec<_>>();
-    for label in labels {
+    for label in name.labels() {
         buf.put_u8(label.len() as u8);
-        label.chars().map(|c| c as u8).for_each(|b| buf.put_u8(b));
+        buf.put_slice(label.as_bytes());
+    }
+    match ptr {
+        Some(offset) => buf.put_u16(0xc000 | offset),
+        // Length byte of 0 for the NULL label.
+        None => buf.put_u8(0),
     }
+    Ok(buf)
+}
+
+/// Serializes name like [serialize], but into w.
+pub(crate) fn serialize_into(
+    name: &DomainName,
+    ptr: Option<u16>,
+    w: &mut Writer,
+) -> anyhow::Result<()> {
+    check_end(name, ptr)?;
+    for label in name.labels() {
+        w.put_u8(label.len() as u8)?;
+        w.put_slice(label.as_bytes())?;
+    }
+    match ptr {
+        Some(offset) => w.put_u16(0xc000 | offset)?,
+        // Length byte of 0 for the NULL label.
+        None => w.put_u8(0)?,
+    }
+    Ok(())
+}
+
+/// Checks that name fits in a message and can end with ptr, or with the root label if there's
+/// no pointer.
+fn check_end(name: &DomainName, ptr: Option<u16>) -> anyhow::Result<()> {
+    validate(name, Strictness::Binary).map_err(|e| anyhow::anyhow!("serializing name: {e}"))?;
     if let Some(offset) = ptr {
         if offset > 2_u16.pow(14) - 1 {
             anyhow::bail!("serializing name: offset too large");
         }
-        if name.ends_with('.') {
+        if name.is_absolute() {
             anyhow::bail!(
                 "serializing name: the root label may not precede the pointer in a compressed name"
             );
         }
-        buf.put_u16(0xc000 | offset);
-    } else {
-        if !name.ends_with('.') {
-            anyhow::bail!("serializing name: a non-compressed name must end with the root label");
-        }
-        // * The call to split above results in an empty string when the name ends with a '.',
-        // * causing a length byte of 0 to be added to the buffer for the NULL label as desired.
+    } else if !name.is_absolute() {
+        anyhow::bail!("serializing name: a non-compressed name must end with the root label");
     }
+    Ok(())
+}
 
-    Ok(buf)
+/// The most compression pointers one name may follow. A name has at most 127 labels, and each
+/// may be reached through a pointer, so no name needs more; a longer chain only makes work.
+pub const MAX_POINTERS: usize = 127;
+
+/// Why a name couldn't be read from a message.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The message ends before the name does.
+    Incomplete,
+    /// The message ends within a pointer.
+    IncompletePointer,
+    /// The message ends within a label.
+    IncompleteLabel,
+    /// A label's length byte starts with 01 or 10, which are reserved (RFC 6891 section 5).
+    ReservedLabelType(u8),
+    /// A pointer at offset at to offset to, which isn't earlier in the message, so could loop.
+    ForwardPointer { at: usize, to: usize },
+    /// The name follows more than [MAX_POINTERS] pointers.
+    TooManyPointers,
+    /// The name's labels take more than [DomainName::MAX_LENGTH] bytes.
+    TooLong,
+    /// The bytes to parse aren't within the message.
+    OutsideMessage,
+    /// The name read isn't one a [DomainName] can hold.
+    Invalid(DomainNameError),
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("parsing name: ")?;
+        match self {
+            ParseError::Incomplete => f.write_str("incomplete name"),
+            ParseError::IncompletePointer => f.write_str("incomplete pointer"),
+            ParseError::IncompleteLabel => f.write_str("incomplete label"),
+            ParseError::ReservedLabelType(len) => write!(
+                f,
+                "use of reserved value in compression indication bits ({len:#04x})"
+            ),
+            ParseError::ForwardPointer { at, to } => write!(
+                f,
+                "pointer at {at} must point to a name that exists earlier in the message, not {to}"
+            ),
+            ParseError::TooManyPointers => {
+                write!(f, "name follows more than {MAX_POINTERS} pointers")
+            }
+            ParseError::TooLong => write!(
+                f,
+                "name exceeds maximum length of {}",
+                DomainName::MAX_LENGTH
+            ),
+            ParseError::OutsideMessage => f.write_str("name isn't within the message"),
+            ParseError::Invalid(e) => write!(f, "{e}"),
+        }
+    }
 }
 
-/// msg must point to the very first byte of the message.
-pub fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<String> {
-    let mut name = String::new();
+impl std::error::Error for ParseError {}
+
+/// msg must point to the very first byte of the message, and unparsed into it.
+///
+/// Any name that fits is read, as with [Strictness::Binary]; questions are held to more.
+/// However the message is made, at most [DomainName::MAX_LENGTH] bytes of labels and
+/// [MAX_POINTERS] pointers are read.
+pub fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> Result<DomainName, ParseError> {
+    let mut labels = Vec::new();
+    // The length of the name on the wire so far, counting the root label's length byte.
+    let mut name_len = 1;
+    let mut pointers = 0;
     let mut buf = *unparsed;
     let mut input_slice_advanced = false;
     loop {
-        if !buf.has_remaining() {
-            anyhow::bail!("parsing name: incomplete name");
-        }
-        let len = {
-            let mut peek: &[u8] = buf;
-            peek.get_u8() as usize
+        let Some(&len) = buf.first() else {
+            return Err(ParseError::Incomplete);
         };
+        let len = len as usize;
         if len == 0 {
             // Advance past the length byte we only peeked at.
             buf.advance(1);
@@ -57,22 +245,30 @@ pub fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Strin
             if !input_slice_advanced {
                 *unparsed = buf;
             }
-            if name.len() <= 255 {
-                return Ok(name);
-            } else {
-                anyhow::bail!("parsing name: name exceeds maximum length of 255");
-            }
+            // The name ends with the NULL label, and the root name consists of only it.
+            labels.push(String::new());
+            // * from_labels only fails with DomainName errors.
+            let name = DomainName::from_labels(labels).map_err(|e| match e {
+                rg_resolver_common::Error::DomainName(e) => ParseError::Invalid(e),
+                e => {
+                    unreachable!("labels read from the wire made an error other than a name's: {e}")
+                }
+            })?;
+            validate(&name, Strictness::Binary).map_err(ParseError::Invalid)?;
+            return Ok(name);
         }
         if is_compressed(len)? {
             if buf.remaining() < 2 {
-                anyhow::bail!("parsing name: incomplete pointer");
+                return Err(ParseError::IncompletePointer);
             }
-            let ptr_offset = unsafe { buf.as_ptr().offset_from(msg.as_ptr()) as usize };
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return Err(ParseError::TooManyPointers);
+            }
+            let at = offset_in(msg, buf)?;
             let offset = (buf.get_u16() & !0xc000) as usize;
-            if offset >= ptr_offset {
-                anyhow::bail!(
-                    "parsing name: pointer must point to a name that exists earlier in the message"
-                );
+            if offset >= at {
+                return Err(ParseError::ForwardPointer { at, to: offset });
             }
             // Advance the input slice when the first pointer is encountered.
             // Pointed-to names are located earlier in the message so
@@ -85,75 +281,237 @@ pub fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Strin
             buf = &msg[offset..];
             continue;
         }
+        // * Stop as soon as the name is too long, rather than reading the rest of it.
+        name_len += len + 1;
+        if name_len > DomainName::MAX_LENGTH {
+            return Err(ParseError::TooLong);
+        }
         // Advance past the length byte we only peeked at.
         buf.advance(1);
         if buf.remaining() < len {
-            anyhow::bail!("parsing name: incomplete label")
+            return Err(ParseError::IncompleteLabel);
         }
         let label = &buf[..len];
         buf.advance(len);
-        let label = String::from_utf8(label.to_vec())
-            .with_context(|| "parsing name: label not valid UTF-8")?;
-        if !label.is_ascii() {
-            anyhow::bail!("parsing name: label not ASCII");
+        let label = String::from_utf8(label.to_vec()).map_err(|e| {
+            ParseError::Invalid(DomainNameError::LabelNotUtf8(escape_label(e.as_bytes())))
+        })?;
+        labels.push(label);
+    }
+}
+
+/// The offset of buf within msg, which it has to be a part of.
+fn offset_in(msg: &[u8], buf: &[u8]) -> Result<usize, ParseError> {
+    (buf.as_ptr() as usize)
+        .checked_sub(msg.as_ptr() as usize)
+        .filter(|&offset| offset + buf.len() <= msg.len())
+        .ok_or(ParseError::OutsideMessage)
+}
+
+/// Splits a name in presentation format into its labels, leaving any escapes in the labels.
+///
+/// A '.' escaped as "\." doesn't separate labels. An absolute name ends with an empty label.
+pub fn split_labels(name: &str) -> Vec<&str> {
+    Labels::new(name).collect()
+}
+
+/// The labels of a name, as split by [split_labels], without collecting them.
+struct Labels<'a> {
+    rest: Option<&'a str>,
+}
+
+impl<'a> Labels<'a> {
+    fn new(name: &'a str) -> Self {
+        Labels { rest: Some(name) }
+    }
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        let mut escaped = false;
+        for (idx, c) in rest.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '.' => {
+                    self.rest = Some(&rest[idx + 1..]);
+                    return Some(&rest[..idx]);
+                }
+                _ => {}
+            }
+        }
+        self.rest = None;
+        Some(rest)
+    }
+}
+
+/// Decodes the RFC 1035 escapes in a label in presentation format:
+/// "\X" is the character X and "\DDD" is the byte with decimal value DDD.
+pub fn unescape_label(label: &str) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(label.len());
+    let mut chars = label.bytes();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match chars.next() {
+            Some(d) if d.is_ascii_digit() => {
+                let (Some(d2), Some(d3)) = (chars.next(), chars.next()) else {
+                    anyhow::bail!("escaped label: \\DDD escape needs three digits");
+                };
+                if !d2.is_ascii_digit() || !d3.is_ascii_digit() {
+                    anyhow::bail!("escaped label: \\DDD escape needs three digits");
+                }
+                let value = (d - b'0') as u16 * 100 + (d2 - b'0') as u16 * 10 + (d3 - b'0') as u16;
+                if value > 255 {
+                    anyhow::bail!("escaped label: \\{value} is out of range");
+                }
+                bytes.push(value as u8);
+            }
+            Some(c) => bytes.push(c),
+            None => anyhow::bail!("escaped label: trailing backslash"),
         }
-        name.push_str(&label);
-        name.push('.');
     }
+    Ok(bytes)
 }
 
-fn is_compressed(len: usize) -> anyhow::Result<bool> {
+/// Encodes a label in presentation format, escaping '.', '\\', and '"' with a backslash and
+/// other bytes that aren't printable as \DDD.
+pub fn escape_label(label: &[u8]) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for &b in label {
+        match b {
+            b'.' | b'\\' | b'"' => {
+                escaped.push('\\');
+                escaped.push(b as char);
+            }
+            0x21..=0x7e => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\{b:03}")),
+        }
+    }
+    escaped
+}
+
+fn is_compressed(len: usize) -> Result<bool, ParseError> {
     match len & 0xc0 {
         0xc0 => Ok(true),
         0x00 => Ok(false),
-        _ => Err(anyhow::anyhow!(
-            "parsing name: use of reserved value in compression indication bits"
-        )),
+        _ => Err(ParseError::ReservedLabelType(len as u8)),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::resolve::Static;
+    use crate::rr;
     use bytes::BufMut;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
 
     #[test]
     fn serialize_uncompressed() -> anyhow::Result<()> {
-        let name = serialize("google.com.", None)?;
+        let name = serialize(&name("google.com."), None)?;
         let expected = [
             6, b'g', b'o', b'o', b'g', b'l', b'e', 3, b'c', b'o', b'm', 0,
         ];
         assert_eq!(name, expected);
 
-        assert!(serialize("google.com", None).is_err());
+        assert!(serialize(&self::name("google.com"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_root_and_tld() -> anyhow::Result<()> {
+        assert_eq!(serialize(&name("."), None)?, [0]);
+        assert_eq!(serialize(&name("com."), None)?, [3, b'c', b'o', b'm', 0]);
+
+        for bad in ["", "google..com."] {
+            assert!(bad.parse::<DomainName>().is_err(), "{bad}");
+        }
+        // * A name too long to send can be made, but not serialized.
+        let long = name(&format!("{}.com.", "a".repeat(64)));
+        assert!(serialize(&long, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_escaped() -> anyhow::Result<()> {
+        let name = serialize(&name("foo\\.bar.a\\098c\\\\."), None)?;
+        let expected = [
+            7, b'f', b'o', b'o', b'.', b'b', b'a', b'r', 4, b'a', b'b', b'c', b'\\', 0,
+        ];
+        assert_eq!(name, expected);
+
+        // * The escaped '.' doesn't end the name with the root label.
+        let relative = self::name("com\\.");
+        assert!(serialize(&relative, None).is_err());
+        assert_eq!(
+            serialize(&relative, Some(7))?,
+            [4, b'c', b'o', b'm', b'.', 0xc0, 7]
+        );
+
+        for bad in ["a\\25.com.", "a\\256.com.", "a\\200.com.", "a\\"] {
+            assert!(bad.parse::<DomainName>().is_err(), "{bad}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn escape_round_trip() -> anyhow::Result<()> {
+        for name in [
+            "foo\\.bar.example.com.",
+            "a\\\\b.com.",
+            "tab\\009\\032.com.",
+            "q\\\".com.",
+        ] {
+            let msg = serialize(&self::name(name), None)?;
+            let mut unparsed = &msg[..];
+            assert_eq!(parse(&msg[..], &mut unparsed)?.to_string(), name);
+        }
+        assert_eq!(split_labels("foo\\.bar.com."), ["foo\\.bar", "com", ""]);
+        assert_eq!(split_labels("a\\\\.com"), ["a\\\\", "com"]);
         Ok(())
     }
 
     #[test]
     fn serialize_compressed() -> anyhow::Result<()> {
-        let name = serialize("api", Some(7))?;
+        let name = serialize(&name("api"), Some(7))?;
         let expected = [3, b'a', b'p', b'i', 0xc0, 7];
         assert_eq!(name, expected);
 
-        assert!(serialize("api.", Some(7)).is_err());
+        assert!(serialize(&self::name("api."), Some(7)).is_err());
         Ok(())
     }
 
     #[test]
-    fn serialize_non_ascii_name() {
-        // Name is unicode "Ф.".
+    fn serialize_non_ascii_name() -> anyhow::Result<()> {
+        // Name is unicode "Ф.", which is sent as its UTF-8.
         let cp = 0x424;
         let b1 = 0xc0_u8 | ((cp >> 6) & 0x1f) as u8;
         let b2 = 0x80_u8 | (cp & 0x3f) as u8;
-        let name = vec![b1, b2];
-        let mut name = String::from_utf8(name).expect("mistake in utf-8 encoding for test");
-        name.push('.');
-        assert!(serialize(&name, None).is_err());
+        let text = String::from_utf8(vec![b1, b2]).expect("mistake in utf-8 encoding for test");
+        let name = name(&format!("{text}."));
+        assert_eq!(serialize(&name, None)?, [2, b1, b2, 0]);
+        assert!(validate(&name, Strictness::Binary).is_ok());
+        assert!(matches!(
+            validate(&name, Strictness::Standard),
+            Err(DomainNameError::LabelNotAscii(_))
+        ));
+        Ok(())
     }
 
     #[test]
     fn serialize_compressed_offset_too_long() {
-        assert!(serialize("api", Some(2_u16.pow(14))).is_err());
+        assert!(serialize(&name("api"), Some(2_u16.pow(14))).is_err());
     }
 
     #[test]
@@ -163,8 +521,8 @@ mod test {
             msg.put_u8(i)
         }
         let name_offset = msg.len();
-        let name = "google.com.";
-        let mut name_ser = serialize(name, None).expect("serialize name");
+        let name = name("google.com.");
+        let mut name_ser = serialize(&name, None).expect("serialize name");
         let name_ser_len = name_ser.len();
         msg.append(&mut name_ser);
 
@@ -180,6 +538,17 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_root_and_tld() -> anyhow::Result<()> {
+        for name in [".", "com."] {
+            let msg = serialize(&self::name(name), None)?;
+            let mut unparsed = &msg[..];
+            assert_eq!(parse(&msg[..], &mut unparsed)?.to_string(), name);
+            assert!(unparsed.is_empty());
+        }
+        Ok(())
+    }
+
     #[test]
     fn parse_compressed() -> anyhow::Result<()> {
         // Test parsing a nested compressed name: "drive.api.google.com.".
@@ -194,7 +563,7 @@ mod test {
         }
         let name1_offset = msg.len();
         let name1 = "google.com.";
-        let mut name1_ser = serialize(name1, None).expect("serialize name1");
+        let mut name1_ser = serialize(&name(name1), None).expect("serialize name1");
         msg.append(&mut name1_ser);
 
         for i in 11..21 {
@@ -202,7 +571,8 @@ mod test {
         }
         let name2_offset = msg.len();
         let name2 = "api";
-        let mut name2_ser = serialize(name2, Some(name1_offset as u16)).expect("serialize name2");
+        let mut name2_ser =
+            serialize(&name(name2), Some(name1_offset as u16)).expect("serialize name2");
         msg.append(&mut name2_ser);
 
         for i in 21..31 {
@@ -210,11 +580,12 @@ mod test {
         }
         let name3_offset = msg.len();
         let name3 = "drive";
-        let mut name3_ser = serialize(name3, Some(name2_offset as u16)).expect("serialize name3");
+        let mut name3_ser =
+            serialize(&name(name3), Some(name2_offset as u16)).expect("serialize name3");
         let name3_ser_len = name3_ser.len();
         msg.append(&mut name3_ser);
 
-        let name = [name3, name2, name1].join(".");
+        let name = name(&[name3, name2, name1].join("."));
         let mut unparsed = &msg[name3_offset..];
         let parse_start = unparsed;
         let parsed_name = parse(&msg[..], &mut unparsed)?;
@@ -338,6 +709,60 @@ mod test {
         assert!(parse(&buf[..], &mut unparsed).is_err());
     }
 
+    #[test]
+    fn parse_pointer_loops() {
+        // * A pointer to itself, and two pointing to each other.
+        let buf = [0xc0, 0];
+        let mut unparsed = &buf[..];
+        assert_eq!(
+            parse(&buf, &mut unparsed),
+            Err(ParseError::ForwardPointer { at: 0, to: 0 })
+        );
+        let buf = [1, b'a', 0xc0, 4, 0xc0, 2];
+        let mut unparsed = &buf[..];
+        assert_eq!(
+            parse(&buf, &mut unparsed),
+            Err(ParseError::ForwardPointer { at: 2, to: 4 })
+        );
+    }
+
+    #[test]
+    fn parse_pointer_chains() -> anyhow::Result<()> {
+        // * "a." followed by pointers that each point to the one before, the first to "a.".
+        let mut buf = vec![1, b'a', 0];
+        let mut starts = vec![0];
+        for _ in 0..MAX_POINTERS + 1 {
+            starts.push(buf.len());
+            buf.put_u16(0xc000 | starts[starts.len() - 2] as u16);
+        }
+        let mut unparsed = &buf[starts[MAX_POINTERS]..];
+        assert_eq!(parse(&buf, &mut unparsed)?, name("a."));
+        assert_eq!(unparsed.len(), 2);
+        let mut unparsed = &buf[starts[MAX_POINTERS + 1]..];
+        assert_eq!(parse(&buf, &mut unparsed), Err(ParseError::TooManyPointers));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_stops_when_too_long() {
+        // * The name never ends, but it's too long before that matters.
+        let mut buf = Vec::new();
+        for _ in 0..5 {
+            buf.put_u8(63);
+            buf.put_bytes(b'a', 63);
+        }
+        let mut unparsed = &buf[..];
+        assert_eq!(parse(&buf, &mut unparsed), Err(ParseError::TooLong));
+    }
+
+    #[test]
+    fn parse_outside_message() {
+        let msg = [1, b'a', 0];
+        let other = [0xc0, 0];
+        let mut unparsed = &other[..];
+        assert_eq!(parse(&msg, &mut unparsed), Err(ParseError::OutsideMessage));
+    }
+
     #[test]
     fn parse_incomplete_label() {
         let mut buf = Vec::new();
@@ -373,7 +798,7 @@ mod test {
     }
 
     #[test]
-    fn parse_label_not_ascii() {
+    fn parse_label_not_ascii() -> anyhow::Result<()> {
         let mut buf = Vec::new();
 
         // Name is unicode "Ф".
@@ -387,8 +812,12 @@ mod test {
         // 0 byte for NULL label.
         buf.put_u8(0);
 
+        // * Messages may carry any label; it's questions that are held to ASCII.
         let mut unparsed = &buf[..];
-        assert!(parse(&buf[..], &mut unparsed).is_err());
+        let parsed = parse(&buf[..], &mut unparsed)?;
+        assert_eq!(parsed.labels().collect::<Vec<_>>(), [name1.as_str()]);
+        assert!(validate(&parsed, Strictness::Standard).is_err());
+        Ok(())
     }
 
     #[test]
@@ -426,4 +855,122 @@ mod test {
         let mut unparsed = &buf[..];
         assert!(parse(&buf[..], &mut unparsed).is_err());
     }
+
+    #[test]
+    fn validate_strictness() {
+        use Strictness::*;
+        for (text, valid) in [
+            ("www.example.com.", [true, true, true]),
+            ("xn--bcher-kva.example", [true, true, true]),
+            ("_sip._udp.example.com.", [false, true, true]),
+            ("-edge.example.", [false, true, true]),
+            ("edge-.example.", [false, true, true]),
+            ("a\\032b.example.", [false, true, true]),
+            ("b\\195\\188cher.local.", [false, false, true]),
+        ] {
+            let name = name(text);
+            for (strictness, valid) in [Hostname, Standard, Binary].into_iter().zip(valid) {
+                assert_eq!(
+                    validate(&name, strictness).is_ok(),
+                    valid,
+                    "{text} {strictness:?}"
+                );
+            }
+        }
+        assert!(matches!(
+            validate(&name("_sip._udp.example."), Hostname),
+            Err(DomainNameError::LabelHasUnderscore(label)) if label == "_sip"
+        ));
+        assert!(matches!(
+            validate(&name("a\\032b.example."), Hostname),
+            Err(DomainNameError::LabelNotHostname(_))
+        ));
+
+        // * Whatever the strictness, a name has to fit in a message.
+        let label = "abcdefghij".repeat(7);
+        assert!(matches!(
+            validate(&name(&format!("test.{label}.com.")), Binary),
+            Err(DomainNameError::LabelTooLong(long)) if long == label
+        ));
+        // * 4 labels of 60 bytes and 1 of 10 take 256 bytes with their lengths and the root's.
+        let long = name(&format!(
+            "{}.abcdefghij.",
+            vec!["abcdefghij".repeat(6); 4].join(".")
+        ));
+        assert!(matches!(
+            validate(&long, Binary),
+            Err(DomainNameError::NameTooLong)
+        ));
+        assert!(validate(&long.parent().unwrap(), Binary).is_ok());
+
+        assert_eq!("HOSTNAME".parse::<Strictness>().unwrap(), Hostname);
+        assert!("lax".parse::<Strictness>().is_err());
+    }
+
+    #[tokio::test]
+    async fn validates_questions() -> anyhow::Result<()> {
+        let a = |name: &str| {
+            rr::ResourceRecord::new(
+                name.parse()?,
+                rr::Type::A,
+                rr::Class::IN,
+                300,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )
+        };
+        let resolver = Arc::new(Static::new(
+            "example.",
+            vec![a("www.example.")?, a("_dns.example.")?],
+        ));
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let strict = Validated::new(resolver.clone(), Strictness::Hostname);
+        assert_eq!(
+            strict
+                .lookup("www.example.", qtype)
+                .await?
+                .unwrap()
+                .records()
+                .len(),
+            1
+        );
+        let e = strict.lookup("_dns.example.", qtype).await.unwrap_err();
+        assert!(e.is::<DomainNameError>(), "{e}");
+
+        let standard = Validated::new(resolver, Strictness::Standard);
+        assert_eq!(
+            standard
+                .lookup("_dns.example.", qtype)
+                .await?
+                .unwrap()
+                .records()
+                .len(),
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_into_matches_serialize() {
+        let mut buf = [0_u8; 300];
+        for (name, ptr) in [
+            ("google.com.", None),
+            (".", None),
+            ("foo\\.bar.com.", None),
+            ("a\\065b.com.", None),
+            ("www", Some(12)),
+            ("relative", None),
+            ("google.com.", Some(12)),
+        ] {
+            let name = self::name(name);
+            let mut w = Writer::new(&mut buf);
+            match (serialize(&name, ptr), serialize_into(&name, ptr, &mut w)) {
+                (Ok(expected), Ok(())) => {
+                    let len = w.len();
+                    assert_eq!(&buf[..len], expected, "{name}");
+                }
+                (Err(expected), Err(e)) => assert_eq!(e.to_string(), expected.to_string()),
+                (expected, actual) => panic!("{name}: {expected:?} != {actual:?}"),
+            }
+        }
+    }
 }
@@ -1,25 +1,1112 @@
-use crate::message::Message;
-use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
-use tracing::info;
-
-const UDP_PORT: u16 = 53;
-
-pub fn tx_then_rx_udp(msg: &Message) -> anyhow::Result<Message> {
-    let sock = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
-    info!("Socket bound");
-    sock.connect(get_nameserver_addr()?)?;
-    info!("Socket connected");
-    let _ = sock.send(msg.serialize()?.as_slice())?;
-    info!("Data sent");
-    let mut buf = [0_u8; 512];
-    let size = sock.recv(&mut buf)?;
-    info!("Received {size} byte response");
-    let mut buf = &buf[..];
-    Ok(Message::parse(&mut buf)?)
-}
-
-fn get_nameserver_addr() -> anyhow::Result<SocketAddrV4> {
-    // TODO: Need to run a command or something to determine this dynamically.
-    // TODO: I ran scutil --dns
-    Ok(SocketAddrV4::new("192.168.50.1".parse()?, UDP_PORT))
+use crate::config::{self, UpstreamConfig};
+use crate::edns;
+use crate::exchange::Exchange;
+use crate::message::{Header, Message};
+use crate::rank::Rankings;
+use crate::transport::Transport;
+use crate::{metrics, querylog};
+use serde::Serialize;
+use std::future::{self, Future};
+use std::io;
+use std::net::{self as std_net, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time;
+use tracing::{debug, info};
+
+/// The EDNS UDP payload sizes advertised to upstreams, largest first. A path that drops
+/// fragments loses large responses, so upstreams that stop answering are moved down the list.
+/// 1232 avoids fragmentation on nearly every path, and 512 is what DNS allows without EDNS.
+pub const UDP_PAYLOAD_SIZES: [u16; 3] = [4096, edns::DEFAULT_UDP_PAYLOAD_SIZE, 512];
+
+/// How often an upstream that was moved to a smaller payload size gets a query at the next
+/// size up, in case the path has changed.
+pub const PAYLOAD_SIZE_REPROBE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The number of times a query is sent to an upstream that doesn't answer before moving on
+/// to the next, each time waiting twice as long for an answer as the time before.
+pub const DEFAULT_ATTEMPTS: u32 = 2;
+
+/// The longest an exchange may take, however many upstreams and attempts are left.
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(10);
+
+/// The number of timeouts in a row at a payload size before moving to the next size down.
+const TIMEOUTS_BEFORE_LOWERING: u32 = 2;
+
+/// The upstreams built from the process-wide configuration, kept so what's learned about
+/// them (RTTs, payload sizes) carries over between queries.
+static FORWARDING: LazyLock<Mutex<Option<Forwarding>>> = LazyLock::new(|| Mutex::new(None));
+
+/// The upstreams [forward] sends to, and the configuration they were built from.
+type Forwarding = (UpstreamConfig, Arc<Upstreams>);
+
+/// Sends query to the configured nameservers, as [Upstreams::exchange] does. See
+/// [config::upstreams].
+pub async fn forward(query: &Message) -> anyhow::Result<Message> {
+    let upstreams = {
+        let config = config::upstreams()?;
+        let mut forwarding = FORWARDING.lock().unwrap();
+        match &*forwarding {
+            Some((current, upstreams)) if *current == config => upstreams.clone(),
+            _ => {
+                let upstreams = Arc::new(config.upstreams());
+                *forwarding = Some((config, upstreams.clone()));
+                upstreams
+            }
+        }
+    };
+    let (upstream, response) = upstreams.exchange(query).await?;
+    querylog::record_upstream(upstream);
+    Ok(response)
+}
+
+/// The stats of the upstreams [forward] sends to, in the configured order; none until it's
+/// sent a query.
+pub fn forwarding_stats() -> Vec<(SocketAddr, UpstreamStats)> {
+    match &*FORWARDING.lock().unwrap() {
+        Some((_, upstreams)) => upstreams.stats(),
+        None => Vec::new(),
+    }
+}
+
+/// The tries at binding a random port from a range before giving up, in case some are in use.
+const BIND_ATTEMPTS: usize = 16;
+
+/// The local ports UDP queries are sent from.
+///
+/// Sending each query from a port picked at random means a forged response has to guess the
+/// port as well as the query ID to be accepted (RFC 5452 section 4.5). By default, the OS
+/// picks an ephemeral port, which current OSes randomize; a range pins the ports to those a
+/// firewall lets through, and ports in it are picked at random.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourcePorts {
+    range: Option<RangeInclusive<u16>>,
+}
+
+impl SourcePorts {
+    /// Ephemeral ports picked by the OS.
+    pub fn ephemeral() -> Self {
+        Self::default()
+    }
+
+    /// Ports in first..=last, picked at random.
+    pub fn range(first: u16, last: u16) -> anyhow::Result<Self> {
+        if first == 0 || first > last {
+            anyhow::bail!("invalid source port range {first}-{last}");
+        }
+        Ok(SourcePorts {
+            range: Some(first..=last),
+        })
+    }
+
+    /// Parses a range of the form `<first>-<last>`.
+    pub fn parse(range: &str) -> anyhow::Result<Self> {
+        let (first, last) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("expected <first>-<last>, found {range}"))?;
+        let port = |port: &str| {
+            port.parse::<u16>()
+                .map_err(|e| anyhow::anyhow!("invalid port {port}: {e}"))
+        };
+        Self::range(port(first)?, port(last)?)
+    }
+
+    /// Binds a UDP socket to send to server from a port picked from these.
+    pub fn bind_udp(&self, server: SocketAddr) -> io::Result<tokio::net::UdpSocket> {
+        let ip = match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let sock = match &self.range {
+            None => std_net::UdpSocket::bind(SocketAddr::new(ip, 0))?,
+            Some(range) => {
+                let mut bound = Err(io::Error::other("no source port tried"));
+                for _ in 0..BIND_ATTEMPTS {
+                    let port = rand::random_range(range.clone());
+                    bound = std_net::UdpSocket::bind(SocketAddr::new(ip, port));
+                    if bound.is_ok() {
+                        break;
+                    }
+                }
+                bound?
+            }
+        };
+        sock.set_nonblocking(true)?;
+        tokio::net::UdpSocket::from_std(sock)
+    }
+}
+
+/// Fetches the response to query from upstream over TCP, as is done after a truncated UDP
+/// response.
+pub async fn exchange_tcp(upstream: SocketAddr, query: &Message) -> anyhow::Result<Message> {
+    Upstreams::exchange_tcp(upstream, query)
+        .await
+        .map_err(|failure| match failure {
+            Failure::Timeout => anyhow::anyhow!("timed out"),
+            Failure::Unreachable(e) => e.into(),
+            Failure::Error(e) => e,
+        })
+}
+
+/// Returns true if buf holds the response to query with the TC bit set, meaning the full
+/// response didn't fit in a datagram and has to be fetched over TCP (RFC 7766 section 5).
+fn is_truncated_response(query: &Message, buf: &[u8]) -> bool {
+    Header::peek(buf).is_ok_and(|header| {
+        header.is_response() && header.id() == query.id() && header.is_truncated()
+    })
+}
+
+fn check_tcp_response(query: &Message, response: Message) -> anyhow::Result<Message> {
+    if !response.is_response_to(query) {
+        anyhow::bail!("TCP response doesn't match the query");
+    }
+    Ok(response)
+}
+
+/// Counts of how the queries sent to one upstream turned out.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct UpstreamStats {
+    /// Queries sent, including those cancelled because another upstream answered first.
+    pub queries: u64,
+    pub answered: u64,
+    pub timeouts: u64,
+    /// Queries failed by an ICMP port, host, or network unreachable error.
+    pub unreachable: u64,
+    pub errors: u64,
+    /// Queries whose UDP response was truncated, so they were retried over TCP.
+    pub tcp_fallbacks: u64,
+}
+
+/// The nameservers queries are sent to over UDP, tried fastest first; see [Rankings]. A
+/// truncated response is fetched again over TCP from the same upstream.
+///
+/// Each upstream is given up to its timeout to answer, after which the query is sent again
+/// and given twice as long, up to the number of attempts. An upstream the OS reports as
+/// unreachable is given up on at once, since no answer is coming. However many upstreams are
+/// left, the exchange fails once its deadline passes.
+///
+/// Queries carrying an OPT record advertise the UDP payload size that's been working for each
+/// upstream; see [UDP_PAYLOAD_SIZES].
+///
+/// Given an [Exchange], queries are sent through it instead, with the same ranking, retries and
+/// deadline; see [Upstreams::with_exchange].
+///
+/// Query names can also be sent in random case, with responses that don't echo the case
+/// dropped as forged; see [Upstreams::with_case_randomization].
+pub struct Upstreams {
+    addrs: Vec<SocketAddr>,
+    timeouts: Vec<Duration>,
+    stats: Mutex<Vec<UpstreamStats>>,
+    payload_sizes: Mutex<Vec<PayloadSize>>,
+    rankings: Rankings,
+    stagger: Option<Duration>,
+    attempts: u32,
+    deadline: Duration,
+    source_ports: SourcePorts,
+    exchange: Option<Arc<dyn Exchange>>,
+    randomize_case: bool,
+}
+
+/// Which UDP payload size works with one upstream.
+#[derive(Clone, Debug, PartialEq)]
+struct PayloadSize {
+    /// The index into UDP_PAYLOAD_SIZES of the size in use.
+    level: usize,
+    /// The timeouts in a row at the size in use.
+    timeouts: u32,
+    /// When to next try the size above the one in use, if there is one.
+    reprobe_at: Option<Instant>,
+}
+
+impl PayloadSize {
+    fn new() -> Self {
+        PayloadSize {
+            level: 0,
+            timeouts: 0,
+            reprobe_at: None,
+        }
+    }
+
+    fn current(&self) -> u16 {
+        UDP_PAYLOAD_SIZES[self.level]
+    }
+
+    /// The size to advertise in the next query: the one in use, or the next size up if it's
+    /// time to reprobe. Only one query at a time probes.
+    fn next_advertised(&mut self, now: Instant) -> u16 {
+        match self.reprobe_at {
+            Some(at) if now >= at => {
+                self.reprobe_at = Some(now + PAYLOAD_SIZE_REPROBE_INTERVAL);
+                UDP_PAYLOAD_SIZES[self.level - 1]
+            }
+            _ => self.current(),
+        }
+    }
+
+    fn answered(&mut self, advertised: u16, now: Instant) {
+        if advertised > self.current() {
+            self.level -= 1;
+            self.reprobe_at = (self.level > 0).then(|| now + PAYLOAD_SIZE_REPROBE_INTERVAL);
+        }
+        self.timeouts = 0;
+    }
+
+    /// Returns true if the size in use was lowered.
+    fn timed_out(&mut self, advertised: u16, now: Instant) -> bool {
+        // * A probe that times out just waits for the next reprobe.
+        if advertised != self.current() || self.level == UDP_PAYLOAD_SIZES.len() - 1 {
+            return false;
+        }
+        self.timeouts += 1;
+        if self.timeouts < TIMEOUTS_BEFORE_LOWERING {
+            return false;
+        }
+        self.level += 1;
+        self.timeouts = 0;
+        self.reprobe_at = Some(now + PAYLOAD_SIZE_REPROBE_INTERVAL);
+        true
+    }
+}
+
+enum Failure {
+    Timeout,
+    Unreachable(io::Error),
+    Error(anyhow::Error),
+}
+
+impl Upstreams {
+    pub fn new(addrs: Vec<SocketAddr>, timeout: Duration) -> Self {
+        Self::with_timeouts(addrs.into_iter().map(|addr| (addr, timeout)).collect())
+    }
+
+    /// Upstreams each given its own timeout.
+    pub fn with_timeouts(servers: Vec<(SocketAddr, Duration)>) -> Self {
+        let (addrs, timeouts): (Vec<_>, Vec<_>) = servers.into_iter().unzip();
+        let stats = Mutex::new(vec![UpstreamStats::default(); addrs.len()]);
+        let payload_sizes = Mutex::new(vec![PayloadSize::new(); addrs.len()]);
+        Upstreams {
+            addrs,
+            timeouts,
+            stats,
+            payload_sizes,
+            rankings: Rankings::new(),
+            stagger: None,
+            attempts: DEFAULT_ATTEMPTS,
+            deadline: DEFAULT_DEADLINE,
+            source_ports: SourcePorts::ephemeral(),
+            exchange: None,
+            randomize_case: false,
+        }
+    }
+
+    /// Sends each query through exchange rather than over UDP from a socket of its own, e.g. to
+    /// use another transport, or a fake nameserver in tests.
+    ///
+    /// The exchange picks its own transport, so no UDP payload sizes are tracked.
+    pub fn with_exchange(mut self, exchange: Arc<dyn Exchange>) -> Self {
+        self.exchange = Some(exchange);
+        self
+    }
+
+    /// Sends each query from a new socket bound to one of source_ports.
+    pub fn with_source_ports(mut self, source_ports: SourcePorts) -> Self {
+        self.source_ports = source_ports;
+        self
+    }
+
+    /// Sends each query to an upstream up to attempts times before moving on to the next.
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Fails exchanges that haven't been answered by deadline.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Races the upstreams rather than waiting for each to answer or time out before trying
+    /// the next: another is queried every stagger until one answers. That trades extra
+    /// queries for less waiting on one that's slow or dropped the query.
+    pub fn with_stagger(mut self, stagger: Duration) -> Self {
+        self.stagger = Some(stagger);
+        self
+    }
+
+    /// Sends each query with the letters of its name in random case, accepting only responses
+    /// that echo the name in the same case (draft-vixie-dnsext-dns0x20). A forged response
+    /// then has to guess a bit for each letter on top of the ID and port. Responses are
+    /// passed on with the name back in the case it was asked in.
+    ///
+    /// Only for upstreams known to copy the name into their responses as it was sent, which
+    /// nearly all do; those that don't would never be answered. Responses fetched over TCP
+    /// aren't checked, as forging one means guessing the connection's sequence numbers.
+    pub fn with_case_randomization(mut self) -> Self {
+        self.randomize_case = true;
+        self
+    }
+
+    /// Sends query to each upstream in turn, fastest first, until one answers, returning
+    /// the upstream that answered along with its response.
+    ///
+    /// With a stagger (see [Upstreams::with_stagger]), the next upstream is queried whenever
+    /// the stagger passes without an answer, without giving up on those already queried, and
+    /// the first answer wins. The queries still outstanding then are cancelled.
+    pub async fn exchange(&self, query: &Message) -> anyhow::Result<(SocketAddr, Message)> {
+        let randomized = self.randomize_case.then(|| query.with_random_case());
+        let sent = randomized.as_ref().unwrap_or(query);
+        let (upstream, mut response) = time::timeout(self.deadline, self.exchange_any(sent))
+            .await
+            .unwrap_or_else(|_| {
+                Err(anyhow::anyhow!(
+                    "no upstream answered within {}ms",
+                    self.deadline.as_millis()
+                ))
+            })?;
+        if randomized.is_some() {
+            response.restore_case(query);
+        }
+        Ok((upstream, response))
+    }
+
+    /// Returns true if response answers query, and echoes its case if that was randomized.
+    fn accepts(&self, query: &Message, response: &Message) -> bool {
+        response.is_response_to(query) && (!self.randomize_case || response.echoes_case_of(query))
+    }
+
+    async fn exchange_any(&self, query: &Message) -> anyhow::Result<(SocketAddr, Message)> {
+        let bytes = query.serialize()?;
+        let mut order = self.rankings.order(&self.addrs, Instant::now()).into_iter();
+        let mut last_failure = anyhow::anyhow!("no upstreams configured");
+        let Some(stagger) = self.stagger else {
+            for idx in order {
+                match self.exchange_with(idx, query, &bytes).await {
+                    Ok(answer) => return Ok(answer),
+                    Err(e) => last_failure = e,
+                }
+            }
+            return Err(last_failure);
+        };
+
+        enum Event<T> {
+            Finished(T),
+            Staggered,
+        }
+        let mut racing = Vec::new();
+        let next_start = time::sleep(Duration::ZERO);
+        tokio::pin!(next_start);
+        loop {
+            // * When every upstream queried so far has failed, the next goes at once.
+            if racing.is_empty() {
+                let Some(idx) = order.next() else {
+                    return Err(last_failure);
+                };
+                racing.push(Box::pin(self.exchange_with(idx, query, &bytes)));
+                next_start.as_mut().reset(time::Instant::now() + stagger);
+            }
+            let event = tokio::select! {
+                result = first_of(&mut racing) => Event::Finished(result),
+                () = &mut next_start, if !order.as_slice().is_empty() => Event::Staggered,
+            };
+            match event {
+                // * Dropping the racing futures cancels the losing queries.
+                Event::Finished(Ok(answer)) => return Ok(answer),
+                Event::Finished(Err(e)) => last_failure = e,
+                Event::Staggered => {
+                    let idx = order
+                        .next()
+                        .expect("only staggered while upstreams are left");
+                    debug!("No answer yet, also querying upstream {}", self.addrs[idx]);
+                    racing.push(Box::pin(self.exchange_with(idx, query, &bytes)));
+                    next_start.as_mut().reset(time::Instant::now() + stagger);
+                }
+            }
+        }
+    }
+
+    /// Sends query, serialized as bytes, to the upstream at idx.
+    async fn exchange_with(
+        &self,
+        idx: usize,
+        query: &Message,
+        bytes: &[u8],
+    ) -> anyhow::Result<(SocketAddr, Message)> {
+        let upstream = self.addrs[idx];
+        let started = Instant::now();
+        if let Some(exchange) = &self.exchange {
+            self.rankings.queried(upstream, started);
+            self.stats.lock().unwrap()[idx].queries += 1;
+            let result = self.exchange_via(exchange.as_ref(), idx, query).await;
+            return self.record(idx, started, result.map(|response| (response, None)));
+        }
+        let mut resized = None;
+        let advertised = query.edns().map(|edns| {
+            let size = self.payload_sizes.lock().unwrap()[idx].next_advertised(Instant::now());
+            if size != edns.udp_payload_size {
+                let mut query = query.clone();
+                query.set_edns(Some(edns::Edns {
+                    udp_payload_size: size,
+                    ..edns.clone()
+                }));
+                resized = Some(query.serialize());
+            }
+            size
+        });
+        let resized = resized.transpose()?;
+        let sent = resized.as_deref().unwrap_or(bytes);
+        let buf_len = advertised.map_or(512, |size| size.max(512)) as usize;
+        self.rankings.queried(upstream, started);
+        self.stats.lock().unwrap()[idx].queries += 1;
+        let result = self.exchange_one(idx, query, sent, buf_len).await;
+        if let Some(advertised) = advertised {
+            let mut payload_sizes = self.payload_sizes.lock().unwrap();
+            let payload_size = &mut payload_sizes[idx];
+            match &result {
+                Ok(_) => payload_size.answered(advertised, Instant::now()),
+                Err(Failure::Timeout) if payload_size.timed_out(advertised, Instant::now()) => {
+                    info!(
+                        "Lowered the UDP payload size for upstream {upstream} to {}",
+                        payload_size.current()
+                    );
+                }
+                Err(_) => {}
+            }
+        }
+        let result = result.map(|(response, transport)| (response, Some(transport)));
+        self.record(idx, started, result)
+    }
+
+    /// Ranks the upstream at idx and counts the query in its stats by how the query started at
+    /// started turned out, given the transport it was answered over if it's known.
+    fn record(
+        &self,
+        idx: usize,
+        started: Instant,
+        result: Result<(Message, Option<Transport>), Failure>,
+    ) -> anyhow::Result<(SocketAddr, Message)> {
+        let upstream = self.addrs[idx];
+        match &result {
+            Ok(_) => {
+                self.rankings.answered(upstream, started.elapsed());
+                metrics::global().observe_rtt(upstream, started.elapsed());
+            }
+            Err(Failure::Timeout | Failure::Unreachable(_)) => {
+                self.rankings.failed(upstream, self.timeouts[idx])
+            }
+            Err(Failure::Error(_)) => {}
+        }
+        let mut stats = self.stats.lock().unwrap();
+        let stats = &mut stats[idx];
+        match result {
+            Ok((response, transport)) => {
+                stats.answered += 1;
+                if transport == Some(Transport::Tcp) {
+                    stats.tcp_fallbacks += 1;
+                }
+                Ok((upstream, response))
+            }
+            Err(Failure::Timeout) => {
+                stats.timeouts += 1;
+                metrics::global().count_timeout(upstream);
+                debug!("Upstream {upstream} timed out");
+                Err(anyhow::anyhow!("querying {upstream}: timed out"))
+            }
+            Err(Failure::Unreachable(e)) => {
+                stats.unreachable += 1;
+                debug!("Upstream {upstream} is unreachable: {e}");
+                Err(anyhow::anyhow!("querying {upstream}: {e}"))
+            }
+            Err(Failure::Error(e)) => {
+                stats.errors += 1;
+                debug!("Query to upstream {upstream} failed: {e}");
+                Err(e.context(format!("querying {upstream}")))
+            }
+        }
+    }
+
+    /// Sends query to the upstream at idx through exchange, sending it again each time the wait
+    /// for an answer times out, as [Upstreams::exchange_one] does.
+    async fn exchange_via(
+        &self,
+        exchange: &dyn Exchange,
+        idx: usize,
+        query: &Message,
+    ) -> Result<Message, Failure> {
+        let upstream = self.addrs[idx];
+        let mut wait = self.timeouts[idx];
+        for attempt in 1..=self.attempts {
+            if attempt > 1 {
+                debug!("Upstream {upstream} didn't answer, sending the query again");
+            }
+            match time::timeout(wait, exchange.exchange(upstream, query)).await {
+                Ok(Ok(response)) if self.accepts(query, &response) => return Ok(response),
+                Ok(Ok(_)) => {
+                    return Err(Failure::Error(anyhow::anyhow!(
+                        "response doesn't match the query"
+                    )))
+                }
+                Ok(Err(e)) => {
+                    return Err(match e.downcast::<io::Error>() {
+                        Ok(e) => classify_io_error(e),
+                        Err(e) => Failure::Error(e),
+                    })
+                }
+                Err(_) => wait *= 2,
+            }
+        }
+        Err(Failure::Timeout)
+    }
+
+    /// Sends bytes to the upstream at idx from a socket of its own, resending them each time
+    /// the wait for an answer times out, up to the number of attempts in all. The first wait
+    /// is the upstream's timeout, and each after is twice as long.
+    async fn exchange_one(
+        &self,
+        idx: usize,
+        query: &Message,
+        bytes: &[u8],
+        buf_len: usize,
+    ) -> Result<(Message, Transport), Failure> {
+        let upstream = self.addrs[idx];
+        let sock = self
+            .source_ports
+            .bind_udp(upstream)
+            .map_err(|e| Failure::Error(e.into()))?;
+        // * The socket has to be connected for the OS to report ICMP errors on it.
+        sock.connect(upstream).await.map_err(classify_io_error)?;
+        let mut buf = vec![0_u8; buf_len];
+        let mut wait = self.timeouts[idx];
+        for attempt in 1..=self.attempts {
+            if attempt > 1 {
+                debug!("Upstream {upstream} didn't answer, retransmitting");
+            }
+            sock.send(bytes).await.map_err(classify_io_error)?;
+            // * A late answer to an earlier attempt is as good as one to this attempt, since
+            // * they're sent from the same socket with the same ID.
+            match time::timeout(wait, self.recv_response(&sock, upstream, query, &mut buf)).await {
+                Ok(result) => return result,
+                Err(_) => wait *= 2,
+            }
+        }
+        Err(Failure::Timeout)
+    }
+
+    async fn recv_response(
+        &self,
+        sock: &tokio::net::UdpSocket,
+        upstream: SocketAddr,
+        query: &Message,
+        buf: &mut [u8],
+    ) -> Result<(Message, Transport), Failure> {
+        loop {
+            let size = sock.recv(buf).await.map_err(classify_io_error)?;
+            if is_truncated_response(query, &buf[..size]) {
+                debug!("Upstream {upstream} truncated its response, retrying over TCP");
+                let response = Self::exchange_tcp(upstream, query).await?;
+                return Ok((response, Transport::Tcp));
+            }
+            // * Ignore stray and forged datagrams; the timeout still bounds the wait.
+            match Message::parse(&buf[..size]) {
+                Ok(response) if self.accepts(query, &response) => {
+                    return Ok((response, Transport::Udp))
+                }
+                Ok(_) => debug!("Dropped a response from {upstream} that doesn't match the query"),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    async fn exchange_tcp(upstream: SocketAddr, query: &Message) -> Result<Message, Failure> {
+        let framed = query.serialize_framed().map_err(Failure::Error)?;
+        let mut stream = tokio::net::TcpStream::connect(upstream)
+            .await
+            .map_err(classify_io_error)?;
+        stream.write_all(&framed).await.map_err(classify_io_error)?;
+        let len = stream.read_u16().await.map_err(classify_io_error)?;
+        let mut buf = vec![0_u8; len as usize];
+        stream
+            .read_exact(&mut buf)
+            .await
+            .map_err(classify_io_error)?;
+        Message::parse(&buf)
+            .and_then(|response| check_tcp_response(query, response))
+            .map_err(Failure::Error)
+    }
+
+    /// The stats for each upstream, in the configured order.
+    pub fn stats(&self) -> Vec<(SocketAddr, UpstreamStats)> {
+        let stats = self.stats.lock().unwrap();
+        self.addrs
+            .iter()
+            .copied()
+            .zip(stats.iter().cloned())
+            .collect()
+    }
+
+    /// The UDP payload size in use for each upstream, in the configured order.
+    pub fn payload_sizes(&self) -> Vec<(SocketAddr, u16)> {
+        let payload_sizes = self.payload_sizes.lock().unwrap();
+        self.addrs
+            .iter()
+            .copied()
+            .zip(payload_sizes.iter().map(PayloadSize::current))
+            .collect()
+    }
+
+    /// The smoothed RTT of each upstream, in the configured order, or None for those not
+    /// yet queried.
+    pub fn srtts(&self) -> Vec<(SocketAddr, Option<Duration>)> {
+        self.addrs
+            .iter()
+            .map(|&addr| (addr, self.rankings.srtt(addr)))
+            .collect()
+    }
+}
+
+/// Waits for the first of futures to finish, removing it from futures.
+async fn first_of<F: Future + Unpin>(futures: &mut Vec<F>) -> F::Output {
+    future::poll_fn(|cx| {
+        for idx in 0..futures.len() {
+            if let Poll::Ready(output) = Pin::new(&mut futures[idx]).poll(cx) {
+                futures.swap_remove(idx);
+                return Poll::Ready(output);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+fn classify_io_error(e: io::Error) -> Failure {
+    match e.kind() {
+        // * An ICMP port unreachable is reported as the connection being refused.
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::HostUnreachable
+        | io::ErrorKind::NetworkUnreachable => Failure::Unreachable(e),
+        _ => Failure::Error(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{self, ResponseCode};
+    use crate::rr;
+    use std::time::Instant;
+
+    /// Answers every query with an empty NOERROR response.
+    async fn fake_upstream() -> anyhow::Result<SocketAddr> {
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn unreachable_fails_over_at_once() -> anyhow::Result<()> {
+        // * Nothing listens on a socket that's been dropped, so the OS answers with ICMP
+        // * port unreachable.
+        let down = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await?
+            .local_addr()?;
+        let up = fake_upstream().await?;
+        let upstreams = Upstreams::new(vec![down, up], Duration::from_secs(5));
+
+        let started = Instant::now();
+        let query = message::address_query(&"example.com.".parse()?);
+        let (answered_by, response) = upstreams.exchange(&query).await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(answered_by, up);
+        assert_eq!(response.id(), query.id());
+
+        let stats = upstreams.stats();
+        assert_eq!(
+            stats[0].1,
+            UpstreamStats {
+                queries: 1,
+                unreachable: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats[1].1.answered, 1);
+
+        // * The unreachable upstream is ranked last, so the next query skips it.
+        upstreams.exchange(&query).await?;
+        let stats = upstreams.stats();
+        assert_eq!(stats[0].1.queries, 1);
+        assert_eq!(stats[1].1.answered, 2);
+        let srtts = upstreams.srtts();
+        assert!(srtts[1].1.unwrap() < srtts[0].1.unwrap());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn staggered_queries_race() -> anyhow::Result<()> {
+        // * Answers, but only after the test would have given up on it.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let slow = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                time::sleep(Duration::from_secs(5)).await;
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+        let fast = fake_upstream().await?;
+        let upstreams = Upstreams::new(vec![slow, fast], Duration::from_secs(10))
+            .with_stagger(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let query = message::address_query(&"example.com.".parse()?);
+        let (answered_by, _) = upstreams.exchange(&query).await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(answered_by, fast);
+
+        // * The slow upstream's query was cancelled, not counted as a timeout.
+        let stats = upstreams.stats();
+        assert_eq!(
+            stats[0].1,
+            UpstreamStats {
+                queries: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stats[1].1.answered, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn staggered_failure_tries_next_at_once() -> anyhow::Result<()> {
+        let down = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await?
+            .local_addr()?;
+        let up = fake_upstream().await?;
+        let upstreams = Upstreams::new(vec![down, up], Duration::from_secs(5))
+            .with_stagger(Duration::from_secs(5));
+
+        let started = Instant::now();
+        let query = message::address_query(&"example.com.".parse()?);
+        let (answered_by, _) = upstreams.exchange(&query).await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(answered_by, up);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retransmits_with_backoff() -> anyhow::Result<()> {
+        // * Drops the first copy of each query and answers the second.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            let mut seen = Vec::new();
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                if !seen.contains(&query.id()) {
+                    seen.push(query.id());
+                    continue;
+                }
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+
+        let query = message::address_query(&"example.com.".parse()?);
+        let once = Upstreams::new(vec![upstream], Duration::from_millis(50)).with_attempts(1);
+        assert!(once.exchange(&query).await.is_err());
+        assert_eq!(once.stats()[0].1.timeouts, 1);
+
+        let query = message::address_query(&"example.com.".parse()?);
+        let upstreams = Upstreams::new(vec![upstream], Duration::from_millis(50));
+        let (_, response) = upstreams.exchange(&query).await?;
+        assert_eq!(response.id(), query.id());
+        assert_eq!(
+            upstreams.stats()[0].1,
+            UpstreamStats {
+                queries: 1,
+                answered: 1,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_at_deadline() -> anyhow::Result<()> {
+        // * Never answers.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        let upstreams = Upstreams::new(vec![upstream], Duration::from_secs(5))
+            .with_deadline(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let query = message::address_query(&"example.com.".parse()?);
+        let e = upstreams.exchange(&query).await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(e.to_string(), "no upstream answered within 100ms");
+        drop(sock);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drops_forged_responses() -> anyhow::Result<()> {
+        // * Answers each query first with a response to another question under the same ID,
+        // * as a forger guessing the ID would.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                let mut forged = message::address_query(&"bank.example.".parse().unwrap());
+                forged.set_id(query.id());
+                let forged = forged.empty_response(ResponseCode::NameError);
+                let _ = sock.send_to(&forged.serialize().unwrap(), from).await;
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+
+        let upstreams = Upstreams::new(vec![upstream], Duration::from_secs(5));
+        let query = message::address_query(&"example.com.".parse()?);
+        let (_, response) = upstreams.exchange(&query).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.questions(), query.questions());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drops_responses_in_another_case() -> anyhow::Result<()> {
+        // * Answers each query first with the name lowercased, as a forger who guessed the ID
+        // * but not the case would.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                let lowered = query.questions()[0].name().to_string().to_ascii_lowercase();
+                let mut forged = message::address_query(&lowered.parse().unwrap());
+                forged.set_id(query.id());
+                let forged = forged.empty_response(ResponseCode::NameError);
+                let _ = sock.send_to(&forged.serialize().unwrap(), from).await;
+                let response = query.empty_response(ResponseCode::NoError);
+                let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+            }
+        });
+
+        let upstreams =
+            Upstreams::new(vec![upstream], Duration::from_secs(5)).with_case_randomization();
+        let query = message::address_query(&"abcdefghijklmnopqrstuvwxyz.example.".parse()?);
+        let (_, response) = upstreams.exchange(&query).await?;
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        // * Passed on in the case it was asked in.
+        assert!(response.echoes_case_of(&query));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn all_unreachable() -> anyhow::Result<()> {
+        let down = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await?
+            .local_addr()?;
+        let upstreams = Upstreams::new(vec![down], Duration::from_secs(5));
+        let query = message::address_query(&"example.com.".parse()?);
+        assert!(upstreams.exchange(&query).await.is_err());
+        assert_eq!(upstreams.stats()[0].1.unreachable, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn payload_size_lowers_and_reprobes() {
+        let start = Instant::now();
+        let mut size = PayloadSize::new();
+        assert_eq!(size.next_advertised(start), 4096);
+
+        // * One timeout could be packet loss; two in a row at the same size lower it.
+        assert!(!size.timed_out(4096, start));
+        size.answered(4096, start);
+        assert!(!size.timed_out(4096, start));
+        assert!(size.timed_out(4096, start));
+        assert_eq!(size.next_advertised(start), 1232);
+
+        // * A failed reprobe keeps the lower size until the next one.
+        let reprobe = start + PAYLOAD_SIZE_REPROBE_INTERVAL;
+        assert_eq!(size.next_advertised(reprobe), 4096);
+        assert_eq!(size.next_advertised(reprobe), 1232);
+        assert!(!size.timed_out(4096, reprobe));
+        assert_eq!(size.current(), 1232);
+
+        let reprobe = reprobe + PAYLOAD_SIZE_REPROBE_INTERVAL;
+        assert_eq!(size.next_advertised(reprobe), 4096);
+        size.answered(4096, reprobe);
+        assert_eq!(size.current(), 4096);
+        assert_eq!(size.reprobe_at, None);
+
+        // * 512 is the floor.
+        for _ in 0..6 {
+            size.timed_out(size.current(), reprobe);
+        }
+        assert_eq!(size.current(), 512);
+    }
+
+    #[tokio::test]
+    async fn lowers_payload_size_when_large_responses_are_lost() -> anyhow::Result<()> {
+        // * Stands in for a path that drops fragments: only answers queries advertising a
+        // * payload size that wouldn't need them.
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                if query.edns().unwrap().udp_payload_size <= edns::DEFAULT_UDP_PAYLOAD_SIZE {
+                    let response = query.empty_response(ResponseCode::NoError);
+                    let _ = sock.send_to(&response.serialize().unwrap(), from).await;
+                }
+            }
+        });
+        let upstreams = Upstreams::new(vec![upstream], Duration::from_millis(100));
+        let query = || {
+            let mut query = message::address_query(&"example.com.".parse().unwrap());
+            query.set_edns(Some(edns::Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE)));
+            query
+        };
+
+        assert!(upstreams.exchange(&query()).await.is_err());
+        assert!(upstreams.exchange(&query()).await.is_err());
+        assert_eq!(upstreams.payload_sizes(), [(upstream, 1232)]);
+        upstreams.exchange(&query()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_truncated_responses_over_tcp() -> anyhow::Result<()> {
+        let sock = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+        let upstream = sock.local_addr()?;
+        let listener = tokio::net::TcpListener::bind(upstream).await?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 512];
+            while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+                let Ok(query) = Message::parse(&buf[..size]) else {
+                    continue;
+                };
+                let response = query.empty_response(ResponseCode::NoError);
+                let mut truncated = response.serialize().unwrap();
+                // * TC is bit 9 of the flags, which start at byte 2.
+                truncated[2] |= 0x02;
+                let _ = sock.send_to(&truncated, from).await;
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let len = stream.read_u16().await.unwrap();
+                let mut buf = vec![0_u8; len as usize];
+                stream.read_exact(&mut buf).await.unwrap();
+                let query = Message::parse(&buf).unwrap();
+                let answer = rr::ResourceRecord::new(
+                    "example.com.".parse().unwrap(),
+                    rr::Type::A,
+                    rr::Class::IN,
+                    300,
+                    rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+                )
+                .unwrap();
+                let response = query.answer_response(vec![answer]);
+                let _ = stream
+                    .write_all(&response.serialize_framed().unwrap())
+                    .await;
+            }
+        });
+
+        let upstreams = Upstreams::new(vec![upstream], Duration::from_secs(5));
+        let query = message::address_query(&"example.com.".parse()?);
+        let (_, response) = upstreams.exchange(&query).await?;
+        assert_eq!(response.answers().len(), 1);
+        let stats = upstreams.stats();
+        assert_eq!(stats[0].1.answered, 1);
+        assert_eq!(stats[0].1.tcp_fallbacks, 1);
+        Ok(())
+    }
+
+    /// Refuses queries to down, ignores those to silent, and answers the rest, counting the
+    /// queries each server gets.
+    struct FakeUpstreams {
+        down: SocketAddr,
+        silent: SocketAddr,
+        queries: Mutex<Vec<SocketAddr>>,
+    }
+
+    impl Exchange for FakeUpstreams {
+        fn exchange<'a>(
+            &'a self,
+            server: SocketAddr,
+            query: &'a Message,
+        ) -> crate::resolve::BoxFuture<'a, anyhow::Result<Message>> {
+            self.queries.lock().unwrap().push(server);
+            Box::pin(async move {
+                if server == self.down {
+                    Err(io::Error::from(io::ErrorKind::ConnectionRefused).into())
+                } else if server == self.silent {
+                    future::pending().await
+                } else {
+                    Ok(query.empty_response(ResponseCode::NoError))
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_through_trait() -> anyhow::Result<()> {
+        let [down, silent, up]: [SocketAddr; 3] = [
+            "192.0.2.1:53".parse()?,
+            "192.0.2.2:53".parse()?,
+            "192.0.2.3:53".parse()?,
+        ];
+        let fake = Arc::new(FakeUpstreams {
+            down,
+            silent,
+            queries: Mutex::new(Vec::new()),
+        });
+        let upstreams = Upstreams::new(vec![down, silent, up], Duration::from_millis(10))
+            .with_exchange(fake.clone());
+
+        let query = message::address_query(&"example.com.".parse()?);
+        let (answered_by, response) = upstreams.exchange(&query).await?;
+        assert_eq!(answered_by, up);
+        assert!(response.is_response_to(&query));
+        // * The refused query isn't sent again, but the ignored one is.
+        assert_eq!(*fake.queries.lock().unwrap(), [down, silent, silent, up]);
+        let stats = upstreams.stats();
+        assert_eq!(stats[0].1.unreachable, 1);
+        assert_eq!(stats[1].1.timeouts, 1);
+        assert_eq!(stats[2].1.answered, 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,334 @@
+use crate::task;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// What changed about the host's network, merged over the debounce period.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct NetworkChange {
+    /// An interface went up or down, or was added or removed.
+    pub links: bool,
+    /// An interface address was added or removed.
+    pub addresses: bool,
+    /// A route, such as the default route, changed.
+    pub routes: bool,
+}
+
+impl NetworkChange {
+    /// Everything changed, e.g. because change notifications were lost.
+    pub const ALL: NetworkChange = NetworkChange {
+        links: true,
+        addresses: true,
+        routes: true,
+    };
+
+    fn merge(&mut self, other: NetworkChange) {
+        self.links |= other.links;
+        self.addresses |= other.addresses;
+        self.routes |= other.routes;
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == NetworkChange::default()
+    }
+}
+
+/// Reports changes to the host's network, e.g. a laptop joining a different Wi-Fi network, so
+/// upstreams can be rediscovered and cached answers from the old network dropped.
+///
+/// Changes come from netlink on Linux and NotifyAddrChange/NotifyRouteChange on Windows.
+/// Joining a network touches links, addresses, and routes in quick succession, so changes
+/// are debounced into one report.
+pub struct NetworkWatcher {
+    changes: mpsc::Receiver<NetworkChange>,
+}
+
+impl NetworkWatcher {
+    pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+    /// Starts watching for changes. Must be called within a Tokio runtime.
+    pub fn start(debounce: Duration) -> anyhow::Result<Self> {
+        let (raw_tx, raw_rx) = mpsc::unbounded_channel();
+        platform::watch(raw_tx)
+            .map_err(|e| anyhow::anyhow!("watching for network changes: {e}"))?;
+        Ok(Self::debounced(raw_rx, debounce))
+    }
+
+    /// Waits for the next change, or returns None if changes can no longer be watched for.
+    pub async fn next(&mut self) -> Option<NetworkChange> {
+        self.changes.recv().await
+    }
+
+    fn debounced(mut raw: mpsc::UnboundedReceiver<NetworkChange>, debounce: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(1);
+        task::spawn_named("network watcher", async move {
+            while let Some(mut change) = raw.recv().await {
+                let settled = tokio::time::sleep(debounce);
+                tokio::pin!(settled);
+                loop {
+                    tokio::select! {
+                        _ = &mut settled => break,
+                        more = raw.recv() => match more {
+                            Some(more) => change.merge(more),
+                            None => break,
+                        },
+                    }
+                }
+                if change.is_empty() {
+                    continue;
+                }
+                if tx.send(change).await.is_err() {
+                    return;
+                }
+            }
+        });
+        NetworkWatcher { changes: rx }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::NetworkChange;
+    use std::io;
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use tokio::io::unix::AsyncFd;
+    use tokio::sync::mpsc;
+    use tracing::warn;
+
+    /// The size of a netlink message header (struct nlmsghdr).
+    const HEADER_LEN: usize = 16;
+
+    pub(super) fn watch(tx: mpsc::UnboundedSender<NetworkChange>) -> io::Result<()> {
+        let sock = AsyncFd::new(open()?)?;
+        tokio::spawn(async move {
+            let mut buf = vec![0_u8; 16 * 1024];
+            loop {
+                let mut guard = match sock.readable().await {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        warn!("Waiting for netlink messages: {e}");
+                        return;
+                    }
+                };
+                let change = match guard.try_io(|sock| recv(sock.get_ref(), &mut buf)) {
+                    Ok(Ok(len)) => parse(&buf[..len]),
+                    // * The kernel dropped messages because they weren't read fast enough, so
+                    // * assume everything changed.
+                    Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => NetworkChange::ALL,
+                    Ok(Err(e)) => {
+                        warn!("Reading netlink messages: {e}");
+                        return;
+                    }
+                    Err(_would_block) => continue,
+                };
+                if tx.send(change).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Opens a netlink socket subscribed to link, address, and route changes.
+    fn open() -> io::Result<OwnedFd> {
+        // SAFETY: socket has no memory safety requirements.
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd was just opened and nothing else owns it.
+        let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_nl is plain data, for which all zeros is valid.
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = (libc::RTMGRP_LINK
+            | libc::RTMGRP_IPV4_IFADDR
+            | libc::RTMGRP_IPV6_IFADDR
+            | libc::RTMGRP_IPV4_ROUTE
+            | libc::RTMGRP_IPV6_ROUTE) as u32;
+        // SAFETY: addr is a valid sockaddr_nl, and its size is passed along with it.
+        let ret = unsafe {
+            libc::bind(
+                sock.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sock)
+    }
+
+    fn recv(sock: &OwnedFd, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: buf is valid for writes of buf.len() bytes.
+        let len = unsafe { libc::recv(sock.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(len as usize)
+    }
+
+    /// Summarizes the netlink messages in a datagram.
+    pub(super) fn parse(buf: &[u8]) -> NetworkChange {
+        let mut change = NetworkChange::default();
+        let mut offset = 0;
+        while let Some(header) = buf.get(offset..offset + HEADER_LEN) {
+            let len = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            match u16::from_ne_bytes([header[4], header[5]]) {
+                libc::RTM_NEWLINK | libc::RTM_DELLINK => change.links = true,
+                libc::RTM_NEWADDR | libc::RTM_DELADDR => change.addresses = true,
+                libc::RTM_NEWROUTE | libc::RTM_DELROUTE => change.routes = true,
+                _ => {}
+            }
+            if len < HEADER_LEN {
+                break;
+            }
+            // * Messages are aligned to 4 bytes.
+            offset += (len + 3) & !3;
+        }
+        change
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::NetworkChange;
+    use std::io;
+    use std::ptr;
+    use std::thread;
+    use tokio::sync::mpsc;
+    use tracing::warn;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::NetworkManagement::IpHelper::{NotifyAddrChange, NotifyRouteChange};
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    type Notify = unsafe extern "system" fn(*mut HANDLE, *const OVERLAPPED) -> u32;
+
+    pub(super) fn watch(tx: mpsc::UnboundedSender<NetworkChange>) -> io::Result<()> {
+        let watches: [(Notify, NetworkChange); 2] = [
+            (
+                NotifyAddrChange,
+                NetworkChange {
+                    addresses: true,
+                    ..NetworkChange::default()
+                },
+            ),
+            (
+                NotifyRouteChange,
+                NetworkChange {
+                    routes: true,
+                    ..NetworkChange::default()
+                },
+            ),
+        ];
+        for (notify, change) in watches {
+            let tx = tx.clone();
+            thread::Builder::new()
+                .name(String::from("network watcher"))
+                .spawn(move || loop {
+                    // SAFETY: Without a handle or OVERLAPPED, this blocks until the next change.
+                    let err = unsafe { notify(ptr::null_mut(), ptr::null()) };
+                    if err != 0 {
+                        warn!(
+                            "Waiting for network changes: {}",
+                            io::Error::from_raw_os_error(err as i32)
+                        );
+                        return;
+                    }
+                    if tx.send(change).is_err() {
+                        return;
+                    }
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::NetworkChange;
+    use std::io;
+    use tokio::sync::mpsc;
+
+    pub(super) fn watch(_tx: mpsc::UnboundedSender<NetworkChange>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn debounces_changes() {
+        let debounce = Duration::from_millis(50);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher = NetworkWatcher::debounced(rx, debounce);
+        for change in [
+            NetworkChange {
+                links: true,
+                ..NetworkChange::default()
+            },
+            NetworkChange {
+                routes: true,
+                ..NetworkChange::default()
+            },
+        ] {
+            tx.send(change).unwrap();
+        }
+        assert_eq!(
+            watcher.next().await,
+            Some(NetworkChange {
+                links: true,
+                addresses: false,
+                routes: true,
+            })
+        );
+
+        // * Messages about anything else aren't reported.
+        tx.send(NetworkChange::default()).unwrap();
+        tokio::time::sleep(debounce * 2).await;
+        tx.send(NetworkChange::ALL).unwrap();
+        assert_eq!(watcher.next().await, Some(NetworkChange::ALL));
+
+        drop(tx);
+        assert_eq!(watcher.next().await, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_netlink() {
+        let message = |len: u32, kind: u16| {
+            let mut buf = len.to_ne_bytes().to_vec();
+            buf.extend(kind.to_ne_bytes());
+            buf.resize(len as usize, 0);
+            // * Pad to the 4 byte alignment.
+            buf.resize((len as usize + 3) & !3, 0);
+            buf
+        };
+        let mut buf = message(18, libc::RTM_NEWADDR);
+        buf.extend(message(20, libc::RTM_DELROUTE));
+        assert_eq!(
+            platform::parse(&buf),
+            NetworkChange {
+                links: false,
+                addresses: true,
+                routes: true,
+            }
+        );
+        assert!(platform::parse(&message(16, libc::NLMSG_DONE as u16)).is_empty());
+        // * A truncated datagram is summarized as far as it goes.
+        assert!(platform::parse(&message(16, libc::RTM_NEWLINK)[..10]).is_empty());
+    }
+}
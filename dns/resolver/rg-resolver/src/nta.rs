@@ -0,0 +1,181 @@
+use crate::name;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Negative trust anchors (RFC 7646): domains whose DNSSEC validation is skipped, for a limited
+/// time, while their operators fix broken signatures.
+///
+/// The validator should consult this before treating a name's records as bogus. Records at or
+/// below a negative trust anchor are insecure instead.
+#[derive(Debug, Default)]
+pub struct NegativeTrustAnchors {
+    /// When each anchor expires, by lowercased name.
+    anchors: HashMap<String, Instant>,
+}
+
+impl NegativeTrustAnchors {
+    /// RFC 7646 section 2 recommends anchors be removed within a week.
+    pub const MAX_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses anchors, one per line, of the form `<name> <seconds until expiry>`.
+    ///
+    /// For example:
+    ///   broken.example. 86400
+    ///
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(config: &str) -> anyhow::Result<Self> {
+        let mut anchors = Self::new();
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let [name, seconds] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                anyhow::bail!(
+                    "parsing negative trust anchors: line {}: expected <name> <seconds>",
+                    line_num + 1
+                );
+            };
+            let seconds = seconds.parse::<u64>().map_err(|e| {
+                anyhow::anyhow!("parsing negative trust anchors: line {}: {e}", line_num + 1)
+            })?;
+            anchors
+                .add(name, Duration::from_secs(seconds))
+                .map_err(|e| {
+                    anyhow::anyhow!("parsing negative trust anchors: line {}: {e}", line_num + 1)
+                })?;
+        }
+        Ok(anchors)
+    }
+
+    /// Skips validation at and below name for lifetime.
+    /// Adding an anchor that already exists replaces its expiry.
+    pub fn add(&mut self, name: &str, lifetime: Duration) -> anyhow::Result<()> {
+        if lifetime > Self::MAX_LIFETIME {
+            anyhow::bail!(
+                "negative trust anchor for {name}: lifetime {}s is longer than {}s",
+                lifetime.as_secs(),
+                Self::MAX_LIFETIME.as_secs()
+            );
+        }
+        if key(name) == "." {
+            anyhow::bail!(
+                "negative trust anchor for {name}: can't disable validation for the root"
+            );
+        }
+        self.anchors.insert(key(name), Instant::now() + lifetime);
+        Ok(())
+    }
+
+    /// Returns true if there was an anchor for name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.anchors.remove(&key(name)).is_some()
+    }
+
+    /// Returns true if validation should be skipped for name, because it's at or below an
+    /// unexpired anchor.
+    pub fn covers(&self, name: &str) -> bool {
+        let now = Instant::now();
+        let labels = name::split_labels(name);
+        (0..labels.len()).any(|start| {
+            let suffix = key(&labels[start..].join("."));
+            self.anchors
+                .get(&suffix)
+                .is_some_and(|expires_at| now < *expires_at)
+        })
+    }
+
+    /// Drops expired anchors, returning their names.
+    pub fn expire(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let expired = self
+            .anchors
+            .iter()
+            .filter(|(_, expires_at)| now >= **expires_at)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in &expired {
+            self.anchors.remove(name);
+        }
+        expired
+    }
+
+    /// The unexpired anchors and how long each has left, sorted by name.
+    pub fn list(&self) -> Vec<(String, Duration)> {
+        let now = Instant::now();
+        let mut anchors = self
+            .anchors
+            .iter()
+            .filter(|(_, expires_at)| now < **expires_at)
+            .map(|(name, expires_at)| (name.clone(), *expires_at - now))
+            .collect::<Vec<_>>();
+        anchors.sort();
+        anchors
+    }
+}
+
+/// Names are compared case-insensitively and fully qualified.
+fn key(name: &str) -> String {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    format!("{name}.")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn covers_names_below_anchor() -> anyhow::Result<()> {
+        let mut anchors = NegativeTrustAnchors::new();
+        anchors.add("Broken.Example.", DAY)?;
+        assert!(anchors.covers("broken.example."));
+        assert!(anchors.covers("www.broken.example"));
+        assert!(!anchors.covers("example."));
+        assert!(!anchors.covers("notbroken.example."));
+
+        assert!(anchors.remove("broken.example"));
+        assert!(!anchors.covers("www.broken.example."));
+        assert!(!anchors.remove("broken.example."));
+        Ok(())
+    }
+
+    #[test]
+    fn anchors_expire() -> anyhow::Result<()> {
+        let mut anchors = NegativeTrustAnchors::new();
+        anchors.add("gone.example.", Duration::ZERO)?;
+        anchors.add("kept.example.", DAY)?;
+        assert!(!anchors.covers("gone.example."));
+        assert_eq!(anchors.list().len(), 1);
+        assert_eq!(anchors.expire(), ["gone.example."]);
+        assert!(anchors.covers("kept.example."));
+        Ok(())
+    }
+
+    #[test]
+    fn parse() -> anyhow::Result<()> {
+        let anchors = NegativeTrustAnchors::parse(
+            "# Signatures expired\nbroken.example. 86400\n\nother.example 60\n",
+        )?;
+        let names = anchors
+            .list()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["broken.example.", "other.example."]);
+
+        let e = NegativeTrustAnchors::parse("broken.example.\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "parsing negative trust anchors: line 1: expected <name> <seconds>"
+        );
+        assert!(NegativeTrustAnchors::parse("broken.example. 700000\n").is_err());
+        assert!(NegativeTrustAnchors::parse(". 60\n").is_err());
+        Ok(())
+    }
+}
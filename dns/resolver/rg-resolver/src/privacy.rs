@@ -0,0 +1,129 @@
+use crate::name;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+/// The process-wide privacy settings, so they're enforced the same everywhere.
+static PRIVACY: LazyLock<Privacy> = LazyLock::new(Privacy::default);
+
+const REDACTED: &str = "<redacted>";
+
+/// Controls whether query names may be written to logs and metrics.
+///
+/// In aggregation-only mode, query names are never written, and queries are only counted
+/// per registrable domain.
+#[derive(Debug, Default)]
+pub struct Privacy {
+    aggregate_only: AtomicBool,
+    domain_counts: Mutex<HashMap<String, u64>>,
+}
+
+impl Privacy {
+    pub fn set_aggregate_only(&self, enabled: bool) {
+        self.aggregate_only.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_aggregate_only(&self) -> bool {
+        self.aggregate_only.load(Ordering::Relaxed)
+    }
+
+    /// The form of name that may be written to logs and metrics.
+    pub fn qname<'a>(&self, name: &'a str) -> &'a str {
+        if self.is_aggregate_only() {
+            REDACTED
+        } else {
+            name
+        }
+    }
+
+    /// Counts a query for name. Only counted in aggregation-only mode, where the counts
+    /// stand in for the per-query logs.
+    pub fn count_query(&self, name: &str) {
+        if !self.is_aggregate_only() {
+            return;
+        }
+        let domain = registrable_domain(name);
+        *self
+            .domain_counts
+            .lock()
+            .unwrap()
+            .entry(domain)
+            .or_default() += 1;
+    }
+
+    /// The number of queries counted for each registrable domain, most queried first.
+    pub fn domain_counts(&self) -> Vec<(String, u64)> {
+        let mut counts = self
+            .domain_counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(domain, count)| (domain.clone(), *count))
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+}
+
+/// The process-wide privacy settings.
+pub fn global() -> &'static Privacy {
+    &PRIVACY
+}
+
+/// The form of name that may be written to logs and metrics under the process-wide settings.
+///
+/// Every place that logs a query name should go through this.
+pub fn qname(name: &str) -> &str {
+    PRIVACY.qname(name)
+}
+
+/// The domain a name was registered under, approximated as its last two labels, lowercased.
+// TODO: Use the public suffix list so e.g. "example.co.uk." isn't counted as "co.uk.".
+pub fn registrable_domain(name: &str) -> String {
+    let labels = name::split_labels(name)
+        .into_iter()
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        return String::from(".");
+    }
+    let start = labels.len().saturating_sub(2);
+    let mut domain = labels[start..].join(".").to_ascii_lowercase();
+    domain.push('.');
+    domain
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn registrable_domains() {
+        assert_eq!(registrable_domain("www.Mail.Google.com."), "google.com.");
+        assert_eq!(registrable_domain("google.com"), "google.com.");
+        assert_eq!(registrable_domain("com."), "com.");
+        assert_eq!(registrable_domain("."), ".");
+        assert_eq!(registrable_domain("a.foo\\.bar.com."), "foo\\.bar.com.");
+    }
+
+    #[test]
+    fn aggregate_only() {
+        let privacy = Privacy::default();
+        assert_eq!(privacy.qname("www.google.com."), "www.google.com.");
+        privacy.count_query("www.google.com.");
+        assert!(privacy.domain_counts().is_empty());
+
+        privacy.set_aggregate_only(true);
+        assert_eq!(privacy.qname("www.google.com."), REDACTED);
+        privacy.count_query("www.google.com.");
+        privacy.count_query("mail.google.com.");
+        privacy.count_query("yahoo.com.");
+        assert_eq!(
+            privacy.domain_counts(),
+            vec![
+                (String::from("google.com."), 2),
+                (String::from("yahoo.com."), 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use crate::transport::Transport;
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Whether records have been authenticated with DNSSEC (RFC 4035 section 4.3).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum SecurityStatus {
+    /// Not validated. Every record is indeterminate until DNSSEC validation is implemented.
+    #[default]
+    Indeterminate,
+    /// Validated with an unbroken chain of trust.
+    Secure,
+    /// Proven to be in an unsigned zone.
+    Insecure,
+    /// Validation failed, so the records shouldn't be trusted.
+    Bogus,
+}
+
+impl fmt::Display for SecurityStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            SecurityStatus::Indeterminate => "indeterminate",
+            SecurityStatus::Secure => "secure",
+            SecurityStatus::Insecure => "insecure",
+            SecurityStatus::Bogus => "bogus",
+        };
+        f.write_str(status)
+    }
+}
+
+/// Where a set of records came from, kept alongside them in the cache so stale or suspicious
+/// data can be traced to its source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    /// The upstream that answered, or the name of the resolver for local sources.
+    pub source: String,
+    /// How the records were fetched, if from an upstream.
+    pub transport: Option<Transport>,
+    pub received_at: SystemTime,
+    pub security: SecurityStatus,
+}
+
+impl Provenance {
+    /// Records received now from source.
+    pub fn new(source: impl Into<String>, transport: Option<Transport>) -> Self {
+        Provenance {
+            source: source.into(),
+            transport,
+            received_at: SystemTime::now(),
+            security: SecurityStatus::default(),
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    /// Formats as e.g. `from 192.0.2.1:53 over udp at 1700000000, indeterminate`,
+    /// with the time in seconds since the Unix epoch.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let received_at = self
+            .received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        write!(f, "from {}", self.source)?;
+        if let Some(transport) = self.transport {
+            write!(f, " over {transport}")?;
+        }
+        write!(f, " at {received_at}, {}", self.security)
+    }
+}
+
+/// Where the answer to a request came from, as reported to clients.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AnswerSource {
+    /// A zone the resolver is authoritative for.
+    Authoritative,
+    /// The cache, without asking a nameserver.
+    Cache,
+    /// A nameserver.
+    Upstream,
+    /// The OS resolver, as a last resort. Its answers carry no TTLs.
+    SystemFallback,
+}
+
+tokio::task_local! {
+    static ANSWER_SOURCE: Cell<Option<AnswerSource>>;
+}
+
+/// Runs request, returning its output and where the answers its lookups found came from.
+///
+/// A request answered from several sources is reported as Upstream, since only some of it was
+/// cached or authoritative. None means no lookup found an answer. A request tracked within
+/// another counts towards the outer one too.
+pub async fn track_answer_source<F: Future>(request: F) -> (F::Output, Option<AnswerSource>) {
+    let (output, source) = ANSWER_SOURCE
+        .scope(Cell::new(None), async {
+            let output = request.await;
+            (output, ANSWER_SOURCE.with(Cell::get))
+        })
+        .await;
+    if let Some(source) = source {
+        record_answer_source(source);
+    }
+    (output, source)
+}
+
+/// Records that a lookup of the request being tracked, if any, was answered from source.
+pub fn record_answer_source(source: AnswerSource) {
+    let _ = ANSWER_SOURCE.try_with(|tracked| {
+        let combined = match tracked.get() {
+            Some(previous) if previous != source => AnswerSource::Upstream,
+            _ => source,
+        };
+        tracked.set(Some(combined));
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn display() {
+        let mut provenance = Provenance::new("192.0.2.1:53", Some(Transport::Udp));
+        provenance.received_at = UNIX_EPOCH + Duration::from_secs(100);
+        assert_eq!(
+            provenance.to_string(),
+            "from 192.0.2.1:53 over udp at 100, indeterminate"
+        );
+
+        provenance.source = String::from("system");
+        provenance.transport = None;
+        provenance.security = SecurityStatus::Bogus;
+        assert_eq!(provenance.to_string(), "from system at 100, bogus");
+    }
+
+    #[tokio::test]
+    async fn tracks_answer_source() {
+        let (_, source) = track_answer_source(async {}).await;
+        assert_eq!(source, None);
+
+        let (_, source) = track_answer_source(async {
+            record_answer_source(AnswerSource::Cache);
+            tokio::task::yield_now().await;
+            record_answer_source(AnswerSource::Cache);
+        })
+        .await;
+        assert_eq!(source, Some(AnswerSource::Cache));
+
+        // * Mixed sources are reported as upstream.
+        let (_, source) = track_answer_source(async {
+            record_answer_source(AnswerSource::Authoritative);
+            record_answer_source(AnswerSource::Cache);
+        })
+        .await;
+        assert_eq!(source, Some(AnswerSource::Upstream));
+
+        // * A tracked request within another is part of it.
+        let (_, source) = track_answer_source(async {
+            record_answer_source(AnswerSource::Cache);
+            track_answer_source(async { record_answer_source(AnswerSource::Upstream) }).await
+        })
+        .await;
+        assert_eq!(source, Some(AnswerSource::Upstream));
+
+        // * Recording outside a tracked request does nothing.
+        record_answer_source(AnswerSource::Cache);
+    }
+}
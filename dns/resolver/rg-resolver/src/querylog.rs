@@ -0,0 +1,244 @@
+//! A tracing span around each query a client sends, and an optional JSON-lines log of them.
+
+use crate::audit::{AuditConfig, RotatingLog};
+use crate::message::QuestionType;
+use crate::provenance::{self, AnswerSource};
+use crate::{context, metrics, privacy};
+use serde::Serialize;
+use std::cell::Cell;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, field, warn, Instrument};
+
+/// The process-wide query log, if one has been set.
+static QUERY_LOG: RwLock<Option<Arc<QueryLog>>> = RwLock::new(None);
+
+/// The client named when the query's request didn't say who sent it, i.e. the binary itself.
+const LOCAL_CLIENT: &str = "local";
+
+tokio::task_local! {
+    static CLIENT: String;
+    static UPSTREAM: Cell<Option<SocketAddr>>;
+}
+
+/// One answered query, as a line of the query log.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct QueryRecord {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub client: String,
+    pub qname: String,
+    pub qtype: String,
+    /// The nameserver the query was last forwarded to, if it was forwarded.
+    pub upstream: Option<SocketAddr>,
+    pub cache_hit: bool,
+    pub outcome: String,
+    pub latency_us: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+/// A log of queries, one JSON object per line, rotated like the audit log.
+///
+/// Safe to share between threads; each record is written whole.
+pub struct QueryLog {
+    log: RotatingLog,
+}
+
+impl QueryLog {
+    pub fn open(config: AuditConfig) -> anyhow::Result<Self> {
+        let log = RotatingLog::open(config, None)?;
+        Ok(QueryLog { log })
+    }
+
+    pub fn write(&self, record: &QueryRecord) -> anyhow::Result<()> {
+        self.log.write_line(&serde_json::to_string(record)?)
+    }
+}
+
+/// Writes every query observed from now on to log.
+pub fn set_query_log(log: QueryLog) {
+    *QUERY_LOG.write().unwrap() = Some(Arc::new(log));
+}
+
+/// Runs request with client as the name of whoever sent it, for the queries it makes.
+pub async fn with_client<F: Future>(client: String, request: F) -> F::Output {
+    CLIENT.scope(client, request).await
+}
+
+/// The name of whoever sent the request being run, or "local" for this binary's own.
+pub fn client() -> String {
+    CLIENT
+        .try_with(String::clone)
+        .unwrap_or_else(|_| String::from(LOCAL_CLIENT))
+}
+
+/// Records that the query being observed, if any, was forwarded to upstream.
+pub fn record_upstream(upstream: SocketAddr) {
+    let _ = UPSTREAM.try_with(|tracked| tracked.set(Some(upstream)));
+}
+
+/// Runs query, which answers qname and qtype, in a span recording the client, the upstream it
+/// was forwarded to, whether the cache answered it, and how long it took, and writes it to the
+/// query log if one is set. describe sums up its output for the log, e.g. as a response code.
+pub async fn observe<F: Future>(
+    qname: &str,
+    qtype: QuestionType,
+    query: F,
+    describe: impl FnOnce(&F::Output) -> String,
+) -> F::Output {
+    let log = QUERY_LOG.read().unwrap().clone();
+    observe_to(log.as_deref(), qname, qtype, query, describe).await
+}
+
+async fn observe_to<F: Future>(
+    log: Option<&QueryLog>,
+    qname: &str,
+    qtype: QuestionType,
+    query: F,
+    describe: impl FnOnce(&F::Output) -> String,
+) -> F::Output {
+    let client = client();
+    let qname = privacy::qname(qname);
+    let span = tracing::info_span!(
+        "query",
+        client = %client,
+        qname = %qname,
+        qtype = %qtype,
+        upstream = field::Empty,
+        cache = field::Empty,
+        latency_us = field::Empty,
+    );
+    let start = Instant::now();
+    let tracked = async {
+        let (output, source) = provenance::track_answer_source(query).await;
+        (output, source, UPSTREAM.with(Cell::get))
+    };
+    let (output, source, upstream) = UPSTREAM
+        .scope(Cell::new(None), tracked)
+        .instrument(span.clone())
+        .await;
+    let latency_us = start.elapsed().as_micros() as u64;
+    let cache_hit = source == Some(AnswerSource::Cache);
+    metrics::global().count_answer_source(source);
+    if let Some(upstream) = upstream {
+        span.record("upstream", field::display(upstream));
+    }
+    span.record("cache", if cache_hit { "hit" } else { "miss" });
+    span.record("latency_us", latency_us);
+    let outcome = describe(&output);
+    span.in_scope(|| debug!("Answered: {outcome}"));
+
+    if let Some(log) = log {
+        let record = QueryRecord {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            client,
+            qname: qname.to_string(),
+            qtype: qtype.to_string(),
+            upstream,
+            cache_hit,
+            outcome,
+            latency_us,
+            correlation_id: context::correlation_id(),
+        };
+        // * A broken query log shouldn't fail the query.
+        if let Err(e) = log.write(&record) {
+            warn!("Writing query log: {e}");
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rr;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn temp_config(test_name: &str) -> AuditConfig {
+        let dir = std::env::temp_dir().join(format!(
+            "rg-resolver-querylog-{test_name}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        AuditConfig::new(dir.join("queries.jsonl"))
+    }
+
+    fn lines(path: &Path) -> anyhow::Result<Vec<serde_json::Value>> {
+        fs::read_to_string(path)?
+            .lines()
+            .map(|line| serde_json::from_str(line).map_err(anyhow::Error::from))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn logs_queries() -> anyhow::Result<()> {
+        let config = temp_config("queries");
+        let log = QueryLog::open(config.clone())?;
+        let upstream = SocketAddr::from(([192, 0, 2, 53], 53));
+        let a = QuestionType::RrType(rr::Type::A);
+
+        let forwarded = async {
+            record_upstream(upstream);
+            provenance::record_answer_source(AnswerSource::Upstream);
+            3
+        };
+        let answer = with_client(
+            String::from("192.0.2.7:5000"),
+            observe_to(Some(&log), "google.com.", a, forwarded, |n| {
+                format!("answered {n}")
+            }),
+        )
+        .await;
+        assert_eq!(answer, 3);
+        let cached = async { provenance::record_answer_source(AnswerSource::Cache) };
+        let mx = QuestionType::RrType(rr::Type::MX);
+        context::with_correlation_id(
+            String::from("req-42"),
+            observe_to(Some(&log), "yahoo.com.", mx, cached, |_| {
+                String::from("NoError")
+            }),
+        )
+        .await;
+
+        let lines = lines(&config.path)?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["client"], "192.0.2.7:5000");
+        assert_eq!(lines[0]["qname"], "google.com.");
+        assert_eq!(lines[0]["qtype"], "A");
+        assert_eq!(lines[0]["upstream"], "192.0.2.53:53");
+        assert_eq!(lines[0]["cache_hit"], false);
+        assert_eq!(lines[0]["outcome"], "answered 3");
+        assert!(lines[0].get("correlation_id").is_none());
+
+        assert_eq!(lines[1]["client"], LOCAL_CLIENT);
+        assert_eq!(lines[1]["qtype"], "MX");
+        assert!(lines[1]["upstream"].is_null());
+        assert_eq!(lines[1]["cache_hit"], true);
+        assert_eq!(lines[1]["correlation_id"], "req-42");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rotates() -> anyhow::Result<()> {
+        let mut config = temp_config("rotates");
+        config.max_bytes = 1;
+        let log = QueryLog::open(config.clone())?;
+        let a = QuestionType::RrType(rr::Type::A);
+        for name in ["a.example.", "b.example."] {
+            observe_to(Some(&log), name, a, async {}, |_| String::new()).await;
+        }
+
+        let rotated = PathBuf::from(format!("{}.1", config.path.display()));
+        assert_eq!(lines(&rotated)?[0]["qname"], "a.example.");
+        assert_eq!(lines(&config.path)?[0]["qname"], "b.example.");
+        Ok(())
+    }
+}
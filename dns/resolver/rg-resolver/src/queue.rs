@@ -0,0 +1,614 @@
+use crate::metrics;
+use crate::resolve::BoxFuture;
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify, Semaphore, TryAcquireError};
+use tracing::warn;
+
+/// What to do with a request when the work queue is full.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShedPolicy {
+    /// Drop the request immediately.
+    Reject,
+    /// Wait up to the given time for room, then drop the request.
+    /// With no limit, the reader simply waits, which stops it reading more requests.
+    Wait(Option<Duration>),
+}
+
+impl FromStr for ShedPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses "reject", "wait", or "wait:<ms>" to wait that long before dropping.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once(':') {
+            None if s == "reject" => Ok(ShedPolicy::Reject),
+            None if s == "wait" => Ok(ShedPolicy::Wait(None)),
+            Some(("wait", ms)) => {
+                let ms = ms
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("invalid shed policy {s}: {e}"))?;
+                Ok(ShedPolicy::Wait(Some(Duration::from_millis(ms))))
+            }
+            _ => anyhow::bail!("unknown shed policy {s}, expected reject, wait or wait:<ms>"),
+        }
+    }
+}
+
+/// How the requests read by a listener queue up for processing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QueueConfig {
+    /// The most requests waiting to be processed.
+    pub capacity: usize,
+    /// The most requests processed at once. A client's connection may carry many requests,
+    /// each queued on its own.
+    pub workers: usize,
+    /// The most requests from one client address waiting or being processed at once. Past
+    /// that the client is told the server is busy.
+    pub per_client: usize,
+    /// What to do with a request when capacity are already waiting.
+    pub policy: ShedPolicy,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: 1024,
+            workers: 256,
+            per_client: 64,
+            // * A listener that stops reading leaves it to the OS to drop what doesn't fit.
+            policy: ShedPolicy::Wait(None),
+        }
+    }
+}
+
+/// Why a request wasn't queued.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Refusal {
+    /// The queue was full, and stayed full for as long as the policy waits.
+    Full,
+    /// The client already has its share of requests waiting or being processed.
+    Busy,
+    /// The processor has gone away.
+    Closed,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Refusal::Full => "the queue is full",
+            Refusal::Busy => "the client has too many requests in flight",
+            Refusal::Closed => "the server is shutting down",
+        })
+    }
+}
+
+/// Counters describing the queue, shared between its two ends.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    enqueued: AtomicU64,
+    shed: AtomicU64,
+    busy: AtomicU64,
+    /// A moving average of how long requests take to process, in microseconds.
+    processing_us: AtomicU64,
+}
+
+impl QueueStats {
+    /// The number of requests waiting to be processed.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// The greatest depth seen.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn enqueued(&self) -> u64 {
+        self.enqueued.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests dropped because the queue was full.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests refused because their client had its share in flight.
+    pub fn busy(&self) -> u64 {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// How long a client told the server is busy should wait before retrying: about as long
+    /// as a request has recently taken to process, so one of its own is likely done by then.
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_micros(self.processing_us.load(Ordering::Relaxed)).max(MIN_RETRY_AFTER)
+    }
+
+    fn processed(&self, took: Duration) {
+        let took = took.as_micros().min(u64::MAX as u128) as u64;
+        // * Each request moves the average an eighth of the way, as TCP's SRTT does.
+        let _ = self
+            .processing_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => took,
+                    _ => average - average / 8 + took / 8,
+                })
+            });
+    }
+}
+
+/// The least retry-after hint given, so a client isn't told to retry straight away.
+const MIN_RETRY_AFTER: Duration = Duration::from_millis(10);
+
+/// The work of answering one request read from a connection, queued by the task reading the
+/// connection, which waits for the answer with [WorkSender::run].
+pub type Job = BoxFuture<'static, ()>;
+
+/// The state shared by a queue's two ends.
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// A permit for each request there's room for.
+    room: Semaphore,
+    /// Wakes the receiver when a request arrives or the last sender goes.
+    ready: Notify,
+    per_client: usize,
+    stats: Arc<QueueStats>,
+}
+
+/// The requests waiting, by client, and the clients taking turns to have one processed.
+struct State<T> {
+    waiting: HashMap<IpAddr, VecDeque<(T, ClientSlot<T>)>>,
+    /// The clients with requests waiting, in the order they're next served.
+    turns: VecDeque<IpAddr>,
+    /// The requests each client has waiting or being processed.
+    in_flight: HashMap<IpAddr, usize>,
+    senders: usize,
+}
+
+impl<T> Shared<T> {
+    /// Counts a request from client as in flight until the returned slot is dropped, unless
+    /// the client already has its share.
+    fn reserve(self: &Arc<Self>, client: IpAddr) -> Option<ClientSlot<T>> {
+        let mut state = self.state.lock().unwrap();
+        let in_flight = state.in_flight.entry(client).or_default();
+        if *in_flight >= self.per_client {
+            return None;
+        }
+        *in_flight += 1;
+        Some(ClientSlot {
+            shared: self.clone(),
+            client,
+        })
+    }
+}
+
+/// One of a client's requests in flight, counted towards its share until dropped.
+struct ClientSlot<T> {
+    shared: Arc<Shared<T>>,
+    client: IpAddr,
+}
+
+impl<T> Drop for ClientSlot<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(in_flight) = state.in_flight.get_mut(&self.client) {
+            *in_flight -= 1;
+            if *in_flight == 0 {
+                state.in_flight.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// The listener's end of a bounded queue of requests awaiting the query processor.
+///
+/// Because the queue is bounded, a slow upstream makes the listener wait (or shed load)
+/// rather than letting requests pile up in memory. Clients take turns to have their requests
+/// processed, and each may only have so many in flight, so one flooding the server can't
+/// starve the rest.
+pub struct WorkSender<T> {
+    shared: Arc<Shared<T>>,
+    policy: ShedPolicy,
+}
+
+/// The query processor's end of the queue.
+pub struct WorkReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a queue holding at most capacity requests, and per_client of any one client's.
+pub fn work_queue<T>(
+    capacity: usize,
+    per_client: usize,
+    policy: ShedPolicy,
+) -> (WorkSender<T>, WorkReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            waiting: HashMap::new(),
+            turns: VecDeque::new(),
+            in_flight: HashMap::new(),
+            senders: 1,
+        }),
+        room: Semaphore::new(capacity),
+        ready: Notify::new(),
+        per_client,
+        stats: Arc::new(QueueStats::default()),
+    });
+    let sender = WorkSender {
+        shared: shared.clone(),
+        policy,
+    };
+    (sender, WorkReceiver { shared })
+}
+
+impl<T> Clone for WorkSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        WorkSender {
+            shared: self.shared.clone(),
+            policy: self.policy,
+        }
+    }
+}
+
+impl<T> Drop for WorkSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.ready.notify_one();
+        }
+    }
+}
+
+impl<T> WorkSender<T> {
+    /// Queues item from client, returning it back along with why if it wasn't queued.
+    pub async fn submit(&self, client: IpAddr, item: T) -> Result<(), (T, Refusal)> {
+        let Some(slot) = self.shared.reserve(client) else {
+            self.shared.stats.busy.fetch_add(1, Ordering::Relaxed);
+            return Err((item, Refusal::Busy));
+        };
+        let stats = &self.shared.stats;
+        // * Count the item before queuing it so the receiver never sees the depth underflow.
+        let depth = stats.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let room = match self.policy {
+            ShedPolicy::Reject => self.shared.room.try_acquire().map_err(|e| match e {
+                TryAcquireError::NoPermits => Refusal::Full,
+                TryAcquireError::Closed => Refusal::Closed,
+            }),
+            ShedPolicy::Wait(None) => self
+                .shared
+                .room
+                .acquire()
+                .await
+                .map_err(|_| Refusal::Closed),
+            ShedPolicy::Wait(Some(timeout)) => {
+                match tokio::time::timeout(timeout, self.shared.room.acquire()).await {
+                    Ok(room) => room.map_err(|_| Refusal::Closed),
+                    Err(_) => Err(Refusal::Full),
+                }
+            }
+        };
+        let room = match room {
+            Ok(room) => room,
+            Err(refusal) => {
+                stats.depth.fetch_sub(1, Ordering::Relaxed);
+                if refusal == Refusal::Full {
+                    stats.shed.fetch_add(1, Ordering::Relaxed);
+                }
+                return Err((item, refusal));
+            }
+        };
+        // * The room is given back when the receiver takes the item.
+        room.forget();
+        let mut state = self.shared.state.lock().unwrap();
+        let waiting = state.waiting.entry(client).or_default();
+        waiting.push_back((item, slot));
+        if waiting.len() == 1 {
+            state.turns.push_back(client);
+        }
+        drop(state);
+        self.shared.ready.notify_one();
+        stats.max_depth.fetch_max(depth, Ordering::Relaxed);
+        stats.enqueued.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &Arc<QueueStats> {
+        &self.shared.stats
+    }
+}
+
+impl WorkSender<Job> {
+    /// Queues work from client, returning what it produces once a worker has run it, or why
+    /// it wasn't run.
+    pub async fn run<O: Send + 'static>(
+        &self,
+        client: IpAddr,
+        work: impl Future<Output = O> + Send + 'static,
+    ) -> Result<O, Refusal> {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::pin(async move {
+            let _ = tx.send(work.await);
+        });
+        self.submit(client, job)
+            .await
+            .map_err(|(_, refusal)| refusal)?;
+        rx.await.map_err(|_| Refusal::Closed)
+    }
+}
+
+impl<T> Drop for WorkReceiver<T> {
+    fn drop(&mut self) {
+        // * Senders waiting for room give up, and later ones don't wait.
+        self.shared.room.close();
+    }
+}
+
+impl<T> WorkReceiver<T> {
+    /// Waits for the next request, returning None once every sender is gone and the queue
+    /// is empty. The request stops counting towards its client's share once it's received.
+    pub async fn recv(&mut self) -> Option<T> {
+        let (item, _) = self.next().await?;
+        Some(item)
+    }
+
+    /// Takes the next client's turn, returning its first request along with the slot that
+    /// counts it towards the client's share.
+    async fn next(&mut self) -> Option<(T, ClientSlot<T>)> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(client) = state.turns.pop_front() {
+                    let waiting = state
+                        .waiting
+                        .get_mut(&client)
+                        .expect("a client taking a turn has requests waiting");
+                    let item = waiting.pop_front().expect("waiting requests aren't empty");
+                    if waiting.is_empty() {
+                        state.waiting.remove(&client);
+                    } else {
+                        state.turns.push_back(client);
+                    }
+                    drop(state);
+                    self.shared.room.add_permits(1);
+                    self.shared.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                    return Some(item);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<QueueStats> {
+        &self.shared.stats
+    }
+
+    /// Runs process on each request as it arrives, at most workers at once, until every
+    /// sender is gone and the queue is empty. Each request counts towards its client's share
+    /// until process is done with it.
+    pub async fn process<F, Fut>(self, workers: usize, mut process: F)
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let stats = self.shared.stats.clone();
+        let requests = futures::stream::unfold(self, |mut rx| async move {
+            let next = rx.next().await?;
+            Some((next, rx))
+        });
+        requests
+            .for_each_concurrent(workers, |(item, slot)| {
+                let processing = process(item);
+                let stats = stats.clone();
+                async move {
+                    let started = Instant::now();
+                    processing.await;
+                    stats.processed(started.elapsed());
+                    drop(slot);
+                }
+            })
+            .await;
+    }
+}
+
+/// Runs read, which submits the requests it reads to a queue made as config says, alongside
+/// a processor running handle on each of them in its own task. Returns what read does, once
+/// the requests it queued have been handled.
+///
+/// name is what the queue's stats are exported to metrics as.
+pub async fn serve<T, R, H, Fut>(
+    name: String,
+    config: QueueConfig,
+    read: impl FnOnce(WorkSender<T>) -> R,
+    handle: H,
+) -> R::Output
+where
+    T: Send + 'static,
+    R: Future,
+    H: Fn(T) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (tx, rx) = work_queue(config.capacity, config.per_client, config.policy);
+    metrics::global().register_queue(name, tx.stats().clone());
+    let processing = rx.process(config.workers, |item| {
+        // * Spawned, so requests are handled on every worker thread rather than this one.
+        let handling = tokio::spawn(handle(item));
+        async move {
+            if let Err(e) = handling.await {
+                warn!("Handling a queued request: {e}");
+            }
+        }
+    });
+    // * read drops its sender when it returns, which lets the processor finish.
+    let (read, ()) = tokio::join!(read(tx), processing);
+    read
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    #[tokio::test]
+    async fn reject_when_full() {
+        let (tx, mut rx) = work_queue(2, 8, ShedPolicy::Reject);
+        assert_eq!(tx.submit(CLIENT, 1).await, Ok(()));
+        assert_eq!(tx.submit(CLIENT, 2).await, Ok(()));
+        assert_eq!(tx.submit(CLIENT, 3).await, Err((3, Refusal::Full)));
+        assert_eq!(tx.stats().depth(), 2);
+        assert_eq!(tx.stats().shed(), 1);
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.stats().depth(), 1);
+        assert_eq!(tx.submit(CLIENT, 4).await, Ok(()));
+        assert_eq!(tx.stats().max_depth(), 2);
+        assert_eq!(tx.stats().enqueued(), 3);
+    }
+
+    #[tokio::test]
+    async fn wait_with_timeout() {
+        let (tx, mut rx) = work_queue(1, 8, ShedPolicy::Wait(Some(Duration::from_millis(100))));
+        assert_eq!(tx.submit(CLIENT, 1).await, Ok(()));
+        assert_eq!(tx.submit(CLIENT, 2).await, Err((2, Refusal::Full)));
+        assert_eq!(tx.stats().shed(), 1);
+
+        // * The processor making room lets a waiting request in.
+        let waiting = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.submit(CLIENT, 3).await }
+        });
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+        assert_eq!(rx.recv().await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn process_up_to_workers_at_once() {
+        let (tx, rx) = work_queue(4, 8, ShedPolicy::Wait(None));
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let processing = rx.process(2, |_| {
+            let (running, most) = (running.clone(), most.clone());
+            async move {
+                let now = running.fetch_add(1, Ordering::Relaxed) + 1;
+                most.fetch_max(now, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+        let submitting = async move {
+            for i in 0..8 {
+                tx.submit(CLIENT, i).await.unwrap();
+            }
+        };
+        tokio::join!(submitting, processing);
+        assert_eq!(most.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn run_jobs() {
+        let (tx, rx) = work_queue::<Job>(1, 8, ShedPolicy::Reject);
+        let processing = tokio::spawn(rx.process(1, |job| job));
+        assert_eq!(tx.run(CLIENT, async { 2 + 2 }).await, Ok(4));
+        drop(tx);
+        processing.await.unwrap();
+
+        // * A job that's shed never runs.
+        let (tx, _rx) = work_queue::<Job>(1, 8, ShedPolicy::Reject);
+        assert!(tx.submit(CLIENT, Box::pin(async {})).await.is_ok());
+        assert_eq!(tx.run(CLIENT, async { 2 + 2 }).await, Err(Refusal::Full));
+        assert_eq!(tx.stats().shed(), 1);
+    }
+
+    #[tokio::test]
+    async fn clients_take_turns() {
+        let (flood, other) = (CLIENT, IpAddr::from([192, 0, 2, 1]));
+        let (tx, mut rx) = work_queue(8, 8, ShedPolicy::Reject);
+        for i in 0..4 {
+            tx.submit(flood, ('f', i)).await.unwrap();
+        }
+        tx.submit(other, ('o', 0)).await.unwrap();
+        tx.submit(other, ('o', 1)).await.unwrap();
+
+        // * The client that queued later still goes second, not after everything flood sent.
+        let mut order = vec![];
+        for _ in 0..6 {
+            order.push(rx.recv().await.unwrap());
+        }
+        assert_eq!(
+            order,
+            [('f', 0), ('o', 0), ('f', 1), ('o', 1), ('f', 2), ('f', 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn busy_past_client_share() {
+        let other = IpAddr::from([192, 0, 2, 1]);
+        let (tx, rx) = work_queue(8, 2, ShedPolicy::Reject);
+        let (release, released) = tokio::sync::watch::channel(false);
+        let processing = tokio::spawn(rx.process(4, move |_| {
+            let mut released = released.clone();
+            async move {
+                let _ = released.wait_for(|released| *released).await;
+            }
+        }));
+        tx.submit(CLIENT, 1).await.unwrap();
+        tx.submit(CLIENT, 2).await.unwrap();
+        assert_eq!(tx.submit(CLIENT, 3).await, Err((3, Refusal::Busy)));
+        assert_eq!(tx.stats().busy(), 1);
+        // * Only the client past its share is refused.
+        assert_eq!(tx.submit(other, 4).await, Ok(()));
+
+        // * Requests count towards the share until they've been processed, not just received.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(tx.stats().depth(), 0);
+        assert_eq!(tx.submit(CLIENT, 5).await, Err((5, Refusal::Busy)));
+
+        release.send(true).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(tx.submit(CLIENT, 6).await, Ok(()));
+        assert!(tx.stats().retry_after() >= MIN_RETRY_AFTER);
+        drop(tx);
+        processing.await.unwrap();
+    }
+
+    #[test]
+    fn parse_shed_policy() -> anyhow::Result<()> {
+        assert_eq!("reject".parse::<ShedPolicy>()?, ShedPolicy::Reject);
+        assert_eq!("wait".parse::<ShedPolicy>()?, ShedPolicy::Wait(None));
+        assert_eq!(
+            "wait:250".parse::<ShedPolicy>()?,
+            ShedPolicy::Wait(Some(Duration::from_millis(250)))
+        );
+        assert!("wait:soon".parse::<ShedPolicy>().is_err());
+        assert!("drop".parse::<ShedPolicy>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let (tx, rx) = work_queue(1, 8, ShedPolicy::Wait(None));
+        drop(rx);
+        assert_eq!(tx.submit(CLIENT, 1).await, Err((1, Refusal::Closed)));
+        // * Nothing was shed; the processor is gone.
+        assert_eq!(tx.stats().shed(), 0);
+
+        let (tx, mut rx) = work_queue::<u32>(1, 8, ShedPolicy::Reject);
+        drop(tx);
+        assert_eq!(rx.recv().await, None);
+    }
+}
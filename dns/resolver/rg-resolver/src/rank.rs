@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a nameserver ranked behind another goes without a query before it's tried first
+/// once, in case it's recovered or gotten faster.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The weight given to each new RTT in a nameserver's smoothed RTT, out of 10.
+const RTT_WEIGHT: u32 = 3;
+
+/// The smoothed RTT a nameserver can be penalized up to.
+const MAX_SRTT: Duration = Duration::from_secs(30);
+
+/// Ranks nameservers by how fast they've been answering, so queries go to the fastest first.
+///
+/// Each nameserver's RTTs are kept as a weighted average. A timeout or unreachable error
+/// doubles it (to at least the timeout), so a nameserver that stops answering falls behind
+/// the others until it answers again. Nameservers that have never been queried rank ahead of
+/// those that have, so each gets measured.
+#[derive(Debug, Default)]
+pub struct Rankings {
+    servers: Mutex<HashMap<SocketAddr, Rtt>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rtt {
+    /// The smoothed RTT, or None if the nameserver hasn't been queried.
+    srtt: Option<Duration>,
+    /// The failures since the nameserver last answered.
+    failures: u32,
+    last_queried: Option<Instant>,
+}
+
+impl Rtt {
+    fn new() -> Self {
+        Rtt {
+            srtt: None,
+            failures: 0,
+            last_queried: None,
+        }
+    }
+
+    fn rank(&self) -> Duration {
+        self.srtt.unwrap_or(Duration::ZERO)
+    }
+
+    fn answered(&mut self, rtt: Duration) {
+        self.srtt = Some(match self.srtt {
+            // * A penalized RTT says nothing about how fast the answers are, so the first
+            // * one after failures replaces it.
+            Some(srtt) if self.failures == 0 => (srtt * (10 - RTT_WEIGHT) + rtt * RTT_WEIGHT) / 10,
+            _ => rtt,
+        });
+        self.failures = 0;
+    }
+
+    fn failed(&mut self, timeout: Duration) {
+        let srtt = self.srtt.unwrap_or(Duration::ZERO);
+        self.srtt = Some((srtt * 2).max(timeout).min(MAX_SRTT));
+        self.failures += 1;
+    }
+}
+
+impl Rankings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The indexes of addrs in the order to try them: fastest first, except that the first
+    /// nameserver due a retry (see [RETRY_INTERVAL]) goes ahead of the rest. Ties keep the
+    /// order of addrs.
+    pub fn order(&self, addrs: &[SocketAddr], now: Instant) -> Vec<usize> {
+        let mut servers = self.servers.lock().unwrap();
+        let ranks = addrs
+            .iter()
+            .map(|addr| servers.get(addr).map_or(Duration::ZERO, Rtt::rank))
+            .collect::<Vec<_>>();
+        let mut order = (0..addrs.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&idx| ranks[idx]);
+
+        let due = order.iter().skip(1).position(|&idx| {
+            servers.get(&addrs[idx]).is_some_and(|rtt| {
+                rtt.last_queried
+                    .is_some_and(|at| now.duration_since(at) >= RETRY_INTERVAL)
+            })
+        });
+        if let Some(pos) = due {
+            let idx = order.remove(pos + 1);
+            order.insert(0, idx);
+            // * Only one query at a time retries it.
+            if let Some(rtt) = servers.get_mut(&addrs[idx]) {
+                rtt.last_queried = Some(now);
+            }
+        }
+        order
+    }
+
+    /// Records that a query was sent to addr.
+    pub fn queried(&self, addr: SocketAddr, now: Instant) {
+        let mut servers = self.servers.lock().unwrap();
+        servers.entry(addr).or_insert_with(Rtt::new).last_queried = Some(now);
+    }
+
+    /// Records that addr answered a query in rtt.
+    pub fn answered(&self, addr: SocketAddr, rtt: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        servers.entry(addr).or_insert_with(Rtt::new).answered(rtt);
+    }
+
+    /// Records that addr didn't answer a query within timeout, or was unreachable.
+    pub fn failed(&self, addr: SocketAddr, timeout: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        servers.entry(addr).or_insert_with(Rtt::new).failed(timeout);
+    }
+
+    /// The smoothed RTT of addr, or None if it hasn't been queried.
+    pub fn srtt(&self, addr: SocketAddr) -> Option<Duration> {
+        let servers = self.servers.lock().unwrap();
+        servers.get(&addr).and_then(|rtt| rtt.srtt)
+    }
+
+    /// The failures since addr last answered.
+    pub fn failures(&self, addr: SocketAddr) -> u32 {
+        let servers = self.servers.lock().unwrap();
+        servers.get(&addr).map_or(0, |rtt| rtt.failures)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "192.0.2.1:53".parse().unwrap(),
+            "192.0.2.2:53".parse().unwrap(),
+            "192.0.2.3:53".parse().unwrap(),
+        ]
+    }
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn prefers_fastest() {
+        let addrs = addrs();
+        let rankings = Rankings::new();
+        let now = Instant::now();
+        // * Unmeasured nameservers keep the configured order.
+        assert_eq!(rankings.order(&addrs, now), [0, 1, 2]);
+
+        rankings.answered(addrs[0], 80 * MS);
+        rankings.answered(addrs[1], 20 * MS);
+        // * Not yet measured, so it's tried before the others.
+        assert_eq!(rankings.order(&addrs, now), [2, 1, 0]);
+        rankings.answered(addrs[2], 50 * MS);
+        assert_eq!(rankings.order(&addrs, now), [1, 2, 0]);
+
+        // * The average moves toward new RTTs without jumping to them.
+        rankings.answered(addrs[1], 120 * MS);
+        assert_eq!(rankings.srtt(addrs[1]), Some(50 * MS));
+        assert_eq!(rankings.order(&addrs, now), [1, 2, 0]);
+        rankings.answered(addrs[1], 120 * MS);
+        assert_eq!(rankings.order(&addrs, now), [2, 1, 0]);
+    }
+
+    #[test]
+    fn penalizes_failures() {
+        let addrs = addrs();
+        let rankings = Rankings::new();
+        let now = Instant::now();
+        for addr in &addrs {
+            rankings.answered(*addr, 10 * MS);
+        }
+        rankings.failed(addrs[0], 2000 * MS);
+        assert_eq!(rankings.srtt(addrs[0]), Some(2000 * MS));
+        rankings.failed(addrs[0], 2000 * MS);
+        assert_eq!(rankings.srtt(addrs[0]), Some(4000 * MS));
+        assert_eq!(rankings.failures(addrs[0]), 2);
+        assert_eq!(rankings.order(&addrs, now), [1, 2, 0]);
+
+        for _ in 0..10 {
+            rankings.failed(addrs[0], 2000 * MS);
+        }
+        assert_eq!(rankings.srtt(addrs[0]), Some(MAX_SRTT));
+
+        // * Answering again wipes out the penalty.
+        rankings.answered(addrs[0], 5 * MS);
+        assert_eq!(rankings.srtt(addrs[0]), Some(5 * MS));
+        assert_eq!(rankings.failures(addrs[0]), 0);
+        assert_eq!(rankings.order(&addrs, now), [0, 1, 2]);
+    }
+
+    #[test]
+    fn retries_demoted() {
+        let addrs = addrs();
+        let rankings = Rankings::new();
+        let start = Instant::now();
+        for addr in &addrs {
+            rankings.queried(*addr, start);
+        }
+        rankings.failed(addrs[0], 2000 * MS);
+        rankings.answered(addrs[1], 10 * MS);
+        rankings.answered(addrs[2], 20 * MS);
+        assert_eq!(rankings.order(&addrs, start), [1, 2, 0]);
+        rankings.queried(addrs[1], start + RETRY_INTERVAL);
+
+        // * Both demoted nameservers are due, and the faster goes first. The other waits
+        // * for the next query.
+        let retry = start + RETRY_INTERVAL;
+        assert_eq!(rankings.order(&addrs, retry), [2, 1, 0]);
+        assert_eq!(rankings.order(&addrs, retry), [0, 1, 2]);
+        assert_eq!(rankings.order(&addrs, retry), [1, 2, 0]);
+    }
+}
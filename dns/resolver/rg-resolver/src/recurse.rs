@@ -0,0 +1,678 @@
+use crate::cache::Cache;
+use crate::classify::{self, Classification};
+use crate::exchange::Exchange;
+use crate::message::{QueryBuilder, QuestionClass, QuestionType};
+use crate::provenance::{self, AnswerSource, Provenance};
+use crate::resolve::{Answer, BoxFuture, RRset, Resolve};
+use crate::{privacy, rr, zone};
+use rg_resolver_common::DomainName;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::debug;
+
+/// The root servers' IPv4 addresses, from the IANA root hints file.
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// Loads the root servers' addresses from a root hints file like IANA's named.root, a zone
+/// file holding the root's NS records and the addresses of the servers they name. These are
+/// the servers resolution starts from when no closer ones are known (SBELT in RFC 1034).
+pub fn load_root_hints(path: &Path) -> anyhow::Result<Vec<SocketAddr>> {
+    let spec = zone::ZoneSpec {
+        origin: String::from("."),
+        path: path.to_path_buf(),
+    };
+    let hints = zone::load(&spec)?;
+    root_hints(&hints.records)
+        .map_err(|e| anyhow::anyhow!("loading root hints {}: {e}", path.display()))
+}
+
+/// The addresses of the root servers named by the root NS records among records.
+fn root_hints(records: &[rr::ResourceRecord]) -> anyhow::Result<Vec<SocketAddr>> {
+    let servers = records
+        .iter()
+        .filter(|rr| rr.name().is_root())
+        .filter_map(|rr| match rr.data() {
+            rr::Data::NS(host) => Some(host),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if servers.is_empty() {
+        anyhow::bail!("no NS records for the root");
+    }
+    let addrs = records
+        .iter()
+        .filter(|rr| servers.contains(&rr.name()))
+        .filter_map(|rr| match rr.data() {
+            rr::Data::A(addr) => Some(SocketAddr::new(IpAddr::V4(*addr), 53)),
+            rr::Data::AAAA(addr) => Some(SocketAddr::new(IpAddr::V6(*addr), 53)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        anyhow::bail!("no addresses for the root servers");
+    }
+    Ok(addrs)
+}
+
+/// The most queries sent to resolve one name, including those resolving nameserver addresses.
+const MAX_QUERIES: usize = 64;
+
+/// How deeply resolving a nameserver's address may in turn need another nameserver's address.
+const MAX_DEPTH: usize = 4;
+
+/// Resolves names itself, starting at the root and following referrals down to the
+/// authoritative nameservers (RFC 1034 section 5.3.3), rather than forwarding to a recursive
+/// resolver.
+pub struct Recursor {
+    exchange: Box<dyn Exchange>,
+    roots: Vec<SocketAddr>,
+    /// Where what the nameservers say is merged, and the namespace it's merged into.
+    cache: Option<(Arc<Cache>, String)>,
+}
+
+/// The state of one resolution: the name, type, and class being resolved (SNAME, STYPE, and
+/// SCLASS in RFC 1034), which change as CNAMEs are followed, and the work left.
+struct Request {
+    sname: DomainName,
+    stype: QuestionType,
+    sclass: QuestionClass,
+    /// The CNAMEs followed so far, across restarts.
+    cnames: RRset,
+    queries_left: usize,
+    /// How many nameserver address lookups this request is nested in.
+    depth: usize,
+}
+
+/// The nameservers for the closest zone known to contain SNAME (SLIST in RFC 1034).
+struct Servers {
+    zone: DomainName,
+    addrs: Vec<SocketAddr>,
+}
+
+impl Recursor {
+    pub fn new(exchange: Box<dyn Exchange>) -> Self {
+        let roots = ROOT_HINTS
+            .iter()
+            .map(|addr| SocketAddr::new(IpAddr::V4(*addr), 53))
+            .collect();
+        Recursor {
+            exchange,
+            roots,
+            cache: None,
+        }
+    }
+
+    /// Starts resolutions at roots rather than the root servers.
+    pub fn with_roots(mut self, roots: Vec<SocketAddr>) -> Self {
+        self.roots = roots;
+        self
+    }
+
+    /// Merges every response into cache under namespace, ranked by the section its records
+    /// came from (see [Cache::insert_classified]), and reaches nameservers at the addresses
+    /// cached for them rather than resolving them again.
+    pub fn with_cache(mut self, cache: Arc<Cache>, namespace: &str) -> Self {
+        self.cache = Some((cache, namespace.to_string()));
+        self
+    }
+
+    fn roots(&self) -> Servers {
+        Servers {
+            zone: DomainName::root(),
+            addrs: self.roots.clone(),
+        }
+    }
+
+    /// Resolves request, returning any CNAMEs followed and then the records found, or what the
+    /// authoritative nameserver said about the end of the CNAMEs if there are none.
+    fn iterate<'a>(&'a self, request: &'a mut Request) -> BoxFuture<'a, anyhow::Result<Answer>> {
+        Box::pin(async move {
+            let mut servers = self.roots();
+            loop {
+                match self.ask(request, &servers).await? {
+                    Classification::Answer(records) => {
+                        let mut rrset = std::mem::take(&mut request.cnames);
+                        rrset.extend(records);
+                        return Ok(Answer::Records(rrset));
+                    }
+                    Classification::NoData { soa } => {
+                        let cnames = std::mem::take(&mut request.cnames);
+                        return Ok(Answer::NoData { cnames, soa });
+                    }
+                    Classification::NxDomain { soa } => {
+                        let cnames = std::mem::take(&mut request.cnames);
+                        return Ok(Answer::NxDomain { cnames, soa });
+                    }
+                    Classification::Cname { chain, target } => {
+                        request.cnames.extend(chain);
+                        let looped = request.cnames.iter().any(|rr| *rr.name() == target);
+                        if looped || request.cnames.len() > classify::MAX_CNAME_CHAIN {
+                            anyhow::bail!("CNAME chain loops or is too long");
+                        }
+                        // * The target may be in any zone, so start again from the root.
+                        request.sname = target;
+                        servers = self.roots();
+                    }
+                    Classification::Referral {
+                        zone,
+                        nameservers,
+                        glue,
+                    } => {
+                        // * Each referral has to get closer to the name, or they could loop.
+                        if !classify::is_below(&zone, &servers.zone) {
+                            anyhow::bail!(
+                                "{} referred the query for {} from {} back up to {zone}",
+                                servers
+                                    .addrs
+                                    .first()
+                                    .map_or(String::new(), |a| a.to_string()),
+                                privacy::qname(&request.sname.to_string()),
+                                servers.zone
+                            );
+                        }
+                        servers = self.nameservers(request, zone, &nameservers, &glue).await?;
+                    }
+                    Classification::Error(_) => unreachable!("ask() only returns usable responses"),
+                }
+            }
+        })
+    }
+
+    /// Sends the query for request to each of servers in turn, returning the first usable
+    /// response.
+    async fn ask(
+        &self,
+        request: &mut Request,
+        servers: &Servers,
+    ) -> anyhow::Result<Classification> {
+        let mut last_err = anyhow::anyhow!("no nameservers for {}", servers.zone);
+        for &server in &servers.addrs {
+            if request.queries_left == 0 {
+                anyhow::bail!("gave up after {MAX_QUERIES} queries");
+            }
+            request.queries_left -= 1;
+            let query = QueryBuilder::new()
+                .question(&request.sname, request.stype, request.sclass)
+                .build();
+            let response = match self.exchange.exchange(server, &query).await {
+                Ok(response) => response,
+                Err(e) => {
+                    debug!("Querying {server} in {}: {e}", servers.zone);
+                    last_err = e.context(format!("querying {server}"));
+                    continue;
+                }
+            };
+            match classify::classify(&query, &response) {
+                Classification::Error(e) => {
+                    debug!("Querying {server} in {}: {e}", servers.zone);
+                    last_err = anyhow::anyhow!("querying {server}: {e}");
+                }
+                classification => {
+                    if let Some((cache, namespace)) = &self.cache {
+                        let provenance = Provenance::new(server.to_string(), None);
+                        cache.insert_classified(
+                            namespace,
+                            &query,
+                            &response,
+                            &classification,
+                            provenance,
+                        );
+                    }
+                    return Ok(classification);
+                }
+            }
+        }
+        Err(last_err)
+    }
+
+    /// The addresses of the nameservers a referral to zone named, from the glue if there is
+    /// any, otherwise by resolving the nameservers' names.
+    async fn nameservers(
+        &self,
+        request: &mut Request,
+        zone: DomainName,
+        nameservers: &[rr::ResourceRecord],
+        glue: &[rr::ResourceRecord],
+    ) -> anyhow::Result<Servers> {
+        let mut addrs = glue
+            .iter()
+            .filter_map(|rr| match rr.data() {
+                rr::Data::A(addr) => Some(SocketAddr::new(IpAddr::V4(*addr), 53)),
+                rr::Data::AAAA(addr) => Some(SocketAddr::new(IpAddr::V6(*addr), 53)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !addrs.is_empty() {
+            return Ok(Servers { zone, addrs });
+        }
+        if request.depth == MAX_DEPTH {
+            anyhow::bail!("nameservers for {zone} are nested too deeply");
+        }
+
+        let mut last_err = anyhow::anyhow!("referral to {zone} has no usable nameservers");
+        for ns in nameservers {
+            let rr::Data::NS(host) = ns.data() else {
+                continue;
+            };
+            if let Some((cache, namespace)) = &self.cache {
+                let cached = cache.addresses(namespace, host);
+                addrs.extend(cached.into_iter().map(|ip| SocketAddr::new(ip, 53)));
+                if !addrs.is_empty() {
+                    return Ok(Servers { zone, addrs });
+                }
+            }
+            // * Without glue, a nameserver inside the zone it serves can't be reached.
+            if host.is_subdomain_of(&zone) {
+                continue;
+            }
+            let mut lookup = Request {
+                sname: host.clone(),
+                stype: QuestionType::RrType(rr::Type::A),
+                sclass: QuestionClass::RrClass(rr::Class::IN),
+                cnames: Vec::new(),
+                queries_left: request.queries_left,
+                depth: request.depth + 1,
+            };
+            let result = self.iterate(&mut lookup).await;
+            request.queries_left = lookup.queries_left;
+            match result {
+                Ok(answer) => {
+                    addrs.extend(answer.records().iter().filter_map(|rr| match rr.data() {
+                        rr::Data::A(addr) => Some(SocketAddr::new(IpAddr::V4(*addr), 53)),
+                        _ => None,
+                    }))
+                }
+                Err(e) => last_err = e.context(format!("resolving nameserver {host}")),
+            }
+            if !addrs.is_empty() {
+                return Ok(Servers { zone, addrs });
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl Resolve for Recursor {
+    fn name(&self) -> &str {
+        "recursor"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let mut request = Request {
+                sname: name.parse()?,
+                stype: qtype,
+                sclass: QuestionClass::RrClass(rr::Class::IN),
+                cnames: Vec::new(),
+                queries_left: MAX_QUERIES,
+                depth: 0,
+            };
+            let answer = self
+                .iterate(&mut request)
+                .await
+                .map_err(|e| e.context(format!("resolving {}", privacy::qname(name))))?;
+            provenance::record_answer_source(AnswerSource::Upstream);
+            Ok(Some(answer))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache::Trust;
+    use crate::message::{Message, ResponseCode};
+
+    /// An authoritative nameserver for one zone.
+    struct FakeServer {
+        addr: SocketAddr,
+        zone: &'static str,
+        records: Vec<rr::ResourceRecord>,
+        /// Child zones, each with its nameserver's name and glue address if any.
+        delegations: Vec<(&'static str, &'static str, Option<Ipv4Addr>)>,
+    }
+
+    /// Answers as the fake servers would. Queries to any other address time out.
+    struct FakeInternet {
+        servers: Vec<FakeServer>,
+    }
+
+    fn addr(last: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, last)), 53)
+    }
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
+
+    fn rr(owner: &str, data: rr::Data) -> rr::ResourceRecord {
+        let r#type = match data {
+            rr::Data::A(_) => rr::Type::A,
+            rr::Data::NS(_) => rr::Type::NS,
+            rr::Data::CNAME(_) => rr::Type::CNAME,
+            rr::Data::SOA { .. } => rr::Type::SOA,
+            _ => unimplemented!(),
+        };
+        rr::ResourceRecord::new(name(owner), r#type, rr::Class::IN, 300, data).unwrap()
+    }
+
+    fn soa(zone: &str) -> rr::ResourceRecord {
+        rr(
+            zone,
+            rr::Data::SOA {
+                mname: name(&format!("ns.{zone}")),
+                rname: name(&format!("hostmaster.{zone}")),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )
+    }
+
+    impl FakeServer {
+        fn respond(&self, query: &Message) -> Message {
+            let question = &query.questions()[0];
+            let qname = question.name();
+            if let Some((child, host, glue)) = self
+                .delegations
+                .iter()
+                .find(|(child, _, _)| qname.is_subdomain_of(&name(child)))
+            {
+                let glue = glue
+                    .map(|glue| vec![rr(host, rr::Data::A(glue))])
+                    .unwrap_or_default();
+                return query.response(
+                    ResponseCode::NoError,
+                    vec![],
+                    vec![rr(child, rr::Data::NS(name(host)))],
+                    glue,
+                );
+            }
+            let at_name = self
+                .records
+                .iter()
+                .filter(|rr| rr.name() == qname)
+                .collect::<Vec<_>>();
+            let answers = at_name
+                .iter()
+                .filter(|rr| {
+                    question.r#type().matches(rr.r#type()) || rr.r#type() == rr::Type::CNAME
+                })
+                .map(|rr| (*rr).clone())
+                .collect::<Vec<_>>();
+            let mut response = if !answers.is_empty() {
+                query.answer_response(answers)
+            } else {
+                let code = if at_name.is_empty() {
+                    ResponseCode::NameError
+                } else {
+                    ResponseCode::NoError
+                };
+                query.response(code, vec![], vec![soa(self.zone)], vec![])
+            };
+            response.set_authoritative_answer(true);
+            response
+        }
+    }
+
+    impl Exchange for FakeInternet {
+        fn exchange<'a>(
+            &'a self,
+            server: SocketAddr,
+            query: &'a Message,
+        ) -> BoxFuture<'a, anyhow::Result<Message>> {
+            Box::pin(async move {
+                match self.servers.iter().find(|s| s.addr == server) {
+                    Some(fake) => Ok(fake.respond(query)),
+                    None => anyhow::bail!("timed out"),
+                }
+            })
+        }
+    }
+
+    /// The root, com., and example.com., where www is an alias for a name in net., whose
+    /// nameserver has no glue and has to be resolved through org.
+    fn internet() -> FakeInternet {
+        let a = |name: &str, last: u8| rr(name, rr::Data::A(Ipv4Addr::new(192, 0, 2, last)));
+        let servers = vec![
+            FakeServer {
+                addr: addr(1),
+                zone: ".",
+                records: vec![],
+                delegations: vec![
+                    ("com.", "a.gtld.net.", Some(Ipv4Addr::new(192, 0, 2, 2))),
+                    ("net.", "b.gtld.net.", Some(Ipv4Addr::new(192, 0, 2, 4))),
+                    ("org.", "a0.org.info.", Some(Ipv4Addr::new(192, 0, 2, 5))),
+                ],
+            },
+            FakeServer {
+                addr: addr(2),
+                zone: "com.",
+                records: vec![],
+                delegations: vec![(
+                    "example.com.",
+                    "ns.example.com.",
+                    Some(Ipv4Addr::new(192, 0, 2, 3)),
+                )],
+            },
+            FakeServer {
+                addr: addr(3),
+                zone: "example.com.",
+                records: vec![
+                    rr("www.example.com.", rr::Data::CNAME(name("edge.cdn.net."))),
+                    a("ns.example.com.", 3),
+                ],
+                delegations: vec![],
+            },
+            FakeServer {
+                addr: addr(4),
+                zone: "net.",
+                records: vec![],
+                delegations: vec![("cdn.net.", "ns.dnshost.org.", None)],
+            },
+            FakeServer {
+                addr: addr(5),
+                zone: "org.",
+                records: vec![],
+                delegations: vec![(
+                    "dnshost.org.",
+                    "ns.dnshost.org.",
+                    Some(Ipv4Addr::new(192, 0, 2, 6)),
+                )],
+            },
+            FakeServer {
+                addr: addr(6),
+                zone: "dnshost.org.",
+                records: vec![a("ns.dnshost.org.", 7)],
+                delegations: vec![],
+            },
+            FakeServer {
+                addr: addr(7),
+                zone: "cdn.net.",
+                records: vec![a("edge.cdn.net.", 100)],
+                delegations: vec![],
+            },
+        ];
+        FakeInternet { servers }
+    }
+
+    #[tokio::test]
+    async fn follows_referrals_and_cnames() -> anyhow::Result<()> {
+        // * The first root doesn't answer, so the second one has to be tried.
+        let recursor = Recursor::new(Box::new(internet())).with_roots(vec![addr(250), addr(1)]);
+
+        let answer = recursor
+            .lookup("www.example.com.", QuestionType::RrType(rr::Type::A))
+            .await?
+            .unwrap();
+        assert_eq!(
+            answer.records(),
+            [
+                rr("www.example.com.", rr::Data::CNAME(name("edge.cdn.net."))),
+                rr("edge.cdn.net.", rr::Data::A(Ipv4Addr::new(192, 0, 2, 100))),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn merges_responses_into_the_cache() -> anyhow::Result<()> {
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let recursor = Recursor::new(Box::new(internet()))
+            .with_roots(vec![addr(1)])
+            .with_cache(cache.clone(), "default");
+        let a = QuestionType::RrType(rr::Type::A);
+        let class = QuestionClass::RrClass(rr::Class::IN);
+        recursor.lookup("www.example.com.", a).await?;
+
+        // * The authoritative answers are cached as such.
+        let hit = cache
+            .get("default", &name("www.example.com."), a, class)
+            .unwrap();
+        assert_eq!(hit.trust, Trust::AuthoritativeAnswer);
+        assert_eq!(hit.answer.records().len(), 2);
+        // * Glue only reaches nameservers.
+        assert!(cache
+            .get("default", &name("ns.example.com."), a, class)
+            .is_none());
+        assert_eq!(
+            cache.addresses("default", &name("ns.example.com.")),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3))]
+        );
+
+        // * Without dnshost.org's server, cdn.net's nameserver is still reached at its cached
+        // * address.
+        let mut internet = internet();
+        internet
+            .servers
+            .retain(|server| server.zone != "dnshost.org.");
+        let recursor = Recursor::new(Box::new(internet))
+            .with_roots(vec![addr(1)])
+            .with_cache(cache, "default");
+        let answer = recursor.lookup("edge.cdn.net.", a).await?.unwrap();
+        assert_eq!(
+            answer,
+            Answer::Records(vec![rr(
+                "edge.cdn.net.",
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 100))
+            )])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_names_and_records() -> anyhow::Result<()> {
+        let recursor = Recursor::new(Box::new(internet())).with_roots(vec![addr(1)]);
+        let answer = recursor
+            .lookup("nope.example.com.", QuestionType::RrType(rr::Type::A))
+            .await?;
+        assert_eq!(
+            answer,
+            Some(Answer::NxDomain {
+                cnames: vec![],
+                soa: Some(soa("example.com."))
+            })
+        );
+        let answer = recursor
+            .lookup("ns.example.com.", QuestionType::RrType(rr::Type::MX))
+            .await?;
+        assert_eq!(
+            answer,
+            Some(Answer::NoData {
+                cnames: vec![],
+                soa: Some(soa("example.com."))
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_unreachable_and_lame_servers() {
+        let recursor = Recursor::new(Box::new(internet())).with_roots(vec![addr(250)]);
+        let e = recursor
+            .lookup("www.example.com.", QuestionType::RrType(rr::Type::A))
+            .await
+            .unwrap_err();
+        assert!(format!("{e:#}").contains("timed out"), "{e:#}");
+
+        // * A server that refers the query back up to the root sends the resolver in circles.
+        let looping = FakeInternet {
+            servers: vec![
+                FakeServer {
+                    addr: addr(1),
+                    zone: ".",
+                    records: vec![],
+                    delegations: vec![("com.", "a.gtld.net.", Some(Ipv4Addr::new(192, 0, 2, 2)))],
+                },
+                FakeServer {
+                    addr: addr(2),
+                    zone: "com.",
+                    records: vec![],
+                    delegations: vec![("com.", "a.gtld.net.", Some(Ipv4Addr::new(192, 0, 2, 2)))],
+                },
+            ],
+        };
+        let recursor = Recursor::new(Box::new(looping)).with_roots(vec![addr(1)]);
+        assert!(recursor
+            .lookup("www.example.com.", QuestionType::RrType(rr::Type::A))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn loads_root_hints() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("rg-resolver-hints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("named.root");
+        std::fs::write(
+            &path,
+            "; Abridged from IANA's named.root.\n\
+             .                        3600000      NS    A.ROOT-SERVERS.NET.\n\
+             A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4\n\
+             A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30\n\
+             .                        3600000      NS    B.ROOT-SERVERS.NET.\n\
+             B.ROOT-SERVERS.NET.      3600000      A     170.247.170.2\n\
+             ; Not a root server, so ignored.\n\
+             ns.example.              3600000      A     192.0.2.1\n",
+        )?;
+        let roots = load_root_hints(&path)?;
+        assert_eq!(
+            roots,
+            [
+                "198.41.0.4:53",
+                "[2001:503:ba3e::2:30]:53",
+                "170.247.170.2:53"
+            ]
+            .map(|addr| addr.parse::<SocketAddr>().unwrap())
+        );
+
+        std::fs::write(&path, "A.ROOT-SERVERS.NET. 3600000 A 198.41.0.4\n")?;
+        assert!(load_root_hints(&path).is_err());
+        std::fs::write(&path, ". 3600000 NS A.ROOT-SERVERS.NET.\n")?;
+        assert!(load_root_hints(&path).is_err());
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
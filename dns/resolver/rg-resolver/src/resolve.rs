@@ -0,0 +1,868 @@
+use crate::classify::{self, Classification};
+use crate::message::{QueryBuilder, QuestionClass, QuestionType};
+use crate::provenance::{self, AnswerSource, SecurityStatus};
+use crate::{config, net, privacy, rr, system};
+use rg_resolver_common::DomainName;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// The resource records answering a question.
+pub type RRset = Vec<rr::ResourceRecord>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What a resolver found out about a question, sorted as [classify::classify] sorts an
+/// upstream's response, so a name that doesn't exist is never taken for one without records.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Answer {
+    /// The records answering the question, preceded by any CNAMEs leading to them. A resolver
+    /// that doesn't follow CNAMEs may answer with only the first of them.
+    Records(RRset),
+    /// The name, or the end of the CNAMEs leading from it, has no records of the asked-for
+    /// type. soa is that of the zone saying so, which bounds how long that may be cached
+    /// (RFC 2308), if the resolver has one.
+    NoData {
+        cnames: RRset,
+        soa: Option<rr::ResourceRecord>,
+    },
+    /// The name, or the end of the CNAMEs leading from it, doesn't exist.
+    NxDomain {
+        cnames: RRset,
+        soa: Option<rr::ResourceRecord>,
+    },
+}
+
+impl Answer {
+    /// A negative answer without CNAMEs or an SOA, from a resolver that has none to give.
+    pub fn no_data() -> Self {
+        Answer::NoData {
+            cnames: RRset::new(),
+            soa: None,
+        }
+    }
+
+    /// The records of the answer section: the CNAMEs and records, or only the CNAMEs of a
+    /// negative answer.
+    pub fn records(&self) -> &[rr::ResourceRecord] {
+        match self {
+            Answer::Records(records) => records,
+            Answer::NoData { cnames, .. } | Answer::NxDomain { cnames, .. } => cnames,
+        }
+    }
+
+    pub fn into_records(self) -> RRset {
+        match self {
+            Answer::Records(records) => records,
+            Answer::NoData { cnames, .. } | Answer::NxDomain { cnames, .. } => cnames,
+        }
+    }
+
+    /// The SOA of the zone saying the name or its records don't exist.
+    pub fn soa(&self) -> Option<&rr::ResourceRecord> {
+        match self {
+            Answer::Records(_) => None,
+            Answer::NoData { soa, .. } | Answer::NxDomain { soa, .. } => soa.as_ref(),
+        }
+    }
+}
+
+/// A source of answers: the nameserver, the system resolver, a local store, etc.
+///
+/// The future is boxed so the trait stays dyn compatible and resolvers can be chained.
+pub trait Resolve: Send + Sync {
+    /// A short name for logging which resolver answered.
+    fn name(&self) -> &str;
+
+    /// Looks up the records of type qtype for name.
+    ///
+    /// Returns Ok(None) if this resolver has no answer, so the next one should be tried.
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>>;
+}
+
+impl<R: Resolve + ?Sized> Resolve for Box<R> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        (**self).lookup(name, qtype)
+    }
+}
+
+impl<R: Resolve + ?Sized> Resolve for Arc<R> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        (**self).lookup(name, qtype)
+    }
+}
+
+/// Forwards queries to the configured nameservers; see [net::forward].
+///
+/// A CNAME chain that ends before reaching records of the asked-for type is followed by
+/// querying for where it ends, up to the configured chain length. The answer holds the whole
+/// chain followed by the records at its end, or what the nameserver said about its end if
+/// there are none.
+pub struct Forwarder;
+
+impl Resolve for Forwarder {
+    fn name(&self) -> &str {
+        "forwarder"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let max_cname_chain = config::upstreams()?.max_cname_chain;
+            let mut cnames = RRset::new();
+            let mut sname = name.parse::<DomainName>()?;
+            loop {
+                let query = QueryBuilder::new()
+                    .recursion_desired(true)
+                    .question(&sname, qtype, QuestionClass::RrClass(rr::Class::IN))
+                    .build();
+                let response = net::forward(&query).await?;
+                provenance::record_answer_source(AnswerSource::Upstream);
+                match classify::classify(&query, &response) {
+                    Classification::Answer(rrset) => {
+                        cnames.extend(rrset);
+                        return Ok(Some(Answer::Records(cnames)));
+                    }
+                    Classification::Cname { chain, target } => {
+                        cnames.extend(chain);
+                        if cnames.iter().any(|rr| *rr.name() == target) {
+                            anyhow::bail!(
+                                "forwarding {}: CNAME chain loops at {}",
+                                privacy::qname(name),
+                                privacy::qname(&target.to_string())
+                            );
+                        }
+                        if cnames.len() > max_cname_chain {
+                            anyhow::bail!(
+                                "forwarding {}: CNAME chain is longer than {max_cname_chain}",
+                                privacy::qname(name)
+                            );
+                        }
+                        sname = target;
+                    }
+                    Classification::NoData { soa } => {
+                        return Ok(Some(Answer::NoData { cnames, soa }));
+                    }
+                    Classification::NxDomain { soa } => {
+                        return Ok(Some(Answer::NxDomain { cnames, soa }));
+                    }
+                    Classification::Referral { zone, .. } => {
+                        anyhow::bail!(
+                            "forwarding {}: nameserver referred the query to {zone}",
+                            privacy::qname(name)
+                        )
+                    }
+                    Classification::Error(e) => {
+                        anyhow::bail!("forwarding {}: {e}", privacy::qname(name))
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Answers address queries using the OS resolver.
+///
+/// The OS resolver doesn't report TTLs, so the records it produces have a TTL of 0. A name
+/// it's already being asked about gets no answer, since when this resolver is the OS's
+/// nameserver that's the OS resolver's own query come back (see [system::InFlight]).
+pub struct System;
+
+impl Resolve for System {
+    fn name(&self) -> &str {
+        "system"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let r#type = match qtype {
+                QuestionType::RrType(r#type @ (rr::Type::A | rr::Type::AAAA)) => r#type,
+                _ => return Ok(None),
+            };
+            let owner = name.parse::<DomainName>()?;
+            let Some(_in_flight) = system::InFlight::begin(name) else {
+                debug!(
+                    "Not asking the OS resolver for {} again while it's looking it up",
+                    privacy::qname(name)
+                );
+                return Ok(None);
+            };
+            let lookup_name = name.to_string();
+            let addrs = tokio::task::spawn_blocking(move || system::lookup_addresses(&lookup_name))
+                .await??;
+            provenance::record_answer_source(AnswerSource::SystemFallback);
+            let rrset = addrs
+                .into_iter()
+                .filter_map(|addr| match (r#type, addr) {
+                    (rr::Type::A, IpAddr::V4(addr)) => Some(rr::Data::A(addr)),
+                    (rr::Type::AAAA, IpAddr::V6(addr)) => Some(rr::Data::AAAA(addr)),
+                    _ => None,
+                })
+                .map(|data| rr::ResourceRecord::new(owner.clone(), r#type, rr::Class::IN, 0, data))
+                .collect::<anyhow::Result<RRset>>()?;
+            // * The OS resolver only has addresses, so can't say whether the name exists.
+            if rrset.is_empty() {
+                return Ok(Some(Answer::no_data()));
+            }
+            Ok(Some(Answer::Records(rrset)))
+        })
+    }
+}
+
+/// Answers from a fixed set of records.
+pub struct Static {
+    name: String,
+    records: Vec<rr::ResourceRecord>,
+}
+
+impl Static {
+    pub fn new(name: &str, records: Vec<rr::ResourceRecord>) -> Self {
+        Static {
+            name: name.to_string(),
+            records,
+        }
+    }
+}
+
+impl Resolve for Static {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let name = name.parse::<DomainName>()?;
+            let rrset = self
+                .records
+                .iter()
+                .filter(|rr| *rr.name() == name && qtype.matches(rr.r#type()))
+                .cloned()
+                .collect::<RRset>();
+            if rrset.is_empty() {
+                return Ok(None);
+            }
+            provenance::record_answer_source(AnswerSource::Authoritative);
+            Ok(Some(Answer::Records(rrset)))
+        })
+    }
+}
+
+/// Tries each resolver in order until one has an answer.
+///
+/// A resolver that fails is skipped. If none has an answer, the last failure is returned.
+pub struct Chain {
+    resolvers: Vec<Box<dyn Resolve>>,
+}
+
+impl Chain {
+    pub fn new(resolvers: Vec<Box<dyn Resolve>>) -> Self {
+        Chain { resolvers }
+    }
+}
+
+impl Resolve for Chain {
+    fn name(&self) -> &str {
+        "chain"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            privacy::global().count_query(name);
+            let logged_name = privacy::qname(name);
+            let mut last_err = None;
+            for resolver in &self.resolvers {
+                match resolver.lookup(name, qtype).await {
+                    Ok(Some(answer)) => {
+                        info!("{logged_name} answered by the {} resolver", resolver.name());
+                        return Ok(Some(answer));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("{} resolver failed for {logged_name}: {e}", resolver.name());
+                        last_err = Some(e);
+                    }
+                }
+            }
+            match last_err {
+                Some(e) => Err(e),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+/// How a resolution ended.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// Records of the asked-for type were found.
+    Answer,
+    /// The name, or the end of its CNAME chain, exists but has no records of the asked-for
+    /// type.
+    NoData,
+    /// The name, or the end of its CNAME chain, doesn't exist.
+    NxDomain,
+    /// No resolver had an answer.
+    NoAnswer,
+}
+
+/// How long a resolution took.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timing {
+    pub total: Duration,
+    /// Each name looked up, in order, and how long its lookup took.
+    pub lookups: Vec<(String, Duration)>,
+}
+
+/// Everything found while resolving a name, from [resolve].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Resolution {
+    /// The CNAMEs followed from the name asked for, in order.
+    pub cnames: RRset,
+    /// The records of the asked-for type at the end of the chain.
+    pub rrset: RRset,
+    pub outcome: Outcome,
+    /// The SOA of the zone a negative answer came from, which says how long it may be cached.
+    pub soa: Option<rr::ResourceRecord>,
+    /// Always indeterminate until DNSSEC validation is implemented.
+    pub security: SecurityStatus,
+    pub timing: Timing,
+}
+
+impl Resolution {
+    /// The name the records are at, after following the CNAMEs.
+    pub fn canonical_name(&self, name: &str) -> String {
+        match self.cnames.last().map(|rr| rr.data()) {
+            Some(rr::Data::CNAME(target)) => target.to_string(),
+            _ => name.to_string(),
+        }
+    }
+}
+
+/// Resolves name to its records of type qtype, following CNAMEs across as many lookups as it
+/// takes.
+///
+/// This is the one call most applications need rather than [Resolve::lookup], which may
+/// return only part of a CNAME chain.
+pub async fn resolve<R: Resolve + ?Sized>(
+    resolver: &R,
+    name: &str,
+    qtype: QuestionType,
+) -> anyhow::Result<Resolution> {
+    let started = Instant::now();
+    let mut resolution = Resolution {
+        cnames: Vec::new(),
+        rrset: Vec::new(),
+        outcome: Outcome::NoAnswer,
+        soa: None,
+        security: SecurityStatus::default(),
+        timing: Timing::default(),
+    };
+    let qname = name.parse::<DomainName>()?;
+    let mut current = qname.clone();
+    loop {
+        let lookup_started = Instant::now();
+        let lookup_name = current.to_string();
+        let answer = resolver.lookup(&lookup_name, qtype).await?;
+        resolution
+            .timing
+            .lookups
+            .push((lookup_name, lookup_started.elapsed()));
+        let Some(answer) = answer else {
+            break;
+        };
+
+        let (chain, found, target) = classify::follow_cnames(answer.records(), &current, qtype);
+        let followed = !chain.is_empty();
+        resolution.cnames.extend(chain);
+        if !found.is_empty() {
+            resolution.rrset = found;
+            resolution.outcome = Outcome::Answer;
+            break;
+        }
+        // * A negative answer is about the end of any CNAMEs it holds.
+        match answer {
+            Answer::NxDomain { soa, .. } => {
+                resolution.outcome = Outcome::NxDomain;
+                resolution.soa = soa;
+                break;
+            }
+            Answer::NoData { soa, .. } => {
+                resolution.outcome = Outcome::NoData;
+                resolution.soa = soa;
+                break;
+            }
+            Answer::Records(_) if !followed => {
+                resolution.outcome = Outcome::NoData;
+                break;
+            }
+            Answer::Records(_) => {}
+        }
+        // * Continue at the end of the chain, unless it loops back on itself.
+        let looped = target == qname || resolution.cnames.iter().any(|rr| *rr.name() == target);
+        if looped || resolution.cnames.len() > classify::MAX_CNAME_CHAIN {
+            anyhow::bail!(
+                "resolving {}: CNAME chain loops or is too long",
+                privacy::qname(name)
+            );
+        }
+        current = target;
+    }
+    resolution.timing.total = started.elapsed();
+    Ok(resolution)
+}
+
+/// The name whose PTR records map addr back to hostnames: 4.3.2.1.in-addr.arpa. for
+/// 1.2.3.4, and the address's nibbles in reverse under ip6.arpa. for an IPv6 address.
+///
+/// IPv4-mapped IPv6 addresses are looked up as the IPv4 address they hold.
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr.to_canonical() {
+        IpAddr::V4(addr) => {
+            let [a, b, c, d] = addr.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa.")
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::with_capacity(72);
+            for byte in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa.");
+            name
+        }
+    }
+}
+
+/// Looks up the hostnames of addr from its PTR records, following any CNAMEs on the way
+/// (RFC 2317 delegates parts of IPv4 reverse zones that way).
+///
+/// Returns no hostnames if the address has none.
+pub async fn hostnames<R: Resolve + ?Sized>(
+    resolver: &R,
+    addr: IpAddr,
+) -> anyhow::Result<Vec<String>> {
+    let resolution = resolve(
+        resolver,
+        &reverse_name(addr),
+        QuestionType::RrType(rr::Type::PTR),
+    )
+    .await?;
+    let hostnames = resolution
+        .rrset
+        .into_iter()
+        .filter_map(|rr| match rr.data() {
+            rr::Data::PTR(hostname) => Some(hostname.to_string()),
+            _ => None,
+        })
+        .collect();
+    Ok(hostnames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::UpstreamConfig;
+    use crate::testing::FakeNameserver;
+    use std::net::Ipv4Addr;
+
+    struct Failing;
+
+    impl Resolve for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            _name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async { anyhow::bail!("unreachable nameserver") })
+        }
+    }
+
+    fn a_record(name: &str, addr: Ipv4Addr) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            name.parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(addr),
+        )
+    }
+
+    const A: QuestionType = QuestionType::RrType(rr::Type::A);
+
+    #[tokio::test]
+    async fn static_lookup() -> anyhow::Result<()> {
+        let record = a_record("google.com.", Ipv4Addr::new(142, 250, 72, 14))?;
+        let store = Static::new("hosts", vec![record.clone()]);
+        assert_eq!(
+            store.lookup("Google.com.", A).await?,
+            Some(Answer::Records(vec![record]))
+        );
+        assert_eq!(store.lookup("yahoo.com.", A).await?, None);
+        assert_eq!(
+            store
+                .lookup("google.com.", QuestionType::RrType(rr::Type::MX))
+                .await?,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chain_falls_through() -> anyhow::Result<()> {
+        let first = a_record("google.com.", Ipv4Addr::new(10, 0, 0, 1))?;
+        let second = a_record("yahoo.com.", Ipv4Addr::new(10, 0, 0, 2))?;
+        let chain = Chain::new(vec![
+            Box::new(Static::new("first", vec![first.clone()])),
+            Box::new(Failing),
+            Box::new(Static::new("second", vec![second.clone()])),
+        ]);
+        assert_eq!(
+            chain.lookup("google.com.", A).await?,
+            Some(Answer::Records(vec![first]))
+        );
+        assert_eq!(
+            chain.lookup("yahoo.com.", A).await?,
+            Some(Answer::Records(vec![second]))
+        );
+        assert!(chain.lookup("bing.com.", A).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chain_without_answer() -> anyhow::Result<()> {
+        let chain = Chain::new(vec![Box::new(Static::new("empty", Vec::new()))]);
+        assert_eq!(chain.lookup("google.com.", A).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn system_lookup() -> anyhow::Result<()> {
+        let (answer, source) =
+            provenance::track_answer_source(System.lookup("localhost.", A)).await;
+        let answer = answer?;
+        assert_eq!(source, Some(AnswerSource::SystemFallback));
+        assert!(answer
+            .as_ref()
+            .map_or(&[][..], Answer::records)
+            .iter()
+            .all(|rr| matches!(rr.data(), rr::Data::A(addr) if addr.is_loopback())));
+        assert_eq!(
+            System
+                .lookup("localhost.", QuestionType::RrType(rr::Type::MX))
+                .await?,
+            None
+        );
+        // * The OS resolver's own query, come back to this resolver, isn't passed back to it.
+        let _in_flight = system::InFlight::begin("loopback.localhost.");
+        assert_eq!(System.lookup("loopback.localhost.", A).await?, None);
+        Ok(())
+    }
+
+    /// Answers with the records at a name of the asked-for type, or else its CNAME, like a
+    /// nameserver that doesn't chase CNAMEs outside its zone.
+    struct Aliases(RRset);
+
+    impl Resolve for Aliases {
+        fn name(&self) -> &str {
+            "aliases"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            name: &'a str,
+            qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async move {
+                let name = name.parse::<DomainName>()?;
+                let at_name = self
+                    .0
+                    .iter()
+                    .filter(|rr| *rr.name() == name)
+                    .collect::<Vec<_>>();
+                let answer = at_name
+                    .iter()
+                    .filter(|rr| qtype.matches(rr.r#type()))
+                    .map(|rr| (*rr).clone())
+                    .collect::<RRset>();
+                if !answer.is_empty() {
+                    return Ok(Some(Answer::Records(answer)));
+                }
+                let cnames = at_name
+                    .iter()
+                    .filter(|rr| rr.r#type() == rr::Type::CNAME)
+                    .map(|rr| (*rr).clone())
+                    .collect::<RRset>();
+                Ok((!cnames.is_empty()).then_some(Answer::Records(cnames)))
+            })
+        }
+    }
+
+    fn cname(name: &str, target: &str) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            name.parse()?,
+            rr::Type::CNAME,
+            rr::Class::IN,
+            300,
+            rr::Data::CNAME(target.parse()?),
+        )
+    }
+
+    fn soa(zone: &str) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            zone.parse()?,
+            rr::Type::SOA,
+            rr::Class::IN,
+            300,
+            rr::Data::SOA {
+                mname: format!("ns.{zone}").parse()?,
+                rname: format!("hostmaster.{zone}").parse()?,
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn resolve_follows_cnames() -> anyhow::Result<()> {
+        let www = cname("www.example.com.", "cdn.example.net.")?;
+        let cdn = cname("cdn.example.net.", "edge.example.org.")?;
+        let edge = a_record("edge.example.org.", Ipv4Addr::new(192, 0, 2, 7))?;
+        let resolver = Aliases(vec![www.clone(), cdn.clone(), edge.clone()]);
+
+        let resolution = resolve(&resolver, "www.example.com.", A).await?;
+        assert_eq!(resolution.outcome, Outcome::Answer);
+        assert_eq!(resolution.cnames, [www, cdn]);
+        assert_eq!(resolution.rrset, [edge]);
+        assert_eq!(resolution.security, SecurityStatus::Indeterminate);
+        assert_eq!(
+            resolution.canonical_name("www.example.com."),
+            "edge.example.org."
+        );
+        let looked_up = resolution
+            .timing
+            .lookups
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            looked_up,
+            ["www.example.com.", "cdn.example.net.", "edge.example.org."]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_without_records() -> anyhow::Result<()> {
+        let www = cname("www.example.com.", "gone.example.net.")?;
+        let resolver = Chain::new(vec![
+            Box::new(Aliases(vec![www.clone()])),
+            Box::new(Static::new("empty", Vec::new())),
+        ]);
+        let resolution = resolve(&resolver, "www.example.com.", A).await?;
+        assert_eq!(resolution.outcome, Outcome::NoAnswer);
+        assert_eq!(resolution.cnames, std::slice::from_ref(&www));
+        assert!(resolution.rrset.is_empty());
+
+        let resolution = resolve(&resolver, "other.example.com.", A).await?;
+        assert_eq!(resolution.outcome, Outcome::NoAnswer);
+        assert_eq!(
+            resolution.canonical_name("other.example.com."),
+            "other.example.com."
+        );
+
+        // * A negative answer says whether there are no records or no name at all.
+        let resolution = resolve(&Empty, "empty.example.com.", A).await?;
+        assert_eq!(resolution.outcome, Outcome::NoData);
+        assert_eq!(resolution.soa, None);
+        let resolver = Chain::new(vec![Box::new(Aliases(vec![www.clone()])), Box::new(Empty)]);
+        let resolution = resolve(&resolver, "www.example.com.", A).await?;
+        assert_eq!(resolution.outcome, Outcome::NxDomain);
+        assert_eq!(resolution.cnames, [www]);
+        assert_eq!(resolution.soa, Some(soa("example.net.")?));
+        Ok(())
+    }
+
+    /// Says names under gone. labels don't exist, and every other name has no records.
+    struct Empty;
+
+    impl Resolve for Empty {
+        fn name(&self) -> &str {
+            "empty"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async move {
+                if !name.starts_with("gone.") {
+                    return Ok(Some(Answer::no_data()));
+                }
+                Ok(Some(Answer::NxDomain {
+                    cnames: RRset::new(),
+                    soa: Some(soa("example.net.")?),
+                }))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn forwarder_follows_cnames() -> anyhow::Result<()> {
+        let www = cname("www.example.com.", "cdn.example.net.")?;
+        let cdn = cname("cdn.example.net.", "edge.example.org.")?;
+        let edge = a_record("edge.example.org.", Ipv4Addr::new(192, 0, 2, 7))?;
+        let looping = vec![
+            cname("a.example.", "b.example.")?,
+            cname("b.example.", "a.example.")?,
+        ];
+        let gone = cname("gone.example.com.", "nowhere.example.com.")?;
+        let soa = soa("example.com.")?;
+        let mut records = vec![
+            www.clone(),
+            cdn.clone(),
+            edge.clone(),
+            gone.clone(),
+            soa.clone(),
+        ];
+        records.extend(looping);
+        let server = FakeNameserver::new(records)
+            .one_cname_per_response()
+            .start()
+            .await?;
+        let upstream = server.addr;
+        config::set_upstreams(UpstreamConfig::parse(&upstream.to_string())?);
+
+        // * Each response holds one CNAME, so the chain takes three queries.
+        let answer = Forwarder.lookup("www.example.com.", A).await?;
+        assert_eq!(answer, Some(Answer::Records(vec![www, cdn, edge])));
+        let e = Forwarder.lookup("a.example.", A).await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "forwarding a.example.: CNAME chain loops at a.example."
+        );
+
+        // * What the nameserver says about where a chain ends is kept, with its SOA.
+        let answer = Forwarder.lookup("gone.example.com.", A).await?;
+        assert_eq!(
+            answer,
+            Some(Answer::NxDomain {
+                cnames: vec![gone],
+                soa: Some(soa.clone())
+            })
+        );
+        let mx = QuestionType::RrType(rr::Type::MX);
+        let answer = Forwarder.lookup("edge.example.org.", mx).await?;
+        assert_eq!(
+            answer,
+            Some(Answer::NoData {
+                cnames: vec![],
+                soa: Some(soa)
+            })
+        );
+
+        config::set_upstreams(UpstreamConfig::parse(&format!(
+            "{upstream}\nmax-cname-chain 1"
+        ))?);
+        assert!(Forwarder.lookup("www.example.com.", A).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            "1.2.0.192.in-addr.arpa."
+        );
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        );
+        assert_eq!(
+            reverse_name("::ffff:192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa."
+        );
+    }
+
+    #[tokio::test]
+    async fn reverse_lookup() -> anyhow::Result<()> {
+        let ptr = |name: &str, hostname: &str| -> anyhow::Result<rr::ResourceRecord> {
+            rr::ResourceRecord::new(
+                name.parse()?,
+                rr::Type::PTR,
+                rr::Class::IN,
+                300,
+                rr::Data::PTR(hostname.parse()?),
+            )
+        };
+        // * The address is in a classless delegation, so its PTR is reached through a CNAME.
+        let resolver = Aliases(vec![
+            cname("1.2.0.192.in-addr.arpa.", "1.0-63.2.0.192.in-addr.arpa.")?,
+            ptr("1.0-63.2.0.192.in-addr.arpa.", "www.example.com.")?,
+        ]);
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(hostnames(&resolver, addr).await?, ["www.example.com."]);
+        assert!(hostnames(&Empty, addr).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resolve_detects_loops() -> anyhow::Result<()> {
+        let resolver = Aliases(vec![
+            cname("a.example.", "b.example.")?,
+            cname("b.example.", "a.example.")?,
+        ]);
+        assert!(resolve(&resolver, "a.example.", A).await.is_err());
+
+        // * So does a chain longer than the limit, one CNAME per lookup.
+        let chain = (0..=classify::MAX_CNAME_CHAIN)
+            .map(|i| cname(&format!("{i}.example."), &format!("{}.example.", i + 1)))
+            .collect::<anyhow::Result<RRset>>()?;
+        assert!(resolve(&Aliases(chain), "0.example.", A).await.is_err());
+        Ok(())
+    }
+}
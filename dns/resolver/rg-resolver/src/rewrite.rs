@@ -0,0 +1,274 @@
+use crate::message::QuestionType;
+use crate::resolve::{Answer, BoxFuture, RRset, Resolve};
+use crate::rr;
+use crate::view::{self, ClientNet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Operator-configured rules mapping resolved addresses to others before they're returned,
+/// e.g. so clients on the LAN reach a server by its internal address rather than through the
+/// router's public one (NAT hairpinning).
+#[derive(Debug, Default)]
+pub struct AddressRewrites {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    from: IpAddr,
+    to: IpAddr,
+    /// The clients the rule applies to, or every client if empty.
+    clients: Vec<ClientNet>,
+    /// The number of records this rule rewrote.
+    hits: AtomicU64,
+}
+
+impl AddressRewrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses rules, one per line, of the form `<address> <replacement> [for <network>...]`.
+    ///
+    /// For example:
+    ///   203.0.113.7 192.168.1.7 for 192.168.1.0/24
+    ///   2001:db8::7 fd00::7
+    ///
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(config: &str) -> anyhow::Result<Self> {
+        let mut rewrites = Self::new();
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error =
+                |e: String| anyhow::anyhow!("parsing address rewrites: line {}: {e}", line_num + 1);
+            let mut fields = line.split_whitespace();
+            let (Some(from), Some(to)) = (fields.next(), fields.next()) else {
+                return Err(error(String::from(
+                    "expected an address and its replacement",
+                )));
+            };
+            let from = from
+                .parse::<IpAddr>()
+                .map_err(|e| error(format!("{from}: {e}")))?;
+            let to = to
+                .parse::<IpAddr>()
+                .map_err(|e| error(format!("{to}: {e}")))?;
+            let clients = match fields.next() {
+                None => Vec::new(),
+                Some("for") => fields
+                    .map(ClientNet::parse)
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .map_err(|e| error(e.to_string()))?,
+                Some(field) => return Err(error(format!("expected for, found {field}"))),
+            };
+            rewrites
+                .add(from, to, clients)
+                .map_err(|e| error(e.to_string()))?;
+        }
+        Ok(rewrites)
+    }
+
+    /// Rewrites from to to in answers for clients in clients, or for every client if it's
+    /// empty. Rules are tried in the order they're added, and the first that matches applies.
+    pub fn add(&mut self, from: IpAddr, to: IpAddr, clients: Vec<ClientNet>) -> anyhow::Result<()> {
+        if from.is_ipv4() != to.is_ipv4() {
+            anyhow::bail!("can't rewrite {from} to {to}, an address of another family");
+        }
+        self.rules.push(Rule {
+            from,
+            to,
+            clients,
+            hits: AtomicU64::new(0),
+        });
+        Ok(())
+    }
+
+    /// Rewrites the A and AAAA records in rrset as the rules for client say.
+    pub fn apply(&self, client: IpAddr, rrset: RRset) -> RRset {
+        if self.rules.is_empty() {
+            return rrset;
+        }
+        rrset
+            .into_iter()
+            .map(|rr| {
+                let addr = match rr.data() {
+                    rr::Data::A(addr) => IpAddr::V4(*addr),
+                    rr::Data::AAAA(addr) => IpAddr::V6(*addr),
+                    _ => return rr,
+                };
+                let Some(rule) = self.rules.iter().find(|rule| {
+                    rule.from == addr
+                        && (rule.clients.is_empty()
+                            || rule.clients.iter().any(|net| net.contains(client)))
+                }) else {
+                    return rr;
+                };
+                rule.hits.fetch_add(1, Ordering::Relaxed);
+                let data = match rule.to {
+                    IpAddr::V4(to) => rr::Data::A(to),
+                    IpAddr::V6(to) => rr::Data::AAAA(to),
+                };
+                rr::ResourceRecord::new(rr.name().clone(), rr.r#type(), rr.class(), rr.ttl(), data)
+                    .expect("rules only rewrite addresses to addresses of the same family")
+            })
+            .collect()
+    }
+
+    /// The number of records each rule has rewritten, by rule in the order added, e.g.
+    /// `203.0.113.7 -> 192.168.1.7`.
+    pub fn hit_counts(&self) -> Vec<(String, u64)> {
+        self.rules
+            .iter()
+            .map(|rule| {
+                (
+                    format!("{} -> {}", rule.from, rule.to),
+                    rule.hits.load(Ordering::Relaxed),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Applies address rewrites to the inner resolver's answers, for the client whose request
+/// made the lookup (see [view::with_client]).
+pub struct Rewritten<R> {
+    inner: R,
+    rewrites: Arc<AddressRewrites>,
+}
+
+impl<R: Resolve> Rewritten<R> {
+    pub fn new(inner: R, rewrites: Arc<AddressRewrites>) -> Self {
+        Rewritten { inner, rewrites }
+    }
+}
+
+impl<R: Resolve> Resolve for Rewritten<R> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let answer = self.inner.lookup(name, qtype).await?;
+            Ok(answer.map(|answer| match answer {
+                Answer::Records(rrset) => {
+                    Answer::Records(self.rewrites.apply(view::client(), rrset))
+                }
+                negative => negative,
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::Static;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn a(addr: Ipv4Addr) -> rr::ResourceRecord {
+        rr::ResourceRecord::new(
+            "nas.example.".parse().unwrap(),
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(addr),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn rewrites_for_matching_clients() -> anyhow::Result<()> {
+        let rewrites = AddressRewrites::parse(
+            "# Reach the NAS directly from the LAN.\n\
+             203.0.113.7 192.168.1.7 for 192.168.1.0/24 10.0.0.0/8\n\
+             \n\
+             2001:db8::7 fd00::7\n",
+        )?;
+        let public = a(Ipv4Addr::new(203, 0, 113, 7));
+        let other = a(Ipv4Addr::new(203, 0, 113, 8));
+
+        let rrset = rewrites.apply(ip("192.168.1.20"), vec![public.clone(), other.clone()]);
+        assert_eq!(rrset, vec![a(Ipv4Addr::new(192, 168, 1, 7)), other.clone()]);
+        // * Clients outside the LAN still get the public address.
+        let rrset = rewrites.apply(ip("198.51.100.1"), vec![public.clone()]);
+        assert_eq!(rrset, vec![public]);
+
+        let v6 = rr::ResourceRecord::new(
+            "nas.example.".parse()?,
+            rr::Type::AAAA,
+            rr::Class::IN,
+            300,
+            rr::Data::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7)),
+        )?;
+        let rrset = rewrites.apply(ip("198.51.100.1"), vec![v6]);
+        assert_eq!(
+            rrset[0].data(),
+            &rr::Data::AAAA(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 7))
+        );
+
+        assert_eq!(
+            rewrites.hit_counts(),
+            vec![
+                (String::from("203.0.113.7 -> 192.168.1.7"), 1),
+                (String::from("2001:db8::7 -> fd00::7"), 1)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(AddressRewrites::parse("203.0.113.7").is_err());
+        assert!(AddressRewrites::parse("203.0.113.7 nas").is_err());
+        assert!(AddressRewrites::parse("203.0.113.7 fd00::7").is_err());
+        assert!(AddressRewrites::parse("203.0.113.7 192.168.1.7 to 10.0.0.0/8").is_err());
+        assert!(AddressRewrites::parse("203.0.113.7 192.168.1.7 for lan").is_err());
+    }
+
+    #[tokio::test]
+    async fn rewrites_answers() -> anyhow::Result<()> {
+        let mut rewrites = AddressRewrites::new();
+        rewrites.add(ip("203.0.113.7"), ip("192.168.1.7"), Vec::new())?;
+        rewrites.add(
+            ip("203.0.113.8"),
+            ip("192.168.1.8"),
+            vec![ClientNet::parse("127.0.0.0/8")?],
+        )?;
+        let public = vec![
+            a(Ipv4Addr::new(203, 0, 113, 7)),
+            a(Ipv4Addr::new(203, 0, 113, 8)),
+        ];
+        let resolver = Rewritten::new(Static::new("lan", public), Arc::new(rewrites));
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let answer = view::with_client(ip("192.168.1.20"), resolver.lookup("nas.example.", qtype));
+        assert_eq!(
+            answer.await?,
+            Some(Answer::Records(vec![
+                a(Ipv4Addr::new(192, 168, 1, 7)),
+                a(Ipv4Addr::new(203, 0, 113, 8))
+            ]))
+        );
+        // * This host's own lookups are from loopback.
+        assert_eq!(
+            resolver.lookup("nas.example.", qtype).await?,
+            Some(Answer::Records(vec![
+                a(Ipv4Addr::new(192, 168, 1, 7)),
+                a(Ipv4Addr::new(192, 168, 1, 8))
+            ]))
+        );
+        Ok(())
+    }
+}
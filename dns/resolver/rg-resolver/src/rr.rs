@@ -1,11 +1,13 @@
-use crate::name;
+use crate::wire::{self, Writer};
+use crate::{encoding, name};
 use anyhow::Context;
 use bytes::{Buf, BufMut};
-use std::net::Ipv4Addr;
+use rg_resolver_common::DomainName;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ResourceRecord {
-    name: String,
+    name: DomainName,
     r#type: Type,
     class: Class,
     ttl: i32,
@@ -14,7 +16,7 @@ pub struct ResourceRecord {
 
 impl ResourceRecord {
     pub fn new(
-        name: String,
+        name: DomainName,
         r#type: Type,
         class: Class,
         ttl: i32,
@@ -37,6 +39,19 @@ impl ResourceRecord {
             Type::MINFO => matches!(data, Data::MINFO { .. }),
             Type::MX => matches!(data, Data::MX { .. }),
             Type::TXT => matches!(data, Data::TXT(_)),
+            Type::AAAA => matches!(data, Data::AAAA(_)),
+            Type::DS => matches!(data, Data::DS { .. }),
+            Type::RRSIG => matches!(data, Data::RRSIG { .. }),
+            Type::NSEC => matches!(data, Data::NSEC { .. }),
+            Type::DNSKEY => matches!(data, Data::DNSKEY { .. }),
+            Type::NSEC3 => matches!(data, Data::NSEC3 { .. }),
+            Type::CAA => matches!(data, Data::CAA { .. }),
+            Type::NAPTR => matches!(data, Data::NAPTR { .. }),
+            Type::SVCB => matches!(data, Data::SVCB(_)),
+            Type::HTTPS => matches!(data, Data::HTTPS(_)),
+            Type::Unknown(code) => {
+                matches!(data, Data::Unknown { type_code, .. } if type_code == code)
+            }
         };
         if !types_match {
             anyhow::bail!("creating RR: type doesn't match data type");
@@ -52,8 +67,8 @@ impl ResourceRecord {
         Ok(rr)
     }
 
-    pub fn name(&self) -> &str {
-        self.name.as_str()
+    pub fn name(&self) -> &DomainName {
+        &self.name
     }
 
     pub fn r#type(&self) -> Type {
@@ -72,6 +87,18 @@ impl ResourceRecord {
         &self.data
     }
 
+    /// This record with its TTL replaced, e.g. by the time left before a cached copy expires.
+    pub fn with_ttl(mut self, ttl: i32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// This record with its owner name replaced, e.g. by the same name in another case.
+    pub fn with_name(mut self, name: DomainName) -> Self {
+        self.name = name;
+        self
+    }
+
     /// msg must point to the very first byte of the message.
     pub fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<ResourceRecord> {
         let name = name::parse(msg, unparsed)?;
@@ -108,9 +135,44 @@ impl ResourceRecord {
         buf.append(&mut data);
         Ok(buf)
     }
+
+    /// Serializes the record into the start of buf, returning the number of bytes written.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let mut w = Writer::new(buf);
+        match self.write(&mut w) {
+            Ok(()) => Ok(w.len()),
+            Err(e) if wire::is_full(&e) => anyhow::bail!("serializing RR: buffer too small"),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        name::serialize_into(&self.name, None, w)?;
+        w.put_u16(self.r#type.serialize())?;
+        w.put_u16(self.class.serialize())?;
+        w.put_i32(self.ttl)?;
+        // * The data length is filled in once the data is written.
+        let len_offset = w.len();
+        w.put_u16(0)?;
+        self.data.write(w)?;
+        w.patch_u16(len_offset, (w.len() - len_offset - 2) as u16);
+        Ok(())
+    }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+impl std::fmt::Display for ResourceRecord {
+    /// The record in presentation format, as dig writes it: name, TTL, class, type and data,
+    /// separated by tabs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name, self.ttl, self.class, self.r#type, self.data
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     A,
     NS,
@@ -128,6 +190,22 @@ pub enum Type {
     MINFO,
     MX,
     TXT,
+    AAAA,
+    // The DNSSEC types (RFC 4034 and RFC 5155). They're parsed so responses from signed zones
+    // can be read, but nothing is validated yet.
+    DS,
+    RRSIG,
+    NSEC,
+    DNSKEY,
+    NSEC3,
+
+    CAA,
+    NAPTR,
+    SVCB,
+    HTTPS,
+    /// Any other data type (RFC 3597), never one of the codes above. Its records are passed
+    /// along without being understood.
+    Unknown(u16),
 }
 
 impl Type {
@@ -153,7 +231,20 @@ impl Type {
             14 => Ok(MINFO),
             15 => Ok(MX),
             16 => Ok(TXT),
-            n => Err(anyhow::anyhow!("invalid RR type '{n}'")),
+            28 => Ok(AAAA),
+            43 => Ok(DS),
+            46 => Ok(RRSIG),
+            47 => Ok(NSEC),
+            48 => Ok(DNSKEY),
+            35 => Ok(NAPTR),
+            50 => Ok(NSEC3),
+            64 => Ok(SVCB),
+            65 => Ok(HTTPS),
+            257 => Ok(CAA),
+            // * 0 is reserved and 128 to 255 are meta types, like OPT and the QTYPEs, which
+            // * no record holds data of.
+            n @ (0 | 128..=255) => Err(anyhow::anyhow!("invalid RR type '{n}'")),
+            n => Ok(Unknown(n)),
         }
     }
 
@@ -176,11 +267,79 @@ impl Type {
             MINFO => 14,
             MX => 15,
             TXT => 16,
+            AAAA => 28,
+            DS => 43,
+            RRSIG => 46,
+            NSEC => 47,
+            DNSKEY => 48,
+            NSEC3 => 50,
+            CAA => 257,
+            NAPTR => 35,
+            SVCB => 64,
+            HTTPS => 65,
+            Unknown(code) => *code,
+        }
+    }
+}
+
+impl std::str::FromStr for Type {
+    type Err = anyhow::Error;
+
+    /// Parses a type's mnemonic, of either case, or the TYPE<n> form of any type.
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        use Type::*;
+        let r#type = match text.to_ascii_uppercase().as_str() {
+            "A" => A,
+            "NS" => NS,
+            "MD" => MD,
+            "MF" => MF,
+            "CNAME" => CNAME,
+            "SOA" => SOA,
+            "MB" => MB,
+            "MG" => MG,
+            "MR" => MR,
+            "NULL" => NULL,
+            "WKS" => WKS,
+            "PTR" => PTR,
+            "HINFO" => HINFO,
+            "MINFO" => MINFO,
+            "MX" => MX,
+            "TXT" => TXT,
+            "AAAA" => AAAA,
+            "DS" => DS,
+            "RRSIG" => RRSIG,
+            "NSEC" => NSEC,
+            "DNSKEY" => DNSKEY,
+            "NSEC3" => NSEC3,
+            "CAA" => CAA,
+            "NAPTR" => NAPTR,
+            "SVCB" => SVCB,
+            "HTTPS" => HTTPS,
+            upper => {
+                let code = upper
+                    .strip_prefix("TYPE")
+                    .and_then(|code| code.parse::<u16>().ok())
+                    .ok_or_else(|| anyhow::anyhow!("unknown RR type {text}"))?;
+                // * TYPE1 is A, not an unknown type.
+                Type::parse(&mut &code.to_be_bytes()[..])?
+            }
+        };
+        Ok(r#type)
+    }
+}
+
+impl std::fmt::Display for Type {
+    /// The type's mnemonic, or TYPE followed by its code if it has none here (RFC 3597
+    /// section 5).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Unknown(code) => write!(f, "TYPE{code}"),
+            known => write!(f, "{known:?}"),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Class {
     IN,
     CS,
@@ -213,45 +372,219 @@ impl Class {
     }
 }
 
+impl std::fmt::Display for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::str::FromStr for Class {
+    type Err = anyhow::Error;
+
+    /// Parses a class's mnemonic, of either case.
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        match text.to_ascii_uppercase().as_str() {
+            "IN" => Ok(Class::IN),
+            "CS" => Ok(Class::CS),
+            "CH" => Ok(Class::CH),
+            "HS" => Ok(Class::HS),
+            _ => Err(anyhow::anyhow!("unknown RR class {text}")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Data {
     A(Ipv4Addr),
-    NS(String),
-    MD(String),
-    MF(String),
-    CNAME(String),
+    NS(DomainName),
+    MD(DomainName),
+    MF(DomainName),
+    CNAME(DomainName),
     SOA {
-        mname: String,
-        rname: String,
+        mname: DomainName,
+        rname: DomainName,
         serial: u32,
         refresh: u32,
         retry: u32,
         expire: u32,
         minimum: i32,
     },
-    MB(String),
-    MG(String),
-    MR(String),
+    MB(DomainName),
+    MG(DomainName),
+    MR(DomainName),
     NULL(Vec<u8>),
     WKS {
         address: Ipv4Addr,
         protocol: u8,
         bit_map: Vec<u8>,
     },
-    PTR(String),
+    PTR(DomainName),
     HINFO {
         cpu: String,
         os: String,
     },
     MINFO {
-        rmailbx: String,
-        emailbx: String,
+        rmailbx: DomainName,
+        emailbx: DomainName,
     },
     MX {
         preference: i16,
-        exchange: String,
+        exchange: DomainName,
     },
     TXT(Vec<String>),
+    AAAA(Ipv6Addr),
+    /// A digest of a child zone's DNSKEY, held by its parent (RFC 4034 section 5).
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+    },
+    /// A signature over the RRset of type_covered at the record's name (RFC 4034 section 3).
+    /// The times are seconds since the epoch, modulo 2^32.
+    RRSIG {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        signature_expiration: u32,
+        signature_inception: u32,
+        key_tag: u16,
+        signer_name: DomainName,
+        signature: Vec<u8>,
+    },
+    /// The next name in the zone, and the types at the record's name (RFC 4034 section 4).
+    /// Types are kept as their codes, since they may not be ones this module knows.
+    NSEC {
+        next_domain_name: DomainName,
+        types: Vec<u16>,
+    },
+    /// A zone's public key (RFC 4034 section 2).
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+    },
+    /// Like NSEC, but between hashed names (RFC 5155 section 3).
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed_owner_name: Vec<u8>,
+        types: Vec<u16>,
+    },
+    /// Which certificate authorities may issue for the name (RFC 8659).
+    CAA {
+        flags: u8,
+        tag: String,
+        value: Vec<u8>,
+    },
+    /// A rule rewriting a string into a name or URI (RFC 3403).
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: String,
+        services: String,
+        regexp: String,
+        replacement: DomainName,
+    },
+    SVCB(ServiceBinding),
+    /// An SVCB record for the https scheme, which browsers query before connecting.
+    HTTPS(ServiceBinding),
+    /// The data of a type this module doesn't understand, kept as it arrived so it can be
+    /// cached and relayed. Such data never holds compressed names (RFC 3597 section 4).
+    Unknown {
+        type_code: u16,
+        rdata: Vec<u8>,
+    },
+}
+
+/// Where and how to reach a service, as SVCB and HTTPS records say (RFC 9460).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceBinding {
+    /// 0 for an alias to target, otherwise the preference of this endpoint, lowest first.
+    pub priority: u16,
+    /// The root name for the record's own name.
+    pub target: DomainName,
+    /// In increasing order of key.
+    pub params: Vec<SvcParam>,
+}
+
+/// A key and its value, left in wire form since most keys are read by the client, not the
+/// resolver.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvcParam {
+    pub key: u16,
+    pub value: Vec<u8>,
+}
+
+impl SvcParam {
+    pub const MANDATORY: u16 = 0;
+    pub const ALPN: u16 = 1;
+    pub const NO_DEFAULT_ALPN: u16 = 2;
+    pub const PORT: u16 = 3;
+    pub const IPV4HINT: u16 = 4;
+    pub const ECH: u16 = 5;
+    pub const IPV6HINT: u16 = 6;
+}
+
+impl ServiceBinding {
+    fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Self> {
+        if unparsed.remaining() < 2 {
+            anyhow::bail!("incomplete priority");
+        }
+        let priority = unparsed.get_u16();
+        let target = name::parse(msg, unparsed).with_context(|| "invalid target")?;
+        let mut params: Vec<SvcParam> = Vec::new();
+        while unparsed.has_remaining() {
+            if unparsed.remaining() < 4 {
+                anyhow::bail!("incomplete SvcParam");
+            }
+            let key = unparsed.get_u16();
+            let len = unparsed.get_u16() as usize;
+            if params.last().is_some_and(|last| key <= last.key) {
+                anyhow::bail!("SvcParam keys out of order");
+            }
+            if unparsed.remaining() < len {
+                anyhow::bail!("incomplete SvcParam {key} value");
+            }
+            params.push(SvcParam {
+                key,
+                value: unparsed[..len].to_vec(),
+            });
+            unparsed.advance(len);
+        }
+        Ok(ServiceBinding {
+            priority,
+            target,
+            params,
+        })
+    }
+
+    fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.put_u16(self.priority);
+        // * The target is never compressed (RFC 9460 section 2.2).
+        data.append(&mut name::serialize(&self.target, None).with_context(|| "invalid target")?);
+        let mut params = self.params.iter().collect::<Vec<_>>();
+        params.sort_by_key(|param| param.key);
+        for pair in params.windows(2) {
+            if pair[0].key == pair[1].key {
+                anyhow::bail!("SvcParam {} given twice", pair[0].key);
+            }
+        }
+        for param in params {
+            let Ok(len) = u16::try_from(param.value.len()) else {
+                anyhow::bail!("SvcParam {} value too long", param.key);
+            };
+            data.put_u16(param.key);
+            data.put_u16(len);
+            data.put_slice(&param.value);
+        }
+        Ok(data)
+    }
 }
 
 impl Data {
@@ -404,9 +737,169 @@ impl Data {
                 }
                 Ok(Data::TXT(txt_data))
             }
+            Type::AAAA => {
+                let Ok(octets) = <[u8; 16]>::try_from(data) else {
+                    anyhow::bail!("parsing RR: type AAAA RR data not 16 bytes");
+                };
+                Ok(Data::AAAA(Ipv6Addr::from(octets)))
+            }
+            Type::DS => {
+                if data.remaining() < 4 {
+                    anyhow::bail!("parsing RR: incomplete type DS RR");
+                }
+                Ok(Data::DS {
+                    key_tag: data.get_u16(),
+                    algorithm: data.get_u8(),
+                    digest_type: data.get_u8(),
+                    digest: data.to_vec(),
+                })
+            }
+            Type::RRSIG => {
+                if data.remaining() < 18 {
+                    anyhow::bail!("parsing RR: incomplete type RRSIG RR");
+                }
+                let type_covered = data.get_u16();
+                let algorithm = data.get_u8();
+                let labels = data.get_u8();
+                let original_ttl = data.get_u32();
+                let signature_expiration = data.get_u32();
+                let signature_inception = data.get_u32();
+                let key_tag = data.get_u16();
+                let signer_name = name::parse(msg, &mut data)
+                    .with_context(|| "parsing RR: type RRSIG RR invalid signer name")?;
+                Ok(Data::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    signature_expiration,
+                    signature_inception,
+                    key_tag,
+                    signer_name,
+                    signature: data.to_vec(),
+                })
+            }
+            Type::NSEC => {
+                let next_domain_name = name::parse(msg, &mut data)
+                    .with_context(|| "parsing RR: type NSEC RR invalid next domain name")?;
+                let types = TypeBitMaps::parse(&mut data)
+                    .with_context(|| "parsing RR: type NSEC RR invalid type bit maps")?;
+                Ok(Data::NSEC {
+                    next_domain_name,
+                    types,
+                })
+            }
+            Type::DNSKEY => {
+                if data.remaining() < 4 {
+                    anyhow::bail!("parsing RR: incomplete type DNSKEY RR");
+                }
+                Ok(Data::DNSKEY {
+                    flags: data.get_u16(),
+                    protocol: data.get_u8(),
+                    algorithm: data.get_u8(),
+                    public_key: data.to_vec(),
+                })
+            }
+            Type::NSEC3 => {
+                if data.remaining() < 5 {
+                    anyhow::bail!("parsing RR: incomplete type NSEC3 RR");
+                }
+                let hash_algorithm = data.get_u8();
+                let flags = data.get_u8();
+                let iterations = data.get_u16();
+                let salt = Self::parse_sized(&mut data)
+                    .with_context(|| "parsing RR: type NSEC3 RR invalid salt")?;
+                let next_hashed_owner_name = Self::parse_sized(&mut data)
+                    .with_context(|| "parsing RR: type NSEC3 RR invalid next hashed owner name")?;
+                let types = TypeBitMaps::parse(&mut data)
+                    .with_context(|| "parsing RR: type NSEC3 RR invalid type bit maps")?;
+                Ok(Data::NSEC3 {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed_owner_name,
+                    types,
+                })
+            }
+            Type::CAA => {
+                if data.remaining() < 2 {
+                    anyhow::bail!("parsing RR: incomplete type CAA RR");
+                }
+                let flags = data.get_u8();
+                let tag_len = data.get_u8() as usize;
+                if tag_len == 0 || data.remaining() < tag_len {
+                    anyhow::bail!("parsing RR: type CAA RR invalid tag");
+                }
+                let tag = String::from_utf8(data[..tag_len].to_vec())
+                    .with_context(|| "parsing RR: type CAA RR invalid tag")?;
+                data.advance(tag_len);
+                Ok(Data::CAA {
+                    flags,
+                    tag,
+                    value: data.to_vec(),
+                })
+            }
+            Type::NAPTR => {
+                if data.remaining() < 4 {
+                    anyhow::bail!("parsing RR: incomplete type NAPTR RR");
+                }
+                Ok(Data::NAPTR {
+                    order: data.get_u16(),
+                    preference: data.get_u16(),
+                    flags: CharacterString::parse(&mut data)
+                        .with_context(|| "parsing RR: type NAPTR RR invalid flags")?,
+                    services: CharacterString::parse(&mut data)
+                        .with_context(|| "parsing RR: type NAPTR RR invalid services")?,
+                    regexp: CharacterString::parse(&mut data)
+                        .with_context(|| "parsing RR: type NAPTR RR invalid regexp")?,
+                    replacement: name::parse(msg, &mut data)
+                        .with_context(|| "parsing RR: type NAPTR RR invalid replacement")?,
+                })
+            }
+            Type::SVCB => Ok(Data::SVCB(
+                ServiceBinding::parse(msg, &mut data)
+                    .with_context(|| "parsing RR: invalid type SVCB RR")?,
+            )),
+            Type::HTTPS => Ok(Data::HTTPS(
+                ServiceBinding::parse(msg, &mut data)
+                    .with_context(|| "parsing RR: invalid type HTTPS RR")?,
+            )),
+            Type::Unknown(type_code) => Ok(Data::Unknown {
+                type_code,
+                rdata: data.to_vec(),
+            }),
         }
     }
 
+    /// The data in the generic form any type can be written in (RFC 3597 section 5): \#, the
+    /// data's length, then its bytes in hex, like "\# 4 c0000201".
+    pub fn to_generic(&self) -> anyhow::Result<String> {
+        let rdata = self.serialize()?;
+        let mut generic = format!("\\# {}", rdata.len());
+        if !rdata.is_empty() {
+            generic.push(' ');
+            rdata
+                .iter()
+                .for_each(|b| generic.push_str(&format!("{b:02x}")));
+        }
+        Ok(generic)
+    }
+
+    /// Parses a field preceded by its 1-byte length, like NSEC3's salt.
+    fn parse_sized(unparsed: &mut &[u8]) -> anyhow::Result<Vec<u8>> {
+        if unparsed.remaining() == 0 {
+            anyhow::bail!("incomplete length");
+        }
+        let len = unparsed.get_u8() as usize;
+        if unparsed.remaining() < len {
+            anyhow::bail!("incomplete field");
+        }
+        let field = unparsed[..len].to_vec();
+        unparsed.advance(len);
+        Ok(field)
+    }
+
     pub fn serialize(&self) -> anyhow::Result<Vec<u8>> {
         let mut data = Vec::new();
         use Data::*;
@@ -516,9 +1009,634 @@ impl Data {
                     );
                 }
             }
+            AAAA(address) => data.put_slice(&address.octets()),
+            DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                data.put_u16(*key_tag);
+                data.put_u8(*algorithm);
+                data.put_u8(*digest_type);
+                data.put_slice(digest);
+            }
+            RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                data.put_u16(*type_covered);
+                data.put_u8(*algorithm);
+                data.put_u8(*labels);
+                data.put_u32(*original_ttl);
+                data.put_u32(*signature_expiration);
+                data.put_u32(*signature_inception);
+                data.put_u16(*key_tag);
+                data.append(
+                    &mut name::serialize(signer_name, None)
+                        .with_context(|| "serializing RR: type RRSIG RR invalid signer name")?,
+                );
+                data.put_slice(signature);
+            }
+            NSEC {
+                next_domain_name,
+                types,
+            } => {
+                data.append(
+                    &mut name::serialize(next_domain_name, None)
+                        .with_context(|| "serializing RR: type NSEC RR invalid next domain name")?,
+                );
+                data.append(&mut TypeBitMaps::serialize(types));
+            }
+            DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                data.put_u16(*flags);
+                data.put_u8(*protocol);
+                data.put_u8(*algorithm);
+                data.put_slice(public_key);
+            }
+            NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                types,
+            } => {
+                data.put_u8(*hash_algorithm);
+                data.put_u8(*flags);
+                data.put_u16(*iterations);
+                data.append(
+                    &mut Self::serialize_sized(salt)
+                        .with_context(|| "serializing RR: type NSEC3 RR invalid salt")?,
+                );
+                data.append(
+                    &mut Self::serialize_sized(next_hashed_owner_name).with_context(|| {
+                        "serializing RR: type NSEC3 RR invalid next hashed owner name"
+                    })?,
+                );
+                data.append(&mut TypeBitMaps::serialize(types));
+            }
+            CAA { flags, tag, value } => {
+                if tag.is_empty() || tag.len() > 255 {
+                    anyhow::bail!("serializing RR: type CAA RR invalid tag");
+                }
+                data.put_u8(*flags);
+                data.put_u8(tag.len() as u8);
+                data.put_slice(tag.as_bytes());
+                data.put_slice(value);
+            }
+            NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                data.put_u16(*order);
+                data.put_u16(*preference);
+                data.append(
+                    &mut CharacterString::serialize(flags)
+                        .with_context(|| "serializing RR: type NAPTR RR invalid flags")?,
+                );
+                data.append(
+                    &mut CharacterString::serialize(services)
+                        .with_context(|| "serializing RR: type NAPTR RR invalid services")?,
+                );
+                data.append(
+                    &mut CharacterString::serialize(regexp)
+                        .with_context(|| "serializing RR: type NAPTR RR invalid regexp")?,
+                );
+                // * The replacement is never compressed (RFC 3403 section 4.1).
+                data.append(
+                    &mut name::serialize(replacement, None)
+                        .with_context(|| "serializing RR: type NAPTR RR invalid replacement")?,
+                );
+            }
+            SVCB(binding) => data.append(
+                &mut binding
+                    .serialize()
+                    .with_context(|| "serializing RR: invalid type SVCB RR")?,
+            ),
+            HTTPS(binding) => data.append(
+                &mut binding
+                    .serialize()
+                    .with_context(|| "serializing RR: invalid type HTTPS RR")?,
+            ),
+            Unknown { rdata, .. } => data.put_slice(rdata),
         };
         Ok(data)
     }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        use Data::*;
+        match self {
+            A(address) => w.put_slice(&address.octets())?,
+            NS(nsdname) => name::serialize_into(nsdname, None, w)
+                .with_context(|| "serializing RR: type NS RR invalid nsdname")?,
+            MD(madname) => name::serialize_into(madname, None, w)
+                .with_context(|| "serializing RR: type MD RR invalid madname")?,
+            MF(madname) => name::serialize_into(madname, None, w)
+                .with_context(|| "serializing RR: type MF RR invalid madname")?,
+            CNAME(cname) => name::serialize_into(cname, None, w)
+                .with_context(|| "serializing RR: type CNAME RR invalid cname")?,
+            SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                name::serialize_into(mname, None, w)
+                    .with_context(|| "serializing RR: type SOA RR invalid mname")?;
+                name::serialize_into(rname, None, w)
+                    .with_context(|| "serializing RR: type SOA RR invalid rname")?;
+                w.put_u32(*serial)?;
+                w.put_u32(*refresh)?;
+                w.put_u32(*retry)?;
+                w.put_u32(*expire)?;
+                w.put_i32(*minimum)?;
+            }
+            MB(madname) => name::serialize_into(madname, None, w)
+                .with_context(|| "serializing RR: type MB RR invalid madname")?,
+            MG(mgmname) => name::serialize_into(mgmname, None, w)
+                .with_context(|| "serializing RR: type MG RR invalid mgmname")?,
+            MR(newname) => name::serialize_into(newname, None, w)
+                .with_context(|| "serializing RR: type MR RR invalid newname")?,
+            NULL(any) => w.put_slice(any)?,
+            WKS {
+                address,
+                protocol,
+                bit_map,
+            } => {
+                w.put_slice(&address.octets())?;
+                w.put_u8(*protocol)?;
+                w.put_slice(bit_map)?;
+            }
+            PTR(ptrdname) => name::serialize_into(ptrdname, None, w)
+                .with_context(|| "serializing RR: type PTR RR invalid ptrdname")?,
+            HINFO { cpu, os } => {
+                CharacterString::write(cpu, w)
+                    .with_context(|| "serializing RR: type HINFO RR invalid cpu")?;
+                CharacterString::write(os, w)
+                    .with_context(|| "serializing RR: type HINFO RR invalid os")?;
+            }
+            MINFO { rmailbx, emailbx } => {
+                name::serialize_into(rmailbx, None, w)
+                    .with_context(|| "serializing RR: type MINFO RR invalid rmailbx")?;
+                name::serialize_into(emailbx, None, w)
+                    .with_context(|| "serializing RR: type MINFO RR invalid emailbx")?;
+            }
+            MX {
+                preference,
+                exchange,
+            } => {
+                w.put_i16(*preference)?;
+                name::serialize_into(exchange, None, w)
+                    .with_context(|| "serializing RR: type MX RR invalid exchange")?;
+            }
+            TXT(txt_data) => {
+                for txt in txt_data {
+                    CharacterString::write(txt, w)
+                        .with_context(|| "serializing RR: type TXT RR invalid character string")?;
+                }
+            }
+            AAAA(address) => w.put_slice(&address.octets())?,
+            DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => {
+                w.put_u16(*key_tag)?;
+                w.put_u8(*algorithm)?;
+                w.put_u8(*digest_type)?;
+                w.put_slice(digest)?;
+            }
+            RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => {
+                w.put_u16(*type_covered)?;
+                w.put_u8(*algorithm)?;
+                w.put_u8(*labels)?;
+                w.put_u32(*original_ttl)?;
+                w.put_u32(*signature_expiration)?;
+                w.put_u32(*signature_inception)?;
+                w.put_u16(*key_tag)?;
+                // * The signer's name is never compressed (RFC 4034 section 3.1.7).
+                name::serialize_into(signer_name, None, w)
+                    .with_context(|| "serializing RR: type RRSIG RR invalid signer name")?;
+                w.put_slice(signature)?;
+            }
+            NSEC {
+                next_domain_name,
+                types,
+            } => {
+                name::serialize_into(next_domain_name, None, w)
+                    .with_context(|| "serializing RR: type NSEC RR invalid next domain name")?;
+                w.put_slice(&TypeBitMaps::serialize(types))?;
+            }
+            DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => {
+                w.put_u16(*flags)?;
+                w.put_u8(*protocol)?;
+                w.put_u8(*algorithm)?;
+                w.put_slice(public_key)?;
+            }
+            NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                types,
+            } => {
+                w.put_u8(*hash_algorithm)?;
+                w.put_u8(*flags)?;
+                w.put_u16(*iterations)?;
+                w.put_slice(
+                    &Self::serialize_sized(salt)
+                        .with_context(|| "serializing RR: type NSEC3 RR invalid salt")?,
+                )?;
+                w.put_slice(&Self::serialize_sized(next_hashed_owner_name).with_context(
+                    || "serializing RR: type NSEC3 RR invalid next hashed owner name",
+                )?)?;
+                w.put_slice(&TypeBitMaps::serialize(types))?;
+            }
+            // * None of these compress names, so their serialization is already their wire form.
+            CAA { .. } | NAPTR { .. } | SVCB(_) | HTTPS(_) => w.put_slice(&self.serialize()?)?,
+            Unknown { rdata, .. } => w.put_slice(rdata)?,
+        };
+        Ok(())
+    }
+
+    fn serialize_sized(field: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Ok(len) = u8::try_from(field.len()) else {
+            anyhow::bail!("field longer than 255 bytes");
+        };
+        let mut data = vec![len];
+        data.extend_from_slice(field);
+        Ok(data)
+    }
+}
+
+impl std::fmt::Display for Data {
+    /// The data in presentation format, as written in zone files and by dig. Types with no
+    /// text form of their own are written in the generic form; see [Data::to_generic].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Data::*;
+        match self {
+            A(address) => write!(f, "{address}"),
+            AAAA(address) => write!(f, "{address}"),
+            NS(name) | MD(name) | MF(name) | CNAME(name) | MB(name) | MG(name) | MR(name)
+            | PTR(name) => write!(f, "{name}"),
+            SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{mname} {rname} {serial} {refresh} {retry} {expire} {minimum}"
+            ),
+            WKS {
+                address,
+                protocol,
+                bit_map,
+            } => {
+                write!(f, "{address} {protocol}")?;
+                // * A bit per port, the first for port 0 (RFC 1035 section 3.4.2).
+                for (byte_idx, byte) in bit_map.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0x80 >> bit) != 0 {
+                            write!(f, " {}", byte_idx * 8 + bit)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            HINFO { cpu, os } => write!(f, "{} {}", quoted(cpu), quoted(os)),
+            MINFO { rmailbx, emailbx } => write!(f, "{rmailbx} {emailbx}"),
+            MX {
+                preference,
+                exchange,
+            } => write!(f, "{preference} {exchange}"),
+            TXT(strings) => {
+                let strings = strings.iter().map(|text| quoted(text)).collect::<Vec<_>>();
+                f.write_str(&strings.join(" "))
+            }
+            DS {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+            } => write!(
+                f,
+                "{key_tag} {algorithm} {digest_type} {}",
+                encoding::encode_hex(digest)
+            ),
+            RRSIG {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                signature_expiration,
+                signature_inception,
+                key_tag,
+                signer_name,
+                signature,
+            } => write!(
+                f,
+                "{} {algorithm} {labels} {original_ttl} {} {} {key_tag} {signer_name} {}",
+                type_of(*type_covered),
+                format_time(*signature_expiration),
+                format_time(*signature_inception),
+                encoding::encode_base64(signature)
+            ),
+            NSEC {
+                next_domain_name,
+                types,
+            } => {
+                write!(f, "{next_domain_name}")?;
+                types
+                    .iter()
+                    .try_for_each(|code| write!(f, " {}", type_of(*code)))
+            }
+            DNSKEY {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            } => write!(
+                f,
+                "{flags} {protocol} {algorithm} {}",
+                encoding::encode_base64(public_key)
+            ),
+            NSEC3 {
+                hash_algorithm,
+                flags,
+                iterations,
+                salt,
+                next_hashed_owner_name,
+                types,
+            } => {
+                // * "-" is an empty salt (RFC 5155 section 3.3).
+                let salt = if salt.is_empty() {
+                    String::from("-")
+                } else {
+                    encoding::encode_hex(salt)
+                };
+                write!(
+                    f,
+                    "{hash_algorithm} {flags} {iterations} {salt} {}",
+                    encoding::encode_base32hex(next_hashed_owner_name)
+                )?;
+                types
+                    .iter()
+                    .try_for_each(|code| write!(f, " {}", type_of(*code)))
+            }
+            CAA { flags, tag, value } => {
+                write!(f, "{flags} {tag} {}", quoted_bytes(value))
+            }
+            NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => write!(
+                f,
+                "{order} {preference} {} {} {} {replacement}",
+                quoted(flags),
+                quoted(services),
+                quoted(regexp)
+            ),
+            SVCB(binding) | HTTPS(binding) => write!(f, "{binding}"),
+            NULL(_) | Unknown { .. } => {
+                f.write_str(&self.to_generic().map_err(|_| std::fmt::Error)?)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceBinding {
+    /// The priority, target and parameters, as `key=value` (RFC 9460 section 2.1).
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.priority, self.target)?;
+        self.params
+            .iter()
+            .try_for_each(|param| write!(f, " {param}"))
+    }
+}
+
+impl std::fmt::Display for SvcParam {
+    /// The parameter as `key=value`, or as `keyN="value"` if its value isn't well formed for
+    /// its key.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = &self.value[..];
+        let list = |items: Vec<String>| items.join(",");
+        let known = match self.key {
+            SvcParam::MANDATORY if value.len().is_multiple_of(2) => Some(list(
+                value
+                    .chunks(2)
+                    .map(|key| svc_param_key(u16::from_be_bytes([key[0], key[1]])))
+                    .collect(),
+            )),
+            SvcParam::ALPN => {
+                let mut ids = Vec::new();
+                let mut rest = value;
+                while let [len, tail @ ..] = rest {
+                    let Some(id) = tail.get(..*len as usize) else {
+                        break;
+                    };
+                    ids.push(String::from_utf8_lossy(id).into_owned());
+                    rest = &tail[*len as usize..];
+                }
+                rest.is_empty().then(|| list(ids))
+            }
+            SvcParam::NO_DEFAULT_ALPN if value.is_empty() => {
+                return f.write_str(&svc_param_key(self.key));
+            }
+            SvcParam::PORT if value.len() == 2 => {
+                Some(u16::from_be_bytes([value[0], value[1]]).to_string())
+            }
+            SvcParam::IPV4HINT if value.len().is_multiple_of(4) => Some(list(
+                value
+                    .chunks(4)
+                    .map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+                    .map(|addr| addr.to_string())
+                    .collect(),
+            )),
+            SvcParam::ECH => Some(encoding::encode_base64(value)),
+            SvcParam::IPV6HINT if value.len().is_multiple_of(16) => Some(list(
+                value
+                    .chunks(16)
+                    .map(|octets| Ipv6Addr::from(<[u8; 16]>::try_from(octets).unwrap()))
+                    .map(|addr| addr.to_string())
+                    .collect(),
+            )),
+            _ => None,
+        };
+        match known {
+            Some(value) => write!(f, "{}={value}", svc_param_key(self.key)),
+            None => write!(f, "key{}={}", self.key, quoted_bytes(value)),
+        }
+    }
+}
+
+/// The name of an SVCB parameter key, or keyN for those without one (RFC 9460 section 14.3.2).
+fn svc_param_key(key: u16) -> String {
+    match key {
+        SvcParam::MANDATORY => String::from("mandatory"),
+        SvcParam::ALPN => String::from("alpn"),
+        SvcParam::NO_DEFAULT_ALPN => String::from("no-default-alpn"),
+        SvcParam::PORT => String::from("port"),
+        SvcParam::IPV4HINT => String::from("ipv4hint"),
+        SvcParam::ECH => String::from("ech"),
+        SvcParam::IPV6HINT => String::from("ipv6hint"),
+        key => format!("key{key}"),
+    }
+}
+
+/// The mnemonic of the type with code, or TYPE followed by the code, as for a meta type that
+/// no record should name but a peer sent anyway.
+fn type_of(code: u16) -> String {
+    match Type::parse(&mut &code.to_be_bytes()[..]) {
+        Ok(r#type) => r#type.to_string(),
+        Err(_) => format!("TYPE{code}"),
+    }
+}
+
+/// text as a quoted character string, with '"' and '\\' escaped and bytes that aren't
+/// printable ASCII written as \DDD (RFC 1035 section 5.1).
+fn quoted(text: &str) -> String {
+    quoted_bytes(text.as_bytes())
+}
+
+fn quoted_bytes(bytes: &[u8]) -> String {
+    let mut quoted = String::with_capacity(bytes.len() + 2);
+    quoted.push('"');
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(b as char);
+            }
+            b' '..=b'~' => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\{b:03}")),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An RRSIG time as YYYYMMDDHHmmSS in UTC (RFC 4034 section 3.2).
+fn format_time(seconds: u32) -> String {
+    let (days, time) = (i64::from(seconds / 86400), seconds % 86400);
+    // * The inverse of the day count in the zone file parser, with years counted from March.
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let m = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * m + 2) / 5 + 1;
+    let month = if m < 10 { m + 3 } else { m - 9 };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The types present at a name, as NSEC and NSEC3 records list them: a bit per type, in
+/// windows of 256 types, leaving out the windows with none (RFC 4034 section 4.1.2).
+struct TypeBitMaps;
+
+impl TypeBitMaps {
+    fn parse(unparsed: &mut &[u8]) -> anyhow::Result<Vec<u16>> {
+        let mut types = Vec::new();
+        let mut last_window = None;
+        while unparsed.has_remaining() {
+            if unparsed.remaining() < 2 {
+                anyhow::bail!("incomplete window");
+            }
+            let window = unparsed.get_u8();
+            let len = unparsed.get_u8() as usize;
+            if last_window.is_some_and(|last| window <= last) {
+                anyhow::bail!("windows out of order");
+            }
+            if !(1..=32).contains(&len) {
+                anyhow::bail!("invalid bit map length {len}");
+            }
+            if unparsed.remaining() < len {
+                anyhow::bail!("incomplete bit map");
+            }
+            for (byte_idx, byte) in unparsed[..len].iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (0x80 >> bit) != 0 {
+                        types.push(u16::from(window) << 8 | (byte_idx * 8 + bit) as u16);
+                    }
+                }
+            }
+            unparsed.advance(len);
+            last_window = Some(window);
+        }
+        Ok(types)
+    }
+
+    fn serialize(types: &[u16]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut types = types.to_vec();
+        types.sort_unstable();
+        types.dedup();
+        for window in types.chunk_by(|a, b| a >> 8 == b >> 8) {
+            let mut bit_map = [0_u8; 32];
+            let mut len = 0;
+            for r#type in window {
+                let low = (r#type & 0xff) as usize;
+                bit_map[low / 8] |= 0x80 >> (low % 8);
+                len = low / 8 + 1;
+            }
+            data.put_u8((window[0] >> 8) as u8);
+            data.put_u8(len as u8);
+            data.put_slice(&bit_map[..len]);
+        }
+        data
+    }
 }
 
 struct CharacterString;
@@ -551,6 +1669,15 @@ impl CharacterString {
         name.as_bytes().iter().for_each(|b| data.put_u8(*b));
         Ok(data)
     }
+
+    fn write(name: &str, w: &mut Writer) -> anyhow::Result<()> {
+        if name.len() > CharacterString::MAX_CHARS {
+            anyhow::bail!("string too long to be a character string");
+        }
+        w.put_u8(name.len() as u8)?;
+        w.put_slice(name.as_bytes())?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -585,11 +1712,24 @@ mod test {
         test_type!([0, 14], MINFO);
         test_type!([0, 15], MX);
         test_type!([0, 16], TXT);
+        test_type!([0, 28], AAAA);
+        test_type!([0, 43], DS);
+        test_type!([0, 46], RRSIG);
+        test_type!([0, 47], NSEC);
+        test_type!([0, 48], DNSKEY);
+        test_type!([0, 50], NSEC3);
+        test_type!([0, 35], NAPTR);
+        test_type!([0, 64], SVCB);
+        test_type!([0, 65], HTTPS);
+        test_type!([1, 1], CAA);
 
         let mut data: &[u8] = &[0, 0];
         assert!(Type::parse(&mut data).is_err());
 
         let mut data: &[u8] = &[0, 17];
+        assert_eq!(Type::parse(&mut data)?, Type::Unknown(17));
+
+        let mut data: &[u8] = &[0, 255];
         assert!(Type::parse(&mut data).is_err());
 
         let mut data: &[u8] = &[1];
@@ -637,9 +1777,10 @@ mod test {
         Ok(())
     }
 
+    #[test]
     fn parse_data() {
         // Incomplete data length.
-        let buf = vec![4];
+        let buf = [4];
         let mut unparsed = &buf[..];
         assert!(Data::parse(&buf[..], &mut unparsed, Type::A).is_err());
 
@@ -653,13 +1794,13 @@ mod test {
     }
 
     macro_rules! test_parse_data {
-        ($data:expr, $type:tt) => {
+        ($data:expr, $($type:tt)+) => {
             let mut ser_data = $data.serialize()?;
             let mut buf = Vec::new();
             buf.put_u16(ser_data.len() as u16);
             buf.append(&mut ser_data);
             let mut unparsed = &buf[..];
-            assert_eq!(Data::parse(&buf[..], &mut unparsed, Type::$type)?, $data);
+            assert_eq!(Data::parse(&buf[..], &mut unparsed, Type::$($type)+)?, $data);
             assert_eq!(
                 unsafe { unparsed.as_ptr().offset_from(buf.as_ptr()) as usize },
                 buf.len()
@@ -678,7 +1819,7 @@ mod test {
     // NS(nsdname)
     #[test]
     fn parse_data_ns() -> anyhow::Result<()> {
-        let data = Data::NS("google.com.".to_string());
+        let data = Data::NS("google.com.".parse()?);
         test_parse_data!(data, NS);
         Ok(())
     }
@@ -686,7 +1827,7 @@ mod test {
     // MD(madname)
     #[test]
     fn parse_data_md() -> anyhow::Result<()> {
-        let data = Data::MD("google.com.".to_string());
+        let data = Data::MD("google.com.".parse()?);
         test_parse_data!(data, MD);
         Ok(())
     }
@@ -694,7 +1835,7 @@ mod test {
     // MF(madname)
     #[test]
     fn parse_data_mf() -> anyhow::Result<()> {
-        let data = Data::MF("google.com.".to_string());
+        let data = Data::MF("google.com.".parse()?);
         test_parse_data!(data, MF);
         Ok(())
     }
@@ -702,7 +1843,7 @@ mod test {
     // CNAME(cname)
     #[test]
     fn parse_data_cname() -> anyhow::Result<()> {
-        let data = Data::CNAME("google.com.".to_string());
+        let data = Data::CNAME("google.com.".parse()?);
         test_parse_data!(data, CNAME);
         Ok(())
     }
@@ -719,8 +1860,8 @@ mod test {
     #[test]
     fn parse_data_soa() -> anyhow::Result<()> {
         let data = Data::SOA {
-            mname: "google.com.".to_string(),
-            rname: "amazon.com.".to_string(),
+            mname: "google.com.".parse()?,
+            rname: "amazon.com.".parse()?,
             serial: 102,
             refresh: 20,
             retry: 45,
@@ -734,7 +1875,7 @@ mod test {
     // MB(madname)
     #[test]
     fn parse_data_mb() -> anyhow::Result<()> {
-        let data = Data::MB("google.com.".to_string());
+        let data = Data::MB("google.com.".parse()?);
         test_parse_data!(data, MB);
         Ok(())
     }
@@ -742,7 +1883,7 @@ mod test {
     // MG(mgmname)
     #[test]
     fn parse_data_mg() -> anyhow::Result<()> {
-        let data = Data::MG("google.com.".to_string());
+        let data = Data::MG("google.com.".parse()?);
         test_parse_data!(data, MG);
         Ok(())
     }
@@ -750,7 +1891,7 @@ mod test {
     // MR(newname)
     #[test]
     fn parse_data_mr() -> anyhow::Result<()> {
-        let data = Data::MR("google.com.".to_string());
+        let data = Data::MR("google.com.".parse()?);
         test_parse_data!(data, MR);
         Ok(())
     }
@@ -782,7 +1923,7 @@ mod test {
     // PTR(ptrdname)
     #[test]
     fn parse_data_ptr() -> anyhow::Result<()> {
-        let data = Data::PTR("google.com.".to_string());
+        let data = Data::PTR("google.com.".parse()?);
         test_parse_data!(data, PTR);
         Ok(())
     }
@@ -802,8 +1943,8 @@ mod test {
     #[test]
     fn parse_data_minfo() -> anyhow::Result<()> {
         let data = Data::MINFO {
-            rmailbx: "google.com.".to_string(),
-            emailbx: "amazon.com.".to_string(),
+            rmailbx: "google.com.".parse()?,
+            emailbx: "amazon.com.".parse()?,
         };
         test_parse_data!(data, MINFO);
         Ok(())
@@ -817,7 +1958,7 @@ mod test {
     fn parse_data_mx() -> anyhow::Result<()> {
         let data = Data::MX {
             preference: 8,
-            exchange: "google.com.".to_string(),
+            exchange: "google.com.".parse()?,
         };
         test_parse_data!(data, MX);
         Ok(())
@@ -835,10 +1976,189 @@ mod test {
         Ok(())
     }
 
+    // AAAA(address)
+    #[test]
+    fn parse_data_aaaa() -> anyhow::Result<()> {
+        let data = Data::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0x5e, 1));
+        test_parse_data!(data, AAAA);
+
+        let mut unparsed: &[u8] = &[0, 4, 192, 0, 2, 1];
+        assert!(Data::parse(&[], &mut unparsed, Type::AAAA).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_ds() -> anyhow::Result<()> {
+        let data = Data::DS {
+            key_tag: 20326,
+            algorithm: 8,
+            digest_type: 2,
+            digest: vec![0xe0, 0x6d, 0x44, 0xb8],
+        };
+        test_parse_data!(data, DS);
+
+        let mut unparsed: &[u8] = &[0, 3, 0x4f, 0x66, 8];
+        assert!(Data::parse(&[], &mut unparsed, Type::DS).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_rrsig() -> anyhow::Result<()> {
+        let data = Data::RRSIG {
+            type_covered: Type::A.serialize(),
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 3600,
+            signature_expiration: 1_700_086_400,
+            signature_inception: 1_700_000_000,
+            key_tag: 12345,
+            signer_name: "example.com.".parse()?,
+            signature: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        test_parse_data!(data, RRSIG);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_nsec() -> anyhow::Result<()> {
+        let data = Data::NSEC {
+            next_domain_name: "host.example.com.".parse()?,
+            types: vec![1, 15, 46, 47, 1234],
+        };
+        test_parse_data!(data, NSEC);
+
+        // * A bit map longer than 32 bytes.
+        let buf = [&[0, 36, 0, 0, 33][..], &[0; 33]].concat();
+        let mut unparsed = &buf[..];
+        assert!(Data::parse(&[], &mut unparsed, Type::NSEC).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_dnskey() -> anyhow::Result<()> {
+        let data = Data::DNSKEY {
+            flags: 257,
+            protocol: 3,
+            algorithm: 13,
+            public_key: vec![0x99, 0xdb, 0x2c, 0xc1],
+        };
+        test_parse_data!(data, DNSKEY);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_nsec3() -> anyhow::Result<()> {
+        let data = Data::NSEC3 {
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 0,
+            salt: vec![0xaa, 0xbb],
+            next_hashed_owner_name: vec![0x2c; 20],
+            types: vec![2, 6, 46, 48, 51],
+        };
+        test_parse_data!(data, NSEC3);
+
+        // * No salt or types at all.
+        let data = Data::NSEC3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 10,
+            salt: vec![],
+            next_hashed_owner_name: vec![0x2c; 20],
+            types: vec![],
+        };
+        test_parse_data!(data, NSEC3);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_caa() -> anyhow::Result<()> {
+        let data = Data::CAA {
+            flags: 128,
+            tag: "issue".to_string(),
+            value: b"ca.example.net; account=230123".to_vec(),
+        };
+        test_parse_data!(data, CAA);
+
+        // * A tag can't be empty.
+        let mut unparsed: &[u8] = &[0, 3, 0, 0, b'x'];
+        assert!(Data::parse(&[], &mut unparsed, Type::CAA).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_naptr() -> anyhow::Result<()> {
+        let data = Data::NAPTR {
+            order: 100,
+            preference: 10,
+            flags: "u".to_string(),
+            services: "E2U+sip".to_string(),
+            regexp: "!^.*$!sip:info@example.com!".to_string(),
+            replacement: ".".parse()?,
+        };
+        test_parse_data!(data, NAPTR);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_svcb() -> anyhow::Result<()> {
+        let binding = ServiceBinding {
+            priority: 1,
+            target: "svc.example.com.".parse()?,
+            params: vec![
+                SvcParam {
+                    key: SvcParam::ALPN,
+                    value: b"\x02h2\x02h3".to_vec(),
+                },
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![0x1f, 0x90],
+                },
+                SvcParam {
+                    key: SvcParam::IPV4HINT,
+                    value: vec![192, 0, 2, 1],
+                },
+            ],
+        };
+        let data = Data::SVCB(binding.clone());
+        test_parse_data!(data, SVCB);
+        let data = Data::HTTPS(binding);
+        test_parse_data!(data, HTTPS);
+
+        // * An alias form record, with no params.
+        let data = Data::HTTPS(ServiceBinding {
+            priority: 0,
+            target: "pool.svc.example.".parse()?,
+            params: vec![],
+        });
+        test_parse_data!(data, HTTPS);
+
+        // * Keys have to increase.
+        let mut unparsed: &[u8] = &[0, 11, 0, 1, 0, 0, 3, 0, 0, 0, 1, 0, 0];
+        assert!(Data::parse(&[], &mut unparsed, Type::SVCB).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_data_unknown() -> anyhow::Result<()> {
+        let data = Data::Unknown {
+            type_code: 731,
+            rdata: vec![0x0a, 0x00, 0x00, 0x01, 0xff],
+        };
+        test_parse_data!(data, Unknown(731));
+
+        let data = Data::Unknown {
+            type_code: 62347,
+            rdata: vec![],
+        };
+        test_parse_data!(data, Unknown(62347));
+        Ok(())
+    }
+
     #[test]
     fn parse_rr() -> anyhow::Result<()> {
         let rr = ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             Type::A,
             Class::IN,
             100,
@@ -861,6 +2181,100 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn display_rr() -> anyhow::Result<()> {
+        let rr = ResourceRecord::new(
+            "example.com.".parse()?,
+            Type::MX,
+            Class::IN,
+            300,
+            Data::MX {
+                preference: 10,
+                exchange: "mail.example.com.".parse()?,
+            },
+        )?;
+        assert_eq!(
+            rr.to_string(),
+            "example.com.\t300\tIN\tMX\t10 mail.example.com."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn display_data() -> anyhow::Result<()> {
+        let data = Data::TXT(vec!["say \"hi\"".to_string(), "caf\u{e9}".to_string()]);
+        assert_eq!(data.to_string(), r#""say \"hi\"" "caf\195\169""#);
+        let data = Data::WKS {
+            address: Ipv4Addr::new(192, 0, 2, 1),
+            protocol: 6,
+            bit_map: vec![0, 0, 0, 0x40],
+        };
+        assert_eq!(data.to_string(), "192.0.2.1 6 25");
+        let data = Data::DS {
+            key_tag: 60485,
+            algorithm: 5,
+            digest_type: 1,
+            digest: vec![0x2b, 0xb1],
+        };
+        assert_eq!(data.to_string(), "60485 5 1 2BB1");
+        let data = Data::RRSIG {
+            type_covered: 1,
+            algorithm: 8,
+            labels: 2,
+            original_ttl: 3600,
+            signature_expiration: 1048354263,
+            signature_inception: 0,
+            key_tag: 2642,
+            signer_name: "example.com.".parse()?,
+            signature: vec![0xde, 0xad, 0xbe, 0xef],
+        };
+        assert_eq!(
+            data.to_string(),
+            "A 8 2 3600 20030322173103 19700101000000 2642 example.com. 3q2+7w=="
+        );
+        let data = Data::NSEC3 {
+            hash_algorithm: 1,
+            flags: 0,
+            iterations: 12,
+            salt: vec![],
+            next_hashed_owner_name: vec![0; 5],
+            types: vec![1, 46, 255],
+        };
+        assert_eq!(data.to_string(), "1 0 12 - 00000000 A RRSIG TYPE255");
+        let data = Data::SVCB(ServiceBinding {
+            priority: 1,
+            target: "svc.example.com.".parse()?,
+            params: vec![
+                SvcParam {
+                    key: SvcParam::ALPN,
+                    value: b"\x02h2\x02h3".to_vec(),
+                },
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![0x1f, 0x90],
+                },
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![0x1f],
+                },
+                SvcParam {
+                    key: 667,
+                    value: b"hi".to_vec(),
+                },
+            ],
+        });
+        assert_eq!(
+            data.to_string(),
+            r#"1 svc.example.com. alpn=h2,h3 port=8080 key3="\031" key667="hi""#
+        );
+        let data = Data::Unknown {
+            type_code: 731,
+            rdata: vec![0x0a, 0x00, 0x00, 0x01, 0xff],
+        };
+        assert_eq!(data.to_string(), r"\# 5 0a000001ff");
+        Ok(())
+    }
+
     #[test]
     fn parse_character_string() -> anyhow::Result<()> {
         let char_str = "testing 1 2 3";
@@ -893,6 +2307,35 @@ mod test {
         assert_eq!(Type::MINFO.serialize(), 14);
         assert_eq!(Type::MX.serialize(), 15);
         assert_eq!(Type::TXT.serialize(), 16);
+        assert_eq!(Type::AAAA.serialize(), 28);
+        assert_eq!(Type::DS.serialize(), 43);
+        assert_eq!(Type::RRSIG.serialize(), 46);
+        assert_eq!(Type::NSEC.serialize(), 47);
+        assert_eq!(Type::DNSKEY.serialize(), 48);
+        assert_eq!(Type::NSEC3.serialize(), 50);
+        assert_eq!(Type::CAA.serialize(), 257);
+        assert_eq!(Type::NAPTR.serialize(), 35);
+        assert_eq!(Type::SVCB.serialize(), 64);
+        assert_eq!(Type::HTTPS.serialize(), 65);
+        assert_eq!(Type::Unknown(65280).serialize(), 65280);
+    }
+
+    #[test]
+    fn display_type() {
+        assert_eq!(Type::AAAA.to_string(), "AAAA");
+        assert_eq!(Type::Unknown(731).to_string(), "TYPE731");
+    }
+
+    #[test]
+    fn type_from_str() -> anyhow::Result<()> {
+        assert_eq!("aaaa".parse::<Type>()?, Type::AAAA);
+        assert_eq!("NSEC3".parse::<Type>()?, Type::NSEC3);
+        assert_eq!("TYPE731".parse::<Type>()?, Type::Unknown(731));
+        assert_eq!("TYPE1".parse::<Type>()?, Type::A);
+        assert!("SRV".parse::<Type>().is_err());
+        assert!("TYPE0".parse::<Type>().is_err());
+        assert!("TYPE70000".parse::<Type>().is_err());
+        Ok(())
     }
 
     #[test]
@@ -913,52 +2356,52 @@ mod test {
 
     #[test]
     fn serialize_data_ns() -> anyhow::Result<()> {
-        let nsdname = "google.com.";
-        let data = Data::NS(nsdname.to_string());
-        let expected = name::serialize(nsdname, None)?;
+        let nsdname: DomainName = "google.com.".parse()?;
+        let data = Data::NS(nsdname.clone());
+        let expected = name::serialize(&nsdname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_md() -> anyhow::Result<()> {
-        let madname = "google.com.";
-        let data = Data::MD(madname.to_string());
-        let expected = name::serialize(madname, None)?;
+        let madname: DomainName = "google.com.".parse()?;
+        let data = Data::MD(madname.clone());
+        let expected = name::serialize(&madname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_mf() -> anyhow::Result<()> {
-        let madname = "google.com.";
-        let data = Data::MF(madname.to_string());
-        let expected = name::serialize(madname, None)?;
+        let madname: DomainName = "google.com.".parse()?;
+        let data = Data::MF(madname.clone());
+        let expected = name::serialize(&madname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_cname() -> anyhow::Result<()> {
-        let cname = "google.com.";
-        let data = Data::CNAME(cname.to_string());
-        let expected = name::serialize(cname, None)?;
+        let cname: DomainName = "google.com.".parse()?;
+        let data = Data::CNAME(cname.clone());
+        let expected = name::serialize(&cname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_soa() -> anyhow::Result<()> {
-        let mname = "google.com.";
-        let rname = "amazon.com.";
+        let mname: DomainName = "google.com.".parse()?;
+        let rname: DomainName = "amazon.com.".parse()?;
         let serial = 25;
         let refresh = 10;
         let retry = 12;
         let expire = 24;
         let minimum = 30;
         let soa = Data::SOA {
-            mname: mname.to_string(),
-            rname: rname.to_string(),
+            mname: mname.clone(),
+            rname: rname.clone(),
             serial,
             refresh,
             retry,
@@ -966,8 +2409,8 @@ mod test {
             minimum,
         };
         let mut expected = Vec::new();
-        expected.append(&mut name::serialize(mname, None)?);
-        expected.append(&mut name::serialize(rname, None)?);
+        expected.append(&mut name::serialize(&mname, None)?);
+        expected.append(&mut name::serialize(&rname, None)?);
         expected.put_u32(serial);
         expected.put_u32(refresh);
         expected.put_u32(retry);
@@ -979,27 +2422,27 @@ mod test {
 
     #[test]
     fn serialize_data_mb() -> anyhow::Result<()> {
-        let madname = "google.com.";
-        let data = Data::MB(madname.to_string());
-        let expected = name::serialize(madname, None)?;
+        let madname: DomainName = "google.com.".parse()?;
+        let data = Data::MB(madname.clone());
+        let expected = name::serialize(&madname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_mg() -> anyhow::Result<()> {
-        let mgmname = "google.com.";
-        let data = Data::MG(mgmname.to_string());
-        let expected = name::serialize(mgmname, None)?;
+        let mgmname: DomainName = "google.com.".parse()?;
+        let data = Data::MG(mgmname.clone());
+        let expected = name::serialize(&mgmname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
 
     #[test]
     fn serialize_data_mr() -> anyhow::Result<()> {
-        let newname = "google.com.";
-        let data = Data::MR(newname.to_string());
-        let expected = name::serialize(newname, None)?;
+        let newname: DomainName = "google.com.".parse()?;
+        let data = Data::MR(newname.clone());
+        let expected = name::serialize(&newname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
@@ -1033,9 +2476,9 @@ mod test {
 
     #[test]
     fn serialize_data_ptr() -> anyhow::Result<()> {
-        let ptrdname = "google.com.";
-        let data = Data::PTR(ptrdname.to_string());
-        let expected = name::serialize(ptrdname, None)?;
+        let ptrdname: DomainName = "google.com.".parse()?;
+        let data = Data::PTR(ptrdname.clone());
+        let expected = name::serialize(&ptrdname, None)?;
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
@@ -1057,15 +2500,15 @@ mod test {
 
     #[test]
     fn serialize_data_minfo() -> anyhow::Result<()> {
-        let rmailbx = "google.com.";
-        let emailbx = "amazon.com.";
+        let rmailbx: DomainName = "google.com.".parse()?;
+        let emailbx: DomainName = "amazon.com.".parse()?;
         let data = Data::MINFO {
-            rmailbx: rmailbx.to_string(),
-            emailbx: emailbx.to_string(),
+            rmailbx: rmailbx.clone(),
+            emailbx: emailbx.clone(),
         };
         let mut expected = Vec::new();
-        expected.append(&mut name::serialize(rmailbx, None)?);
-        expected.append(&mut name::serialize(emailbx, None)?);
+        expected.append(&mut name::serialize(&rmailbx, None)?);
+        expected.append(&mut name::serialize(&emailbx, None)?);
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
@@ -1073,14 +2516,14 @@ mod test {
     #[test]
     fn serialize_data_mx() -> anyhow::Result<()> {
         let preference = 12;
-        let exchange = "google.com.";
+        let exchange: DomainName = "google.com.".parse()?;
         let data = Data::MX {
             preference,
-            exchange: exchange.to_string(),
+            exchange: exchange.clone(),
         };
         let mut expected = Vec::new();
         expected.put_i16(preference);
-        expected.append(&mut name::serialize(exchange, None)?);
+        expected.append(&mut name::serialize(&exchange, None)?);
         assert_eq!(data.serialize()?, expected);
         Ok(())
     }
@@ -1103,12 +2546,117 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn serialize_data_nsec() -> anyhow::Result<()> {
+        // * The example from RFC 4034 section 4.3, with its types given out of order.
+        let data = Data::NSEC {
+            next_domain_name: "host.example.com.".parse()?,
+            types: vec![1234, 1, 47, 15, 46, 1],
+        };
+        let mut expected = name::serialize(&"host.example.com.".parse()?, None)?;
+        expected.extend_from_slice(&[0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03]);
+        expected.extend_from_slice(&[0x04, 0x1b]);
+        expected.extend_from_slice(&[0; 26]);
+        expected.push(0x20);
+        assert_eq!(data.serialize()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_data_https() -> anyhow::Result<()> {
+        // * The params are written in order of key, whatever order they're given in.
+        let data = Data::HTTPS(ServiceBinding {
+            priority: 1,
+            target: ".".parse()?,
+            params: vec![
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![0x01, 0xbb],
+                },
+                SvcParam {
+                    key: SvcParam::ALPN,
+                    value: b"\x02h3".to_vec(),
+                },
+            ],
+        });
+        let expected = [0, 1, 0, 0, 1, 0, 3, 2, b'h', b'3', 0, 3, 0, 2, 0x01, 0xbb];
+        assert_eq!(data.serialize()?, expected);
+
+        let data = Data::SVCB(ServiceBinding {
+            priority: 1,
+            target: ".".parse()?,
+            params: vec![
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![],
+                },
+                SvcParam {
+                    key: SvcParam::PORT,
+                    value: vec![],
+                },
+            ],
+        });
+        assert!(data.serialize().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn data_to_generic() -> anyhow::Result<()> {
+        // * The examples from RFC 3597 section 5.
+        let data = Data::Unknown {
+            type_code: 731,
+            rdata: vec![0x0a, 0x00, 0x00, 0x01],
+        };
+        assert_eq!(data.to_generic()?, "\\# 4 0a000001");
+        let data = Data::Unknown {
+            type_code: 62347,
+            rdata: vec![],
+        };
+        assert_eq!(data.to_generic()?, "\\# 0");
+
+        // * Known types can be written this way too.
+        let data = Data::A(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(data.to_generic()?, "\\# 4 0a000001");
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_rr_unknown() -> anyhow::Result<()> {
+        let rr = ResourceRecord::new(
+            "example.com.".parse()?,
+            Type::Unknown(731),
+            Class::IN,
+            300,
+            Data::Unknown {
+                type_code: 731,
+                rdata: vec![1, 2, 3],
+            },
+        )?;
+        let buf = rr.serialize()?;
+        let mut unparsed = &buf[..];
+        assert_eq!(ResourceRecord::parse(&buf, &mut unparsed)?, rr);
+
+        // * The data has to be of the record's type.
+        let mismatched = ResourceRecord::new(
+            "example.com.".parse()?,
+            Type::Unknown(732),
+            Class::IN,
+            300,
+            Data::Unknown {
+                type_code: 731,
+                rdata: vec![],
+            },
+        );
+        assert!(mismatched.is_err());
+        Ok(())
+    }
+
     /// ! When/if a nameserver is implemented, which ideally will use compressed names,
     /// ! this test should be updated to exercise compressed names in ResourceRecord instances.
     #[test]
     fn serialize_rr() -> anyhow::Result<()> {
         let rr = ResourceRecord::new(
-            "google.com.".to_string(),
+            "google.com.".parse()?,
             Type::A,
             Class::IN,
             100,
@@ -0,0 +1,1609 @@
+use crate::admin::Admin;
+use crate::journal::ShuttingDown;
+use crate::message::QuestionType;
+use crate::provenance::{self, AnswerSource};
+use crate::queue::{self, QueueConfig, Refusal};
+use crate::resolve::{self, Answer, BoxFuture, Outcome, RRset, Resolution, Resolve};
+use crate::shutdown::ShutdownSignal;
+use crate::{metrics, privacy, querylog, rr, view};
+use futures::{SinkExt, StreamExt};
+use rg_resolver_common::batch::{BATCH_RESULT, MAX_BATCH_SIZE};
+use rg_resolver_common::handshake::{ClientHello, ServerHello};
+use rg_resolver_common::rpc::{
+    self, Notification, RpcError, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST, LOOKUP_FAILED,
+    METHOD_NOT_FOUND, NAME_ERROR, PARSE_ERROR, SERVER_BUSY, SERVER_RESTARTING, UNSUPPORTED_VERSION,
+};
+use rg_resolver_common::{
+    Address, BatchAnswer, BatchQuery, BatchResult, Capabilities, DomainName, DomainNameError,
+    FrameCodec, FullAnswer, Qclass, Qtype, Record, RecordData, ResolvedAddress,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+use tracing::{debug, info, warn};
+
+/// The TCP port clients connect to unless told otherwise.
+pub const DEFAULT_PORT: u16 = 17553;
+
+pub(crate) const HOST_NAME_TO_ADDRESS: &str = "host_name_to_address";
+pub(crate) const ADDRESS_TO_HOSTNAME: &str = "address_to_hostname";
+pub(crate) const GENERAL_LOOKUP: &str = "general_lookup";
+const RESOLVE_BATCH: &str = "resolve_batch";
+pub(crate) const FLUSH_CACHE: &str = "flush_cache";
+pub(crate) const INSPECT_CACHE: &str = "inspect_cache";
+pub(crate) const DUMP_STATS: &str = "dump_stats";
+pub(crate) const RELOAD_CONFIG: &str = "reload_config";
+const ADD_NEGATIVE_TRUST_ANCHOR: &str = "add_negative_trust_anchor";
+const REMOVE_NEGATIVE_TRUST_ANCHOR: &str = "remove_negative_trust_anchor";
+pub(crate) const SET_LOG_FILTER: &str = "set_log_filter";
+
+/// Binds the listener clients connect to, on the loopback address.
+pub async fn bind(port: u16) -> io::Result<TcpListener> {
+    TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port)).await
+}
+
+/// Parses an address to listen on: a port on the loopback address, e.g. "17553", an address on
+/// default_port, e.g. "::1", or both, e.g. "[::1]:17553" or "0.0.0.0:17553".
+pub fn parse_listen_addr(spec: &str, default_port: u16) -> anyhow::Result<SocketAddr> {
+    if let Ok(port) = spec.parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    }
+    if let Ok(ip) = spec.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, default_port));
+    }
+    spec.parse()
+        .map_err(|e| anyhow::anyhow!("invalid listen address {spec}: {e}"))
+}
+
+/// Binds a listener on each of addrs, failing if any of them can't be bound.
+///
+/// A listener on [::] usually accepts IPv4 clients too, so adding 0.0.0.0 on the same port
+/// fails as the address being in use.
+pub async fn bind_all(addrs: &[SocketAddr]) -> anyhow::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("binding {addr}: {e}"))?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+/// Serves clients from every listener, like [serve], until accepting on any of them fails or
+/// shutdown is requested.
+pub async fn serve_all(
+    listeners: Vec<TcpListener>,
+    resolver: Arc<dyn Resolve>,
+    admin: Arc<Admin>,
+    queue: QueueConfig,
+    signal: ShutdownSignal,
+) -> io::Result<()> {
+    let serving = listeners.into_iter().map(|listener| {
+        serve(
+            listener,
+            resolver.clone(),
+            admin.clone(),
+            queue,
+            signal.clone(),
+        )
+    });
+    futures::future::try_join_all(serving).await?;
+    Ok(())
+}
+
+/// Accepts clients until accepting fails or shutdown is requested, answering each one's
+/// requests from resolver, and the admin methods of those on loopback through admin.
+///
+/// Each client's task holds a clone of signal until it's done, so draining the [Shutdown]
+/// waits for the requests in flight to be answered.
+///
+/// Each client's requests are queued as queue says, so at most its workers are handled at
+/// once across every client, and the rest wait, or their client is disconnected, as its policy
+/// says.
+///
+/// [Shutdown]: crate::shutdown::Shutdown
+pub async fn serve(
+    listener: TcpListener,
+    resolver: Arc<dyn Resolve>,
+    admin: Arc<Admin>,
+    queue: QueueConfig,
+    signal: ShutdownSignal,
+) -> io::Result<()> {
+    let name = format!("rpc {}", listener.local_addr()?);
+    let mut requested = signal.clone();
+    let read = |jobs: queue::WorkSender<queue::Job>| async move {
+        loop {
+            let (socket, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = requested.requested() => return Ok(()),
+            };
+            let (resolver, signal, jobs) = (resolver.clone(), signal.clone(), jobs.clone());
+            // * Anyone who can reach a listener on another address can look names up, but
+            // * only local clients may operate the daemon.
+            let admin = peer.ip().is_loopback().then(|| admin.clone());
+            tokio::spawn(async move {
+                let _connection = metrics::global().connection();
+                if let Err(e) = process(socket, peer, resolver, admin, &jobs, signal).await {
+                    warn!("Client {peer}: {e}");
+                }
+            });
+        }
+    };
+    queue::serve(name, queue, read, |job| job).await
+}
+
+/// What the server supports, offered to clients in the handshake.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        // * The types clients have a typed representation for.
+        record_types: [
+            "A", "NS", "CNAME", "SOA", "PTR", "HINFO", "MX", "TXT", "AAAA",
+        ]
+        .map(String::from)
+        .to_vec(),
+        batching: true,
+        watch: false,
+        compression: true,
+        verbose: true,
+    }
+}
+
+/// Answers the JSON-RPC requests a client sends over socket from peer, in order, until it
+/// disconnects. Each request is handled by a worker of jobs, and the connection is closed if
+/// it's shed. A client with too many requests in flight is told the server is busy, and how
+/// long to wait before retrying.
+///
+/// The client may start with a [ClientHello], which is answered with a [ServerHello] to agree
+/// on the protocol version and capabilities. Clients from before the handshake send requests
+/// straight away and get the defaults.
+///
+/// Each request and response is one frame; see [FrameCodec]. The answers to a resolve_batch
+/// request are sent as batch_result notifications while it runs, before its response.
+///
+/// Lookups are answered with just their records unless the request, or the handshake for every
+/// request, asks for verbose answers; see [FullAnswer].
+///
+/// Once shutdown is requested, the request being handled is still answered, but the connection
+/// is closed instead of reading another.
+pub async fn process(
+    socket: TcpStream,
+    peer: SocketAddr,
+    resolver: Arc<dyn Resolve>,
+    admin: Option<Arc<Admin>>,
+    jobs: &queue::WorkSender<queue::Job>,
+    mut signal: ShutdownSignal,
+) -> anyhow::Result<()> {
+    // * The client is named by its address until it says its name in a handshake.
+    let mut client = peer.to_string();
+    // * Nothing is compressed until a handshake negotiates it.
+    let mut connection = Framed::new(socket, FrameCodec::new(&Capabilities::default()));
+    let (results, mut streamed) = mpsc::unbounded_channel();
+    let mut greeted = false;
+    let mut verbose = false;
+    loop {
+        let payload = tokio::select! {
+            payload = connection.next() => match payload {
+                Some(payload) => payload?,
+                None => break,
+            },
+            _ = signal.requested() => {
+                debug!("Closing connection for shutdown");
+                break;
+            }
+        };
+        if !greeted {
+            greeted = true;
+            if let Ok(hello) = serde_json::from_slice::<ClientHello>(&payload) {
+                client = format!("{} ({client})", hello.client_name);
+                let negotiated = handshake(&mut connection, &hello).await?;
+                *connection.codec_mut() = FrameCodec::new(&negotiated);
+                verbose = negotiated.verbose;
+                continue;
+            }
+        }
+        let payload = Arc::new(payload);
+        let (resolver, admin) = (resolver.clone(), admin.clone());
+        let (results, named) = (results.clone(), client.clone());
+        let queued = payload.clone();
+        let handling = jobs.run(peer.ip(), async move {
+            let handling = handle(
+                &queued,
+                resolver.as_ref(),
+                admin.as_deref(),
+                verbose,
+                &results,
+            );
+            let handling = querylog::with_client(named, handling);
+            view::with_client(peer.ip(), handling).await
+        });
+        tokio::pin!(handling);
+        let response = loop {
+            tokio::select! {
+                response = &mut handling => break response,
+                Some(result) = streamed.recv() => {
+                    send(&mut connection, &Notification::new(BATCH_RESULT, result)).await?
+                }
+            }
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(Refusal::Busy) => busy(&payload, jobs.stats().retry_after()),
+            Err(refusal) => {
+                debug!("Closing the connection from {client}, {refusal}");
+                break;
+            }
+        };
+        while let Ok(result) = streamed.try_recv() {
+            send(&mut connection, &Notification::new(BATCH_RESULT, result)).await?;
+        }
+        if let Some(response) = response {
+            send(&mut connection, &response).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The reply to the request in payload when its client has too many in flight, if it has an
+/// id to reply to.
+fn busy(payload: &[u8], retry_after: Duration) -> Option<Response> {
+    let request = serde_json::from_slice::<Value>(payload).ok()?;
+    let error =
+        RpcError::new(SERVER_BUSY, "server busy").with_data(retry_after.as_millis().to_string());
+    Some(Response::err(Some(request_id(&request)?), error))
+}
+
+/// The id of request, if it has a valid one.
+fn request_id(request: &Value) -> Option<u32> {
+    request
+        .get("id")
+        .and_then(Value::as_u64)
+        .and_then(|id| u32::try_from(id).ok())
+}
+
+/// A client's connection, split into frames.
+type Connection = Framed<TcpStream, FrameCodec>;
+
+/// Replies to the client's hello, returning the capabilities agreed on.
+async fn handshake(
+    connection: &mut Connection,
+    hello: &ClientHello,
+) -> anyhow::Result<Capabilities> {
+    match ServerHello::negotiate(hello, &capabilities()) {
+        Ok(reply) => {
+            debug!(
+                "Client {} speaks protocol version {}",
+                hello.client_name, reply.version
+            );
+            send(connection, &reply).await?;
+            Ok(reply.capabilities)
+        }
+        Err(e) => {
+            let error = RpcError::new(UNSUPPORTED_VERSION, e.to_string());
+            send(connection, &Response::err(None, error)).await?;
+            anyhow::bail!("{} {e}", hello.client_name)
+        }
+    }
+}
+
+async fn send(connection: &mut Connection, message: &impl Serialize) -> anyhow::Result<()> {
+    let payload = serde_json::to_vec(message)?;
+    connection.send(payload.as_slice()).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Absent for a notification, which gets no response.
+    id: Option<u32>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct GeneralLookupParams {
+    qname: String,
+    qtype: Qtype,
+    qclass: Qclass,
+    /// Whether to answer with a [FullAnswer] rather than just the records, if not as the
+    /// handshake agreed.
+    #[serde(default)]
+    verbose: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct FlushCacheParams {
+    /// The name whose entries to remove, rather than every entry.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InspectCacheParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct SetLogFilterParams {
+    /// What to log, as for RUST_LOG, e.g. "debug".
+    filter: String,
+}
+
+#[derive(Deserialize)]
+struct AddAnchorParams {
+    name: String,
+    /// How long until the anchor expires.
+    seconds: u64,
+}
+
+#[derive(Deserialize)]
+struct RemoveAnchorParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ResolveBatchParams {
+    queries: Vec<BatchQuery>,
+}
+
+/// Where the answers to a batch's queries are sent as they're found.
+type Results = mpsc::UnboundedSender<BatchResult>;
+
+/// Each method has its own result type, so responses hold whichever one as JSON.
+type Response = rpc::Response<Value>;
+
+/// Runs the request in payload, returning the response to send back, if any. The admin
+/// methods are refused without admin. Lookups are answered in full if verbose is set, unless
+/// the request says otherwise.
+async fn handle(
+    payload: &[u8],
+    resolver: &dyn Resolve,
+    admin: Option<&Admin>,
+    verbose: bool,
+    results: &Results,
+) -> Option<Response> {
+    let request = match serde_json::from_slice::<Value>(payload) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(Response::err(
+                None,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    // * The id is echoed back even if the rest of the request is invalid, when it can be found.
+    let id = request_id(&request);
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) if request.jsonrpc == rpc::JSONRPC_VERSION => request,
+        Ok(_) => {
+            let error = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+            return Some(Response::err(id, error));
+        }
+        Err(e) => {
+            return Some(Response::err(
+                id,
+                RpcError::new(INVALID_REQUEST, e.to_string()),
+            ))
+        }
+    };
+    let id = request.id?;
+
+    let response = match dispatch(
+        id,
+        &request.method,
+        request.params,
+        resolver,
+        admin,
+        verbose,
+        results,
+    )
+    .await
+    {
+        Ok(result) => Response::ok(id, result),
+        Err(error) => Response::err(Some(id), error),
+    };
+    Some(response)
+}
+
+async fn dispatch(
+    id: u32,
+    method: &str,
+    params: Value,
+    resolver: &dyn Resolve,
+    admin: Option<&Admin>,
+    verbose: bool,
+    results: &Results,
+) -> Result<Value, RpcError> {
+    let invalid_params = |e: serde_json::Error| RpcError::new(INVALID_PARAMS, e.to_string());
+    let require_admin = || {
+        admin.ok_or_else(|| {
+            let message = format!("{method} is only available to clients on loopback");
+            RpcError::new(INVALID_REQUEST, message)
+        })
+    };
+    match method {
+        HOST_NAME_TO_ADDRESS => {
+            let [name]: [String; 1] = serde_json::from_value(params).map_err(invalid_params)?;
+            debug!("{method} {}", privacy::qname(&name));
+            to_result(resolve_address(resolver, name).await?)
+        }
+        GENERAL_LOOKUP => {
+            let params: GeneralLookupParams =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            // * The resolvers only look up the Internet class.
+            if params.qclass != Qclass::IN {
+                let message = format!("unsupported QCLASS {}, expected IN", params.qclass);
+                return Err(RpcError::new(INVALID_PARAMS, message));
+            }
+            debug!(
+                "{method} {} {}",
+                privacy::qname(&params.qname),
+                params.qtype
+            );
+            let qtype = question_type(params.qtype);
+            if !params.verbose.unwrap_or(verbose) {
+                return to_result(records(&lookup(resolver, &params.qname, qtype).await?)?);
+            }
+            let (answer, source) =
+                provenance::track_answer_source(lookup_answer(resolver, &params.qname, qtype))
+                    .await;
+            let answer = answer?;
+            to_result(FullAnswer {
+                answer: records(answer.as_ref().map_or(&[], Answer::records))?,
+                authority: records(
+                    answer
+                        .as_ref()
+                        .and_then(Answer::soa)
+                        .map_or(&[], std::slice::from_ref),
+                )?,
+                authoritative: source == Some(AnswerSource::Authoritative),
+                from_cache: source == Some(AnswerSource::Cache),
+            })
+        }
+        ADDRESS_TO_HOSTNAME => {
+            let [address]: [String; 1] = serde_json::from_value(params).map_err(invalid_params)?;
+            let addr = address.parse::<IpAddr>().map_err(|e| {
+                RpcError::new(INVALID_PARAMS, format!("invalid address {address}: {e}"))
+            })?;
+            let name = resolve::reverse_name(addr);
+            debug!("{method} {}", privacy::qname(&name));
+            to_result(records(
+                &lookup(resolver, &name, QuestionType::RrType(rr::Type::PTR)).await?,
+            )?)
+        }
+        RESOLVE_BATCH => {
+            let params: ResolveBatchParams =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            let count = params.queries.len();
+            if count > MAX_BATCH_SIZE {
+                let message = format!("{count} queries is more than the {MAX_BATCH_SIZE} allowed");
+                return Err(RpcError::new(INVALID_PARAMS, message));
+            }
+            debug!("{method} of {count} names");
+            resolve_batch(resolver, id, params.queries, results).await;
+            // * The answers have all been sent, so the response just says how many there were.
+            to_result(count)
+        }
+        FLUSH_CACHE => {
+            let admin = require_admin()?;
+            let params: Option<FlushCacheParams> =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            let name = params.and_then(|params| params.name);
+            let qname = name
+                .as_deref()
+                .map(|name| {
+                    name.parse::<DomainName>().map_err(|e| {
+                        RpcError::new(INVALID_PARAMS, format!("invalid name {name}: {e}"))
+                    })
+                })
+                .transpose()?;
+            let flushed = admin.flush_cache(qname.as_ref());
+            info!(
+                "{method} {} removed {flushed} entries",
+                name.as_deref().map_or("(all)", privacy::qname)
+            );
+            to_result(flushed)
+        }
+        INSPECT_CACHE => {
+            let admin = require_admin()?;
+            let params: InspectCacheParams =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            let name = params.name.parse::<DomainName>().map_err(|e| {
+                RpcError::new(INVALID_PARAMS, format!("invalid name {}: {e}", params.name))
+            })?;
+            to_result(admin.cache_entries(&name))
+        }
+        DUMP_STATS => to_result(require_admin()?.stats()),
+        SET_LOG_FILTER => {
+            let admin = require_admin()?;
+            let params: SetLogFilterParams =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            admin
+                .set_log_filter(&params.filter)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            info!("{method} {}", params.filter);
+            to_result(())
+        }
+        RELOAD_CONFIG => {
+            let reloaded = require_admin()?.reload_config().map_err(|e| {
+                warn!("{method}: {e}");
+                RpcError::new(INTERNAL_ERROR, format!("reloading config: {e}"))
+            })?;
+            info!("{method} loaded {} upstreams", reloaded.upstreams);
+            to_result(reloaded)
+        }
+        ADD_NEGATIVE_TRUST_ANCHOR => {
+            let admin = require_admin()?;
+            let params: AddAnchorParams = serde_json::from_value(params).map_err(invalid_params)?;
+            admin
+                .add_negative_trust_anchor(&params.name, Duration::from_secs(params.seconds))
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            info!(
+                "{method} {} for {}s",
+                privacy::qname(&params.name),
+                params.seconds
+            );
+            to_result(())
+        }
+        REMOVE_NEGATIVE_TRUST_ANCHOR => {
+            let admin = require_admin()?;
+            let params: RemoveAnchorParams =
+                serde_json::from_value(params).map_err(invalid_params)?;
+            let removed = admin.remove_negative_trust_anchor(&params.name);
+            info!(
+                "{method} {} removed {removed}",
+                privacy::qname(&params.name)
+            );
+            to_result(removed)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {method}"),
+        )),
+    }
+}
+
+/// Resolves name to all of its addresses, following CNAMEs, and reports where they came from.
+async fn resolve_address(
+    resolver: &dyn Resolve,
+    name: String,
+) -> Result<ResolvedAddress, RpcError> {
+    let ((a, aaaa), source) = provenance::track_answer_source(async {
+        tokio::join!(
+            resolve_observed(resolver, &name, QuestionType::RrType(rr::Type::A)),
+            resolve_observed(resolver, &name, QuestionType::RrType(rr::Type::AAAA)),
+        )
+    })
+    .await;
+    let (a, aaaa) = (a.map_err(lookup_error)?, aaaa.map_err(lookup_error)?);
+    // * NXDOMAIN is about the name, whatever the type asked for.
+    if a.outcome == Outcome::NxDomain || aaaa.outcome == Outcome::NxDomain {
+        return Err(name_error(name));
+    }
+    let addresses = a
+        .rrset
+        .iter()
+        .chain(&aaaa.rrset)
+        .filter_map(|rr| {
+            let address = match rr.data() {
+                rr::Data::A(addr) => IpAddr::V4(*addr),
+                rr::Data::AAAA(addr) => IpAddr::V6(*addr),
+                _ => return None,
+            };
+            Some(Address {
+                address,
+                ttl: ttl(rr),
+            })
+        })
+        .collect();
+    Ok(ResolvedAddress {
+        canonical_name: a.canonical_name(&name),
+        name,
+        addresses,
+        authoritative: source == Some(AnswerSource::Authoritative),
+        from_cache: source == Some(AnswerSource::Cache),
+    })
+}
+
+/// Resolves name as [resolve::resolve] does, as a query of its own in the query log.
+async fn resolve_observed(
+    resolver: &dyn Resolve,
+    name: &str,
+    qtype: QuestionType,
+) -> anyhow::Result<Resolution> {
+    let resolving = resolve::resolve(resolver, name, qtype);
+    querylog::observe(name, qtype, resolving, |resolution| match resolution {
+        Ok(resolution) if resolution.outcome == Outcome::NxDomain => String::from("no such name"),
+        Ok(resolution) => format!("answered {}", resolution.rrset.len()),
+        Err(e) => format!("error: {e}"),
+    })
+    .await
+}
+
+/// Resolves every query of batch at once, sending each answer to results as soon as it's found.
+async fn resolve_batch(
+    resolver: &dyn Resolve,
+    batch: u32,
+    queries: Vec<BatchQuery>,
+    results: &Results,
+) {
+    let mut lookups = queries
+        .into_iter()
+        .enumerate()
+        .map(|(index, query)| {
+            let lookup: BoxFuture<'_, ()> = Box::pin(async move {
+                let answer = match query.qtype {
+                    None => resolve_address(resolver, query.qname)
+                        .await
+                        .map(BatchAnswer::Addresses),
+                    Some(qtype) => lookup(resolver, &query.qname, question_type(qtype))
+                        .await
+                        .and_then(|rrset| records(&rrset))
+                        .map(BatchAnswer::Records),
+                };
+                let answer = answer.unwrap_or_else(BatchAnswer::Error);
+                let _ = results.send(BatchResult {
+                    batch,
+                    index,
+                    answer,
+                });
+            });
+            Some(lookup)
+        })
+        .collect::<Vec<_>>();
+    // * The lookups borrow the resolver, so they're polled together here rather than spawned.
+    std::future::poll_fn(|cx| {
+        let mut running = false;
+        for slot in &mut lookups {
+            if let Some(lookup) = slot {
+                if lookup.as_mut().poll(cx).is_ready() {
+                    *slot = None;
+                } else {
+                    running = true;
+                }
+            }
+        }
+        if running {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await
+}
+
+/// Looks up name, treating no answer from any resolver as no records and a name that doesn't
+/// exist as a NAME_ERROR.
+async fn lookup(
+    resolver: &dyn Resolve,
+    name: &str,
+    qtype: QuestionType,
+) -> Result<RRset, RpcError> {
+    let answer = lookup_answer(resolver, name, qtype).await?;
+    Ok(answer.map(Answer::into_records).unwrap_or_default())
+}
+
+/// Looks up name's records of type qtype, returning the whole answer, if there is one. NXDOMAIN
+/// is an error.
+async fn lookup_answer(
+    resolver: &dyn Resolve,
+    name: &str,
+    qtype: QuestionType,
+) -> Result<Option<Answer>, RpcError> {
+    let looking_up = resolver.lookup(name, qtype);
+    let answer = querylog::observe(name, qtype, looking_up, |answer| match answer {
+        Ok(Some(Answer::Records(rrset))) => format!("answered {}", rrset.len()),
+        Ok(Some(Answer::NoData { .. })) => String::from("no records"),
+        Ok(Some(Answer::NxDomain { .. })) => String::from("no such name"),
+        Ok(None) => String::from("no answer"),
+        Err(e) => format!("error: {e}"),
+    });
+    match answer.await {
+        Ok(Some(Answer::NxDomain { .. })) => Err(name_error(name.to_string())),
+        Ok(answer) => Ok(answer),
+        Err(e) => Err(lookup_error(e)),
+    }
+}
+
+fn lookup_error(e: anyhow::Error) -> RpcError {
+    // * A client told the server is restarting can send the request again later.
+    if e.is::<ShuttingDown>() {
+        return RpcError::new(SERVER_RESTARTING, e.to_string());
+    }
+    // * The name was refused before it was looked up, so it's the request that's at fault.
+    if e.is::<DomainNameError>() {
+        return RpcError::new(INVALID_PARAMS, format!("{e:#}"));
+    }
+    RpcError::new(LOOKUP_FAILED, e.to_string())
+}
+
+/// The error for a lookup of name, which doesn't exist.
+fn name_error(name: String) -> RpcError {
+    RpcError::new(NAME_ERROR, "name doesn't exist").with_data(name)
+}
+
+/// The records of rrset in the form clients read them.
+fn records(rrset: &[rr::ResourceRecord]) -> Result<Vec<Record>, RpcError> {
+    rrset
+        .iter()
+        .map(record)
+        .collect::<anyhow::Result<_>>()
+        .map_err(|e| RpcError::new(LOOKUP_FAILED, e.to_string()))
+}
+
+fn to_result(result: impl Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(result).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+fn question_type(qtype: Qtype) -> QuestionType {
+    use rr::Type::*;
+    let rr_type = match qtype {
+        Qtype::A => A,
+        Qtype::NS => NS,
+        Qtype::MD => MD,
+        Qtype::MF => MF,
+        Qtype::CNAME => CNAME,
+        Qtype::SOA => SOA,
+        Qtype::MB => MB,
+        Qtype::MG => MG,
+        Qtype::MR => MR,
+        Qtype::NULL => NULL,
+        Qtype::WKS => WKS,
+        Qtype::PTR => PTR,
+        Qtype::HINFO => HINFO,
+        Qtype::MINFO => MINFO,
+        Qtype::MX => MX,
+        Qtype::TXT => TXT,
+        Qtype::AAAA => AAAA,
+        Qtype::AXFR => return QuestionType::Afxr,
+        Qtype::MAILB => return QuestionType::Mailb,
+        Qtype::MAILA => return QuestionType::Maila,
+        Qtype::ANY => return QuestionType::All,
+    };
+    QuestionType::RrType(rr_type)
+}
+
+/// The record as it's sent to clients. Types clients have no representation for are sent
+/// with their wire-format data.
+fn record(rr: &rr::ResourceRecord) -> anyhow::Result<Record> {
+    let data = match rr.data().clone() {
+        rr::Data::A(addr) => RecordData::A(addr),
+        rr::Data::AAAA(addr) => RecordData::AAAA(addr),
+        rr::Data::NS(name) => RecordData::NS(name.to_string()),
+        rr::Data::CNAME(name) => RecordData::CNAME(name.to_string()),
+        rr::Data::PTR(name) => RecordData::PTR(name.to_string()),
+        rr::Data::SOA {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => RecordData::SOA {
+            mname: mname.to_string(),
+            rname: rname.to_string(),
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum: minimum as u32,
+        },
+        rr::Data::HINFO { cpu, os } => RecordData::HINFO { cpu, os },
+        rr::Data::MX {
+            preference,
+            exchange,
+        } => RecordData::MX {
+            preference: preference as u16,
+            exchange: exchange.to_string(),
+        },
+        rr::Data::TXT(strings) => RecordData::TXT(strings),
+        data => RecordData::Other {
+            type_code: rr.r#type().serialize(),
+            rdata: data.serialize()?,
+        },
+    };
+    Ok(Record {
+        name: rr.name().to_string(),
+        ttl: ttl(rr),
+        data,
+    })
+}
+
+fn ttl(rr: &rr::ResourceRecord) -> u32 {
+    // * A negative TTL is treated as zero, per RFC 2181 section 8.
+    rr.ttl().max(0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, Cached};
+    use crate::resolve::{BoxFuture, Static};
+    use crate::shutdown::Shutdown;
+    use std::net::Ipv6Addr;
+
+    fn resolver() -> anyhow::Result<Static> {
+        let record = |name: &str, r#type, data| {
+            rr::ResourceRecord::new(name.parse()?, r#type, rr::Class::IN, 300, data)
+        };
+        Ok(Static::new(
+            "test",
+            vec![
+                record(
+                    "example.com.",
+                    rr::Type::A,
+                    rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+                )?,
+                record(
+                    "example.com.",
+                    rr::Type::AAAA,
+                    rr::Data::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                )?,
+                record(
+                    "example.com.",
+                    rr::Type::MX,
+                    rr::Data::MX {
+                        preference: 10,
+                        exchange: "mail.example.com.".parse()?,
+                    },
+                )?,
+                record(
+                    "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa.",
+                    rr::Type::PTR,
+                    rr::Data::PTR("www.example.com.".parse()?),
+                )?,
+                record(
+                    "example.com.",
+                    rr::Type::MINFO,
+                    rr::Data::MINFO {
+                        rmailbx: "a.".parse()?,
+                        emailbx: "b.".parse()?,
+                    },
+                )?,
+            ],
+        ))
+    }
+
+    /// Runs request, ignoring any batch results.
+    async fn respond(request: &str, resolver: &dyn Resolve) -> Option<Response> {
+        let (results, _) = mpsc::unbounded_channel();
+        handle(request.as_bytes(), resolver, None, false, &results).await
+    }
+
+    async fn call(request: &str) -> Option<Value> {
+        let resolver = resolver().unwrap();
+        let response = respond(request, &resolver).await?;
+        Some(serde_json::to_value(response).unwrap())
+    }
+
+    #[tokio::test]
+    async fn host_name_to_address() {
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 7, "method": "host_name_to_address", "params": ["example.com."] }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["jsonrpc"], "2.0");
+        assert_eq!(response["id"], 7);
+        assert!(response.get("error").is_none());
+        let resolved: ResolvedAddress = serde_json::from_value(response["result"].clone()).unwrap();
+        assert_eq!(resolved.name, "example.com.");
+        assert_eq!(resolved.canonical_name, "example.com.");
+        assert_eq!(
+            resolved.addresses,
+            [
+                Address {
+                    address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                    ttl: 300
+                },
+                Address {
+                    address: IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                    ttl: 300
+                },
+            ]
+        );
+        // * The test resolver's records are a zone it's authoritative for.
+        assert!(resolved.authoritative);
+        assert!(!resolved.from_cache);
+    }
+
+    #[tokio::test]
+    async fn host_name_to_address_from_cache() -> anyhow::Result<()> {
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let resolver = Cached::new(resolver()?, cache, "default");
+        let request = r#"{ "jsonrpc": "2.0", "id": 1, "method": "host_name_to_address", "params": ["example.com."] }"#;
+
+        let response = respond(request, &resolver).await.unwrap();
+        let resolved: ResolvedAddress = serde_json::from_value(response.into_result()?)?;
+        assert_eq!(resolved.addresses.len(), 2);
+        assert!(resolved.authoritative);
+        assert!(!resolved.from_cache);
+
+        let response = respond(request, &resolver).await.unwrap();
+        let resolved: ResolvedAddress = serde_json::from_value(response.into_result()?)?;
+        assert_eq!(resolved.addresses.len(), 2);
+        assert!(!resolved.authoritative);
+        assert!(resolved.from_cache);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn admin_methods() -> anyhow::Result<()> {
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let resolver = Cached::new(resolver()?, cache.clone(), "default");
+        let admin = Admin::new().with_cache(cache);
+        let call = |request: &'static str, admin| {
+            let resolver = &resolver;
+            async move {
+                let (results, _) = mpsc::unbounded_channel();
+                let response = handle(request.as_bytes(), resolver, admin, false, &results).await;
+                response.unwrap().into_result()
+            }
+        };
+        let lookup = r#"{ "jsonrpc": "2.0", "id": 1, "method": "host_name_to_address", "params": ["example.com."] }"#;
+        call(lookup, None).await?;
+
+        let dump = r#"{ "jsonrpc": "2.0", "id": 2, "method": "dump_stats" }"#;
+        let stats = call(dump, Some(&admin)).await?;
+        assert_eq!(stats["cache"]["entries"], 2);
+        assert_eq!(stats["cache"]["misses"], 2);
+        assert!(stats["overrides"].is_null());
+
+        let inspect = r#"{ "jsonrpc": "2.0", "id": 3, "method": "inspect_cache", "params": { "name": "example.com." } }"#;
+        let entries = call(inspect, Some(&admin)).await?;
+        assert_eq!(entries.as_array().map(Vec::len), Some(2));
+        assert_eq!(entries[0]["qtype"], "A");
+
+        let flush = r#"{ "jsonrpc": "2.0", "id": 3, "method": "flush_cache", "params": { "name": "other.example." } }"#;
+        assert_eq!(call(flush, Some(&admin)).await?, 0);
+        let flush = r#"{ "jsonrpc": "2.0", "id": 4, "method": "flush_cache", "params": { "name": "Example.com." } }"#;
+        assert_eq!(call(flush, Some(&admin)).await?, 2);
+        call(lookup, None).await?;
+        let flush = r#"{ "jsonrpc": "2.0", "id": 5, "method": "flush_cache" }"#;
+        assert_eq!(call(flush, Some(&admin)).await?, 2);
+
+        // * Only clients on loopback are given the admin handle.
+        let error = call(flush, None).await.unwrap_err();
+        assert_eq!(error.code, INVALID_REQUEST);
+
+        let add = r#"{ "jsonrpc": "2.0", "id": 6, "method": "add_negative_trust_anchor", "params": { "name": "Broken.Example.", "seconds": 3600 } }"#;
+        call(add, Some(&admin)).await?;
+        let stats = call(dump, Some(&admin)).await?;
+        assert_eq!(
+            stats["negative_trust_anchors"][0]["name"],
+            "broken.example."
+        );
+        let remove = r#"{ "jsonrpc": "2.0", "id": 7, "method": "remove_negative_trust_anchor", "params": { "name": "broken.example." } }"#;
+        assert_eq!(call(remove, Some(&admin)).await?, true);
+        assert_eq!(call(remove, Some(&admin)).await?, false);
+        // * RFC 7646 anchors last at most a week.
+        let add = r#"{ "jsonrpc": "2.0", "id": 8, "method": "add_negative_trust_anchor", "params": { "name": "broken.example.", "seconds": 700000 } }"#;
+        assert_eq!(
+            call(add, Some(&admin)).await.unwrap_err().code,
+            INVALID_PARAMS
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn general_lookup() {
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 8, "method": "general_lookup",
+                 "params": { "qname": "example.com.", "qtype": "mx", "qclass": "IN" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["id"], 8);
+        assert_eq!(
+            response["result"][0]["data"],
+            serde_json::json!({ "preference": 10, "exchange": "mail.example.com." })
+        );
+
+        // * Types clients can't represent are sent raw.
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 1, "method": "general_lookup",
+                 "params": { "qname": "example.com.", "qtype": "MINFO", "qclass": "IN" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["result"][0]["type"], "Other");
+        assert_eq!(response["result"][0]["data"]["type_code"], 14);
+
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 1, "method": "general_lookup",
+                 "params": { "qname": "nowhere.example.", "qtype": "A", "qclass": "IN" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["result"], serde_json::json!([]));
+
+        // * ANY returns every record at the name.
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 1, "method": "general_lookup",
+                 "params": { "qname": "example.com.", "qtype": "*", "qclass": "in" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["result"].as_array().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn address_to_hostname() {
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 5, "method": "address_to_hostname", "params": ["2001:db8::1"] }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["result"][0]["type"], "PTR");
+        assert_eq!(response["result"][0]["data"], "www.example.com.");
+
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 5, "method": "address_to_hostname", "params": ["example.com."] }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let code = |response: Option<Value>| response.unwrap()["error"]["code"].clone();
+
+        let response = call("{ not json").await.unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+
+        let response = call(r#"{ "jsonrpc": "1.0", "id": 3, "method": "general_lookup" }"#).await;
+        assert_eq!(response.as_ref().unwrap()["id"], 3);
+        assert_eq!(code(response), INVALID_REQUEST);
+        assert_eq!(
+            code(call(r#"{ "jsonrpc": "2.0", "id": 3 }"#).await),
+            INVALID_REQUEST
+        );
+
+        assert_eq!(
+            code(call(r#"{ "jsonrpc": "2.0", "id": 3, "method": "teleport" }"#).await),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            code(
+                call(r#"{ "jsonrpc": "2.0", "id": 3, "method": "host_name_to_address", "params": [] }"#)
+                    .await
+            ),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(
+                call(
+                    r#"{ "jsonrpc": "2.0", "id": 3, "method": "general_lookup",
+                         "params": { "qname": "example.com.", "qtype": "A", "qclass": "CH" } }"#
+                )
+                .await
+            ),
+            INVALID_PARAMS
+        );
+        let response = call(
+            r#"{ "jsonrpc": "2.0", "id": 3, "method": "general_lookup",
+                 "params": { "qname": "example.com.", "qtype": "TELEPORT", "qclass": "IN" } }"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        assert!(response["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("unknown QTYPE 'TELEPORT'"));
+
+        // * Notifications aren't answered.
+        assert!(call(
+            r#"{ "jsonrpc": "2.0", "method": "host_name_to_address", "params": ["example.com."] }"#
+        )
+        .await
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn resolve_batch() -> anyhow::Result<()> {
+        let resolver = resolver()?;
+        let request = r#"{ "jsonrpc": "2.0", "id": 6, "method": "resolve_batch", "params": { "queries": [
+            { "qname": "example.com." },
+            { "qname": "example.com.", "qtype": "MX" },
+            { "qname": "nowhere.example.", "qtype": "A" }
+        ] } }"#;
+        let (results, mut streamed) = mpsc::unbounded_channel();
+        let response = handle(request.as_bytes(), &resolver, None, false, &results)
+            .await
+            .unwrap();
+        assert_eq!(response.into_result()?, 3);
+
+        let mut answers = Vec::new();
+        while let Ok(result) = streamed.try_recv() {
+            assert_eq!(result.batch, 6);
+            answers.push((result.index, result.answer));
+        }
+        answers.sort_by_key(|(index, _)| *index);
+        assert_eq!(answers.len(), 3);
+        assert!(
+            matches!(&answers[0].1, BatchAnswer::Addresses(resolved) if resolved.addresses.len() == 2)
+        );
+        assert!(
+            matches!(&answers[1].1, BatchAnswer::Records(records) if records[0].data.target_name() == Some("mail.example.com."))
+        );
+        assert!(matches!(&answers[2].1, BatchAnswer::Records(records) if records.is_empty()));
+
+        // * A failed query doesn't fail the batch.
+        let request = r#"{ "jsonrpc": "2.0", "id": 7, "method": "resolve_batch", "params": { "queries": [{ "qname": "example.com." }] } }"#;
+        let response = handle(request.as_bytes(), &Restarting, None, false, &results)
+            .await
+            .unwrap();
+        assert_eq!(response.into_result()?, 1);
+        let result = streamed.try_recv()?;
+        assert!(matches!(result.answer, BatchAnswer::Error(e) if e.code == SERVER_RESTARTING));
+
+        let queries = vec![serde_json::json!({ "qname": "example.com." }); MAX_BATCH_SIZE + 1];
+        let request = serde_json::json!({
+            "jsonrpc": "2.0", "id": 8, "method": "resolve_batch", "params": { "queries": queries }
+        });
+        let response = handle(
+            request.to_string().as_bytes(),
+            &resolver,
+            None,
+            false,
+            &results,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.into_result().unwrap_err().code, INVALID_PARAMS);
+        assert!(streamed.try_recv().is_err());
+        Ok(())
+    }
+
+    /// Fails every lookup the way a journaled resolver does when the server shuts down.
+    struct Restarting;
+
+    impl Resolve for Restarting {
+        fn name(&self) -> &str {
+            "restarting"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            _name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async { Err(ShuttingDown.into()) })
+        }
+    }
+
+    /// Says no name exists.
+    struct Nowhere;
+
+    impl Resolve for Nowhere {
+        fn name(&self) -> &str {
+            "nowhere"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            _name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async {
+                Ok(Some(Answer::NxDomain {
+                    cnames: RRset::new(),
+                    soa: None,
+                }))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn name_error() {
+        for request in [
+            r#"{ "jsonrpc": "2.0", "id": 5, "method": "host_name_to_address", "params": ["nowhere.example."] }"#,
+            r#"{ "jsonrpc": "2.0", "id": 5, "method": "general_lookup", "params": { "qname": "nowhere.example.", "qtype": "MX", "qclass": "IN" } }"#,
+        ] {
+            let response = respond(request, &Nowhere).await.unwrap();
+            let error = response.into_result().unwrap_err();
+            assert_eq!(error.code, NAME_ERROR);
+            assert_eq!(error.message, "name doesn't exist");
+            assert_eq!(error.data.as_deref(), Some("nowhere.example."));
+        }
+    }
+
+    /// Says names have no records of the type asked for, under example.com.'s SOA.
+    struct Unlisted;
+
+    impl Resolve for Unlisted {
+        fn name(&self) -> &str {
+            "unlisted"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            _name: &'a str,
+            _qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async {
+                let soa = rr::Data::SOA {
+                    mname: "ns.example.com.".parse()?,
+                    rname: "hostmaster.example.com.".parse()?,
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 300,
+                };
+                let soa = rr::ResourceRecord::new(
+                    "example.com.".parse()?,
+                    rr::Type::SOA,
+                    rr::Class::IN,
+                    300,
+                    soa,
+                )?;
+                Ok(Some(Answer::NoData {
+                    cnames: RRset::new(),
+                    soa: Some(soa),
+                }))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn verbose_lookups() -> anyhow::Result<()> {
+        let (results, _) = mpsc::unbounded_channel();
+        let request = r#"{ "jsonrpc": "2.0", "id": 1, "method": "general_lookup",
+            "params": { "qname": "example.com.", "qtype": "A", "qclass": "IN", "verbose": true } }"#;
+        let response = handle(request.as_bytes(), &resolver()?, None, false, &results).await;
+        let answer: FullAnswer = serde_json::from_value(response.unwrap().into_result()?)?;
+        assert_eq!(answer.answer.len(), 1);
+        assert!(answer.authority.is_empty());
+        // * The test resolver's records are a zone it's authoritative for.
+        assert!(answer.authoritative && !answer.from_cache);
+
+        // * A client that agreed on verbose answers in the handshake gets them for every
+        // * lookup, unless it asks otherwise.
+        let request = r#"{ "jsonrpc": "2.0", "id": 2, "method": "general_lookup",
+            "params": { "qname": "www.example.com.", "qtype": "MX", "qclass": "IN" } }"#;
+        let response = handle(request.as_bytes(), &Unlisted, None, true, &results).await;
+        let answer: FullAnswer = serde_json::from_value(response.unwrap().into_result()?)?;
+        assert!(answer.answer.is_empty());
+        assert!(
+            matches!(answer.authority[..], [Record { ref name, data: RecordData::SOA { serial: 1, .. }, .. }] if name == "example.com.")
+        );
+
+        let request = r#"{ "jsonrpc": "2.0", "id": 3, "method": "general_lookup",
+            "params": { "qname": "www.example.com.", "qtype": "MX", "qclass": "IN", "verbose": false } }"#;
+        let response = handle(request.as_bytes(), &Unlisted, None, true, &results).await;
+        assert_eq!(response.unwrap().into_result()?, serde_json::json!([]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restarting() {
+        let request = r#"{ "jsonrpc": "2.0", "id": 4, "method": "host_name_to_address", "params": ["example.com."] }"#;
+        let response = respond(request, &Restarting).await.unwrap();
+        let error = response.into_result().unwrap_err();
+        assert_eq!(error.code, SERVER_RESTARTING);
+        assert_eq!(error.message, "server restarting");
+    }
+
+    /// Connects to a server listening at addr, before any handshake.
+    async fn connect(addr: SocketAddr) -> anyhow::Result<Connection> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Framed::new(
+            socket,
+            FrameCodec::new(&Capabilities::default()),
+        ))
+    }
+
+    async fn recv(connection: &mut Connection) -> anyhow::Result<Value> {
+        let payload = connection.next().await.expect("connection closed")?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+
+    #[tokio::test]
+    async fn negotiates_handshake() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(resolver()?),
+            Arc::new(Admin::new()),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        let mut connection = connect(addr).await?;
+        let wanted = Capabilities {
+            record_types: vec![String::from("A"), String::from("LOC")],
+            batching: true,
+            watch: true,
+            compression: true,
+            verbose: true,
+        };
+        send(
+            &mut connection,
+            &ClientHello::new(String::from("test"), wanted),
+        )
+        .await?;
+        let reply: ServerHello = serde_json::from_value(recv(&mut connection).await?)?;
+        assert_eq!(
+            reply.version,
+            rg_resolver_common::handshake::PROTOCOL_VERSION
+        );
+        assert_eq!(reply.capabilities.record_types, ["A"]);
+        assert!(reply.capabilities.batching && reply.capabilities.compression);
+        assert!(reply.capabilities.verbose);
+        assert!(!reply.capabilities.watch);
+
+        // * Requests follow with the negotiated codec.
+        *connection.codec_mut() = FrameCodec::new(&reply.capabilities);
+        let request = r#"{ "jsonrpc": "2.0", "id": 1, "method": "host_name_to_address", "params": ["example.com."] }"#;
+        connection.send(request.as_bytes()).await?;
+        assert_eq!(recv(&mut connection).await?["id"], 1);
+
+        // * A client too old for the server is told so and disconnected.
+        let mut connection = connect(addr).await?;
+        let mut hello = ClientHello::new(String::from("ancient"), Capabilities::default());
+        hello.version = 0;
+        send(&mut connection, &hello).await?;
+        let response: Response = serde_json::from_value(recv(&mut connection).await?)?;
+        assert_eq!(
+            response.into_result().unwrap_err().code,
+            UNSUPPORTED_VERSION
+        );
+        assert!(connection.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_framed_requests() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(resolver()?),
+            Arc::new(Admin::new()),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        let mut connection = connect(addr).await?;
+        // * Both requests are sent before reading, so they may arrive in one read.
+        for id in 1..=2 {
+            let request = format!(
+                r#"{{ "jsonrpc": "2.0", "id": {id}, "method": "host_name_to_address", "params": ["example.com."] }}"#
+            );
+            connection.send(request.as_bytes()).await?;
+        }
+        for id in 1..=2 {
+            let response = recv(&mut connection).await?;
+            assert_eq!(response["id"], id);
+            assert_eq!(response["result"]["addresses"].as_array().unwrap().len(), 2);
+        }
+
+        // * A batch's answers are streamed as notifications before its response.
+        let request = r#"{ "jsonrpc": "2.0", "id": 3, "method": "resolve_batch",
+                           "params": { "queries": [{ "qname": "example.com." }, { "qname": "example.com.", "qtype": "MX" }] } }"#;
+        connection.send(request.as_bytes()).await?;
+        for _ in 0..2 {
+            let notification = recv(&mut connection).await?;
+            assert_eq!(notification["method"], BATCH_RESULT);
+            assert_eq!(notification["params"]["batch"], 3);
+            assert!(notification.get("id").is_none());
+        }
+        let response = recv(&mut connection).await?;
+        assert_eq!(response["id"], 3);
+        assert_eq!(response["result"], 2);
+        Ok(())
+    }
+
+    /// Answers like [Static], but only after a while, so shutdown can start mid-request.
+    struct Slow(Static);
+
+    impl Resolve for Slow {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            name: &'a str,
+            qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                self.0.lookup(name, qtype).await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn drains_on_shutdown() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        let serving = tokio::spawn(serve(
+            listener,
+            Arc::new(Slow(resolver()?)),
+            Arc::new(Admin::new()),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        let mut connection = connect(addr).await?;
+        let request = r#"{ "jsonrpc": "2.0", "id": 1, "method": "host_name_to_address", "params": ["example.com."] }"#;
+        connection.send(request.as_bytes()).await?;
+        // * Shutting down while the request is being resolved.
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let draining = tokio::spawn(shutdown.drain(std::time::Duration::from_secs(5)));
+
+        // * The request in flight is still answered, then the connection is closed.
+        let response = recv(&mut connection).await?;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["addresses"].as_array().unwrap().len(), 2);
+        assert!(connection.next().await.is_none());
+        assert!(draining.await?);
+        serving.await??;
+
+        // * No more clients are accepted.
+        assert!(TcpStream::connect(addr).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sheds_requests_when_queue_is_full() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        let queue = QueueConfig {
+            capacity: 1,
+            workers: 1,
+            policy: queue::ShedPolicy::Reject,
+            ..QueueConfig::default()
+        };
+        tokio::spawn(serve(
+            listener,
+            Arc::new(Slow(resolver()?)),
+            Arc::new(Admin::new()),
+            queue,
+            shutdown.subscribe(),
+        ));
+
+        let request = r#"{ "jsonrpc": "2.0", "id": 1, "method": "host_name_to_address", "params": ["example.com."] }"#;
+        // * The first client's request is being handled, the second's waits for it, and the
+        // * third's has nowhere to wait. Connecting takes no room in the queue.
+        let mut served = connect(addr).await?;
+        let mut waiting = connect(addr).await?;
+        let mut shed = connect(addr).await?;
+        served.send(request.as_bytes()).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        waiting.send(request.as_bytes()).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        shed.send(request.as_bytes()).await?;
+        assert!(shed.next().await.is_none());
+
+        assert_eq!(recv(&mut served).await?["id"], 1);
+        assert_eq!(recv(&mut waiting).await?["id"], 1);
+        // * A connected client's next request is queued as well.
+        served.send(request.as_bytes()).await?;
+        assert_eq!(recv(&mut served).await?["id"], 1);
+        let metrics = metrics::global().render();
+        assert!(metrics
+            .lines()
+            .any(|l| l == format!("rg_resolver_queue_shed_total{{queue=\"rpc {addr}\"}} 1")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn busy_past_client_share() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shutdown = Shutdown::new();
+        let queue = QueueConfig {
+            per_client: 1,
+            ..QueueConfig::default()
+        };
+        tokio::spawn(serve(
+            listener,
+            Arc::new(Slow(resolver()?)),
+            Arc::new(Admin::new()),
+            queue,
+            shutdown.subscribe(),
+        ));
+
+        // * Both connections are from the same address, so they share its one request.
+        let mut first = connect(addr).await?;
+        let mut second = connect(addr).await?;
+        let request = |id: u32| {
+            format!(
+                r#"{{ "jsonrpc": "2.0", "id": {id}, "method": "host_name_to_address", "params": ["example.com."] }}"#
+            )
+        };
+        first.send(request(1).as_bytes()).await?;
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        second.send(request(2).as_bytes()).await?;
+        let refused: Response = serde_json::from_value(recv(&mut second).await?)?;
+        assert_eq!(refused.id, Some(2));
+        let error = refused.into_result().unwrap_err();
+        assert_eq!(error.code, SERVER_BUSY);
+        assert!(error.retry_after().is_some());
+
+        // * The connection stays open, and once the first request is done there's room again.
+        assert_eq!(recv(&mut first).await?["id"], 1);
+        second.send(request(3).as_bytes()).await?;
+        assert_eq!(recv(&mut second).await?["id"], 3);
+        Ok(())
+    }
+
+    #[test]
+    fn listen_addrs() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_listen_addr("5353", DEFAULT_PORT)?,
+            "127.0.0.1:5353".parse()?
+        );
+        assert_eq!(
+            parse_listen_addr("::1", DEFAULT_PORT)?,
+            "[::1]:17553".parse()?
+        );
+        assert_eq!(
+            parse_listen_addr("0.0.0.0", DEFAULT_PORT)?,
+            "0.0.0.0:17553".parse()?
+        );
+        assert_eq!(
+            parse_listen_addr("[::]:5353", DEFAULT_PORT)?,
+            "[::]:5353".parse()?
+        );
+        assert!(parse_listen_addr("localhost:5353", DEFAULT_PORT).is_err());
+        assert!(parse_listen_addr("70000", DEFAULT_PORT).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_every_listener() -> anyhow::Result<()> {
+        let mut addrs = vec!["127.0.0.1:0".parse()?, "127.0.0.1:0".parse()?];
+        // * Not every host has IPv6, e.g. some containers.
+        if std::net::TcpListener::bind("[::1]:0").is_ok() {
+            addrs.push("[::1]:0".parse()?);
+        }
+        let listeners = bind_all(&addrs).await?;
+        let bound = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<io::Result<Vec<_>>>()?;
+        let shutdown = Shutdown::new();
+        let serving = tokio::spawn(serve_all(
+            listeners,
+            Arc::new(resolver()?),
+            Arc::new(Admin::new()),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        for (id, addr) in bound.into_iter().enumerate() {
+            let mut connection = connect(addr).await?;
+            let request = format!(
+                r#"{{ "jsonrpc": "2.0", "id": {id}, "method": "host_name_to_address", "params": ["example.com."] }}"#
+            );
+            connection.send(request.as_bytes()).await?;
+            assert_eq!(recv(&mut connection).await?["id"], id);
+        }
+
+        assert!(shutdown.drain(std::time::Duration::from_secs(5)).await);
+        serving.await??;
+        Ok(())
+    }
+}
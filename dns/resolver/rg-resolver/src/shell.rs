@@ -0,0 +1,267 @@
+//! An interactive shell for operating a running daemon over its JSON-RPC listener, for the
+//! binary's shell subcommand: looking names up, inspecting and flushing the cache, changing
+//! what's logged and showing stats. The admin commands only work from loopback.
+
+use crate::server::{
+    ADDRESS_TO_HOSTNAME, DUMP_STATS, FLUSH_CACHE, GENERAL_LOOKUP, HOST_NAME_TO_ADDRESS,
+    INSPECT_CACHE, RELOAD_CONFIG, SET_LOG_FILTER,
+};
+use futures::{SinkExt, StreamExt};
+use rg_resolver_common::rpc::{self, JSONRPC_VERSION};
+use rg_resolver_common::{Capabilities, FrameCodec};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+/// What the shell prints before reading each command.
+const PROMPT: &str = "rg> ";
+
+const HELP: &str = "\
+lookup <name> [type]  look up name's addresses, or its records of type
+reverse <address>     look up the names of address
+cache <name>          show what's cached for name
+flush [name]          remove name's entries from the cache, or every entry
+log <filter>          log what filter says from now on, as for RUST_LOG, e.g. debug
+stats                 show the cache, upstream and monitor stats
+reload                read the upstreams and overrides again
+help                  show this
+quit                  leave the shell";
+
+/// A command typed into the shell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Lookup { name: String, qtype: Option<String> },
+    Reverse(String),
+    Cache(String),
+    Flush(Option<String>),
+    Log(String),
+    Stats,
+    Reload,
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parses a line typed into the shell, returning None if it's blank.
+    pub fn parse(line: &str) -> anyhow::Result<Option<Command>> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(None);
+        };
+        let args = words.collect::<Vec<_>>();
+        let command = match (command, args.as_slice()) {
+            ("lookup", [name]) => Command::Lookup {
+                name: name.to_string(),
+                qtype: None,
+            },
+            ("lookup", [name, qtype]) => Command::Lookup {
+                name: name.to_string(),
+                qtype: Some(qtype.to_string()),
+            },
+            ("reverse", [addr]) => Command::Reverse(addr.to_string()),
+            ("cache", [name]) => Command::Cache(name.to_string()),
+            ("flush", []) => Command::Flush(None),
+            ("flush", [name]) => Command::Flush(Some(name.to_string())),
+            ("log", [filter]) => Command::Log(filter.to_string()),
+            ("stats", []) => Command::Stats,
+            ("reload", []) => Command::Reload,
+            ("help", []) => Command::Help,
+            ("quit" | "exit", []) => Command::Quit,
+            (
+                "lookup" | "reverse" | "cache" | "flush" | "log" | "stats" | "reload" | "help"
+                | "quit" | "exit",
+                _,
+            ) => anyhow::bail!("wrong arguments for {command}, see help"),
+            _ => anyhow::bail!("unknown command {command}, see help"),
+        };
+        Ok(Some(command))
+    }
+
+    /// The method and params of the request that carries out the command, if it's sent to the
+    /// daemon.
+    fn request(&self) -> Option<(&'static str, Value)> {
+        Some(match self {
+            Command::Lookup { name, qtype: None } => (HOST_NAME_TO_ADDRESS, json!([name])),
+            Command::Lookup {
+                name,
+                qtype: Some(qtype),
+            } => (
+                GENERAL_LOOKUP,
+                json!({ "qname": name, "qtype": qtype, "qclass": "IN" }),
+            ),
+            Command::Reverse(addr) => (ADDRESS_TO_HOSTNAME, json!([addr])),
+            Command::Cache(name) => (INSPECT_CACHE, json!({ "name": name })),
+            Command::Flush(name) => (FLUSH_CACHE, json!({ "name": name })),
+            Command::Log(filter) => (SET_LOG_FILTER, json!({ "filter": filter })),
+            Command::Stats => (DUMP_STATS, Value::Null),
+            Command::Reload => (RELOAD_CONFIG, Value::Null),
+            Command::Help | Command::Quit => return None,
+        })
+    }
+}
+
+/// A connection to a daemon's JSON-RPC listener, sending it the shell's commands.
+pub struct Shell {
+    connection: Framed<TcpStream, FrameCodec>,
+    next_id: u32,
+}
+
+impl Shell {
+    pub async fn connect(addr: SocketAddr) -> anyhow::Result<Shell> {
+        let socket = TcpStream::connect(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("connecting to {addr}: {e}"))?;
+        Ok(Shell {
+            // * Without a handshake nothing is compressed.
+            connection: Framed::new(socket, FrameCodec::new(&Capabilities::default())),
+            next_id: 1,
+        })
+    }
+
+    /// Reads commands from input until it ends or says quit, printing each one's result, or
+    /// why it failed, to output. Prompts for each command if prompt is set.
+    pub async fn run(
+        &mut self,
+        input: impl AsyncBufRead + Unpin,
+        output: &mut impl Write,
+        prompt: bool,
+    ) -> anyhow::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if prompt {
+                write!(output, "{PROMPT}")?;
+                output.flush()?;
+            }
+            let Some(line) = lines.next_line().await? else {
+                break;
+            };
+            let command = match Command::parse(&line) {
+                Ok(Some(command)) => command,
+                Ok(None) => continue,
+                Err(e) => {
+                    writeln!(output, "{e}")?;
+                    continue;
+                }
+            };
+            match command {
+                Command::Quit => break,
+                Command::Help => writeln!(output, "{HELP}")?,
+                command => {
+                    let (method, params) = command.request().expect("sent to the daemon");
+                    match self.call(method, params).await? {
+                        Ok(result) => {
+                            writeln!(output, "{}", serde_json::to_string_pretty(&result)?)?
+                        }
+                        Err(e) => writeln!(output, "error: {e}")?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a request and waits for its response. Fails only if the connection does; an error
+    /// response is returned as the request's result.
+    pub async fn call(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<Result<Value, rpc::RpcError>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = json!({ "jsonrpc": JSONRPC_VERSION, "id": id, "method": method });
+        if !params.is_null() {
+            request["params"] = params;
+        }
+        self.connection
+            .send(serde_json::to_vec(&request)?.as_slice())
+            .await?;
+        loop {
+            let payload = self
+                .connection
+                .next()
+                .await
+                .ok_or_else(|| anyhow::anyhow!("the daemon closed the connection"))??;
+            let message: Value = serde_json::from_slice(&payload)?;
+            // * Notifications, e.g. a batch's results, have no id and aren't for us.
+            if message.get("id").and_then(Value::as_u64) != Some(id.into()) {
+                continue;
+            }
+            let response: rpc::Response<Value> = serde_json::from_value(message)?;
+            return Ok(response.into_result());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::admin::Admin;
+    use crate::cache::{Cache, Cached};
+    use crate::queue::QueueConfig;
+    use crate::resolve::Static;
+    use crate::rr;
+    use crate::server::serve;
+    use crate::shutdown::Shutdown;
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parse_commands() -> anyhow::Result<()> {
+        assert_eq!(Command::parse("  ")?, None);
+        assert_eq!(
+            Command::parse("lookup example.com. MX")?,
+            Some(Command::Lookup {
+                name: String::from("example.com."),
+                qtype: Some(String::from("MX")),
+            })
+        );
+        assert_eq!(Command::parse("flush")?, Some(Command::Flush(None)));
+        assert_eq!(Command::parse("exit")?, Some(Command::Quit));
+        assert!(Command::parse("stats now").is_err());
+        assert!(Command::parse("dig example.com.").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn runs_commands() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let cache = Arc::new(Cache::new(Cache::DEFAULT_SHARDS));
+        let record = rr::ResourceRecord::new(
+            "example.com.".parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+        )?;
+        let resolver = Cached::new(Static::new("test", vec![record]), cache.clone(), "default");
+        let shutdown = Shutdown::new();
+        tokio::spawn(serve(
+            listener,
+            Arc::new(resolver),
+            Arc::new(Admin::new().with_cache(cache)),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        let mut shell = Shell::connect(addr).await?;
+        let input =
+            "lookup example.com.\ncache example.com.\nbogus\nflush\nlog nonsense=[\nquit\nstats\n";
+        let mut output = Vec::new();
+        shell.run(input.as_bytes(), &mut output, false).await?;
+        let output = String::from_utf8(output)?;
+        assert!(output.contains("\"addresses\""));
+        assert!(output.contains("\"namespace\": \"default\""));
+        assert!(output.contains("unknown command bogus"));
+        assert!(output.lines().any(|line| line == "1"));
+        assert!(output.contains("error: "));
+        // * Nothing after quit is run.
+        assert!(!output.contains("\"upstreams\""));
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long shutting down waits for clients' requests to finish unless told otherwise.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tells the tasks holding its signals to stop, and waits for them to.
+///
+/// A task stops by dropping its signal, so waiting for the tasks is waiting for every signal
+/// to be dropped.
+pub struct Shutdown {
+    requested: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            requested: watch::Sender::new(false),
+        }
+    }
+
+    /// A signal for a task to stop on. Shutting down waits for it and every clone of it to
+    /// be dropped.
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal(self.requested.subscribe())
+    }
+
+    /// Tells every task to stop, then waits up to timeout for them to. Returns false if some
+    /// were still running when it gave up.
+    pub async fn drain(self, timeout: Duration) -> bool {
+        self.requested.send_replace(true);
+        tokio::time::timeout(timeout, self.requested.closed())
+            .await
+            .is_ok()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// Waits until the task should stop.
+    ///
+    /// Never finishes if the Shutdown is dropped without draining, since nothing can ask the
+    /// task to stop any more.
+    pub async fn requested(&mut self) {
+        if self.0.wait_for(|requested| *requested).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn drains_tasks() {
+        let shutdown = Shutdown::new();
+        let mut signal = shutdown.subscribe();
+        assert!(!signal.is_requested());
+        let task = tokio::spawn(async move {
+            signal.requested().await;
+            // * Finishing the work in hand before stopping.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        });
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+        assert!(task.is_finished());
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_timeout() {
+        let shutdown = Shutdown::new();
+        let stuck = shutdown.subscribe();
+        assert!(!shutdown.drain(Duration::from_millis(10)).await);
+        assert!(stuck.is_requested());
+    }
+
+    #[tokio::test]
+    async fn never_requested_once_dropped() {
+        let mut signal = Shutdown::new().subscribe();
+        let requested = tokio::time::timeout(Duration::from_millis(10), signal.requested()).await;
+        assert!(requested.is_err());
+    }
+}
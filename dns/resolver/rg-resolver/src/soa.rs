@@ -0,0 +1,239 @@
+use crate::rr;
+use std::cmp::Ordering;
+use std::time::{Duration, Instant};
+
+/// A zone serial number, compared with RFC 1982 serial number arithmetic so it can wrap around.
+///
+/// Two serials exactly 2^31 apart can't be ordered: neither is greater.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Serial(pub u32);
+
+impl Serial {
+    /// The largest increment that keeps the result greater than the original (RFC 1982 section 3.1).
+    pub const MAX_INCREMENT: u32 = (1 << 31) - 1;
+
+    /// Adds n, wrapping around, or returns None if n is too large to be meaningful.
+    pub fn checked_add(self, n: u32) -> Option<Serial> {
+        if n > Self::MAX_INCREMENT {
+            return None;
+        }
+        Some(Serial(self.0.wrapping_add(n)))
+    }
+}
+
+impl PartialOrd for Serial {
+    /// RFC 1982 section 3.2.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        const HALF: u32 = 1 << 31;
+        let (i1, i2) = (self.0, other.0);
+        if i1 == i2 {
+            Some(Ordering::Equal)
+        } else if (i1 < i2 && i2 - i1 < HALF) || (i1 > i2 && i1 - i2 > HALF) {
+            Some(Ordering::Less)
+        } else if (i1 < i2 && i2 - i1 > HALF) || (i1 > i2 && i1 - i2 < HALF) {
+            Some(Ordering::Greater)
+        } else {
+            None
+        }
+    }
+}
+
+/// The timers from a zone's SOA record that drive how a secondary keeps it up to date
+/// (RFC 1035 section 3.3.13).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timers {
+    /// How long to wait before checking the primary for a newer serial.
+    pub refresh: Duration,
+    /// How long to wait before checking again after a check fails.
+    pub retry: Duration,
+    /// How long the zone can go without a successful check before it's no longer authoritative.
+    pub expire: Duration,
+}
+
+impl Timers {
+    /// The zone's serial and timers, if data is an SOA record's.
+    pub fn from_soa(data: &rr::Data) -> Option<(Serial, Timers)> {
+        let rr::Data::SOA {
+            serial,
+            refresh,
+            retry,
+            expire,
+            ..
+        } = data
+        else {
+            return None;
+        };
+        let timers = Timers {
+            refresh: Duration::from_secs((*refresh).into()),
+            retry: Duration::from_secs((*retry).into()),
+            expire: Duration::from_secs((*expire).into()),
+        };
+        Some((Serial(*serial), timers))
+    }
+}
+
+/// Whether a secondary's copy of a zone can still be served.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Freshness {
+    /// Up to date as of the last check.
+    Fresh,
+    /// Still servable, but the primary should be checked for a newer serial.
+    CheckDue,
+    /// Not successfully checked within the expire time, so it mustn't be served.
+    Expired,
+}
+
+/// Tracks when a secondary zone was last refreshed and when it should next be checked.
+#[derive(Debug)]
+pub struct ZoneRefresh {
+    serial: Serial,
+    timers: Timers,
+    last_success: Instant,
+    next_check: Instant,
+}
+
+impl ZoneRefresh {
+    /// A zone just loaded at now with the given SOA values.
+    pub fn new(serial: Serial, timers: Timers, now: Instant) -> Self {
+        ZoneRefresh {
+            serial,
+            timers,
+            last_success: now,
+            next_check: now + timers.refresh,
+        }
+    }
+
+    pub fn serial(&self) -> Serial {
+        self.serial
+    }
+
+    pub fn next_check(&self) -> Instant {
+        self.next_check
+    }
+
+    pub fn freshness(&self, now: Instant) -> Freshness {
+        if now >= self.last_success + self.timers.expire {
+            Freshness::Expired
+        } else if now >= self.next_check {
+            Freshness::CheckDue
+        } else {
+            Freshness::Fresh
+        }
+    }
+
+    /// Returns true if the primary's serial is newer, so the zone should be transferred.
+    pub fn needs_transfer(&self, primary: Serial) -> bool {
+        primary > self.serial
+    }
+
+    /// Records that the primary was checked at now, and had (or was transferred at) the given
+    /// SOA values.
+    pub fn record_success(&mut self, serial: Serial, timers: Timers, now: Instant) {
+        *self = Self::new(serial, timers, now);
+    }
+
+    /// Records that checking the primary failed at now, so it's retried sooner.
+    pub fn record_failure(&mut self, now: Instant) {
+        self.next_check = now + self.timers.retry;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serial_ordering() {
+        assert!(Serial(2) > Serial(1));
+        assert!(Serial(1) < Serial(2));
+        assert_eq!(Serial(7).partial_cmp(&Serial(7)), Some(Ordering::Equal));
+
+        // * Wraparound: 0 follows u32::MAX.
+        assert!(Serial(0) > Serial(u32::MAX));
+        assert!(Serial(5) > Serial(u32::MAX - 5));
+        assert!(Serial(u32::MAX - 5) < Serial(5));
+
+        // * Less than half the space ahead is greater, more than half is behind.
+        assert!(Serial((1 << 31) - 1) > Serial(0));
+        assert!(Serial((1 << 31) + 1) < Serial(0));
+
+        // * Exactly half way is undefined.
+        assert_eq!(Serial(1 << 31).partial_cmp(&Serial(0)), None);
+        assert_eq!(Serial(0).partial_cmp(&Serial(1 << 31)), None);
+    }
+
+    #[test]
+    fn serial_addition() {
+        assert_eq!(Serial(u32::MAX).checked_add(1), Some(Serial(0)));
+        assert_eq!(
+            Serial(10).checked_add(Serial::MAX_INCREMENT),
+            Some(Serial(10 + Serial::MAX_INCREMENT))
+        );
+        assert!(Serial(10).checked_add(Serial::MAX_INCREMENT).unwrap() > Serial(10));
+        assert_eq!(Serial(10).checked_add(Serial::MAX_INCREMENT + 1), None);
+
+        let serial = Serial(u32::MAX - 1);
+        assert!(serial.checked_add(100).unwrap() > serial);
+    }
+
+    fn timers() -> Timers {
+        Timers {
+            refresh: Duration::from_secs(3600),
+            retry: Duration::from_secs(600),
+            expire: Duration::from_secs(86400),
+        }
+    }
+
+    #[test]
+    fn timers_from_soa() -> anyhow::Result<()> {
+        let soa = rr::Data::SOA {
+            mname: "ns1.example.com.".parse()?,
+            rname: "hostmaster.example.com.".parse()?,
+            serial: 42,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 300,
+        };
+        assert_eq!(Timers::from_soa(&soa), Some((Serial(42), timers())));
+        let ns = rr::Data::NS("ns1.".parse()?);
+        assert_eq!(Timers::from_soa(&ns), None);
+        Ok(())
+    }
+
+    #[test]
+    fn refresh_retry_expire() {
+        let start = Instant::now();
+        let mut zone = ZoneRefresh::new(Serial(u32::MAX), timers(), start);
+        assert_eq!(zone.freshness(start), Freshness::Fresh);
+        assert_eq!(
+            zone.freshness(start + Duration::from_secs(3600)),
+            Freshness::CheckDue
+        );
+
+        // * A failed check retries sooner, but doesn't extend the expiry.
+        let failed_at = start + Duration::from_secs(3600);
+        zone.record_failure(failed_at);
+        assert_eq!(zone.next_check(), failed_at + Duration::from_secs(600));
+        assert_eq!(
+            zone.freshness(failed_at + Duration::from_secs(1)),
+            Freshness::Fresh
+        );
+        assert_eq!(
+            zone.freshness(start + Duration::from_secs(86400)),
+            Freshness::Expired
+        );
+
+        // * The primary's serial has wrapped around past ours.
+        assert!(zone.needs_transfer(Serial(3)));
+        assert!(!zone.needs_transfer(Serial(u32::MAX)));
+        let refreshed_at = start + Duration::from_secs(4000);
+        zone.record_success(Serial(3), timers(), refreshed_at);
+        // * The expiry restarts from the successful check, though another check is due.
+        assert_eq!(zone.serial(), Serial(3));
+        assert_eq!(
+            zone.freshness(start + Duration::from_secs(86400)),
+            Freshness::CheckDue
+        );
+    }
+}
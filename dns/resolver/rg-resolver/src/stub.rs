@@ -0,0 +1,590 @@
+use crate::authority::Authority;
+use crate::edns::{self, Edns};
+use crate::message::{Message, Opcode, QuestionClass, ResponseCode};
+use crate::provenance::{self, AnswerSource};
+use crate::queue::{self, QueueConfig};
+use crate::resolve::{self, BoxFuture, Outcome, Resolve};
+use crate::shutdown::ShutdownSignal;
+use crate::tsig::{self, Tsig};
+use crate::{metrics, privacy, querylog, rr, view};
+use rg_resolver_common::DomainNameError;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tracing::{debug, warn};
+
+/// The port DNS clients send queries to.
+pub const DNS_PORT: u16 = 53;
+
+/// How long a TCP client may wait between queries before the connection is closed
+/// (RFC 7766 section 6.2.3).
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest datagram a query can arrive in.
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+/// The sockets answering DNS queries on one address.
+pub struct Listener {
+    pub udp: UdpSocket,
+    pub tcp: TcpListener,
+}
+
+/// Binds UDP and TCP on each of addrs, failing if any of them can't be bound.
+///
+/// Port 53 usually needs root, or CAP_NET_BIND_SERVICE on Linux.
+pub async fn bind_all(addrs: &[SocketAddr]) -> anyhow::Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let udp = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("binding {addr}/udp: {e}"))?;
+        let tcp = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("binding {addr}/tcp: {e}"))?;
+        listeners.push(Listener { udp, tcp });
+    }
+    Ok(listeners)
+}
+
+/// Answers DNS queries on every listener from authority's zones or else from resolver, until
+/// receiving on any of them fails or shutdown is requested. Each socket queues what it reads
+/// as queue says.
+pub async fn serve_all(
+    listeners: Vec<Listener>,
+    resolver: Arc<dyn Resolve>,
+    authority: Arc<Authority>,
+    queue: QueueConfig,
+    signal: ShutdownSignal,
+) -> io::Result<()> {
+    let serving = listeners.into_iter().flat_map(|listener| {
+        let (resolver, authority) = (resolver.clone(), authority.clone());
+        let serving: [BoxFuture<'static, io::Result<()>>; 2] = [
+            Box::pin(serve_udp(
+                listener.udp,
+                resolver.clone(),
+                authority.clone(),
+                queue,
+                signal.clone(),
+            )),
+            Box::pin(serve_tcp(
+                listener.tcp,
+                resolver,
+                authority,
+                queue,
+                signal.clone(),
+            )),
+        ];
+        serving
+    });
+    futures::future::try_join_all(serving).await?;
+    Ok(())
+}
+
+/// Answers the queries arriving on socket, queued as queue says, each in its own task.
+///
+/// While the queue is full, a query is dropped or waited with as its policy says, and the
+/// client is left to retry.
+pub async fn serve_udp(
+    socket: UdpSocket,
+    resolver: Arc<dyn Resolve>,
+    authority: Arc<Authority>,
+    queue: QueueConfig,
+    signal: ShutdownSignal,
+) -> io::Result<()> {
+    let socket = Arc::new(socket);
+    let name = format!("dns/udp {}", socket.local_addr()?);
+    let (receiving, mut requested) = (socket.clone(), signal.clone());
+    let read = |tx: queue::WorkSender<(Vec<u8>, SocketAddr)>| async move {
+        let mut buf = vec![0_u8; MAX_DATAGRAM_LEN];
+        loop {
+            let (len, peer) = tokio::select! {
+                received = receiving.recv_from(&mut buf) => match received {
+                    Ok(received) => received,
+                    // * Windows reports ICMP errors for earlier sends as receive errors.
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                    Err(e) => return Err(e),
+                },
+                _ = requested.requested() => return Ok(()),
+            };
+            if let Err((_, refusal)) = tx.submit(peer.ip(), (buf[..len].to_vec(), peer)).await {
+                debug!("Dropped a query from {peer}, {refusal}");
+            }
+        }
+    };
+    let handle = |(query, peer): (Vec<u8>, SocketAddr)| {
+        let (socket, resolver) = (socket.clone(), resolver.clone());
+        let authority = authority.clone();
+        async move {
+            let answering = answer(&query, resolver.as_ref(), &authority);
+            let answering = querylog::with_client(peer.to_string(), answering);
+            let Some((query, response)) = view::with_client(peer.ip(), answering).await else {
+                debug!("Dropped an unparseable query from {peer}");
+                return;
+            };
+            // * The client says how big a datagram it can take, or it's the original 512 bytes.
+            let limit = query
+                .edns()
+                .map_or(512, |edns| edns.udp_payload_size.max(512) as usize);
+            let sent = match response.serialize_truncating(limit) {
+                Ok(bytes) => socket.send_to(&bytes, peer).await.map(drop),
+                Err(e) => Err(io::Error::other(e)),
+            };
+            if let Err(e) = sent {
+                warn!("DNS client {peer}: {e}");
+            }
+        }
+    };
+    // * serve returns once the queued queries are answered, and signal is held until then.
+    queue::serve(name, queue, read, handle).await
+}
+
+/// Accepts DNS clients on listener, answering each one's queries in order. The queries are
+/// queued as queue says, so at most its workers are answered at once across every client.
+pub async fn serve_tcp(
+    listener: TcpListener,
+    resolver: Arc<dyn Resolve>,
+    authority: Arc<Authority>,
+    queue: QueueConfig,
+    signal: ShutdownSignal,
+) -> io::Result<()> {
+    let name = format!("dns/tcp {}", listener.local_addr()?);
+    let mut requested = signal.clone();
+    let read = |jobs: queue::WorkSender<queue::Job>| async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = requested.requested() => return Ok(()),
+            };
+            let (resolver, authority) = (resolver.clone(), authority.clone());
+            let (signal, jobs) = (signal.clone(), jobs.clone());
+            tokio::spawn(async move {
+                let _connection = metrics::global().connection();
+                let processing = process_tcp(stream, peer, resolver, authority, &jobs, signal);
+                if let Err(e) = processing.await {
+                    warn!("DNS client {peer}: {e}");
+                }
+            });
+        }
+    };
+    queue::serve(name, queue, read, |job| job).await
+}
+
+/// Answers the length-prefixed queries on stream from peer until the client disconnects, falls
+/// idle, or shutdown is requested. Each query is answered by a worker of jobs, and the
+/// connection is closed if it's shed.
+async fn process_tcp(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    resolver: Arc<dyn Resolve>,
+    authority: Arc<Authority>,
+    jobs: &queue::WorkSender<queue::Job>,
+    mut signal: ShutdownSignal,
+) -> anyhow::Result<()> {
+    loop {
+        let len = tokio::select! {
+            len = tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_u16()) => match len {
+                Ok(Ok(len)) => len,
+                // * The client closed the connection, or stopped using it.
+                Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(_) => return Ok(()),
+                Ok(Err(e)) => return Err(e.into()),
+            },
+            _ = signal.requested() => return Ok(()),
+        };
+        let mut query = vec![0_u8; len as usize];
+        // * A client that stops partway through a query is as idle as one that sends nothing.
+        match tokio::time::timeout(TCP_IDLE_TIMEOUT, stream.read_exact(&mut query)).await {
+            Ok(read) => read?,
+            Err(_) => return Ok(()),
+        };
+        let (resolver, authority) = (resolver.clone(), authority.clone());
+        let answered = jobs.run(peer.ip(), async move {
+            let answering = answer(&query, resolver.as_ref(), &authority);
+            let answering = querylog::with_client(peer.to_string(), answering);
+            view::with_client(peer.ip(), answering).await
+        });
+        let answered = match answered.await {
+            Ok(answered) => answered,
+            Err(refusal) => {
+                debug!("Closing the connection from {peer}, {refusal}");
+                return Ok(());
+            }
+        };
+        let Some((_, response)) = answered else {
+            anyhow::bail!("unparseable query");
+        };
+        stream.write_all(&response.serialize_framed()?).await?;
+    }
+}
+
+/// Answers the query in buf authoritatively if it's about a name in authority's zones, or else
+/// from resolver, following CNAMEs the way a recursive server does.
+///
+/// Returns the parsed query along with the response, or None if buf isn't a query at all.
+pub async fn answer(
+    buf: &[u8],
+    resolver: &dyn Resolve,
+    authority: &Authority,
+) -> Option<(Message, Message)> {
+    let query = Message::parse(buf).ok()?;
+    if query.is_response() {
+        return None;
+    }
+    let key = match signing_key(&query, authority.required_keys()) {
+        Ok(key) => key,
+        Err(rejection) => return Some((query, *rejection)),
+    };
+    let responding = respond(&query, resolver, authority);
+    let mut response = match query.questions() {
+        [question] => {
+            let describe = |response: &Message| response.response_code().to_string();
+            let qname = question.name().to_string();
+            let response = querylog::observe(&qname, question.r#type(), responding, describe).await;
+            metrics::global().count_query(question.r#type(), response.response_code());
+            response
+        }
+        _ => responding.await,
+    };
+    response.set_recursion_available(true);
+    // * A client using EDNS gets an OPT record back, saying how big a datagram this server takes.
+    if query.edns().is_some() {
+        response.set_edns(Some(Edns::new(edns::DEFAULT_UDP_PAYLOAD_SIZE)));
+    }
+    // * Signed last, as the signature covers everything before it.
+    if let Some(key) = key {
+        let request_mac = query.tsig().map(Tsig::mac);
+        if let Err(e) = key.sign(&mut response, request_mac) {
+            warn!("Signing the response with {}: {e}", key.name());
+        }
+    }
+    Some((query, response))
+}
+
+/// The key to sign the response to query with, the one it's signed with, if keys are
+/// required. A query that isn't signed with one of them gets the response rejecting it
+/// instead.
+fn signing_key<'a>(
+    query: &Message,
+    keys: &'a [tsig::Key],
+) -> Result<Option<&'a tsig::Key>, Box<Message>> {
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let Some(request) = query.tsig() else {
+        return Err(Box::new(query.empty_response(ResponseCode::Refused)));
+    };
+    let reject = |error| {
+        let mut response = query.empty_response(ResponseCode::NotAuth);
+        response.set_tsig(Some(Tsig::rejection(request, error)));
+        Box::new(response)
+    };
+    let Some(key) = keys.iter().find(|key| key.name() == request.key_name()) else {
+        return Err(reject(tsig::Error::BadKey));
+    };
+    match key.verify(query, None) {
+        Ok(()) => Ok(Some(key)),
+        // * Only a query with the right signature, but sent at the wrong time, gets a signed
+        // * rejection.
+        Err(tsig::Error::BadTime) => {
+            let mut response = query.empty_response(ResponseCode::NotAuth);
+            match key.sign_rejection(&mut response, request.mac(), tsig::Error::BadTime) {
+                Ok(()) => Err(Box::new(response)),
+                Err(_) => Err(reject(tsig::Error::BadTime)),
+            }
+        }
+        Err(error) => Err(reject(error)),
+    }
+}
+
+/// Answers query from the authority's zones or by resolving it.
+///
+/// A name that doesn't exist is answered NXDOMAIN, and it or one without records of the type
+/// asked for comes with the SOA the resolver found, so clients can cache that (RFC 2308).
+async fn respond(query: &Message, resolver: &dyn Resolve, authority: &Authority) -> Message {
+    let error = |code| query.empty_response(code);
+    if query.opcode() != Opcode::StandardQuery {
+        return error(ResponseCode::NotImplemented);
+    }
+    // * Nothing defines an answer to several questions at once (RFC 9619).
+    let [question] = query.questions() else {
+        return error(ResponseCode::FormatError);
+    };
+    if question.class() != QuestionClass::RrClass(rr::Class::IN) {
+        return error(ResponseCode::NotImplemented);
+    }
+    if let Some(response) = authority.answer(query) {
+        provenance::record_answer_source(AnswerSource::Authoritative);
+        return response;
+    }
+    let qname = question.name().to_string();
+    match resolve::resolve(resolver, &qname, question.r#type()).await {
+        Ok(resolution) if resolution.outcome == Outcome::NoAnswer => {
+            error(ResponseCode::ServerFailure)
+        }
+        Ok(resolution) => {
+            let code = match resolution.outcome {
+                Outcome::NxDomain => ResponseCode::NameError,
+                _ => ResponseCode::NoError,
+            };
+            // * The CNAMEs come first, so the client can follow them to the records.
+            let mut answers = resolution.cnames;
+            answers.extend(resolution.rrset);
+            let authorities = resolution.soa.into_iter().collect();
+            query.response(code, answers, authorities, vec![])
+        }
+        Err(e) if e.is::<DomainNameError>() => {
+            debug!("Refusing {}: {e:#}", privacy::qname(&qname));
+            error(ResponseCode::Refused)
+        }
+        Err(e) => {
+            warn!("Resolving {}: {e}", privacy::qname(&qname));
+            error(ResponseCode::ServerFailure)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{QueryBuilder, QuestionType};
+    use crate::resolve::{Answer, RRset};
+    use crate::shutdown::Shutdown;
+    use rg_resolver_common::DomainName;
+    use std::net::Ipv4Addr;
+
+    /// Answers as a nameserver does, unlike [resolve::Static]: a name's CNAME answers a
+    /// question of any type, so the stub has a chain to follow, and names in the zone of its
+    /// SOA that have no records of the type, or none at all, get a negative answer.
+    struct Nameserver(RRset);
+
+    impl Resolve for Nameserver {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn lookup<'a>(
+            &'a self,
+            name: &'a str,
+            qtype: QuestionType,
+        ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+            Box::pin(async move {
+                let name = name.parse::<DomainName>()?;
+                let at_name = self.0.iter().filter(|rr| *rr.name() == name);
+                let rrset = at_name
+                    .clone()
+                    .filter(|rr| qtype.matches(rr.r#type()) || rr.r#type() == rr::Type::CNAME)
+                    .cloned()
+                    .collect::<RRset>();
+                if !rrset.is_empty() {
+                    return Ok(Some(Answer::Records(rrset)));
+                }
+                let Some(soa) = self
+                    .0
+                    .iter()
+                    .find(|rr| rr.r#type() == rr::Type::SOA && name.is_subdomain_of(rr.name()))
+                else {
+                    return Ok(None);
+                };
+                let (cnames, soa) = (RRset::new(), Some(soa.clone()));
+                Ok(Some(match at_name.count() {
+                    0 => Answer::NxDomain { cnames, soa },
+                    _ => Answer::NoData { cnames, soa },
+                }))
+            })
+        }
+    }
+
+    fn resolver() -> anyhow::Result<Nameserver> {
+        let record = |name: &str, r#type, data| {
+            rr::ResourceRecord::new(name.parse()?, r#type, rr::Class::IN, 300, data)
+        };
+        Ok(Nameserver(vec![
+            record(
+                "www.example.com.",
+                rr::Type::CNAME,
+                rr::Data::CNAME("example.com.".parse()?),
+            )?,
+            record(
+                "example.com.",
+                rr::Type::A,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )?,
+            record(
+                "example.com.",
+                rr::Type::SOA,
+                rr::Data::SOA {
+                    mname: "ns.example.com.".parse()?,
+                    rname: "hostmaster.example.com.".parse()?,
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 300,
+                },
+            )?,
+        ]))
+    }
+
+    fn query(name: &str, qtype: rr::Type) -> Message {
+        QueryBuilder::new()
+            .recursion_desired(true)
+            .question(
+                &name.parse().unwrap(),
+                QuestionType::RrType(qtype),
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build()
+    }
+
+    #[tokio::test]
+    async fn answers_queries() -> anyhow::Result<()> {
+        let resolver = resolver()?;
+        let authority = Authority::default();
+        let sent = query("www.example.com.", rr::Type::A);
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert!(response.is_response_to(&sent));
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let types = response
+            .answers()
+            .iter()
+            .map(|rr| rr.r#type())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [rr::Type::CNAME, rr::Type::A]);
+
+        // * A negative answer comes with the SOA, so the client can cache it.
+        let sent = query("gone.example.com.", rr::Type::A);
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NameError);
+        assert!(response.answers().is_empty());
+        assert_eq!(response.authorities()[0].r#type(), rr::Type::SOA);
+        let sent = query("www.example.com.", rr::Type::MX);
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers()[0].r#type(), rr::Type::CNAME);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.authorities()[0].r#type(), rr::Type::SOA);
+
+        // * No resolver knowing the name is a failure, not an empty answer.
+        let sent = query("nowhere.example.", rr::Type::A);
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::ServerFailure);
+
+        let sent = QueryBuilder::new()
+            .question(
+                &"example.com.".parse()?,
+                QuestionType::RrType(rr::Type::A),
+                QuestionClass::RrClass(rr::Class::CH),
+            )
+            .build();
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NotImplemented);
+
+        // * Responses and garbage aren't answered.
+        assert!(answer(&response.serialize()?, &resolver, &authority)
+            .await
+            .is_none());
+        assert!(answer(b"\x00\x01", &resolver, &authority).await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn requires_signed_queries() -> anyhow::Result<()> {
+        let resolver = resolver()?;
+        let key: tsig::Key = "client.example.com:c2VjcmV0".parse()?;
+        let authority = Authority::default().with_required_keys(vec![key.clone()]);
+
+        let mut sent = query("www.example.com.", rr::Type::A);
+        key.sign(&mut sent, None)?;
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        let response = Message::parse(&response.serialize()?)?;
+        key.verify(&response, sent.tsig().map(Tsig::mac))?;
+        // * The signature covers the whole answer, chain and all.
+        assert_eq!(response.answers().len(), 2);
+
+        let unsigned = query("www.example.com.", rr::Type::A);
+        let (_, response) = answer(&unsigned.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+
+        let other: tsig::Key = "other.example.com:c2VjcmV0".parse()?;
+        let mut sent = query("www.example.com.", rr::Type::A);
+        other.sign(&mut sent, None)?;
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NotAuth);
+        assert_eq!(
+            response.tsig().and_then(Tsig::error),
+            Some(tsig::Error::BadKey)
+        );
+
+        let forged: tsig::Key = "client.example.com:Zm9yZ2Vk".parse()?;
+        let mut sent = query("www.example.com.", rr::Type::A);
+        forged.sign(&mut sent, None)?;
+        let (_, response) = answer(&sent.serialize()?, &resolver, &authority)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.tsig().and_then(Tsig::error),
+            Some(tsig::Error::BadSig)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serves_udp_and_tcp() -> anyhow::Result<()> {
+        let addr: SocketAddr = "127.0.0.1:0".parse()?;
+        let udp = UdpSocket::bind(addr).await?;
+        // * TCP on the port UDP got, as clients retry truncated answers on the same port.
+        let tcp = TcpListener::bind(udp.local_addr()?).await?;
+        let server = udp.local_addr()?;
+        let shutdown = Shutdown::new();
+        let serving = tokio::spawn(serve_all(
+            vec![Listener { udp, tcp }],
+            Arc::new(resolver()?),
+            Arc::new(Authority::default()),
+            QueueConfig::default(),
+            shutdown.subscribe(),
+        ));
+
+        let sent = query("example.com.", rr::Type::A);
+        let client = UdpSocket::bind(addr).await?;
+        client.send_to(&sent.serialize()?, server).await?;
+        let mut buf = [0_u8; 512];
+        let len = client.recv(&mut buf).await?;
+        let response = Message::parse(&buf[..len])?;
+        assert!(response.is_response_to(&sent));
+        assert_eq!(response.answers().len(), 1);
+
+        let mut stream = TcpStream::connect(server).await?;
+        for _ in 0..2 {
+            stream.write_all(&sent.serialize_framed()?).await?;
+            let len = stream.read_u16().await?;
+            let mut buf = vec![0_u8; len as usize];
+            stream.read_exact(&mut buf).await?;
+            assert!(Message::parse(&buf)?.is_response_to(&sent));
+        }
+        drop(stream);
+
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+        serving.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::Mutex;
+
+/// The names being looked up with the OS resolver, lowercased.
+static IN_FLIGHT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// A name being looked up with the OS resolver, until it's dropped.
+pub struct InFlight(String);
+
+impl InFlight {
+    /// Marks name as being looked up, or returns None if it already is.
+    ///
+    /// When this resolver is the OS's nameserver, the OS resolver passes it the question it was
+    /// asked, so a second lookup of a name in flight is likely that question come back, and
+    /// asking the OS resolver again would loop.
+    pub fn begin(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if in_flight.contains(&name) {
+            return None;
+        }
+        in_flight.push(name.clone());
+        Some(InFlight(name))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().retain(|name| *name != self.0);
+    }
+}
+
+/// Looks up the addresses of name using the OS resolution facilities
+/// (getaddrinfo on Unix, the Windows equivalent on Windows).
+///
+/// This is a last resort for when no nameserver can be reached, so the results
+/// carry no TTLs or other record data.
+pub fn lookup_addresses(name: &str) -> anyhow::Result<Vec<IpAddr>> {
+    // The OS resolver expects a host name rather than an absolute domain name.
+    let host = name.strip_suffix('.').unwrap_or(name);
+    if host.is_empty() {
+        anyhow::bail!("system lookup: empty name");
+    }
+    let mut addrs = Vec::new();
+    for sock_addr in (host, 0).to_socket_addrs()? {
+        if !addrs.contains(&sock_addr.ip()) {
+            addrs.push(sock_addr.ip());
+        }
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_localhost() -> anyhow::Result<()> {
+        let addrs = lookup_addresses("localhost.")?;
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(IpAddr::is_loopback));
+        Ok(())
+    }
+
+    #[test]
+    fn in_flight() {
+        let first = InFlight::begin("loop.example.");
+        assert!(first.is_some());
+        assert!(InFlight::begin("LOOP.example.").is_none());
+        drop(first);
+        assert!(InFlight::begin("loop.example.").is_some());
+    }
+
+    #[test]
+    fn lookup_empty_name() {
+        assert!(lookup_addresses(".").is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::task::JoinHandle;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::reload;
+
+/// Spawns a long-lived task under a name that shows up in tokio-console.
+///
+/// Tasks can only be named when built with RUSTFLAGS="--cfg tokio_unstable";
+/// otherwise this is tokio::spawn.
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("spawning task");
+
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Changes which events are logged, for [set_log_filter]. Set by [init_tracing].
+type SetLogFilter = Box<dyn Fn(EnvFilter) -> anyhow::Result<()> + Send + Sync>;
+
+static SET_LOG_FILTER: OnceLock<SetLogFilter> = OnceLock::new();
+
+/// Sets up logging, at the level RUST_LOG says or else info, and with the console feature,
+/// also serves task instrumentation to tokio-console on its default port.
+pub fn init_tracing() {
+    use tracing_subscriber::prelude::*;
+
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = SET_LOG_FILTER.set(Box::new(move |filter| Ok(handle.reload(filter)?)));
+
+    // * Only the log is filtered; tokio-console still sees every task.
+    #[cfg(feature = "console")]
+    tracing_subscriber::registry()
+        .with(console_subscriber::spawn())
+        .with(tracing_subscriber::fmt::layer().with_filter(filter))
+        .init();
+
+    #[cfg(not(feature = "console"))]
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(filter))
+        .init();
+}
+
+/// Logs what directives say from now on, given as for RUST_LOG, e.g. "debug" or
+/// "rg_resolver::net=trace".
+pub fn set_log_filter(directives: &str) -> anyhow::Result<()> {
+    let filter = EnvFilter::builder().parse(directives)?;
+    let set = SET_LOG_FILTER
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("logging isn't set up"))?;
+    set(filter)
+}
@@ -0,0 +1,282 @@
+//! An in-process nameserver for tests, so resolution can be exercised without the network.
+
+use crate::message::{Message, ResponseCode};
+use crate::rr;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+
+/// A nameserver answering from canned records over UDP and TCP on the same loopback port.
+///
+/// It answers like an authoritative server for every name: the records of the asked-for type at
+/// the name, following CNAMEs through the records, NXDOMAIN for a name with no records, and
+/// NODATA for one without records of the type. Negative answers carry the first SOA record, if
+/// there is one.
+pub struct FakeNameserver {
+    records: Vec<rr::ResourceRecord>,
+    /// UDP queries to ignore before answering any, to make the client retransmit.
+    dropped: usize,
+    truncating: bool,
+    one_cname: bool,
+}
+
+impl FakeNameserver {
+    pub fn new(records: Vec<rr::ResourceRecord>) -> Self {
+        FakeNameserver {
+            records,
+            dropped: 0,
+            truncating: false,
+            one_cname: false,
+        }
+    }
+
+    /// Ignores the first queries UDP queries.
+    pub fn dropping(mut self, queries: usize) -> Self {
+        self.dropped = queries;
+        self
+    }
+
+    /// Answers every UDP query with an empty, truncated response, so the full response has to
+    /// be fetched over TCP.
+    pub fn truncating(mut self) -> Self {
+        self.truncating = true;
+        self
+    }
+
+    /// Answers with just the CNAME for a name that has one, as an upstream that leaves the
+    /// rest of the chain to the resolver would.
+    pub fn one_cname_per_response(mut self) -> Self {
+        self.one_cname = true;
+        self
+    }
+
+    /// Starts answering queries, until the returned server is dropped.
+    pub async fn start(self) -> anyhow::Result<RunningNameserver> {
+        let udp = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = udp.local_addr()?;
+        let tcp = TcpListener::bind(addr).await?;
+        let server = Arc::new(self);
+        let counts = Arc::new(Counts::default());
+        let tasks = vec![
+            tokio::spawn(serve_udp(udp, server.clone(), counts.clone())),
+            tokio::spawn(serve_tcp(tcp, server, counts.clone())),
+        ];
+        Ok(RunningNameserver {
+            addr,
+            counts,
+            tasks,
+        })
+    }
+
+    fn respond(&self, query: &Message) -> Message {
+        let Some(question) = query.questions().first() else {
+            return query.empty_response(ResponseCode::FormatError);
+        };
+        let mut answers = Vec::new();
+        let mut current = question.name();
+        let code = loop {
+            let at_name = self
+                .records
+                .iter()
+                .filter(|rr| rr.name() == current)
+                .collect::<Vec<_>>();
+            if at_name.is_empty() {
+                // * A chain leading to a name that doesn't exist is NXDOMAIN too.
+                break ResponseCode::NameError;
+            }
+            let found = at_name
+                .iter()
+                .filter(|rr| question.r#type().matches(rr.r#type()))
+                .map(|rr| (*rr).clone())
+                .collect::<Vec<_>>();
+            if !found.is_empty() {
+                answers.extend(found);
+                break ResponseCode::NoError;
+            }
+            let cname = at_name.iter().find_map(|rr| match rr.data() {
+                rr::Data::CNAME(target) => Some((*rr, target)),
+                _ => None,
+            });
+            let Some((cname, target)) = cname else {
+                break ResponseCode::NoError;
+            };
+            // * A looping chain stops where it would go round again.
+            if answers.contains(cname) {
+                break ResponseCode::NoError;
+            }
+            answers.push(cname.clone());
+            if self.one_cname {
+                return query.answer_response(answers);
+            }
+            current = target;
+        };
+        let authorities = match code {
+            ResponseCode::NoError if !answers.iter().all(is_cname) => Vec::new(),
+            _ => self
+                .records
+                .iter()
+                .find(|rr| rr.r#type() == rr::Type::SOA)
+                .cloned()
+                .into_iter()
+                .collect(),
+        };
+        query.response(code, answers, authorities, vec![])
+    }
+}
+
+fn is_cname(rr: &rr::ResourceRecord) -> bool {
+    rr.r#type() == rr::Type::CNAME
+}
+
+/// The queries a running nameserver has received.
+#[derive(Default)]
+struct Counts {
+    udp: AtomicUsize,
+    tcp: AtomicUsize,
+}
+
+/// A started [FakeNameserver], which stops when dropped.
+pub struct RunningNameserver {
+    pub addr: SocketAddr,
+    counts: Arc<Counts>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl RunningNameserver {
+    /// The UDP queries received, including those ignored.
+    pub fn udp_queries(&self) -> usize {
+        self.counts.udp.load(Ordering::Relaxed)
+    }
+
+    pub fn tcp_queries(&self) -> usize {
+        self.counts.tcp.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for RunningNameserver {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+async fn serve_udp(sock: UdpSocket, server: Arc<FakeNameserver>, counts: Arc<Counts>) {
+    let mut buf = vec![0_u8; 65535];
+    while let Ok((size, from)) = sock.recv_from(&mut buf).await {
+        let received = counts.udp.fetch_add(1, Ordering::Relaxed) + 1;
+        if received <= server.dropped {
+            continue;
+        }
+        let Ok(query) = Message::parse(&buf[..size]) else {
+            continue;
+        };
+        let response = server.respond(&query);
+        let limit = match (server.truncating, query.edns()) {
+            (true, _) => 0,
+            (false, Some(edns)) => edns.udp_payload_size.max(512) as usize,
+            (false, None) => 512,
+        };
+        let _ = sock
+            .send_to(&response.serialize_truncating(limit).unwrap(), from)
+            .await;
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, server: Arc<FakeNameserver>, counts: Arc<Counts>) {
+    while let Ok((mut stream, _)) = listener.accept().await {
+        let (server, counts) = (server.clone(), counts.clone());
+        tokio::spawn(async move {
+            while let Ok(len) = stream.read_u16().await {
+                let mut buf = vec![0_u8; len as usize];
+                if stream.read_exact(&mut buf).await.is_err() {
+                    return;
+                }
+                counts.tcp.fetch_add(1, Ordering::Relaxed);
+                let Ok(query) = Message::parse(&buf) else {
+                    return;
+                };
+                let response = server.respond(&query).serialize_framed().unwrap();
+                if stream.write_all(&response).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::classify::{self, Classification};
+    use crate::message::QuestionType;
+    use crate::net::Upstreams;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    fn records() -> anyhow::Result<Vec<rr::ResourceRecord>> {
+        let record = |name: &str, r#type, data| {
+            rr::ResourceRecord::new(name.parse()?, r#type, rr::Class::IN, 300, data)
+        };
+        Ok(vec![
+            record(
+                "www.example.com.",
+                rr::Type::CNAME,
+                rr::Data::CNAME("example.com.".parse()?),
+            )?,
+            record(
+                "example.com.",
+                rr::Type::A,
+                rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )?,
+        ])
+    }
+
+    fn query(name: &str) -> Message {
+        Message::query(&name.parse().unwrap(), QuestionType::RrType(rr::Type::A))
+    }
+
+    #[tokio::test]
+    async fn follows_cnames() -> anyhow::Result<()> {
+        let server = FakeNameserver::new(records()?).start().await?;
+        let upstreams = Upstreams::new(vec![server.addr], Duration::from_secs(5));
+
+        let query = query("www.example.com.");
+        let (_, response) = upstreams.exchange(&query).await?;
+        let Classification::Answer(records) = classify::classify(&query, &response) else {
+            panic!("not an answer: {response:?}");
+        };
+        assert_eq!(records, self::records()?);
+
+        let query = self::query("nowhere.example.com.");
+        let (_, response) = upstreams.exchange(&query).await?;
+        assert_eq!(response.response_code(), ResponseCode::NameError);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retransmits_to_dropping_server() -> anyhow::Result<()> {
+        let server = FakeNameserver::new(records()?).dropping(1).start().await?;
+        let upstreams = Upstreams::new(vec![server.addr], Duration::from_millis(50));
+
+        let (_, response) = upstreams.exchange(&query("example.com.")).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(server.udp_queries(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_tcp() -> anyhow::Result<()> {
+        let server = FakeNameserver::new(records()?).truncating().start().await?;
+        let upstreams = Upstreams::new(vec![server.addr], Duration::from_secs(5));
+
+        let (_, response) = upstreams.exchange(&query("example.com.")).await?;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!((server.udp_queries(), server.tcp_queries()), (1, 1));
+        assert_eq!(upstreams.stats()[0].1.tcp_fallbacks, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A way of carrying queries to an upstream.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+    /// DNS over TLS (RFC 7858).
+    Tls,
+}
+
+impl FromStr for Transport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "udp" => Ok(Transport::Udp),
+            "tcp" => Ok(Transport::Tcp),
+            "tls" | "dot" => Ok(Transport::Tls),
+            _ => anyhow::bail!("unknown transport {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+            Transport::Tls => "tls",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The transports an upstream may be reached over, most preferred first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportPolicy {
+    pub preferences: Vec<Transport>,
+    /// How long to stay on a fallback transport before trying the preferred one again.
+    pub retry_upgrade: Duration,
+}
+
+impl TransportPolicy {
+    const DEFAULT_RETRY_UPGRADE: Duration = Duration::from_secs(5 * 60);
+
+    pub fn new(preferences: Vec<Transport>) -> anyhow::Result<Self> {
+        if preferences.is_empty() {
+            anyhow::bail!("transport policy: no transports");
+        }
+        Ok(TransportPolicy {
+            preferences,
+            retry_upgrade: Self::DEFAULT_RETRY_UPGRADE,
+        })
+    }
+
+    /// Parses a policy of the form `<transport>[,<transport>...] [retry <minutes>]`,
+    /// e.g. `dot,udp,tcp retry 10`.
+    pub fn parse(config: &str) -> anyhow::Result<Self> {
+        let mut fields = config.split_whitespace();
+        let Some(preferences) = fields.next() else {
+            anyhow::bail!("parsing transport policy: empty policy");
+        };
+        let preferences = preferences
+            .split(',')
+            .map(Transport::from_str)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("parsing transport policy: {e}"))?;
+        let mut policy = Self::new(preferences)?;
+        match (fields.next(), fields.next(), fields.next()) {
+            (None, _, _) => {}
+            (Some("retry"), Some(minutes), None) => {
+                let minutes = minutes
+                    .parse::<u64>()
+                    .map_err(|e| anyhow::anyhow!("parsing transport policy: {e}"))?;
+                policy.retry_upgrade = Duration::from_secs(minutes * 60);
+            }
+            _ => anyhow::bail!("parsing transport policy: expected `retry <minutes>`"),
+        }
+        Ok(policy)
+    }
+}
+
+/// Chooses the transport for one upstream, stepping down the preferences when a transport
+/// can't be set up (e.g. the TLS handshake fails) and periodically trying to step back up.
+pub struct TransportSelector {
+    policy: TransportPolicy,
+    state: Mutex<SelectorState>,
+}
+
+#[derive(Default)]
+struct SelectorState {
+    step: Step,
+    downgrades: u64,
+    upgrades: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Step {
+    /// Using the most preferred transport.
+    #[default]
+    Preferred,
+    /// Using the fallback at this index into the preferences.
+    Downgraded { index: usize, since: Instant },
+    /// Trying the most preferred transport again, returning to the fallback if it still fails.
+    Upgrading { fallback: usize },
+}
+
+impl TransportSelector {
+    pub fn new(policy: TransportPolicy) -> Self {
+        TransportSelector {
+            policy,
+            state: Mutex::new(SelectorState::default()),
+        }
+    }
+
+    /// The transport to use for the next query.
+    pub fn select(&self) -> Transport {
+        let mut state = self.state.lock().unwrap();
+        if let Step::Downgraded { index, since } = state.step {
+            if since.elapsed() >= self.policy.retry_upgrade {
+                state.step = Step::Upgrading { fallback: index };
+            }
+        }
+        self.transport(state.step)
+    }
+
+    /// The transport in use, without starting an upgrade attempt.
+    pub fn current(&self) -> Transport {
+        self.transport(self.state.lock().unwrap().step)
+    }
+
+    /// Records a query carried over transport.
+    pub fn record_success(&self, transport: Transport) {
+        let mut state = self.state.lock().unwrap();
+        if let Step::Upgrading { .. } = state.step {
+            if transport == self.policy.preferences[0] {
+                state.step = Step::Preferred;
+                state.upgrades += 1;
+            }
+        }
+    }
+
+    /// Records that transport couldn't be set up, so the next fallback should be used.
+    pub fn record_failure(&self, transport: Transport) {
+        let mut state = self.state.lock().unwrap();
+        if transport != self.transport(state.step) {
+            // * A failure reported late, after the selector already moved on.
+            return;
+        }
+        let last = self.policy.preferences.len() - 1;
+        let index = match state.step {
+            Step::Preferred => 1.min(last),
+            Step::Downgraded { index, .. } => (index + 1).min(last),
+            Step::Upgrading { fallback } => fallback,
+        };
+        if index == 0 {
+            // * There's nothing to fall back to.
+            return;
+        }
+        state.step = Step::Downgraded {
+            index,
+            since: Instant::now(),
+        };
+        state.downgrades += 1;
+    }
+
+    /// The number of times the selector stepped down and back up to the preferred transport.
+    pub fn switches(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.downgrades, state.upgrades)
+    }
+
+    fn transport(&self, step: Step) -> Transport {
+        match step {
+            Step::Preferred | Step::Upgrading { .. } => self.policy.preferences[0],
+            Step::Downgraded { index, .. } => self.policy.preferences[index],
+        }
+    }
+}
+
+/// The transport selectors of every upstream.
+#[derive(Default)]
+pub struct UpstreamTransports {
+    selectors: BTreeMap<SocketAddr, TransportSelector>,
+}
+
+impl UpstreamTransports {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, upstream: SocketAddr, policy: TransportPolicy) {
+        self.selectors
+            .insert(upstream, TransportSelector::new(policy));
+    }
+
+    pub fn get(&self, upstream: SocketAddr) -> Option<&TransportSelector> {
+        self.selectors.get(&upstream)
+    }
+
+    /// The transport currently in use for each upstream.
+    pub fn report(&self) -> Vec<(SocketAddr, Transport)> {
+        self.selectors
+            .iter()
+            .map(|(&upstream, selector)| (upstream, selector.current()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_policy() -> anyhow::Result<()> {
+        let policy = TransportPolicy::parse("dot,UDP,tcp retry 10")?;
+        assert_eq!(
+            policy.preferences,
+            [Transport::Tls, Transport::Udp, Transport::Tcp]
+        );
+        assert_eq!(policy.retry_upgrade, Duration::from_secs(600));
+        assert_eq!(
+            TransportPolicy::parse("udp")?.retry_upgrade,
+            TransportPolicy::DEFAULT_RETRY_UPGRADE
+        );
+
+        assert!(TransportPolicy::parse("").is_err());
+        assert!(TransportPolicy::parse("quic").is_err());
+        assert!(TransportPolicy::parse("tls retry").is_err());
+        assert!(TransportPolicy::parse("tls every 10").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn downgrade() -> anyhow::Result<()> {
+        let selector = TransportSelector::new(TransportPolicy::parse("tls,udp,tcp")?);
+        assert_eq!(selector.select(), Transport::Tls);
+        selector.record_failure(Transport::Tls);
+        assert_eq!(selector.select(), Transport::Udp);
+        selector.record_failure(Transport::Udp);
+        assert_eq!(selector.select(), Transport::Tcp);
+        // * The last fallback is kept even when it fails.
+        selector.record_failure(Transport::Tcp);
+        assert_eq!(selector.select(), Transport::Tcp);
+        // * A late failure of a transport no longer in use changes nothing.
+        selector.record_failure(Transport::Tls);
+        assert_eq!(selector.select(), Transport::Tcp);
+        assert_eq!(selector.switches(), (3, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn upgrade() -> anyhow::Result<()> {
+        let mut policy = TransportPolicy::parse("tls,udp")?;
+        policy.retry_upgrade = Duration::ZERO;
+        let selector = TransportSelector::new(policy);
+
+        selector.record_failure(Transport::Tls);
+        assert_eq!(selector.current(), Transport::Udp);
+        // * The retry interval has passed, so the next query tries TLS again.
+        assert_eq!(selector.select(), Transport::Tls);
+        selector.record_failure(Transport::Tls);
+        assert_eq!(selector.current(), Transport::Udp);
+
+        assert_eq!(selector.select(), Transport::Tls);
+        selector.record_success(Transport::Tls);
+        assert_eq!(selector.current(), Transport::Tls);
+        assert_eq!(selector.switches(), (2, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn stays_downgraded_until_retry() -> anyhow::Result<()> {
+        let selector = TransportSelector::new(TransportPolicy::parse("tls,udp retry 10")?);
+        selector.record_failure(Transport::Tls);
+        assert_eq!(selector.select(), Transport::Udp);
+        selector.record_success(Transport::Udp);
+        assert_eq!(selector.select(), Transport::Udp);
+        Ok(())
+    }
+
+    #[test]
+    fn report() -> anyhow::Result<()> {
+        let a: SocketAddr = "192.0.2.1:853".parse()?;
+        let b: SocketAddr = "192.0.2.2:53".parse()?;
+        let mut transports = UpstreamTransports::new();
+        transports.add(a, TransportPolicy::parse("tls,udp")?);
+        transports.add(b, TransportPolicy::parse("udp,tcp")?);
+        transports.get(a).unwrap().record_failure(Transport::Tls);
+        assert_eq!(
+            transports.report(),
+            [(a, Transport::Udp), (b, Transport::Udp)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,767 @@
+//! Transaction signatures (RFC 8945): an HMAC over a message, keyed by a secret the client and
+//! server share, carried in a TSIG pseudo-record at the very end of the message. A response's
+//! MAC also covers the request's, tying the two together.
+
+use crate::edns::Padding;
+use crate::message::Message;
+use crate::wire::Writer;
+use crate::{encoding, name};
+use bytes::{Buf, BufMut};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use rg_resolver_common::DomainName;
+use sha2::{Sha256, Sha384, Sha512};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The RR type of the TSIG pseudo-record.
+const TSIG_TYPE: u16 = 250;
+
+/// The class of the TSIG pseudo-record, ANY.
+const TSIG_CLASS: u16 = 255;
+
+/// How many seconds apart the signer's and verifier's clocks may be, as RFC 8945 section 10
+/// recommends.
+pub const DEFAULT_FUDGE: u16 = 300;
+
+/// The most messages of a stream, like a zone transfer, that may go unsigned in a row
+/// (RFC 8945 section 5.3.1).
+const MAX_UNSIGNED: usize = 99;
+
+/// A MAC algorithm, named by a domain name in the TSIG record.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha384,
+    HmacSha512,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> DomainName {
+        self.to_string()
+            .parse()
+            .expect("algorithm names are valid domain names")
+    }
+
+    /// The length of the MAC, which is never sent truncated.
+    fn mac_len(&self) -> usize {
+        match self {
+            Algorithm::HmacSha256 => 32,
+            Algorithm::HmacSha384 => 48,
+            Algorithm::HmacSha512 => 64,
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::HmacSha256 => compute_mac::<Hmac<Sha256>>(secret, data),
+            Algorithm::HmacSha384 => compute_mac::<Hmac<Sha384>>(secret, data),
+            Algorithm::HmacSha512 => compute_mac::<Hmac<Sha512>>(secret, data),
+        }
+    }
+
+    /// Returns true if mac is the MAC of data, comparing in constant time.
+    fn verify(&self, secret: &[u8], data: &[u8], mac: &[u8]) -> bool {
+        match self {
+            Algorithm::HmacSha256 => verify_hmac::<Hmac<Sha256>>(secret, data, mac),
+            Algorithm::HmacSha384 => verify_hmac::<Hmac<Sha384>>(secret, data, mac),
+            Algorithm::HmacSha512 => verify_hmac::<Hmac<Sha512>>(secret, data, mac),
+        }
+    }
+}
+
+fn compute_mac<M: Mac + KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn verify_hmac<M: Mac + KeyInit>(secret: &[u8], data: &[u8], tag: &[u8]) -> bool {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.verify_slice(tag).is_ok()
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Algorithm::HmacSha256 => "hmac-sha256.",
+            Algorithm::HmacSha384 => "hmac-sha384.",
+            Algorithm::HmacSha512 => "hmac-sha512.",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    /// Parses an algorithm's name, with or without the trailing dot, in any case.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim_end_matches('.').to_ascii_lowercase().as_str() {
+            "hmac-sha256" => Ok(Algorithm::HmacSha256),
+            "hmac-sha384" => Ok(Algorithm::HmacSha384),
+            "hmac-sha512" => Ok(Algorithm::HmacSha512),
+            _ => anyhow::bail!("unsupported TSIG algorithm {s}"),
+        }
+    }
+}
+
+/// Why a message's signature wasn't accepted. All but Unsigned are the TSIG error codes a
+/// server reports them with, in a NOTAUTH response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The message has no TSIG record.
+    Unsigned,
+    /// The MAC doesn't match.
+    BadSig,
+    /// The key or algorithm isn't known.
+    BadKey,
+    /// The message was signed longer ago than the fudge allows, or too far in the future.
+    BadTime,
+    /// The MAC is truncated.
+    BadTrunc,
+    /// Another error code, from a server.
+    Other(u16),
+}
+
+impl Error {
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => None,
+            16 => Some(Error::BadSig),
+            17 => Some(Error::BadKey),
+            18 => Some(Error::BadTime),
+            22 => Some(Error::BadTrunc),
+            n => Some(Error::Other(n)),
+        }
+    }
+
+    /// The code the error is reported with. Unsigned has none of its own, so it's reported as
+    /// BADSIG.
+    fn code(&self) -> u16 {
+        match self {
+            Error::Unsigned | Error::BadSig => 16,
+            Error::BadKey => 17,
+            Error::BadTime => 18,
+            Error::BadTrunc => 22,
+            Error::Other(code) => *code,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Unsigned => f.write_str("message isn't signed"),
+            Error::BadSig => f.write_str("BADSIG"),
+            Error::BadKey => f.write_str("BADKEY"),
+            Error::BadTime => f.write_str("BADTIME"),
+            Error::BadTrunc => f.write_str("BADTRUNC"),
+            Error::Other(code) => write!(f, "TSIG error {code}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A secret shared with a client or server, and the name and algorithm it's known by.
+#[derive(Clone, PartialEq)]
+pub struct Key {
+    name: DomainName,
+    algorithm: Algorithm,
+    secret: Vec<u8>,
+}
+
+impl Key {
+    pub fn new(name: DomainName, algorithm: Algorithm, secret: Vec<u8>) -> Self {
+        Key {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    pub fn name(&self) -> &DomainName {
+        &self.name
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Signs message, replacing any TSIG record it has. A response is signed with the MAC of
+    /// the request it answers.
+    pub fn sign(&self, message: &mut Message, request_mac: Option<&[u8]>) -> anyhow::Result<()> {
+        self.sign_at(message, request_mac, None, now())
+    }
+
+    /// Signs a response rejecting a request with error, as only BADTIME is (RFC 8945 section
+    /// 5.2.3). The response carries the time here, so the client can tell how far off it is.
+    pub fn sign_rejection(
+        &self,
+        response: &mut Message,
+        request_mac: &[u8],
+        error: Error,
+    ) -> anyhow::Result<()> {
+        self.sign_at(response, Some(request_mac), Some(error), now())
+    }
+
+    fn sign_at(
+        &self,
+        message: &mut Message,
+        request_mac: Option<&[u8]>,
+        error: Option<Error>,
+        time_signed: u64,
+    ) -> anyhow::Result<()> {
+        message.set_tsig(None);
+        let signed = message.serialize_padded(Padding::None)?;
+        let other = match error {
+            Some(Error::BadTime) => time_signed.to_be_bytes()[2..].to_vec(),
+            _ => Vec::new(),
+        };
+        let mut tsig = Tsig {
+            key_name: self.name.clone(),
+            algorithm: self.algorithm.name(),
+            time_signed,
+            fudge: DEFAULT_FUDGE,
+            mac: Vec::new(),
+            original_id: message.id(),
+            error: error.map_or(0, |error| error.code()),
+            other,
+            signed,
+        };
+        let mut data = prior_mac(request_mac);
+        data.extend_from_slice(&tsig.signed);
+        data.append(&mut tsig.variables(false)?);
+        tsig.mac = self.algorithm.mac(&self.secret, &data);
+        message.set_tsig(Some(tsig));
+        Ok(())
+    }
+
+    /// Checks that message is signed with this key. A response has to be signed with the MAC
+    /// of the request it answers, and an error the server reports in its TSIG record is
+    /// returned as it is.
+    pub fn verify(&self, message: &Message, request_mac: Option<&[u8]>) -> Result<(), Error> {
+        let tsig = message.tsig().ok_or(Error::Unsigned)?;
+        let mut data = prior_mac(request_mac);
+        data.extend_from_slice(&tsig.signed);
+        data.append(&mut tsig.variables(false).map_err(|_| Error::BadKey)?);
+        self.check(tsig, &data, now())
+    }
+
+    /// Checks tsig's MAC is that of data, in the order RFC 8945 section 5.2 does.
+    fn check(&self, tsig: &Tsig, data: &[u8], now: u64) -> Result<(), Error> {
+        if tsig.key_name != self.name || tsig.algorithm != self.algorithm.name() {
+            return Err(Error::BadKey);
+        }
+        if let Some(error) = Error::from_code(tsig.error) {
+            return Err(error);
+        }
+        if tsig.mac.len() < self.algorithm.mac_len() {
+            return Err(Error::BadTrunc);
+        }
+        if !self.algorithm.verify(&self.secret, data, &tsig.mac) {
+            return Err(Error::BadSig);
+        }
+        if now.abs_diff(tsig.time_signed) > u64::from(tsig.fudge) {
+            return Err(Error::BadTime);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Key {
+    /// The key without its secret, so it can't end up in a log.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// Parses a key as dig's -y option takes it, `[<algorithm>:]<name>:<base64 secret>`,
+    /// with HMAC-SHA256 unless an algorithm is given.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let fields = s.split(':').collect::<Vec<_>>();
+        let (algorithm, name, secret) = match fields[..] {
+            [name, secret] => (Algorithm::HmacSha256, name, secret),
+            [algorithm, name, secret] => (algorithm.parse()?, name, secret),
+            _ => anyhow::bail!("invalid TSIG key {s}, expected [algorithm:]name:secret"),
+        };
+        // * Key names are absolute, whether or not they're written with the trailing dot.
+        let absolute = if name.ends_with('.') {
+            name.to_string()
+        } else {
+            format!("{name}.")
+        };
+        let name = absolute
+            .parse::<DomainName>()
+            .map_err(|e| anyhow::anyhow!("invalid TSIG key name {name}: {e}"))?;
+        let secret = encoding::decode_base64(secret)
+            .map_err(|e| anyhow::anyhow!("invalid TSIG key {name}: {e}"))?;
+        Ok(Key::new(name, algorithm, secret))
+    }
+}
+
+/// Checks the signatures on a stream of responses, like a zone transfer, where each one
+/// signed covers the MAC before it and any unsigned messages since (RFC 8945 section 5.3.1).
+pub struct Verifier {
+    key: Key,
+    prior_mac: Vec<u8>,
+    /// The messages since the last signed one.
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+    verified_any: bool,
+}
+
+impl Verifier {
+    /// Checks the responses to a request signed with key and request_mac.
+    pub fn new(key: Key, request_mac: &[u8]) -> Self {
+        Verifier {
+            key,
+            prior_mac: request_mac.to_vec(),
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+            verified_any: false,
+        }
+    }
+
+    /// Checks message, parsed from buf. The first message has to be signed, but later ones
+    /// may not be, as long as one of the next 100 is.
+    pub fn verify(&mut self, buf: &[u8], message: &Message) -> Result<(), Error> {
+        let Some(tsig) = message.tsig() else {
+            if !self.verified_any || self.unsigned_count == MAX_UNSIGNED {
+                return Err(Error::Unsigned);
+            }
+            self.unsigned.extend_from_slice(buf);
+            self.unsigned_count += 1;
+            return Ok(());
+        };
+        let mut data = prior_mac(Some(self.prior_mac.as_slice()));
+        data.append(&mut self.unsigned);
+        data.extend_from_slice(&tsig.signed);
+        // * Only the first message's MAC covers all the TSIG variables; the rest, just the time.
+        data.append(
+            &mut tsig
+                .variables(self.verified_any)
+                .map_err(|_| Error::BadKey)?,
+        );
+        self.key.check(tsig, &data, now())?;
+        self.prior_mac.clone_from(&tsig.mac);
+        self.unsigned_count = 0;
+        self.verified_any = true;
+        Ok(())
+    }
+
+    /// Checks that the stream ended with a signed message.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.verified_any && self.unsigned_count == 0 {
+            Ok(())
+        } else {
+            Err(Error::Unsigned)
+        }
+    }
+}
+
+/// A message's TSIG pseudo-record. Like the OPT record, it isn't included in the additional
+/// records.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tsig {
+    key_name: DomainName,
+    algorithm: DomainName,
+    /// Seconds since the Unix epoch, in 48 bits.
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+    /// The message the MAC was computed over: the bytes before the TSIG record, with the
+    /// original ID and without the TSIG record counted.
+    signed: Vec<u8>,
+}
+
+impl Tsig {
+    /// An unsigned TSIG record rejecting the request signed with request, for any error but
+    /// BADTIME (RFC 8945 section 5.2).
+    pub fn rejection(request: &Tsig, error: Error) -> Tsig {
+        Tsig {
+            mac: Vec::new(),
+            error: error.code(),
+            other: Vec::new(),
+            signed: Vec::new(),
+            ..request.clone()
+        }
+    }
+
+    pub fn key_name(&self) -> &DomainName {
+        &self.key_name
+    }
+
+    pub fn algorithm(&self) -> &DomainName {
+        &self.algorithm
+    }
+
+    pub fn mac(&self) -> &[u8] {
+        &self.mac
+    }
+
+    /// The error the signer reports, if any.
+    pub fn error(&self) -> Option<Error> {
+        Error::from_code(self.error)
+    }
+
+    pub(crate) fn is_next<'a>(msg: &'a [u8], unparsed: &'a [u8]) -> bool {
+        let mut peek = unparsed;
+        name::parse(msg, &mut peek).is_ok() && peek.get(..2) == Some(&TSIG_TYPE.to_be_bytes()[..])
+    }
+
+    /// msg must point to the very first byte of the message.
+    pub(crate) fn parse<'a>(msg: &'a [u8], unparsed: &mut &'a [u8]) -> anyhow::Result<Self> {
+        let start = msg.len() - unparsed.len();
+        let key_name = name::parse(msg, unparsed)?;
+        if unparsed.remaining() < 10 {
+            anyhow::bail!("parsing TSIG record: incomplete record");
+        }
+        let _type = unparsed.get_u16();
+        let class = unparsed.get_u16();
+        let ttl = unparsed.get_u32();
+        if class != TSIG_CLASS || ttl != 0 {
+            anyhow::bail!("parsing TSIG record: class must be ANY and TTL 0");
+        }
+        let data_len = unparsed.get_u16() as usize;
+        if unparsed.remaining() < data_len {
+            anyhow::bail!("parsing TSIG record: incomplete data");
+        }
+        let mut data = &unparsed[..data_len];
+        unparsed.advance(data_len);
+
+        let algorithm = name::parse(msg, &mut data)?;
+        if data.remaining() < 10 {
+            anyhow::bail!("parsing TSIG record: incomplete data");
+        }
+        let time_signed = (u64::from(data.get_u16()) << 32) | u64::from(data.get_u32());
+        let fudge = data.get_u16();
+        let mac_len = data.get_u16() as usize;
+        if data.remaining() < mac_len + 6 {
+            anyhow::bail!("parsing TSIG record: incomplete MAC");
+        }
+        let mac = data[..mac_len].to_vec();
+        data.advance(mac_len);
+        let original_id = data.get_u16();
+        let error = data.get_u16();
+        let other_len = data.get_u16() as usize;
+        if data.remaining() != other_len {
+            anyhow::bail!("parsing TSIG record: other data doesn't fill the record");
+        }
+        let other = data.to_vec();
+
+        // * The MAC was computed before the record was added, and before any forwarder gave
+        // * the message another ID (RFC 8945 section 4.3.1).
+        let mut signed = msg[..start].to_vec();
+        signed[..2].copy_from_slice(&original_id.to_be_bytes());
+        let additional_count = u16::from_be_bytes([signed[10], signed[11]]).saturating_sub(1);
+        signed[10..12].copy_from_slice(&additional_count.to_be_bytes());
+
+        Ok(Tsig {
+            key_name,
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+            signed,
+        })
+    }
+
+    pub(crate) fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = name::serialize(&self.key_name, None)?;
+        buf.put_u16(TSIG_TYPE);
+        buf.put_u16(TSIG_CLASS);
+        buf.put_u32(0);
+        let mut data = name::serialize(&self.algorithm, None)?;
+        data.put_u16((self.time_signed >> 32) as u16);
+        data.put_u32(self.time_signed as u32);
+        data.put_u16(self.fudge);
+        data.put_u16(self.mac.len() as u16);
+        data.put_slice(&self.mac);
+        data.put_u16(self.original_id);
+        data.put_u16(self.error);
+        data.put_u16(self.other.len() as u16);
+        data.put_slice(&self.other);
+        buf.put_u16(data.len() as u16);
+        buf.append(&mut data);
+        Ok(buf)
+    }
+
+    pub(crate) fn write(&self, w: &mut Writer) -> anyhow::Result<()> {
+        name::serialize_into(&self.key_name, None, w)?;
+        w.put_u16(TSIG_TYPE)?;
+        w.put_u16(TSIG_CLASS)?;
+        w.put_u32(0)?;
+        // * The data length is filled in once the data is written.
+        let len_offset = w.len();
+        w.put_u16(0)?;
+        name::serialize_into(&self.algorithm, None, w)?;
+        w.put_u16((self.time_signed >> 32) as u16)?;
+        w.put_u32(self.time_signed as u32)?;
+        w.put_u16(self.fudge)?;
+        w.put_u16(self.mac.len() as u16)?;
+        w.put_slice(&self.mac)?;
+        w.put_u16(self.original_id)?;
+        w.put_u16(self.error)?;
+        w.put_u16(self.other.len() as u16)?;
+        w.put_slice(&self.other)?;
+        w.patch_u16(len_offset, (w.len() - len_offset - 2) as u16);
+        Ok(())
+    }
+
+    /// The TSIG variables the MAC covers after the message (RFC 8945 section 4.3.3), or with
+    /// timers_only just the time signed and fudge, as for the later messages of a stream.
+    fn variables(&self, timers_only: bool) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        if !timers_only {
+            buf.append(&mut canonical(&self.key_name)?);
+            buf.put_u16(TSIG_CLASS);
+            buf.put_u32(0);
+            buf.append(&mut canonical(&self.algorithm)?);
+        }
+        buf.put_u16((self.time_signed >> 32) as u16);
+        buf.put_u32(self.time_signed as u32);
+        buf.put_u16(self.fudge);
+        if !timers_only {
+            buf.put_u16(self.error);
+            buf.put_u16(self.other.len() as u16);
+            buf.put_slice(&self.other);
+        }
+        Ok(buf)
+    }
+}
+
+impl std::fmt::Display for Tsig {
+    /// The record as dig shows it in its TSIG pseudo-section.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let error = match self.error() {
+            Some(error) => error.to_string(),
+            None => "NOERROR".to_string(),
+        };
+        write!(
+            f,
+            "{}\t0\tANY\tTSIG\t{} {} {} {} {} {} {error} {}",
+            self.key_name,
+            self.algorithm,
+            self.time_signed,
+            self.fudge,
+            self.mac.len(),
+            encoding::encode_base64(&self.mac),
+            self.original_id,
+            self.other.len()
+        )
+    }
+}
+
+/// A MAC, preceded by its length, as a response's MAC covers its request's.
+fn prior_mac(mac: Option<&[u8]>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mac) = mac {
+        buf.put_u16(mac.len() as u16);
+        buf.put_slice(mac);
+    }
+    buf
+}
+
+/// name uncompressed and in lower case, as the MAC covers it.
+fn canonical(name: &DomainName) -> anyhow::Result<Vec<u8>> {
+    let mut buf = name::serialize(name, None)?;
+    // * No label is longer than 63 bytes, so no length byte is an upper case letter.
+    buf.make_ascii_lowercase();
+    Ok(buf)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{QuestionType, ResponseCode};
+    use crate::rr;
+
+    fn key() -> anyhow::Result<Key> {
+        "hmac-sha256:transfer.example.com:c2VjcmV0IHNoYXJlZCBieSBjbGllbnQgYW5kIHNlcnZlcg==".parse()
+    }
+
+    fn query() -> anyhow::Result<Message> {
+        Ok(Message::query(
+            &"example.com.".parse()?,
+            QuestionType::RrType(rr::Type::SOA),
+        ))
+    }
+
+    #[test]
+    fn parse_key() -> anyhow::Result<()> {
+        let key = key()?;
+        assert_eq!(key.name(), &"transfer.example.com.".parse()?);
+        assert_eq!(key.algorithm(), Algorithm::HmacSha256);
+        assert_eq!(key.secret, b"secret shared by client and server");
+        assert!(!format!("{key:?}").contains("secret"));
+
+        let key = "upd.example.com.:c2VjcmV0".parse::<Key>()?;
+        assert_eq!(key.algorithm(), Algorithm::HmacSha256);
+        assert_eq!(
+            "HMAC-SHA512.:k:c2VjcmV0".parse::<Key>()?.algorithm(),
+            Algorithm::HmacSha512
+        );
+
+        assert!("hmac-md5:k:c2VjcmV0".parse::<Key>().is_err());
+        assert!("k:not base64".parse::<Key>().is_err());
+        assert!("k".parse::<Key>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_verify() -> anyhow::Result<()> {
+        let key = key()?;
+        let mut query = query()?;
+        key.sign(&mut query, None)?;
+
+        // * Verified as it arrives, from the wire.
+        let received = Message::parse(&query.serialize()?)?;
+        key.verify(&received, None)?;
+        assert_eq!(received.tsig().map(Tsig::key_name), Some(key.name()));
+
+        // * The response's MAC covers the query's.
+        let request_mac = received.tsig().unwrap().mac().to_vec();
+        let mut response = received.empty_response(ResponseCode::NoError);
+        key.sign(&mut response, Some(request_mac.as_slice()))?;
+        let response = Message::parse(&response.serialize()?)?;
+        key.verify(&response, Some(request_mac.as_slice()))?;
+        assert_eq!(
+            key.verify(&response, Some(&[0_u8; 32][..])),
+            Err(Error::BadSig)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_rejects() -> anyhow::Result<()> {
+        let key = key()?;
+        let mut query = query()?;
+        assert_eq!(key.verify(&query, None), Err(Error::Unsigned));
+
+        key.sign(&mut query, None)?;
+        let mut wire = query.serialize()?;
+        // * Changing the ID doesn't matter, as the original is signed; changing the question
+        // * does.
+        wire[0] ^= 0xff;
+        key.verify(&Message::parse(&wire)?, None)?;
+        wire[13] ^= 0x20;
+        assert_eq!(
+            key.verify(&Message::parse(&wire)?, None),
+            Err(Error::BadSig)
+        );
+
+        let other = Key::new(
+            "other.example.com.".parse()?,
+            Algorithm::HmacSha256,
+            b"secret".to_vec(),
+        );
+        assert_eq!(other.verify(&query, None), Err(Error::BadKey));
+
+        let mut stale = query.clone();
+        key.sign_at(&mut stale, None, None, now() - 3600)?;
+        assert_eq!(key.verify(&stale, None), Err(Error::BadTime));
+        Ok(())
+    }
+
+    #[test]
+    fn rejection() -> anyhow::Result<()> {
+        let key = key()?;
+        let mut query = query()?;
+        key.sign(&mut query, None)?;
+        let request = query.tsig().unwrap();
+
+        let mut response = query.empty_response(ResponseCode::NotAuth);
+        response.set_tsig(Some(Tsig::rejection(request, Error::BadSig)));
+        let response = Message::parse(&response.serialize()?)?;
+        assert_eq!(response.tsig().and_then(Tsig::error), Some(Error::BadSig));
+        assert_eq!(
+            key.verify(&response, Some(request.mac())),
+            Err(Error::BadSig)
+        );
+
+        let mut response = query.empty_response(ResponseCode::NotAuth);
+        key.sign_rejection(&mut response, request.mac(), Error::BadTime)?;
+        let tsig = response.tsig().unwrap();
+        assert_eq!(tsig.error(), Some(Error::BadTime));
+        assert_eq!(tsig.other.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_stream() -> anyhow::Result<()> {
+        let key = key()?;
+        let request_mac = [7; 32];
+        let message = |id| -> anyhow::Result<Message> {
+            let mut message = query()?.empty_response(ResponseCode::NoError);
+            message.set_id(id);
+            Ok(message)
+        };
+
+        // * The first message covers all the variables, later ones only the timers, and an
+        // * unsigned message is covered by the next signed one.
+        let first = message(1)?;
+        let first = sign_in_stream(&key, first, &request_mac, false, &[])?;
+        let unsigned = message(1)?.serialize()?;
+        let last = message(1)?;
+        let last = sign_in_stream(&key, last, first.tsig().unwrap().mac(), true, &unsigned)?;
+
+        let mut verifier = Verifier::new(key.clone(), &request_mac);
+        for wire in [first.serialize()?, unsigned.clone(), last.serialize()?] {
+            verifier.verify(&wire, &Message::parse(&wire)?)?;
+        }
+        verifier.finish()?;
+
+        let mut verifier = Verifier::new(key.clone(), &request_mac);
+        verifier.verify(&first.serialize()?, &first)?;
+        verifier.verify(&unsigned, &Message::parse(&unsigned)?)?;
+        assert_eq!(verifier.finish(), Err(Error::Unsigned));
+
+        let mut verifier = Verifier::new(key, &request_mac);
+        assert_eq!(
+            verifier.verify(&unsigned, &Message::parse(&unsigned)?),
+            Err(Error::Unsigned)
+        );
+        Ok(())
+    }
+
+    /// message signed as a server signs one of the messages of a stream after prior_mac and
+    /// the unsigned messages since.
+    fn sign_in_stream(
+        key: &Key,
+        mut message: Message,
+        prior: &[u8],
+        timers_only: bool,
+        unsigned: &[u8],
+    ) -> anyhow::Result<Message> {
+        key.sign_at(&mut message, Some(prior), None, now())?;
+        let mut tsig = message.tsig().unwrap().clone();
+        let mut data = prior_mac(Some(prior));
+        data.extend_from_slice(unsigned);
+        data.extend_from_slice(&tsig.signed);
+        data.append(&mut tsig.variables(timers_only)?);
+        tsig.mac = key.algorithm.mac(&key.secret, &data);
+        message.set_tsig(Some(tsig));
+        Ok(message)
+    }
+}
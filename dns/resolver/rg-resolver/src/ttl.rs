@@ -0,0 +1,219 @@
+use crate::metrics;
+use rg_resolver_common::DomainName;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Operator-configured TTL bounds scoped to domain suffixes, applied to records as they're
+/// inserted into the cache.
+///
+/// The rules are held in a tree of labels, root first, so the rule for the longest matching
+/// suffix of a name is found by walking the name's labels from the right.
+#[derive(Debug, Default)]
+pub struct TtlOverrides {
+    root: Node,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    children: HashMap<String, Node>,
+    rule: Option<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    suffix: String,
+    min: Option<u32>,
+    max: Option<u32>,
+    /// The number of records whose TTL this rule changed.
+    applied: AtomicU64,
+}
+
+impl TtlOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses overrides, one per line, of the form `<suffix> min|max <seconds> [min|max <seconds>]`.
+    ///
+    /// For example:
+    ///   internal.example. max 30
+    ///   cdn.example. min 300 max 3600
+    ///
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(config: &str) -> anyhow::Result<Self> {
+        let mut overrides = Self::new();
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(suffix) = fields.next() else {
+                continue;
+            };
+            let (mut min, mut max) = (None, None);
+            while let Some(bound) = fields.next() {
+                let Some(seconds) = fields.next() else {
+                    anyhow::bail!(
+                        "parsing TTL overrides: line {}: missing seconds",
+                        line_num + 1
+                    );
+                };
+                let seconds = seconds.parse::<u32>().map_err(|e| {
+                    anyhow::anyhow!("parsing TTL overrides: line {}: {e}", line_num + 1)
+                })?;
+                match bound {
+                    "min" => min = Some(seconds),
+                    "max" => max = Some(seconds),
+                    _ => anyhow::bail!(
+                        "parsing TTL overrides: line {}: unknown bound {bound}",
+                        line_num + 1
+                    ),
+                }
+            }
+            overrides.add(suffix, min, max)?;
+        }
+        Ok(overrides)
+    }
+
+    /// Bounds the TTL of records at or below suffix to [min, max].
+    /// A later rule for the same suffix replaces the earlier one.
+    pub fn add(&mut self, suffix: &str, min: Option<u32>, max: Option<u32>) -> anyhow::Result<()> {
+        if min.is_none() && max.is_none() {
+            anyhow::bail!("TTL override for {suffix}: no bounds");
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                anyhow::bail!("TTL override for {suffix}: min {min} is greater than max {max}");
+            }
+        }
+        let name = suffix
+            .parse::<DomainName>()
+            .map_err(|e| anyhow::anyhow!("TTL override for {suffix}: {e}"))?;
+        let mut node = &mut self.root;
+        for label in lowercase_labels(&name) {
+            node = node.children.entry(label).or_default();
+        }
+        node.rule = Some(Rule {
+            suffix: suffix.to_string(),
+            min,
+            max,
+            applied: AtomicU64::new(0),
+        });
+        Ok(())
+    }
+
+    /// Returns ttl bounded by the rule for the longest suffix of name that has one.
+    pub fn apply(&self, name: &DomainName, ttl: u32) -> u32 {
+        let mut node = &self.root;
+        let mut rule = node.rule.as_ref();
+        for label in lowercase_labels(name) {
+            let Some(child) = node.children.get(&label) else {
+                break;
+            };
+            node = child;
+            rule = node.rule.as_ref().or(rule);
+        }
+        let Some(rule) = rule else {
+            return ttl;
+        };
+
+        let mut bounded = ttl;
+        if let Some(max) = rule.max {
+            bounded = bounded.min(max);
+        }
+        if let Some(min) = rule.min {
+            bounded = bounded.max(min);
+        }
+        if bounded != ttl {
+            rule.applied.fetch_add(1, Ordering::Relaxed);
+            metrics::global().count_ttl_override(&rule.suffix);
+        }
+        bounded
+    }
+
+    /// The number of times each rule has changed a TTL, by suffix.
+    pub fn applied_counts(&self) -> Vec<(String, u64)> {
+        let mut counts = Vec::new();
+        let mut pending = vec![&self.root];
+        while let Some(node) = pending.pop() {
+            if let Some(rule) = &node.rule {
+                counts.push((rule.suffix.clone(), rule.applied.load(Ordering::Relaxed)));
+            }
+            pending.extend(node.children.values());
+        }
+        counts.sort();
+        counts
+    }
+}
+
+/// The lowercased labels of name, root first.
+fn lowercase_labels(name: &DomainName) -> impl Iterator<Item = String> + '_ {
+    name.labels().rev().map(str::to_ascii_lowercase)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn longest_suffix_wins() -> anyhow::Result<()> {
+        let mut overrides = TtlOverrides::new();
+        overrides.add("example.", None, Some(600))?;
+        overrides.add("internal.example.", None, Some(30))?;
+        overrides.add("cdn.example.", Some(300), None)?;
+
+        assert_eq!(overrides.apply(&name("www.internal.example."), 3600), 30);
+        assert_eq!(overrides.apply(&name("Internal.Example."), 3600), 30);
+        assert_eq!(overrides.apply(&name("img.cdn.example."), 60), 300);
+        // * The cdn rule has no max, so the example. rule's max doesn't apply either.
+        assert_eq!(overrides.apply(&name("img.cdn.example."), 86400), 86400);
+        assert_eq!(overrides.apply(&name("www.example."), 3600), 600);
+        assert_eq!(overrides.apply(&name("www.example."), 60), 60);
+        assert_eq!(overrides.apply(&name("google.com."), 3600), 3600);
+        Ok(())
+    }
+
+    #[test]
+    fn counts_applied_overrides() -> anyhow::Result<()> {
+        let mut overrides = TtlOverrides::new();
+        overrides.add("internal.example.", None, Some(30))?;
+        overrides.add("cdn.example.", Some(300), None)?;
+        overrides.apply(&name("a.internal.example."), 3600);
+        overrides.apply(&name("b.internal.example."), 3600);
+        overrides.apply(&name("c.internal.example."), 10);
+        overrides.apply(&name("cdn.example."), 60);
+        assert_eq!(
+            overrides.applied_counts(),
+            vec![
+                (String::from("cdn.example."), 1),
+                (String::from("internal.example."), 2)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_config() -> anyhow::Result<()> {
+        let overrides = TtlOverrides::parse(
+            "# Keep internal names fresh.\n\
+             internal.example. max 30\n\
+             \n\
+             cdn.example. min 300 max 3600\n",
+        )?;
+        assert_eq!(overrides.apply(&name("host.internal.example."), 300), 30);
+        assert_eq!(overrides.apply(&name("cdn.example."), 10), 300);
+        assert_eq!(overrides.apply(&name("cdn.example."), 7200), 3600);
+
+        assert!(TtlOverrides::parse("example. max").is_err());
+        assert!(TtlOverrides::parse("example. max thirty").is_err());
+        assert!(TtlOverrides::parse("example. ceiling 30").is_err());
+        assert!(TtlOverrides::parse("example.").is_err());
+        assert!(TtlOverrides::parse("example. min 60 max 30").is_err());
+        Ok(())
+    }
+}
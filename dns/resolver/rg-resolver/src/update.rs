@@ -0,0 +1,243 @@
+//! Dynamic updates (RFC 2136): adding records to and deleting records from a zone on its
+//! primary nameserver, optionally only if the zone meets some prerequisites. Primaries
+//! usually only accept updates signed with a TSIG key.
+
+use crate::exchange::{self, Exchange};
+use crate::message::{Message, Prerequisite, ResponseCode, Update, UpdateBuilder};
+use crate::{rr, tsig};
+use rg_resolver_common::DomainName;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for the primary to answer an update.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends updates of one zone to its primary nameserver, over TCP unless given another
+/// exchange.
+pub struct Updater {
+    server: SocketAddr,
+    zone: DomainName,
+    exchange: Arc<dyn Exchange>,
+    /// The key updates are signed with, and the primary's responses have to be.
+    key: Option<tsig::Key>,
+}
+
+impl Updater {
+    pub fn new(server: SocketAddr, zone: DomainName) -> Self {
+        Updater {
+            server,
+            zone,
+            exchange: Arc::new(exchange::Tcp {
+                timeout: DEFAULT_TIMEOUT,
+            }),
+            key: None,
+        }
+    }
+
+    /// Sends updates over TCP, waiting timeout for the primary to answer.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_exchange(Arc::new(exchange::Tcp { timeout }))
+    }
+
+    /// Sends updates with exchange rather than over TCP, e.g. to a fake primary in tests.
+    pub fn with_exchange(mut self, exchange: Arc<dyn Exchange>) -> Self {
+        self.exchange = exchange;
+        self
+    }
+
+    /// Signs updates with key, and requires the primary's responses to be signed with it.
+    pub fn with_key(mut self, key: tsig::Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn zone(&self) -> &DomainName {
+        &self.zone
+    }
+
+    /// Builds the UPDATE making updates if the zone meets prerequisites.
+    pub fn message(&self, prerequisites: Vec<Prerequisite>, updates: Vec<Update>) -> Message {
+        let builder = prerequisites
+            .into_iter()
+            .fold(UpdateBuilder::new(&self.zone), |builder, prerequisite| {
+                builder.prerequisite(prerequisite)
+            });
+        updates
+            .into_iter()
+            .fold(builder, |builder, update| builder.update(update))
+            .build()
+    }
+
+    /// Makes updates if the zone meets prerequisites, failing with the primary's response
+    /// code, e.g. NXRRSET for an RRset required to exist, unless it applied them. The updates
+    /// are applied all together or not at all.
+    pub async fn send(
+        &self,
+        prerequisites: Vec<Prerequisite>,
+        updates: Vec<Update>,
+    ) -> anyhow::Result<()> {
+        let mut update = self.message(prerequisites, updates);
+        if let Some(key) = &self.key {
+            key.sign(&mut update, None)?;
+        }
+        let response = self.exchange.exchange(self.server, &update).await?;
+        if let Some(key) = &self.key {
+            let request_mac = update.tsig().map(tsig::Tsig::mac);
+            key.verify(&response, request_mac)
+                .map_err(|e| anyhow::anyhow!("updating {} on {}: {e}", self.zone, self.server))?;
+        }
+        match response.response_code() {
+            ResponseCode::NoError => Ok(()),
+            code => anyhow::bail!("updating {} on {}: {code}", self.zone, self.server),
+        }
+    }
+
+    /// Adds record to the zone.
+    pub async fn add(&self, record: rr::ResourceRecord) -> anyhow::Result<()> {
+        self.send(Vec::new(), vec![Update::Add(record)]).await
+    }
+
+    /// Deletes record from the zone. Deleting a record the zone doesn't have isn't an error.
+    pub async fn delete(&self, record: rr::ResourceRecord) -> anyhow::Result<()> {
+        self.send(Vec::new(), vec![Update::Delete(record)]).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::BoxFuture;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    fn a(name: &str, last: u8) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            name.parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, last)),
+        )
+    }
+
+    /// A primary that keeps the UPDATEs it receives, as they arrive on the wire, and answers
+    /// them with code, signing the responses with key if it has one.
+    struct FakePrimary {
+        code: ResponseCode,
+        key: Option<tsig::Key>,
+        received: Mutex<Vec<Message>>,
+    }
+
+    impl FakePrimary {
+        fn new(code: ResponseCode) -> Arc<Self> {
+            Self::with_key(code, None)
+        }
+
+        fn with_key(code: ResponseCode, key: Option<tsig::Key>) -> Arc<Self> {
+            Arc::new(FakePrimary {
+                code,
+                key,
+                received: Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    impl Exchange for FakePrimary {
+        fn exchange<'a>(
+            &'a self,
+            _server: SocketAddr,
+            query: &'a Message,
+        ) -> BoxFuture<'a, anyhow::Result<Message>> {
+            Box::pin(async move {
+                let received = Message::parse(&query.serialize()?)?;
+                let mut response = received.empty_response(self.code);
+                if let Some(key) = &self.key {
+                    let request_mac = received.tsig().map(tsig::Tsig::mac);
+                    key.sign(&mut response, request_mac)?;
+                }
+                self.received.lock().unwrap().push(received);
+                Ok(response)
+            })
+        }
+    }
+
+    fn updater(primary: &Arc<FakePrimary>) -> anyhow::Result<Updater> {
+        let updater = Updater::new("192.0.2.53:53".parse()?, "example.com.".parse()?)
+            .with_exchange(primary.clone());
+        Ok(updater)
+    }
+
+    #[tokio::test]
+    async fn add_and_delete() -> anyhow::Result<()> {
+        let primary = FakePrimary::new(ResponseCode::NoError);
+        let updater = updater(&primary)?;
+        updater.add(a("www.example.com.", 1)?).await?;
+        updater.delete(a("www.example.com.", 2)?).await?;
+
+        let received = primary.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].zone(), Some(&"example.com.".parse()?));
+        assert_eq!(
+            received[0].updates(),
+            [Update::Add(a("www.example.com.", 1)?)]
+        );
+        // * The TTL of a record to delete is sent as 0.
+        assert_eq!(
+            received[1].updates(),
+            [Update::Delete(a("www.example.com.", 2)?.with_ttl(0))]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_with_prerequisites() -> anyhow::Result<()> {
+        let primary = FakePrimary::new(ResponseCode::NoError);
+        let updater = updater(&primary)?;
+        let name: DomainName = "www.example.com.".parse()?;
+        let prerequisites = vec![
+            Prerequisite::NameInUse(name.clone()),
+            Prerequisite::RrsetAbsent(name.clone(), rr::Type::AAAA),
+        ];
+        let updates = vec![
+            Update::DeleteRrset(name.clone(), rr::Type::A),
+            Update::Add(a("www.example.com.", 3)?),
+        ];
+        updater.send(prerequisites.clone(), updates.clone()).await?;
+
+        let received = primary.received.lock().unwrap();
+        assert_eq!(received[0].prerequisites(), prerequisites);
+        assert_eq!(received[0].updates(), updates);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_fails_unless_applied() -> anyhow::Result<()> {
+        let primary = FakePrimary::new(ResponseCode::NxRrset);
+        let updater = updater(&primary)?;
+        let name: DomainName = "www.example.com.".parse()?;
+        let result = updater
+            .send(
+                vec![Prerequisite::RrsetExists(name.clone(), rr::Type::A)],
+                vec![Update::DeleteName(name)],
+            )
+            .await;
+        assert!(result.unwrap_err().to_string().contains("NXRRSET"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signed() -> anyhow::Result<()> {
+        let key: tsig::Key = "update.example.com:c2VjcmV0".parse()?;
+        let primary = FakePrimary::with_key(ResponseCode::NoError, Some(key.clone()));
+        let signing = updater(&primary)?.with_key(key.clone());
+        signing.add(a("www.example.com.", 1)?).await?;
+        key.verify(&primary.received.lock().unwrap()[0], None)?;
+
+        // * A response without the primary's signature isn't trusted.
+        let unsigned = FakePrimary::new(ResponseCode::NoError);
+        let signing = updater(&unsigned)?.with_key(key);
+        assert!(signing.add(a("www.example.com.", 1)?).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::message::QuestionType;
+use crate::resolve::{Answer, BoxFuture, Resolve};
+use rg_resolver_common::DomainName;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+
+tokio::task_local! {
+    static CLIENT: IpAddr;
+}
+
+/// Runs request with client as the address of whoever sent it, so [Views] answers the queries
+/// it makes from the view serving that client.
+pub async fn with_client<F: Future>(client: IpAddr, request: F) -> F::Output {
+    CLIENT.scope(client, request).await
+}
+
+/// The address of whoever sent the request being run. This host's own lookups are from
+/// 127.0.0.1.
+pub fn client() -> IpAddr {
+    CLIENT
+        .try_with(|client| *client)
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+/// A block of client addresses, e.g. 10.0.0.0/8 or 2001:db8::/32.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClientNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl ClientNet {
+    /// Parses `<address>/<prefix length>`, or a bare address for just that client.
+    pub fn parse(net: &str) -> anyhow::Result<Self> {
+        let (addr, prefix_len) = match net.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (net, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|e| anyhow::anyhow!("parsing client network {net}: {e}"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "parsing client network {net}: prefix length must be 0 to {max_len}"
+                    )
+                })?,
+            None => max_len,
+        };
+        Ok(ClientNet { addr, prefix_len })
+    }
+
+    /// Returns true if client is in this network. IPv4 clients reaching an IPv6 socket
+    /// (::ffff:a.b.c.d) match IPv4 networks.
+    pub fn contains(&self, client: IpAddr) -> bool {
+        let (net, client, bits) = match (self.addr, client.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(client)) => {
+                (u32::from(net) as u128, u32::from(client) as u128, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(client)) => (u128::from(net), u128::from(client), 128),
+            _ => return false,
+        };
+        let host_bits = bits - self.prefix_len as u32;
+        // * Shifting a u128 by 128 overflows, so a /0 is handled separately.
+        host_bits == bits || net >> host_bits == client >> host_bits
+    }
+}
+
+/// A policy for a group of clients, like a BIND view: its own zones, forwarding rules, and
+/// blocklist, expressed as the resolver answering for it, and its own cache namespace.
+pub struct View {
+    name: String,
+    clients: Vec<ClientNet>,
+    blocked: Vec<DomainName>,
+    resolver: Box<dyn Resolve>,
+}
+
+impl View {
+    /// A view answering from resolver. It matches no clients until some are added with
+    /// [View::with_clients].
+    pub fn new(name: &str, resolver: Box<dyn Resolve>) -> Self {
+        View {
+            name: name.to_string(),
+            clients: Vec::new(),
+            blocked: Vec::new(),
+            resolver,
+        }
+    }
+
+    /// Serves the clients in nets. Use 0.0.0.0/0 and ::/0 for a view serving everyone.
+    pub fn with_clients(mut self, nets: Vec<ClientNet>) -> Self {
+        self.clients = nets;
+        self
+    }
+
+    /// Answers names at or below each of zones as having no records instead of resolving them.
+    pub fn with_blocked(mut self, zones: Vec<DomainName>) -> Self {
+        self.blocked = zones;
+        self
+    }
+
+    /// Returns true if this view serves client.
+    pub fn matches(&self, client: IpAddr) -> bool {
+        self.clients.iter().any(|net| net.contains(client))
+    }
+
+    /// The namespace of this view's cache entries, so views never see each other's answers.
+    pub fn cache_namespace(&self) -> &str {
+        &self.name
+    }
+
+    fn is_blocked(&self, name: &DomainName) -> bool {
+        self.blocked.iter().any(|zone| name.is_subdomain_of(zone))
+    }
+}
+
+impl Resolve for View {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            if self.is_blocked(&name.parse()?) {
+                return Ok(Some(Answer::no_data()));
+            }
+            self.resolver.lookup(name, qtype).await
+        })
+    }
+}
+
+/// The views of one resolver instance. A client is served by the first view that matches it,
+/// chosen for each query by the address it was run with; see [with_client]. Queries run
+/// without one, e.g. the binary's own lookup, are from loopback.
+pub struct Views {
+    views: Vec<View>,
+}
+
+impl Views {
+    pub fn new(views: Vec<View>) -> anyhow::Result<Self> {
+        for (i, view) in views.iter().enumerate() {
+            if views[..i].iter().any(|other| other.name == view.name) {
+                anyhow::bail!("view {} is defined more than once", view.name);
+            }
+        }
+        Ok(Views { views })
+    }
+
+    /// Parses views, one per line, of the form `<name> clients <net>... [block <zone>...]`,
+    /// each answering from the resolver made by resolver from its name.
+    ///
+    /// For example:
+    ///   lab clients 10.1.0.0/16 block ads.example.
+    ///   production clients 10.0.0.0/8 ::/0
+    ///
+    /// Blank lines and lines starting with '#' are ignored.
+    pub fn parse(
+        config: &str,
+        mut resolver: impl FnMut(&str) -> Box<dyn Resolve>,
+    ) -> anyhow::Result<Self> {
+        let mut views = Vec::new();
+        for (line_num, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e| anyhow::anyhow!("parsing views: line {}: {e}", line_num + 1);
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some("clients")) = (fields.next(), fields.next()) else {
+                return Err(error(anyhow::anyhow!("expected <name> clients <net>...")));
+            };
+            let (mut clients, mut blocked) = (Vec::new(), Vec::new());
+            let mut blocking = false;
+            for field in fields {
+                match field {
+                    "block" if !blocking => blocking = true,
+                    zone if blocking => {
+                        blocked.push(zone.parse().map_err(|e| {
+                            error(anyhow::anyhow!("invalid blocked zone {zone}: {e}"))
+                        })?)
+                    }
+                    net => clients.push(ClientNet::parse(net).map_err(error)?),
+                }
+            }
+            if clients.is_empty() {
+                return Err(error(anyhow::anyhow!("view {name} has no clients")));
+            }
+            let view = View::new(name, resolver(name))
+                .with_clients(clients)
+                .with_blocked(blocked);
+            views.push(view);
+        }
+        Views::new(views)
+    }
+
+    /// The view serving client, or None if no view does and the client should be refused.
+    pub fn select(&self, client: IpAddr) -> Option<&View> {
+        self.views.iter().find(|view| view.matches(client))
+    }
+}
+
+impl Resolve for Views {
+    fn name(&self) -> &str {
+        "views"
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        qtype: QuestionType,
+    ) -> BoxFuture<'a, anyhow::Result<Option<Answer>>> {
+        Box::pin(async move {
+            let client = client();
+            let Some(view) = self.select(client) else {
+                anyhow::bail!("refused: no view serves client {client}");
+            };
+            view.lookup(name, qtype).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resolve::Static;
+    use crate::rr;
+
+    fn net(net: &str) -> ClientNet {
+        ClientNet::parse(net).unwrap()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    /// Answers db.internal. with address.
+    fn resolver(name: &str, address: Ipv4Addr) -> Box<dyn Resolve> {
+        let record = rr::ResourceRecord::new(
+            "db.internal.".parse().unwrap(),
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(address),
+        )
+        .unwrap();
+        Box::new(Static::new(name, vec![record]))
+    }
+
+    fn view(name: &str, address: Ipv4Addr) -> View {
+        View::new(name, resolver(name, address))
+    }
+
+    #[test]
+    fn client_nets() {
+        assert!(net("10.0.0.0/8").contains(ip("10.200.1.1")));
+        assert!(!net("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(net("10.0.0.0/8").contains(ip("::ffff:10.0.0.1")));
+        assert!(!net("10.0.0.0/8").contains(ip("2001:db8::1")));
+        assert!(net("2001:db8::/32").contains(ip("2001:db8:1::1")));
+        assert!(net("0.0.0.0/0").contains(ip("192.0.2.1")));
+        assert!(net("::/0").contains(ip("2001:db8::1")));
+        assert!(net("192.0.2.7").contains(ip("192.0.2.7")));
+        assert!(!net("192.0.2.7").contains(ip("192.0.2.8")));
+
+        assert!(ClientNet::parse("10.0.0.0/33").is_err());
+        assert!(ClientNet::parse("lab/8").is_err());
+    }
+
+    #[tokio::test]
+    async fn selects_view_by_client() -> anyhow::Result<()> {
+        let views = Views::new(vec![
+            view("lab", Ipv4Addr::new(10, 0, 0, 5)).with_clients(vec![net("10.1.0.0/16")]),
+            view("production", Ipv4Addr::new(192, 0, 2, 5))
+                .with_clients(vec![net("10.0.0.0/8"), net("::/0")])
+                .with_blocked(vec!["ads.example.".parse()?]),
+        ])?;
+
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let lab = views.select(ip("10.1.2.3")).unwrap();
+        assert_eq!(lab.cache_namespace(), "lab");
+        let answer = lab.lookup("db.internal.", qtype).await?.unwrap();
+        assert_eq!(
+            answer.records()[0].data(),
+            &rr::Data::A(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        assert_eq!(lab.lookup("www.ads.example.", qtype).await?, None);
+
+        let production = views.select(ip("10.2.0.1")).unwrap();
+        assert_eq!(production.cache_namespace(), "production");
+        let answer = production.lookup("db.internal.", qtype).await?.unwrap();
+        assert_eq!(
+            answer.records()[0].data(),
+            &rr::Data::A(Ipv4Addr::new(192, 0, 2, 5))
+        );
+        assert_eq!(
+            production.lookup("www.ads.example.", qtype).await?,
+            Some(Answer::no_data())
+        );
+        assert_eq!(
+            views.select(ip("2001:db8::1")).unwrap().name(),
+            "production"
+        );
+
+        assert!(views.select(ip("192.0.2.1")).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn answers_from_clients_view() -> anyhow::Result<()> {
+        let views = Views::parse(
+            "# Lab machines get their own answers.\n\
+             lab clients 10.1.0.0/16 127.0.0.1 block ads.example.\n\
+             \n\
+             production clients 10.0.0.0/8\n",
+            |name| match name {
+                "lab" => resolver(name, Ipv4Addr::new(10, 0, 0, 5)),
+                _ => resolver(name, Ipv4Addr::new(192, 0, 2, 5)),
+            },
+        )?;
+        let qtype = QuestionType::RrType(rr::Type::A);
+        let answer = |client: &str| {
+            let views = &views;
+            let client = ip(client);
+            async move { with_client(client, views.lookup("db.internal.", qtype)).await }
+        };
+        let address = |answer: Answer| answer.records()[0].data().clone();
+        let lab = rr::Data::A(Ipv4Addr::new(10, 0, 0, 5));
+        let production = rr::Data::A(Ipv4Addr::new(192, 0, 2, 5));
+        assert_eq!(address(answer("10.2.0.1").await?.unwrap()), production);
+        assert_eq!(address(answer("10.1.0.1").await?.unwrap()), lab);
+        assert!(answer("192.0.2.1").await.is_err());
+        // * Without a client, the query is from loopback.
+        let rrset = views.lookup("db.internal.", qtype).await?.unwrap();
+        assert_eq!(address(rrset), lab);
+        assert_eq!(
+            views.lookup("www.ads.example.", qtype).await?,
+            Some(Answer::no_data())
+        );
+
+        assert!(Views::parse("lab 10.0.0.0/8", |_| unreachable!()).is_err());
+        assert!(Views::parse("lab clients", |_| unreachable!()).is_err());
+        assert!(Views::parse("lab clients lab/8", |_| unreachable!()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_duplicate_views() {
+        let duplicate = Views::new(vec![
+            view("lab", Ipv4Addr::LOCALHOST),
+            view("lab", Ipv4Addr::LOCALHOST),
+        ]);
+        assert!(duplicate.is_err());
+    }
+}
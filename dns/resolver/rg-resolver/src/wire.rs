@@ -0,0 +1,99 @@
+/// Writes wire format into a caller-provided buffer, failing rather than growing or panicking
+/// when it runs out of room.
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+/// Returned when a Writer runs out of room.
+#[derive(Debug)]
+pub(crate) struct Full;
+
+impl std::fmt::Display for Full {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("buffer too small")
+    }
+}
+
+impl std::error::Error for Full {}
+
+/// Returns true if e was caused by a Writer running out of room, rather than by what was
+/// being written.
+pub(crate) fn is_full(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<Full>())
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Writer { buf, len: 0 }
+    }
+
+    /// The number of bytes written.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn put_slice(&mut self, bytes: &[u8]) -> Result<(), Full> {
+        let end = self.len + bytes.len();
+        let Some(dest) = self.buf.get_mut(self.len..end) else {
+            return Err(Full);
+        };
+        dest.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    pub(crate) fn put_u8(&mut self, n: u8) -> Result<(), Full> {
+        self.put_slice(&[n])
+    }
+
+    pub(crate) fn put_u16(&mut self, n: u16) -> Result<(), Full> {
+        self.put_slice(&n.to_be_bytes())
+    }
+
+    pub(crate) fn put_i16(&mut self, n: i16) -> Result<(), Full> {
+        self.put_slice(&n.to_be_bytes())
+    }
+
+    pub(crate) fn put_u32(&mut self, n: u32) -> Result<(), Full> {
+        self.put_slice(&n.to_be_bytes())
+    }
+
+    pub(crate) fn put_i32(&mut self, n: i32) -> Result<(), Full> {
+        self.put_slice(&n.to_be_bytes())
+    }
+
+    pub(crate) fn put_zeros(&mut self, count: usize) -> Result<(), Full> {
+        let end = self.len + count;
+        let Some(dest) = self.buf.get_mut(self.len..end) else {
+            return Err(Full);
+        };
+        dest.fill(0);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Overwrites the u16 written at offset, e.g. a length that wasn't known until later.
+    pub(crate) fn patch_u16(&mut self, offset: usize, n: u16) {
+        self.buf[offset..offset + 2].copy_from_slice(&n.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_until_full() {
+        let mut buf = [0xff_u8; 7];
+        let mut writer = Writer::new(&mut buf);
+        writer.put_u16(0).unwrap();
+        writer.put_u32(0x01020304).unwrap();
+        writer.patch_u16(0, 0xabcd);
+        assert!(writer.put_u16(5).is_err());
+        writer.put_u8(9).unwrap();
+        assert_eq!(writer.len(), 7);
+        assert!(writer.put_zeros(1).is_err());
+        assert_eq!(buf, [0xab, 0xcd, 1, 2, 3, 4, 9]);
+    }
+}
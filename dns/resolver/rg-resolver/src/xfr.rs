@@ -0,0 +1,535 @@
+//! Zone transfers from a primary nameserver over TCP: the whole zone with AXFR (RFC 5936), or
+//! only what changed since a version the client has with IXFR (RFC 1995). The records are
+//! returned as they arrive, so a large zone never has to be held in memory at once. Given a
+//! TSIG key, the query is signed and the transfer has to be signed with the same key.
+
+use crate::message::{Message, QueryBuilder, QuestionClass, QuestionType, ResponseCode};
+use crate::rr;
+use crate::soa::Serial;
+use crate::tsig::{self, Verifier};
+use rg_resolver_common::DomainName;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+/// A record as a transfer delivers it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transferred {
+    /// A record of the zone, from a full transfer. The zone's SOA record comes first.
+    Record(rr::ResourceRecord),
+    /// A record removed from the zone, from an incremental transfer. The changes of each
+    /// version start by removing the previous version's SOA record.
+    Removed(rr::ResourceRecord),
+    /// A record added to the zone, from an incremental transfer. The additions of each version
+    /// start with its SOA record.
+    Added(rr::ResourceRecord),
+}
+
+impl std::fmt::Display for Transferred {
+    /// The record in presentation format, marked - if it was removed or + if it was added.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transferred::Record(record) => write!(f, "{record}"),
+            Transferred::Removed(record) => write!(f, "- {record}"),
+            Transferred::Added(record) => write!(f, "+ {record}"),
+        }
+    }
+}
+
+/// Where a transfer is in the stream of records, which is framed by the new version's SOA
+/// record at the start and again at the end.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    /// Waiting for the opening SOA record.
+    Opening,
+    /// An IXFR's opening SOA record was newer than the client's version. Whether the next
+    /// record is an SOA record tells whether the server sent the changes or the whole zone.
+    Opened,
+    Full,
+    Removing,
+    Adding,
+    Done,
+}
+
+/// A zone transfer in progress, on a connection of its own.
+pub struct ZoneTransfer {
+    stream: TcpStream,
+    query: Message,
+    /// How long to wait for each message.
+    timeout: Duration,
+    /// For an IXFR, the serial of the version the client has.
+    known: Option<Serial>,
+    /// The serial of the version being transferred, from the opening SOA record.
+    serial: Option<Serial>,
+    /// Records received but not yet returned.
+    pending: VecDeque<rr::ResourceRecord>,
+    /// The opening SOA record, held back until it's known what kind of transfer it opens.
+    opening: Option<rr::ResourceRecord>,
+    state: State,
+    /// Checks the messages' signatures if the query was signed.
+    verifier: Option<Verifier>,
+}
+
+impl ZoneTransfer {
+    /// Starts transferring the whole of zone from server, signed with key if given one.
+    pub async fn axfr(
+        server: SocketAddr,
+        zone: &DomainName,
+        timeout: Duration,
+        key: Option<&tsig::Key>,
+    ) -> anyhow::Result<Self> {
+        let query = QueryBuilder::new()
+            .question(
+                zone,
+                QuestionType::Afxr,
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .build();
+        Self::start(server, query, timeout, None, key).await
+    }
+
+    /// Starts transferring the changes to zone since the version with serial, falling back
+    /// to transferring the whole zone if server doesn't implement IXFR. If the client's
+    /// version is current, the transfer has no records.
+    pub async fn ixfr(
+        server: SocketAddr,
+        zone: &DomainName,
+        serial: Serial,
+        timeout: Duration,
+        key: Option<&tsig::Key>,
+    ) -> anyhow::Result<Self> {
+        // * Only the serial of the client's SOA record is read (RFC 1995 section 3).
+        let soa = rr::ResourceRecord::new(
+            zone.clone(),
+            rr::Type::SOA,
+            rr::Class::IN,
+            0,
+            rr::Data::SOA {
+                mname: DomainName::root(),
+                rname: DomainName::root(),
+                serial: serial.0,
+                refresh: 0,
+                retry: 0,
+                expire: 0,
+                minimum: 0,
+            },
+        )?;
+        let query = QueryBuilder::new()
+            .question(
+                zone,
+                QuestionType::Ixfr,
+                QuestionClass::RrClass(rr::Class::IN),
+            )
+            .authority(soa)
+            .build();
+        let mut transfer = Self::start(server, query, timeout, Some(serial), key).await?;
+        match transfer.receive().await? {
+            ResponseCode::NoError => Ok(transfer),
+            ResponseCode::NotImplemented | ResponseCode::FormatError => {
+                Self::axfr(server, zone, timeout, key).await
+            }
+            code => anyhow::bail!("IXFR of {zone} from {server}: {code}"),
+        }
+    }
+
+    async fn start(
+        server: SocketAddr,
+        mut query: Message,
+        timeout: Duration,
+        known: Option<Serial>,
+        key: Option<&tsig::Key>,
+    ) -> anyhow::Result<Self> {
+        let mut verifier = None;
+        if let Some(key) = key {
+            key.sign(&mut query, None)?;
+            let request_mac = query.tsig().expect("just signed").mac();
+            verifier = Some(Verifier::new(key.clone(), request_mac));
+        }
+        let mut stream = time::timeout(timeout, TcpStream::connect(server))
+            .await
+            .map_err(|_| anyhow::anyhow!("connecting to {server}: timed out"))?
+            .map_err(|e| anyhow::anyhow!("connecting to {server}: {e}"))?;
+        stream.write_all(&query.serialize_framed()?).await?;
+        Ok(ZoneTransfer {
+            stream,
+            query,
+            timeout,
+            known,
+            serial: None,
+            pending: VecDeque::new(),
+            opening: None,
+            state: State::Opening,
+            verifier,
+        })
+    }
+
+    /// The next record, or None once the closing SOA record has arrived.
+    pub async fn next(&mut self) -> anyhow::Result<Option<Transferred>> {
+        while self.state != State::Done {
+            let Some(record) = self.pending.pop_front() else {
+                match self.receive().await? {
+                    ResponseCode::NoError => continue,
+                    code => anyhow::bail!("transfer failed: {code}"),
+                }
+            };
+            if let Some(transferred) = self.step(record)? {
+                return Ok(Some(transferred));
+            }
+        }
+        if let Some(verifier) = &self.verifier {
+            verifier
+                .finish()
+                .map_err(|e| anyhow::anyhow!("transfer signature: {e}"))?;
+        }
+        Ok(None)
+    }
+
+    /// Every remaining record, in the order they arrive.
+    pub async fn collect(mut self) -> anyhow::Result<Vec<Transferred>> {
+        let mut records = Vec::new();
+        while let Some(transferred) = self.next().await? {
+            records.push(transferred);
+        }
+        Ok(records)
+    }
+
+    /// Reads the next message, queueing its answers if it has no error.
+    async fn receive(&mut self) -> anyhow::Result<ResponseCode> {
+        let (buf, response) = time::timeout(self.timeout, read_message(&mut self.stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("transfer timed out"))??;
+        // * Only the first message has to repeat the question (RFC 5936 section 2.2).
+        let matches = response.is_response_to(&self.query)
+            || (response.is_response()
+                && response.id() == self.query.id()
+                && response.questions().is_empty());
+        if !matches {
+            anyhow::bail!("transfer response doesn't match the query");
+        }
+        if let Some(verifier) = &mut self.verifier {
+            verifier
+                .verify(&buf, &response)
+                .map_err(|e| anyhow::anyhow!("transfer signature: {e}"))?;
+        }
+        if response.response_code() == ResponseCode::NoError {
+            self.pending.extend(response.answers().iter().cloned());
+        }
+        Ok(response.response_code())
+    }
+
+    /// Moves the transfer on by record, returning what it delivers, if anything.
+    fn step(&mut self, record: rr::ResourceRecord) -> anyhow::Result<Option<Transferred>> {
+        let serial = soa_serial(&record);
+        let transferred = match self.state {
+            State::Opening => {
+                let Some(serial) = serial else {
+                    anyhow::bail!("transfer doesn't start with an SOA record");
+                };
+                self.serial = Some(serial);
+                match self.known {
+                    None => {
+                        self.state = State::Full;
+                        Some(Transferred::Record(record))
+                    }
+                    Some(known) if serial > known => {
+                        self.opening = Some(record);
+                        self.state = State::Opened;
+                        None
+                    }
+                    // * A lone SOA record no newer than the client's version means it's current
+                    // * (RFC 1995 section 2).
+                    Some(_) => {
+                        self.state = State::Done;
+                        None
+                    }
+                }
+            }
+            State::Opened => {
+                let opening = self.opening.take().expect("held while opened");
+                if serial.is_some() && serial != self.serial {
+                    self.state = State::Removing;
+                    Some(Transferred::Removed(record))
+                } else {
+                    // * The whole zone, which may be only its SOA record.
+                    self.pending.push_front(record);
+                    self.state = State::Full;
+                    Some(Transferred::Record(opening))
+                }
+            }
+            State::Full if serial.is_some() => {
+                self.state = State::Done;
+                None
+            }
+            State::Full => Some(Transferred::Record(record)),
+            State::Removing if serial.is_some() => {
+                self.state = State::Adding;
+                Some(Transferred::Added(record))
+            }
+            State::Removing => Some(Transferred::Removed(record)),
+            // * After the last version's additions, the new SOA record closes the transfer.
+            State::Adding if serial.is_some() && serial == self.serial => {
+                self.state = State::Done;
+                None
+            }
+            State::Adding if serial.is_some() => {
+                self.state = State::Removing;
+                Some(Transferred::Removed(record))
+            }
+            State::Adding => Some(Transferred::Added(record)),
+            State::Done => None,
+        };
+        Ok(transferred)
+    }
+}
+
+fn soa_serial(record: &rr::ResourceRecord) -> Option<Serial> {
+    match record.data() {
+        rr::Data::SOA { serial, .. } => Some(Serial(*serial)),
+        _ => None,
+    }
+}
+
+/// Reads the next message, returning it along with the bytes it was parsed from.
+async fn read_message(stream: &mut TcpStream) -> anyhow::Result<(Vec<u8>, Message)> {
+    let len = stream
+        .read_u16()
+        .await
+        .map_err(|e| anyhow::anyhow!("transfer ended before its closing SOA record: {e}"))?;
+    let mut buf = vec![0_u8; len as usize];
+    stream.read_exact(&mut buf).await?;
+    let message = Message::parse(&buf)?;
+    Ok((buf, message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn soa(serial: u32) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            "example.com.".parse()?,
+            rr::Type::SOA,
+            rr::Class::IN,
+            3600,
+            rr::Data::SOA {
+                mname: "ns.example.com.".parse()?,
+                rname: "admin.example.com.".parse()?,
+                serial,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        )
+    }
+
+    fn a(name: &str, last: u8) -> anyhow::Result<rr::ResourceRecord> {
+        rr::ResourceRecord::new(
+            name.parse()?,
+            rr::Type::A,
+            rr::Class::IN,
+            300,
+            rr::Data::A(Ipv4Addr::new(192, 0, 2, last)),
+        )
+    }
+
+    /// A primary answering each connection's query with the messages respond gives it.
+    async fn serve(
+        respond: impl Fn(&Message) -> Vec<Message> + Send + 'static,
+    ) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (_, query) = read_message(&mut stream).await?;
+                for response in respond(&query) {
+                    stream.write_all(&response.serialize_framed()?).await?;
+                }
+            }
+            anyhow::Ok(())
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn axfr() -> anyhow::Result<()> {
+        let server = serve(|query| {
+            vec![
+                query.answer_response(vec![soa(1).unwrap(), a("www.example.com.", 1).unwrap()]),
+                query.answer_response(vec![a("ftp.example.com.", 2).unwrap()]),
+                query.answer_response(vec![soa(1).unwrap()]),
+            ]
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let records = ZoneTransfer::axfr(server, &zone, TIMEOUT, None)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(
+            records,
+            [
+                Transferred::Record(soa(1)?),
+                Transferred::Record(a("www.example.com.", 1)?),
+                Transferred::Record(a("ftp.example.com.", 2)?),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn axfr_cut_short() -> anyhow::Result<()> {
+        let server = serve(|query| {
+            vec![query.answer_response(vec![soa(1).unwrap(), a("www.example.com.", 1).unwrap()])]
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let mut transfer = ZoneTransfer::axfr(server, &zone, TIMEOUT, None).await?;
+        assert_eq!(transfer.next().await?, Some(Transferred::Record(soa(1)?)));
+        assert!(transfer.next().await?.is_some());
+        assert!(transfer.next().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn axfr_signed() -> anyhow::Result<()> {
+        let key: tsig::Key = "xfr.example.com:c2VjcmV0".parse()?;
+        let signing_key = key.clone();
+        let server = serve(move |query| {
+            let request_mac = query.tsig().map(|tsig| tsig.mac().to_vec());
+            let mut response = query.answer_response(vec![
+                soa(1).unwrap(),
+                a("www.example.com.", 1).unwrap(),
+                soa(1).unwrap(),
+            ]);
+            // * Only a query signed with the key gets a signed response.
+            if signing_key.verify(query, None).is_ok() {
+                signing_key
+                    .sign(&mut response, request_mac.as_deref())
+                    .unwrap();
+            }
+            vec![response]
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let records = ZoneTransfer::axfr(server, &zone, TIMEOUT, Some(&key))
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(records.len(), 2);
+
+        let other: tsig::Key = "xfr.example.com:b3RoZXI=".parse()?;
+        let mut transfer = ZoneTransfer::axfr(server, &zone, TIMEOUT, Some(&other)).await?;
+        assert!(transfer.next().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ixfr() -> anyhow::Result<()> {
+        // * Version 1 to 2 replaces www's address, and 2 to 3 adds ftp.
+        let server = serve(|query| {
+            assert_eq!(query.questions()[0].r#type(), QuestionType::Ixfr);
+            assert_eq!(soa_serial(&query.authorities()[0]), Some(Serial(1)));
+            let records = vec![
+                soa(3).unwrap(),
+                soa(1).unwrap(),
+                a("www.example.com.", 1).unwrap(),
+                soa(2).unwrap(),
+                a("www.example.com.", 2).unwrap(),
+                soa(2).unwrap(),
+                soa(3).unwrap(),
+                a("ftp.example.com.", 3).unwrap(),
+                soa(3).unwrap(),
+            ];
+            vec![query.answer_response(records)]
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let records = ZoneTransfer::ixfr(server, &zone, Serial(1), TIMEOUT, None)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(
+            records,
+            [
+                Transferred::Removed(soa(1)?),
+                Transferred::Removed(a("www.example.com.", 1)?),
+                Transferred::Added(soa(2)?),
+                Transferred::Added(a("www.example.com.", 2)?),
+                Transferred::Removed(soa(2)?),
+                Transferred::Added(soa(3)?),
+                Transferred::Added(a("ftp.example.com.", 3)?),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ixfr_current() -> anyhow::Result<()> {
+        let server = serve(|query| vec![query.answer_response(vec![soa(3).unwrap()])]).await?;
+        let zone = "example.com.".parse()?;
+        let transfer = ZoneTransfer::ixfr(server, &zone, Serial(3), TIMEOUT, None).await?;
+        assert!(transfer.collect().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ixfr_whole_zone() -> anyhow::Result<()> {
+        // * A server may send the whole zone rather than the changes.
+        let server = serve(|query| {
+            let records = vec![
+                soa(3).unwrap(),
+                a("www.example.com.", 1).unwrap(),
+                soa(3).unwrap(),
+            ];
+            vec![query.answer_response(records)]
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let records = ZoneTransfer::ixfr(server, &zone, Serial(1), TIMEOUT, None)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(
+            records,
+            [
+                Transferred::Record(soa(3)?),
+                Transferred::Record(a("www.example.com.", 1)?),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ixfr_falls_back_to_axfr() -> anyhow::Result<()> {
+        let server = serve(|query| match query.questions()[0].r#type() {
+            QuestionType::Ixfr => vec![query.empty_response(ResponseCode::NotImplemented)],
+            _ => vec![query.answer_response(vec![
+                soa(3).unwrap(),
+                a("www.example.com.", 1).unwrap(),
+                soa(3).unwrap(),
+            ])],
+        })
+        .await?;
+        let zone = "example.com.".parse()?;
+        let records = ZoneTransfer::ixfr(server, &zone, Serial(1), TIMEOUT, None)
+            .await?
+            .collect()
+            .await?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], Transferred::Record(soa(3)?));
+
+        let server = serve(|query| vec![query.empty_response(ResponseCode::Refused)]).await?;
+        assert!(ZoneTransfer::ixfr(server, &zone, Serial(1), TIMEOUT, None)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
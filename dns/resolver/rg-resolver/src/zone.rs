@@ -0,0 +1,1185 @@
+use crate::{encoding, rr};
+use rg_resolver_common::DomainName;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+/// The deepest $INCLUDE nesting allowed, which also stops a file from including itself forever.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A zone file to load, and the origin its relative names are relative to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZoneSpec {
+    pub origin: String,
+    pub path: PathBuf,
+}
+
+impl ZoneSpec {
+    /// Parses a spec of the form `<origin>:<path>`, e.g. `example.com.:zones/example.com.zone`.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let Some((origin, path)) = spec.split_once(':') else {
+            anyhow::bail!("zone {spec}: expected <origin>:<path>");
+        };
+        if origin.is_empty() || path.is_empty() {
+            anyhow::bail!("zone {spec}: expected <origin>:<path>");
+        }
+        Ok(ZoneSpec {
+            origin: absolute(origin),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// The records loaded from a zone file and the files it included.
+#[derive(Debug)]
+pub struct Zone {
+    pub origin: DomainName,
+    pub records: Vec<rr::ResourceRecord>,
+}
+
+impl Zone {
+    /// Arranges the records by name.
+    pub fn tree(&self) -> Tree {
+        let mut apex = Node::default();
+        for rr in &self.records {
+            // * Loading rejects records outside the zone, so none are left out here.
+            let Some(labels) = relative_labels(rr.name(), &self.origin) else {
+                continue;
+            };
+            let node = labels.into_iter().fold(&mut apex, |node, label| {
+                node.children.entry(label).or_default()
+            });
+            node.records.push(rr.clone());
+        }
+        Tree {
+            origin: self.origin.clone(),
+            apex,
+        }
+    }
+}
+
+/// A zone's records arranged by name, from the origin down, so finding a name's records or
+/// whether it exists doesn't mean searching them all.
+#[derive(Debug)]
+pub struct Tree {
+    origin: DomainName,
+    apex: Node,
+}
+
+/// A name in a zone, which exists even without records of its own if a name below it has
+/// some (an empty non-terminal, RFC 4592 section 2.2.2).
+#[derive(Debug, Default)]
+pub struct Node {
+    records: Vec<rr::ResourceRecord>,
+    /// Keyed by label, lowercased and with escapes decoded.
+    children: BTreeMap<Vec<u8>, Node>,
+}
+
+impl Tree {
+    pub fn origin(&self) -> &DomainName {
+        &self.origin
+    }
+
+    /// Returns true if name is the origin or below it.
+    pub fn contains(&self, name: &DomainName) -> bool {
+        relative_labels(name, &self.origin).is_some()
+    }
+
+    /// The node at name, or None if the name doesn't exist in the zone.
+    pub fn find(&self, name: &DomainName) -> Option<&Node> {
+        relative_labels(name, &self.origin)?
+            .iter()
+            .try_fold(&self.apex, |node, label| node.children.get(label))
+    }
+
+    /// Looks name up the way an authoritative server does (RFC 1034 section 4.3.2), stopping
+    /// at a delegation and falling back to a wildcard (RFC 4592).
+    pub fn lookup(&self, name: &DomainName) -> Found<'_> {
+        let Some(labels) = relative_labels(name, &self.origin) else {
+            return Found::NoName;
+        };
+        let mut node = &self.apex;
+        for label in &labels {
+            match node.children.get(label) {
+                // * NS records below the apex mark where the zone hands over to another.
+                Some(child) if child.is_delegation() => return Found::Delegation(child),
+                Some(child) => node = child,
+                // * A wildcard only matches below the deepest name that does exist.
+                None => match node.children.get(b"*".as_slice()) {
+                    Some(wildcard) => return Found::Wildcard(wildcard),
+                    None => return Found::NoName,
+                },
+            }
+        }
+        Found::Name(node)
+    }
+
+    /// The SOA record at the apex.
+    pub fn soa(&self) -> Option<&rr::ResourceRecord> {
+        self.apex
+            .records
+            .iter()
+            .find(|rr| rr.r#type() == rr::Type::SOA)
+    }
+}
+
+/// What a [Tree::lookup] found.
+#[derive(Debug)]
+pub enum Found<'a> {
+    /// The name exists, though it may have no records of its own.
+    Name(&'a Node),
+    /// The name is at or below a delegation, the node holding its NS records.
+    Delegation(&'a Node),
+    /// The name doesn't exist, but this wildcard stands in for it.
+    Wildcard(&'a Node),
+    NoName,
+}
+
+impl Node {
+    pub fn records(&self) -> &[rr::ResourceRecord] {
+        &self.records
+    }
+
+    /// The child with label, compared as the tree compares labels.
+    pub fn child(&self, label: &[u8]) -> Option<&Node> {
+        self.children.get(&label.to_ascii_lowercase())
+    }
+
+    fn is_delegation(&self) -> bool {
+        self.records.iter().any(|rr| rr.r#type() == rr::Type::NS)
+    }
+}
+
+/// The labels of name below origin, from the one just below origin down, as the tree keys
+/// them. None if name isn't origin or below it.
+fn relative_labels(name: &DomainName, origin: &DomainName) -> Option<Vec<Vec<u8>>> {
+    if !name.is_subdomain_of(origin) {
+        return None;
+    }
+    let mut labels = name
+        .labels()
+        .map(|label| label.as_bytes().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    labels.truncate(labels.len() - origin.num_labels());
+    labels.reverse();
+    Some(labels)
+}
+
+/// Loads every zone, each on its own blocking thread, returning the results in the same order.
+pub async fn load_all(specs: &[ZoneSpec]) -> Vec<anyhow::Result<Zone>> {
+    let loads = specs
+        .iter()
+        .cloned()
+        .map(|spec| tokio::task::spawn_blocking(move || load(&spec)))
+        .collect::<Vec<_>>();
+    let mut zones = Vec::with_capacity(loads.len());
+    for load in loads {
+        zones.push(load.await.unwrap_or_else(|e| Err(e.into())));
+    }
+    zones
+}
+
+/// Loads a zone from a master file (RFC 1035 section 5).
+///
+/// Errors name the file, line, and column they were found at.
+pub fn load(spec: &ZoneSpec) -> anyhow::Result<Zone> {
+    let origin = spec
+        .origin
+        .parse::<DomainName>()
+        .map_err(|e| anyhow::anyhow!("invalid origin {}: {e}", spec.origin))?;
+    let mut loader = Loader {
+        zone_origin: origin,
+        records: Vec::new(),
+        default_ttl: None,
+        last_ttl: None,
+    };
+    loader.load_file(&spec.path, &spec.origin, 0)?;
+    Ok(Zone {
+        origin: loader.zone_origin,
+        records: loader.records,
+    })
+}
+
+struct Loader {
+    zone_origin: DomainName,
+    records: Vec<rr::ResourceRecord>,
+    /// The TTL set by $TTL (RFC 2308 section 4).
+    default_ttl: Option<i32>,
+    /// The TTL of the last record that gave one, used when there's no $TTL.
+    last_ttl: Option<i32>,
+}
+
+/// A field of an entry, with where it starts.
+#[derive(Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+/// One record or directive, which parentheses can spread over several lines.
+#[derive(Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    /// The entry started with whitespace, so it's owned by the previous owner.
+    inherits_owner: bool,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path, origin: &str, depth: usize) -> anyhow::Result<()> {
+        let file = path.display().to_string();
+        let contents =
+            fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{file}: reading zone: {e}"))?;
+        let entries = tokenize(&contents)
+            .map_err(|(line, column, e)| anyhow::anyhow!("{file}:{line}:{column}: {e}"))?;
+
+        // * $ORIGIN only applies until the end of the file it's in (RFC 1035 section 5.1).
+        let mut origin = origin.to_string();
+        let mut owner: Option<DomainName> = None;
+        for entry in entries {
+            let at = |token: &Token, e: &dyn std::fmt::Display| {
+                anyhow::anyhow!("{file}:{}:{}: {e}", token.line, token.column)
+            };
+            let first = &entry.tokens[0];
+            match first.text.as_str() {
+                "$ORIGIN" => {
+                    let [_, name] = entry.tokens.as_slice() else {
+                        return Err(at(first, &"expected $ORIGIN <name>"));
+                    };
+                    origin = qualify(&name.text, &origin);
+                }
+                "$TTL" => {
+                    let [_, ttl] = entry.tokens.as_slice() else {
+                        return Err(at(first, &"expected $TTL <ttl>"));
+                    };
+                    self.default_ttl = Some(parse_ttl(&ttl.text).map_err(|e| at(ttl, &e))?);
+                }
+                "$INCLUDE" => {
+                    let (include, include_origin) = match entry.tokens.as_slice() {
+                        [_, include] => (include, origin.clone()),
+                        [_, include, name] => (include, qualify(&name.text, &origin)),
+                        _ => return Err(at(first, &"expected $INCLUDE <file> [<origin>]")),
+                    };
+                    if depth + 1 >= MAX_INCLUDE_DEPTH {
+                        return Err(at(include, &"$INCLUDE nested too deeply"));
+                    }
+                    // * Included files are found relative to the file including them.
+                    let include_path = path.parent().unwrap_or(Path::new(".")).join(&include.text);
+                    self.load_file(&include_path, &include_origin, depth + 1)?;
+                }
+                directive if directive.starts_with('$') => {
+                    return Err(at(first, &format!("unknown directive {directive}")));
+                }
+                _ => {
+                    let mut tokens = entry.tokens.iter().peekable();
+                    if !entry.inherits_owner {
+                        let name = tokens.next().unwrap();
+                        let text = qualify(&name.text, &origin);
+                        owner = Some(
+                            text.parse::<DomainName>()
+                                .map_err(|e| at(name, &format!("invalid name {text}: {e}")))?,
+                        );
+                    }
+                    let Some(owner) = owner.clone() else {
+                        return Err(at(first, &"record has no owner"));
+                    };
+                    if !owner.is_subdomain_of(&self.zone_origin) {
+                        let e = format!("{owner} is outside the zone {}", self.zone_origin);
+                        return Err(at(first, &e));
+                    }
+                    let rr = self
+                        .parse_record(owner, &origin, &mut tokens, first)
+                        .map_err(|(token, e)| at(token, &e))?;
+                    self.records.push(rr);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the `[<ttl>] [<class>] <type> <rdata>` after the owner.
+    fn parse_record<'a>(
+        &mut self,
+        owner: DomainName,
+        origin: &str,
+        tokens: &mut std::iter::Peekable<std::slice::Iter<'a, Token>>,
+        first: &'a Token,
+    ) -> Result<rr::ResourceRecord, (&'a Token, anyhow::Error)> {
+        let mut ttl = None;
+        let mut class = None;
+        // * The TTL and class may come in either order.
+        while let Some(token) = tokens.peek() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text).map_err(|e| (*token, e))?);
+            } else if let (None, Ok(parsed)) = (class, token.text.parse::<rr::Class>()) {
+                class = Some(parsed);
+            } else {
+                break;
+            }
+            tokens.next();
+        }
+        let Some(type_token) = tokens.next() else {
+            return Err((first, anyhow::anyhow!("record has no type")));
+        };
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            None => {
+                return Err((
+                    first,
+                    anyhow::anyhow!("record has no TTL and there's no $TTL"),
+                ))
+            }
+        };
+        if self.default_ttl.is_none() {
+            self.last_ttl = Some(ttl);
+        }
+
+        let rdata = tokens.collect::<Vec<_>>();
+        let (r#type, data) = parse_rdata(type_token, &rdata, origin)?;
+        rr::ResourceRecord::new(owner, r#type, class.unwrap_or(rr::Class::IN), ttl, data)
+            .map_err(|e| (type_token, e))
+    }
+}
+
+/// Splits a zone file into entries, or returns the line, column, and description of the
+/// first syntax error.
+fn tokenize(contents: &str) -> Result<Vec<Entry>, (usize, usize, String)> {
+    let mut entries = Vec::new();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut inherits_owner = false;
+    let mut paren_start = None;
+
+    for (line_idx, text) in contents.lines().enumerate() {
+        let line = line_idx + 1;
+        if paren_start.is_none() {
+            inherits_owner = text.starts_with([' ', '\t']);
+        }
+        let mut chars = text.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            let column = idx + 1;
+            match c {
+                ';' => break,
+                ' ' | '\t' => {}
+                '(' => {
+                    if paren_start.is_some() {
+                        return Err((line, column, String::from("nested parentheses")));
+                    }
+                    paren_start = Some((line, column));
+                }
+                ')' => {
+                    if paren_start.take().is_none() {
+                        return Err((line, column, String::from("unmatched )")));
+                    }
+                }
+                '"' => {
+                    let mut quoted = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, c)) => quoted.push(c),
+                                None => break,
+                            },
+                            Some((_, c)) => quoted.push(c),
+                            None => {
+                                return Err((line, column, String::from("unterminated string")))
+                            }
+                        }
+                    }
+                    tokens.push(Token {
+                        text: quoted,
+                        line,
+                        column,
+                    });
+                }
+                c => {
+                    let mut word = String::from(c);
+                    while let Some(&(_, c)) = chars.peek() {
+                        if matches!(c, ' ' | '\t' | ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token {
+                        text: word,
+                        line,
+                        column,
+                    });
+                }
+            }
+        }
+        if paren_start.is_none() && !tokens.is_empty() {
+            entries.push(Entry {
+                tokens: std::mem::take(&mut tokens),
+                inherits_owner,
+            });
+        }
+    }
+    if let Some((line, column)) = paren_start {
+        return Err((line, column, String::from("unmatched (")));
+    }
+    Ok(entries)
+}
+
+/// Parses the data of a record, returning the field at fault with any error.
+///
+/// Any type's data can also be given in the generic form, `\# <length> <hex>` (RFC 3597
+/// section 5), which is the only form for NULL and types without a mnemonic.
+fn parse_rdata<'a>(
+    type_token: &'a Token,
+    rdata: &[&'a Token],
+    origin: &str,
+) -> Result<(rr::Type, rr::Data), (&'a Token, anyhow::Error)> {
+    let name = |token: &'a Token| {
+        let text = qualify(&token.text, origin);
+        text.parse::<DomainName>()
+            .map_err(|e| (token, anyhow::anyhow!("invalid name {text}: {e}")))
+    };
+    let ttl = |token: &'a Token| parse_ttl(&token.text).map_err(|e| (token, e));
+    fn number<T: std::str::FromStr<Err = std::num::ParseIntError>>(
+        token: &Token,
+    ) -> Result<T, (&Token, anyhow::Error)> {
+        token
+            .text
+            .parse()
+            .map_err(|e| (token, anyhow::anyhow!("invalid number {}: {e}", token.text)))
+    }
+    fn address<T: std::str::FromStr<Err = std::net::AddrParseError>>(
+        token: &Token,
+    ) -> Result<T, (&Token, anyhow::Error)> {
+        token.text.parse().map_err(|e| {
+            (
+                token,
+                anyhow::anyhow!("invalid address {}: {e}", token.text),
+            )
+        })
+    }
+    // * Binary fields may be split over several fields, to spread long keys over lines.
+    let binary = |tokens: &[&'a Token], decode: fn(&str) -> anyhow::Result<Vec<u8>>| {
+        let text = tokens
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect::<String>();
+        decode(&text).map_err(|e| (tokens[0], e))
+    };
+    let types = |tokens: &[&'a Token]| {
+        tokens
+            .iter()
+            .map(|token| {
+                let r#type = token.text.parse::<rr::Type>();
+                r#type
+                    .map(|r#type| r#type.serialize())
+                    .map_err(|e| (*token, e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let r#type = type_token.text.parse::<rr::Type>().map_err(|_| {
+        (
+            type_token,
+            anyhow::anyhow!("unsupported record type {}", type_token.text),
+        )
+    })?;
+    if let [generic, len, hex @ ..] = rdata {
+        if generic.text == "\\#" {
+            let len = number::<u16>(len)?;
+            let data = match hex {
+                [] => Vec::new(),
+                hex => binary(hex, encoding::decode_hex)?,
+            };
+            if data.len() != len as usize {
+                return Err((*generic, anyhow::anyhow!("\\# data isn't {len} bytes")));
+            }
+            let mut wire = len.to_be_bytes().to_vec();
+            wire.extend(data);
+            let data = rr::Data::parse(&wire, &mut &wire[..], r#type)
+                .map_err(|e| (*generic, anyhow::anyhow!("invalid {type} data: {e}")))?;
+            return Ok((r#type, data));
+        }
+    }
+
+    use rr::Type;
+    let data = match (r#type, rdata) {
+        (Type::A, [a]) => rr::Data::A(address(a)?),
+        (Type::AAAA, [a]) => rr::Data::AAAA(address(a)?),
+        (Type::NS, [host]) => rr::Data::NS(name(host)?),
+        (Type::MD, [host]) => rr::Data::MD(name(host)?),
+        (Type::MF, [host]) => rr::Data::MF(name(host)?),
+        (Type::CNAME, [target]) => rr::Data::CNAME(name(target)?),
+        (Type::MB, [host]) => rr::Data::MB(name(host)?),
+        (Type::MG, [mailbox]) => rr::Data::MG(name(mailbox)?),
+        (Type::MR, [mailbox]) => rr::Data::MR(name(mailbox)?),
+        (Type::PTR, [target]) => rr::Data::PTR(name(target)?),
+        (Type::MX, [preference, exchange]) => rr::Data::MX {
+            preference: number(preference)?,
+            exchange: name(exchange)?,
+        },
+        (Type::SOA, [mname, rname, serial, refresh, retry, expire, minimum]) => rr::Data::SOA {
+            mname: name(mname)?,
+            rname: name(rname)?,
+            serial: number(serial)?,
+            refresh: ttl(refresh)? as u32,
+            retry: ttl(retry)? as u32,
+            expire: ttl(expire)? as u32,
+            minimum: ttl(minimum)?,
+        },
+        (Type::WKS, [a, protocol, ports @ ..]) => {
+            let protocol = match protocol.text.to_ascii_lowercase().as_str() {
+                "tcp" => 6,
+                "udp" => 17,
+                _ => number(protocol)?,
+            };
+            // * A bit per port, the first for port 0 (RFC 1035 section 3.4.2).
+            let mut bit_map = Vec::new();
+            for port in ports {
+                let port = number::<u16>(port)? as usize;
+                if bit_map.len() <= port / 8 {
+                    bit_map.resize(port / 8 + 1, 0);
+                }
+                bit_map[port / 8] |= 0x80 >> (port % 8);
+            }
+            rr::Data::WKS {
+                address: address(a)?,
+                protocol,
+                bit_map,
+            }
+        }
+        (Type::HINFO, [cpu, os]) => rr::Data::HINFO {
+            cpu: cpu.text.clone(),
+            os: os.text.clone(),
+        },
+        (Type::MINFO, [rmailbx, emailbx]) => rr::Data::MINFO {
+            rmailbx: name(rmailbx)?,
+            emailbx: name(emailbx)?,
+        },
+        (Type::TXT, strings) if !strings.is_empty() => {
+            rr::Data::TXT(strings.iter().map(|token| token.text.clone()).collect())
+        }
+        (Type::DS, [key_tag, algorithm, digest_type, digest @ ..]) if !digest.is_empty() => {
+            rr::Data::DS {
+                key_tag: number(key_tag)?,
+                algorithm: number(algorithm)?,
+                digest_type: number(digest_type)?,
+                digest: binary(digest, encoding::decode_hex)?,
+            }
+        }
+        (
+            Type::RRSIG,
+            [type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer_name, signature @ ..],
+        ) if !signature.is_empty() => rr::Data::RRSIG {
+            type_covered: types(&[*type_covered])?[0],
+            algorithm: number(algorithm)?,
+            labels: number(labels)?,
+            original_ttl: ttl(original_ttl)? as u32,
+            signature_expiration: parse_time(&expiration.text).map_err(|e| (*expiration, e))?,
+            signature_inception: parse_time(&inception.text).map_err(|e| (*inception, e))?,
+            key_tag: number(key_tag)?,
+            signer_name: name(signer_name)?,
+            signature: binary(signature, encoding::decode_base64)?,
+        },
+        (Type::NSEC, [next_domain_name, covered @ ..]) => rr::Data::NSEC {
+            next_domain_name: name(next_domain_name)?,
+            types: types(covered)?,
+        },
+        (Type::DNSKEY, [flags, protocol, algorithm, public_key @ ..]) if !public_key.is_empty() => {
+            rr::Data::DNSKEY {
+                flags: number(flags)?,
+                protocol: number(protocol)?,
+                algorithm: number(algorithm)?,
+                public_key: binary(public_key, encoding::decode_base64)?,
+            }
+        }
+        (Type::NSEC3, [hash_algorithm, flags, iterations, salt, next, covered @ ..]) => {
+            rr::Data::NSEC3 {
+                hash_algorithm: number(hash_algorithm)?,
+                flags: number(flags)?,
+                iterations: number(iterations)?,
+                // * "-" is an empty salt (RFC 5155 section 3.3).
+                salt: match salt.text.as_str() {
+                    "-" => Vec::new(),
+                    _ => binary(&[*salt], encoding::decode_hex)?,
+                },
+                next_hashed_owner_name: binary(&[*next], encoding::decode_base32hex)?,
+                types: types(covered)?,
+            }
+        }
+        (Type::CAA, [flags, tag, value]) => rr::Data::CAA {
+            flags: number(flags)?,
+            tag: tag.text.clone(),
+            value: value.text.as_bytes().to_vec(),
+        },
+        (Type::NAPTR, [order, preference, flags, services, regexp, replacement]) => {
+            rr::Data::NAPTR {
+                order: number(order)?,
+                preference: number(preference)?,
+                flags: flags.text.clone(),
+                services: services.text.clone(),
+                regexp: regexp.text.clone(),
+                replacement: name(replacement)?,
+            }
+        }
+        (Type::SVCB | Type::HTTPS, [priority, target, params @ ..]) => {
+            let binding = rr::ServiceBinding {
+                priority: number(priority)?,
+                target: name(target)?,
+                params: parse_svc_params(params)?,
+            };
+            match r#type {
+                Type::SVCB => rr::Data::SVCB(binding),
+                _ => rr::Data::HTTPS(binding),
+            }
+        }
+        (Type::NULL | Type::Unknown(_), _) => {
+            return Err((
+                type_token,
+                anyhow::anyhow!("{type} data can only be given as \\# <length> <hex>"),
+            ))
+        }
+        _ => {
+            return Err((
+                type_token,
+                anyhow::anyhow!("wrong number of fields for {type}"),
+            ))
+        }
+    };
+    Ok((r#type, data))
+}
+
+/// Parses SVCB and HTTPS parameters, given as `key=value` or just `key` (RFC 9460 section 2.1).
+fn parse_svc_params<'a>(
+    tokens: &[&'a Token],
+) -> Result<Vec<rr::SvcParam>, (&'a Token, anyhow::Error)> {
+    let mut params = Vec::new();
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        let (key, value) = match token.text.split_once('=') {
+            // * A quoted value is a field of its own, after the "key=".
+            Some((key, "")) => match tokens.next() {
+                Some(value) => (key, Some(value.text.as_str())),
+                None => (key, Some("")),
+            },
+            Some((key, value)) => (key, Some(value)),
+            None => (token.text.as_str(), None),
+        };
+        let param = parse_svc_param(key, value).map_err(|e| (*token, e))?;
+        params.push(param);
+    }
+    Ok(params)
+}
+
+fn parse_svc_param(key: &str, value: Option<&str>) -> anyhow::Result<rr::SvcParam> {
+    fn key_code(key: &str) -> anyhow::Result<u16> {
+        Ok(match key {
+            "mandatory" => rr::SvcParam::MANDATORY,
+            "alpn" => rr::SvcParam::ALPN,
+            "no-default-alpn" => rr::SvcParam::NO_DEFAULT_ALPN,
+            "port" => rr::SvcParam::PORT,
+            "ipv4hint" => rr::SvcParam::IPV4HINT,
+            "ech" => rr::SvcParam::ECH,
+            "ipv6hint" => rr::SvcParam::IPV6HINT,
+            _ => key
+                .strip_prefix("key")
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("unknown SvcParam key {key}"))?,
+        })
+    }
+    let code = key_code(key)?;
+    let list = || value.unwrap_or_default().split(',');
+    let mut encoded = Vec::new();
+    match (code, value) {
+        (rr::SvcParam::NO_DEFAULT_ALPN, None) => {}
+        (_, None) => anyhow::bail!("SvcParam {key} needs a value"),
+        (rr::SvcParam::NO_DEFAULT_ALPN, Some(_)) => anyhow::bail!("SvcParam {key} takes no value"),
+        (rr::SvcParam::MANDATORY, _) => {
+            for key in list() {
+                encoded.extend(key_code(key)?.to_be_bytes());
+            }
+        }
+        (rr::SvcParam::ALPN, _) => {
+            for protocol in list() {
+                let Ok(len) = u8::try_from(protocol.len()) else {
+                    anyhow::bail!("ALPN protocol {protocol} too long");
+                };
+                encoded.push(len);
+                encoded.extend(protocol.bytes());
+            }
+        }
+        (rr::SvcParam::PORT, Some(port)) => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|e| anyhow::anyhow!("invalid port {port}: {e}"))?;
+            encoded.extend(port.to_be_bytes());
+        }
+        (rr::SvcParam::IPV4HINT, _) => {
+            for addr in list() {
+                let addr = addr
+                    .parse::<Ipv4Addr>()
+                    .map_err(|e| anyhow::anyhow!("invalid address {addr}: {e}"))?;
+                encoded.extend(addr.octets());
+            }
+        }
+        (rr::SvcParam::IPV6HINT, _) => {
+            for addr in list() {
+                let addr = addr
+                    .parse::<Ipv6Addr>()
+                    .map_err(|e| anyhow::anyhow!("invalid address {addr}: {e}"))?;
+                encoded.extend(addr.octets());
+            }
+        }
+        (rr::SvcParam::ECH, Some(config)) => encoded = encoding::decode_base64(config)?,
+        (_, Some(value)) => encoded.extend(value.bytes()),
+    }
+    Ok(rr::SvcParam {
+        key: code,
+        value: encoded,
+    })
+}
+
+/// Parses an RRSIG time, either as seconds since the epoch or as YYYYMMDDHHmmSS in UTC
+/// (RFC 4034 section 3.2).
+fn parse_time(text: &str) -> anyhow::Result<u32> {
+    let invalid = || anyhow::anyhow!("invalid time {text}");
+    if text.len() != 14 || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse().map_err(|_| invalid());
+    }
+    let field = |range: std::ops::Range<usize>| text[range].parse::<i64>().map_err(|_| invalid());
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    if second > 60 {
+        return Err(invalid());
+    }
+    // * Days from 1970-01-01 to the date in the proleptic Gregorian calendar, counting
+    // * years from March so leap days come last.
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    // * The field holds the time modulo 2^32 (RFC 4034 section 3.1.5).
+    Ok(seconds.rem_euclid(1 << 32) as u32)
+}
+
+/// Parses a TTL in seconds, or with BIND's unit suffixes, e.g. 1h30m.
+fn parse_ttl(text: &str) -> anyhow::Result<i32> {
+    let invalid = || anyhow::anyhow!("invalid TTL {text}");
+    if let Ok(seconds) = text.parse::<u32>() {
+        return i32::try_from(seconds).map_err(|_| invalid());
+    }
+    let mut total: i64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value = digits.parse::<i64>().map_err(|_| invalid())?;
+        total += value * unit;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    i32::try_from(total).map_err(|_| invalid())
+}
+
+/// Makes name absolute, relative to origin. "@" is the origin itself.
+fn qualify(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if name.ends_with('.') && !name.ends_with("\\.") {
+        name.to_string()
+    } else if origin == "." {
+        format!("{name}.")
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+fn absolute(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{name}.")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A directory under the system temp directory that's removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "rg-resolver-zone-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn spec(origin: &str, path: PathBuf) -> ZoneSpec {
+        ZoneSpec {
+            origin: origin.to_string(),
+            path,
+        }
+    }
+
+    fn name(text: &str) -> DomainName {
+        text.parse().unwrap()
+    }
+
+    fn a(zone: &Zone, owner: &str) -> Vec<Ipv4Addr> {
+        let owner = name(owner);
+        zone.records
+            .iter()
+            .filter(|rr| *rr.name() == owner)
+            .filter_map(|rr| match rr.data() {
+                rr::Data::A(addr) => Some(*addr),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn load_zone() -> anyhow::Result<()> {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "example.zone",
+            [
+                "$TTL 1h",
+                "@   IN  SOA ns1 hostmaster (",
+                "        2024010101 ; serial",
+                "        3600 600 1w 300 )",
+                "    IN  NS  ns1",
+                "ns1     A   192.0.2.1",
+                "www 300 IN A 192.0.2.10",
+                "    IN 60 A 192.0.2.11",
+                "mail    MX  10 mx.other.net.",
+                r#"txt     TXT "hello world" "a \"quoted\" word""#,
+                "www     AAAA 2001:db8::10",
+            ]
+            .join("\n"),
+        );
+        let zone = load(&spec("example.com.", path))?;
+        assert_eq!(zone.records.len(), 8);
+
+        let soa = &zone.records[0];
+        assert_eq!(soa.name(), &name("example.com."));
+        assert_eq!(soa.ttl(), 3600);
+        assert!(matches!(
+            soa.data(),
+            rr::Data::SOA { mname, serial: 2024010101, expire: 604800, .. } if *mname == name("ns1.example.com.")
+        ));
+        // * A record starting with whitespace belongs to the previous owner.
+        assert_eq!(zone.records[1].name(), &name("example.com."));
+        assert_eq!(
+            a(&zone, "www.example.com."),
+            [Ipv4Addr::new(192, 0, 2, 10), Ipv4Addr::new(192, 0, 2, 11)]
+        );
+        assert_eq!(zone.records[3].ttl(), 300);
+        assert_eq!(zone.records[4].ttl(), 60);
+        assert!(matches!(
+            zone.records[6].data(),
+            rr::Data::TXT(strings) if strings == &["hello world", "a \"quoted\" word"]
+        ));
+        assert_eq!(
+            zone.records[7].data(),
+            &rr::Data::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x10))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_every_type() -> anyhow::Result<()> {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "example.zone",
+            [
+                "$TTL 300",
+                "@       DS      60485 5 1 ( 2BB183AF5F22588179A53B0A",
+                "                98631FAD1A292118 )",
+                "        DNSKEY  257 3 5 AwEA AaA=",
+                "        RRSIG   A 5 2 86400 20030322173103 ( 1048354263 2642 example.com. Zg== )",
+                "        NSEC    host.example.com. A MX RRSIG NSEC TYPE1234",
+                "        CAA     0 issue \"ca.example.net\"",
+                "        HTTPS   1 . alpn=\"h2,h3\" port=8443 ipv4hint=192.0.2.1,192.0.2.2",
+                "svc     SVCB    0 pool.svc.example.",
+                "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 12 aabbccdd (",
+                "                2t7b4g4vsa5smi47k61mv5bv1a22bojr MX DNSKEY NS SOA )",
+                "naptr   NAPTR   100 10 \"u\" \"E2U+sip\" \"!^.*$!sip:info@example.com!\" .",
+                "mail    MINFO   admin errors",
+                "        MB      mail.other.net.",
+                "wks     WKS     192.0.2.1 tcp 25 80",
+                "raw     TYPE731 \\# 4 0a000001",
+                "        NULL    \\# 2 abcd",
+                "        A       \\# 4 C0000201",
+            ]
+            .join("\n"),
+        );
+        let zone = load(&spec("example.com.", path))?;
+        let data = zone.records.iter().map(|rr| rr.data()).collect::<Vec<_>>();
+        assert_eq!(
+            data[0],
+            &rr::Data::DS {
+                key_tag: 60485,
+                algorithm: 5,
+                digest_type: 1,
+                digest: encoding::decode_hex("2BB183AF5F22588179A53B0A98631FAD1A292118")?,
+            }
+        );
+        assert!(matches!(
+            data[1],
+            rr::Data::DNSKEY { flags: 257, public_key, .. } if public_key == &[3, 1, 0, 1, 0xa0]
+        ));
+        assert!(matches!(
+            data[2],
+            rr::Data::RRSIG {
+                type_covered: 1,
+                signature_expiration: 1048354263,
+                signature_inception: 1048354263,
+                ..
+            }
+        ));
+        assert!(matches!(
+            data[3],
+            rr::Data::NSEC { types, .. } if types == &[1, 15, 46, 47, 1234]
+        ));
+        assert_eq!(
+            data[4],
+            &rr::Data::CAA {
+                flags: 0,
+                tag: String::from("issue"),
+                value: b"ca.example.net".to_vec(),
+            }
+        );
+        let rr::Data::HTTPS(binding) = data[5] else {
+            panic!("not HTTPS: {:?}", data[5]);
+        };
+        assert!(binding.target.is_root());
+        assert_eq!(binding.params[0].value, b"\x02h2\x02h3");
+        assert_eq!(binding.params[1].value, 8443_u16.to_be_bytes());
+        assert_eq!(binding.params[2].value, [192, 0, 2, 1, 192, 0, 2, 2]);
+        assert!(matches!(data[6], rr::Data::SVCB(binding) if binding.priority == 0));
+        assert!(matches!(
+            data[7],
+            rr::Data::NSEC3 { iterations: 12, salt, next_hashed_owner_name, .. }
+                if salt == &[0xaa, 0xbb, 0xcc, 0xdd] && next_hashed_owner_name.len() == 20
+        ));
+        assert!(matches!(
+            data[8],
+            rr::Data::NAPTR { services, replacement, .. } if services == "E2U+sip" && replacement.is_root()
+        ));
+        assert!(matches!(
+            data[9],
+            rr::Data::MINFO { rmailbx, .. } if *rmailbx == name("admin.example.com.")
+        ));
+        assert_eq!(data[10], &rr::Data::MB(name("mail.other.net.")));
+        assert!(matches!(
+            data[11],
+            rr::Data::WKS { protocol: 6, bit_map, .. } if bit_map.len() == 11 && bit_map[3] == 0x40
+        ));
+        assert_eq!(
+            data[12],
+            &rr::Data::Unknown {
+                type_code: 731,
+                rdata: vec![0x0a, 0, 0, 1],
+            }
+        );
+        assert_eq!(data[13], &rr::Data::NULL(vec![0xab, 0xcd]));
+        assert_eq!(data[14], &rr::Data::A(Ipv4Addr::new(192, 0, 2, 1)));
+        Ok(())
+    }
+
+    #[test]
+    fn tree() -> anyhow::Result<()> {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "example.zone",
+            "$TTL 300\n\
+             @ NS ns1\n\
+             www A 192.0.2.1\n\
+             WWW A 192.0.2.2\n\
+             host.lab A 192.0.2.3\n",
+        );
+        let tree = load(&spec("example.com.", path))?.tree();
+        assert_eq!(tree.origin(), &name("example.com."));
+        assert_eq!(tree.find(&name("example.com.")).unwrap().records().len(), 1);
+        // * Names are compared ignoring case.
+        let www = tree.find(&name("Www.Example.Com.")).unwrap();
+        assert_eq!(www.records().len(), 2);
+        // * A name with none of its own records exists if a name below it has some.
+        let lab = tree.find(&name("lab.example.com.")).unwrap();
+        assert!(lab.records().is_empty());
+        assert_eq!(lab.child(b"HOST").unwrap().records().len(), 1);
+        assert!(tree.find(&name("ftp.example.com.")).is_none());
+
+        assert!(tree.contains(&name("anything.example.com.")));
+        assert!(!tree.contains(&name("example.net.")));
+        assert!(!tree.contains(&name("com.")));
+        Ok(())
+    }
+
+    #[test]
+    fn include_and_origin() -> anyhow::Result<()> {
+        let dir = TempDir::new();
+        dir.write(
+            "sub/hosts.zone",
+            "$ORIGIN lab.example.com.\n\
+             printer A 192.0.2.20\n",
+        );
+        dir.write("sub/other.zone", "printer A 192.0.2.30\n");
+        let path = dir.write(
+            "example.zone",
+            "$TTL 300\n\
+             $INCLUDE sub/hosts.zone\n\
+             $INCLUDE sub/other.zone office\n\
+             ; The included file's $ORIGIN doesn't apply here.\n\
+             printer A 192.0.2.40\n",
+        );
+        let zone = load(&spec("example.com.", path))?;
+        assert_eq!(
+            a(&zone, "printer.lab.example.com."),
+            [Ipv4Addr::new(192, 0, 2, 20)]
+        );
+        assert_eq!(
+            a(&zone, "printer.office.example.com."),
+            [Ipv4Addr::new(192, 0, 2, 30)]
+        );
+        assert_eq!(
+            a(&zone, "printer.example.com."),
+            [Ipv4Addr::new(192, 0, 2, 40)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn errors_name_file_line_and_column() {
+        let dir = TempDir::new();
+        let check = |contents: &str, expected: &str| {
+            let path = dir.write("bad.zone", contents);
+            let e = load(&spec("example.com.", path.clone())).unwrap_err();
+            assert_eq!(e.to_string(), format!("{}:{expected}", path.display()));
+        };
+        check(
+            "$TTL 300\nwww A 192.0.2.300\n",
+            "2:7: invalid address 192.0.2.300: invalid IPv4 address syntax",
+        );
+        check("www 300 A 192.0.2.1\nftp A (\n", "2:7: unmatched (");
+        check(
+            "www A 192.0.2.1\n",
+            "1:1: record has no TTL and there's no $TTL",
+        );
+        check(
+            "$TTL 300\nwww SRV 0 0 80 host\n",
+            "2:5: unsupported record type SRV",
+        );
+        check(
+            "$TTL 300\nwww MX 10\n",
+            "2:5: wrong number of fields for MX",
+        );
+        check("$TTL 5x\n", "1:6: invalid TTL 5x");
+        check(
+            "$TTL 300\nwww CNAME a..b\n",
+            "2:11: invalid name a..b.example.com.: invalid QNAME: interior label missing",
+        );
+        check("$TTL 300\n  A 192.0.2.1\n", "2:3: record has no owner");
+        check(
+            "$TTL 300\nwww.example.net. A 192.0.2.1\n",
+            "2:1: www.example.net. is outside the zone example.com.",
+        );
+        check(
+            "$TTL 300\nraw TYPE731 10 20\n",
+            "2:5: TYPE731 data can only be given as \\# <length> <hex>",
+        );
+        check(
+            "$TTL 300\nraw A \\# 3 c00002\n",
+            "2:7: invalid A data: parsing RR: type A RR data not 4 bytes",
+        );
+        check(
+            "$TTL 300\nraw A \\# 4 c00002\n",
+            "2:7: \\# data isn't 4 bytes",
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let dir = TempDir::new();
+        let path = dir.write("loop.zone", "$INCLUDE loop.zone\n");
+        let e = load(&spec("example.com.", path)).unwrap_err();
+        assert!(e.to_string().ends_with("$INCLUDE nested too deeply"));
+
+        let path = dir.write("missing.zone", "$INCLUDE nope.zone\n");
+        let e = load(&spec("example.com.", path)).unwrap_err();
+        assert!(e.to_string().contains("nope.zone: reading zone"));
+    }
+
+    #[tokio::test]
+    async fn load_in_parallel() {
+        let dir = TempDir::new();
+        let good = dir.write("good.zone", "$TTL 300\nwww A 192.0.2.1\n");
+        let bad = dir.write("bad.zone", "www A 192.0.2.1\n");
+        let zones = load_all(&[spec("a.example.", good), spec("b.example.", bad)]).await;
+        assert_eq!(zones[0].as_ref().unwrap().origin, name("a.example."));
+        assert!(zones[1].is_err());
+    }
+
+    #[test]
+    fn parse_spec_and_ttls() -> anyhow::Result<()> {
+        assert_eq!(
+            ZoneSpec::parse("example.com:zones/example.zone")?,
+            spec("example.com.", PathBuf::from("zones/example.zone"))
+        );
+        assert!(ZoneSpec::parse("example.com").is_err());
+
+        assert_eq!(parse_ttl("300")?, 300);
+        assert_eq!(parse_ttl("1h30m")?, 5400);
+        assert_eq!(parse_ttl("2D")?, 172800);
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("3000000000").is_err());
+
+        assert_eq!(parse_time("20030322173103")?, 1048354263);
+        assert_eq!(parse_time("20000229000000")?, 951782400);
+        assert_eq!(parse_time("1048354263")?, 1048354263);
+        assert!(parse_time("20031322173103").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How lookups are spread across the resolver instances.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    /// Each lookup goes to the next healthy server in turn.
+    RoundRobin,
+    /// Lookups go to the healthy server with the lowest smoothed round-trip time.
+    /// Servers with no measurements yet are tried first.
+    LatencyWeighted,
+}
+
+/// The resolver instances a client can send lookups to, with their health.
+///
+/// A server is taken out of rotation after max_failures consecutive failures and put back
+/// after retry_after, or as soon as a lookup or health check to it succeeds.
+pub struct ServerPool {
+    strategy: Strategy,
+    max_failures: u32,
+    retry_after: Duration,
+    state: Mutex<PoolState>,
+}
+
+struct PoolState {
+    servers: Vec<Server>,
+    next: usize,
+}
+
+struct Server {
+    addr: SocketAddr,
+    consecutive_failures: u32,
+    down_since: Option<Instant>,
+    smoothed_rtt: Option<Duration>,
+}
+
+impl ServerPool {
+    const DEFAULT_MAX_FAILURES: u32 = 3;
+    const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+    pub fn new(addrs: Vec<SocketAddr>, strategy: Strategy) -> ServerPool {
+        let servers = addrs
+            .into_iter()
+            .map(|addr| Server {
+                addr,
+                consecutive_failures: 0,
+                down_since: None,
+                smoothed_rtt: None,
+            })
+            .collect();
+        ServerPool {
+            strategy,
+            max_failures: Self::DEFAULT_MAX_FAILURES,
+            retry_after: Self::DEFAULT_RETRY_AFTER,
+            state: Mutex::new(PoolState { servers, next: 0 }),
+        }
+    }
+
+    pub fn with_health_policy(mut self, max_failures: u32, retry_after: Duration) -> ServerPool {
+        self.max_failures = max_failures.max(1);
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// The servers to try for the next lookup, in order: the chosen server first, then the
+    /// others to fail over to. Servers that are down come last, so a lookup is still attempted
+    /// when every server is down.
+    pub fn candidates(&self) -> Vec<SocketAddr> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let num_servers = state.servers.len();
+        if num_servers == 0 {
+            return Vec::new();
+        }
+
+        let start = state.next % num_servers;
+        state.next = state.next.wrapping_add(1);
+        let mut order = (0..num_servers)
+            .map(|n| (start + n) % num_servers)
+            .collect::<Vec<_>>();
+        if self.strategy == Strategy::LatencyWeighted {
+            // * The sort is stable, so servers with equal RTTs still take turns.
+            order.sort_by_key(|&idx| state.servers[idx].smoothed_rtt.unwrap_or_default());
+        }
+        let is_up = |server: &Server| match server.down_since {
+            None => true,
+            Some(since) => now.duration_since(since) >= self.retry_after,
+        };
+        let (up, down): (Vec<_>, Vec<_>) = order
+            .into_iter()
+            .partition(|&idx| is_up(&state.servers[idx]));
+        up.into_iter()
+            .chain(down)
+            .map(|idx| state.servers[idx].addr)
+            .collect()
+    }
+
+    /// Records a lookup to addr that was answered after rtt.
+    pub fn record_success(&self, addr: SocketAddr, rtt: Duration) {
+        self.update(addr, |server| {
+            server.consecutive_failures = 0;
+            server.down_since = None;
+            // * Weight the latest measurement by 1/8, as TCP does for its RTT estimate.
+            server.smoothed_rtt = Some(match server.smoothed_rtt {
+                Some(srtt) => (srtt * 7 + rtt) / 8,
+                None => rtt,
+            });
+        });
+    }
+
+    /// Records a lookup to addr that failed, taking the server out of rotation if it keeps failing.
+    pub fn record_failure(&self, addr: SocketAddr) {
+        let max_failures = self.max_failures;
+        self.update(addr, |server| {
+            server.consecutive_failures += 1;
+            if server.consecutive_failures >= max_failures {
+                server.down_since = Some(Instant::now());
+            }
+        });
+    }
+
+    /// Checks every server with probe, which returns the round-trip time if the server is healthy.
+    pub fn health_check(&self, mut probe: impl FnMut(SocketAddr) -> Option<Duration>) {
+        let addrs = self.servers();
+        for addr in addrs {
+            match probe(addr) {
+                Some(rtt) => self.record_success(addr, rtt),
+                None => self.record_failure(addr),
+            }
+        }
+    }
+
+    /// Every server, in the order configured.
+    pub fn servers(&self) -> Vec<SocketAddr> {
+        let state = self.state.lock().unwrap();
+        state.servers.iter().map(|server| server.addr).collect()
+    }
+
+    /// Returns true if addr is in rotation.
+    pub fn is_healthy(&self, addr: SocketAddr) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .servers
+            .iter()
+            .any(|server| server.addr == addr && server.down_since.is_none())
+    }
+
+    fn update(&self, addr: SocketAddr, f: impl FnOnce(&mut Server)) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(server) = state.servers.iter_mut().find(|server| server.addr == addr) {
+            f(server);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        (1..=3)
+            .map(|n| SocketAddr::from(([127, 0, 0, n], 17553)))
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let pool = ServerPool::new(addrs(), Strategy::RoundRobin);
+        let firsts = (0..4).map(|_| pool.candidates()[0]).collect::<Vec<_>>();
+        let a = addrs();
+        assert_eq!(firsts, [a[0], a[1], a[2], a[0]]);
+        assert_eq!(pool.candidates(), [a[1], a[2], a[0]]);
+    }
+
+    #[test]
+    fn failover() {
+        let a = addrs();
+        let pool = ServerPool::new(a.clone(), Strategy::RoundRobin)
+            .with_health_policy(2, Duration::from_secs(60));
+        pool.record_failure(a[0]);
+        assert!(pool.is_healthy(a[0]));
+        pool.record_failure(a[0]);
+        assert!(!pool.is_healthy(a[0]));
+        // * The down server is still a last resort.
+        assert_eq!(pool.candidates(), [a[1], a[2], a[0]]);
+        assert_eq!(pool.candidates(), [a[1], a[2], a[0]]);
+        assert_eq!(pool.candidates(), [a[2], a[1], a[0]]);
+
+        pool.record_success(a[0], Duration::from_millis(1));
+        assert!(pool.is_healthy(a[0]));
+    }
+
+    #[test]
+    fn retry_after_elapses() {
+        let a = addrs();
+        let pool =
+            ServerPool::new(a.clone(), Strategy::RoundRobin).with_health_policy(1, Duration::ZERO);
+        pool.record_failure(a[0]);
+        assert!(!pool.is_healthy(a[0]));
+        assert_eq!(pool.candidates()[0], a[0]);
+    }
+
+    #[test]
+    fn latency_weighted() {
+        let a = addrs();
+        let pool = ServerPool::new(a.clone(), Strategy::LatencyWeighted)
+            .with_health_policy(1, Duration::from_secs(60));
+        pool.health_check(|addr| match addr {
+            addr if addr == a[0] => Some(Duration::from_millis(30)),
+            addr if addr == a[1] => Some(Duration::from_millis(5)),
+            _ => None,
+        });
+        assert_eq!(pool.candidates(), [a[1], a[0], a[2]]);
+
+        // * Slow responses from the fastest server eventually move it behind the others.
+        for _ in 0..20 {
+            pool.record_success(a[1], Duration::from_millis(100));
+        }
+        assert_eq!(pool.candidates()[0], a[0]);
+    }
+
+    #[test]
+    fn empty_pool() {
+        let pool = ServerPool::new(Vec::new(), Strategy::RoundRobin);
+        assert!(pool.candidates().is_empty());
+    }
+}
@@ -1,19 +1,74 @@
+pub mod balance;
+#[cfg(feature = "stub")]
+pub mod stub;
+#[cfg(feature = "rpc")]
+pub mod transport;
+
+#[cfg(feature = "rpc")]
+use rg_resolver_common::{BatchQuery, Qclass, Qtype, ResolvedAddress, RpcError};
+#[cfg(feature = "rpc")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "rpc")]
+use std::fmt::{self, Display};
+#[cfg(feature = "rpc")]
+use std::io;
+#[cfg(feature = "rpc")]
 use std::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(feature = "rpc")]
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(feature = "rpc")]
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Frame(rg_resolver_common::Error),
+    /// The daemon's reply to the handshake couldn't be accepted.
+    Handshake(rg_resolver_common::Error),
+    Json(serde_json::Error),
+    /// The daemon answered the request with an error.
+    Rpc(RpcError),
+    /// The connection closed before the response arrived.
+    Closed,
+}
+
+#[cfg(feature = "rpc")]
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Error::*;
+        match self {
+            Io(e) => write!(f, "connection failed: {}", e),
+            Frame(e) => write!(f, "{}", e),
+            Handshake(e) => write!(f, "{}", e),
+            Json(e) => write!(f, "invalid JSON-RPC message: {}", e),
+            Rpc(e) => write!(f, "resolver error: {}", e),
+            Closed => f.write_str("connection closed before the response arrived"),
+        }
+    }
+}
+
+#[cfg(feature = "rpc")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "rpc")]
 static NEXT_ID: AtomicU32 = AtomicU32::new(1);
 
+#[cfg(feature = "rpc")]
 fn next_id() -> u32 {
     NEXT_ID.fetch_add(1, Ordering::SeqCst)
 }
 
-pub fn hostname_to_address(hostname: String) -> String {
-    let req = HostNameToAddress::new(next_id(), hostname);
-    // Send request to server
-    // Wait for response
-    // Return response
+/// Looks up the addresses of hostname through the daemon on this host.
+///
+/// Connects for just this lookup; use a [transport::Client] to make several.
+#[cfg(feature = "rpc")]
+pub fn hostname_to_address(hostname: String) -> Result<ResolvedAddress> {
+    let mut client =
+        transport::Client::connect(transport::DEFAULT_SERVER, transport::DEFAULT_TIMEOUT)?;
+    client.hostname_to_address(hostname)
 }
 
+#[cfg(feature = "rpc")]
 #[derive(Serialize, Deserialize)]
 struct JsonRpc {
     jsonrpc: String,
@@ -21,12 +76,14 @@ struct JsonRpc {
     method: String,
 }
 
+#[cfg(feature = "rpc")]
 impl JsonRpc {
     fn new(id: u32, method: String) -> JsonRpc {
-        JsonRpc { jsonrpc: String::from("2.0"), id, method }
+        JsonRpc { jsonrpc: String::from(rg_resolver_common::rpc::JSONRPC_VERSION), id, method }
     }
 }
 
+#[cfg(feature = "rpc")]
 #[derive(Serialize, Deserialize)]
 struct HostNameToAddress {
     #[serde(flatten)]
@@ -35,6 +92,7 @@ struct HostNameToAddress {
     params: [String; 1],
 }
 
+#[cfg(feature = "rpc")]
 impl HostNameToAddress {
     const METHOD_NAME: &'static str = "host_name_to_address";
 
@@ -44,6 +102,7 @@ impl HostNameToAddress {
     }
 }
 
+#[cfg(feature = "rpc")]
 #[derive(Serialize, Deserialize)]
 struct AddressToHostname {
     #[serde(flatten)]
@@ -52,6 +111,7 @@ struct AddressToHostname {
     params: [String; 1],
 }
 
+#[cfg(feature = "rpc")]
 impl AddressToHostname {
     const METHOD_NAME: &'static str = "address_to_hostname";
 
@@ -61,40 +121,71 @@ impl AddressToHostname {
     }
 }
 
+#[cfg(feature = "rpc")]
 #[derive(Serialize, Deserialize)]
 struct GeneralLookup {
     #[serde(flatten)]
     jsonrpc: JsonRpc,
 
-    #[serde(flatten)]
     params: GeneralLookupParams,
 }
 
+#[cfg(feature = "rpc")]
 impl GeneralLookup {
     const METHOD_NAME: &'static str = "general_lookup";
 
-    fn new(id: u32, qname: String, qtype: String, qclass: String) -> GeneralLookup {
+    fn new(id: u32, qname: String, qtype: Qtype, qclass: Qclass) -> GeneralLookup {
         let jsonrpc = JsonRpc::new(id, String::from(Self::METHOD_NAME));
         let params = GeneralLookupParams::new(qname, qtype, qclass);
         GeneralLookup { jsonrpc, params }
     }
 }
 
+#[cfg(feature = "rpc")]
 #[derive(Serialize, Deserialize)]
 struct GeneralLookupParams {
     qname: String,
-    qtype: String,
-    qclass: String,
+    qtype: Qtype,
+    qclass: Qclass,
 }
 
+#[cfg(feature = "rpc")]
 impl GeneralLookupParams {
-    fn new(qname: String, qtype: String, qclass: String) -> GeneralLookupParams {
+    fn new(qname: String, qtype: Qtype, qclass: Qclass) -> GeneralLookupParams {
         GeneralLookupParams { qname, qtype, qclass }
     }
 }
 
+#[cfg(feature = "rpc")]
+#[derive(Serialize, Deserialize)]
+struct ResolveBatch {
+    #[serde(flatten)]
+    jsonrpc: JsonRpc,
+
+    params: ResolveBatchParams,
+}
+
+#[cfg(feature = "rpc")]
+impl ResolveBatch {
+    const METHOD_NAME: &'static str = "resolve_batch";
+
+    fn new(id: u32, queries: Vec<BatchQuery>) -> ResolveBatch {
+        let jsonrpc = JsonRpc::new(id, String::from(Self::METHOD_NAME));
+        ResolveBatch { jsonrpc, params: ResolveBatchParams { queries } }
+    }
+}
+
+#[cfg(feature = "rpc")]
+#[derive(Serialize, Deserialize)]
+struct ResolveBatchParams {
+    queries: Vec<BatchQuery>,
+}
+
+/// A response before its result is read as the type the method returns.
+#[cfg(feature = "rpc")]
+type RpcResponse = rg_resolver_common::Response<serde_json::Value>;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "rpc"))]
 mod tests {
     use super::*;
 
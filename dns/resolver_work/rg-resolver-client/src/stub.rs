@@ -0,0 +1,305 @@
+//! Blocking address lookups through the local resolver, using only std.
+//!
+//! For small utilities and build scripts that just need an address and don't want the rest of
+//! the client's dependencies. Queries are plain DNS over UDP.
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the local resolver listens.
+pub const DEFAULT_SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NAME_ERROR: u8 = 3;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// Looks up the IPv4 addresses of name through the local resolver.
+/// A name that doesn't exist has no addresses.
+pub fn resolve_a(name: &str) -> io::Result<Vec<Ipv4Addr>> {
+    resolve_a_with(DEFAULT_SERVER, name, DEFAULT_TIMEOUT)
+}
+
+/// Looks up the IPv6 addresses of name through the local resolver.
+/// A name that doesn't exist has no addresses.
+pub fn resolve_aaaa(name: &str) -> io::Result<Vec<Ipv6Addr>> {
+    resolve_aaaa_with(DEFAULT_SERVER, name, DEFAULT_TIMEOUT)
+}
+
+pub fn resolve_a_with(
+    server: SocketAddr,
+    name: &str,
+    timeout: Duration,
+) -> io::Result<Vec<Ipv4Addr>> {
+    let addrs = lookup(server, name, TYPE_A, timeout)?
+        .into_iter()
+        .filter_map(|data| <[u8; 4]>::try_from(data).ok().map(Ipv4Addr::from))
+        .collect();
+    Ok(addrs)
+}
+
+pub fn resolve_aaaa_with(
+    server: SocketAddr,
+    name: &str,
+    timeout: Duration,
+) -> io::Result<Vec<Ipv6Addr>> {
+    let addrs = lookup(server, name, TYPE_AAAA, timeout)?
+        .into_iter()
+        .filter_map(|data| <[u8; 16]>::try_from(data).ok().map(Ipv6Addr::from))
+        .collect();
+    Ok(addrs)
+}
+
+/// Queries server for the records of type qtype at name, returning their data.
+fn lookup(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> io::Result<Vec<Vec<u8>>> {
+    let id = next_id();
+    let query = build_query(id, name, qtype)?;
+
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let sock = UdpSocket::bind(bind_addr)?;
+    sock.connect(server)?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.send(&query)?;
+
+    let mut buf = [0_u8; 1232];
+    loop {
+        let size = sock.recv(&mut buf)?;
+        let response = &buf[..size];
+        // Ignore datagrams that don't answer this query.
+        if response.len() < 12 || u16::from_be_bytes([response[0], response[1]]) != id {
+            continue;
+        }
+        return parse_response(response, qtype);
+    }
+}
+
+fn next_id() -> u16 {
+    // Start from the clock so IDs aren't predictable across runs.
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u16;
+    seed.wrapping_add(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+fn build_query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(12 + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired.
+    query.extend_from_slice(&[0x01, 0x00]);
+    // One question, no records.
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        if label.len() > 63 || !label.is_ascii() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid label {label:?} in {name}"),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn parse_response(msg: &[u8], qtype: u16) -> io::Result<Vec<Vec<u8>>> {
+    let invalid =
+        |what: &str| io::Error::new(ErrorKind::InvalidData, format!("invalid response: {what}"));
+    let flags = u16::from_be_bytes([msg[2], msg[3]]);
+    if flags & 0x8000 == 0 {
+        return Err(invalid("not a response"));
+    }
+    if flags & 0x0200 != 0 {
+        return Err(invalid("truncated"));
+    }
+    match (flags & 0x000f) as u8 {
+        0 => {}
+        RCODE_NAME_ERROR => return Ok(Vec::new()),
+        rcode => {
+            return Err(io::Error::other(format!(
+                "resolver responded with error {rcode}"
+            )))
+        }
+    }
+    let question_count = u16::from_be_bytes([msg[4], msg[5]]);
+    let answer_count = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut offset = 12;
+    for _ in 0..question_count {
+        offset = skip_name(msg, offset).ok_or_else(|| invalid("bad question name"))? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..answer_count {
+        offset = skip_name(msg, offset).ok_or_else(|| invalid("bad record name"))?;
+        let fixed = msg
+            .get(offset..offset + 10)
+            .ok_or_else(|| invalid("incomplete record"))?;
+        let r#type = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        offset += 10;
+        let data = msg
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("incomplete record data"))?;
+        offset += len;
+        // Any CNAMEs leading to the addresses are skipped.
+        if r#type == qtype && class == CLASS_IN {
+            records.push(data.to_vec());
+        }
+    }
+    Ok(records)
+}
+
+/// Returns the offset just past the name starting at offset, or None if the name is malformed
+/// once any compression pointers are followed.
+fn skip_name(msg: &[u8], offset: usize) -> Option<usize> {
+    let mut end = None;
+    let mut pos = offset;
+    // The length so far, counting each label's length byte.
+    let mut name_len = 0;
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            // The limit of 255 counts the root label's length byte too.
+            0 if name_len < 255 => return Some(end.unwrap_or(pos + 1)),
+            0 => return None,
+            len if len & 0xc0 == 0xc0 => {
+                let target = (u16::from_be_bytes([len, *msg.get(pos + 1)?]) & 0x3fff) as usize;
+                // Pointers only go back, so following them can't loop.
+                if target >= pos {
+                    return None;
+                }
+                // A pointer ends the name where it appears.
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            // Other label types aren't in use.
+            len if len & 0xc0 != 0 => return None,
+            len => {
+                msg.get(pos + 1..pos + 1 + len as usize)?;
+                name_len += 1 + len as usize;
+                pos += 1 + len as usize;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Answers one query with the given records, each as (type, data), in the same message.
+    fn fake_resolver(rcode: u8, records: Vec<(u16, Vec<u8>)>) -> SocketAddr {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0_u8; 512];
+            let (size, from) = sock.recv_from(&mut buf).unwrap();
+            let mut response = buf[..size].to_vec();
+            response[2] |= 0x80;
+            response[3] = 0x80 | rcode;
+            response[7] = records.len() as u8;
+            for (r#type, data) in records {
+                // Point back at the question name.
+                response.extend_from_slice(&[0xc0, 12]);
+                response.extend_from_slice(&r#type.to_be_bytes());
+                response.extend_from_slice(&CLASS_IN.to_be_bytes());
+                response.extend_from_slice(&300_u32.to_be_bytes());
+                response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                response.extend_from_slice(&data);
+            }
+            sock.send_to(&response, from).unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn resolves_a() {
+        let server = fake_resolver(
+            0,
+            vec![
+                (5, vec![0]),
+                (TYPE_A, vec![192, 0, 2, 1]),
+                (TYPE_A, vec![192, 0, 2, 2]),
+            ],
+        );
+        let addrs = resolve_a_with(server, "example.com.", DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(
+            addrs,
+            [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn resolves_aaaa() {
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let server = fake_resolver(0, vec![(TYPE_AAAA, addr.octets().to_vec())]);
+        let addrs = resolve_aaaa_with(server, "example.com", DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(addrs, [addr]);
+    }
+
+    #[test]
+    fn nxdomain_has_no_addresses() {
+        let server = fake_resolver(RCODE_NAME_ERROR, vec![]);
+        assert!(resolve_a_with(server, "nope.example.", DEFAULT_TIMEOUT)
+            .unwrap()
+            .is_empty());
+
+        let server = fake_resolver(2, vec![]);
+        assert!(resolve_a_with(server, "example.com.", DEFAULT_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn parses_test_vectors() {
+        use rg_resolver_common::vectors::{self, Expected};
+
+        for vector in vectors::all() {
+            let parsed = parse_response(&vector.message, vectors::TYPE_A);
+            match vector.expected {
+                Expected::Malformed => {
+                    assert!(parsed.is_err(), "{} should be rejected", vector.name)
+                }
+                Expected::Answers(answers) => {
+                    let addresses = answers
+                        .into_iter()
+                        .filter(|answer| answer.rtype == vectors::TYPE_A)
+                        .map(|answer| answer.rdata)
+                        .collect::<Vec<_>>();
+                    assert_eq!(parsed.unwrap(), addresses, "{}", vector.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let label = "a".repeat(64);
+        assert_eq!(
+            build_query(1, &label, TYPE_A).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(build_query(1, "bücher.example.", TYPE_A).is_err());
+    }
+}
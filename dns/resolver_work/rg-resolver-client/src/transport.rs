@@ -0,0 +1,724 @@
+//! Connections to the resolver daemon, over which JSON-RPC requests are sent.
+//!
+//! Each request and response is one frame; see [FrameCodec]. A connection starts with a
+//! handshake agreeing on the protocol version and capabilities. Responses are matched to
+//! requests by id, and the answers of a batch to its request by the batch's id.
+
+use crate::{
+    AddressToHostname, Error, GeneralLookup, HostNameToAddress, ResolveBatch, Result, RpcResponse,
+};
+use rg_resolver_common::batch::BATCH_RESULT;
+use rg_resolver_common::handshake::{ClientHello, ServerHello};
+use rg_resolver_common::rpc::Notification;
+use rg_resolver_common::{
+    BatchQuery, BatchResult, Capabilities, FrameCodec, Qclass, Qtype, Record, ResolvedAddress,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Where the resolver daemon listens.
+pub const DEFAULT_SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 17553);
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The name a client gives the daemon in the handshake unless given another.
+pub const DEFAULT_CLIENT_NAME: &str = concat!("rg-resolver-client/", env!("CARGO_PKG_VERSION"));
+
+/// What this client supports, offered to the daemon in the handshake.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        // * The types with a typed representation in RecordData.
+        record_types: [
+            "A", "NS", "CNAME", "SOA", "PTR", "HINFO", "MX", "TXT", "AAAA",
+        ]
+        .map(String::from)
+        .to_vec(),
+        batching: true,
+        watch: false,
+        compression: true,
+        // * general_lookup returns just the records.
+        verbose: false,
+    }
+}
+
+fn codec() -> FrameCodec {
+    // * Nothing is compressed until a handshake negotiates it.
+    FrameCodec::new(&Capabilities::default())
+}
+
+fn encode(codec: &FrameCodec, request: &impl Serialize) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(request).map_err(Error::Json)?;
+    codec.encode(&payload).map_err(Error::Frame)
+}
+
+/// Checks the daemon's reply to hello, returning the capabilities both sides agreed on.
+fn accept(reply: &[u8], hello: &ClientHello) -> Result<Capabilities> {
+    match serde_json::from_slice::<ServerHello>(reply) {
+        Ok(reply) => reply.accept(hello).map_err(Error::Handshake),
+        // * A daemon that won't speak any version this client does replies with an error.
+        Err(e) => match serde_json::from_slice::<RpcResponse>(reply) {
+            Ok(RpcResponse {
+                error: Some(error), ..
+            }) => Err(Error::Rpc(error)),
+            _ => Err(Error::Json(e)),
+        },
+    }
+}
+
+/// The result of response, read as the type its method returns.
+fn result<T: DeserializeOwned>(response: RpcResponse) -> Result<T> {
+    let result = response.into_result().map_err(Error::Rpc)?;
+    serde_json::from_value(result).map_err(Error::Json)
+}
+
+/// A message from the daemon.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Message {
+    /// Sent while a request runs, e.g. the answer to one query of a batch.
+    Notification(Notification<Value>),
+    Response(RpcResponse),
+}
+
+/// The batch answer notification holds, if it is one.
+fn batch_result(notification: Notification<Value>) -> Option<BatchResult> {
+    if notification.method != BATCH_RESULT {
+        return None;
+    }
+    serde_json::from_value(notification.params).ok()
+}
+
+/// A blocking connection to the resolver daemon, sending one request at a time.
+pub struct Client {
+    stream: TcpStream,
+    codec: FrameCodec,
+    buf: Vec<u8>,
+    capabilities: Capabilities,
+}
+
+impl Client {
+    /// Connects to the daemon at addr, failing any connect, send, or receive that takes
+    /// longer than timeout.
+    pub fn connect(addr: SocketAddr, timeout: Duration) -> Result<Client> {
+        Client::connect_as(addr, timeout, String::from(DEFAULT_CLIENT_NAME))
+    }
+
+    /// Connects like [Client::connect], naming the client client_name in the daemon's logs.
+    pub fn connect_as(addr: SocketAddr, timeout: Duration, client_name: String) -> Result<Client> {
+        let stream = TcpStream::connect_timeout(&addr, timeout).map_err(Error::Io)?;
+        stream.set_read_timeout(Some(timeout)).map_err(Error::Io)?;
+        stream.set_write_timeout(Some(timeout)).map_err(Error::Io)?;
+        let mut client = Client {
+            stream,
+            codec: codec(),
+            buf: Vec::new(),
+            capabilities: Capabilities::default(),
+        };
+
+        let hello = ClientHello::new(client_name, capabilities());
+        let frame = encode(&client.codec, &hello)?;
+        client.stream.write_all(&frame).map_err(Error::Io)?;
+        let reply = client.recv_frame()?;
+        client.capabilities = accept(&reply, &hello)?;
+        client.codec = FrameCodec::new(&client.capabilities);
+        Ok(client)
+    }
+
+    /// What both this client and the daemon support.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// The addresses of hostname, after any CNAMEs leading to them.
+    pub fn hostname_to_address(&mut self, hostname: String) -> Result<ResolvedAddress> {
+        let id = crate::next_id();
+        self.call(id, &HostNameToAddress::new(id, hostname))
+    }
+
+    /// The PTR records of address.
+    pub fn address_to_hostname(&mut self, address: String) -> Result<Vec<Record>> {
+        let id = crate::next_id();
+        self.call(id, &AddressToHostname::new(id, address))
+    }
+
+    /// The records of type qtype and class qclass at qname.
+    pub fn general_lookup(
+        &mut self,
+        qname: String,
+        qtype: Qtype,
+        qclass: Qclass,
+    ) -> Result<Vec<Record>> {
+        let id = crate::next_id();
+        self.call(id, &GeneralLookup::new(id, qname, qtype, qclass))
+    }
+
+    /// Resolves every query at once, calling on_result with each answer as it arrives, in the
+    /// order they're found. Returns how many queries there were.
+    ///
+    /// The timeout applies to each answer rather than the whole batch.
+    pub fn resolve_batch(
+        &mut self,
+        queries: Vec<BatchQuery>,
+        on_result: impl FnMut(BatchResult),
+    ) -> Result<usize> {
+        let id = crate::next_id();
+        self.call_streaming(id, &ResolveBatch::new(id, queries), on_result)
+    }
+
+    fn call<T: DeserializeOwned>(&mut self, id: u32, request: &impl Serialize) -> Result<T> {
+        self.call_streaming(id, request, |_| {})
+    }
+
+    fn call_streaming<T: DeserializeOwned>(
+        &mut self,
+        id: u32,
+        request: &impl Serialize,
+        mut on_result: impl FnMut(BatchResult),
+    ) -> Result<T> {
+        let frame = encode(&self.codec, request)?;
+        self.stream.write_all(&frame).map_err(Error::Io)?;
+        loop {
+            let response = match self.recv()? {
+                Message::Notification(notification) => {
+                    // * Answers to a batch that timed out earlier are dropped, like late responses.
+                    match batch_result(notification) {
+                        Some(result) if result.batch == id => on_result(result),
+                        _ => {}
+                    }
+                    continue;
+                }
+                Message::Response(response) => response,
+            };
+            match response.id {
+                Some(response_id) if response_id == id => return result(response),
+                // * The daemon couldn't read the id of the request, and this is the only one.
+                None if response.error.is_some() => return result(response),
+                // * A late response to a request that timed out earlier.
+                _ => continue,
+            }
+        }
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        let payload = self.recv_frame()?;
+        serde_json::from_slice(&payload).map_err(Error::Json)
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some((payload, used)) = self.codec.decode(&self.buf).map_err(Error::Frame)? {
+                self.buf.drain(..used);
+                return Ok(payload);
+            }
+            let mut chunk = [0_u8; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(Error::Closed),
+                Ok(size) => self.buf.extend_from_slice(&chunk[..size]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use self::nonblocking::AsyncClient;
+
+#[cfg(feature = "async")]
+mod nonblocking {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::TcpStream;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio::task::JoinHandle;
+    use tokio_util::codec::{Framed, FramedRead, FramedWrite};
+
+    type Pending = Arc<Mutex<Waiting>>;
+
+    /// The requests waiting for their responses, and the batches among them for their answers.
+    #[derive(Default)]
+    struct Waiting {
+        responses: HashMap<u32, oneshot::Sender<RpcResponse>>,
+        batches: HashMap<u32, mpsc::UnboundedSender<BatchResult>>,
+    }
+
+    /// An async connection to the resolver daemon. Any number of requests can be in flight
+    /// at once; each is answered as its response arrives.
+    pub struct AsyncClient {
+        writer: tokio::sync::Mutex<FramedWrite<OwnedWriteHalf, FrameCodec>>,
+        capabilities: Capabilities,
+        pending: Pending,
+        timeout: Duration,
+        reader: JoinHandle<()>,
+    }
+
+    impl AsyncClient {
+        /// Connects to the daemon at addr, failing the connect or any request that takes
+        /// longer than timeout.
+        pub async fn connect(addr: SocketAddr, timeout: Duration) -> Result<AsyncClient> {
+            AsyncClient::connect_as(addr, timeout, String::from(DEFAULT_CLIENT_NAME)).await
+        }
+
+        /// Connects like [AsyncClient::connect], naming the client client_name in the
+        /// daemon's logs.
+        pub async fn connect_as(
+            addr: SocketAddr,
+            timeout: Duration,
+            client_name: String,
+        ) -> Result<AsyncClient> {
+            let hello = ClientHello::new(client_name, capabilities());
+            let (connection, capabilities) = tokio::time::timeout(timeout, async {
+                let stream = TcpStream::connect(addr).await.map_err(Error::Io)?;
+                let mut connection = Framed::new(stream, codec());
+                let capabilities = handshake(&mut connection, &hello).await?;
+                Ok((connection, capabilities))
+            })
+            .await
+            .map_err(|_| Error::Io(ErrorKind::TimedOut.into()))??;
+
+            let codec = FrameCodec::new(&capabilities);
+            let parts = connection.into_parts();
+            let (reader, writer) = parts.io.into_split();
+            let mut reader = FramedRead::new(reader, codec.clone());
+            // * Anything the daemon sent after its reply has already been read.
+            *reader.read_buffer_mut() = parts.read_buf;
+            let pending = Pending::default();
+            let reader = tokio::spawn(read_responses(reader, pending.clone()));
+            Ok(AsyncClient {
+                writer: tokio::sync::Mutex::new(FramedWrite::new(writer, codec)),
+                capabilities,
+                pending,
+                timeout,
+                reader,
+            })
+        }
+
+        /// What both this client and the daemon support.
+        pub fn capabilities(&self) -> &Capabilities {
+            &self.capabilities
+        }
+
+        /// The addresses of hostname, after any CNAMEs leading to them.
+        pub async fn hostname_to_address(&self, hostname: String) -> Result<ResolvedAddress> {
+            let id = crate::next_id();
+            self.call(id, &HostNameToAddress::new(id, hostname)).await
+        }
+
+        /// The PTR records of address.
+        pub async fn address_to_hostname(&self, address: String) -> Result<Vec<Record>> {
+            let id = crate::next_id();
+            self.call(id, &AddressToHostname::new(id, address)).await
+        }
+
+        /// The records of type qtype and class qclass at qname.
+        pub async fn general_lookup(
+            &self,
+            qname: String,
+            qtype: Qtype,
+            qclass: Qclass,
+        ) -> Result<Vec<Record>> {
+            let id = crate::next_id();
+            self.call(id, &GeneralLookup::new(id, qname, qtype, qclass))
+                .await
+        }
+
+        /// Resolves every query at once, calling on_result with each answer as it arrives, in
+        /// the order they're found. Returns how many queries there were.
+        ///
+        /// The timeout applies to the whole batch.
+        pub async fn resolve_batch(
+            &self,
+            queries: Vec<BatchQuery>,
+            on_result: impl FnMut(BatchResult),
+        ) -> Result<usize> {
+            let id = crate::next_id();
+            self.call_streaming(id, &ResolveBatch::new(id, queries), on_result)
+                .await
+        }
+
+        async fn call<T: DeserializeOwned>(&self, id: u32, request: &impl Serialize) -> Result<T> {
+            self.call_streaming(id, request, |_| {}).await
+        }
+
+        async fn call_streaming<T: DeserializeOwned>(
+            &self,
+            id: u32,
+            request: &impl Serialize,
+            mut on_result: impl FnMut(BatchResult),
+        ) -> Result<T> {
+            let payload = serde_json::to_vec(request).map_err(Error::Json)?;
+            let (tx, mut rx) = oneshot::channel();
+            let (results, mut streamed) = mpsc::unbounded_channel();
+            {
+                let mut pending = self.pending.lock().unwrap();
+                pending.responses.insert(id, tx);
+                pending.batches.insert(id, results);
+            }
+            // * The request is forgotten however this returns, so a late response is dropped.
+            let _forget = Forget {
+                pending: &self.pending,
+                id,
+            };
+
+            let exchange = async {
+                self.writer
+                    .lock()
+                    .await
+                    .send(payload.as_slice())
+                    .await
+                    .map_err(connection_error)?;
+                let response = loop {
+                    tokio::select! {
+                        biased;
+                        Some(result) = streamed.recv() => on_result(result),
+                        response = &mut rx => break response.map_err(|_| Error::Closed)?,
+                    }
+                };
+                // * Every answer is sent before the response, so they've all arrived by now.
+                while let Ok(result) = streamed.try_recv() {
+                    on_result(result);
+                }
+                result(response)
+            };
+            tokio::time::timeout(self.timeout, exchange)
+                .await
+                .unwrap_or(Err(Error::Io(ErrorKind::TimedOut.into())))
+        }
+    }
+
+    impl Drop for AsyncClient {
+        fn drop(&mut self) {
+            self.reader.abort();
+        }
+    }
+
+    /// Sends hello and reads the daemon's reply.
+    async fn handshake(
+        connection: &mut Framed<TcpStream, FrameCodec>,
+        hello: &ClientHello,
+    ) -> Result<Capabilities> {
+        let payload = serde_json::to_vec(hello).map_err(Error::Json)?;
+        connection
+            .send(payload.as_slice())
+            .await
+            .map_err(connection_error)?;
+        match connection.next().await {
+            Some(reply) => accept(&reply.map_err(connection_error)?, hello),
+            None => Err(Error::Closed),
+        }
+    }
+
+    /// Reports failing to read or write the connection as an I/O error rather than a bad frame.
+    fn connection_error(e: rg_resolver_common::Error) -> Error {
+        match e {
+            rg_resolver_common::Error::Io(e) => Error::Io(e),
+            e => Error::Frame(e),
+        }
+    }
+
+    struct Forget<'a> {
+        pending: &'a Pending,
+        id: u32,
+    }
+
+    impl Drop for Forget<'_> {
+        fn drop(&mut self) {
+            let mut pending = self.pending.lock().unwrap();
+            pending.responses.remove(&self.id);
+            pending.batches.remove(&self.id);
+        }
+    }
+
+    /// Hands each response and batch answer to the request waiting for it until the
+    /// connection fails, then fails every request still waiting.
+    async fn read_responses(mut reader: FramedRead<OwnedReadHalf, FrameCodec>, pending: Pending) {
+        // * A frame that can't be read ends the connection like a read failing does.
+        while let Some(Ok(payload)) = reader.next().await {
+            // * Messages that can't be matched to a request have no one to go to.
+            let response = match serde_json::from_slice::<Message>(&payload) {
+                Ok(Message::Response(response)) => response,
+                Ok(Message::Notification(notification)) => {
+                    if let Some(result) = batch_result(notification) {
+                        if let Some(tx) = pending.lock().unwrap().batches.get(&result.batch) {
+                            let _ = tx.send(result);
+                        }
+                    }
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            let waiting = response
+                .id
+                .and_then(|id| pending.lock().unwrap().responses.remove(&id));
+            if let Some(tx) = waiting {
+                let _ = tx.send(response);
+            }
+        }
+        // * Dropping the senders fails every request still waiting with Closed.
+        let mut pending = pending.lock().unwrap();
+        pending.responses.clear();
+        pending.batches.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rg_resolver_common::{rpc, BatchAnswer};
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers the requests on one connection with reply, which is given each request and
+    /// returns the responses to send, in order. The handshake is accepted without compression.
+    fn fake_daemon(reply: impl Fn(Value) -> Vec<Value> + Send + 'static) -> SocketAddr {
+        fake_daemon_greeting(
+            |hello| {
+                let server = Capabilities {
+                    batching: true,
+                    ..Capabilities::default()
+                };
+                serde_json::to_value(ServerHello::negotiate(&hello, &server).unwrap()).unwrap()
+            },
+            reply,
+        )
+    }
+
+    /// Like [fake_daemon], replying to the client's hello with greet.
+    fn fake_daemon_greeting(
+        greet: impl Fn(ClientHello) -> Value + Send + 'static,
+        reply: impl Fn(Value) -> Vec<Value> + Send + 'static,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let codec = codec();
+            let mut buf = Vec::new();
+            let mut greeted = false;
+            loop {
+                while let Some((payload, used)) = codec.decode(&buf).unwrap() {
+                    buf.drain(..used);
+                    let responses = if greeted {
+                        reply(serde_json::from_slice(&payload).unwrap())
+                    } else {
+                        greeted = true;
+                        vec![greet(serde_json::from_slice(&payload).unwrap())]
+                    };
+                    for response in responses {
+                        stream
+                            .write_all(&encode(&codec, &response).unwrap())
+                            .unwrap();
+                    }
+                }
+                let mut chunk = [0_u8; 4096];
+                match stream.read(&mut chunk).unwrap() {
+                    0 => return,
+                    size => buf.extend_from_slice(&chunk[..size]),
+                }
+            }
+        });
+        addr
+    }
+
+    fn resolved(name: &str) -> Value {
+        json!({
+            "name": name,
+            "canonical_name": name,
+            "addresses": [{ "address": "192.0.2.1", "ttl": 300 }],
+            "authoritative": false,
+            "from_cache": true,
+        })
+    }
+
+    #[test]
+    fn blocking_lookups() {
+        let server = fake_daemon(|request| {
+            let id = request["id"].clone();
+            let response = match request["method"].as_str().unwrap() {
+                "host_name_to_address" => {
+                    // * A stale response to some earlier request comes first.
+                    let stale = json!({ "jsonrpc": "2.0", "id": 0, "result": [] });
+                    let name = request["params"][0].clone();
+                    let fresh = json!({ "jsonrpc": "2.0", "id": id, "result": resolved(name.as_str().unwrap()) });
+                    return vec![stale, fresh];
+                }
+                "general_lookup" => {
+                    assert_eq!(request["params"]["qtype"], "MX");
+                    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32000, "message": "unreachable" } })
+                }
+                _ => {
+                    json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "bad" } })
+                }
+            };
+            vec![response]
+        });
+
+        let mut client = Client::connect(server, DEFAULT_TIMEOUT).unwrap();
+        let resolved = client
+            .hostname_to_address(String::from("example.com."))
+            .unwrap();
+        assert_eq!(resolved.name, "example.com.");
+        assert_eq!(
+            resolved.ip_addrs().collect::<Vec<_>>(),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+        assert_eq!(resolved.addresses[0].ttl, 300);
+        assert!(resolved.from_cache);
+
+        let error = client
+            .general_lookup(String::from("example.com."), Qtype::MX, Qclass::IN)
+            .unwrap_err();
+        assert!(matches!(error, Error::Rpc(e) if e.code == rpc::LOOKUP_FAILED));
+
+        let error = client
+            .address_to_hostname(String::from("192.0.2.1"))
+            .unwrap_err();
+        assert!(matches!(error, Error::Rpc(e) if e.code == rpc::PARSE_ERROR));
+    }
+
+    /// Answers a resolve_batch request with each query's answer, last query first, then the
+    /// response.
+    fn batch_reply(request: Value) -> Vec<Value> {
+        let id = request["id"].clone();
+        let queries = request["params"]["queries"].as_array().unwrap().clone();
+        // * An answer to some earlier batch comes first.
+        let stale = json!({ "jsonrpc": "2.0", "method": "batch_result",
+                            "params": { "batch": 0, "index": 0, "answer": { "records": [] } } });
+        let mut messages = vec![stale];
+        for (index, query) in queries.iter().enumerate().rev() {
+            let answer = match query.get("qtype") {
+                None => json!({ "addresses": resolved(query["qname"].as_str().unwrap()) }),
+                Some(_) => json!({ "error": { "code": -32000, "message": "unreachable" } }),
+            };
+            let params = json!({ "batch": id, "index": index, "answer": answer });
+            messages.push(json!({ "jsonrpc": "2.0", "method": "batch_result", "params": params }));
+        }
+        messages.push(json!({ "jsonrpc": "2.0", "id": id, "result": queries.len() }));
+        messages
+    }
+
+    fn batch_queries() -> Vec<BatchQuery> {
+        vec![
+            BatchQuery::addresses("first.example."),
+            BatchQuery::records("second.example.", Qtype::MX),
+        ]
+    }
+
+    #[test]
+    fn blocking_batch() {
+        let server = fake_daemon(batch_reply);
+        let mut client = Client::connect(server, DEFAULT_TIMEOUT).unwrap();
+        let mut results = Vec::new();
+        let count = client
+            .resolve_batch(batch_queries(), |result| results.push(result))
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].index, 1);
+        assert!(
+            matches!(&results[0].answer, BatchAnswer::Error(e) if e.code == rpc::LOOKUP_FAILED)
+        );
+        assert!(
+            matches!(&results[1].answer, BatchAnswer::Addresses(resolved) if resolved.name == "first.example.")
+        );
+    }
+
+    #[test]
+    fn handshake() {
+        let server = fake_daemon_greeting(
+            |hello| {
+                assert_eq!(hello.client_name, "test-client");
+                assert!(hello.capabilities.supports_type("MX"));
+                // * The daemon claims more than the client asked for.
+                json!({ "version": hello.version, "capabilities": { "batching": true, "watch": true } })
+            },
+            |_| Vec::new(),
+        );
+        let client =
+            Client::connect_as(server, DEFAULT_TIMEOUT, String::from("test-client")).unwrap();
+        assert!(client.capabilities().batching);
+        assert!(!client.capabilities().watch);
+        assert!(!client.capabilities().compression);
+
+        let server = fake_daemon_greeting(
+            |_| json!({ "jsonrpc": "2.0", "id": null, "error": { "code": rpc::UNSUPPORTED_VERSION, "message": "too old" } }),
+            |_| Vec::new(),
+        );
+        assert!(matches!(
+            Client::connect(server, DEFAULT_TIMEOUT),
+            Err(Error::Rpc(e)) if e.code == rpc::UNSUPPORTED_VERSION
+        ));
+
+        let server = fake_daemon_greeting(
+            |_| json!({ "version": 0, "capabilities": {} }),
+            |_| Vec::new(),
+        );
+        assert!(matches!(
+            Client::connect(server, DEFAULT_TIMEOUT),
+            Err(Error::Handshake(_))
+        ));
+    }
+
+    #[test]
+    fn blocking_timeout() {
+        let server = fake_daemon(|_| Vec::new());
+        let mut client = Client::connect(server, Duration::from_millis(200)).unwrap();
+        // * The daemon never answers, so the read times out.
+        assert!(matches!(
+            client.hostname_to_address(String::from("example.com.")),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_lookups_out_of_order() {
+        use std::sync::Mutex;
+
+        // * The first request is answered only once the second arrives, and after it.
+        let held = Mutex::new(None);
+        let server = fake_daemon(move |request| {
+            let name = request["params"][0].as_str().unwrap().to_string();
+            let response =
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": resolved(&name) });
+            let mut held = held.lock().unwrap();
+            match held.take() {
+                None => {
+                    *held = Some(response);
+                    Vec::new()
+                }
+                Some(first) => vec![response, first],
+            }
+        });
+
+        let client = AsyncClient::connect(server, DEFAULT_TIMEOUT).await.unwrap();
+        let (first, second) = tokio::join!(
+            client.hostname_to_address(String::from("first.example.")),
+            client.hostname_to_address(String::from("second.example.")),
+        );
+        assert_eq!(first.unwrap().name, "first.example.");
+        assert_eq!(second.unwrap().name, "second.example.");
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_batch() {
+        let server = fake_daemon(batch_reply);
+        let client = AsyncClient::connect(server, DEFAULT_TIMEOUT).await.unwrap();
+        let mut indexes = Vec::new();
+        let count = client
+            .resolve_batch(batch_queries(), |result| indexes.push(result.index))
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(indexes, [1, 0]);
+    }
+}
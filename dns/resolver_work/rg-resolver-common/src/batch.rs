@@ -0,0 +1,104 @@
+use crate::{Qtype, Record, ResolvedAddress, RpcError};
+use serde::{Deserialize, Serialize};
+
+/// The most queries one resolve_batch request may hold.
+pub const MAX_BATCH_SIZE: usize = 1024;
+
+/// The method of the notifications a resolve_batch request's answers are streamed in.
+pub const BATCH_RESULT: &str = "batch_result";
+
+/// One name to resolve in a batch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchQuery {
+    pub qname: String,
+    /// The type of records to look up, or None to resolve the name's addresses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qtype: Option<Qtype>,
+}
+
+impl BatchQuery {
+    /// Resolves the addresses of qname, as host_name_to_address does.
+    pub fn addresses(qname: impl Into<String>) -> BatchQuery {
+        BatchQuery {
+            qname: qname.into(),
+            qtype: None,
+        }
+    }
+
+    /// Looks up the records of type qtype at qname, as general_lookup does.
+    pub fn records(qname: impl Into<String>, qtype: Qtype) -> BatchQuery {
+        BatchQuery {
+            qname: qname.into(),
+            qtype: Some(qtype),
+        }
+    }
+}
+
+/// The answer to one query of a batch, sent as soon as it's resolved.
+///
+/// Answers arrive in the order they complete, not the order the queries were sent in; index
+/// is the position of the query in the request.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// The id of the resolve_batch request.
+    pub batch: u32,
+    pub index: usize,
+    pub answer: BatchAnswer,
+}
+
+/// Serialized as e.g. `{ "records": [...] }`, named by which kind of answer it is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchAnswer {
+    /// The answer to a query without a type.
+    Addresses(ResolvedAddress),
+    /// The answer to a query with a type.
+    Records(Vec<Record>),
+    /// Why this query failed. The other queries of the batch are unaffected.
+    Error(RpcError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RecordData;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn serde() {
+        let queries = vec![
+            BatchQuery::addresses("example.com."),
+            BatchQuery::records("example.com.", Qtype::MX),
+        ];
+        let json = serde_json::to_value(&queries).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{ "qname": "example.com." }, { "qname": "example.com.", "qtype": "MX" }])
+        );
+        assert_eq!(
+            serde_json::from_value::<Vec<BatchQuery>>(json).unwrap(),
+            queries
+        );
+
+        let result = BatchResult {
+            batch: 9,
+            index: 1,
+            answer: BatchAnswer::Records(vec![Record {
+                name: String::from("example.com."),
+                ttl: 60,
+                data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            }]),
+        };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["answer"]["records"][0]["data"], "192.0.2.1");
+        assert_eq!(serde_json::from_value::<BatchResult>(json).unwrap(), result);
+
+        let json = serde_json::json!({
+            "batch": 9,
+            "index": 0,
+            "answer": { "error": { "code": -32000, "message": "unreachable" } }
+        });
+        let result: BatchResult = serde_json::from_value(json).unwrap();
+        assert!(matches!(result.answer, BatchAnswer::Error(e) if e.code == -32000));
+    }
+}
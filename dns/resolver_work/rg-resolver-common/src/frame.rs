@@ -0,0 +1,285 @@
+use crate::{Capabilities, Error, Result};
+use std::fmt::{self, Display};
+use std::io::Read;
+
+/// The length of a frame's header: the payload length as a big-endian u32, then the flags.
+pub const HEADER_LEN: usize = 5;
+/// The longest payload, compressed or not, either side will accept.
+pub const MAX_PAYLOAD_LEN: usize = 16 * 1024 * 1024;
+/// Payloads shorter than this aren't worth compressing.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// The payload is compressed with zstd.
+const FLAG_COMPRESSED: u8 = 0x01;
+
+/// Splits the byte stream of a connection into messages, compressing large ones if both sides
+/// negotiated compression.
+///
+/// Each frame is a 5 byte header followed by the payload. The header holds the payload length
+/// and flags saying how the payload is encoded.
+#[derive(Clone, Debug)]
+pub struct FrameCodec {
+    compression: bool,
+    threshold: usize,
+}
+
+impl FrameCodec {
+    /// A codec for a connection with the negotiated capabilities.
+    pub fn new(negotiated: &Capabilities) -> FrameCodec {
+        FrameCodec {
+            compression: negotiated.compression,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+
+    /// Only compress payloads at least threshold bytes long.
+    pub fn with_threshold(mut self, threshold: usize) -> FrameCodec {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Encodes payload as a frame, compressing it if that was negotiated, it's long enough,
+    /// and compressing makes it shorter.
+    pub fn encode(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut flags = 0;
+        let mut compressed = None;
+        if self.compression && payload.len() >= self.threshold {
+            let shorter = zstd::bulk::compress(payload, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .map_err(|e| Error::Frame(FrameError::Corrupt(e.to_string())))?;
+            if shorter.len() < payload.len() {
+                flags |= FLAG_COMPRESSED;
+                compressed = Some(shorter);
+            }
+        }
+        let body = compressed.as_deref().unwrap_or(payload);
+        if body.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::Frame(FrameError::TooLong(body.len())));
+        }
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + body.len());
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.push(flags);
+        frame.extend_from_slice(body);
+        Ok(frame)
+    }
+
+    /// Decodes the frame at the start of buf, returning its payload and the number of bytes
+    /// of buf it took up, or None if buf doesn't hold a whole frame yet.
+    pub fn decode(&self, buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+        let Some(header) = buf.get(..HEADER_LEN) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let flags = header[4];
+        if len > MAX_PAYLOAD_LEN {
+            return Err(Error::Frame(FrameError::TooLong(len)));
+        }
+        if flags & !FLAG_COMPRESSED != 0 {
+            return Err(Error::Frame(FrameError::UnknownFlags(flags)));
+        }
+        let Some(body) = buf.get(HEADER_LEN..HEADER_LEN + len) else {
+            return Ok(None);
+        };
+
+        let payload = if flags & FLAG_COMPRESSED == 0 {
+            body.to_vec()
+        } else {
+            if !self.compression {
+                return Err(Error::Frame(FrameError::NotNegotiated));
+            }
+            decompress(body)?
+        };
+        Ok(Some((payload, HEADER_LEN + len)))
+    }
+}
+
+/// Reads and writes frames on an async stream, e.g. `Framed::new(socket, codec)`.
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Vec<u8>>> {
+        use bytes::Buf;
+
+        match FrameCodec::decode(self, src)? {
+            Some((payload, used)) => {
+                src.advance(used);
+                Ok(Some(payload))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Encoder<&[u8]> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, payload: &[u8], dst: &mut bytes::BytesMut) -> Result<()> {
+        dst.extend_from_slice(&FrameCodec::encode(self, payload)?);
+        Ok(())
+    }
+}
+
+/// Decompresses body, refusing to produce more than MAX_PAYLOAD_LEN bytes so a small frame
+/// can't exhaust memory.
+fn decompress(body: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = |e: std::io::Error| Error::Frame(FrameError::Corrupt(e.to_string()));
+    let decoder = zstd::stream::read::Decoder::new(body).map_err(corrupt)?;
+    let mut payload = Vec::new();
+    decoder
+        .take(MAX_PAYLOAD_LEN as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(corrupt)?;
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(Error::Frame(FrameError::TooLong(payload.len())));
+    }
+    Ok(payload)
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    TooLong(usize),
+    UnknownFlags(u8),
+    /// The payload was compressed, but compression wasn't negotiated.
+    NotNegotiated,
+    Corrupt(String),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use FrameError::*;
+        match self {
+            TooLong(len) => write!(
+                f,
+                "payload of {} bytes exceeds the maximum of {} bytes",
+                len, MAX_PAYLOAD_LEN
+            ),
+            UnknownFlags(flags) => write!(f, "unknown flags {:#04x}", flags),
+            NotNegotiated => f.write_str("compressed payload but compression wasn't negotiated"),
+            Corrupt(e) => write!(f, "corrupt compressed payload: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codec(compression: bool) -> FrameCodec {
+        FrameCodec::new(&Capabilities {
+            compression,
+            ..Capabilities::default()
+        })
+    }
+
+    fn batch_response() -> Vec<u8> {
+        (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"name":"host{}.example.com.","address":"192.0.2.1"}}"#,
+                    i
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+            .into_bytes()
+    }
+
+    #[test]
+    fn large_payloads_are_compressed() {
+        let codec = codec(true);
+        let payload = batch_response();
+        let frame = codec.encode(&payload).unwrap();
+        assert_eq!(frame[4], FLAG_COMPRESSED);
+        assert!(frame.len() < payload.len() / 2);
+
+        let (decoded, used) = codec.decode(&frame).unwrap().unwrap();
+        assert_eq!(decoded, payload);
+        assert_eq!(used, frame.len());
+    }
+
+    #[test]
+    fn small_payloads_skip_compression() {
+        let payload = br#"{"name":"example.com.","address":"192.0.2.1"}"#;
+        let frame = codec(true).encode(payload).unwrap();
+        assert_eq!(frame[4], 0);
+        assert_eq!(&frame[HEADER_LEN..], payload);
+
+        // * The threshold can be lowered, but payloads that don't shrink are still sent as is.
+        let frame = codec(true).with_threshold(0).encode(b"x").unwrap();
+        assert_eq!(frame[4], 0);
+    }
+
+    #[test]
+    fn without_compression() {
+        let payload = batch_response();
+        let frame = codec(false).encode(&payload).unwrap();
+        assert_eq!(frame[4], 0);
+        assert_eq!(codec(false).decode(&frame).unwrap().unwrap().0, payload);
+
+        let compressed = codec(true).encode(&payload).unwrap();
+        assert!(matches!(
+            codec(false).decode(&compressed),
+            Err(Error::Frame(FrameError::NotNegotiated))
+        ));
+    }
+
+    #[test]
+    fn partial_and_consecutive_frames() {
+        let codec = codec(true);
+        let mut stream = codec.encode(b"first").unwrap();
+        stream.extend(codec.encode(&batch_response()).unwrap());
+
+        let (first, used) = codec.decode(&stream).unwrap().unwrap();
+        assert_eq!(first, b"first");
+        let rest = &stream[used..];
+        assert!(codec.decode(&rest[..3]).unwrap().is_none());
+        assert!(codec.decode(&rest[..rest.len() - 1]).unwrap().is_none());
+        assert_eq!(codec.decode(rest).unwrap().unwrap().0, batch_response());
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let codec = codec(true);
+        let too_long = ((MAX_PAYLOAD_LEN + 1) as u32).to_be_bytes();
+        assert!(matches!(
+            codec.decode(&[too_long[0], too_long[1], too_long[2], too_long[3], 0]),
+            Err(Error::Frame(FrameError::TooLong(_)))
+        ));
+        assert!(matches!(
+            codec.decode(&[0, 0, 0, 0, 0x80]),
+            Err(Error::Frame(FrameError::UnknownFlags(0x80)))
+        ));
+        assert!(matches!(
+            codec.decode(&[0, 0, 0, 3, FLAG_COMPRESSED, 1, 2, 3]),
+            Err(Error::Frame(FrameError::Corrupt(_)))
+        ));
+    }
+
+    #[cfg(feature = "codec")]
+    #[test]
+    fn tokio_codec() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = codec(true);
+        let mut buf = BytesMut::new();
+        Encoder::encode(&mut codec, &b"first"[..], &mut buf).unwrap();
+        Encoder::encode(&mut codec, &batch_response()[..], &mut buf).unwrap();
+        let end = buf.split_off(buf.len() - 1);
+
+        assert_eq!(
+            Decoder::decode(&mut codec, &mut buf).unwrap().unwrap(),
+            b"first"
+        );
+        assert!(Decoder::decode(&mut codec, &mut buf).unwrap().is_none());
+        buf.unsplit(end);
+        assert_eq!(
+            Decoder::decode(&mut codec, &mut buf).unwrap().unwrap(),
+            batch_response()
+        );
+        assert!(buf.is_empty());
+    }
+}
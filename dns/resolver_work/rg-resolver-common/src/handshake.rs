@@ -0,0 +1,192 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+
+/// The newest protocol version this crate speaks.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// The oldest protocol version this crate still speaks.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// What one side of a connection supports.
+///
+/// Fields missing from a peer's message default to unsupported, and fields this crate doesn't
+/// know about are ignored, so either side can add capabilities without breaking the other.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    /// The record types that can be looked up, e.g. "A" and "MX".
+    pub record_types: Vec<String>,
+    /// Many names can be resolved in one request.
+    pub batching: bool,
+    /// Clients can subscribe to changes to a name's records.
+    pub watch: bool,
+    /// Large responses can be compressed.
+    pub compression: bool,
+    /// Lookups are answered in full, as with general_lookup's verbose param, unless a request
+    /// says otherwise.
+    pub verbose: bool,
+}
+
+impl Capabilities {
+    /// The capabilities supported by both self and other.
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        let record_types = self
+            .record_types
+            .iter()
+            .filter(|t| other.supports_type(t))
+            .cloned()
+            .collect();
+        Capabilities {
+            record_types,
+            batching: self.batching && other.batching,
+            watch: self.watch && other.watch,
+            compression: self.compression && other.compression,
+            verbose: self.verbose && other.verbose,
+        }
+    }
+
+    pub fn supports_type(&self, record_type: &str) -> bool {
+        self.record_types
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(record_type))
+    }
+}
+
+/// Sent by the client when it connects.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientHello {
+    /// The newest protocol version the client speaks.
+    pub version: u16,
+    /// Identifies the client in the server's logs.
+    pub client_name: String,
+    pub capabilities: Capabilities,
+}
+
+impl ClientHello {
+    pub fn new(client_name: String, capabilities: Capabilities) -> ClientHello {
+        ClientHello {
+            version: PROTOCOL_VERSION,
+            client_name,
+            capabilities,
+        }
+    }
+}
+
+/// The server's reply to a ClientHello.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerHello {
+    /// The protocol version the connection will use.
+    pub version: u16,
+    /// The capabilities both sides support.
+    pub capabilities: Capabilities,
+}
+
+impl ServerHello {
+    /// Builds the reply to hello for a server with the given capabilities.
+    pub fn negotiate(hello: &ClientHello, capabilities: &Capabilities) -> Result<ServerHello> {
+        let version = hello.version.min(PROTOCOL_VERSION);
+        if version < MIN_PROTOCOL_VERSION {
+            return Err(Error::Handshake(HandshakeError::UnsupportedVersion(
+                hello.version,
+            )));
+        }
+        Ok(ServerHello {
+            version,
+            capabilities: capabilities.intersect(&hello.capabilities),
+        })
+    }
+
+    /// Checks the reply on the client side, returning the negotiated capabilities.
+    pub fn accept(&self, hello: &ClientHello) -> Result<Capabilities> {
+        if self.version < MIN_PROTOCOL_VERSION || self.version > hello.version {
+            return Err(Error::Handshake(HandshakeError::UnsupportedVersion(
+                self.version,
+            )));
+        }
+        // * Don't trust the server to only claim capabilities the client asked for.
+        Ok(self.capabilities.intersect(&hello.capabilities))
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    UnsupportedVersion(u16),
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use HandshakeError::*;
+        match self {
+            UnsupportedVersion(version) => write!(
+                f,
+                "protocol version {} is not supported (supported versions are {} to {})",
+                version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(record_types: &[&str], batching: bool) -> Capabilities {
+        Capabilities {
+            record_types: record_types.iter().map(|t| t.to_string()).collect(),
+            batching,
+            watch: false,
+            compression: true,
+            verbose: false,
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        let hello = ClientHello::new(String::from("test"), capabilities(&["A", "mx"], true));
+        let server = capabilities(&["A", "AAAA", "MX"], false);
+        let reply = ServerHello::negotiate(&hello, &server).unwrap();
+        assert_eq!(reply.version, PROTOCOL_VERSION);
+        assert_eq!(reply.capabilities, capabilities(&["A", "MX"], false));
+
+        let negotiated = reply.accept(&hello).unwrap();
+        assert!(negotiated.supports_type("mx"));
+        assert!(!negotiated.supports_type("AAAA"));
+        assert!(!negotiated.batching);
+        assert!(negotiated.compression);
+    }
+
+    #[test]
+    fn newer_client() {
+        let mut hello = ClientHello::new(String::from("test"), Capabilities::default());
+        hello.version = PROTOCOL_VERSION + 1;
+        let reply = ServerHello::negotiate(&hello, &Capabilities::default()).unwrap();
+        assert_eq!(reply.version, PROTOCOL_VERSION);
+        assert!(reply.accept(&hello).is_ok());
+    }
+
+    #[test]
+    fn unsupported_versions() {
+        let mut hello = ClientHello::new(String::from("test"), Capabilities::default());
+        hello.version = MIN_PROTOCOL_VERSION - 1;
+        assert!(matches!(
+            ServerHello::negotiate(&hello, &Capabilities::default()),
+            Err(Error::Handshake(HandshakeError::UnsupportedVersion(_)))
+        ));
+
+        let hello = ClientHello::new(String::from("test"), Capabilities::default());
+        let reply = ServerHello {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: Capabilities::default(),
+        };
+        assert!(reply.accept(&hello).is_err());
+    }
+
+    #[test]
+    fn unknown_and_missing_capabilities() {
+        let json = r#"{ "version": 1, "client_name": "old", "capabilities": { "batching": true, "teleport": true } }"#;
+        let hello: ClientHello = serde_json::from_str(json).unwrap();
+        assert!(hello.capabilities.batching);
+        assert!(!hello.capabilities.watch);
+        assert!(hello.capabilities.record_types.is_empty());
+    }
+}
@@ -1,11 +1,34 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::str::FromStr;
+
+pub mod batch;
+pub mod frame;
+pub mod handshake;
+pub mod question;
+pub mod record;
+pub mod rpc;
+pub mod vectors;
+
+pub use batch::{BatchAnswer, BatchQuery, BatchResult};
+pub use frame::{FrameCodec, FrameError};
+pub use handshake::{Capabilities, HandshakeError};
+pub use question::{Qclass, Qtype, QuestionError};
+pub use record::{Address, FullAnswer, Record, RecordData, ResolvedAddress};
+pub use rpc::{Notification, Response, RpcError};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     DomainName(DomainNameError),
+    Handshake(HandshakeError),
+    Frame(FrameError),
+    Question(QuestionError),
+    /// Reading or writing frames failed; see [FrameCodec]'s Decoder and Encoder.
+    Io(io::Error),
 }
 
 impl Display for Error {
@@ -13,20 +36,39 @@ impl Display for Error {
         use Error::*;
         match self {
             DomainName(e) => write!(f, "invalid QNAME: {}", e),
+            Handshake(e) => write!(f, "handshake failed: {}", e),
+            Frame(e) => write!(f, "invalid frame: {}", e),
+            Question(e) => write!(f, "invalid question: {}", e),
+            Io(e) => write!(f, "connection failed: {}", e),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
-#[derive(Debug)]
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum DomainNameError {
     Empty,
     FirstLabelMissing,
     InteriorLabelMissing,
     LabelTooLong(String),
     LabelNotAscii(String),
+    /// A label escaped to bytes that aren't UTF-8, which a label has to be to be held.
+    LabelNotUtf8(String),
+    /// A label with an underscore, which host names may not have (RFC 952).
+    LabelHasUnderscore(String),
+    /// A label that isn't letters, digits and hyphens, not starting or ending with a hyphen, as
+    /// host names must be (RFC 1123 section 2.1).
+    LabelNotHostname(String),
     NameTooLong,
+    AlreadyAbsolute,
+    InvalidEscape(String),
 }
 
 impl Display for DomainNameError {
@@ -43,37 +85,59 @@ impl Display for DomainNameError {
                 DomainName::MAX_LABEL_LENGTH
             ),
             LabelNotAscii(label) => write!(f, "label '{}' was not ASCII", label),
+            LabelNotUtf8(name) => write!(f, "'{}' has a label that isn't UTF-8", name),
+            LabelHasUnderscore(label) => write!(f, "label '{}' has an underscore", label),
+            LabelNotHostname(label) => write!(
+                f,
+                "label '{}' isn't letters, digits and inner hyphens",
+                label
+            ),
             NameTooLong => write!(
                 f,
                 "exceeded max length of {} characters",
                 DomainName::MAX_LENGTH
             ),
+            AlreadyAbsolute => f.write_str("already ends with the root label"),
+            InvalidEscape(name) => write!(f, "'{}' has an invalid escape", name),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl std::error::Error for DomainNameError {}
+
+/// A domain name, as its labels without escapes.
+///
+/// Names are compared and hashed without regard to case, as DNS does, but keep the case they
+/// were given in, e.g. for [Display].
+///
+/// Only a name's structure is checked when it's made. Whether its labels and it are short
+/// enough to send, and what characters they may have, is checked by the resolver, as strictly
+/// as it's configured to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DomainName {
     labels: Vec<String>,
 }
 
 impl DomainName {
-    const MAX_LENGTH: usize = 255;
-    const MAX_LABEL_LENGTH: usize = 63;
+    /// The most bytes a name takes in a message, root label included (RFC 1035 section 2.3.4).
+    pub const MAX_LENGTH: usize = 255;
+    /// The most bytes a label may have.
+    pub const MAX_LABEL_LENGTH: usize = 63;
 
+    /// Parses a name in presentation format, where "\\." is a '.' within a label and
+    /// "\\DDD" is the character with decimal value DDD.
     pub fn new(name: String) -> Result<DomainName> {
-        // TODO: Move this check to the resolver.
-        if name.len() > DomainName::MAX_LENGTH {
-            return Err(Error::DomainName(DomainNameError::NameTooLong));
-        }
-        let labels = name
-            .split('.')
-            .map(|lbl| lbl.trim())
-            .map(String::from)
-            .collect::<Vec<_>>();
-        if labels.is_empty() {
+        let name = name.trim();
+        if name.is_empty() {
             return Err(Error::DomainName(DomainNameError::Empty));
         }
+        // The root name consists of only the null root label.
+        if name == "." {
+            return Ok(DomainName {
+                labels: vec![String::new()],
+            });
+        }
+        let labels = DomainName::split_labels(name)?;
         // A domain name must start with a label.
         if labels.first().unwrap().is_empty() {
             return Err(Error::DomainName(DomainNameError::FirstLabelMissing));
@@ -86,67 +150,426 @@ impl DomainName {
             if idx + 1 < labels.len() && label.is_empty() {
                 return Err(Error::DomainName(DomainNameError::InteriorLabelMissing));
             }
-            if label.len() > DomainName::MAX_LABEL_LENGTH {
-                return Err(Error::DomainName(DomainNameError::LabelTooLong(
-                    label.clone(),
-                )));
-            }
-            if !label.is_ascii() {
-                return Err(Error::DomainName(DomainNameError::LabelNotAscii(
-                    label.clone(),
-                )));
+        }
+        Ok(DomainName { labels })
+    }
+
+    /// Builds a name from its labels, left to right and without escapes, e.g. as read from a
+    /// message. An absolute name ends with the empty root label.
+    pub fn from_labels(labels: Vec<String>) -> Result<DomainName> {
+        if labels.is_empty() {
+            return Err(Error::DomainName(DomainNameError::Empty));
+        }
+        if labels.len() == 1 && labels[0].is_empty() {
+            return Ok(DomainName::root());
+        }
+        if labels[0].is_empty() {
+            return Err(Error::DomainName(DomainNameError::FirstLabelMissing));
+        }
+        for (idx, label) in labels.iter().enumerate() {
+            if idx + 1 < labels.len() && label.is_empty() {
+                return Err(Error::DomainName(DomainNameError::InteriorLabelMissing));
             }
         }
         Ok(DomainName { labels })
     }
 
+    /// The root name, ".".
+    pub fn root() -> DomainName {
+        DomainName {
+            labels: vec![String::new()],
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.is_absolute() && self.num_labels() == 0
+    }
+
     pub fn is_absolute(&self) -> bool {
-        self.labels.last().unwrap().len() == 0
+        self.labels.last().unwrap().is_empty()
+    }
+
+    /// Iterates over the labels from left to right, excluding the null root label.
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.labels
+            .iter()
+            .map(String::as_str)
+            .filter(|lbl| !lbl.is_empty())
+    }
+
+    /// The number of labels, excluding the null root label.
+    pub fn num_labels(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// The name with its first label removed.
+    ///
+    /// The parent of a single-label absolute name is the root.
+    /// The root and single-label relative names have no parent.
+    pub fn parent(&self) -> Option<DomainName> {
+        if self.num_labels() == 0 || (self.num_labels() == 1 && !self.is_absolute()) {
+            return None;
+        }
+        Some(DomainName {
+            labels: self.labels[1..].to_vec(),
+        })
+    }
+
+    /// Iterates over the parent chain, starting with this name itself.
+    pub fn ancestors(&self) -> impl Iterator<Item = DomainName> {
+        std::iter::successors(Some(self.clone()), DomainName::parent)
+    }
+
+    /// Returns true if this name is equal to or below other.
+    ///
+    /// Labels are compared case-insensitively. An absolute name is never a subdomain of a
+    /// relative name and vice versa.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        if self.is_absolute() != other.is_absolute() || self.num_labels() < other.num_labels() {
+            return false;
+        }
+        self.labels()
+            .rev()
+            .zip(other.labels().rev())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Returns true if the names are the same down to the case of each letter, unlike ==.
+    pub fn eq_exact(&self, other: &DomainName) -> bool {
+        self.labels == other.labels
+    }
+
+    /// Prepends label to this name, e.g. "www" + "google.com." = "www.google.com.".
+    pub fn child(&self, label: &str) -> Result<DomainName> {
+        let label = label.trim();
+        if label.is_empty() {
+            return Err(Error::DomainName(DomainNameError::FirstLabelMissing));
+        }
+        let mut labels = Vec::with_capacity(self.labels.len() + 1);
+        labels.push(label.to_string());
+        labels.extend(self.labels.iter().cloned());
+        Ok(DomainName { labels })
+    }
+
+    /// Appends origin to this relative name, e.g. "www" + "google.com." = "www.google.com.".
+    pub fn join(&self, origin: &DomainName) -> Result<DomainName> {
+        if self.is_absolute() {
+            return Err(Error::DomainName(DomainNameError::AlreadyAbsolute));
+        }
+        let mut labels = self.labels.clone();
+        labels.extend(origin.labels.iter().cloned());
+        Ok(DomainName { labels })
+    }
+
+    /// Splits a name in presentation format into its labels, decoding their escapes.
+    fn split_labels(name: &str) -> Result<Vec<String>> {
+        let mut labels = Vec::new();
+        let mut start = 0;
+        let mut escaped = false;
+        for (idx, c) in name.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '.' => {
+                    labels.push(DomainName::unescape(name, name[start..idx].trim())?);
+                    start = idx + 1;
+                }
+                _ => {}
+            }
+        }
+        labels.push(DomainName::unescape(name, name[start..].trim())?);
+        Ok(labels)
+    }
+
+    /// Decodes the escapes in label, a label of name. "\\DDD" escapes bytes, so the label's
+    /// bytes have to be UTF-8 once decoded.
+    fn unescape(name: &str, label: &str) -> Result<String> {
+        let invalid_escape = || Error::DomainName(DomainNameError::InvalidEscape(name.to_string()));
+        let mut unescaped = Vec::with_capacity(label.len());
+        let mut chars = label.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let digits = [Some(d), chars.next(), chars.next()]
+                        .into_iter()
+                        .map(|d| d.and_then(|d| d.to_digit(10)))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(invalid_escape)?;
+                    let value = digits[0] * 100 + digits[1] * 10 + digits[2];
+                    let value = u8::try_from(value).map_err(|_| invalid_escape())?;
+                    unescaped.push(value);
+                }
+                Some(c) => unescaped.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => return Err(invalid_escape()),
+            }
+        }
+        String::from_utf8(unescaped)
+            .map_err(|_| Error::DomainName(DomainNameError::LabelNotUtf8(name.to_string())))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Qtype;
+impl FromStr for DomainName {
+    type Err = Error;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Qclass;
+    /// Parses a name in presentation format, as [DomainName::new] does.
+    fn from_str(s: &str) -> Result<Self> {
+        DomainName::new(s.to_string())
+    }
+}
+
+impl PartialEq for DomainName {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    /// Hashes the labels lowercased, so names equal but for case hash the same.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for b in label.bytes() {
+                state.write_u8(b.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+/// Formats the name in presentation format, escaping characters that would otherwise be
+/// ambiguous or unprintable, the latter byte by byte as "\\DDD".
+impl Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.num_labels() == 0 && self.is_absolute() {
+            return f.write_str(".");
+        }
+        for (idx, label) in self.labels.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(".")?;
+            }
+            for c in label.chars() {
+                match c {
+                    '.' | '\\' | '"' => write!(f, "\\{}", c)?,
+                    '!'..='~' => write!(f, "{}", c)?,
+                    _ => {
+                        for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                            write!(f, "\\{:03}", b)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn qname_too_long() {
-        let name = "abcdefghij".repeat(30);
-        let qname = DomainName::new(name);
-        assert!(
-            qname.is_err() && matches!(qname, Err(Error::DomainName(DomainNameError::NameTooLong)))
-        )
+    fn only_structure_checked() {
+        // * How long names and labels are and what they hold is left to the resolver.
+        let long = DomainName::new("abcdefghij".repeat(30)).unwrap();
+        assert_eq!(long.num_labels(), 1);
+        let label = "abcdefghij".repeat(7);
+        let name = DomainName::new(format!("test.{}.google.com", label)).unwrap();
+        assert_eq!(labels_of(&name), ["test", &label, "google", "com"]);
+        let name = DomainName::new(String::from("_sip._udp.bücher.example.")).unwrap();
+        assert_eq!(labels_of(&name), ["_sip", "_udp", "bücher", "example"]);
+        assert_eq!(name.to_string(), "_sip._udp.b\\195\\188cher.example.");
+    }
+
+    fn labels_of(name: &DomainName) -> Vec<&str> {
+        name.labels().collect()
     }
 
     #[test]
-    fn qname_not_ascii() {
-        let mut name = String::from("test");
-        name.push(char::from_u32(0x99).unwrap());
-        name.push_str(".google.com");
-        let qname = DomainName::new(name);
-        assert!(
-            qname.is_err() && matches!(qname, Err(Error::DomainName(DomainNameError::NotAscii)))
-        )
+    fn labels() {
+        let name = DomainName::new(String::from("www.google.com.")).unwrap();
+        assert_eq!(labels_of(&name), ["www", "google", "com"]);
+        assert_eq!(name.num_labels(), 3);
+
+        let name = DomainName::new(String::from("www.google")).unwrap();
+        assert_eq!(labels_of(&name), ["www", "google"]);
+        assert_eq!(name.num_labels(), 2);
     }
 
     #[test]
-    fn qname_label_too_long() {
-        let bad_label = "abcdefghij".repeat(7);
-        let name = format!("test.{}.google.com", bad_label);
-        let qname = DomainName::new(name);
-        assert!(
-            qname.is_err()
-                && match qname {
-                    Err(Error::DomainName(DomainNameError::LabelTooLong(label))) =>
-                        label == bad_label,
-                    _ => false,
-                }
-        )
+    fn root_and_tld() {
+        let root = DomainName::new(String::from(".")).unwrap();
+        assert!(root.is_absolute());
+        assert_eq!(root.num_labels(), 0);
+        assert!(root.parent().is_none());
+
+        let tld = DomainName::new(String::from("com.")).unwrap();
+        assert_eq!(labels_of(&tld), ["com"]);
+        assert!(tld.is_absolute());
+        assert!(tld.is_subdomain_of(&root));
+        assert_eq!(tld.parent().unwrap().num_labels(), 0);
+        assert_eq!(labels_of(&root.child("com").unwrap()), ["com"]);
+
+        assert!(matches!(
+            DomainName::new(String::new()),
+            Err(Error::DomainName(DomainNameError::Empty))
+        ));
+        assert!(matches!(
+            DomainName::new(String::from("..")),
+            Err(Error::DomainName(DomainNameError::FirstLabelMissing))
+        ));
+    }
+
+    #[test]
+    fn escapes() {
+        let name = DomainName::new(String::from("foo\\.bar.example.com.")).unwrap();
+        assert_eq!(labels_of(&name), ["foo.bar", "example", "com"]);
+        assert_eq!(name.to_string(), "foo\\.bar.example.com.");
+
+        let name = DomainName::new(String::from("a\\098c\\\\.tab\\009")).unwrap();
+        assert_eq!(labels_of(&name), ["abc\\", "tab\t"]);
+        assert!(!name.is_absolute());
+        assert_eq!(name.to_string(), "abc\\\\.tab\\009");
+
+        // * An escaped trailing '.' doesn't make the name absolute.
+        let name = DomainName::new(String::from("com\\.")).unwrap();
+        assert!(!name.is_absolute());
+        assert_eq!(DomainName::new(String::from(".")).unwrap().to_string(), ".");
+
+        for bad in ["a\\25.com", "a\\256.com", "a\\2x5.com", "com\\"] {
+            assert!(matches!(
+                DomainName::new(String::from(bad)),
+                Err(Error::DomainName(DomainNameError::InvalidEscape(_)))
+            ));
+        }
+        // * \DDD escapes a byte, so UTF-8 is escaped a byte at a time.
+        let name = DomainName::new(String::from("b\\195\\188cher.com")).unwrap();
+        assert_eq!(labels_of(&name), ["bücher", "com"]);
+        assert!(matches!(
+            DomainName::new(String::from("a\\200.com")),
+            Err(Error::DomainName(DomainNameError::LabelNotUtf8(_)))
+        ));
+    }
+
+    #[test]
+    fn from_labels() {
+        let labels = ["www", "Google", "com", ""].map(String::from).to_vec();
+        let name = DomainName::from_labels(labels).unwrap();
+        assert_eq!(name.to_string(), "www.Google.com.");
+        assert!(DomainName::from_labels(vec![String::new()])
+            .unwrap()
+            .is_root());
+
+        // * Labels are taken as they are, so a '.' in one is escaped when displayed.
+        let labels = ["a.b", "com"].map(String::from).to_vec();
+        let name = DomainName::from_labels(labels).unwrap();
+        assert_eq!(labels_of(&name), ["a.b", "com"]);
+        assert_eq!(name.to_string(), "a\\.b.com");
+
+        assert!(matches!(
+            DomainName::from_labels(vec![]),
+            Err(Error::DomainName(DomainNameError::Empty))
+        ));
+        assert!(matches!(
+            DomainName::from_labels(["a", "", "com", ""].map(String::from).to_vec()),
+            Err(Error::DomainName(DomainNameError::InteriorLabelMissing))
+        ));
+    }
+
+    #[test]
+    fn eq_ignores_case() {
+        use std::collections::HashSet;
+
+        let name: DomainName = "WWW.Google.com.".parse().unwrap();
+        assert_eq!(name, "www.google.COM.".parse().unwrap());
+        assert_eq!(name.to_string(), "WWW.Google.com.");
+        assert_ne!(name, "www.google.com".parse().unwrap());
+        assert_ne!(name, "mail.google.com.".parse().unwrap());
+
+        assert!(name.eq_exact(&"WWW.Google.com.".parse().unwrap()));
+        assert!(!name.eq_exact(&"www.google.COM.".parse().unwrap()));
+
+        let names = HashSet::from([name]);
+        assert!(names.contains(&"www.GOOGLE.com.".parse().unwrap()));
+    }
+
+    #[test]
+    fn parent() {
+        let name = DomainName::new(String::from("www.google.com.")).unwrap();
+        let parent = name.parent().unwrap();
+        assert_eq!(labels_of(&parent), ["google", "com"]);
+        assert!(parent.is_absolute());
+
+        let tld = parent.parent().unwrap();
+        let root = tld.parent().unwrap();
+        assert_eq!(root.num_labels(), 0);
+        assert!(root.is_absolute());
+        assert!(root.parent().is_none());
+
+        let relative = DomainName::new(String::from("www")).unwrap();
+        assert!(relative.parent().is_none());
+    }
+
+    #[test]
+    fn ancestors() {
+        let name = DomainName::new(String::from("www.google.com.")).unwrap();
+        let chain = name
+            .ancestors()
+            .map(|name| name.num_labels())
+            .collect::<Vec<_>>();
+        assert_eq!(chain, [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn is_subdomain_of() {
+        let name = DomainName::new(String::from("www.Google.com.")).unwrap();
+        let google = DomainName::new(String::from("google.COM.")).unwrap();
+        let amazon = DomainName::new(String::from("amazon.com.")).unwrap();
+        let relative = DomainName::new(String::from("google.com")).unwrap();
+
+        assert!(name.is_subdomain_of(&google));
+        assert!(name.is_subdomain_of(&name));
+        assert!(!google.is_subdomain_of(&name));
+        assert!(!name.is_subdomain_of(&amazon));
+        assert!(!name.is_subdomain_of(&relative));
+        assert!(name
+            .ancestors()
+            .all(|ancestor| name.is_subdomain_of(&ancestor)));
+    }
+
+    #[test]
+    fn child() {
+        let google = DomainName::new(String::from("google.com.")).unwrap();
+        let name = google.child("www").unwrap();
+        assert_eq!(labels_of(&name), ["www", "google", "com"]);
+        assert!(name.is_absolute());
+
+        assert!(matches!(
+            google.child(""),
+            Err(Error::DomainName(DomainNameError::FirstLabelMissing))
+        ));
+    }
+
+    #[test]
+    fn join() {
+        let www = DomainName::new(String::from("www.mail")).unwrap();
+        let google = DomainName::new(String::from("google.com.")).unwrap();
+        let name = www.join(&google).unwrap();
+        assert_eq!(labels_of(&name), ["www", "mail", "google", "com"]);
+        assert!(name.is_absolute());
+
+        assert!(matches!(
+            google.join(&www),
+            Err(Error::DomainName(DomainNameError::AlreadyAbsolute))
+        ));
     }
 }
@@ -0,0 +1,298 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// The type of records a question asks for: any record type, or one of the types only
+/// questions have (RFC 1035 section 3.2.3).
+///
+/// Parsed from and sent as its mnemonic, e.g. "MX", ignoring case. "*" is also accepted for ANY.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Qtype {
+    A,
+    NS,
+    MD,
+    MF,
+    CNAME,
+    SOA,
+    MB,
+    MG,
+    MR,
+    NULL,
+    WKS,
+    PTR,
+    HINFO,
+    MINFO,
+    MX,
+    TXT,
+    AAAA,
+    AXFR,
+    MAILB,
+    MAILA,
+    ANY,
+}
+
+impl Qtype {
+    const ALL: [Qtype; 21] = [
+        Qtype::A,
+        Qtype::NS,
+        Qtype::MD,
+        Qtype::MF,
+        Qtype::CNAME,
+        Qtype::SOA,
+        Qtype::MB,
+        Qtype::MG,
+        Qtype::MR,
+        Qtype::NULL,
+        Qtype::WKS,
+        Qtype::PTR,
+        Qtype::HINFO,
+        Qtype::MINFO,
+        Qtype::MX,
+        Qtype::TXT,
+        Qtype::AAAA,
+        Qtype::AXFR,
+        Qtype::MAILB,
+        Qtype::MAILA,
+        Qtype::ANY,
+    ];
+
+    /// The value of the type on the wire.
+    pub fn code(&self) -> u16 {
+        use Qtype::*;
+        match self {
+            A => 1,
+            NS => 2,
+            MD => 3,
+            MF => 4,
+            CNAME => 5,
+            SOA => 6,
+            MB => 7,
+            MG => 8,
+            MR => 9,
+            NULL => 10,
+            WKS => 11,
+            PTR => 12,
+            HINFO => 13,
+            MINFO => 14,
+            MX => 15,
+            TXT => 16,
+            AAAA => 28,
+            AXFR => 252,
+            MAILB => 253,
+            MAILA => 254,
+            ANY => 255,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        use Qtype::*;
+        match self {
+            A => "A",
+            NS => "NS",
+            MD => "MD",
+            MF => "MF",
+            CNAME => "CNAME",
+            SOA => "SOA",
+            MB => "MB",
+            MG => "MG",
+            MR => "MR",
+            NULL => "NULL",
+            WKS => "WKS",
+            PTR => "PTR",
+            HINFO => "HINFO",
+            MINFO => "MINFO",
+            MX => "MX",
+            TXT => "TXT",
+            AAAA => "AAAA",
+            AXFR => "AXFR",
+            MAILB => "MAILB",
+            MAILA => "MAILA",
+            ANY => "ANY",
+        }
+    }
+}
+
+impl FromStr for Qtype {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "*" {
+            return Ok(Qtype::ANY);
+        }
+        Qtype::ALL
+            .into_iter()
+            .find(|qtype| qtype.mnemonic().eq_ignore_ascii_case(s))
+            .ok_or_else(|| Error::Question(QuestionError::UnknownType(s.to_string())))
+    }
+}
+
+impl TryFrom<String> for Qtype {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Qtype> for String {
+    fn from(qtype: Qtype) -> String {
+        qtype.mnemonic().to_string()
+    }
+}
+
+impl Display for Qtype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+/// The class of records a question asks for (RFC 1035 section 3.2.5).
+///
+/// Parsed from and sent as its mnemonic, e.g. "IN", ignoring case. "*" is also accepted for ANY.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Qclass {
+    IN,
+    CS,
+    CH,
+    HS,
+    ANY,
+}
+
+impl Qclass {
+    const ALL: [Qclass; 5] = [Qclass::IN, Qclass::CS, Qclass::CH, Qclass::HS, Qclass::ANY];
+
+    /// The value of the class on the wire.
+    pub fn code(&self) -> u16 {
+        use Qclass::*;
+        match self {
+            IN => 1,
+            CS => 2,
+            CH => 3,
+            HS => 4,
+            ANY => 255,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        use Qclass::*;
+        match self {
+            IN => "IN",
+            CS => "CS",
+            CH => "CH",
+            HS => "HS",
+            ANY => "ANY",
+        }
+    }
+}
+
+impl FromStr for Qclass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "*" {
+            return Ok(Qclass::ANY);
+        }
+        Qclass::ALL
+            .into_iter()
+            .find(|qclass| qclass.mnemonic().eq_ignore_ascii_case(s))
+            .ok_or_else(|| Error::Question(QuestionError::UnknownClass(s.to_string())))
+    }
+}
+
+impl TryFrom<String> for Qclass {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Qclass> for String {
+    fn from(qclass: Qclass) -> String {
+        qclass.mnemonic().to_string()
+    }
+}
+
+impl Display for Qclass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+#[derive(Debug)]
+pub enum QuestionError {
+    UnknownType(String),
+    UnknownClass(String),
+}
+
+impl Display for QuestionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use QuestionError::*;
+        match self {
+            UnknownType(qtype) => write!(f, "unknown QTYPE '{}'", qtype),
+            UnknownClass(qclass) => write!(f, "unknown QCLASS '{}'", qclass),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mnemonics() {
+        assert_eq!("MX".parse::<Qtype>().unwrap(), Qtype::MX);
+        assert_eq!("aaaa".parse::<Qtype>().unwrap(), Qtype::AAAA);
+        assert_eq!("*".parse::<Qtype>().unwrap(), Qtype::ANY);
+        assert_eq!("Any".parse::<Qtype>().unwrap(), Qtype::ANY);
+        assert_eq!("in".parse::<Qclass>().unwrap(), Qclass::IN);
+        assert_eq!("*".parse::<Qclass>().unwrap(), Qclass::ANY);
+
+        for qtype in Qtype::ALL {
+            assert_eq!(qtype.to_string().parse::<Qtype>().unwrap(), qtype);
+        }
+        for qclass in Qclass::ALL {
+            assert_eq!(qclass.to_string().parse::<Qclass>().unwrap(), qclass);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown() {
+        assert!(matches!(
+            "TELEPORT".parse::<Qtype>(),
+            Err(Error::Question(QuestionError::UnknownType(qtype))) if qtype == "TELEPORT"
+        ));
+        assert!(matches!(
+            "".parse::<Qtype>(),
+            Err(Error::Question(QuestionError::UnknownType(_)))
+        ));
+        assert!(matches!(
+            "INTERNET".parse::<Qclass>(),
+            Err(Error::Question(QuestionError::UnknownClass(_)))
+        ));
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(Qtype::A.code(), 1);
+        assert_eq!(Qtype::AAAA.code(), 28);
+        assert_eq!(Qtype::ANY.code(), 255);
+        assert_eq!(Qclass::IN.code(), 1);
+        assert_eq!(Qclass::ANY.code(), 255);
+    }
+
+    #[test]
+    fn serde_as_mnemonics() {
+        assert_eq!(serde_json::to_value(Qtype::MX).unwrap(), "MX");
+        assert_eq!(serde_json::to_value(Qclass::IN).unwrap(), "IN");
+        assert_eq!(
+            serde_json::from_value::<Qtype>(serde_json::json!("cname")).unwrap(),
+            Qtype::CNAME
+        );
+        let e = serde_json::from_value::<Qclass>(serde_json::json!("XX")).unwrap_err();
+        assert!(e.to_string().contains("unknown QCLASS 'XX'"));
+    }
+}
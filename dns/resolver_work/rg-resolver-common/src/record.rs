@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A resource record returned to a client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub data: RecordData,
+}
+
+/// The typed data of a resource record.
+///
+/// Serialized as `{ "type": "MX", "data": { "preference": 10, "exchange": "mx.google.com." } }`
+/// so clients can match on the type without inspecting the data.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum RecordData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    NS(String),
+    CNAME(String),
+    SOA {
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    PTR(String),
+    HINFO {
+        cpu: String,
+        os: String,
+    },
+    MX {
+        preference: u16,
+        exchange: String,
+    },
+    /// Each character string of the record, in order.
+    TXT(Vec<String>),
+    /// A record type clients don't have a typed representation for, with its raw data.
+    Other {
+        type_code: u16,
+        rdata: Vec<u8>,
+    },
+}
+
+impl RecordData {
+    /// The address held by an A or AAAA record.
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        match self {
+            RecordData::A(addr) => Some(IpAddr::V4(*addr)),
+            RecordData::AAAA(addr) => Some(IpAddr::V6(*addr)),
+            _ => None,
+        }
+    }
+
+    /// The domain name held by an NS, CNAME, PTR, or MX record.
+    pub fn target_name(&self) -> Option<&str> {
+        match self {
+            RecordData::NS(name) | RecordData::CNAME(name) | RecordData::PTR(name) => Some(name),
+            RecordData::MX { exchange, .. } => Some(exchange),
+            _ => None,
+        }
+    }
+}
+
+impl From<Ipv4Addr> for RecordData {
+    fn from(addr: Ipv4Addr) -> Self {
+        RecordData::A(addr)
+    }
+}
+
+impl From<Ipv6Addr> for RecordData {
+    fn from(addr: Ipv6Addr) -> Self {
+        RecordData::AAAA(addr)
+    }
+}
+
+impl From<IpAddr> for RecordData {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => RecordData::A(addr),
+            IpAddr::V6(addr) => RecordData::AAAA(addr),
+        }
+    }
+}
+
+impl TryFrom<&RecordData> for IpAddr {
+    type Error = ();
+
+    fn try_from(data: &RecordData) -> std::result::Result<Self, Self::Error> {
+        data.ip_addr().ok_or(())
+    }
+}
+
+/// The addresses a hostname resolved to, answering `host_name_to_address`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResolvedAddress {
+    /// The name that was looked up.
+    pub name: String,
+    /// The name the addresses belong to after following any CNAMEs, or name if there were none.
+    pub canonical_name: String,
+    /// Every A and AAAA record found, IPv4 first.
+    pub addresses: Vec<Address>,
+    /// The answer came from a zone the resolver is authoritative for.
+    pub authoritative: bool,
+    /// The answer came from the resolver's cache rather than a nameserver.
+    pub from_cache: bool,
+}
+
+impl ResolvedAddress {
+    /// Just the addresses, in the order they were found.
+    pub fn ip_addrs(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.addresses.iter().map(|address| address.address)
+    }
+}
+
+/// The full answer to a `general_lookup` made verbose, rather than just its records.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FullAnswer {
+    /// The records answering the question, preceded by any CNAMEs leading to them: all a
+    /// minimal answer has.
+    pub answer: Vec<Record>,
+    /// The SOA of the zone saying the records don't exist, for a negative answer.
+    pub authority: Vec<Record>,
+    /// The answer came from a zone the resolver is authoritative for.
+    pub authoritative: bool,
+    /// The answer came from the resolver's cache rather than a nameserver.
+    pub from_cache: bool,
+}
+
+/// An address of a resolved hostname and how long it may be cached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Address {
+    pub address: IpAddr,
+    pub ttl: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: RecordData) -> serde_json::Value {
+        let record = Record {
+            name: String::from("google.com."),
+            ttl: 300,
+            data,
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        let parsed: Record = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(parsed, record);
+        json
+    }
+
+    #[test]
+    fn serde_a() {
+        let json = round_trip(RecordData::A(Ipv4Addr::new(142, 250, 72, 14)));
+        assert_eq!(
+            json,
+            serde_json::json!({ "name": "google.com.", "ttl": 300, "type": "A", "data": "142.250.72.14" })
+        );
+    }
+
+    #[test]
+    fn serde_aaaa() {
+        let addr = "2607:f8b0:4005:80f::200e".parse::<Ipv6Addr>().unwrap();
+        let json = round_trip(RecordData::AAAA(addr));
+        assert_eq!(json["data"], "2607:f8b0:4005:80f::200e");
+    }
+
+    #[test]
+    fn serde_mx() {
+        let json = round_trip(RecordData::MX {
+            preference: 10,
+            exchange: String::from("smtp.google.com."),
+        });
+        assert_eq!(json["type"], "MX");
+        assert_eq!(json["data"]["preference"], 10);
+        assert_eq!(json["data"]["exchange"], "smtp.google.com.");
+    }
+
+    #[test]
+    fn serde_txt() {
+        let json = round_trip(RecordData::TXT(vec![
+            String::from("v=spf1"),
+            String::from("-all"),
+        ]));
+        assert_eq!(json["data"], serde_json::json!(["v=spf1", "-all"]));
+    }
+
+    #[test]
+    fn serde_soa_and_other() {
+        round_trip(RecordData::SOA {
+            mname: String::from("ns1.google.com."),
+            rname: String::from("dns-admin.google.com."),
+            serial: 1,
+            refresh: 900,
+            retry: 900,
+            expire: 1800,
+            minimum: 60,
+        });
+        round_trip(RecordData::Other {
+            type_code: 99,
+            rdata: vec![1, 2, 3],
+        });
+    }
+
+    #[test]
+    fn ip_addr_conversions() {
+        let v4 = Ipv4Addr::new(10, 0, 0, 1);
+        let v6 = Ipv6Addr::LOCALHOST;
+        assert_eq!(RecordData::from(v4).ip_addr(), Some(IpAddr::V4(v4)));
+        assert_eq!(RecordData::from(IpAddr::V6(v6)), RecordData::AAAA(v6));
+        assert_eq!(IpAddr::try_from(&RecordData::AAAA(v6)), Ok(IpAddr::V6(v6)));
+        assert!(IpAddr::try_from(&RecordData::CNAME(String::from("google.com."))).is_err());
+    }
+
+    #[test]
+    fn serde_resolved_address() {
+        let resolved = ResolvedAddress {
+            name: String::from("www.google.com."),
+            canonical_name: String::from("www3.l.google.com."),
+            addresses: vec![
+                Address {
+                    address: IpAddr::V4(Ipv4Addr::new(142, 250, 72, 14)),
+                    ttl: 300,
+                },
+                Address {
+                    address: "2607:f8b0:4005:80f::200e".parse().unwrap(),
+                    ttl: 120,
+                },
+            ],
+            authoritative: false,
+            from_cache: true,
+        };
+        let json = serde_json::to_value(&resolved).unwrap();
+        assert_eq!(
+            json["addresses"][0],
+            serde_json::json!({ "address": "142.250.72.14", "ttl": 300 })
+        );
+        assert_eq!(json["canonical_name"], "www3.l.google.com.");
+        assert_eq!(json["from_cache"], true);
+        let parsed: ResolvedAddress = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, resolved);
+        assert_eq!(parsed.ip_addrs().count(), 2);
+    }
+
+    #[test]
+    fn target_name() {
+        let mx = RecordData::MX {
+            preference: 5,
+            exchange: String::from("smtp.google.com."),
+        };
+        assert_eq!(mx.target_name(), Some("smtp.google.com."));
+        assert_eq!(RecordData::A(Ipv4Addr::LOCALHOST).target_name(), None);
+    }
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+/// The JSON-RPC version every message is sent with.
+pub const JSONRPC_VERSION: &str = "2.0";
+
+// The error codes reserved by the JSON-RPC 2.0 spec.
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+// The resolver's own error codes, from the range the spec leaves to servers. They're part of
+// the protocol, so they're never renumbered.
+/// The lookup failed, e.g. because no nameserver could be reached.
+pub const LOOKUP_FAILED: i32 = -32000;
+/// The name doesn't exist (NXDOMAIN).
+pub const NAME_ERROR: i32 = -32001;
+/// The resolver is restarting; the request can be sent again once it's back.
+pub const SERVER_RESTARTING: i32 = -32002;
+/// The server doesn't speak any protocol version the client does. Sent in reply to the
+/// client's hello, before the connection is closed.
+pub const UNSUPPORTED_VERSION: i32 = -32003;
+/// The client has too many requests in flight. Its data is how many milliseconds to wait
+/// before sending the request again.
+pub const SERVER_BUSY: i32 = -32004;
+
+/// A JSON-RPC 2.0 response: either the result of the request or why it failed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Response<T> {
+    pub jsonrpc: String,
+    /// None (null) if the request's id couldn't be read.
+    pub id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl<T> Response<T> {
+    pub fn ok(id: u32, result: T) -> Response<T> {
+        Response {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn err(id: Option<u32>, error: RpcError) -> Response<T> {
+        Response {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+
+    /// The result, or the error if the request failed. A response with neither is treated
+    /// as an internal error.
+    pub fn into_result(self) -> std::result::Result<T, RpcError> {
+        match (self.result, self.error) {
+            (_, Some(error)) => Err(error),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(RpcError::new(
+                INTERNAL_ERROR,
+                "response holds neither a result nor an error",
+            )),
+        }
+    }
+}
+
+/// A JSON-RPC 2.0 notification: a message without an id, which isn't answered.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Notification<T> {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: T,
+}
+
+impl<T> Notification<T> {
+    pub fn new(method: impl Into<String>, params: T) -> Notification<T> {
+        Notification {
+            jsonrpc: String::from(JSONRPC_VERSION),
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+/// Why a request failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// One of the codes in this module.
+    pub code: i32,
+    pub message: String,
+    /// More detail, e.g. the name that doesn't exist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+impl RpcError {
+    pub fn new(code: i32, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: impl Into<String>) -> RpcError {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// How long to wait before sending the request again, if the server said it's busy.
+    pub fn retry_after(&self) -> Option<Duration> {
+        if self.code != SERVER_BUSY {
+            return None;
+        }
+        let ms = self.data.as_deref()?.parse().ok()?;
+        Some(Duration::from_millis(ms))
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)?;
+        if let Some(data) = &self.data {
+            write!(f, ": {}", data)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Record, RecordData};
+    use std::net::Ipv4Addr;
+
+    fn round_trip(response: &Response<Vec<Record>>) -> serde_json::Value {
+        let json = serde_json::to_value(response).unwrap();
+        let parsed: Response<Vec<Record>> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(&parsed, response);
+        json
+    }
+
+    #[test]
+    fn result_round_trip() {
+        let record = Record {
+            name: String::from("example.com."),
+            ttl: 300,
+            data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        };
+        let response = Response::ok(7, vec![record.clone()]);
+        let json = round_trip(&response);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "result": [{ "name": "example.com.", "ttl": 300, "type": "A", "data": "192.0.2.1" }]
+            })
+        );
+        assert_eq!(response.into_result(), Ok(vec![record]));
+    }
+
+    #[test]
+    fn error_round_trip() {
+        let error = RpcError::new(NAME_ERROR, "name doesn't exist").with_data("nowhere.example.");
+        let response = Response::<Vec<Record>>::err(Some(3), error.clone());
+        let json = round_trip(&response);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "error": { "code": -32001, "message": "name doesn't exist", "data": "nowhere.example." }
+            })
+        );
+        assert_eq!(response.into_result(), Err(error));
+
+        // * Errors for requests whose id couldn't be read have a null id, and data is optional.
+        let response = Response::<Vec<Record>>::err(None, RpcError::new(PARSE_ERROR, "bad JSON"));
+        let json = round_trip(&response);
+        assert_eq!(json["id"], serde_json::Value::Null);
+        assert!(json["error"].get("data").is_none());
+    }
+
+    #[test]
+    fn neither_result_nor_error() {
+        let response: Response<Vec<Record>> =
+            serde_json::from_str(r#"{ "jsonrpc": "2.0", "id": 1 }"#).unwrap();
+        assert_eq!(response.into_result().unwrap_err().code, INTERNAL_ERROR);
+    }
+
+    #[test]
+    fn retry_after() {
+        let busy = RpcError::new(SERVER_BUSY, "server busy").with_data("250");
+        assert_eq!(busy.retry_after(), Some(Duration::from_millis(250)));
+        assert_eq!(
+            RpcError::new(SERVER_BUSY, "server busy").retry_after(),
+            None
+        );
+        let restarting = RpcError::new(SERVER_RESTARTING, "restarting").with_data("250");
+        assert_eq!(restarting.retry_after(), None);
+    }
+}
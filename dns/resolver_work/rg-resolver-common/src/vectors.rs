@@ -0,0 +1,326 @@
+//! Hand-crafted DNS messages exercising wire-format edge cases, with what parsing each should
+//! produce.
+//!
+//! The resolver's parser, the client's stub parser, and any fuzzer seeding its corpus all run
+//! the same vectors, so they agree on what's valid.
+
+/// The ID of every vector's message.
+pub const ID: u16 = 0x1234;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NULL: u16 = 10;
+pub const TYPE_TXT: u16 = 16;
+pub const CLASS_IN: u16 = 1;
+
+/// The offset of the question name, the target of most compression pointers.
+const QNAME_OFFSET: u16 = 12;
+
+/// A complete response message and what parsing it should produce.
+#[derive(Clone, Debug)]
+pub struct Vector {
+    /// A short, unique name for reporting failures.
+    pub name: &'static str,
+    pub message: Vec<u8>,
+    pub expected: Expected,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expected {
+    /// The message parses, and its answer section holds these records in order.
+    Answers(Vec<Answer>),
+    /// The message is malformed and must be rejected as a whole.
+    Malformed,
+}
+
+/// A record in the answer section, with its owner in presentation format and fully qualified.
+/// rdata never holds names, so it's the same whether or not a parser decompresses it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Answer {
+    pub owner: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+/// Every vector.
+pub fn all() -> Vec<Vector> {
+    vec![
+        max_length_name(),
+        name_too_long(),
+        extended_label_type(),
+        deep_compression(),
+        forward_pointer(),
+        self_pointer(),
+        empty_rdata(),
+        zero_ttl(),
+        root_owner(),
+        truncated_rdata(),
+    ]
+}
+
+/// A name of exactly 255 octets, the most RFC 1035 allows, counting the root label.
+fn max_length_name() -> Vector {
+    let labels = [
+        "a".repeat(63),
+        "b".repeat(63),
+        "c".repeat(63),
+        "d".repeat(61),
+    ];
+    let qname = wire_name(&labels);
+    debug_assert_eq!(qname.len(), 255);
+    Vector {
+        name: "max_length_name",
+        message: response(
+            &qname,
+            TYPE_A,
+            &[record(&pointer(QNAME_OFFSET), TYPE_A, 300, &[192, 0, 2, 1])],
+        ),
+        expected: Expected::Answers(vec![Answer {
+            owner: format!("{}.", labels.join(".")),
+            rtype: TYPE_A,
+            ttl: 300,
+            rdata: vec![192, 0, 2, 1],
+        }]),
+    }
+}
+
+/// A name one octet longer than the maximum.
+fn name_too_long() -> Vector {
+    let labels = [
+        "a".repeat(63),
+        "b".repeat(63),
+        "c".repeat(63),
+        "d".repeat(62),
+    ];
+    let qname = wire_name(&labels);
+    debug_assert_eq!(qname.len(), 256);
+    Vector {
+        name: "name_too_long",
+        message: response(&qname, TYPE_A, &[]),
+        expected: Expected::Malformed,
+    }
+}
+
+/// A label length byte of 0x40, whose top bits mark a label type other than a normal label or
+/// a pointer. None are in use, so it can't be parsed.
+fn extended_label_type() -> Vector {
+    let mut qname = vec![0x40];
+    qname.extend_from_slice(&[b'a'; 0x40]);
+    qname.push(0);
+    Vector {
+        name: "extended_label_type",
+        message: response(&qname, TYPE_A, &[]),
+        expected: Expected::Malformed,
+    }
+}
+
+/// Answers whose owners are each a pointer to the previous answer's owner, so the last one has
+/// to follow a chain of 32 pointers back to the question name.
+fn deep_compression() -> Vector {
+    const COUNT: u8 = 32;
+    let qname = wire_name(&["example", "com"]);
+    let mut answers = Vec::new();
+    let mut owner_offset = QNAME_OFFSET;
+    let mut next_offset = QNAME_OFFSET + qname.len() as u16 + 4;
+    for i in 0..COUNT {
+        let answer = record(&pointer(owner_offset), TYPE_A, 300, &[192, 0, 2, i]);
+        owner_offset = next_offset;
+        next_offset += answer.len() as u16;
+        answers.push(answer);
+    }
+    Vector {
+        name: "deep_compression",
+        message: response(&qname, TYPE_A, &answers),
+        expected: Expected::Answers(
+            (0..COUNT)
+                .map(|i| Answer {
+                    owner: String::from("example.com."),
+                    rtype: TYPE_A,
+                    ttl: 300,
+                    rdata: vec![192, 0, 2, i],
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// An owner pointing past itself, which could be used to build loops.
+fn forward_pointer() -> Vector {
+    let qname = wire_name(&["example", "com"]);
+    let owner_offset = QNAME_OFFSET + qname.len() as u16 + 4;
+    Vector {
+        name: "forward_pointer",
+        message: response(
+            &qname,
+            TYPE_A,
+            &[record(
+                &pointer(owner_offset + 2),
+                TYPE_A,
+                300,
+                &[192, 0, 2, 1],
+            )],
+        ),
+        expected: Expected::Malformed,
+    }
+}
+
+/// An owner pointing at itself, the shortest loop.
+fn self_pointer() -> Vector {
+    let qname = wire_name(&["example", "com"]);
+    let owner_offset = QNAME_OFFSET + qname.len() as u16 + 4;
+    Vector {
+        name: "self_pointer",
+        message: response(
+            &qname,
+            TYPE_A,
+            &[record(&pointer(owner_offset), TYPE_A, 300, &[192, 0, 2, 1])],
+        ),
+        expected: Expected::Malformed,
+    }
+}
+
+/// A NULL record with no data at all, which is valid, alongside the address asked for.
+fn empty_rdata() -> Vector {
+    let qname = wire_name(&["example", "com"]);
+    let owner = pointer(QNAME_OFFSET);
+    Vector {
+        name: "empty_rdata",
+        message: response(
+            &qname,
+            TYPE_A,
+            &[
+                record(&owner, TYPE_NULL, 300, &[]),
+                record(&owner, TYPE_A, 300, &[192, 0, 2, 1]),
+            ],
+        ),
+        expected: Expected::Answers(vec![
+            Answer {
+                owner: String::from("example.com."),
+                rtype: TYPE_NULL,
+                ttl: 300,
+                rdata: Vec::new(),
+            },
+            Answer {
+                owner: String::from("example.com."),
+                rtype: TYPE_A,
+                ttl: 300,
+                rdata: vec![192, 0, 2, 1],
+            },
+        ]),
+    }
+}
+
+/// A TTL of zero: the record may be used for this query but not cached.
+fn zero_ttl() -> Vector {
+    let qname = wire_name(&["example", "com"]);
+    Vector {
+        name: "zero_ttl",
+        message: response(
+            &qname,
+            TYPE_A,
+            &[record(&pointer(QNAME_OFFSET), TYPE_A, 0, &[192, 0, 2, 1])],
+        ),
+        expected: Expected::Answers(vec![Answer {
+            owner: String::from("example.com."),
+            rtype: TYPE_A,
+            ttl: 0,
+            rdata: vec![192, 0, 2, 1],
+        }]),
+    }
+}
+
+/// A record owned by the root, whose name is just the root label.
+fn root_owner() -> Vector {
+    let txt = b"\x05hello";
+    Vector {
+        name: "root_owner",
+        message: response(&[0], TYPE_TXT, &[record(&[0], TYPE_TXT, 300, txt)]),
+        expected: Expected::Answers(vec![Answer {
+            owner: String::from("."),
+            rtype: TYPE_TXT,
+            ttl: 300,
+            rdata: txt.to_vec(),
+        }]),
+    }
+}
+
+/// A record whose data length runs past the end of the message.
+fn truncated_rdata() -> Vector {
+    let qname = wire_name(&["example", "com"]);
+    let mut answer = record(&pointer(QNAME_OFFSET), TYPE_A, 300, &[192, 0, 2, 1]);
+    answer.truncate(answer.len() - 1);
+    Vector {
+        name: "truncated_rdata",
+        message: response(&qname, TYPE_A, &[answer]),
+        expected: Expected::Malformed,
+    }
+}
+
+fn wire_name<S: AsRef<str>>(labels: &[S]) -> Vec<u8> {
+    let mut name = Vec::new();
+    for label in labels {
+        let label = label.as_ref();
+        name.push(label.len() as u8);
+        name.extend_from_slice(label.as_bytes());
+    }
+    name.push(0);
+    name
+}
+
+fn pointer(offset: u16) -> [u8; 2] {
+    (0xc000 | offset).to_be_bytes()
+}
+
+fn record(owner: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+    let mut record = owner.to_vec();
+    record.extend_from_slice(&rtype.to_be_bytes());
+    record.extend_from_slice(&CLASS_IN.to_be_bytes());
+    record.extend_from_slice(&ttl.to_be_bytes());
+    record.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    record.extend_from_slice(rdata);
+    record
+}
+
+/// A NOERROR response with qname as its question and answers as its answer section.
+fn response(qname: &[u8], qtype: u16, answers: &[Vec<u8>]) -> Vec<u8> {
+    let mut message = ID.to_be_bytes().to_vec();
+    // Response, recursion desired and available.
+    message.extend_from_slice(&[0x81, 0x80]);
+    message.extend_from_slice(&1_u16.to_be_bytes());
+    message.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    message.extend_from_slice(&[0, 0, 0, 0]);
+    message.extend_from_slice(qname);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    for answer in answers {
+        message.extend_from_slice(answer);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let vectors = all();
+        for (i, vector) in vectors.iter().enumerate() {
+            assert!(
+                vectors[..i].iter().all(|other| other.name != vector.name),
+                "{} is defined more than once",
+                vector.name
+            );
+        }
+    }
+
+    #[test]
+    fn answer_counts_match() {
+        for vector in all() {
+            if let Expected::Answers(answers) = &vector.expected {
+                let count = u16::from_be_bytes([vector.message[6], vector.message[7]]);
+                assert_eq!(count as usize, answers.len(), "{}", vector.name);
+            }
+        }
+    }
+}
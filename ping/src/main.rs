@@ -1,4 +1,5 @@
 use std::cmp;
+use std::collections::BTreeMap;
 use std::net::Ipv4Addr;
 use std::sync::{Mutex, Condvar};
 use std::thread;
@@ -7,10 +8,11 @@ use std::mem::MaybeUninit;
 
 use clap::Parser;
 use windows::Win32::Foundation::*;
-use windows::Win32::NetworkManagement::IpHelper::ICMP_ECHO_REPLY;
+use windows::Win32::NetworkManagement::IpHelper::*;
 use windows::Win32::Networking::WinSock::*;
 use windows::Win32::System::Console::*;
 
+mod multi;
 mod ping;
 
 static mut STATS: Mutex<PingStats> = Mutex::new(PingStats::new());
@@ -22,7 +24,7 @@ pub struct CliArgs {
     /// Ping the specified host until stopped.
     /// To see statistics and continue - type Control-Break;
     /// To stop - type Control-C.
-    #[arg(short = 't', verbatim_doc_comment)]
+    #[arg(short = 't', conflicts_with = "targets_file", verbatim_doc_comment)]
     until_stopped: bool,
     /// Resolve addresses to hostnames.
     #[arg(short = 'a', verbatim_doc_comment)]
@@ -39,23 +41,64 @@ pub struct CliArgs {
     /// Time To Live.
     #[arg(short = 'i', verbatim_doc_comment)]
     ttl: Option<u8>,
+    /// Type Of Service.
+    /// The TOS of each reply is displayed.
+    #[arg(short = 'v', long = "tos", verbatim_doc_comment)]
+    tos: Option<u8>,
+    /// Differentiated Services Code Point (0-63), sent in the upper 6 bits of the TOS.
+    /// The DSCP of each reply is displayed.
+    #[arg(long, conflicts_with = "tos", value_parser = clap::value_parser!(u8).range(0..64), verbatim_doc_comment)]
+    dscp: Option<u8>,
     /// Timeout in milliseconds to wait for each reply.
     #[arg(short = 'w', default_value_t = 4000, verbatim_doc_comment)]
     timeout: u32,
     /// Source address to use.
     #[arg(short = 'S', verbatim_doc_comment)]
     srcaddr: Option<Ipv4Addr>,
+    /// Read the hosts to ping from a file, one per line.
+    /// Use - to read them from standard input.
+    /// Blank lines and lines starting with # are ignored.
+    #[arg(long, value_name = "FILE", verbatim_doc_comment)]
+    targets_file: Option<String>,
+    /// Maximum number of targets from the targets file to ping at once.
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..), verbatim_doc_comment)]
+    concurrency: u32,
+    /// Print one compact row per target from the targets file,
+    /// with ! for each reply, . for each timeout, U for each unreachable error,
+    /// & for each TTL expired error, and M for each needs fragmentation error.
+    #[arg(long, verbatim_doc_comment)]
+    matrix: bool,
+    /// Print a JSON summary per target from the targets file, one per line.
+    #[arg(long, conflicts_with = "matrix", verbatim_doc_comment)]
+    json: bool,
+    /// Allow pinging a broadcast or multicast address.
+    /// The reply from each responder is shown, and statistics are kept per responder.
+    #[arg(long, conflicts_with = "targets_file", verbatim_doc_comment)]
+    allow_broadcast: bool,
     /// The target host to ping.
-    #[arg(verbatim_doc_comment)]
-    target_name: String,
+    #[arg(required_unless_present = "targets_file", verbatim_doc_comment)]
+    target_name: Option<String>,
 }
 
 pub fn main() -> anyhow::Result<()> {
     ping::init_winsock()?;
 
     let args = CliArgs::parse();
+    if let Some(path) = &args.targets_file {
+        let targets = multi::read_targets(path)?;
+        return multi::run(&args, targets);
+    }
+    let target_name = args.target_name.as_deref().unwrap();
 
-    let (tgt_ip, tgt_hostname) = get_tgt_ip_and_hostname(&args)?;
+    let (tgt_ip, tgt_hostname) = get_tgt_ip_and_hostname(target_name, args.resolve_addresses)?;
+    // Many hosts can answer, which the single reply per request of the normal output would hide.
+    let broadcast = is_broadcast_or_multicast(tgt_ip)?;
+    if broadcast && !args.allow_broadcast {
+        anyhow::bail!(
+            "{} is a broadcast or multicast address; pass --allow-broadcast to ping it",
+            tgt_ip
+        );
+    }
     {
         // Set the target IP address for use by the console handler (if ever called).
         unsafe {
@@ -77,46 +120,29 @@ pub fn main() -> anyhow::Result<()> {
     let icmp_handle = ping::icmp_create()?;
     ping::set_console_handler(Some(console_handler))?;
 
-    let src_addr = match args.srcaddr {
-        Some(addr) => addr,
-        None => Ipv4Addr::UNSPECIFIED,
-    };
-    let ttl = match args.ttl {
-        Some(ttl) => ttl,
-        None => 128,
-    };
-
     let mut done = false;
     while !done {
-        let reply = match ping::send_ping(
-            icmp_handle,
-            src_addr,
-            tgt_ip,
-            args.size,
-            ttl,
-            args.dont_fragment,
-            args.timeout,
-        ) {
-            Ok(reply) => Some(reply),
-            Err(e) => {
-                match e {
-                    ping::Error::SendEcho(e) if e.code() == WSA_QOS_ADMISSION_FAILURE.0 as u32 => None,
-                    _ => return Err(e.into()),
-                }
-            }
-        };
-
-        let requests_sent = {
+        let requests_sent = if broadcast {
+            let probes = send_broadcast(icmp_handle, tgt_ip, &args)?;
             let mut stats = unsafe { STATS.lock().unwrap() };
-            match reply {
-                Some(reply) => {
-                    print_reply_info(&reply);
+            record_broadcast(&mut stats, &probes, &args);
+            stats.requests_sent
+        } else {
+            let probe = send_one(icmp_handle, tgt_ip, &args)?;
+            let mut stats = unsafe { STATS.lock().unwrap() };
+            match probe {
+                Probe::Reply(reply) => {
+                    print_reply_info(&reply, &args);
                     update_stats(&mut stats, &reply)
                 }
-                None => {
+                Probe::TimedOut => {
                     stats.requests_sent += 1;
                     println!("Request timed out.");
                 }
+                Probe::Error { status, from } => {
+                    print_error_info(status, from);
+                    update_error_stats(&mut stats, status)
+                }
             }
             stats.requests_sent
         };
@@ -159,13 +185,179 @@ unsafe extern "system" fn console_handler(ctrl_type: u32) -> BOOL {
     false.into()
 }
 
-fn get_tgt_ip_and_hostname(args: &CliArgs) -> anyhow::Result<(Ipv4Addr, Option<String>)> {
-    let name = &args.target_name;
+/// The outcome of a single echo request.
+enum Probe {
+    Reply(ICMP_ECHO_REPLY),
+    TimedOut,
+    /// An ICMP error came back instead of an echo reply.
+    /// from is the host that reported the error, when known.
+    Error { status: u32, from: Option<Ipv4Addr> },
+}
+
+/// Sends a single echo request.
+fn send_one(icmp_handle: IcmpHandle, tgt_ip: Ipv4Addr, args: &CliArgs) -> anyhow::Result<Probe> {
+    let (src_addr, ttl, tos) = request_options(args);
+    match ping::send_ping(
+        icmp_handle,
+        src_addr,
+        tgt_ip,
+        args.size,
+        ttl,
+        tos,
+        args.dont_fragment,
+        args.timeout,
+    ) {
+        Ok(reply) => Ok(probe_from_reply(reply)),
+        Err(e) => probe_from_error(e),
+    }
+}
+
+/// The most replies collected for one echo request to a broadcast or multicast address.
+const MAX_RESPONDERS: usize = 64;
+
+/// Sends a single echo request to a broadcast or multicast address,
+/// returning the outcome from each host that answered.
+fn send_broadcast(
+    icmp_handle: IcmpHandle,
+    tgt_ip: Ipv4Addr,
+    args: &CliArgs,
+) -> anyhow::Result<Vec<Probe>> {
+    let (src_addr, ttl, tos) = request_options(args);
+    match ping::send_ping_all(
+        icmp_handle,
+        src_addr,
+        tgt_ip,
+        args.size,
+        ttl,
+        tos,
+        args.dont_fragment,
+        args.timeout,
+        MAX_RESPONDERS,
+    ) {
+        Ok(replies) => Ok(replies.into_iter().map(probe_from_reply).collect()),
+        Err(e) => Ok(vec![probe_from_error(e)?]),
+    }
+}
+
+/// The source address, TTL, and TOS to send echo requests with.
+fn request_options(args: &CliArgs) -> (Ipv4Addr, u8, u8) {
+    let src_addr = match args.srcaddr {
+        Some(addr) => addr,
+        None => Ipv4Addr::UNSPECIFIED,
+    };
+    let ttl = match args.ttl {
+        Some(ttl) => ttl,
+        None => 128,
+    };
+    let tos = match (args.tos, args.dscp) {
+        (Some(tos), _) => tos,
+        (None, Some(dscp)) => dscp << 2,
+        (None, None) => 0,
+    };
+    (src_addr, ttl, tos)
+}
+
+fn probe_from_reply(reply: ICMP_ECHO_REPLY) -> Probe {
+    match reply.Status {
+        IP_SUCCESS => Probe::Reply(reply),
+        IP_REQ_TIMED_OUT => Probe::TimedOut,
+        status => Probe::Error {
+            status,
+            from: Some(Ipv4Addr::from(reply.Address.swap_bytes())),
+        },
+    }
+}
+
+fn probe_from_error(e: ping::Error) -> anyhow::Result<Probe> {
+    match e {
+        ping::Error::SendEcho(e) if e.code() == WSA_QOS_ADMISSION_FAILURE.0 as u32 => Ok(Probe::TimedOut),
+        // No reply was returned, but the error code says which ICMP error came back.
+        ping::Error::SendEcho(e) if e.code() > IP_STATUS_BASE && e.code() <= IP_GENERAL_FAILURE => {
+            Ok(Probe::Error { status: e.code(), from: None })
+        }
+        _ => Err(e.into()),
+    }
+}
+
+/// Returns true if addr is the limited broadcast address, a multicast address,
+/// or the directed broadcast address of a subnet a local interface is on.
+pub fn is_broadcast_or_multicast(addr: Ipv4Addr) -> anyhow::Result<bool> {
+    if addr.is_broadcast() || addr.is_multicast() {
+        return Ok(true);
+    }
+    Ok(ping::broadcast_addrs()?.contains(&addr))
+}
+
+/// Prints and records the outcome of one echo request to a broadcast or multicast address.
+fn record_broadcast(stats: &mut PingStats, probes: &[Probe], args: &CliArgs) {
+    let mut responders = BTreeMap::new();
+    for probe in probes {
+        if let Probe::Reply(reply) = probe {
+            let addr = Ipv4Addr::from(reply.Address.swap_bytes());
+            // A host that answers twice is only counted once per request.
+            if responders.insert(addr, reply.RoundTripTime).is_none() {
+                print_reply_info(reply, args);
+            }
+        }
+    }
+
+    let Some(&fastest) = responders.values().min() else {
+        // No host answered, so report the request the way a unicast one would be.
+        match probes.first() {
+            Some(Probe::Error { status, from }) => {
+                print_error_info(*status, *from);
+                update_error_stats(stats, *status);
+            }
+            _ => {
+                stats.requests_sent += 1;
+                println!("Request timed out.");
+            }
+        }
+        return;
+    };
+
+    stats.requests_sent += 1;
+    stats.replies_rcvd += 1;
+    stats.min_rtt = cmp::min(stats.min_rtt, fastest);
+    stats.max_rtt = cmp::max(stats.max_rtt, fastest);
+    let n = stats.replies_rcvd;
+    stats.avg_rtt = (((n - 1) * stats.avg_rtt + fastest) as f64 / n as f64).round() as u32;
+    for (addr, rtt) in responders {
+        let responder = stats.responders.entry(addr).or_insert(ResponderStats {
+            replies_rcvd: 0,
+            min_rtt: rtt,
+            max_rtt: rtt,
+            total_rtt: 0,
+        });
+        responder.replies_rcvd += 1;
+        responder.min_rtt = cmp::min(responder.min_rtt, rtt);
+        responder.max_rtt = cmp::max(responder.max_rtt, rtt);
+        responder.total_rtt += rtt as u64;
+    }
+}
+
+/// The message the system ping prints for an ICMP error status.
+fn icmp_error_message(status: u32) -> &'static str {
+    match status {
+        IP_DEST_NET_UNREACHABLE => "Destination net unreachable.",
+        IP_DEST_HOST_UNREACHABLE => "Destination host unreachable.",
+        IP_DEST_PROT_UNREACHABLE => "Destination protocol unreachable.",
+        IP_DEST_PORT_UNREACHABLE => "Destination port unreachable.",
+        IP_TTL_EXPIRED_TRANSIT => "TTL expired in transit.",
+        IP_PACKET_TOO_BIG => "Packet needs to be fragmented but DF set.",
+        _ => "General failure.",
+    }
+}
+
+fn get_tgt_ip_and_hostname(
+    name: &str,
+    resolve_addresses: bool,
+) -> anyhow::Result<(Ipv4Addr, Option<String>)> {
     match name.parse::<Ipv4Addr>() {
         Ok(ip_addr) => {
             // User specified an IP address.
             let mut hostname: Option<String> = None;
-            if args.resolve_addresses {
+            if resolve_addresses {
                 // If resolving the IP address to a hostname fails,
                 // ignore the error and move on.
                 let res = ping::resolve_ip(ip_addr);
@@ -177,22 +369,52 @@ fn get_tgt_ip_and_hostname(args: &CliArgs) -> anyhow::Result<(Ipv4Addr, Option<S
         }
         Err(_) => {
             // User specified a hostname.
-            Ok((ping::resolve_hostname(name)?, Some(name.clone())))
+            Ok((ping::resolve_hostname(name)?, Some(name.to_string())))
         }
     }
 }
 
-fn print_reply_info(reply: &ICMP_ECHO_REPLY) {
+fn print_reply_info(reply: &ICMP_ECHO_REPLY, args: &CliArgs) {
     let addr = Ipv4Addr::from(reply.Address.swap_bytes());
+    // Only show the received marking when the user asked for one,
+    // so the default output matches the system ping.
+    let marking = if args.dscp.is_some() {
+        format!(" DSCP={}", reply.Options.Tos >> 2)
+    } else if args.tos.is_some() {
+        format!(" TOS={}", reply.Options.Tos)
+    } else {
+        String::new()
+    };
     println!(
-        "Reply from {}: bytes={} time={}ms TTL={}",
+        "Reply from {}: bytes={} time={}ms TTL={}{}",
         addr.to_string(),
         reply.DataSize,
         reply.RoundTripTime,
-        reply.Options.Ttl
+        reply.Options.Ttl,
+        marking
     );
 }
 
+fn print_error_info(status: u32, from: Option<Ipv4Addr>) {
+    match from {
+        Some(addr) => println!("Reply from {}: {}", addr, icmp_error_message(status)),
+        None => println!("{}", icmp_error_message(status)),
+    }
+}
+
+fn update_error_stats(stats: &mut PingStats, status: u32) {
+    stats.requests_sent += 1;
+    match status {
+        IP_DEST_NET_UNREACHABLE
+        | IP_DEST_HOST_UNREACHABLE
+        | IP_DEST_PROT_UNREACHABLE
+        | IP_DEST_PORT_UNREACHABLE => stats.unreachable += 1,
+        IP_TTL_EXPIRED_TRANSIT => stats.ttl_expired += 1,
+        IP_PACKET_TOO_BIG => stats.needs_fragmentation += 1,
+        _ => stats.other_errors += 1,
+    }
+}
+
 fn update_stats(stats: &mut PingStats, reply: &ICMP_ECHO_REPLY) {
     stats.requests_sent += 1;
     stats.replies_rcvd += 1;
@@ -212,6 +434,12 @@ fn print_stats(stats: &PingStats, tgt_ip: Ipv4Addr) {
         "\tPackets: Sent = {}, Received = {}, Lost = {} ({}% loss),",
         stats.requests_sent, stats.replies_rcvd, lost, loss_perc
     );
+    if stats.errors() > 0 {
+        println!(
+            "\tErrors: Unreachable = {}, TTL expired = {}, Needs fragmentation = {}, Other = {}",
+            stats.unreachable, stats.ttl_expired, stats.needs_fragmentation, stats.other_errors
+        );
+    }
     if stats.replies_rcvd > 0 {
         println!("Approximate round trip times in milli-seconds:");
         println!(
@@ -219,6 +447,19 @@ fn print_stats(stats: &PingStats, tgt_ip: Ipv4Addr) {
             stats.min_rtt, stats.max_rtt, stats.avg_rtt
         );
     }
+    if !stats.responders.is_empty() {
+        println!("Replies by responder:");
+        for (addr, responder) in &stats.responders {
+            println!(
+                "\t{}: Received = {}, Minimum = {}ms, Maximum = {}ms, Average = {}ms",
+                addr,
+                responder.replies_rcvd,
+                responder.min_rtt,
+                responder.max_rtt,
+                (responder.total_rtt as f64 / responder.replies_rcvd as f64).round() as u32
+            );
+        }
+    }
 }
 
 struct PingStats {
@@ -227,6 +468,19 @@ struct PingStats {
     min_rtt: u32,
     max_rtt: u32,
     avg_rtt: u32,
+    unreachable: u32,
+    ttl_expired: u32,
+    needs_fragmentation: u32,
+    other_errors: u32,
+    /// Replies by source address, when pinging a broadcast or multicast address.
+    responders: BTreeMap<Ipv4Addr, ResponderStats>,
+}
+
+struct ResponderStats {
+    replies_rcvd: u32,
+    min_rtt: u32,
+    max_rtt: u32,
+    total_rtt: u64,
 }
 
 impl PingStats {
@@ -237,6 +491,16 @@ impl PingStats {
             min_rtt: 3600000,
             max_rtt: 0,
             avg_rtt: 0,
+            unreachable: 0,
+            ttl_expired: 0,
+            needs_fragmentation: 0,
+            other_errors: 0,
+            responders: BTreeMap::new(),
         }
     }
+
+    /// The number of ICMP error replies received.
+    fn errors(&self) -> u32 {
+        self.unreachable + self.ttl_expired + self.needs_fragmentation + self.other_errors
+    }
 }
@@ -0,0 +1,258 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read};
+use std::net::Ipv4Addr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use windows::Win32::NetworkManagement::IpHelper::*;
+
+use crate::{ping, CliArgs, PingStats, Probe};
+
+/// Reads the targets to ping from path, or from standard input if path is "-".
+pub fn read_targets(path: &str) -> anyhow::Result<Vec<String>> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        contents
+    } else {
+        fs::read_to_string(path)?
+    };
+    let targets = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if targets.is_empty() {
+        anyhow::bail!("no targets found in {}", path);
+    }
+    Ok(targets)
+}
+
+/// Pings every target, at most args.concurrency at a time, printing a summary for each
+/// target as it finishes.
+pub fn run(args: &CliArgs, targets: Vec<String>) -> anyhow::Result<()> {
+    let queue = Mutex::new(targets.into_iter().collect::<VecDeque<_>>());
+    let num_targets = queue.lock().unwrap().len();
+    let num_workers = cmp::min(args.concurrency as usize, num_targets);
+
+    if !args.json {
+        println!();
+        println!(
+            "Pinging {} targets with {} bytes of data:",
+            num_targets, args.size
+        );
+    }
+
+    thread::scope(|scope| {
+        let workers = (0..num_workers)
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    let icmp_handle = ping::icmp_create()?;
+                    loop {
+                        let Some(target) = queue.lock().unwrap().pop_front() else {
+                            return Ok(());
+                        };
+                        let summary = ping_target(args, icmp_handle, target);
+                        print_summary(args, &summary)?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })
+}
+
+fn ping_target(args: &CliArgs, icmp_handle: IcmpHandle, target: String) -> TargetSummary {
+    let mut summary = TargetSummary::new(target);
+    let tgt_ip = match crate::get_tgt_ip_and_hostname(&summary.target, false) {
+        Ok((tgt_ip, _)) => tgt_ip,
+        Err(e) => {
+            summary.error = Some(e.to_string());
+            return summary;
+        }
+    };
+    summary.address = Some(tgt_ip);
+    match crate::is_broadcast_or_multicast(tgt_ip) {
+        Ok(false) => {}
+        Ok(true) => {
+            summary.error = Some(String::from(
+                "broadcast and multicast addresses can only be pinged on their own, with --allow-broadcast",
+            ));
+            return summary;
+        }
+        Err(e) => {
+            summary.error = Some(e.to_string());
+            return summary;
+        }
+    }
+
+    let mut stats = PingStats::new();
+    for n in 0..args.count {
+        if n > 0 {
+            thread::sleep(Duration::from_secs(1));
+        }
+        match crate::send_one(icmp_handle, tgt_ip, args) {
+            Ok(Probe::Reply(reply)) => {
+                crate::update_stats(&mut stats, &reply);
+                summary.marks.push('!');
+            }
+            Ok(Probe::TimedOut) => {
+                stats.requests_sent += 1;
+                summary.marks.push('.');
+            }
+            Ok(Probe::Error { status, .. }) => {
+                crate::update_error_stats(&mut stats, status);
+                summary.marks.push(match status {
+                    IP_DEST_NET_UNREACHABLE
+                    | IP_DEST_HOST_UNREACHABLE
+                    | IP_DEST_PROT_UNREACHABLE
+                    | IP_DEST_PORT_UNREACHABLE => 'U',
+                    IP_TTL_EXPIRED_TRANSIT => '&',
+                    IP_PACKET_TOO_BIG => 'M',
+                    _ => '?',
+                });
+            }
+            Err(e) => {
+                summary.error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    summary.record_stats(&stats);
+    summary
+}
+
+fn print_summary(args: &CliArgs, summary: &TargetSummary) -> anyhow::Result<()> {
+    // Hold the lock so the lines of a summary aren't interleaved with another target's.
+    let _stdout = io::stdout().lock();
+
+    if args.json {
+        println!("{}", serde_json::to_string(summary)?);
+        return Ok(());
+    }
+
+    if args.matrix {
+        let address = match summary.address {
+            Some(addr) => addr.to_string(),
+            None => String::from("-"),
+        };
+        let replies = summary.marks.iter().collect::<String>();
+        match &summary.error {
+            Some(e) => println!("{:<32} {:<15} {} {}", summary.target, address, replies, e),
+            None => println!(
+                "{:<32} {:<15} {} {}% loss",
+                summary.target, address, replies, summary.loss_percent
+            ),
+        }
+        return Ok(());
+    }
+
+    println!();
+    match (&summary.address, &summary.error) {
+        (None, Some(e)) => println!("Ping request could not find host {}: {}", summary.target, e),
+        (Some(addr), error) => {
+            println!("Ping statistics for {} [{}]:", summary.target, addr);
+            println!(
+                "\tPackets: Sent = {}, Received = {}, Lost = {} ({}% loss),",
+                summary.sent,
+                summary.received,
+                summary.sent - summary.received,
+                summary.loss_percent
+            );
+            let errors = summary.unreachable
+                + summary.ttl_expired
+                + summary.needs_fragmentation
+                + summary.other_errors;
+            if errors > 0 {
+                println!(
+                    "\tErrors: Unreachable = {}, TTL expired = {}, Needs fragmentation = {}, Other = {}",
+                    summary.unreachable,
+                    summary.ttl_expired,
+                    summary.needs_fragmentation,
+                    summary.other_errors
+                );
+            }
+            if let (Some(min), Some(max), Some(avg)) =
+                (summary.min_rtt_ms, summary.max_rtt_ms, summary.avg_rtt_ms)
+            {
+                println!("Approximate round trip times in milli-seconds:");
+                println!(
+                    "\tMinimum = {}ms, Maximum = {}ms, Average = {}ms",
+                    min, max, avg
+                );
+            }
+            if let Some(e) = error {
+                println!("\tStopped early: {}", e);
+            }
+        }
+        (None, None) => unreachable!("target has neither an address nor an error"),
+    }
+    Ok(())
+}
+
+/// The outcome of pinging a single target.
+#[derive(Serialize)]
+struct TargetSummary {
+    target: String,
+    address: Option<Ipv4Addr>,
+    error: Option<String>,
+    sent: u32,
+    received: u32,
+    loss_percent: u32,
+    min_rtt_ms: Option<u32>,
+    max_rtt_ms: Option<u32>,
+    avg_rtt_ms: Option<u32>,
+    unreachable: u32,
+    ttl_expired: u32,
+    needs_fragmentation: u32,
+    other_errors: u32,
+    /// The matrix mark for each echo request, in the order sent.
+    #[serde(skip)]
+    marks: Vec<char>,
+}
+
+impl TargetSummary {
+    fn new(target: String) -> Self {
+        TargetSummary {
+            target,
+            address: None,
+            error: None,
+            sent: 0,
+            received: 0,
+            loss_percent: 0,
+            min_rtt_ms: None,
+            max_rtt_ms: None,
+            avg_rtt_ms: None,
+            unreachable: 0,
+            ttl_expired: 0,
+            needs_fragmentation: 0,
+            other_errors: 0,
+            marks: Vec::new(),
+        }
+    }
+
+    fn record_stats(&mut self, stats: &PingStats) {
+        self.sent = stats.requests_sent;
+        self.received = stats.replies_rcvd;
+        self.unreachable = stats.unreachable;
+        self.ttl_expired = stats.ttl_expired;
+        self.needs_fragmentation = stats.needs_fragmentation;
+        self.other_errors = stats.other_errors;
+        if stats.requests_sent > 0 {
+            let lost = stats.requests_sent - stats.replies_rcvd;
+            self.loss_percent = (lost as f64 * 100_f64 / stats.requests_sent as f64).round() as u32;
+        }
+        if stats.replies_rcvd > 0 {
+            self.min_rtt_ms = Some(stats.min_rtt);
+            self.max_rtt_ms = Some(stats.max_rtt);
+            self.avg_rtt_ms = Some(stats.avg_rtt);
+        }
+    }
+}
@@ -19,6 +19,7 @@ pub enum Error {
     ResolveIpAddr(wp::Error),
     IcmpHandle(wp::Error),
     SendEcho(wp::Error),
+    AddrTable(wp::Error),
 }
 
 impl fmt::Display for Error {
@@ -31,6 +32,7 @@ impl fmt::Display for Error {
             ResolveIpAddr(e) => write!(f, "failed to resolve IP address to hostname: {}", e),
             IcmpHandle(e) => write!(f, "failed to open an ICMP handle: {}", e),
             SendEcho(e) => write!(f, "failed to send the echo request: {}", e),
+            AddrTable(e) => write!(f, "failed to get the local IPv4 addresses: {}", e),
         }
     }
 }
@@ -116,20 +118,20 @@ fn build_request_data(size: u16) -> Vec<u8> {
         .collect()
 }
 
-fn get_request_options(ttl: u8, dont_fragment: bool) -> IP_OPTION_INFORMATION {
+fn get_request_options(ttl: u8, tos: u8, dont_fragment: bool) -> IP_OPTION_INFORMATION {
     IP_OPTION_INFORMATION {
         Ttl: ttl,
-        Tos: 0,
+        Tos: tos,
         Flags: if dont_fragment { IP_FLAG_DF as u8 } else { 0 },
         OptionsSize: 0,
         OptionsData: std::ptr::null::<u8>() as *mut u8,
     }
 }
 
-fn build_reply_buffer(sz_request_data: usize) -> Vec<MaybeUninit<u8>> {
+fn build_reply_buffer(sz_request_data: usize, max_replies: usize) -> Vec<MaybeUninit<u8>> {
     let mut buf: Vec<MaybeUninit<u8>> = Vec::new();
-    let sz_reply_buf =
-        mem::size_of::<ICMP_ECHO_REPLY>() + sz_request_data + 8 + mem::size_of::<IO_STATUS_BLOCK>();
+    let sz_reply_buf = (mem::size_of::<ICMP_ECHO_REPLY>() + sz_request_data + 8) * max_replies
+        + mem::size_of::<IO_STATUS_BLOCK>();
     buf.reserve(sz_reply_buf);
     buf
 }
@@ -140,12 +142,40 @@ pub fn send_ping(
     dst_addr: Ipv4Addr,
     size: u16,
     ttl: u8,
+    tos: u8,
     dont_fragment: bool,
     timeout: u32,
 ) -> Result<ICMP_ECHO_REPLY> {
+    let replies = send_ping_all(
+        icmp_handle,
+        src_addr,
+        dst_addr,
+        size,
+        ttl,
+        tos,
+        dont_fragment,
+        timeout,
+        1,
+    )?;
+    Ok(replies[0])
+}
+
+/// Sends an echo request and returns every reply to it, up to max_replies.
+/// A request to a broadcast or multicast address can be answered by many hosts.
+pub fn send_ping_all(
+    icmp_handle: IcmpHandle,
+    src_addr: Ipv4Addr,
+    dst_addr: Ipv4Addr,
+    size: u16,
+    ttl: u8,
+    tos: u8,
+    dont_fragment: bool,
+    timeout: u32,
+    max_replies: usize,
+) -> Result<Vec<ICMP_ECHO_REPLY>> {
     let request_data = build_request_data(size);
-    let request_options = get_request_options(ttl, dont_fragment);
-    let mut reply_buf = build_reply_buffer(request_data.len());
+    let request_options = get_request_options(ttl, tos, dont_fragment);
+    let mut reply_buf = build_reply_buffer(request_data.len(), max_replies);
 
     let num_replies = unsafe {
         IcmpSendEcho2Ex(
@@ -166,6 +196,42 @@ pub fn send_ping(
     if num_replies == 0 {
         Err(Error::SendEcho(wp::last_error()))
     } else {
-        Ok(unsafe { *(reply_buf.as_ptr() as *const ICMP_ECHO_REPLY) })
+        // The replies are stored as an array at the start of the buffer.
+        let replies = reply_buf.as_ptr() as *const ICMP_ECHO_REPLY;
+        Ok((0..num_replies as usize)
+            .map(|n| unsafe { *replies.add(n) })
+            .collect())
     }
 }
+
+/// The directed broadcast address of each subnet a local interface is on.
+pub fn broadcast_addrs() -> Result<Vec<Ipv4Addr>> {
+    let mut size = 0;
+    let status = unsafe { GetIpAddrTable(None, &mut size, false) };
+    if status != ERROR_INSUFFICIENT_BUFFER.0 {
+        return Err(Error::AddrTable(wp::Error::from_win_error(
+            WIN32_ERROR(status).into(),
+        )));
+    }
+    // Use u32s so the buffer is aligned for the table.
+    let mut buf = vec![0_u32; (size as usize + 3) / 4];
+    let table = buf.as_mut_ptr() as *mut MIB_IPADDRTABLE;
+    let status = unsafe { GetIpAddrTable(Some(table), &mut size, false) };
+    if status != NO_ERROR.0 {
+        return Err(Error::AddrTable(wp::Error::from_win_error(
+            WIN32_ERROR(status).into(),
+        )));
+    }
+    let rows = unsafe {
+        std::slice::from_raw_parts((*table).table.as_ptr(), (*table).dwNumEntries as usize)
+    };
+    Ok(rows
+        .iter()
+        .filter(|row| row.dwMask != u32::MAX)
+        .map(|row| {
+            let addr = u32::from_be(row.dwAddr);
+            let mask = u32::from_be(row.dwMask);
+            Ipv4Addr::from(addr & mask | !mask)
+        })
+        .collect())
+}